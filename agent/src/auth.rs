@@ -0,0 +1,64 @@
+//! Authentication of the agent's connections to central command.
+//!
+//! When central command requires agents to authenticate (its `AGENT_AUTH` setting), every
+//! connection the agent opens starts with an `Authenticate` message carrying its credential, and
+//! over the message bus every message is preceded by one:
+//! - `AGENT_JWT_SVID_FILE`: A SPIFFE JWT-SVID, e.g. as written by the SPIFFE helper. The file is
+//!   read for every connection, so rotated SVIDs are picked up without a restart.
+//! - `AGENT_AUTH_TOKEN`: The token shared with central command, used when no SVID file is set.
+//!
+//! Central command closes the connection when the credential is rejected.
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use std::io;
+
+use crate::{frame_header, get_agent_auth_token, get_agent_jwt_svid_file, get_agent_name};
+use core_logic::messages::{Authenticate, Credential, Message};
+
+/// The configured credential, or `None` when the agent does not authenticate.
+fn credential() -> io::Result<Option<Credential>> {
+    if let Some(path) = get_agent_jwt_svid_file() {
+        let svid = std::fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Unable to read JWT-SVID {}: {}", path.display(), e),
+            )
+        })?;
+        return Ok(Some(Credential::JwtSvid(svid.trim().to_string())));
+    }
+    Ok(get_agent_auth_token().map(|token| Credential::Token(token.to_string())))
+}
+
+/// The `Authenticate` message to send, or `None` when no credential is configured.
+pub fn authenticate_message() -> io::Result<Option<Message>> {
+    Ok(credential()?.map(|credential| {
+        Message::Authenticate(Authenticate {
+            agent_name: get_agent_name(),
+            credential,
+        })
+    }))
+}
+
+/// Authenticates `stream` if a credential is configured.
+pub async fn authenticate(stream: &mut TcpStream) -> io::Result<()> {
+    let Some(message) = authenticate_message()? else {
+        return Ok(());
+    };
+    let serialized: Vec<u8> = message.try_into().map_err(io::Error::other)?;
+    stream.write_all(&frame_header(&serialized)).await?;
+    stream.write_all(&serialized).await?;
+
+    let mut reply = [0; 2];
+    match stream.read_exact(&mut reply).await {
+        Ok(_) if &reply == b"OK" => {
+            debug!("Authenticated with central command");
+            Ok(())
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Central command rejected the agent's credential",
+        )),
+    }
+}
@@ -0,0 +1,111 @@
+//! Detects whether the agent runs in a container and, on Kubernetes, where: the node, pod and
+//! namespace reported at registration, so the agents page can show them, and the node's labels,
+//! so jobs can target nodes with `JobV1::node_selector`.
+//!
+//! The runtime is recognized from what it leaves in the container: the `KUBERNETES_SERVICE_HOST`
+//! variable set in every pod, `/run/.containerenv` (Podman), `/.dockerenv` (Docker), or the cgroup
+//! of the agent's process.
+//!
+//! # Kubernetes
+//! The pod details come from the downward API, e.g.
+//!
+//! ```yaml
+//! env:
+//!   - name: AGENT_NODE_NAME
+//!     valueFrom: { fieldRef: { fieldPath: spec.nodeName } }
+//!   - name: AGENT_POD_NAME
+//!     valueFrom: { fieldRef: { fieldPath: metadata.name } }
+//!   - name: AGENT_POD_NAMESPACE
+//!     valueFrom: { fieldRef: { fieldPath: metadata.namespace } }
+//! volumeMounts:
+//!   - { name: node-labels, mountPath: /etc/podinfo }
+//! ```
+//!
+//! Without them the pod name is taken from the hostname and the namespace from the service
+//! account. The node labels are read from `AGENT_NODE_LABELS_FILE`, in the downward API's
+//! `key="value"` per line format. The downward API only exposes the pod's own labels, so the node
+//! labels have to reach the file another way: a downward API volume of pod labels the node's
+//! labels were copied to (e.g. topology labels), or a file an init container wrote from the node.
+//! It is read each time the agent registers.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use core_logic::messages::ContainerInfo;
+
+const DEFAULT_NODE_LABELS_FILE: &str = "/etc/podinfo/node-labels";
+const SERVICE_ACCOUNT_NAMESPACE_FILE: &str =
+    "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// The container the agent runs in, or `None` when it runs on a host.
+pub fn detect() -> Option<ContainerInfo> {
+    let runtime = runtime()?;
+    if runtime != "kubernetes" {
+        return Some(ContainerInfo {
+            runtime: runtime.to_string(),
+            node_name: None,
+            pod_name: None,
+            pod_namespace: None,
+            node_labels: vec![],
+        });
+    }
+    let labels_file =
+        env::var("AGENT_NODE_LABELS_FILE").unwrap_or_else(|_| DEFAULT_NODE_LABELS_FILE.to_string());
+    Some(ContainerInfo {
+        runtime: runtime.to_string(),
+        node_name: non_empty_var("AGENT_NODE_NAME"),
+        pod_name: non_empty_var("AGENT_POD_NAME").or_else(|| non_empty_var("HOSTNAME")),
+        pod_namespace: non_empty_var("AGENT_POD_NAMESPACE").or_else(|| {
+            fs::read_to_string(SERVICE_ACCOUNT_NAMESPACE_FILE)
+                .ok()
+                .map(|namespace| namespace.trim().to_string())
+                .filter(|namespace| !namespace.is_empty())
+        }),
+        node_labels: fs::read_to_string(labels_file)
+            .map(|labels| parse_labels(&labels))
+            .unwrap_or_default(),
+    })
+}
+
+/// e.g. `kubernetes` or `docker`, or `None` outside a container.
+fn runtime() -> Option<&'static str> {
+    if env::var_os("KUBERNETES_SERVICE_HOST").is_some() {
+        return Some("kubernetes");
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some("podman");
+    }
+    if Path::new("/.dockerenv").exists() {
+        return Some("docker");
+    }
+    let cgroup = fs::read_to_string("/proc/self/cgroup").ok()?;
+    [
+        ("kubepods", "kubernetes"),
+        ("libpod", "podman"),
+        ("docker", "docker"),
+        ("containerd", "containerd"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| cgroup.contains(marker))
+    .map(|(_, runtime)| runtime)
+}
+
+fn non_empty_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Labels in the downward API's format, one `key="value"` per line, as `key=value`.
+fn parse_labels(labels: &str) -> Vec<String> {
+    labels
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            let value = value.replace("\\\"", "\"").replace("\\\\", "\\");
+            (!key.is_empty()).then(|| format!("{}={}", key, value))
+        })
+        .collect()
+}
@@ -0,0 +1,91 @@
+//! Runs the commands of jobs with a container (see `core_logic::datastore::jobs::JobContainer`)
+//! in a new container instead of on the host, so they get the environment of their image and
+//! leave nothing behind on the host but what they write to their mounts.
+//!
+//! The command the agent would run on its host, as built for `AGENT_SHELL`, becomes the command
+//! of `docker run --rm` or `podman run --rm` (see `AGENT_CONTAINER_RUNTIME`), with the job's
+//! mounts and the container's and the step's environment. The step's `cwd`, or else the
+//! container's `workdir`, is the working directory in the container. Its stdin is closed, so the
+//! run's output, exit code, timeout and cancellation work as on the host: the exit code is the
+//! command's, or 125 when the runtime could not start the container (e.g. the image does not
+//! exist) and 126 or 127 when the command could not be run in it.
+//!
+//! Killing the runtime's client does not stop the container, so each container is named after
+//! the run and removed with `stop` when the run is interrupted.
+use bson::oid::ObjectId;
+use tokio::process::Command;
+use tracing::warn;
+
+use std::io;
+
+use crate::get_agent_container_runtime;
+use core_logic::messages::{JobContainer, JobStep};
+
+/// A unique name for a container of `job_name`, e.g. `rad-nightly-backup-6650f1...`.
+pub fn container_name(job_name: &str) -> String {
+    let name: String = job_name
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                true => c,
+                false => '-',
+            },
+        )
+        .take(40)
+        .collect();
+    format!("rad-{}-{}", name, ObjectId::new().to_hex())
+}
+
+/// `command` run in a new container named `name` of `container` instead of on the host, with
+/// the `cwd` and `env` of `step`.
+pub fn wrap(
+    command: &Command,
+    container: &JobContainer,
+    step: &JobStep,
+    name: &str,
+) -> io::Result<Command> {
+    let runtime = get_agent_container_runtime().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "No container runtime found; install docker or podman or set AGENT_CONTAINER_RUNTIME",
+        )
+    })?;
+    let mut wrapped = Command::new(runtime);
+    wrapped.args(["run", "--rm", "--name", name]);
+    for mount in &container.mounts {
+        wrapped.arg("--volume").arg(mount);
+    }
+    for var in container.env.iter().chain(&step.env) {
+        wrapped.arg("--env").arg(var);
+    }
+    if let Some(workdir) = step.cwd.as_ref().or(container.workdir.as_ref()) {
+        wrapped.arg("--workdir").arg(workdir);
+    }
+    let command = command.as_std();
+    wrapped
+        .arg("--")
+        .arg(&container.image)
+        .arg(command.get_program())
+        .args(command.get_args());
+    Ok(wrapped)
+}
+
+/// Removes the container `name` of an interrupted run, killing its command.
+pub async fn stop(name: &str) {
+    let Some(runtime) = get_agent_container_runtime() else {
+        return;
+    };
+    let removed = Command::new(runtime)
+        .args(["rm", "--force", name])
+        .output()
+        .await;
+    match removed {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => warn!(
+            "Failed to remove container {}: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => warn!("Failed to remove container {}: {}", name, e),
+    }
+}
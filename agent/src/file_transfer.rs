@@ -0,0 +1,108 @@
+//! Files central command pushes to the agent ahead of the jobs that need them.
+//!
+//! Each file arrives as `FileChunk`s in order (see the File Distribution section of
+//! `core_logic::messages`). They are written to `<destination>.part`, which is moved over the
+//! destination once the last chunk arrived and the file's SHA-256 matches, so a job never sees a
+//! half written file. Missing directories are created and the file's mode is set on Unix.
+//!
+//! A transfer that fails is only logged: the job's `DispatchJob` lists the digests of its files,
+//! and `verify` fails the run before anything is started if a file is missing or differs.
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use core_logic::messages::{FileChunk, FileDigest};
+
+/// Where the chunks of a file going to `destination` are collected.
+fn partial_path(destination: &str) -> PathBuf {
+    PathBuf::from(format!("{}.part", destination))
+}
+
+/// Writes `chunk` to its file, moving the file into place after its last chunk.
+pub async fn receive(chunk: FileChunk) -> io::Result<()> {
+    let partial = partial_path(&chunk.destination);
+    let mut file = match chunk.offset {
+        0 => {
+            if let Some(parent) = Path::new(&chunk.destination).parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&partial)
+                .await?
+        }
+        offset => {
+            let file = OpenOptions::new().append(true).open(&partial).await?;
+            let written = file.metadata().await?.len();
+            if written != offset {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Chunk of {} at offset {} does not follow the {} bytes received",
+                        chunk.destination, offset, written
+                    ),
+                ));
+            }
+            file
+        }
+    };
+    file.write_all(&chunk.data).await?;
+    file.flush().await?;
+    drop(file);
+
+    if chunk.offset + (chunk.data.len() as u64) < chunk.size {
+        return Ok(());
+    }
+    let actual = sha256_of(&partial).await?;
+    if actual != chunk.sha256.to_ascii_lowercase() {
+        let _ = fs::remove_file(&partial).await;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Checksum mismatch for {}: expected {}, got {}",
+                chunk.destination, chunk.sha256, actual
+            ),
+        ));
+    }
+    #[cfg(unix)]
+    if let Some(mode) = chunk.mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&partial, std::fs::Permissions::from_mode(mode)).await?;
+    }
+    fs::rename(&partial, &chunk.destination).await
+}
+
+/// Checks that every one of `files` is in place with the contents it was pushed with.
+pub async fn verify(files: &[FileDigest]) -> Result<(), String> {
+    for file in files {
+        let actual = sha256_of(Path::new(&file.destination))
+            .await
+            .map_err(|e| format!("File {} was not received: {}", file.destination, e))?;
+        if actual != file.sha256.to_ascii_lowercase() {
+            return Err(format!(
+                "File {} does not match the pushed file (sha256 {}, expected {})",
+                file.destination, actual, file.sha256
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn sha256_of(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
@@ -3,6 +3,8 @@
 ///
 /// # Fields
 /// - `sender`: An asynchronous channel sender used to queue job names for completion notification.
+/// - `running`: Handles to cancel the jobs currently executing or extend their timeout, keyed by
+///   job name.
 ///
 /// # Example
 /// ```rust
@@ -11,39 +13,204 @@
 /// ```
 ///
 /// # Usage
-/// - Use `JobDispatcher::new` to create a new dispatcher, passing an `Arc<Mutex<CentralCommandWriter>>`.
+/// - Use `JobDispatcher::new` to create a new dispatcher, passing an `Arc<PriorityLock<CentralCommandWriter>>`.
 /// - Call `spawn` with a `DispatchJob` to execute a job asynchronously.
+/// - Call `cancel` with a job name to kill a running job and its child processes.
+/// - Call `extend_timeout` with a job name to give a running job more time before it is killed.
 /// - Upon job completion, a `JobComplete` message is sent to the central command.
 ///
 /// # Notes
-/// - The actual command execution is performed using `tokio::process::Command`.
-/// - Job completion is notified via an mpsc channel and handled in a background task.
+/// - The actual command execution is performed using `tokio::process::Command`, either directly or
+///   through the shell selected by `AGENT_SHELL` (`sh`, `cmd` or `powershell`).
+/// - Output is read as it is produced and capped at `AGENT_MAX_OUTPUT_BYTES`, keeping its head and
+///   tail; the full output can be spilled to `AGENT_OUTPUT_ARTIFACT_DIR` (see `output`).
+/// - Jobs that exceed their timeout or are cancelled have their whole process tree killed. Once a
+///   run has used `AGENT_TIMEOUT_WARNING_PERCENT` of its timeout, a `JobProgress` warning is sent
+///   to central command so the job's owners can intervene before it is killed.
+/// - A job with `steps` runs each step's command in turn, with its own working directory and
+///   environment, and reports every step's result. A failed step ends the job unless it is marked
+///   `continue_on_error`, and the remaining steps are reported as skipped. The timeout covers all
+///   the steps together, and a timed out or cancelled step always ends the job.
+/// - When the agent is started with `--simulate`, nothing is executed and each job reports a
+///   synthetic result after a fake duration instead (see `simulate`).
+/// - A job with a `script` runs it in place of its command: the script is written to a private
+///   temporary file, run with its interpreter and removed afterwards (see `script`).
+/// - A job with a `container` runs its command, or each step, in a new container of its image
+///   instead of on the host (see `container_run`).
+/// - A job's pushed files are checked against the digests in its `DispatchJob` first; the run
+///   fails without starting anything if one is missing or differs (see `file_transfer`).
+/// - Secrets are redacted from the output and command line before they are sent, using the agent's
+///   redaction patterns plus the job's `redact_patterns`.
+/// - Job completion is notified via an mpsc channel and handled in a background task, which signs
+///   the result with the agent's receipt key (see `core_logic::receipts`) and appends it to the
+///   spool, from which another task sends it once central command is reachable (see `spool`).
 /// - Logging is performed using the `tracing` crate.
 use bson::DateTime;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncRead;
 use tokio::process::Command;
 use tokio::spawn;
-use tokio::sync::Mutex;
 use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::oneshot;
+use tokio::sync::{Mutex, Notify, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
-use tracing::{error, info};
+use tracing::{Instrument, error, info, warn};
 
-use crate::{CentralCommandWriter, get_agent_name};
-use core_logic::messages::{DispatchJob, JobComplete, JobOutCome, Message};
+use crate::output::{self, CollectedOutput};
+use crate::{
+    CentralCommandWriter, get_agent_health, get_agent_max_output_bytes, get_agent_name,
+    get_agent_output_artifact_dir, get_agent_receipt_signer, get_agent_redactor, get_agent_shell,
+    get_agent_simulate, get_agent_spool, get_agent_timeout_warning_percent, simulate,
+};
+use crate::{container_run, file_transfer, process, script};
+use core_logic::health::CheckStatus;
+use core_logic::logging;
+use core_logic::messages::{
+    DispatchJob, JobComplete, JobOutCome, JobProgress, JobStep, Message, Priority, StepResult,
+};
+use core_logic::priority::PriorityLock;
+use core_logic::redaction::{RedactionError, Redactor};
+
+/// Return code reported when a job is killed for exceeding its timeout.
+const TIMED_OUT_RETURN_CODE: i32 = 124;
+/// Return code reported when a job is killed because it was cancelled.
+const CANCELLED_RETURN_CODE: i32 = 130;
+
+/// How the agent launches a job's command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellMode {
+    /// Execute the command directly with whitespace separated arguments.
+    Direct,
+    /// Run the command line through `sh -c`.
+    Sh,
+    /// Run the command line through `cmd /C`.
+    Cmd,
+    /// Run the command line through `powershell -Command`.
+    PowerShell,
+}
+
+impl From<&str> for ShellMode {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "sh" => ShellMode::Sh,
+            "cmd" => ShellMode::Cmd,
+            "powershell" | "pwsh" => ShellMode::PowerShell,
+            _ => ShellMode::Direct, // Default to running the command directly
+        }
+    }
+}
+
+impl ShellMode {
+    /// Builds the `Command` used to run `command` with `args` in this mode.
+    pub fn build_command(&self, command: &str, args: &str) -> Command {
+        let command_line = format!("{} {}", command, args).trim().to_string();
+        match self {
+            ShellMode::Direct => {
+                let mut cmd = Command::new(command);
+                cmd.args(args.split_whitespace());
+                cmd
+            }
+            ShellMode::Sh => {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c").arg(command_line);
+                cmd
+            }
+            ShellMode::Cmd => {
+                let mut cmd = Command::new("cmd");
+                cmd.arg("/C").arg(command_line);
+                cmd
+            }
+            ShellMode::PowerShell => {
+                let mut cmd = Command::new("powershell");
+                cmd.args(["-NoProfile", "-NonInteractive", "-Command"])
+                    .arg(command_line);
+                cmd
+            }
+        }
+    }
+}
+
+/// How a spawned job finished.
+pub(crate) enum RunResult {
+    Exited(std::io::Result<ExitStatus>),
+    TimedOut(u32),
+    Cancelled,
+    /// Finished a simulated run (see `simulate`) with this return code.
+    Simulated(i32),
+}
+
+/// Handles to a running job, used to cancel it or extend its timeout while it runs.
+#[derive(Clone)]
+pub(crate) struct RunControl {
+    cancel: Arc<Notify>,
+    timeout: Arc<watch::Sender<Option<u32>>>, // Seconds, counted from the start of the run
+}
+
+impl RunControl {
+    fn new(timeout: Option<u32>) -> Self {
+        Self {
+            cancel: Arc::new(Notify::new()),
+            timeout: Arc::new(watch::Sender::new(timeout)),
+        }
+    }
+
+    /// The job's timeout, including any extensions so far.
+    fn timeout(&self) -> Option<u32> {
+        *self.timeout.borrow()
+    }
+
+    /// Resolves once the run is cancelled.
+    pub(crate) async fn cancelled(&self) {
+        self.cancel.notified().await
+    }
+
+    /// Resolves with the timeout once it has elapsed since `started`, following extensions made
+    /// while waiting. Never resolves for jobs without a timeout.
+    pub(crate) async fn timeout_elapsed(&self, started: Instant) -> u32 {
+        let mut timeout = self.timeout.subscribe();
+        loop {
+            let current = *timeout.borrow_and_update();
+            let deadline = async {
+                match current {
+                    Some(seconds) => {
+                        tokio::time::sleep_until(started + Duration::from_secs(seconds as u64))
+                            .await
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = deadline => return current.unwrap_or_default(),
+                _ = timeout.changed() => (), // Extended, wait for the new deadline
+            }
+        }
+    }
+}
 
 pub struct JobDispatcher {
     sender: Sender<JobComplete>,
+    running: Arc<Mutex<HashMap<String, RunControl>>>,
+    central_command_writer: Arc<PriorityLock<CentralCommandWriter>>,
 }
 
 impl JobDispatcher {
-    pub fn new(central_command_writer: Arc<Mutex<CentralCommandWriter>>) -> Self {
+    pub fn new(central_command_writer: Arc<PriorityLock<CentralCommandWriter>>) -> Self {
         let (sender, mut receiver) = mpsc::channel::<JobComplete>(100);
 
+        let writer = central_command_writer.clone();
+        let spooled = Arc::new(Notify::new());
+        Self::spawn_spool_flusher(central_command_writer.clone(), spooled.clone());
         spawn(async move {
             while let Some(job_info) = receiver.recv().await {
                 //info!("Received job: {}", job_name);
                 // Here you would handle the job, e.g., by sending it to the central command
-                let message = Message::JobComplete(JobComplete {
+                let mut job_complete = JobComplete {
                     started_at: job_info.started_at,
                     completed_at: job_info.completed_at,
                     job_name: job_info.job_name.clone(),
@@ -52,76 +219,612 @@ impl JobDispatcher {
                     outcome: job_info.outcome,
                     return_code: job_info.return_code,
                     output: job_info.output,
-                });
-                let mut writer = central_command_writer.lock().await;
-                writer.write(message).await;
+                    triggered_by: job_info.triggered_by,
+                    truncated: job_info.truncated,
+                    artifact: job_info.artifact,
+                    signature: None,
+                    run_id: job_info.run_id,
+                    steps: job_info.steps,
+                    timeout_extension: job_info.timeout_extension,
+                };
+                let signature = get_agent_receipt_signer().sign(&(&job_complete).into());
+                job_complete.signature = Some(signature);
+                let span = logging::run_span(
+                    job_complete.run_id.as_deref(),
+                    &job_complete.job_name,
+                    &job_complete.agent_name,
+                );
+                let message = Message::JobComplete(job_complete);
+                if let Some(spool) = get_agent_spool() {
+                    match spool.push(&message).instrument(span.clone()).await {
+                        Ok(()) => {
+                            spooled.notify_one();
+                            continue;
+                        }
+                        Err(e) => {
+                            let _entered = span.enter();
+                            warn!("Failed to spool result, sending it from memory: {}", e);
+                        }
+                    }
+                }
+                let mut writer = writer.lock(message.priority()).await;
+                writer.write(message).instrument(span).await;
                 drop(writer); // Explicitly drop the lock to release it
             }
         });
 
-        JobDispatcher { sender }
+        let running: Arc<Mutex<HashMap<String, RunControl>>> = Arc::default();
+        Self::report_queue_depth(&sender, &running);
+
+        JobDispatcher {
+            sender,
+            running,
+            central_command_writer,
+        }
+    }
+
+    /// Sends spooled results whenever one is added, so finishing jobs never wait on central command.
+    fn spawn_spool_flusher(
+        central_command_writer: Arc<PriorityLock<CentralCommandWriter>>,
+        spooled: Arc<Notify>,
+    ) {
+        spawn(async move {
+            loop {
+                spooled.notified().await;
+                central_command_writer
+                    .lock(Priority::Bulk)
+                    .await
+                    .flush_spool()
+                    .await;
+            }
+        });
+    }
+
+    /// Reports the results waiting to be sent to central command on the agent's health endpoint.
+    /// The dispatcher is not ready once the queue is full, as finished jobs then stall.
+    fn report_queue_depth(
+        sender: &Sender<JobComplete>,
+        running: &Arc<Mutex<HashMap<String, RunControl>>>,
+    ) {
+        // Weak, so the probe does not keep the result channel open.
+        let sender = sender.downgrade();
+        let running = running.clone();
+        get_agent_health().probe("dispatcher", move || {
+            let Some(sender) = sender.upgrade() else {
+                return CheckStatus {
+                    ok: false,
+                    detail: "stopped".to_string(),
+                };
+            };
+            let queued = sender.max_capacity() - sender.capacity();
+            let running = running
+                .try_lock()
+                .map(|running| running.len().to_string())
+                .unwrap_or_else(|_| "?".to_string());
+            CheckStatus {
+                ok: sender.capacity() > 0,
+                detail: format!("{} running, {} results queued", running, queued),
+            }
+        });
+    }
+
+    /// Cancels a running job, killing its process tree.
+    /// Returns `false` if no job with that name is running.
+    pub async fn cancel(&mut self, job_name: &str) -> bool {
+        match self.running.lock().await.get(job_name) {
+            Some(control) => {
+                control.cancel.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds `seconds` to the timeout of a running job, returning the new timeout.
+    /// Returns `None` if no job with that name is running, or it has no timeout to extend.
+    pub async fn extend_timeout(&mut self, job_name: &str, seconds: u32) -> Option<u32> {
+        let running = self.running.lock().await;
+        let control = running.get(job_name)?;
+        control.timeout()?;
+        control.timeout.send_modify(|timeout| {
+            *timeout = timeout.map(|timeout| timeout.saturating_add(seconds));
+        });
+        control.timeout()
     }
 
-    // Todo make real command runner
     pub async fn spawn(&mut self, job: DispatchJob) {
         let sender = self.sender.clone();
-        spawn(async move {
-            let job_name = job.job_name.clone();
-            let command_name = job.command.clone();
-            let args = job.args.clone();
-            let valid_return_codes = job.valid_return_codes.clone();
-            // Here you would run the job, e.g., by executing a command
-            info!("Spawning job: {} with command: {}", job_name, command_name);
+        let running = self.running.clone();
+        let central_command_writer = self.central_command_writer.clone();
+        let control = RunControl::new(job.timeout);
+        running
+            .lock()
+            .await
+            .insert(job.job_name.clone(), control.clone());
 
-            let start_time = DateTime::now();
+        let span = logging::run_span(job.run_id.as_deref(), &job.job_name, &get_agent_name());
+        spawn(
+            async move {
+                let job_name = job.job_name.clone();
+                let shell = get_agent_shell();
+                match job.steps.len() {
+                    0 if job.script.is_some() => info!(
+                        "Spawning job: {} with a {} script",
+                        job_name,
+                        job.script.as_ref().map_or("", |script| &script.interpreter)
+                    ),
+                    0 => info!(
+                        "Spawning job: {} with command: {} ({:?})",
+                        job_name, job.command, shell
+                    ),
+                    steps => info!(
+                        "Spawning job: {} with {} steps ({:?})",
+                        job_name, steps, shell
+                    ),
+                }
 
-            let mut command = Command::new(command_name.clone());
+                let started = Instant::now();
+                let start_time = DateTime::now();
+                let timeout_warning =
+                    Self::warn_before_timeout(&job, &control, started, central_command_writer);
 
-            command.args(args.split_whitespace());
+                let mut redactor = get_agent_redactor().clone();
+                let redactor = redactor.extend(&job.redact_patterns).map(|()| redactor);
 
-            let output = match command.output().await {
-                Ok(output) => Some(output),
-                Err(e) => {
-                    error!("Failed to execute command: {}", e);
-                    None
+                // A single command job runs as a step of its own. The job's environment is set
+                // for every step, under the step's own.
+                let with_job_env = |env: &[String]| job.env.iter().chain(env).cloned().collect();
+                let steps = match job.steps.is_empty() {
+                    true => vec![JobStep {
+                        name: job_name.clone(),
+                        command: match &job.script {
+                            Some(script) => format!("{} script", script.interpreter),
+                            None => job.command.clone(),
+                        },
+                        args: job.args.clone(),
+                        cwd: None,
+                        env: job.env.clone(),
+                        continue_on_error: false,
+                    }],
+                    false => job
+                        .steps
+                        .iter()
+                        .map(|step| JobStep {
+                            env: with_job_env(&step.env),
+                            ..step.clone()
+                        })
+                        .collect(),
+                };
+                // Nothing runs without the files pushed for the job.
+                let files = match get_agent_simulate() {
+                    true => Ok(()),
+                    false => file_transfer::verify(&job.files).await,
+                };
+                let mut results = vec![];
+                let mut outcome = JobOutCome::Success;
+                for step in &steps {
+                    if outcome != JobOutCome::Success {
+                        results.push(Self::skipped_step(step));
+                        continue;
+                    }
+                    let result = match &files {
+                        Ok(()) => Self::run_step(&job, step, started, &redactor, &control).await,
+                        Err(e) => {
+                            error!("Not running job {}: {}", job_name, e);
+                            Self::failed_step(step, e)
+                        }
+                    };
+                    let interrupted =
+                        matches!(result.outcome, JobOutCome::TimedOut | JobOutCome::Cancelled);
+                    if interrupted
+                        || (result.outcome != JobOutCome::Success && !step.continue_on_error)
+                    {
+                        outcome = result.outcome.clone();
+                    }
+                    results.push(result);
                 }
-            };
 
-            let return_code = output.as_ref().and_then(|o| o.status.code()).unwrap_or(-1);
+                let end_time = DateTime::now();
 
-            let outcome = match valid_return_codes {
-                Some(valid_codes) if valid_codes.contains(&return_code) => JobOutCome::Success,
-                _ => JobOutCome::Failure,
-            };
+                drop(timeout_warning); // The run finished, no need to warn
+                running.lock().await.remove(&job_name);
+
+                let summary = match job.steps.is_empty() {
+                    true => results.pop(),
+                    false => None,
+                }
+                .unwrap_or_else(|| Self::summarize_steps(&results, outcome));
+
+                let job_complete = JobComplete {
+                    started_at: start_time.timestamp_millis(),
+                    completed_at: end_time.timestamp_millis(),
+                    job_name: job_name.clone(),
+                    agent_name: get_agent_name(),
+                    outcome: summary.outcome,
+                    command: summary.command,
+                    return_code: summary.return_code,
+                    output: summary.output,
+                    triggered_by: job.triggered_by.clone(),
+                    truncated: summary.truncated,
+                    artifact: summary.artifact,
+                    signature: None, // Signed once it is sent
+                    run_id: job.run_id.clone(),
+                    steps: results,
+                    timeout_extension: control.timeout().unwrap_or_default()
+                        - job.timeout.unwrap_or_default(),
+                };
 
-            let output = match output {
-                Some(output) => {
-                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-                    if !stderr.is_empty() {
-                        stderr
-                    } else {
-                        String::from_utf8_lossy(&output.stdout).to_string()
+                if let Err(e) = sender.send(job_complete).await {
+                    error!("Failed to send job name: {}", e);
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    /// Sends central command a `JobProgress` warning once the run has used
+    /// `AGENT_TIMEOUT_WARNING_PERCENT` of its timeout, counted from `started`, and again whenever
+    /// an extension brings it back under that share. Dropping the returned sender when the run
+    /// finishes cancels any warning not sent yet. Nothing is started for jobs without a timeout.
+    fn warn_before_timeout(
+        job: &DispatchJob,
+        control: &RunControl,
+        started: Instant,
+        central_command_writer: Arc<PriorityLock<CentralCommandWriter>>,
+    ) -> Option<oneshot::Sender<()>> {
+        let percent = get_agent_timeout_warning_percent() as u64;
+        control.timeout().filter(|_| percent > 0)?;
+        let mut timeout = control.timeout.subscribe();
+        let (finished_sender, mut finished) = oneshot::channel::<()>();
+        let job_name = job.job_name.clone();
+        let run_id = job.run_id.clone();
+        let task = async move {
+            loop {
+                let current = timeout.borrow_and_update().unwrap_or_default();
+                let warn_after = Duration::from_millis(current as u64 * 1000 * percent / 100);
+                tokio::select! {
+                    _ = tokio::time::sleep_until(started + warn_after) => (),
+                    Ok(()) = timeout.changed() => continue, // Extended, wait for the new share
+                    _ = &mut finished => return,
+                }
+                let elapsed = started.elapsed().as_secs() as u32;
+                warn!(
+                    "Job {} has run for {} of its {} second timeout",
+                    job_name, elapsed, current
+                );
+                let message = Message::JobProgress(JobProgress {
+                    job_name: job_name.clone(),
+                    agent_name: get_agent_name(),
+                    run_id: run_id.clone(),
+                    elapsed,
+                    timeout: current,
+                });
+                let mut writer = central_command_writer.lock(message.priority()).await;
+                writer.write(message).await;
+                drop(writer);
+                tokio::select! {
+                    Ok(()) = timeout.changed() => (),
+                    _ = &mut finished => return,
+                }
+            }
+        };
+        spawn(task.instrument(tracing::Span::current()));
+        Some(finished_sender)
+    }
+
+    /// Runs one step of `job`, redacting its command line and output.
+    async fn run_step(
+        job: &DispatchJob,
+        step: &JobStep,
+        started: Instant,
+        redactor: &Result<Redactor, RedactionError>,
+        control: &RunControl,
+    ) -> StepResult {
+        let job_name = &job.job_name;
+        let start_time = DateTime::now();
+
+        let (result, collected) = if get_agent_simulate() {
+            simulate::run(job, step, started, control).await
+        } else {
+            Self::run_command(job, step, started, &start_time, redactor, control).await
+        };
+        let interrupted = match result {
+            RunResult::TimedOut(_) => Some(JobOutCome::TimedOut),
+            RunResult::Cancelled => Some(JobOutCome::Cancelled),
+            _ => None,
+        };
+        let artifact = collected
+            .artifact
+            .map(|path| path.to_string_lossy().to_string());
+
+        let (return_code, output, truncated) = match result {
+            RunResult::Exited(Ok(status)) => (
+                process::map_exit_status(status),
+                collected.text,
+                collected.truncated,
+            ),
+            RunResult::Simulated(return_code) => (return_code, collected.text, collected.truncated),
+            RunResult::Exited(Err(e)) => {
+                error!("Failed to execute command: {}", e);
+                (-1, String::new(), false)
+            }
+            RunResult::TimedOut(timeout) => {
+                warn!("Job {} timed out after {} seconds", job_name, timeout);
+                (
+                    TIMED_OUT_RETURN_CODE,
+                    format!("Job timed out after {} seconds", timeout),
+                    false,
+                )
+            }
+            RunResult::Cancelled => {
+                warn!("Job {} was cancelled", job_name);
+                (
+                    CANCELLED_RETURN_CODE,
+                    "Job was cancelled".to_string(),
+                    false,
+                )
+            }
+        };
+
+        let outcome = match (interrupted, &job.valid_return_codes) {
+            (Some(outcome), _) => outcome,
+            (None, Some(valid_codes)) if valid_codes.contains(&return_code) => JobOutCome::Success,
+            _ => JobOutCome::Failure,
+        };
+
+        let (command, output) = match redactor {
+            Ok(redactor) => (
+                redactor.redact(&format!("{} {}", step.command, step.args)),
+                redactor.redact(&output),
+            ),
+            Err(e) => {
+                // Without every pattern applied, secrets could leak, so nothing is sent.
+                error!("Withholding output of job {}: {}", job_name, e);
+                (step.command.clone(), format!("Output withheld: {}", e))
+            }
+        };
+
+        StepResult {
+            name: step.name.clone(),
+            command,
+            started_at: start_time.timestamp_millis(),
+            completed_at: DateTime::now().timestamp_millis(),
+            return_code,
+            outcome,
+            output,
+            truncated,
+            artifact,
+        }
+    }
+
+    /// A step that was not run because an earlier one ended the job.
+    fn skipped_step(step: &JobStep) -> StepResult {
+        let now = DateTime::now().timestamp_millis();
+        StepResult {
+            name: step.name.clone(),
+            command: step.command.clone(),
+            started_at: now,
+            completed_at: now,
+            return_code: 0,
+            outcome: JobOutCome::Skipped,
+            output: String::new(),
+            truncated: false,
+            artifact: None,
+        }
+    }
+
+    /// A step that was not run because the job could not be started.
+    fn failed_step(step: &JobStep, reason: &str) -> StepResult {
+        StepResult {
+            outcome: JobOutCome::Failure,
+            return_code: -1,
+            output: reason.to_string(),
+            ..Self::skipped_step(step)
+        }
+    }
+
+    /// The overall result of a multi-step job: the last step's return code, one line of output
+    /// per step and the outcome of the step that ended the job, if one did.
+    fn summarize_steps(results: &[StepResult], outcome: JobOutCome) -> StepResult {
+        let ran: Vec<&StepResult> = results
+            .iter()
+            .filter(|result| result.outcome != JobOutCome::Skipped)
+            .collect();
+        let output: Vec<String> = results
+            .iter()
+            .map(|result| match result.outcome {
+                JobOutCome::Skipped => format!("{}: Skipped", result.name),
+                _ => format!(
+                    "{}: {:?} (return code {})",
+                    result.name, result.outcome, result.return_code
+                ),
+            })
+            .collect();
+        StepResult {
+            name: String::new(),
+            command: ran
+                .iter()
+                .map(|result| result.command.as_str())
+                .collect::<Vec<_>>()
+                .join("; "),
+            started_at: ran.first().map(|r| r.started_at).unwrap_or_default(),
+            completed_at: ran.last().map(|r| r.completed_at).unwrap_or_default(),
+            return_code: ran.last().map(|r| r.return_code).unwrap_or_default(),
+            outcome,
+            output: output.join("\n"),
+            truncated: ran.iter().any(|result| result.truncated),
+            artifact: None, // Each step reports its own
+        }
+    }
+
+    /// Runs a step's command, or the job's script, collecting its output until it exits, the job
+    /// times out or is cancelled.
+    async fn run_command(
+        job: &DispatchJob,
+        step: &JobStep,
+        started: Instant,
+        start_time: &DateTime,
+        redactor: &Result<Redactor, RedactionError>,
+        control: &RunControl,
+    ) -> (RunResult, CollectedOutput) {
+        // Kept until the command exited; dropping it removes the script.
+        let (mut command, _script) = match &job.script {
+            Some(job_script) => match script::prepare(job_script, &step.args).await {
+                Ok((command, file)) => (command, Some(file)),
+                Err(e) => return (RunResult::Exited(Err(e)), CollectedOutput::default()),
+            },
+            None => (
+                get_agent_shell().build_command(&step.command, &step.args),
+                None,
+            ),
+        };
+        // Jobs with a container run the command in it, with the step's directory and environment.
+        let container_name = match &job.container {
+            Some(container) => {
+                let name = container_run::container_name(&job.job_name);
+                match container_run::wrap(&command, container, step, &name) {
+                    Ok(wrapped) => command = wrapped,
+                    Err(e) => return (RunResult::Exited(Err(e)), CollectedOutput::default()),
+                }
+                Some(name)
+            }
+            None => {
+                if let Some(cwd) = &step.cwd {
+                    command.current_dir(cwd);
+                }
+                for var in &step.env {
+                    match var.split_once('=') {
+                        Some((key, value)) => command.env(key, value),
+                        None => command.env(var, ""),
+                    };
+                }
+                None
+            }
+        };
+        command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        process::configure_process_group(&mut command);
+
+        match command.spawn() {
+            Ok(mut child) => {
+                let pid = child.id();
+                // Only spill when every redaction pattern can be applied to the artifact.
+                let spill_redactor = redactor.as_ref().ok().cloned();
+                let stdout = Self::collect_output(
+                    child.stdout.take(),
+                    &job.job_name,
+                    start_time,
+                    "stdout",
+                    spill_redactor.clone(),
+                );
+                let stderr = Self::collect_output(
+                    child.stderr.take(),
+                    &job.job_name,
+                    start_time,
+                    "stderr",
+                    spill_redactor,
+                );
+
+                let result = Self::wait_for_child(child, started, control).await;
+                if !matches!(result, RunResult::Exited(_)) {
+                    if let Some(pid) = pid {
+                        process::kill_process_tree(pid).await;
+                    }
+                    if let Some(name) = &container_name {
+                        container_run::stop(name).await;
                     }
                 }
-                None => String::new(),
-            };
+                let stdout = stdout.await.unwrap_or_default();
+                let stderr = stderr.await.unwrap_or_default();
+                (result, Self::select_output(stdout, stderr).await)
+            }
+            Err(e) => (RunResult::Exited(Err(e)), CollectedOutput::default()),
+        }
+    }
 
-            let end_time = DateTime::now();
-
-            let job_complete = JobComplete {
-                started_at: start_time.timestamp_millis(),
-                completed_at: end_time.timestamp_millis(),
-                job_name: job_name.clone(),
-                agent_name: get_agent_name(),
-                outcome,
-                command: format!("{} {}", command_name, args),
-                return_code,
-                output,
+    /// Starts reading one of the child's output streams, spilling it to an artifact when
+    /// `AGENT_OUTPUT_ARTIFACT_DIR` is set and `redactor` is available.
+    fn collect_output<R: AsyncRead + Unpin + Send + 'static>(
+        reader: Option<R>,
+        job_name: &str,
+        start_time: &DateTime,
+        stream: &str,
+        redactor: Option<Redactor>,
+    ) -> JoinHandle<CollectedOutput> {
+        let spill_path = get_agent_output_artifact_dir()
+            .filter(|_| redactor.is_some())
+            .map(|dir| Self::artifact_path(dir, job_name, start_time, stream));
+        let job_name = job_name.to_string();
+        let stream = stream.to_string();
+        spawn(async move {
+            let Some(reader) = reader else {
+                return CollectedOutput::default();
             };
-
-            if let Err(e) = sender.send(job_complete).await {
-                error!("Failed to send job name: {}", e);
+            let redactor = redactor.unwrap_or_default();
+            match output::collect(reader, get_agent_max_output_bytes(), spill_path, &redactor).await
+            {
+                Ok(collected) => collected,
+                Err(e) => {
+                    error!("Failed to read {} of job {}: {}", stream, job_name, e);
+                    CollectedOutput::default()
+                }
             }
-        });
+        })
+    }
+
+    /// `<dir>/<job name>-<start millis>-<stream>.log`, with the job name made safe for a file name.
+    fn artifact_path(
+        dir: &std::path::Path,
+        job_name: &str,
+        start_time: &DateTime,
+        stream: &str,
+    ) -> PathBuf {
+        let safe_name: String = job_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        dir.join(format!(
+            "{}-{}-{}.log",
+            safe_name,
+            start_time.timestamp_millis(),
+            stream
+        ))
+    }
+
+    /// Reports stderr when the job wrote any, otherwise stdout, removing the other's artifact.
+    async fn select_output(stdout: CollectedOutput, stderr: CollectedOutput) -> CollectedOutput {
+        let (selected, discarded) = if !stderr.text.is_empty() {
+            (stderr, stdout)
+        } else {
+            (stdout, stderr)
+        };
+        if let Some(path) = discarded.artifact
+            && let Err(e) = tokio::fs::remove_file(&path).await
+        {
+            warn!("Unable to remove output artifact {}: {}", path.display(), e);
+        }
+        selected
+    }
+
+    /// Waits for the child to exit, the job's timeout (counted from `started`) to elapse, or the
+    /// job to be cancelled.
+    async fn wait_for_child(
+        mut child: tokio::process::Child,
+        started: Instant,
+        control: &RunControl,
+    ) -> RunResult {
+        tokio::select! {
+            status = child.wait() => RunResult::Exited(status),
+            timeout = control.timeout_elapsed(started) => RunResult::TimedOut(timeout),
+            _ = control.cancelled() => RunResult::Cancelled,
+        }
     }
 }
@@ -0,0 +1,1375 @@
+//! # Rust Action Dispatch Agent
+//!
+//! This crate implements an agent for a distributed action dispatch system. The agent connects to a central command server,
+//! registers itself, listens for incoming job dispatch requests, and executes jobs as instructed.
+//!
+//! ## Features
+//! - Connects and registers with a central command server.
+//! - Listens for incoming TCP connections for job dispatch requests.
+//! - Handles job execution and communication with the central server.
+//! - Automatic reconnection logic for central command server failures.
+//! - Reverse dispatch and an optional message bus transport for agents that central command cannot dial.
+//! - Pull mode, in which the agent polls central command for its jobs instead of having them pushed.
+//!
+//! ## Environment Variables
+//! - `AGENT_PORT`: The port central command dials to reach the agent (default: 8081). If it is
+//!   already in use the agent listens on an ephemeral port instead and reports that port when it
+//!   registers.
+//! - `AGENT_PORT_STRICT`: When `true`, the agent exits with an error instead of falling back to an
+//!   ephemeral port if `AGENT_PORT` is in use (e.g. when firewall rules only allow that port).
+//! - `AGENT_LISTEN_ADDRESSES`: Comma separated addresses the agent listens on, e.g.
+//!   `127.0.0.1:8081,10.0.0.5:8081` (default: `[::]:<AGENT_PORT>`). At least one should use `AGENT_PORT`.
+//! - `CENTRAL_COMMAND_ADDRESS`: Address of the central command server (default: "127.0.0.1:8080").
+//! - `AGENT_REVERSE_DISPATCH`: When `true`, the agent opens no listeners and receives dispatches over
+//!   its own connection to central command, so `AGENT_PORT` does not need to be reachable.
+//! - `AGENT_PULL`: When `true`, the agent opens no listeners and polls central command for its jobs
+//!   over its own connection, claiming them itself, which suits agents that are only connected
+//!   now and then (see `reverse_dispatch`) (default: `false`).
+//! - `AGENT_POLL_INTERVAL_SECONDS`: Seconds between polls in pull mode; each poll also tells
+//!   central command the agent is online (default: 5).
+//! - `AGENT_POLL_MAX_JOBS`: Most jobs central command hands out in answer to one poll (default: 4).
+//! - `AGENT_MULTIPLEX`: When `true`, messages to central command are sent in `Envelope`s without
+//!   waiting for each to be acknowledged before the next, and acknowledgments are matched to them
+//!   by id (see Multiplexing in `core_logic::messages`). Job results still leave the spool only
+//!   once acknowledged. Needs a central command that understands envelopes (default: `false`).
+//! - `AGENT_FRAME_CHECKSUMS`: When `true`, frames sent to central command carry a CRC32 of their
+//!   body, so corrupted messages are detected and sent again (see `core_logic::framing`). Set to
+//!   `false` for central commands that predate checksummed frames (default: `true`).
+//! - `MESSAGE_BUS_URL`: When set (e.g. `nats://127.0.0.1:4222`), the agent talks to central command
+//!   through the message bus instead of TCP and opens no listeners, so it only makes outbound
+//!   connections (see `core_logic::bus`).
+//! - `AGENT_AUTH_TOKEN`: Token presented to central command when it authenticates agents with a
+//!   shared token (see `auth`).
+//! - `AGENT_JWT_SVID_FILE`: A file holding the agent's SPIFFE JWT-SVID, presented to central command
+//!   when it authenticates agents with SPIFFE. Takes precedence over `AGENT_AUTH_TOKEN`.
+//! - `AGENT_HEALTH_ADDRESS`: When set (e.g. `0.0.0.0:9091`), the agent serves `/healthz` and
+//!   `/readyz` there, reporting its connection to central command and its dispatcher queue depth
+//!   (see `core_logic::health`).
+//! - `AGENT_NAME`: The name of the agent (default: "default_agent").
+//! - `AGENT_RECEIPT_KEY_FILE`: PKCS#8 file holding the Ed25519 key the agent signs job results with
+//!   (see `core_logic::receipts`). Generated on first start if it does not exist
+//!   (default: "agent_receipt_key.pk8").
+//! - `AGENT_SHELL`: How job commands are launched: `direct`, `sh`, `cmd` or `powershell` (default: "direct").
+//! - `AGENT_SCRIPT_INTERPRETERS`: Comma separated `interpreter=program` overrides for the programs
+//!   that run job scripts, e.g. `python=/usr/bin/python3.12` (see `script`).
+//! - `AGENT_CHUNK_SIZE`: Bytes written to central command at a time when sending a message
+//!   (default: 8192).
+//! - `AGENT_ADAPTIVE_CHUNKS`: When `true`, the chunk size adapts to the observed throughput, which
+//!   helps on high-latency links (see `core_logic::flow_control`) (default: `false`).
+//! - `AGENT_TCP_KEEPALIVE_SECONDS`: Seconds without traffic after which TCP keepalive probes are
+//!   sent on connections to and from central command, so half-open connections are reset
+//!   (see `core_logic::keepalive`); `0` keeps the OS default (default: 30).
+//! - `AGENT_IDLE_TIMEOUT_SECONDS`: Seconds a connection central command dialed may go without a
+//!   message before the agent drops it and accepts a new one. Central command pings every 5
+//!   seconds, so only dead connections go quiet this long; `0` waits forever (default: 30).
+//! - `AGENT_MAX_OUTPUT_BYTES`: Most output kept per job; longer output keeps its first and last halves
+//!   and the run is marked truncated (default: 1048576).
+//! - `AGENT_OUTPUT_ARTIFACT_DIR`: When set, the full output of jobs whose output was truncated is
+//!   written to a file in this directory and its path recorded on the run.
+//! - `AGENT_REDACTION_DEFAULTS`: When `false`, the built-in redaction patterns (see
+//!   `core_logic::redaction`) are not applied to job output (default: `true`).
+//! - `AGENT_REDACTION_PATTERNS_FILE`: A file of additional regular expressions, one per line, redacted
+//!   from the output of every job. Blank lines and lines starting with `#` are ignored.
+//! - `AGENT_SPOOL_FILE`: File job results are kept in until central command acknowledges them, so
+//!   results finished while it is unreachable survive an agent restart (see `spool`). Empty
+//!   disables the spool (default: "agent_spool").
+//! - `AGENT_SPOOL_MAX_BYTES`: Largest the spool may grow to; further results are only held in
+//!   memory. `0` is unlimited (default: 268435456).
+//! - `AGENT_TIMEOUT_WARNING_PERCENT`: Share of a job's timeout after which the agent sends central
+//!   command a `JobProgress` warning that the run is likely to be killed; `0` disables it
+//!   (default: 80).
+//! - `AGENT_REMOTE_SHELL`: When `true`, operators allowed by the web UI can open an interactive
+//!   shell on the agent through central command, running `$SHELL` (or `/bin/sh`) as the agent's
+//!   user (see `remote_shell`). Needs the listen port to be reachable (default: `false`).
+//! - `AGENT_NODE_NAME`, `AGENT_POD_NAME`, `AGENT_POD_NAMESPACE`: The Kubernetes node, pod and
+//!   namespace the agent runs in, set from the downward API (see `container`).
+//! - `AGENT_NODE_LABELS_FILE`: File of the Kubernetes node's labels, one `key="value"` per line,
+//!   that jobs can select nodes by (default: "/etc/podinfo/node-labels").
+//! - `AGENT_CONTAINER_RUNTIME`: The program jobs with a container are run with, `docker` or
+//!   `podman` (default: whichever of them is found on `PATH` first; see `container_run`).
+//! - `AGENT_SIMULATE`: When `true`, same as passing `--simulate`.
+//! - `AGENT_SIMULATE_DURATION_MS`: How long a simulated job takes, fixed (`1500`) or a random value
+//!   in a range (`500-5000`) (default: 1000).
+//! - `AGENT_SIMULATE_FAILURE_RATE`: Fraction of simulated jobs that fail (default: 0.1).
+//!
+//! ## Command Line
+//! - `--simulate`: Accept dispatches but execute nothing; each job waits for a fake duration and
+//!   reports a synthetic outcome and output. For load-testing central command and demoing the UI.
+//!
+//! ## Main Components
+//! - [`ConnectionManager`]: Manages connections to the central command server and handles incoming job requests.
+//! - [`CentralCommandWriter`]: Handles sending messages to the central command server with automatic reconnection.
+//! - [`JobDispatcher`]: Responsible for executing dispatched jobs (see `job_dispatch` module).
+//! - `auth`: Authenticates the agent's connections to central command.
+//! - `file_transfer`: Writes the files central command pushes ahead of the jobs that need them.
+//! - `updater`: Downloads, verifies and installs new agent binaries pushed by central command.
+//! - `output`: Collects job output up to `AGENT_MAX_OUTPUT_BYTES`, spilling the rest to an artifact.
+//! - `platform`: Detects the OS, architecture, kernel, shells and features reported at registration.
+//! - `container`: Detects the container runtime and, on Kubernetes, the node, pod, namespace and
+//!   node labels reported at registration.
+//! - `container_run`: Runs the commands of jobs with a container in `docker run` or `podman run`.
+//! - `remote_shell`: Interactive shells opened by operators on a pseudo-terminal.
+//! - `script`: Writes the scripts jobs carry to temporary files and builds the commands running them.
+//! - `reverse_dispatch`: Receives dispatches, or polls for jobs, over the agent's own connection to
+//!   central command.
+//! - `simulate`: Synthetic job results for `--simulate`.
+//! - `spool`: Persists job results until central command acknowledges them.
+//! - `process`: Platform specific process group handling, process-tree kills and exit code mapping
+//!   so the agent runs natively on both Unix and Windows hosts.
+//!
+//! ## Protocol
+//! - Messages are serialized and sent over TCP.
+//! - Each message sent to the central command server expects an "OK" reply.
+//!
+//! ## Logging
+//! - Uses the `tracing` crate for structured logging at various levels (info, debug, error).
+//! - `LOG_FORMAT=json` emits one JSON object per line, and `RUST_LOG` filters events (see
+//!   `core_logic::logging`). Each job's log lines carry its `run_id`, `job_name` and `agent_name`.
+//!
+//! ## Example Usage
+//! ```sh
+//! AGENT_PORT=9000 AGENT_NAME=my_agent cargo run
+//! AGENT_NAME=load_test_1 AGENT_SIMULATE_DURATION_MS=200-2000 cargo run -- --simulate
+//! ```
+//!
+//! ## Error Handling
+//! - Connection attempts to the central command server are retried up to 60 times with a 5-second delay between attempts.
+//! - Serialization and I/O errors are logged and handled gracefully.
+//!
+//! ## Extensibility
+//! - The agent is designed to be extended with additional message types and job handling logic.
+//!
+//! ## Dependencies
+//! - `tokio` for async networking
+//! - `tracing` for logging
+//! - `hostname` for retrieving the system hostname
+//! - `core_logic::communications` for message definitions
+mod auth;
+mod container;
+mod container_run;
+mod file_transfer;
+mod job_dispatch;
+mod output;
+mod platform;
+mod process;
+mod remote_shell;
+mod reverse_dispatch;
+mod script;
+mod simulate;
+mod spool;
+mod updater;
+
+use rkyv::rancor;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, sync::OnceLock};
+
+use core_logic::bus::{self, MessageBus};
+use core_logic::flow_control::ChunkSizer;
+use core_logic::framing;
+use core_logic::health::Health;
+use core_logic::keepalive;
+use core_logic::messages::{
+    ArchivedMessage, Envelope, Message, MessageBuffer, Priority, RegisterAgent,
+};
+use core_logic::priority::{PriorityLock, PriorityReceiver};
+use core_logic::receipts::ReceiptSigner;
+use core_logic::redaction::{DEFAULT_REDACTION_PATTERNS, Redactor};
+use job_dispatch::ShellMode;
+use reverse_dispatch::CentralCommandStream;
+use spool::Spool;
+
+pub const DEFAULT_CENTRAL_COMMAND_ADDRESS: &str = "127.0.0.1:8080";
+pub const VERSION: &str = "0.1.0";
+
+static AGENT_PORT: OnceLock<u16> = OnceLock::new();
+static AGENT_PORT_STRICT: OnceLock<bool> = OnceLock::new();
+static AGENT_LISTEN_ADDRESSES: OnceLock<Vec<String>> = OnceLock::new();
+static CENTRAL_COMMAND_ADDRESS: OnceLock<String> = OnceLock::new();
+static MESSAGE_BUS_URL: OnceLock<Option<String>> = OnceLock::new();
+static AGENT_REVERSE_DISPATCH: OnceLock<bool> = OnceLock::new();
+static AGENT_PULL: OnceLock<bool> = OnceLock::new();
+static AGENT_POLL_INTERVAL_SECONDS: OnceLock<u64> = OnceLock::new();
+static AGENT_POLL_MAX_JOBS: OnceLock<u32> = OnceLock::new();
+static AGENT_MULTIPLEX: OnceLock<bool> = OnceLock::new();
+static AGENT_FRAME_CHECKSUMS: OnceLock<bool> = OnceLock::new();
+static AGENT_NAME: OnceLock<String> = OnceLock::new();
+static AGENT_AUTH_TOKEN: OnceLock<Option<String>> = OnceLock::new();
+static AGENT_JWT_SVID_FILE: OnceLock<Option<PathBuf>> = OnceLock::new();
+static AGENT_SHELL: OnceLock<ShellMode> = OnceLock::new();
+static AGENT_REDACTOR: OnceLock<Redactor> = OnceLock::new();
+static AGENT_MAX_OUTPUT_BYTES: OnceLock<usize> = OnceLock::new();
+static AGENT_OUTPUT_ARTIFACT_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+static AGENT_RECEIPT_SIGNER: OnceLock<ReceiptSigner> = OnceLock::new();
+static AGENT_SPOOL: OnceLock<Option<Spool>> = OnceLock::new();
+static AGENT_HEALTH: OnceLock<Health> = OnceLock::new();
+static AGENT_TIMEOUT_WARNING_PERCENT: OnceLock<u8> = OnceLock::new();
+static AGENT_REMOTE_SHELL: OnceLock<bool> = OnceLock::new();
+static AGENT_SIMULATE: OnceLock<bool> = OnceLock::new();
+static AGENT_CONTAINER_RUNTIME: OnceLock<Option<String>> = OnceLock::new();
+static AGENT_CHUNK_SIZE: OnceLock<usize> = OnceLock::new();
+static AGENT_ADAPTIVE_CHUNKS: OnceLock<bool> = OnceLock::new();
+static AGENT_TCP_KEEPALIVE_SECONDS: OnceLock<u64> = OnceLock::new();
+static AGENT_IDLE_TIMEOUT_SECONDS: OnceLock<u64> = OnceLock::new();
+
+const HEARTBEAT_SECS: u64 = 10; // Interval between pings when central command cannot ping the agent
+const DISPATCH_CHANNEL_CAPACITY: usize = 100;
+const MAX_IN_FLIGHT: usize = 64; // Unacknowledged messages on a multiplexed connection
+const MAX_REJECTED_SENDS: usize = 3; // Times a message central command could not read is resent
+
+fn get_agent_port() -> u16 {
+    *AGENT_PORT.get_or_init(|| {
+        env::var("AGENT_PORT")
+            .unwrap_or("8081".to_string())
+            .parse()
+            .expect("Invalid AGENT_PORT")
+    })
+}
+
+fn get_agent_port_strict() -> bool {
+    *AGENT_PORT_STRICT.get_or_init(|| {
+        env::var("AGENT_PORT_STRICT")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+fn get_agent_listen_addresses() -> &'static [String] {
+    AGENT_LISTEN_ADDRESSES.get_or_init(|| {
+        let addresses: Vec<String> = env::var("AGENT_LISTEN_ADDRESSES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|address| !address.is_empty())
+            .map(str::to_string)
+            .collect();
+        if addresses.is_empty() {
+            vec![format!("[::]:{}", get_agent_port())]
+        } else {
+            addresses
+        }
+    })
+}
+
+fn get_central_command_address() -> &'static str {
+    CENTRAL_COMMAND_ADDRESS.get_or_init(|| {
+        env::var("CENTRAL_COMMAND_ADDRESS")
+            .unwrap_or_else(|_| DEFAULT_CENTRAL_COMMAND_ADDRESS.to_string())
+    })
+}
+
+fn get_message_bus_url() -> Option<&'static str> {
+    MESSAGE_BUS_URL
+        .get_or_init(|| env::var("MESSAGE_BUS_URL").ok())
+        .as_deref()
+}
+
+fn get_reverse_dispatch() -> bool {
+    *AGENT_REVERSE_DISPATCH.get_or_init(|| {
+        env::var("AGENT_REVERSE_DISPATCH")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+fn get_agent_pull() -> bool {
+    *AGENT_PULL.get_or_init(|| {
+        env::var("AGENT_PULL")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+fn get_agent_poll_interval_seconds() -> u64 {
+    *AGENT_POLL_INTERVAL_SECONDS.get_or_init(|| {
+        let seconds = env::var("AGENT_POLL_INTERVAL_SECONDS")
+            .unwrap_or("5".to_string())
+            .parse()
+            .expect("Invalid AGENT_POLL_INTERVAL_SECONDS");
+        assert!(seconds > 0, "AGENT_POLL_INTERVAL_SECONDS must be positive");
+        seconds
+    })
+}
+
+fn get_agent_poll_max_jobs() -> u32 {
+    *AGENT_POLL_MAX_JOBS.get_or_init(|| {
+        env::var("AGENT_POLL_MAX_JOBS")
+            .unwrap_or("4".to_string())
+            .parse()
+            .expect("Invalid AGENT_POLL_MAX_JOBS")
+    })
+}
+
+fn get_agent_multiplex() -> bool {
+    *AGENT_MULTIPLEX.get_or_init(|| {
+        env::var("AGENT_MULTIPLEX")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+fn get_agent_frame_checksums() -> bool {
+    *AGENT_FRAME_CHECKSUMS.get_or_init(|| {
+        env::var("AGENT_FRAME_CHECKSUMS")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(true)
+    })
+}
+
+/// The header of the frame carrying `serialized` to central command, checksummed unless
+/// `AGENT_FRAME_CHECKSUMS` is off.
+pub fn frame_header(serialized: &[u8]) -> Vec<u8> {
+    match get_agent_frame_checksums() {
+        true => framing::frame_header(serialized).to_vec(),
+        false => (serialized.len() as u32).to_be_bytes().to_vec(),
+    }
+}
+
+pub fn get_agent_name() -> String {
+    AGENT_NAME
+        .get_or_init(|| env::var("AGENT_NAME").unwrap_or_else(|_| "default_agent".to_string()))
+        .to_string()
+}
+
+fn get_agent_auth_token() -> Option<&'static str> {
+    AGENT_AUTH_TOKEN
+        .get_or_init(|| {
+            env::var("AGENT_AUTH_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty())
+        })
+        .as_deref()
+}
+
+fn get_agent_jwt_svid_file() -> Option<&'static Path> {
+    AGENT_JWT_SVID_FILE
+        .get_or_init(|| env::var("AGENT_JWT_SVID_FILE").ok().map(PathBuf::from))
+        .as_deref()
+}
+
+pub fn get_agent_shell() -> ShellMode {
+    *AGENT_SHELL.get_or_init(|| {
+        env::var("AGENT_SHELL")
+            .unwrap_or_else(|_| "direct".to_string())
+            .as_str()
+            .into()
+    })
+}
+
+pub fn get_agent_max_output_bytes() -> usize {
+    *AGENT_MAX_OUTPUT_BYTES.get_or_init(|| {
+        env::var("AGENT_MAX_OUTPUT_BYTES")
+            .unwrap_or("1048576".to_string())
+            .parse()
+            .expect("Invalid AGENT_MAX_OUTPUT_BYTES")
+    })
+}
+
+pub fn get_agent_output_artifact_dir() -> Option<&'static Path> {
+    AGENT_OUTPUT_ARTIFACT_DIR
+        .get_or_init(|| {
+            env::var("AGENT_OUTPUT_ARTIFACT_DIR")
+                .ok()
+                .map(PathBuf::from)
+        })
+        .as_deref()
+}
+
+/// Redaction applied to every job's output: the built-in defaults plus `AGENT_REDACTION_PATTERNS_FILE`.
+pub fn get_agent_redactor() -> &'static Redactor {
+    AGENT_REDACTOR.get_or_init(|| {
+        let mut patterns: Vec<String> = match env::var("AGENT_REDACTION_DEFAULTS") {
+            Ok(value) if matches!(value.to_lowercase().as_str(), "0" | "false" | "no") => vec![],
+            _ => DEFAULT_REDACTION_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+        };
+        if let Ok(path) = env::var("AGENT_REDACTION_PATTERNS_FILE") {
+            let contents = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Unable to read {}: {}", path, e));
+            patterns.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+        Redactor::new(&patterns).expect("Invalid AGENT_REDACTION_PATTERNS_FILE")
+    })
+}
+
+/// Key the agent signs job results with, loaded from (or generated at) `AGENT_RECEIPT_KEY_FILE`.
+pub fn get_agent_receipt_signer() -> &'static ReceiptSigner {
+    AGENT_RECEIPT_SIGNER.get_or_init(|| {
+        let path = env::var("AGENT_RECEIPT_KEY_FILE")
+            .unwrap_or_else(|_| "agent_receipt_key.pk8".to_string());
+        ReceiptSigner::load_or_generate(Path::new(&path))
+            .unwrap_or_else(|e| panic!("Unable to load receipt key: {}", e))
+    })
+}
+
+/// Checks reported on `AGENT_HEALTH_ADDRESS`.
+/// Spool of undelivered job results at `AGENT_SPOOL_FILE`, or `None` when it is disabled or cannot
+/// be opened.
+pub fn get_agent_spool() -> Option<&'static Spool> {
+    AGENT_SPOOL
+        .get_or_init(|| {
+            let path = env::var("AGENT_SPOOL_FILE").unwrap_or_else(|_| "agent_spool".to_string());
+            if path.is_empty() {
+                return None;
+            }
+            let max_bytes = env::var("AGENT_SPOOL_MAX_BYTES")
+                .unwrap_or((256 * 1024 * 1024).to_string())
+                .parse()
+                .expect("Invalid AGENT_SPOOL_MAX_BYTES");
+            Spool::open(PathBuf::from(&path), max_bytes)
+                .inspect_err(|e| {
+                    error!(
+                        "Unable to open spool {}, results are not persisted: {}",
+                        path, e
+                    )
+                })
+                .ok()
+        })
+        .as_ref()
+}
+
+pub fn get_agent_health() -> &'static Health {
+    AGENT_HEALTH.get_or_init(|| Health::new(&["central_command"]))
+}
+
+pub fn get_agent_timeout_warning_percent() -> u8 {
+    *AGENT_TIMEOUT_WARNING_PERCENT.get_or_init(|| {
+        let percent = env::var("AGENT_TIMEOUT_WARNING_PERCENT")
+            .unwrap_or("80".to_string())
+            .parse()
+            .expect("Invalid AGENT_TIMEOUT_WARNING_PERCENT");
+        assert!(
+            percent < 100,
+            "AGENT_TIMEOUT_WARNING_PERCENT must be less than 100"
+        );
+        percent
+    })
+}
+
+pub fn get_agent_remote_shell() -> bool {
+    *AGENT_REMOTE_SHELL.get_or_init(|| {
+        env::var("AGENT_REMOTE_SHELL")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+pub fn get_agent_container_runtime() -> Option<&'static str> {
+    AGENT_CONTAINER_RUNTIME
+        .get_or_init(|| match env::var("AGENT_CONTAINER_RUNTIME") {
+            Ok(runtime) if !runtime.is_empty() => Some(runtime),
+            _ => ["docker", "podman"]
+                .into_iter()
+                .find(|runtime| platform::on_path(runtime))
+                .map(String::from),
+        })
+        .as_deref()
+}
+
+pub fn get_agent_simulate() -> bool {
+    *AGENT_SIMULATE.get_or_init(|| {
+        env::args().any(|arg| arg == "--simulate")
+            || env::var("AGENT_SIMULATE")
+                .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false)
+    })
+}
+
+fn get_agent_chunk_size() -> usize {
+    *AGENT_CHUNK_SIZE.get_or_init(|| {
+        env::var("AGENT_CHUNK_SIZE")
+            .unwrap_or("8192".to_string())
+            .parse()
+            .expect("Invalid AGENT_CHUNK_SIZE")
+    })
+}
+
+fn get_agent_adaptive_chunks() -> bool {
+    *AGENT_ADAPTIVE_CHUNKS.get_or_init(|| {
+        env::var("AGENT_ADAPTIVE_CHUNKS")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+fn get_agent_tcp_keepalive_seconds() -> u64 {
+    *AGENT_TCP_KEEPALIVE_SECONDS.get_or_init(|| {
+        env::var("AGENT_TCP_KEEPALIVE_SECONDS")
+            .unwrap_or("30".to_string())
+            .parse()
+            .expect("Invalid AGENT_TCP_KEEPALIVE_SECONDS")
+    })
+}
+
+fn get_agent_idle_timeout_seconds() -> u64 {
+    *AGENT_IDLE_TIMEOUT_SECONDS.get_or_init(|| {
+        env::var("AGENT_IDLE_TIMEOUT_SECONDS")
+            .unwrap_or("30".to_string())
+            .parse()
+            .expect("Invalid AGENT_IDLE_TIMEOUT_SECONDS")
+    })
+}
+
+/// Enables keepalive on a connection to or from central command, logging if the OS refuses.
+fn enable_keepalive(stream: &TcpStream) {
+    let idle = Duration::from_secs(get_agent_tcp_keepalive_seconds());
+    if let Err(e) = keepalive::enable(stream, idle) {
+        warn!("Failed to enable TCP keepalive: {}", e);
+    }
+}
+
+/// Resolves once a connection central command dialed has gone `AGENT_IDLE_TIMEOUT_SECONDS` without
+/// a message, or never when it is `0`.
+async fn idle_timeout() {
+    match get_agent_idle_timeout_seconds() {
+        0 => std::future::pending().await,
+        seconds => tokio::time::sleep(Duration::from_secs(seconds)).await,
+    }
+}
+
+/// IANA name of the host's local timezone, falling back to UTC when it cannot be determined.
+fn get_timezone() -> String {
+    iana_time_zone::get_timezone().unwrap_or_else(|e| {
+        warn!("Unable to determine local timezone, reporting UTC: {}", e);
+        "UTC".to_string()
+    })
+}
+
+/// Locale of the agent process as reported by the usual POSIX environment variables.
+fn get_locale() -> String {
+    ["LC_ALL", "LC_TIME", "LANG"]
+        .iter()
+        .filter_map(|key| env::var(key).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+}
+
+fn display_agent_info() {
+    info!("-------------------------------------------------");
+    info!("\tRust Action Dispatch Agent");
+    info!("-------------------------------------------------");
+    info!(
+        "\tAgent Name: {} Port: {} Version: {}",
+        get_agent_name(),
+        get_agent_port(),
+        VERSION
+    );
+    match get_message_bus_url() {
+        Some(url) => info!("\tMessage Bus: {}", url),
+        None if get_agent_pull() => info!(
+            "\tPulling jobs every {} seconds from Central Command: {}",
+            get_agent_poll_interval_seconds(),
+            get_central_command_address()
+        ),
+        None if get_reverse_dispatch() => info!(
+            "\tReverse Dispatch via Central Command: {}",
+            get_central_command_address()
+        ),
+        None => info!(
+            "\tListening on: {} Central Command: {}",
+            get_agent_listen_addresses().join(", "),
+            get_central_command_address()
+        ),
+    }
+    info!(
+        "\tPlatform: {}/{} Kernel: {} Shells: {}",
+        platform::os(),
+        platform::arch(),
+        platform::kernel(),
+        platform::shells().join(", ")
+    );
+    match container::detect() {
+        Some(container) if container.runtime == "kubernetes" => info!(
+            "\tContainer: kubernetes pod {} in {} on node {} ({} node labels)",
+            container.pod_name.as_deref().unwrap_or("unknown"),
+            container.pod_namespace.as_deref().unwrap_or("unknown"),
+            container.node_name.as_deref().unwrap_or("unknown"),
+            container.node_labels.len()
+        ),
+        Some(container) => info!("\tContainer: {}", container.runtime),
+        None => {}
+    }
+    info!("\tShell: {:?}", get_agent_shell());
+    info!(
+        "\tRedaction: {}",
+        if get_agent_redactor().is_empty() {
+            "disabled"
+        } else {
+            "enabled"
+        }
+    );
+    info!("\tTimezone: {} Locale: {}", get_timezone(), get_locale());
+    if get_agent_simulate() {
+        warn!("\tSIMULATION MODE: jobs are not executed, results are synthetic");
+    }
+    info!("-------------------------------------------------");
+}
+
+/// Runs the agent until it fails. Logging is set up by the caller (see `core_logic::logging`).
+pub async fn run() -> io::Result<()> {
+    display_agent_info();
+
+    if let Ok(address) = env::var("AGENT_HEALTH_ADDRESS") {
+        tokio::spawn(get_agent_health().clone().serve(address));
+    }
+
+    let mut connection_manager = ConnectionManager::try_new().await.map_err(|e| {
+        error!("Failed to create connection manager: {}", e);
+        e
+    })?;
+
+    connection_manager.register().await;
+    connection_manager.listen().await?;
+
+    Ok(())
+}
+
+/// Manages the application's connections, including the central command writer and job dispatcher.
+///
+/// # Fields
+/// - `central_command_writer`: Shared, thread-safe writer for sending commands to the central system.
+///   Heartbeats take it ahead of queued job results (see `core_logic::priority`).
+/// - `job_dispatcher`: Responsible for dispatching jobs to appropriate handlers.
+/// - `dispatches`: Messages pushed by central command when using reverse dispatch, or handed out
+///   in answer to polls in pull mode.
+/// - `listeners`: Listeners central command dials, bound before registering.
+/// - `agent_port`: The port reported to central command, which differs from `AGENT_PORT` when that
+///   port was in use.
+pub struct ConnectionManager {
+    central_command_writer: Arc<PriorityLock<CentralCommandWriter>>,
+    job_dispatcher: job_dispatch::JobDispatcher,
+    dispatches: Option<mpsc::Receiver<Message>>,
+    listeners: Vec<TcpListener>,
+    agent_port: u16,
+}
+
+/// Sends messages to central command, over TCP or over the message bus when `MESSAGE_BUS_URL` is set.
+/// With reverse dispatch, messages pushed over the TCP connection are forwarded to `dispatches`.
+/// With `AGENT_MULTIPLEX`, messages are written as `Envelope`s without waiting for each to be
+/// acknowledged; `in_flight` holds those not yet acknowledged, written again after a reconnect.
+pub struct CentralCommandWriter {
+    stream: Option<CentralCommandStream>,
+    bus: Option<Arc<dyn MessageBus>>,
+    dispatches: Option<mpsc::Sender<Message>>,
+    chunk_sizer: ChunkSizer,
+    in_flight: VecDeque<(u64, Vec<u8>)>, // Envelope ids and their serialized messages
+    next_id: u64,
+}
+
+impl CentralCommandWriter {
+    pub async fn try_new(dispatches: Option<mpsc::Sender<Message>>) -> Result<Self, io::Error> {
+        if let Some(url) = get_message_bus_url() {
+            let bus = bus::connect(url).await.map_err(|e| {
+                get_agent_health().set("central_command", false, e.to_string());
+                io::Error::other(e)
+            })?;
+            info!("Connected to message bus at {}", url);
+            get_agent_health().set(
+                "central_command",
+                true,
+                format!("connected to message bus at {}", url),
+            );
+            return Ok(Self {
+                stream: None,
+                bus: Some(bus),
+                dispatches: None,
+                chunk_sizer: ChunkSizer::new(get_agent_chunk_size(), get_agent_adaptive_chunks()),
+                in_flight: VecDeque::new(),
+                next_id: 0,
+            });
+        }
+
+        let mut writer = Self {
+            stream: None,
+            bus: None,
+            dispatches,
+            chunk_sizer: ChunkSizer::new(get_agent_chunk_size(), get_agent_adaptive_chunks()),
+            in_flight: VecDeque::new(),
+            next_id: 0,
+        };
+        writer.stream = Some(writer.connect().await?);
+
+        Ok(writer)
+    }
+
+    /// Connects to central command, asking it to dispatch over the connection if enabled.
+    async fn connect(&self) -> io::Result<CentralCommandStream> {
+        let result = self.open_stream().await;
+        match &result {
+            Ok(_) => get_agent_health().set(
+                "central_command",
+                true,
+                format!("connected to {}", get_central_command_address()),
+            ),
+            Err(e) => get_agent_health().set("central_command", false, e.to_string()),
+        }
+        result
+    }
+
+    async fn open_stream(&self) -> io::Result<CentralCommandStream> {
+        let mut stream = Self::connect_to_central_command().await?;
+        auth::authenticate(&mut stream).await?;
+        match (&self.dispatches, get_agent_multiplex()) {
+            (Some(dispatches), _) if get_agent_pull() => {
+                reverse_dispatch::open_pull(stream, dispatches.clone()).await
+            }
+            (Some(dispatches), _) => reverse_dispatch::open(stream, dispatches.clone()).await,
+            (None, true) => Ok(reverse_dispatch::duplex(stream, None)),
+            (None, false) => Ok(CentralCommandStream::Direct(stream)),
+        }
+    }
+
+    pub fn bus(&self) -> Option<Arc<dyn MessageBus>> {
+        self.bus.clone()
+    }
+
+    fn stream(&mut self) -> io::Result<&mut CentralCommandStream> {
+        self.stream.as_mut().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Not connected to central command",
+            )
+        })
+    }
+
+    pub async fn connect_to_central_command() -> io::Result<TcpStream> {
+        const MAX_ATTEMPTS: usize = 60;
+        const RETRY_DELAY: u64 = 5;
+
+        let mut attempts = 0;
+        loop {
+            info!("Attempting to connect to central command...");
+            match TcpStream::connect(get_central_command_address()).await {
+                Ok(stream) => {
+                    info!("Reconnected to central command.");
+                    enable_keepalive(&stream);
+                    return Ok(stream);
+                }
+                Err(e) => {
+                    info!("Failed to connect to central command: {}", e);
+                    attempts += 1;
+                    if attempts >= MAX_ATTEMPTS {
+                        error!(
+                            "Failed to reconnect to central command after {} attempts: {}",
+                            e, attempts
+                        );
+                        return Err(e);
+                    }
+                    tokio::time::sleep(tokio::time::Duration::from_secs(RETRY_DELAY)).await;
+                }
+            }
+        }
+    }
+
+    pub async fn reconnect_to_central_command(&mut self) -> io::Result<()> {
+        self.stream = Some(self.connect().await?);
+        Ok(())
+    }
+
+    /// Sends `message`, then replays any job results spooled while central command was unreachable.
+    pub async fn write(&mut self, message: Message) {
+        if self.deliver(&message).await {
+            self.flush_spool().await;
+        }
+    }
+
+    /// Sends the spooled job results in order, stopping at the first that cannot be delivered.
+    pub async fn flush_spool(&mut self) {
+        let Some(spool) = get_agent_spool() else {
+            return;
+        };
+        loop {
+            let spooled = match spool.front().await {
+                Ok(Some(spooled)) => spooled,
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Failed to read spooled result: {}", e);
+                    break;
+                }
+            };
+            let delivered = match get_agent_multiplex() && self.bus.is_none() {
+                true => self.deliver_multiplexed(&spooled.message, true).await,
+                false => self.deliver(&spooled.message).await,
+            };
+            if !delivered {
+                break;
+            }
+            if let Err(e) = spool.pop(&spooled).await {
+                error!("Failed to remove delivered result from spool: {}", e);
+                break;
+            }
+        }
+    }
+
+    /// Sends `message`, reconnecting as needed. Returns `false` if central command could not be
+    /// reached, so the message should be sent again later.
+    async fn deliver(&mut self, message: &Message) -> bool {
+        if let Some(bus) = &self.bus {
+            let subject = bus::central_subject(&get_agent_name());
+            let authenticate = match auth::authenticate_message() {
+                Ok(authenticate) => authenticate,
+                Err(e) => {
+                    error!("Failed to read the agent's credential: {}", e);
+                    get_agent_health().set("central_command", false, e.to_string());
+                    return false;
+                }
+            };
+            let authenticated = match authenticate {
+                Some(authenticate) => bus.publish(subject.clone(), authenticate).await,
+                None => Ok(()),
+            };
+            let published = match authenticated {
+                Ok(()) => bus.publish(subject, message.clone()).await,
+                Err(e) => Err(e),
+            };
+            match published {
+                Ok(()) => {
+                    debug!("Sent message to central command: {:?}", message);
+                    get_agent_health().set(
+                        "central_command",
+                        true,
+                        format!(
+                            "connected to message bus at {}",
+                            get_message_bus_url().unwrap_or_default()
+                        ),
+                    );
+                }
+                Err(e) => {
+                    error!("Failed to publish message to central command: {}", e);
+                    get_agent_health().set("central_command", false, e.to_string());
+                    return false;
+                }
+            }
+            return true;
+        }
+        if get_agent_multiplex() {
+            return self.deliver_multiplexed(message, false).await;
+        }
+
+        let serialized = match Self::serialize_message(message) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to serialize message: {}", e);
+                return true; // Sending it again would fail the same way
+            }
+        };
+
+        let header = frame_header(&serialized);
+
+        let mut rejected = 0;
+        loop {
+            if let Err(e) = self.write_frame_header(&header).await {
+                error!("Error writing frame header: {}", e);
+                if self.try_reconnect().await.is_err() {
+                    return false;
+                }
+                continue;
+            }
+
+            if let Err(e) = self.write_message_chunks(&serialized).await {
+                error!("Error writing message chunks: {}", e);
+                if self.try_reconnect().await.is_err() {
+                    return false;
+                }
+                continue;
+            }
+
+            match self.read_ok_reply().await {
+                Ok(true) => break,
+                // Central command could not read the message, e.g. it failed its checksum.
+                Ok(false) if rejected < MAX_REJECTED_SENDS => {
+                    rejected += 1;
+                    warn!("Central command could not read the message, sending it again");
+                }
+                Ok(false) => {
+                    error!("Central command could not read the message, giving up");
+                    break;
+                }
+                Err(e) => {
+                    error!("Error reading reply: {}", e);
+                    if self.try_reconnect().await.is_err() {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        debug!("Sent message to central command: {:?}", message);
+        true
+    }
+
+    /// Sends `message` in an `Envelope` without waiting for central command to acknowledge it,
+    /// unless `acknowledged` is set, reconnecting as needed. Returns `false` if central command
+    /// could not be reached; messages it had not acknowledged are then dropped.
+    async fn deliver_multiplexed(&mut self, message: &Message, acknowledged: bool) -> bool {
+        let id = self.next_id;
+        let serialized = match Envelope::request(id, message)
+            .and_then(|envelope| Self::serialize_message(&Message::Envelope(envelope)))
+        {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to serialize message: {}", e);
+                return true; // Sending it again would fail the same way
+            }
+        };
+        self.next_id = self.next_id.wrapping_add(1);
+        self.in_flight.push_back((id, serialized));
+
+        let mut unwritten = self.in_flight.len() - 1;
+        loop {
+            match self
+                .write_in_flight(unwritten, acknowledged.then_some(id))
+                .await
+            {
+                Ok(()) => break,
+                Err(e) => {
+                    error!("Error sending message to central command: {}", e);
+                    if self.try_reconnect().await.is_err() {
+                        warn!(
+                            "Dropping {} messages central command did not acknowledge",
+                            self.in_flight.len()
+                        );
+                        self.in_flight.clear();
+                        return false;
+                    }
+                    // Nothing written on the old connection is known to have arrived.
+                    unwritten = 0;
+                }
+            }
+        }
+        debug!("Sent message {} to central command: {:?}", id, message);
+        true
+    }
+
+    /// Writes the in flight messages from `from` on, then waits until `wait_for` is acknowledged
+    /// and no more than `MAX_IN_FLIGHT` messages are waiting to be.
+    async fn write_in_flight(&mut self, from: usize, wait_for: Option<u64>) -> io::Result<()> {
+        let unwritten: Vec<Vec<u8>> = self
+            .in_flight
+            .range(from..)
+            .map(|(_, serialized)| serialized.clone())
+            .collect();
+        for serialized in unwritten {
+            self.write_frame_header(&frame_header(&serialized)).await?;
+            self.write_message_chunks(&serialized).await?;
+        }
+
+        for id in self.stream()?.acknowledged() {
+            self.in_flight.retain(|(pending, _)| *pending != id);
+        }
+        let waiting = |in_flight: &VecDeque<(u64, Vec<u8>)>| {
+            in_flight.len() > MAX_IN_FLIGHT
+                || wait_for.is_some_and(|id| in_flight.iter().any(|(pending, _)| *pending == id))
+        };
+        while waiting(&self.in_flight) {
+            let id = self.stream()?.next_acknowledgment().await?;
+            self.in_flight.retain(|(pending, _)| *pending != id);
+        }
+        Ok(())
+    }
+
+    fn serialize_message(message: &Message) -> Result<Vec<u8>, rancor::Error> {
+        message.clone().try_into()
+    }
+
+    async fn write_frame_header(&mut self, header: &[u8]) -> io::Result<()> {
+        self.stream()?.write_all(header).await
+    }
+
+    async fn write_message_chunks(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut offset = 0;
+        while offset < data.len() {
+            let end = std::cmp::min(offset + self.chunk_sizer.size(), data.len());
+            let started = std::time::Instant::now();
+            self.stream()?.write_all(&data[offset..end]).await?;
+            self.chunk_sizer
+                .record_write(end - offset, started.elapsed());
+            offset = end;
+        }
+        Ok(())
+    }
+
+    async fn read_ok_reply(&mut self) -> io::Result<bool> {
+        self.stream()?.read_ok_reply().await
+    }
+
+    async fn try_reconnect(&mut self) -> io::Result<()> {
+        self.reconnect_to_central_command().await
+    }
+}
+
+impl ConnectionManager {
+    pub async fn try_new() -> io::Result<Self> {
+        // Listeners are bound first so the port that ends up being used can be registered.
+        let (listeners, agent_port) =
+            match get_message_bus_url().is_none() && !get_reverse_dispatch() && !get_agent_pull() {
+                true => Self::bind_listeners()?,
+                false => (vec![], get_agent_port()),
+            };
+
+        let (sender, dispatches) = match get_reverse_dispatch() || get_agent_pull() {
+            true => {
+                let (sender, receiver) = mpsc::channel(DISPATCH_CHANNEL_CAPACITY);
+                (Some(sender), Some(receiver))
+            }
+            false => (None, None),
+        };
+        let central_command_writer = Arc::new(PriorityLock::new(
+            CentralCommandWriter::try_new(sender).await?,
+        ));
+
+        Ok(Self {
+            central_command_writer: central_command_writer.clone(),
+            job_dispatcher: job_dispatch::JobDispatcher::new(central_command_writer),
+            dispatches,
+            listeners,
+            agent_port,
+        })
+    }
+
+    async fn register(&mut self) {
+        let registered_agent = RegisterAgent {
+            name: get_agent_name(),
+            hostname: hostname::get()
+                .expect("Unable to get hostname!")
+                .to_string_lossy()
+                .to_string(),
+            port: self.agent_port,
+            version: VERSION.to_string(),
+            timezone: get_timezone(),
+            locale: get_locale(),
+            receipt_public_key: Some(get_agent_receipt_signer().public_key()),
+            os: platform::os(),
+            arch: platform::arch(),
+            kernel: platform::kernel(),
+            shells: platform::shells(),
+            features: platform::features(),
+            container: container::detect(),
+        };
+        let message = Message::RegisterAgent(registered_agent);
+        self.central_command_writer
+            .lock(message.priority())
+            .await
+            .write(message)
+            .await;
+    }
+
+    async fn ping_central_command(&mut self) {
+        let message = Message::Ping;
+        self.central_command_writer
+            .lock(message.priority())
+            .await
+            .write(message)
+            .await;
+    }
+
+    async fn handle_message(
+        &mut self,
+        message: Message,
+        peer_addr: impl std::fmt::Display,
+    ) -> io::Result<()> {
+        match message {
+            Message::Ping => {
+                debug!("Ping from {}", peer_addr);
+                self.ping_central_command().await;
+            }
+            Message::DispatchJob(job) => {
+                // Handle job dispatching logic here
+                info!("Running job {} from {}", job.job_name, peer_addr);
+                self.job_dispatcher.spawn(job).await;
+            }
+            Message::DispatchBatch(jobs) => {
+                info!("Running {} jobs from {}", jobs.len(), peer_addr);
+                for job in jobs {
+                    info!("Running job {} from {}", job.job_name, peer_addr);
+                    self.job_dispatcher.spawn(job).await;
+                }
+            }
+            Message::FileChunk(chunk) if get_agent_simulate() => {
+                debug!("Simulating, not writing {}", chunk.destination);
+            }
+            Message::FileChunk(chunk) => {
+                let destination = chunk.destination.clone();
+                if chunk.offset == 0 {
+                    info!(
+                        "Receiving {} ({} bytes) for job {} from {}",
+                        destination, chunk.size, chunk.job_name, peer_addr
+                    );
+                }
+                if let Err(e) = file_transfer::receive(chunk).await {
+                    error!("Failed to write {}: {}", destination, e);
+                }
+            }
+            Message::UpdateAgent(update) => {
+                info!(
+                    "Received update to version {} from {}",
+                    update.version, peer_addr
+                );
+                updater::spawn_update(update);
+            }
+            Message::CancelJob(cancel_job) => {
+                info!("Cancelling job {} from {}", cancel_job.job_name, peer_addr);
+                if !self.job_dispatcher.cancel(&cancel_job.job_name).await {
+                    warn!(
+                        "Job {} is not running, nothing to cancel",
+                        cancel_job.job_name
+                    );
+                }
+            }
+            Message::ExtendTimeout(extend) => {
+                match self
+                    .job_dispatcher
+                    .extend_timeout(&extend.job_name, extend.seconds)
+                    .await
+                {
+                    Some(timeout) => info!(
+                        "Extended the timeout of job {} by {} to {} seconds from {}",
+                        extend.job_name, extend.seconds, timeout, peer_addr
+                    ),
+                    None => warn!(
+                        "Job {} is not running with a timeout, nothing to extend",
+                        extend.job_name
+                    ),
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Binds a listener for every address in `AGENT_LISTEN_ADDRESSES`, returning them with the
+    /// port to register. Addresses using `AGENT_PORT` fall back to an ephemeral port when it is in
+    /// use, unless `AGENT_PORT_STRICT` is set.
+    fn bind_listeners() -> io::Result<(Vec<TcpListener>, u16)> {
+        let agent_port = get_agent_port();
+        let mut fallback_port: Option<u16> = None;
+        let mut listeners = vec![];
+
+        for address in get_agent_listen_addresses() {
+            let listener = match std::net::TcpListener::bind(address) {
+                Ok(listener) => listener,
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                    let socket_addr = Self::resolve_listen_address(address)?;
+                    if socket_addr.port() != agent_port {
+                        return Err(e);
+                    }
+                    if get_agent_port_strict() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!(
+                                "AGENT_PORT {} is already in use on {} and AGENT_PORT_STRICT is set",
+                                agent_port, address
+                            ),
+                        ));
+                    }
+                    // Every conflicting address shares one fallback port so a single port is registered.
+                    let listener = std::net::TcpListener::bind(SocketAddr::new(
+                        socket_addr.ip(),
+                        fallback_port.unwrap_or(0),
+                    ))?;
+                    let port = listener.local_addr()?.port();
+                    warn!(
+                        "AGENT_PORT {} is already in use on {}, listening on port {} instead",
+                        agent_port, address, port
+                    );
+                    fallback_port = Some(port);
+                    listener
+                }
+                Err(e) => return Err(e),
+            };
+            listener.set_nonblocking(true)?;
+            let listener = TcpListener::from_std(listener)?;
+            info!("Listening on: {}", listener.local_addr()?);
+            listeners.push(listener);
+        }
+
+        Ok((listeners, fallback_port.unwrap_or(agent_port)))
+    }
+
+    fn resolve_listen_address(address: &str) -> io::Result<SocketAddr> {
+        address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid listen address: {}", address),
+            )
+        })
+    }
+
+    /// Pings central command periodically, as it cannot ping agents it does not dial. A failed
+    /// ping also reconnects a dropped reverse dispatch connection.
+    fn spawn_heartbeat(&self) {
+        let central_command_writer = self.central_command_writer.clone();
+        tokio::spawn(async move {
+            let mut heartbeat =
+                tokio::time::interval(tokio::time::Duration::from_secs(HEARTBEAT_SECS));
+            loop {
+                heartbeat.tick().await;
+                central_command_writer
+                    .lock(Priority::Control)
+                    .await
+                    .write(Message::Ping)
+                    .await;
+            }
+        });
+    }
+
+    /// Polls central command for jobs every `AGENT_POLL_INTERVAL_SECONDS`, in place of the
+    /// heartbeat. A failed poll also reconnects a dropped connection, which polls as it opens.
+    fn spawn_poll(&self) {
+        let central_command_writer = self.central_command_writer.clone();
+        tokio::spawn(async move {
+            let mut poll = tokio::time::interval(tokio::time::Duration::from_secs(
+                get_agent_poll_interval_seconds(),
+            ));
+            poll.tick().await; // Opening the connection polled
+            loop {
+                poll.tick().await;
+                central_command_writer
+                    .lock(Priority::Control)
+                    .await
+                    .write(reverse_dispatch::poll_work())
+                    .await;
+            }
+        });
+    }
+
+    /// Receives messages pushed by central command over the agent's own connection, handling
+    /// cancellations ahead of queued dispatches. In pull mode these are the jobs handed out in
+    /// answer to the agent's polls.
+    async fn listen_reverse(&mut self, dispatches: mpsc::Receiver<Message>) -> io::Result<()> {
+        match get_agent_pull() {
+            true => self.spawn_poll(),
+            false => self.spawn_heartbeat(),
+        }
+
+        let mut dispatches = PriorityReceiver::new(dispatches);
+
+        while let Some(message) = dispatches.recv().await {
+            self.handle_message(message, "central command").await?;
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "Reverse dispatch channel closed",
+        ))
+    }
+
+    /// Receives messages from central command over the message bus, sending a heartbeat so
+    /// central command keeps the agent marked online.
+    async fn listen_bus(&mut self, bus: Arc<dyn MessageBus>) -> io::Result<()> {
+        let subject = bus::agent_subject(&get_agent_name());
+        let mut messages = bus
+            .subscribe(subject.clone())
+            .await
+            .map_err(io::Error::other)?;
+        info!("Listening on message bus subject: {}", subject);
+        self.spawn_heartbeat();
+
+        while let Some(bus_message) = futures::StreamExt::next(&mut messages).await {
+            debug!("Received: {:?} from message bus", bus_message.message);
+            self.handle_message(bus_message.message, "message bus")
+                .await?;
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "Message bus subscription closed",
+        ))
+    }
+
+    pub async fn listen(&mut self) -> io::Result<()> {
+        let bus = self
+            .central_command_writer
+            .lock(Priority::Control)
+            .await
+            .bus();
+        if let Some(bus) = bus {
+            return self.listen_bus(bus).await;
+        }
+        if let Some(dispatches) = self.dispatches.take() {
+            return self.listen_reverse(dispatches).await;
+        }
+
+        let listeners = std::mem::take(&mut self.listeners);
+
+        loop {
+            let accepts = listeners.iter().map(|listener| Box::pin(listener.accept()));
+            let (accepted, _, _) = futures::future::select_all(accepts).await;
+            let (mut stream, peer_addr) = accepted?;
+            info!("New connection from: {}", peer_addr);
+            enable_keepalive(&stream);
+
+            // Spawn a new task to handle the connection
+            // Aligned so messages are read in place, without copying them out of the buffer.
+            let mut buffer = MessageBuffer::new();
+
+            loop {
+                tokio::select! {
+                    result = stream.read(buffer.slice_mut(65536)) => {
+                        match result {
+                            Ok(0) => {
+                                info!("Connection with {} closed by peer.", peer_addr);
+                                break; // Connection closed by the client
+                            }
+                            Ok(n) => {
+                                let message = match buffer.message(n) {
+                                    // Pings need nothing from the message, so skip deserializing it.
+                                    Ok(ArchivedMessage::Ping) => Message::Ping,
+                                    Ok(archived) => Message::from(archived),
+                                    Err(e) => {
+                                        error!("Failed to parse message: {}", e);
+                                        continue;
+                                    }
+                                };
+                                debug!("Received: {:?} from {}", message, peer_addr.ip());
+
+                                // The connection carries the shell from now on.
+                                if let Message::OpenShell(open) = message {
+                                    remote_shell::spawn(stream, open, peer_addr);
+                                    break;
+                                }
+
+                                self.handle_message(message, peer_addr).await?;
+
+                                // Echo the data back to the client (example of keeping the connection active)
+                                if let Err(e) = stream.write_all(b"OK").await {
+                                    error!("Error writing to {}: {}", peer_addr, e);
+                                    break;
+                                }
+                            }
+                            Err(e) => {
+                                error!("Error reading from {}: {}", peer_addr, e);
+                                break;
+                            }
+                        }
+                    }
+                    _ = idle_timeout() => {
+                        warn!(
+                            "No message from {} in {} seconds, closing the connection.",
+                            peer_addr,
+                            get_agent_idle_timeout_seconds()
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
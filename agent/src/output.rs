@@ -0,0 +1,161 @@
+//! Bounded collection of a job's stdout and stderr.
+//!
+//! Output is read as it is produced instead of being buffered whole, keeping only the first and
+//! last `AGENT_MAX_OUTPUT_BYTES / 2` bytes so a chatty job cannot exhaust the agent's memory or
+//! exceed the size of a Mongo document. When `AGENT_OUTPUT_ARTIFACT_DIR` is set the full output is
+//! also spilled to a file there, which is kept only if the output had to be truncated.
+//!
+//! Spilled output is redacted line by line, so redaction patterns spanning several lines (such as
+//! PEM private keys) only apply to the output sent to central command.
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, BufWriter};
+use tracing::warn;
+
+use std::collections::VecDeque;
+use std::io;
+use std::path::PathBuf;
+
+use core_logic::redaction::Redactor;
+
+/// Longest piece of a line redacted and spilled at once.
+const MAX_SPILL_LINE_BYTES: usize = 64 * 1024;
+
+/// Output read from one stream, truncated to its head and tail if it exceeded the cap.
+#[derive(Debug, Default)]
+pub struct CollectedOutput {
+    pub text: String,
+    pub truncated: bool,
+    pub artifact: Option<PathBuf>, // Full output, kept only when truncated
+}
+
+struct Collector {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    head_capacity: usize,
+    tail_capacity: usize,
+    total: usize,
+}
+
+impl Collector {
+    fn new(max_bytes: usize) -> Self {
+        let head_capacity = max_bytes / 2;
+        Self {
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            head_capacity,
+            tail_capacity: max_bytes - head_capacity,
+            total: 0,
+        }
+    }
+
+    fn push(&mut self, mut data: &[u8]) {
+        self.total += data.len();
+        if self.head.len() < self.head_capacity {
+            let take = data.len().min(self.head_capacity - self.head.len());
+            self.head.extend_from_slice(&data[..take]);
+            data = &data[take..];
+        }
+        if data.len() >= self.tail_capacity {
+            self.tail.clear();
+            self.tail.extend(&data[data.len() - self.tail_capacity..]);
+            return;
+        }
+        let overflow = (self.tail.len() + data.len()).saturating_sub(self.tail_capacity);
+        self.tail.drain(..overflow);
+        self.tail.extend(data);
+    }
+
+    fn truncated(&self) -> bool {
+        self.total > self.head.len() + self.tail.len()
+    }
+
+    fn into_text(mut self) -> String {
+        let truncated = self.total - self.head.len() - self.tail.len();
+        let tail = self.tail.make_contiguous();
+        if truncated == 0 {
+            let mut bytes = self.head;
+            bytes.extend_from_slice(tail);
+            return String::from_utf8_lossy(&bytes).to_string();
+        }
+        format!(
+            "{}\n... [{} bytes truncated] ...\n{}",
+            String::from_utf8_lossy(&self.head),
+            truncated,
+            String::from_utf8_lossy(tail)
+        )
+    }
+}
+
+/// Reads `reader` to the end, keeping at most `max_bytes` and spilling everything, redacted, to
+/// `spill_path`.
+pub async fn collect<R: AsyncRead + Unpin>(
+    reader: R,
+    max_bytes: usize,
+    spill_path: Option<PathBuf>,
+    redactor: &Redactor,
+) -> io::Result<CollectedOutput> {
+    let mut reader = BufReader::new(reader);
+    let mut collector = Collector::new(max_bytes);
+    let mut spill = match &spill_path {
+        Some(path) => match File::create(path).await {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(e) => {
+                warn!("Unable to create output artifact {}: {}", path.display(), e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut line = Vec::new();
+    loop {
+        let buffer = reader.fill_buf().await?;
+        if buffer.is_empty() {
+            break;
+        }
+        let (consumed, line_complete) = match buffer.iter().position(|&b| b == b'\n') {
+            Some(newline) => (newline + 1, true),
+            None => (buffer.len(), false),
+        };
+        collector.push(&buffer[..consumed]);
+        if spill.is_some() {
+            line.extend_from_slice(&buffer[..consumed]);
+        }
+        reader.consume(consumed);
+
+        if let Some(writer) = spill.as_mut()
+            && (line_complete || line.len() >= MAX_SPILL_LINE_BYTES)
+        {
+            let redacted = redactor.redact(&String::from_utf8_lossy(&line));
+            writer.write_all(redacted.as_bytes()).await?;
+            line.clear();
+        }
+    }
+    if let Some(mut writer) = spill.take() {
+        if !line.is_empty() {
+            let redacted = redactor.redact(&String::from_utf8_lossy(&line));
+            writer.write_all(redacted.as_bytes()).await?;
+        }
+        writer.flush().await?;
+        spill = Some(writer);
+    }
+
+    let truncated = collector.truncated();
+    let artifact = match (spill, spill_path) {
+        (Some(_), Some(path)) if truncated => Some(path),
+        (Some(_), Some(path)) => {
+            // The whole output fits in the run, so the artifact is not needed.
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                warn!("Unable to remove output artifact {}: {}", path.display(), e);
+            }
+            None
+        }
+        _ => None,
+    };
+
+    Ok(CollectedOutput {
+        text: collector.into_text(),
+        truncated,
+        artifact,
+    })
+}
@@ -0,0 +1,105 @@
+//! Platform details the agent reports to central command when it registers, so jobs can be
+//! limited to platforms (see `JobV1::platforms`) and the agents page can show where each agent
+//! runs.
+//!
+//! Everything is detected at startup: the OS and architecture the agent was built for, the running
+//! kernel, the shells found on `PATH` and the optional agent features that are enabled.
+use std::env;
+use std::path::Path;
+
+use crate::{
+    get_agent_container_runtime, get_agent_output_artifact_dir, get_agent_pull,
+    get_agent_remote_shell, get_agent_simulate, get_agent_spool, get_message_bus_url,
+    get_reverse_dispatch,
+};
+use core_logic::datastore::agents::normalize_arch;
+
+/// Shells looked for on `PATH`, by the name they are reported as.
+#[cfg(unix)]
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "fish", "pwsh"];
+#[cfg(windows)]
+const SHELLS: &[&str] = &["cmd", "powershell", "pwsh", "bash"];
+
+/// e.g. `linux`, `macos` or `windows`.
+pub fn os() -> String {
+    env::consts::OS.to_string()
+}
+
+/// e.g. `amd64` or `arm64`.
+pub fn arch() -> String {
+    normalize_arch(env::consts::ARCH).to_string()
+}
+
+/// The kernel release, e.g. `6.8.0-45-generic`, or empty if it cannot be determined.
+#[cfg(unix)]
+pub fn kernel() -> String {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return String::new();
+    }
+    let release = unsafe { std::ffi::CStr::from_ptr(uts.release.as_ptr()) };
+    release.to_string_lossy().into_owned()
+}
+
+/// The Windows version, e.g. `10.0.19045.3803`, or empty if it cannot be determined.
+#[cfg(windows)]
+pub fn kernel() -> String {
+    // `ver` prints e.g. "Microsoft Windows [Version 10.0.19045.3803]".
+    std::process::Command::new("cmd")
+        .args(["/C", "ver"])
+        .output()
+        .ok()
+        .and_then(|output| {
+            let text = String::from_utf8_lossy(&output.stdout).to_string();
+            let start = text.find("Version ")? + "Version ".len();
+            let end = start + text[start..].find(']')?;
+            Some(text[start..end].to_string())
+        })
+        .unwrap_or_default()
+}
+
+/// The shells of `SHELLS` found on `PATH`.
+pub fn shells() -> Vec<String> {
+    SHELLS
+        .iter()
+        .filter(|shell| on_path(shell))
+        .map(|shell| shell.to_string())
+        .collect()
+}
+
+/// Whether the program `name` is found on `PATH`.
+pub fn on_path(name: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| is_executable(&dir, name)))
+}
+
+fn is_executable(dir: &Path, name: &str) -> bool {
+    match cfg!(windows) {
+        true => dir.join(format!("{}.exe", name)).is_file(),
+        false => dir.join(name).is_file(),
+    }
+}
+
+/// The optional agent features that are enabled, e.g. `reverse_dispatch` or `spool`, and
+/// `dispatch_batch`, telling central command the agent understands `DispatchBatch`.
+pub fn features() -> Vec<String> {
+    [
+        ("dispatch_batch", true),
+        ("nats", cfg!(feature = "nats")),
+        ("message_bus", get_message_bus_url().is_some()),
+        ("reverse_dispatch", get_reverse_dispatch()),
+        ("pull", get_agent_pull()),
+        ("spool", get_agent_spool().is_some()),
+        (
+            "output_artifacts",
+            get_agent_output_artifact_dir().is_some(),
+        ),
+        ("remote_shell", get_agent_remote_shell()),
+        ("containers", get_agent_container_runtime().is_some()),
+        ("simulate", get_agent_simulate()),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature.to_string())
+    .collect()
+}
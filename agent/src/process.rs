@@ -0,0 +1,66 @@
+//! Platform specific process handling for jobs run by the agent.
+//!
+//! Jobs are spawned into their own process group (Unix) or console process group (Windows) so
+//! that a timeout or cancellation can take down the whole process tree rather than only the
+//! immediate child, and exit statuses are mapped to a plain return code on every platform.
+use std::process::ExitStatus;
+use tokio::process::Command;
+
+/// Places the spawned job into its own process group so it can be killed as a tree.
+#[cfg(unix)]
+pub fn configure_process_group(command: &mut Command) {
+    command.process_group(0);
+}
+
+/// Places the spawned job into its own process group so it can be killed as a tree.
+#[cfg(windows)]
+pub fn configure_process_group(command: &mut Command) {
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+}
+
+/// Kills the process group led by `pid`, including any children the job started.
+#[cfg(unix)]
+pub async fn kill_process_tree(pid: u32) {
+    // The job was spawned with `process_group(0)` so its pid is also the group id.
+    let result = unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) };
+    if result != 0 {
+        tracing::error!(
+            "Failed to kill process group {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Kills the process tree rooted at `pid`, including any children the job started.
+#[cfg(windows)]
+pub async fn kill_process_tree(pid: u32) {
+    let result = Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .output()
+        .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to kill process tree {}: {}", pid, e);
+    }
+}
+
+/// Maps an exit status to a return code.
+/// Processes terminated by a signal report `128 + signal`, matching shell conventions.
+#[cfg(unix)]
+pub fn map_exit_status(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(-1)
+}
+
+/// Maps an exit status to a return code.
+/// Windows always reports an exit code; NTSTATUS failures (e.g. `0xC0000005`) come through as
+/// negative values.
+#[cfg(windows)]
+pub fn map_exit_status(status: ExitStatus) -> i32 {
+    status.code().unwrap_or(-1)
+}
@@ -0,0 +1,259 @@
+//! Interactive shells opened on the agent by an operator through the web UI and central command,
+//! for debugging a host without SSH. Disabled unless `AGENT_REMOTE_SHELL` is set.
+//!
+//! Central command dials the agent's listen port for each shell and sends `OpenShell`. The agent
+//! answers `OK`, then runs `$SHELL` (or `/bin/sh`) on a pseudo-terminal of the requested size and
+//! exchanges frames with central command on the connection until either side ends the session
+//! (see the Remote Shells section of `core_logic::messages`):
+//!
+//! - `ShellData` frames from central command are written to the terminal, and everything the
+//!   terminal prints is sent back as `ShellData`.
+//! - `ResizeShell` resizes the terminal.
+//! - When the shell exits the agent sends `CloseShell` with its exit code and closes the
+//!   connection. When central command sends `CloseShell` or closes the connection, the shell and
+//!   everything it started is killed.
+//!
+//! Every session is logged with its id and the operator it was opened for. Remote shells are only
+//! supported on Unix; on other platforms the agent answers `OpenShell` with a `CloseShell`.
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::get_agent_remote_shell;
+use core_logic::messages::{CloseShell, Message, OpenShell};
+
+/// Largest frame accepted from central command; keystrokes and pastes are far smaller.
+#[cfg(unix)]
+const MAX_INPUT_FRAME: usize = 1024 * 1024;
+
+/// Runs the shell `open` asks for on `stream`, a connection central command dialed for it.
+pub fn spawn(stream: TcpStream, open: OpenShell, peer_addr: SocketAddr) {
+    tokio::spawn(async move {
+        info!(
+            session_id = %open.session_id,
+            "Opening remote shell for {} from {}", open.user, peer_addr
+        );
+        let started = Instant::now();
+        match serve(stream, &open).await {
+            Ok(reason) => info!(
+                session_id = %open.session_id,
+                "Remote shell for {} closed after {} seconds: {}",
+                open.user,
+                started.elapsed().as_secs(),
+                reason
+            ),
+            Err(e) => error!(
+                session_id = %open.session_id,
+                "Remote shell for {} failed: {}", open.user, e
+            ),
+        }
+    });
+}
+
+/// Runs the session, returning why it ended.
+async fn serve(mut stream: TcpStream, open: &OpenShell) -> io::Result<String> {
+    stream.write_all(b"OK").await?;
+    let (reader, writer) = stream.into_split();
+    let writer = Arc::new(Mutex::new(writer));
+    let reason = match get_agent_remote_shell() {
+        true => run_shell(reader, writer.clone(), open).await?,
+        false => "Remote shells are disabled on this agent (AGENT_REMOTE_SHELL)".to_string(),
+    };
+    let close = Message::CloseShell(CloseShell {
+        reason: reason.clone(),
+    });
+    let mut writer = writer.lock().await;
+    // The other side may already be gone, e.g. when it ended the session.
+    if let Err(e) = write_message(&mut writer, close).await {
+        warn!("Failed to send CloseShell: {}", e);
+    }
+    let _ = writer.shutdown().await;
+    Ok(reason)
+}
+
+async fn write_message(writer: &mut OwnedWriteHalf, message: Message) -> io::Result<()> {
+    let frame = message.to_frame().map_err(io::Error::other)?;
+    writer.write_all(&frame).await
+}
+
+#[cfg(not(unix))]
+async fn run_shell(
+    _reader: tokio::net::tcp::OwnedReadHalf,
+    _writer: Arc<Mutex<OwnedWriteHalf>>,
+    _open: &OpenShell,
+) -> io::Result<String> {
+    Ok(format!(
+        "Remote shells are not supported on {}",
+        std::env::consts::OS
+    ))
+}
+
+#[cfg(unix)]
+async fn run_shell(
+    mut reader: tokio::net::tcp::OwnedReadHalf,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    open: &OpenShell,
+) -> io::Result<String> {
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+
+    use crate::process::{kill_process_tree, map_exit_status};
+    use core_logic::framing;
+    use core_logic::messages::ShellData;
+
+    // Output still buffered in the terminal after the shell exited.
+    const DRAIN_OUTPUT_TIMEOUT: Duration = Duration::from_secs(1);
+
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+    let (pty, mut child) = match pty::spawn(&shell, open.cols, open.rows) {
+        Ok(spawned) => spawned,
+        Err(e) => return Ok(format!("Failed to start {}: {}", shell, e)),
+    };
+    let pid = child.id();
+
+    let mut output = tokio::fs::File::from_std(pty.try_clone()?);
+    let output_writer = writer.clone();
+    let forward_output = tokio::spawn(async move {
+        let mut buffer = [0u8; 8192];
+        // Reading fails with EIO once every process using the terminal exited.
+        while let Ok(n) = output.read(&mut buffer).await
+            && n > 0
+        {
+            let data = Message::ShellData(ShellData {
+                data: buffer[..n].to_vec(),
+            });
+            if write_message(&mut *output_writer.lock().await, data)
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let mut input = tokio::fs::File::from_std(pty.try_clone()?);
+    let forward_input = async {
+        loop {
+            let frame = match framing::read_frame(&mut reader, MAX_INPUT_FRAME).await {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return "Closed by central command".to_string(),
+                Err(e) => return format!("Connection to central command failed: {}", e),
+            };
+            match Message::try_from(frame) {
+                Ok(Message::ShellData(data)) => {
+                    if let Err(e) = input.write_all(&data.data).await {
+                        return format!("Failed to write to the terminal: {}", e);
+                    }
+                    let _ = input.flush().await;
+                }
+                Ok(Message::ResizeShell(resize)) => {
+                    if let Err(e) = pty::resize(&pty, resize.cols, resize.rows) {
+                        warn!("Failed to resize remote shell: {}", e);
+                    }
+                }
+                Ok(Message::CloseShell(close)) => return close.reason,
+                Ok(message) => warn!("Ignoring {} in a remote shell", message.kind()),
+                Err(e) => warn!("Failed to parse remote shell message: {}", e),
+            }
+        }
+    };
+
+    let reason = tokio::select! {
+        reason = forward_input => {
+            if let Some(pid) = pid {
+                kill_process_tree(pid).await;
+            }
+            let _ = child.wait().await;
+            reason
+        }
+        status = child.wait() => match status {
+            Ok(status) => format!("Exited with {}", map_exit_status(status)),
+            Err(e) => format!("Failed to wait for {}: {}", shell, e),
+        },
+    };
+    if tokio::time::timeout(DRAIN_OUTPUT_TIMEOUT, forward_output)
+        .await
+        .is_err()
+    {
+        // A background process the shell left behind still holds the terminal.
+        if let Some(pid) = pid {
+            kill_process_tree(pid).await;
+        }
+    }
+    Ok(reason)
+}
+
+/// Pseudo-terminals for remote shells.
+#[cfg(unix)]
+mod pty {
+    use tokio::process::{Child, Command};
+
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::process::Stdio;
+
+    fn winsize(cols: u16, rows: u16) -> libc::winsize {
+        libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+
+    /// Starts `shell` on a new terminal of `cols` by `rows`, in its own session so it can be
+    /// killed with everything it started. Returns the terminal's controlling side.
+    pub fn spawn(shell: &str, cols: u16, rows: u16) -> io::Result<(File, Child)> {
+        let (mut master, mut slave) = (0, 0);
+        let size = winsize(cols, rows);
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                &size,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+        let mut command = Command::new(shell);
+        command
+            .env("TERM", "dumb")
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave))
+            .kill_on_drop(true);
+        unsafe {
+            command.pre_exec(|| {
+                // A new session with the terminal as its controlling terminal, so job control
+                // works and the session's pid is also its process group id.
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        Ok((master, child))
+    }
+
+    pub fn resize(master: &File, cols: u16, rows: u16) -> io::Result<()> {
+        let size = winsize(cols, rows);
+        match unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ as _, &size) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Reverse dispatch over the agent's connection to central command.
+//!
+//! With `AGENT_REVERSE_DISPATCH=true` the agent opens no listeners. Every connection it makes to
+//! central command starts with a `ReverseDispatch` message; after that central command pushes
+//! dispatches as frames on the same connection and acknowledges the agent's own messages with a
+//! zero-length frame. A reader task splits the two apart. Frames that fail their checksum or do
+//! not parse are logged and skipped, and the reader resynchronizes with the next frame if the
+//! stream fell out of step (see `core_logic::framing`).
+//!
+//! With `AGENT_PULL=true` the connection starts with a `PollWork` message instead, and the agent
+//! sends another every `AGENT_POLL_INTERVAL_SECONDS`. Central command claims the runs waiting for
+//! the agent in answer and sends them on the connection like reverse dispatches, along with
+//! control messages such as cancellations (see Pull Dispatch in `core_logic::messages`).
+//!
+//! The same reader task serves multiplexed connections (`AGENT_MULTIPLEX=true`), on which central
+//! command answers each of the agent's `Envelope`s with an `Envelope` of the same id. Their ids
+//! are passed on as they arrive, so the agent can write further messages without waiting.
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use std::io;
+
+use crate::{frame_header, get_agent_name, get_agent_poll_max_jobs};
+use core_logic::framing::{FrameReader, ProtocolError};
+use core_logic::messages::{Message, PollWork, ReverseDispatch};
+
+/// A connection to central command.
+pub enum CentralCommandStream {
+    /// Replies to the agent's messages are a plain `OK`.
+    Direct(TcpStream),
+    /// Replies arrive through `acks` while pushed messages are forwarded by the reader task. An
+    /// acknowledgment carries the id of the `Envelope` it answers, or `None` for a zero-length
+    /// frame.
+    Duplex {
+        writer: OwnedWriteHalf,
+        acks: mpsc::UnboundedReceiver<Option<u64>>,
+    },
+}
+
+impl CentralCommandStream {
+    pub async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            CentralCommandStream::Direct(stream) => stream.write_all(data).await,
+            CentralCommandStream::Duplex { writer, .. } => writer.write_all(data).await,
+        }
+    }
+
+    pub async fn read_ok_reply(&mut self) -> io::Result<bool> {
+        match self {
+            CentralCommandStream::Direct(stream) => {
+                let mut reply = [0; 2];
+                stream.read_exact(&mut reply).await?;
+                Ok(&reply == b"OK")
+            }
+            CentralCommandStream::Duplex { acks, .. } => match acks.recv().await {
+                Some(_) => Ok(true),
+                None => Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "Reverse dispatch connection closed",
+                )),
+            },
+        }
+    }
+
+    /// Ids of the `Envelope`s acknowledged since last asked, without waiting.
+    pub fn acknowledged(&mut self) -> Vec<u64> {
+        let mut ids = vec![];
+        if let CentralCommandStream::Duplex { acks, .. } = self {
+            while let Ok(ack) = acks.try_recv() {
+                ids.extend(ack);
+            }
+        }
+        ids
+    }
+
+    /// Waits for the next `Envelope` to be acknowledged, returning its id.
+    pub async fn next_acknowledgment(&mut self) -> io::Result<u64> {
+        let CentralCommandStream::Duplex { acks, .. } = self else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Connection is not multiplexed",
+            ));
+        };
+        loop {
+            match acks.recv().await {
+                Some(Some(id)) => return Ok(id),
+                Some(None) => continue, // Not for an envelope
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "Connection to central command closed",
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Splits `stream` for multiplexing, forwarding messages central command pushes to `dispatches`.
+pub fn duplex(
+    stream: TcpStream,
+    dispatches: Option<mpsc::Sender<Message>>,
+) -> CentralCommandStream {
+    let (reader, writer) = stream.into_split();
+    let (ack_sender, acks) = mpsc::unbounded_channel();
+    tokio::spawn(read_frames(reader, ack_sender, dispatches));
+    CentralCommandStream::Duplex { writer, acks }
+}
+
+/// Asks central command to dispatch over `stream` and starts forwarding pushed messages to
+/// `dispatches`.
+pub async fn open(
+    stream: TcpStream,
+    dispatches: mpsc::Sender<Message>,
+) -> io::Result<CentralCommandStream> {
+    let request = Message::ReverseDispatch(ReverseDispatch {
+        agent_name: get_agent_name(),
+    });
+    let stream = request_dispatches(stream, request).await?;
+    info!("Receiving dispatches over the connection to central command");
+    Ok(duplex(stream, Some(dispatches)))
+}
+
+/// Polls central command for jobs over `stream` and starts forwarding the jobs handed out to
+/// `dispatches`.
+pub async fn open_pull(
+    stream: TcpStream,
+    dispatches: mpsc::Sender<Message>,
+) -> io::Result<CentralCommandStream> {
+    let stream = request_dispatches(stream, poll_work()).await?;
+    info!("Pulling jobs over the connection to central command");
+    Ok(duplex(stream, Some(dispatches)))
+}
+
+/// A poll for up to `AGENT_POLL_MAX_JOBS` jobs.
+pub fn poll_work() -> Message {
+    Message::PollWork(PollWork {
+        agent_name: get_agent_name(),
+        max_jobs: get_agent_poll_max_jobs(),
+    })
+}
+
+/// Sends `request`, the first message on the connection, and waits for central command to
+/// accept it before the connection is split.
+async fn request_dispatches(mut stream: TcpStream, request: Message) -> io::Result<TcpStream> {
+    let kind = request.kind();
+    let serialized: Vec<u8> = request.try_into().map_err(io::Error::other)?;
+    stream.write_all(&frame_header(&serialized)).await?;
+    stream.write_all(&serialized).await?;
+
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if &reply != b"OK" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Central command rejected {}", kind),
+        ));
+    }
+    Ok(stream)
+}
+
+/// Reads the next frame, logging bytes skipped to get back in step with the stream.
+async fn read_frame(
+    reader: &mut OwnedReadHalf,
+    frames: &mut FrameReader,
+) -> io::Result<Option<Vec<u8>>> {
+    let Some(header) = frames.read_header(reader).await? else {
+        return Ok(None);
+    };
+    if header.skipped > 0 {
+        warn!(
+            "{} from central command",
+            ProtocolError::Resynchronized {
+                skipped: header.skipped
+            }
+        );
+    }
+    let mut frame = vec![0u8; header.len];
+    reader.read_exact(&mut frame).await?;
+    header.verify(&frame)?;
+    Ok(Some(frame))
+}
+
+/// Reads frames from central command until the connection closes.
+async fn read_frames(
+    mut reader: OwnedReadHalf,
+    acks: mpsc::UnboundedSender<Option<u64>>,
+    dispatches: Option<mpsc::Sender<Message>>,
+) {
+    let mut frames = FrameReader::new(0);
+    loop {
+        let frame = match read_frame(&mut reader, &mut frames).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                info!("Reverse dispatch connection closed");
+                break;
+            }
+            Err(e) => match ProtocolError::from_io(&e) {
+                Some(ProtocolError::ChecksumMismatch { .. }) => {
+                    warn!("Skipping frame from central command: {}", e);
+                    continue;
+                }
+                _ => {
+                    error!("Failed to read frame from central command: {}", e);
+                    break;
+                }
+            },
+        };
+        if frame.is_empty() {
+            if acks.send(None).is_err() {
+                break;
+            }
+            continue;
+        }
+
+        let message: Message = match frame.try_into() {
+            Ok(message) => message,
+            Err(e) => {
+                warn!(
+                    "Skipping frame from central command: {}",
+                    ProtocolError::Malformed(e.to_string())
+                );
+                continue;
+            }
+        };
+        debug!("Received: {:?} from central command", message);
+        let forwarded = match (message, &dispatches) {
+            (Message::Envelope(envelope), _) if envelope.payload.is_empty() => {
+                if acks.send(Some(envelope.id)).is_err() {
+                    break;
+                }
+                continue;
+            }
+            (message, Some(dispatches)) => dispatches.send(message).await,
+            (message, None) => {
+                warn!("Unexpected {} from central command", message.kind());
+                continue;
+            }
+        };
+        if forwarded.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Scripts carried by a job's `DispatchJob` instead of a command.
+//!
+//! The script body is written to a new file in the system temporary directory that only the
+//! agent's user can read, write and execute, run with the script's interpreter and the job's
+//! arguments, and removed when the run is over, however it ended. Scripts are run directly by the
+//! interpreter, whatever `AGENT_SHELL` is set to.
+//!
+//! # Configuration
+//! - `AGENT_SCRIPT_INTERPRETERS`: Comma separated `interpreter=program` overrides, e.g.
+//!   `python=/usr/bin/python3.12,bash=/usr/local/bin/bash`. By default `bash`, `python3` (`python`
+//!   on Windows) and `pwsh` (`powershell` on Windows) are looked up on the `PATH`.
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::warn;
+
+use core_logic::messages::JobScript;
+
+static INTERPRETERS: OnceLock<HashMap<String, String>> = OnceLock::new();
+/// Tells apart the files of scripts written in the same instant.
+static SCRIPT_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn interpreter_overrides() -> &'static HashMap<String, String> {
+    INTERPRETERS.get_or_init(|| {
+        env::var("AGENT_SCRIPT_INTERPRETERS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| entry.split_once('='))
+            .map(|(name, program)| (name.trim().to_lowercase(), program.trim().to_string()))
+            .filter(|(name, program)| !name.is_empty() && !program.is_empty())
+            .collect()
+    })
+}
+
+/// How a script for one interpreter is written and run.
+struct Interpreter {
+    program: &'static str,
+    extension: &'static str,
+    /// Arguments placed before the script's path.
+    flags: &'static [&'static str],
+}
+
+impl Interpreter {
+    fn for_name(name: &str) -> io::Result<Self> {
+        let windows = cfg!(windows);
+        match name {
+            "bash" => Ok(Interpreter {
+                program: "bash",
+                extension: "sh",
+                flags: &[],
+            }),
+            "python" => Ok(Interpreter {
+                program: if windows { "python" } else { "python3" },
+                extension: "py",
+                flags: &[],
+            }),
+            "powershell" => Ok(Interpreter {
+                program: if windows { "powershell" } else { "pwsh" },
+                extension: "ps1",
+                flags: &[
+                    "-NoProfile",
+                    "-NonInteractive",
+                    "-ExecutionPolicy",
+                    "Bypass",
+                    "-File",
+                ],
+            }),
+            other => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unsupported script interpreter {}", other),
+            )),
+        }
+    }
+}
+
+/// A script written to disk, removed when dropped.
+#[derive(Debug)]
+pub struct ScriptFile {
+    path: PathBuf,
+}
+
+impl ScriptFile {
+    /// Writes `script` to a new file only the agent's user can access.
+    async fn write(script: &JobScript, extension: &str) -> io::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        let path = env::temp_dir().join(format!(
+            "rad-script-{}-{}-{}.{}",
+            std::process::id(),
+            nanos,
+            SCRIPT_COUNTER.fetch_add(1, Ordering::Relaxed),
+            extension
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o700);
+        let mut file = options.open(&path).await?;
+        // Removed from here on, even if writing it fails.
+        let script_file = ScriptFile { path };
+        file.write_all(script.body.as_bytes()).await?;
+        file.flush().await?;
+        Ok(script_file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove script {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Writes `script` to disk and builds the `Command` that runs it with `args`. The returned
+/// `ScriptFile` must be kept until the command exited.
+pub async fn prepare(script: &JobScript, args: &str) -> io::Result<(Command, ScriptFile)> {
+    let name = script.interpreter.to_lowercase();
+    let interpreter = Interpreter::for_name(&name)?;
+    let file = ScriptFile::write(script, interpreter.extension).await?;
+    let program = interpreter_overrides()
+        .get(&name)
+        .map(String::as_str)
+        .unwrap_or(interpreter.program);
+
+    let mut command = Command::new(program);
+    command
+        .args(interpreter.flags)
+        .arg(file.path())
+        .args(args.split_whitespace());
+    Ok((command, file))
+}
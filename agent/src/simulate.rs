@@ -0,0 +1,114 @@
+//! Simulated job execution, enabled with `--simulate` or `AGENT_SIMULATE=true`.
+//!
+//! A simulating agent registers and accepts dispatches like any other, but runs nothing: each job
+//! waits for a fake duration and reports a synthetic outcome and output. This makes it safe to
+//! load-test central command with many agents or to demo the web UI.
+//!
+//! Timeouts and cancellation behave as they do for real jobs, and the synthetic result goes
+//! through the same redaction and reporting path.
+//!
+//! # Configuration
+//! - `AGENT_SIMULATE_DURATION_MS`: How long each job takes, either fixed (`1500`) or picked at
+//!   random from a range (`500-5000`) (default: 1000).
+//! - `AGENT_SIMULATE_FAILURE_RATE`: Fraction of jobs that fail, from `0` to `1` (default: 0.1).
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::job_dispatch::{RunControl, RunResult};
+use crate::output::CollectedOutput;
+use core_logic::messages::{DispatchJob, JobStep};
+
+static SIMULATION: OnceLock<Simulation> = OnceLock::new();
+
+struct Simulation {
+    min_duration_ms: u64,
+    max_duration_ms: u64,
+    failure_rate: f64,
+}
+
+fn get_simulation() -> &'static Simulation {
+    SIMULATION.get_or_init(|| {
+        let duration =
+            env::var("AGENT_SIMULATE_DURATION_MS").unwrap_or_else(|_| "1000".to_string());
+        let (min_duration_ms, max_duration_ms): (u64, u64) = match duration.split_once('-') {
+            Some((min, max)) => (
+                min.trim()
+                    .parse()
+                    .expect("Invalid AGENT_SIMULATE_DURATION_MS"),
+                max.trim()
+                    .parse()
+                    .expect("Invalid AGENT_SIMULATE_DURATION_MS"),
+            ),
+            None => {
+                let duration = duration
+                    .trim()
+                    .parse()
+                    .expect("Invalid AGENT_SIMULATE_DURATION_MS");
+                (duration, duration)
+            }
+        };
+        let failure_rate: f64 = env::var("AGENT_SIMULATE_FAILURE_RATE")
+            .unwrap_or_else(|_| "0.1".to_string())
+            .parse()
+            .expect("Invalid AGENT_SIMULATE_FAILURE_RATE");
+        Simulation {
+            min_duration_ms: min_duration_ms.min(max_duration_ms),
+            max_duration_ms: max_duration_ms.max(min_duration_ms),
+            failure_rate: failure_rate.clamp(0.0, 1.0),
+        }
+    })
+}
+
+/// A pseudo-random number in `[0, 1)`. Good enough to vary simulated runs.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Pretends to run a step of `job`, returning how it finished and its synthetic output. The job's
+/// timeout is counted from `started`.
+pub(crate) async fn run(
+    job: &DispatchJob,
+    step: &JobStep,
+    started: Instant,
+    control: &RunControl,
+) -> (RunResult, CollectedOutput) {
+    let simulation = get_simulation();
+    let spread = simulation.max_duration_ms - simulation.min_duration_ms;
+    let duration_ms = simulation.min_duration_ms + (random_fraction() * (spread + 1) as f64) as u64;
+    let fails = random_fraction() < simulation.failure_rate;
+
+    let valid_return_codes = job.valid_return_codes.clone().unwrap_or_default();
+    let return_code = match fails {
+        false => valid_return_codes.first().copied().unwrap_or_default(),
+        true => (1..)
+            .find(|code| !valid_return_codes.contains(code))
+            .unwrap_or(1),
+    };
+
+    let result = tokio::select! {
+        _ = tokio::time::sleep(Duration::from_millis(duration_ms)) => RunResult::Simulated(return_code),
+        timeout = control.timeout_elapsed(started) => RunResult::TimedOut(timeout),
+        _ = control.cancelled() => RunResult::Cancelled,
+    };
+
+    let output = CollectedOutput {
+        text: format!(
+            "Simulated run of: {} {}\nNo command was executed.\nDuration: {} ms, return code: {}\n",
+            step.command, step.args, duration_ms, return_code
+        ),
+        truncated: false,
+        artifact: None,
+    };
+    (result, output)
+}
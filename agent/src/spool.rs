@@ -0,0 +1,184 @@
+//! Durable queue of job results waiting to be sent to central command.
+//!
+//! A `JobComplete` is appended to the spool file as soon as the run finishes, before it is sent,
+//! and removed once central command acknowledged it. Results finished while central command is
+//! unreachable, or still queued when the agent restarts, are replayed in order after the next
+//! message gets through. Delivery is at least once: a result acknowledged just before the agent
+//! stopped may be sent again.
+//!
+//! # Files
+//!
+//! - `AGENT_SPOOL_FILE`: Legacy frames, without checksums (see `core_logic::framing`), appended
+//!   in the order the results finished.
+//! - `AGENT_SPOOL_FILE.offset`: Bytes of the spool file already delivered, replaced atomically
+//!   after each delivery. Once everything is delivered both are truncated.
+//!
+//! A frame left incomplete by a crash part way through appending it is discarded when the spool
+//! is opened. Results that would grow the spool past `AGENT_SPOOL_MAX_BYTES` are not spooled and
+//! are only sent from memory.
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use core_logic::framing::{self, LEGACY_FRAME_HEADER_LEN};
+use core_logic::messages::Message;
+
+#[derive(Debug)]
+pub struct Spool {
+    path: PathBuf,
+    offset_path: PathBuf,
+    max_bytes: u64,
+    state: Mutex<SpoolState>,
+}
+
+#[derive(Debug)]
+struct SpoolState {
+    offset: u64, // Bytes already delivered
+    len: u64,    // Bytes in the spool file
+}
+
+/// The oldest undelivered message and the bytes it takes in the spool.
+#[derive(Debug)]
+pub struct Spooled {
+    pub message: Message,
+    frame_len: u64,
+}
+
+impl Spool {
+    /// Opens the spool at `path`, creating it if needed and discarding an incomplete last frame.
+    pub fn open(path: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        let offset_path = PathBuf::from(format!("{}.offset", path.display()));
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(&path)?;
+        let file_len = file.metadata()?.len();
+        let offset = match std::fs::read_to_string(&offset_path) {
+            Ok(contents) => contents.trim().parse().unwrap_or_else(|e| {
+                warn!("Ignoring invalid {}: {}", offset_path.display(), e);
+                0
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        let offset = if offset > file_len { 0 } else { offset };
+        let len = Self::complete_len(&mut file, offset, file_len)?;
+        if len < file_len {
+            warn!(
+                "Discarding {} bytes of an incomplete result at the end of {}",
+                file_len - len,
+                path.display()
+            );
+            file.set_len(len)?;
+        }
+        Ok(Self {
+            path,
+            offset_path,
+            max_bytes,
+            state: Mutex::new(SpoolState { offset, len }),
+        })
+    }
+
+    /// Length of the frames in `file` from `offset` on that were appended completely.
+    fn complete_len(file: &mut std::fs::File, offset: u64, file_len: u64) -> io::Result<u64> {
+        let mut end = offset;
+        let mut len_buf = [0u8; LEGACY_FRAME_HEADER_LEN];
+        while end + LEGACY_FRAME_HEADER_LEN as u64 <= file_len {
+            file.seek(SeekFrom::Start(end))?;
+            file.read_exact(&mut len_buf)?;
+            let next = end + LEGACY_FRAME_HEADER_LEN as u64 + u32::from_be_bytes(len_buf) as u64;
+            if next > file_len {
+                break;
+            }
+            end = next;
+        }
+        Ok(end)
+    }
+
+    /// Appends `message` and flushes it to disk. Fails if the spool would exceed its limit.
+    pub async fn push(&self, message: &Message) -> io::Result<()> {
+        let frame = message
+            .clone()
+            .to_legacy_frame()
+            .map_err(io::Error::other)?;
+        let mut state = self.state.lock().await;
+        if self.max_bytes > 0 && state.len + frame.len() as u64 > self.max_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "{} would exceed AGENT_SPOOL_MAX_BYTES ({})",
+                    self.path.display(),
+                    self.max_bytes
+                ),
+            ));
+        }
+        let mut file = OpenOptions::new().append(true).open(&self.path).await?;
+        file.write_all(&frame).await?;
+        file.sync_data().await?;
+        state.len += frame.len() as u64;
+        Ok(())
+    }
+
+    /// The oldest undelivered message. Frames that no longer parse are skipped.
+    pub async fn front(&self) -> io::Result<Option<Spooled>> {
+        loop {
+            let offset = {
+                let state = self.state.lock().await;
+                if state.offset >= state.len {
+                    return Ok(None);
+                }
+                state.offset
+            };
+            let mut file = File::open(&self.path).await?;
+            file.seek(SeekFrom::Start(offset)).await?;
+            let Some(frame) = framing::read_frame(&mut BufReader::new(file), 0).await? else {
+                return Ok(None);
+            };
+            let frame_len = (LEGACY_FRAME_HEADER_LEN + frame.len()) as u64;
+            match Message::try_from(frame) {
+                Ok(message) => return Ok(Some(Spooled { message, frame_len })),
+                Err(e) => {
+                    warn!(
+                        "Skipping unreadable result in {}: {}",
+                        self.path.display(),
+                        e
+                    );
+                    self.advance(frame_len).await?;
+                }
+            }
+        }
+    }
+
+    /// Removes `spooled`, the message last returned by `front`, once it was delivered.
+    pub async fn pop(&self, spooled: &Spooled) -> io::Result<()> {
+        self.advance(spooled.frame_len).await
+    }
+
+    async fn advance(&self, frame_len: u64) -> io::Result<()> {
+        let mut state = self.state.lock().await;
+        state.offset = (state.offset + frame_len).min(state.len);
+        if state.offset == state.len {
+            // Everything was delivered: start over instead of growing the file forever.
+            OpenOptions::new()
+                .write(true)
+                .open(&self.path)
+                .await?
+                .set_len(0)
+                .await?;
+            state.offset = 0;
+            state.len = 0;
+        }
+        Self::write_offset(&self.offset_path, state.offset).await
+    }
+
+    async fn write_offset(offset_path: &Path, offset: u64) -> io::Result<()> {
+        let temp = PathBuf::from(format!("{}.tmp", offset_path.display()));
+        fs::write(&temp, offset.to_string()).await?;
+        fs::rename(&temp, offset_path).await
+    }
+}
@@ -0,0 +1,160 @@
+//! Self update support for the agent.
+//!
+//! When central command sends an `UpdateAgent` message the agent downloads the new binary,
+//! verifies its SHA-256 checksum, swaps it in place of the running executable and restarts
+//! itself. The restarted agent reports its new version when it registers again.
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::VERSION;
+use core_logic::messages::UpdateAgent;
+
+/// Delay before restarting so the acknowledgment for the update message reaches central command.
+const RESTART_DELAY_MILLIS: u64 = 500;
+
+#[derive(Debug)]
+pub enum UpdateError {
+    Download(reqwest::Error),
+    ChecksumMismatch { expected: String, actual: String },
+    Io(io::Error),
+}
+
+impl std::fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateError::Download(e) => write!(f, "Download error: {}", e),
+            UpdateError::ChecksumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "Checksum mismatch: expected {}, got {}",
+                    expected, actual
+                )
+            }
+            UpdateError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {}
+
+/// Applies an update in the background and restarts the agent once the new binary is in place.
+pub fn spawn_update(update: UpdateAgent) {
+    if update.version == VERSION {
+        info!(
+            "Agent is already running version {}, skipping update",
+            VERSION
+        );
+        return;
+    }
+
+    tokio::spawn(async move {
+        info!(
+            "Updating agent from version {} to {} using {}",
+            VERSION, update.version, update.url
+        );
+        match apply_update(&update).await {
+            Ok(executable) => {
+                tokio::time::sleep(Duration::from_millis(RESTART_DELAY_MILLIS)).await;
+                let error = restart(&executable);
+                error!("Failed to restart agent after update: {}", error);
+            }
+            Err(e) => error!("Failed to update agent to {}: {}", update.version, e),
+        }
+    });
+}
+
+/// Downloads and verifies the new binary, then swaps it in place of the current executable.
+/// Returns the path of the executable to restart.
+async fn apply_update(update: &UpdateAgent) -> Result<PathBuf, UpdateError> {
+    let bytes = download(&update.url).await?;
+    verify_checksum(&bytes, &update.checksum)?;
+
+    let executable = env::current_exe().map_err(UpdateError::Io)?;
+    swap_executable(&executable, &bytes).map_err(UpdateError::Io)?;
+    info!(
+        "Installed agent version {} at {}",
+        update.version,
+        executable.display()
+    );
+    Ok(executable)
+}
+
+async fn download(url: &str) -> Result<Vec<u8>, UpdateError> {
+    let response = reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(UpdateError::Download)?;
+    let bytes = response.bytes().await.map_err(UpdateError::Download)?;
+    Ok(bytes.to_vec())
+}
+
+/// Checks the SHA-256 of `bytes` against a hex checksum, optionally prefixed with `sha256:`.
+fn verify_checksum(bytes: &[u8], checksum: &str) -> Result<(), UpdateError> {
+    let expected = checksum
+        .trim()
+        .trim_start_matches("sha256:")
+        .to_ascii_lowercase();
+    let actual = hex::encode(Sha256::digest(bytes));
+    if actual != expected {
+        return Err(UpdateError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+/// Writes the new binary next to the current one and renames it into place.
+/// The previous binary is kept with an `.old` extension so a failed update can be rolled back by hand.
+fn swap_executable(executable: &Path, bytes: &[u8]) -> io::Result<()> {
+    let staged = executable.with_extension("new");
+    let previous = executable.with_extension("old");
+
+    std::fs::write(&staged, bytes)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    if previous.exists() {
+        std::fs::remove_file(&previous)?;
+    }
+    // Windows allows renaming a running executable but not overwriting it.
+    std::fs::rename(executable, &previous)?;
+    if let Err(e) = std::fs::rename(&staged, executable) {
+        warn!(
+            "Failed to install new binary, restoring previous one: {}",
+            e
+        );
+        std::fs::rename(&previous, executable)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Replaces the current process with the new executable, keeping the same arguments.
+#[cfg(unix)]
+fn restart(executable: &Path) -> io::Error {
+    use std::os::unix::process::CommandExt;
+
+    info!("Restarting agent...");
+    std::process::Command::new(executable)
+        .args(env::args_os().skip(1))
+        .exec()
+}
+
+/// Starts the new executable with the same arguments and exits the current process.
+#[cfg(windows)]
+fn restart(executable: &Path) -> io::Error {
+    info!("Restarting agent...");
+    match std::process::Command::new(executable)
+        .args(env::args_os().skip(1))
+        .spawn()
+    {
+        Ok(_) => std::process::exit(0),
+        Err(e) => e,
+    }
+}
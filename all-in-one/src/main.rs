@@ -0,0 +1,137 @@
+//! `all-in-one` runs central command, a local agent and the web UI in one process, for laptops
+//! and demos.
+//!
+//! # Wiring
+//! - Central command and the agent exchange messages over an in-process message bus (a
+//!   `memory://` URL, see `core_logic::bus`) instead of TCP, so the agent opens no listen port
+//!   and central command never dials it.
+//! - The web UI serves its pages from the source tree's templates, on port 8000 as usual.
+//! - All three use the MongoDB at `MONGODB_URI`. When it is unset, all-in-one starts and manages
+//!   a `mongod` of its own instead, see `mongod`. The datastore is not embedded: `mongod` has to
+//!   be installed, or all-in-one fails at startup.
+//!
+//! Every component keeps its own configuration; the defaults below only apply when the variable
+//! is unset. Flags are seen by every component: `--simulate` makes the agent simulate jobs and
+//! `--dry-run` puts central command in dry-run mode.
+//!
+//! The process exits when any of the components, or the managed `mongod`, stops, or on Ctrl-C or
+//! SIGTERM. The managed `mongod` is stopped with it.
+//!
+//! # Configuration
+//! - `MESSAGE_BUS_URL`: The bus central command and the agent share (default:
+//!   `memory://all-in-one`). A `nats://` URL makes the bundled agent use NATS like remote ones.
+//! - `AGENT_NAME`: Name of the bundled agent (default: `local`).
+//! - `ROCKET_TEMPLATE_DIR`: The web UI's templates (default: those of the source tree).
+//!
+//! # Example
+//!
+//! ```sh
+//! cargo run --release -p all-in-one -- --simulate
+//! ```
+mod mongod;
+
+use tracing::{error, info};
+
+use std::env;
+use std::future;
+use std::process::ExitCode;
+
+use crate::mongod::Mongod;
+
+const DEFAULT_MESSAGE_BUS_URL: &str = "memory://all-in-one";
+const DEFAULT_AGENT_NAME: &str = "local";
+
+/// Sets `name` to `value` unless it is set already.
+fn set_default(name: &str, value: &str) {
+    if env::var_os(name).is_none() {
+        // SAFETY: Called from `main` before the runtime starts any other thread.
+        unsafe { env::set_var(name, value) };
+    }
+}
+
+async fn run(mongod: Option<Mongod>) -> ExitCode {
+    core_logic::logging::init();
+    let mut mongod_child = match &mongod {
+        Some(mongod) => match mongod.spawn() {
+            Ok(child) => {
+                info!(
+                    "Started mongod with its data in {}",
+                    mongod.data_dir().display()
+                );
+                Some(child)
+            }
+            Err(e) => {
+                error!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    info!(
+        "Running central command, agent {} and the web UI in one process",
+        env::var("AGENT_NAME").unwrap_or_default()
+    );
+
+    let agent = tokio::spawn(agent::run());
+    let webui = tokio::spawn(async { webui::rocket().await.launch().await.map(|_| ()) });
+    let stopped = tokio::select! {
+        result = central_command::run() => result.map_err(|e| format!("Central command failed: {}", e)),
+        result = agent => match result {
+            Ok(result) => result.map_err(|e| format!("Agent failed: {}", e)),
+            Err(e) => Err(format!("Agent panicked: {}", e)),
+        },
+        result = webui => match result {
+            Ok(result) => result.map_err(|e| format!("Web UI failed: {}", e)),
+            Err(e) => Err(format!("Web UI panicked: {}", e)),
+        },
+        result = async {
+            match mongod_child.as_mut() {
+                Some(child) => child.wait().await,
+                None => future::pending().await,
+            }
+        } => match result {
+            Ok(status) => Err(format!("mongod exited: {}", status)),
+            Err(e) => Err(format!("Error waiting for mongod: {}", e)),
+        },
+        _ = terminated() => Ok(()),
+    };
+    if let Some(child) = mongod_child {
+        info!("Stopping mongod");
+        Mongod::stop(child).await;
+    }
+    match stopped {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Resolves on SIGTERM, e.g. from a service manager. Ctrl-C is handled by central command.
+async fn terminated() {
+    #[cfg(unix)]
+    if let Ok(mut signal) =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    {
+        signal.recv().await;
+        return;
+    }
+    future::pending::<()>().await
+}
+
+fn main() -> ExitCode {
+    set_default("MESSAGE_BUS_URL", DEFAULT_MESSAGE_BUS_URL);
+    set_default("AGENT_NAME", DEFAULT_AGENT_NAME);
+    set_default("ROCKET_TEMPLATE_DIR", webui::TEMPLATE_DIR);
+    let mongod = Mongod::from_env();
+    if let Some(mongod) = &mongod {
+        set_default("MONGODB_URI", &mongod.uri());
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to build runtime")
+        .block_on(run(mongod))
+}
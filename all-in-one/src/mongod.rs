@@ -0,0 +1,121 @@
+//! The `mongod` all-in-one starts when `MONGODB_URI` is unset, so nothing has to be running
+//! beforehand. MongoDB is not embedded: `mongod` has to be installed, and all-in-one fails at
+//! startup, saying so, when it is not found. It listens on localhost only and keeps its data
+//! between runs in `MONGOD_DATA_DIR`, which is never removed; delete it to start over. It is
+//! stopped with all-in-one, given `STOP_TIMEOUT_SECONDS` to shut down cleanly before it is killed.
+//!
+//! # Configuration
+//! - `MONGOD_PATH`: The `mongod` binary (default: `mongod`, looked up on `PATH`).
+//! - `MONGOD_DATA_DIR`: Its data directory, created if missing; its log is written there too
+//!   (default: `.all-in-one/mongodb`).
+//! - `MONGOD_PORT`: Its port (default: `27018`, so it doesn't clash with a MongoDB on the usual
+//!   port).
+use tokio::process::{Child, Command};
+use tokio::time::timeout;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const DEFAULT_MONGOD_PATH: &str = "mongod";
+const DEFAULT_DATA_DIR: &str = ".all-in-one/mongodb";
+const DEFAULT_PORT: u16 = 27018;
+/// Time `mongod` is given to shut down before it is killed.
+const STOP_TIMEOUT_SECONDS: u64 = 10;
+
+pub struct Mongod {
+    path: String,
+    data_dir: PathBuf,
+    port: u16,
+}
+
+impl Mongod {
+    /// The `mongod` to start, or `None` when `MONGODB_URI` names a database to use instead.
+    pub fn from_env() -> Option<Self> {
+        if env::var_os("MONGODB_URI").is_some() {
+            return None;
+        }
+        Some(Mongod {
+            path: env::var("MONGOD_PATH").unwrap_or_else(|_| DEFAULT_MONGOD_PATH.to_string()),
+            data_dir: env::var_os("MONGOD_DATA_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_DIR)),
+            port: env::var("MONGOD_PORT")
+                .ok()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(DEFAULT_PORT),
+        })
+    }
+
+    /// The connection string of the started `mongod`.
+    pub fn uri(&self) -> String {
+        format!("mongodb://127.0.0.1:{}", self.port)
+    }
+
+    /// Starts `mongod`. Components connecting before it accepts connections retry, see
+    /// `MONGODB_STARTUP_RETRIES`.
+    pub fn spawn(&self) -> io::Result<Child> {
+        let path = find_executable(&self.path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} was not found on PATH; install MongoDB, set MONGOD_PATH to its mongod, \
+                     or set MONGODB_URI to use a running database instead",
+                    self.path
+                ),
+            )
+        })?;
+        fs::create_dir_all(&self.data_dir)?;
+        let mut command = Command::new(path);
+        // Out of the terminal's process group, so Ctrl-C reaches all-in-one only and `stop` shuts
+        // it down after the components.
+        #[cfg(unix)]
+        command.process_group(0);
+        command
+            .arg("--dbpath")
+            .arg(&self.data_dir)
+            .arg("--logpath")
+            .arg(self.data_dir.join("mongod.log"))
+            .arg("--logappend")
+            .args(["--bind_ip", "127.0.0.1", "--port", &self.port.to_string()])
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("Failed to start {}: {}", self.path, e)))
+    }
+
+    pub fn data_dir(&self) -> &PathBuf {
+        &self.data_dir
+    }
+
+    /// Stops `child` cleanly: it is asked to shut down, then killed if it has not exited within
+    /// `STOP_TIMEOUT_SECONDS`. Does nothing when it exited already.
+    pub async fn stop(mut child: Child) {
+        #[cfg(unix)]
+        if let Some(pid) = child.id() {
+            // SAFETY: `pid` is our child, not reaped yet as `id` returned it.
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+            if timeout(Duration::from_secs(STOP_TIMEOUT_SECONDS), child.wait())
+                .await
+                .is_ok()
+            {
+                return;
+            }
+        }
+        let _ = child.kill().await;
+    }
+}
+
+/// `path` when it names a file, or the file it names in one of the `PATH` directories when it is
+/// a bare name.
+fn find_executable(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let name = format!("{}{}", path.display(), env::consts::EXE_SUFFIX);
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&name))
+        .find(|candidate| candidate.is_file())
+}
@@ -0,0 +1,12 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    {
+        // Compile the proto with protox so a system `protoc` isn't required.
+        println!("cargo:rerun-if-changed=proto/agent.proto");
+        let file_descriptors = protox::compile(["proto/agent.proto"], ["proto"])?;
+        tonic_build::configure()
+            .build_client(false)
+            .compile_fds(file_descriptors)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,214 @@
+/// `AgentCache` keeps the agents collection in memory, so the `AgentManager` looks agents up
+/// without querying MongoDB every time it dials agents or dispatches jobs.
+///
+/// # Overview
+/// - The cache follows a change stream of the agents collection, so registrations, heartbeats,
+///   drains and edits made through the web UI or by other instances show up as they happen. It is
+///   also reloaded in full every `AGENT_CACHE_REFRESH_SECONDS`, in case a change was missed.
+/// - Change streams need MongoDB to run as a replica set. Against a standalone server the cache is
+///   reloaded every few seconds instead, as agents were fetched before it existed, and following
+///   the change stream is tried again now and then.
+/// - The command receiver reloads an agent as soon as it registers, so a new agent is dialed
+///   without waiting for the change stream.
+///
+/// # Configuration
+/// - `AGENT_CACHE_REFRESH_SECONDS`: Seconds between full reloads while following the change
+///   stream (default: 60).
+///
+/// # Example
+/// ```rust
+/// let cache = AgentCache::default();
+/// cache.refresh(&datastore).await?;
+/// spawn(cache.clone().start(datastore.clone()));
+/// let draining = cache.draining().await;
+/// ```
+use bson::{Document, doc};
+use futures::stream::TryStreamExt;
+use mongodb::change_stream::event::OperationType;
+use mongodb::options::FullDocumentType;
+use tokio::sync::RwLock;
+use tokio::time::{Instant, sleep, timeout};
+use tracing::{debug, info, warn};
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::get_agent_cache_refresh_seconds;
+use core_logic::datastore::{
+    Datastore,
+    agents::{AgentV1, Status as AgentStatus},
+};
+
+/// Seconds between reloads while the change stream cannot be followed.
+const POLL_INTERVAL_SECONDS: u64 = 5;
+/// Seconds to poll before trying to follow the change stream again.
+const CHANGE_STREAM_RETRY_SECONDS: u64 = 300;
+
+#[derive(Debug, Clone, Default)]
+pub struct AgentCache {
+    agents: Arc<RwLock<HashMap<String, AgentV1>>>, // By name, including agents in the trash
+}
+
+impl AgentCache {
+    /// Reloads every agent from the datastore.
+    pub async fn refresh(&self, datastore: &Datastore) -> Result<(), Box<dyn Error>> {
+        let collection = datastore.get_collection::<AgentV1>("agents").await?;
+        let agents: Vec<AgentV1> = collection.find(doc! {}).await?.try_collect().await?;
+        *self.agents.write().await = agents
+            .into_iter()
+            .map(|agent| (agent.name.clone(), agent))
+            .collect();
+        Ok(())
+    }
+
+    /// Reloads `agent_name` from the datastore, e.g. after it registered.
+    pub async fn reload(
+        &self,
+        datastore: &Datastore,
+        agent_name: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let collection = datastore.get_collection::<AgentV1>("agents").await?;
+        let agent = collection.find_one(doc! { "name": agent_name }).await?;
+        let mut agents = self.agents.write().await;
+        match agent {
+            Some(agent) => agents.insert(agent_name.to_string(), agent),
+            None => agents.remove(agent_name),
+        };
+        Ok(())
+    }
+
+    /// Agents not in the trash.
+    pub async fn agents(&self) -> Vec<AgentV1> {
+        self.agents
+            .read()
+            .await
+            .values()
+            .filter(|agent| agent.deleted_at.is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// The agents named `names` that exist, by name.
+    pub async fn get_many(&self, names: &[String]) -> HashMap<String, AgentV1> {
+        let agents = self.agents.read().await;
+        names
+            .iter()
+            .filter_map(|name| Some((name.clone(), agents.get(name)?.clone())))
+            .collect()
+    }
+
+    /// Names of the agents set to drain or moved to the trash, which are not given new jobs.
+    pub async fn draining(&self) -> HashSet<String> {
+        self.agents
+            .read()
+            .await
+            .values()
+            .filter(|agent| agent.draining || agent.deleted_at.is_some())
+            .map(|agent| agent.name.clone())
+            .collect()
+    }
+
+    /// Names of the agents the datastore has as connected, whichever instance reaches them.
+    pub async fn connected(&self) -> Vec<String> {
+        self.agents
+            .read()
+            .await
+            .values()
+            .filter(|agent| AgentStatus::is_connected(&agent.status))
+            .map(|agent| agent.name.clone())
+            .collect()
+    }
+
+    /// Keeps the cache up to date until central command stops.
+    pub async fn start(self, datastore: Arc<Datastore>) {
+        loop {
+            match self
+                .follow_changes(&datastore)
+                .await
+                .map_err(|e| e.to_string()) // Box<dyn Error> is not Send
+            {
+                Ok(()) => info!("Agent change stream ended, reloading agents"),
+                Err(e) => warn!(
+                    "Cannot follow agent changes ({}), reloading agents every {} seconds",
+                    e, POLL_INTERVAL_SECONDS
+                ),
+            }
+            let retry_at = Instant::now() + Duration::from_secs(CHANGE_STREAM_RETRY_SECONDS);
+            while Instant::now() < retry_at {
+                if let Err(e) = self.refresh(&datastore).await.map_err(|e| e.to_string()) {
+                    warn!("Error reloading agents: {}", e);
+                }
+                sleep(Duration::from_secs(POLL_INTERVAL_SECONDS)).await;
+            }
+        }
+    }
+
+    /// Applies the changes to the agents collection as they happen, reloading every agent every
+    /// `AGENT_CACHE_REFRESH_SECONDS`. Returns when the change stream ends or fails.
+    async fn follow_changes(&self, datastore: &Datastore) -> Result<(), Box<dyn Error>> {
+        let collection = datastore.get_collection::<AgentV1>("agents").await?;
+        let mut changes = collection
+            .watch()
+            .full_document(FullDocumentType::UpdateLookup)
+            .await?;
+        // Reloaded once the stream is open, so no change falls in between.
+        self.refresh(datastore).await?;
+        info!("Following changes to agents");
+
+        let refresh_interval = Duration::from_secs(get_agent_cache_refresh_seconds());
+        let mut refresh_at = Instant::now() + refresh_interval;
+        loop {
+            let wait = refresh_at.saturating_duration_since(Instant::now());
+            let Ok(change) = timeout(wait, changes.try_next()).await else {
+                self.refresh(datastore).await?;
+                refresh_at = Instant::now() + refresh_interval;
+                continue;
+            };
+            let Some(change) = change? else {
+                return Ok(());
+            };
+            match change.operation_type {
+                OperationType::Insert | OperationType::Update | OperationType::Replace => {
+                    match change.full_document {
+                        Some(agent) => self.insert(agent).await,
+                        // Deleted before it could be looked up; the delete follows.
+                        None => debug!("Agent change without a document"),
+                    }
+                }
+                OperationType::Delete => {
+                    self.remove(change.document_key.as_ref()).await;
+                }
+                OperationType::Drop | OperationType::DropDatabase | OperationType::Invalidate => {
+                    self.agents.write().await.clear();
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Stores a changed agent, dropping it under its old name if it was renamed.
+    async fn insert(&self, agent: AgentV1) {
+        let mut agents = self.agents.write().await;
+        if agents
+            .get(&agent.name)
+            .is_none_or(|cached| cached.id != agent.id)
+        {
+            agents.retain(|_, cached| cached.id != agent.id);
+        }
+        agents.insert(agent.name.clone(), agent);
+    }
+
+    /// Removes the agent deleted from the datastore, as found by its `_id`.
+    async fn remove(&self, document_key: Option<&Document>) {
+        let Some(id) = document_key.and_then(|key| key.get_object_id("_id").ok()) else {
+            return;
+        };
+        self.agents
+            .write()
+            .await
+            .retain(|_, agent| agent.id != Some(id));
+    }
+}
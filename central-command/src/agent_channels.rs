@@ -0,0 +1,105 @@
+/// `AgentChannels` tracks agents that are reachable through a connection the agent opened itself
+/// (for example a gRPC dispatch stream or a message bus subscription) rather than by central command dialing the agent's port.
+///
+/// Transports register a channel for an agent when its connection opens and prune it once the
+/// connection (and so the receiving half of the channel) has closed. The `AgentManager`
+/// dispatches jobs to these agents by pushing messages into the channel, and skips dialing them
+/// over TCP. Agents that pull their jobs register with `register_puller`: jobs are not pushed to
+/// them, they claim them when they poll, but control messages such as cancellations are.
+///
+/// # Example
+/// ```rust
+/// let channels = AgentChannels::default();
+/// let (sender, receiver) = tokio::sync::mpsc::channel(16);
+/// channels.register("my_agent", sender).await;
+/// channels.send("my_agent", Message::Ping).await?;
+/// ```
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::Sender;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use core_logic::messages::{Message, MessageError};
+
+#[derive(Debug, Clone, Default)]
+pub struct AgentChannels {
+    channels: Arc<Mutex<HashMap<String, Sender<Message>>>>,
+    pullers: Arc<Mutex<HashSet<String>>>, // Agents that claim their jobs instead
+}
+
+impl AgentChannels {
+    /// Registers the channel used to reach `agent_name`, replacing any previous one.
+    pub async fn register(&self, agent_name: &str, sender: Sender<Message>) {
+        self.channels
+            .lock()
+            .await
+            .insert(agent_name.to_string(), sender);
+        self.pullers.lock().await.remove(agent_name);
+    }
+
+    /// Registers the channel of an agent that pulls its jobs, replacing any previous one.
+    pub async fn register_puller(&self, agent_name: &str, sender: Sender<Message>) {
+        self.channels
+            .lock()
+            .await
+            .insert(agent_name.to_string(), sender);
+        self.pullers.lock().await.insert(agent_name.to_string());
+    }
+
+    /// Whether `agent_name` pulls its jobs rather than having them pushed.
+    pub async fn pulls(&self, agent_name: &str) -> bool {
+        self.pullers.lock().await.contains(agent_name)
+    }
+
+    /// Removes the channel for `agent_name` if its receiver has been dropped.
+    /// A channel registered by a newer connection for the same agent is left in place.
+    pub async fn prune(&self, agent_name: &str) {
+        let mut channels = self.channels.lock().await;
+        if channels
+            .get(agent_name)
+            .is_some_and(|sender| sender.is_closed())
+        {
+            channels.remove(agent_name);
+            self.pullers.lock().await.remove(agent_name);
+        }
+    }
+
+    /// Names of all agents currently reachable through a channel.
+    pub async fn names(&self) -> Vec<String> {
+        self.channels
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, sender)| !sender.is_closed())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    pub async fn contains(&self, agent_name: &str) -> bool {
+        self.channels
+            .lock()
+            .await
+            .get(agent_name)
+            .is_some_and(|sender| !sender.is_closed())
+    }
+
+    /// Sends a message to `agent_name`, dropping the channel if the connection has gone away.
+    pub async fn send(&self, agent_name: &str, message: Message) -> Result<(), MessageError> {
+        let sender = self.channels.lock().await.get(agent_name).cloned();
+        let Some(sender) = sender else {
+            return Err(MessageError::AcknowledgeError(format!(
+                "No channel registered for agent {}",
+                agent_name
+            )));
+        };
+        if sender.send(message).await.is_err() {
+            self.prune(agent_name).await;
+            return Err(MessageError::AcknowledgeError(format!(
+                "Channel for agent {} is closed",
+                agent_name
+            )));
+        }
+        Ok(())
+    }
+}
@@ -2,23 +2,68 @@
 /// dispatching jobs, and maintaining the state of connected agents in a distributed system.
 ///
 /// # Responsibilities
-/// - Maintains a map of currently connected agents and their TCP streams.
-/// - Periodically fetches agent information from a database and attempts to connect to new agents.
-/// - Pings connected agents to ensure they are still reachable, removing any that are unreachable.
-/// - Dispatches jobs to agents based on job requirements and agent availability.
+/// - Maintains a map of currently connected agents and their TCP streams, recording traffic on
+///   them in `ConnectionMetrics`.
+/// - Dispatches to agents connected through `AgentChannels` (e.g. gRPC) without dialing them.
+/// - Leaves the runs of agents that pull their jobs in the cycle's `agents_pending`, for them to
+///   claim when they poll (see `claim_runs`).
+/// - Reads agents from the `AgentCache`, kept up to date from the database, and attempts to connect
+///   to new agents.
+/// - Pings connected agents to ensure they are still reachable, removing any that are unreachable
+///   and marking those slower than `AGENT_DEGRADED_PING_MS` to answer as degraded. Dialed
+///   connections use TCP keepalive, and an agent that does not acknowledge a message within
+///   `AGENT_IDLE_TIMEOUT_SECONDS` is removed, so half-open connections are pruned.
+/// - Records an SLA breach warning, for the `Notifier` to send, for each agent still running a
+///   cycle that has gone on for longer than its job's `sla.expected_duration`, and an overdue
+///   warning for each job whose scheduled run has not started within its `sla.max_start_delay`.
+/// - Marks agents offline whose last heartbeat is older than `AGENT_OFFLINE_AFTER_SECONDS`, which
+///   catches agents central command does not dial, such as channel and reverse dispatch agents.
+///   Such an agent is only online again once it kept answering for `AGENT_ONLINE_AFTER_SECONDS`.
+/// - Skips agents an operator set to drain when dispatching, keeping them marked as draining.
+/// - Holds back dispatches over the global, per-job or per-agent rate limits until their token
+///   buckets refill, leaving the agents in the cycle's `agents_pending` (see `dispatch_limits`).
+/// - Dispatches jobs to agents based on job requirements, the platforms and Kubernetes node labels
+///   agents reported and agent availability, holding back jobs in a blackout window until it
+///   ends. Jobs only run on agents of their namespace, and jobs that require approval only once an operator approved the run.
+/// - Pushes a job's files to each agent ahead of its dispatch (see `file_distribution`).
 /// - Updates job status and tracks which agents are running which jobs in the database.
+/// - Pushes operator requested binary updates to connected agents.
+/// - Answers operator requested pings with the round-trip time or the error encountered.
+/// - Sends operator requested cancellations to the agents running a job.
+/// - Sends operator requested timeout extensions to the agents running a job.
+/// - In dry-run mode, records which runs it would dispatch to which agents instead of dispatching
+///   them or sending agents anything but pings (see `dry_run`).
+/// - Does all of the above only while this instance leads, so standbys that share the datastore
+///   neither dial agents nor dispatch jobs (see `leader`). When agents are partitioned, every
+///   instance does so instead, for the agents of the partitions it holds (see `partitions`), and
+///   only marking silent agents offline is left to the leader.
 ///
 /// # Key Methods
-/// - `new`: Creates a new `AgentManager` with the provided datastore.
-/// - `fetch_database_agents`: Retrieves all agents from the database and converts them into `ConnectedAgent` instances.
+/// - `new`: Creates a new `AgentManager` with the provided datastore, agent channels, agent cache, connection metrics, scheduler strategy, leadership and agent partitions.
+/// - `fetch_database_agents`: Retrieves all agents from the agent cache and converts them into `ConnectedAgent` instances.
 /// - `check_for_unconnected_agents`: Checks for agents in the database that are not currently connected and attempts to connect to them.
 /// - `fetch_unconnected_agents`: Returns a list of agents from the database that are not currently connected.
 /// - `connect_unconnected_agents`: Attempts to establish TCP connections to a list of unconnected agents.
 /// - `ping_existing_agents`: Sends a ping message to each connected agent and removes those that are unreachable.
-/// - `run_job`: Dispatches a job to the required agents and updates the job's running state in the database.
-/// - `get_jobs_to_run`: Retrieves jobs from the database that are ready to run and updates their status.
+/// - `close_released_agents`: Closes the connections to agents of partitions this instance no longer holds.
+/// - `reaches`: Whether this instance is the one to send messages to an agent.
+/// - `update_agent_heartbeat`: Records that an agent answered, as online, degraded or draining.
+/// - `mark_stale_agents`: Marks agents offline that have not answered for `AGENT_OFFLINE_AFTER_SECONDS`.
+/// - `record_sla_breaches`: Records warnings and raises alerts for runs going on for longer than their job's SLA expects.
+/// - `record_overdue_jobs`: Records warnings and raises alerts for scheduled runs that did not start as soon as their job's SLA expects.
+/// - `fetch_draining_agents`: Names of the agents that are not given new jobs, from the database for callers without the agent cache.
+/// - `run_jobs`: Pushes the due jobs' files to and dispatches them to the required agents, in batches to agents that understand `DispatchBatch`, giving each run a `run_id` that correlates its logs, records each cycle's `JobExecutionV1` and updates the jobs' running state in the database.
+/// - `claim_runs`: Hands the runs waiting for an agent that pulls its jobs to it, claiming each on the job document.
+/// - `fail_claimed_run`: Records a claimed run that could not be delivered and releases the claim.
+/// - `record_dispatch_failure`: Stores a `DispatchFailed` run for an agent the job could not be delivered to.
+/// - `record_scheduling_lag`: Records how far behind `next_run` a job was dispatched.
+/// - `get_jobs_to_run`: Retrieves jobs from the database that are ready to run, lets the `SchedulerStrategy` choose which start and where, and updates their status.
 /// - `add_agent_to_running_job`: Updates a job in the database to include an agent in its running list.
-/// - `start`: Launches background tasks to periodically check for new agents, ping existing agents, connect to unconnected agents, and dispatch jobs.
+/// - `push_pending_updates`: Sends `UpdateAgent` messages for agents with an operator requested update.
+/// - `answer_ping_requests`: Pings agents with an operator requested ping and records the round-trip time.
+/// - `send_cancel_requests`: Sends `CancelJob` to the agents running a job with a requested cancellation.
+/// - `send_timeout_extensions`: Sends `ExtendTimeout` to the agents whose runs an operator asked to extend.
+/// - `start`: Loads the agent cache and launches background tasks to keep it up to date, periodically check for new agents, ping existing agents, connect to unconnected agents, and dispatch jobs.
 ///
 /// # Usage
 /// Create an `AgentManager` instance and call `start` to begin managing agents and dispatching jobs.
@@ -26,7 +71,16 @@
 /// # Example
 /// ```rust
 /// let datastore = Arc::new(Datastore::new(...));
-/// let agent_manager = AgentManager::new(datastore).await;
+/// let agent_manager = AgentManager::new(
+///     datastore,
+///     AgentChannels::default(),
+///     AgentCache::default(),
+///     ConnectionMetrics::new(get_central_command_id()),
+///     scheduler::scheduler_from_env(),
+///     Leadership::always(),
+///     AgentPartitions::all(),
+/// )
+/// .await;
 /// agent_manager.start().await;
 /// ```
 ///
@@ -37,13 +91,14 @@
 /// # Errors
 /// Most methods return `Result` types and log errors using the `tracing` crate.
 /// Errors are handled gracefully to ensure the manager continues running.
-use bson::{DateTime, Document, doc};
+use bson::{DateTime, doc};
 use futures::stream::TryStreamExt;
 use tokio::net::TcpStream;
 use tokio::spawn;
 use tokio::sync::Mutex;
-use tokio::time::sleep;
-use tracing::{debug, error, info};
+use tokio::time::{Instant, sleep};
+use tracing::{Instrument, debug, error, info, warn};
+use uuid::Uuid;
 
 use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
@@ -51,18 +106,51 @@ use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::agent_cache::AgentCache;
+use crate::agent_channels::AgentChannels;
+use crate::connection_metrics::ConnectionMetrics;
+use crate::dispatch_limits::DispatchLimiter;
+use crate::dry_run::DispatchPlanner;
+use crate::file_distribution::PushedFile;
+use crate::leader::Leadership;
+use crate::paging;
+use crate::partitions::AgentPartitions;
+use crate::scheduler::SchedulerStrategy;
+use crate::{
+    get_agent_degraded_ping_ms, get_agent_dispatch_rate_limit_per_minute,
+    get_agent_idle_timeout_seconds, get_agent_offline_after_seconds,
+    get_agent_online_after_seconds, get_dispatch_rate_limit_per_minute, get_dry_run,
+    get_job_dispatch_rate_limit_per_minute, get_misfire_grace_seconds, get_tcp_keepalive_seconds,
+};
 use core_logic::datastore::{
     Datastore,
-    agents::{AgentV1, Status as AgentStatus},
-    jobs::{JobV1, Status},
+    agent_events::{AgentEventKind, AgentEventV1},
+    agent_groups::AgentGroupV1,
+    agents::{AgentV1, PingResult, Status as AgentStatus},
+    alerts::{AlertKind, AlertV1},
+    blackout_windows::BlackoutWindowV1,
+    connections::ConnectionKind,
+    job_executions::JobExecutionV1,
+    job_warnings::JobWarningV1,
+    jobs::{EXECUTOR_AGENTS, JobV1, MisfirePolicy, Status, next_run_datetime},
+    runs::RunsV1,
 };
-use core_logic::messages::{DispatchJob, Message, MessageError};
+use core_logic::keepalive;
+use core_logic::logging;
+use core_logic::messages::{CancelJob, DispatchJob, ExtendTimeout, Message, MessageError};
 use tokio::io::AsyncReadExt;
 
+/// Scheduling lag above which a dispatch is logged as a warning.
+const SCHEDULING_LAG_WARNING_MS: i64 = 5000;
+/// Bytes of `DispatchJob`s, each serialized on its own, sent to an agent in one `DispatchBatch`,
+/// so the batch fits the single read the agent's listen port takes of each message.
+const DISPATCH_BATCH_MAX_BYTES: usize = 48 * 1024;
+
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
 pub struct ConnectedAgent {
     name: String,
     address: SocketAddr,
+    dispatch_batch: bool, // Whether the agent understands `DispatchBatch`
 }
 
 impl TryFrom<AgentV1> for ConnectedAgent {
@@ -74,43 +162,86 @@ impl TryFrom<AgentV1> for ConnectedAgent {
             std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid address")
         })?;
         Ok(ConnectedAgent {
+            dispatch_batch: agent
+                .features
+                .iter()
+                .any(|feature| feature == "dispatch_batch"),
             name: agent.name,
             address: socket_addr,
         })
     }
 }
 
+/// The cycle of a job being dispatched by `AgentManager::run_jobs`.
+struct CycleDispatch<'a> {
+    job: &'a JobV1,
+    agents_to_run: HashSet<String>, // Agents this instance dispatches the cycle to
+    files: Vec<PushedFile>,         // Pushed to each agent ahead of the run
+    dispatched: HashSet<String>,    // Agents dispatched to over TCP
+}
+
+/// A connection central command dialed to an agent.
+#[derive(Debug)]
+pub struct AgentStream {
+    stream: TcpStream,
+    connection_id: String, // Id in `ConnectionMetrics`
+}
+
 #[derive(Debug)]
 pub struct AgentManager {
     datastore: Arc<Datastore>,
-    connected_agents: HashMap<ConnectedAgent, TcpStream>,
+    connected_agents: HashMap<ConnectedAgent, AgentStream>,
+    agent_channels: AgentChannels,
+    agent_cache: AgentCache,
+    connection_metrics: ConnectionMetrics,
+    scheduler: Arc<dyn SchedulerStrategy>,
+    leadership: Leadership,
+    partitions: AgentPartitions,
+    dispatch_limiter: DispatchLimiter,
+    planner: Option<DispatchPlanner>, // Plans dispatches instead of running jobs in dry-run mode
 }
 
 impl AgentManager {
-    pub async fn new(datastore: Arc<Datastore>) -> Self {
+    pub async fn new(
+        datastore: Arc<Datastore>,
+        agent_channels: AgentChannels,
+        agent_cache: AgentCache,
+        connection_metrics: ConnectionMetrics,
+        scheduler: Arc<dyn SchedulerStrategy>,
+        leadership: Leadership,
+        partitions: AgentPartitions,
+    ) -> Self {
+        let planner = get_dry_run().then(|| {
+            DispatchPlanner::new(datastore.clone(), agent_cache.clone(), scheduler.clone())
+        });
         Self {
             datastore,
             connected_agents: HashMap::new(),
+            agent_channels,
+            agent_cache,
+            connection_metrics,
+            scheduler,
+            leadership,
+            partitions,
+            dispatch_limiter: DispatchLimiter::new(
+                get_dispatch_rate_limit_per_minute(),
+                get_job_dispatch_rate_limit_per_minute(),
+                get_agent_dispatch_rate_limit_per_minute(),
+            ),
+            planner,
         }
     }
 
-    /// Fetch agents from the database
-    /// This function retrieves all agents from the database and converts them into `ConnectedAgent` instances
-    async fn fetch_database_agents(
-        &self,
-    ) -> Result<HashSet<ConnectedAgent>, Box<dyn std::error::Error>> {
-        let collection = self.datastore.get_collection::<AgentV1>("agents").await?;
-        let filter = Document::new();
-        let mut cursor = collection.find(filter).await?;
-        let mut agents = vec![];
-        while let Some(agent) = cursor.try_next().await? {
-            agents.push(agent);
-        }
-        let agents: HashSet<ConnectedAgent> = agents
-            .iter()
-            .filter_map(|agent| agent.clone().try_into().ok())
-            .collect();
-        Ok(agents)
+    /// Fetch agents from the agent cache
+    /// This function retrieves all agents known to the database, except those in the trash, and
+    /// converts them into `ConnectedAgent` instances
+    async fn fetch_database_agents(&self) -> HashSet<ConnectedAgent> {
+        self.agent_cache
+            .agents()
+            .await
+            .into_iter()
+            .filter_map(|agent| agent.try_into().ok())
+            .collect()
     }
 
     /// Check for unconnected agents and connect to them.
@@ -131,24 +262,24 @@ impl AgentManager {
     }
 
     /// Get unconnected agents.
-    /// Fetch agents from the database and filter out those that are already connected
+    /// Fetch agents from the database and filter out those that are already connected, or in a
+    /// partition this instance does not hold
     async fn fetch_unconnected_agents(&mut self) -> Vec<ConnectedAgent> {
-        let fetched_agents = match self.fetch_database_agents().await {
-            Ok(agents) => agents,
-            Err(e) => {
-                error!("Error fetching agents: {}", e);
-                return Vec::new();
-            }
-        };
+        let fetched_agents = self.fetch_database_agents().await;
         debug!("Fetched agents: {:?}", fetched_agents);
 
+        // Agents connected through a channel opened the connection themselves and are not dialed.
+        let channel_agents = self.agent_channels.names().await;
+
         fetched_agents
             .iter()
             .filter(|agent| {
-                !self.connected_agents.keys().any(|connected_agent| {
-                    connected_agent.address.port() == agent.address.port()
-                        && connected_agent.address.ip() == agent.address.ip()
-                })
+                !channel_agents.contains(&agent.name)
+                    && self.partitions.owns(&agent.name)
+                    && !self.connected_agents.keys().any(|connected_agent| {
+                        connected_agent.address.port() == agent.address.port()
+                            && connected_agent.address.ip() == agent.address.ip()
+                    })
             })
             .cloned()
             .collect()
@@ -161,11 +292,30 @@ impl AgentManager {
             match TcpStream::connect(agent.address).await {
                 Ok(stream) => {
                     info!("Connected to agent {}!", agent.address);
-                    self.connected_agents.insert(agent, stream);
+                    let keepalive = Duration::from_secs(get_tcp_keepalive_seconds());
+                    if let Err(e) = keepalive::enable(&stream, keepalive) {
+                        warn!(
+                            "Failed to enable keepalive to agent {}: {}",
+                            agent.address, e
+                        );
+                    }
+                    let connection_id = self
+                        .connection_metrics
+                        .open(ConnectionKind::Outbound, agent.address, Some(&agent.name))
+                        .await;
+                    self.connected_agents.insert(
+                        agent,
+                        AgentStream {
+                            stream,
+                            connection_id,
+                        },
+                    );
                 }
                 Err(e) => {
                     error!("Error connecting to agent {}: {}", agent.address, e);
-                    if let Err(err) = Self::update_agent_offline(datastore.clone(), &agent).await {
+                    if let Err(err) =
+                        Self::update_agent_offline(datastore.clone(), &agent.name).await
+                    {
                         error!("Failed to update agent {} to offline: {}", agent.name, err);
                     }
                 }
@@ -176,6 +326,7 @@ impl AgentManager {
     /// Check if connected agents are still reachable
     /// This function sends a ping message to each connected agent and removes those that are unreachable
     async fn ping_existing_agents(&mut self) {
+        self.close_released_agents().await;
         let mut agents_to_remove = Vec::new();
 
         let datastore = self.datastore.clone();
@@ -184,7 +335,8 @@ impl AgentManager {
             debug!("Pinging agent {}!", agent.address);
 
             let message = Message::Ping;
-            match Self::write_to_agent(stream, &message).await {
+            let sent_at = Instant::now();
+            match Self::write_to_agent(stream, &message, &self.connection_metrics).await {
                 Ok(_) => {
                     debug!("Agent {} is reachable.", agent.address);
                 }
@@ -194,7 +346,8 @@ impl AgentManager {
                     continue; // Skip to the next agent
                 }
             }
-            match Self::update_agent_online(datastore.clone(), agent).await {
+            let degraded = sent_at.elapsed().as_millis() > get_agent_degraded_ping_ms() as u128;
+            match Self::update_agent_heartbeat(datastore.clone(), &agent.name, degraded).await {
                 Ok(_) => {
                     debug!("Updated agent {} to online status.", agent.name);
                 }
@@ -207,83 +360,1126 @@ impl AgentManager {
         for agent in agents_to_remove {
             debug!("Removing agent {} due to failed ping.", agent.address);
             // Update the agent's status to offline in the database
-            if let Err(e) = Self::update_agent_offline(datastore.clone(), &agent).await {
+            if let Err(e) = Self::update_agent_offline(datastore.clone(), &agent.name).await {
                 error!("Failed to update agent {} to offline: {}", agent.name, e);
             }
-            self.connected_agents.remove(&agent);
+            if let Some(stream) = self.connected_agents.remove(&agent) {
+                self.connection_metrics.close(&stream.connection_id).await;
+            }
         }
     }
 
-    async fn update_agent_offline(
+    /// Closes the connections to agents whose partition this instance gave up, without marking
+    /// them offline: the instance that holds the partition now dials them.
+    async fn close_released_agents(&mut self) {
+        let released: Vec<ConnectedAgent> = self
+            .connected_agents
+            .keys()
+            .filter(|agent| !self.partitions.owns(&agent.name))
+            .cloned()
+            .collect();
+        for agent in released {
+            info!(
+                "Disconnecting from agent {}, its partition {} is no longer held",
+                agent.name,
+                self.partitions.of(&agent.name)
+            );
+            if let Some(stream) = self.connected_agents.remove(&agent) {
+                self.connection_metrics.close(&stream.connection_id).await;
+            }
+        }
+    }
+
+    /// Whether this instance sends messages to `agent_name`: it holds the agent's partition, or the
+    /// agent connected to it through a channel.
+    async fn reaches(&self, agent_name: &str) -> bool {
+        self.partitions.owns(agent_name) || self.agent_channels.contains(agent_name).await
+    }
+
+    /// Names of the agents this instance dialed or that connected to it through a channel, other
+    /// than those that pull their jobs.
+    async fn reached_agents(&self) -> Vec<String> {
+        let mut reached = self
+            .connected_agents
+            .keys()
+            .map(|a| a.name.clone())
+            .collect::<Vec<_>>();
+        for agent_name in self.agent_channels.names().await {
+            if !self.agent_channels.pulls(&agent_name).await {
+                reached.push(agent_name);
+            }
+        }
+        reached
+    }
+
+    pub(crate) async fn update_agent_offline(
         datastore: Arc<Datastore>,
-        agent: &ConnectedAgent,
+        agent_name: &str,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let collection = datastore.get_collection::<AgentV1>("agents").await?;
-        let filter = doc! { "name": &agent.name };
+        let filter = doc! { "name": agent_name };
         let update = doc! {
             "$set": {
                 //"last_ping": DateTime::now(),
                 "status": AgentStatus::Offline as i32, // Update status to Offline
+                "heartbeat_since": null,
             }
         };
-        collection.update_one(filter, update).await?;
+        let previous = collection.find_one_and_update(filter, update).await?;
+        if let Some(previous) = previous
+            && previous.status.is_connected()
+        {
+            Self::record_agent_event(&datastore, agent_name, AgentEventKind::Disconnected, "")
+                .await;
+        }
         Ok(())
     }
 
-    async fn update_agent_online(
+    /// Records a heartbeat from an agent whose round-trip time is not measured, e.g. one that
+    /// pings central command over its own connection.
+    pub(crate) async fn update_agent_online(
+        datastore: Arc<Datastore>,
+        agent_name: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Self::update_agent_heartbeat(datastore, agent_name, false).await
+    }
+
+    /// Records that the agent answered. It is marked `Draining` when an operator set it to drain,
+    /// otherwise `Degraded` when it was slow to answer and `Online` when it was not. An agent
+    /// marked offline by `mark_stale_agents` stays offline until it has been answering for
+    /// `AGENT_ONLINE_AFTER_SECONDS`.
+    pub(crate) async fn update_agent_heartbeat(
         datastore: Arc<Datastore>,
-        agent: &ConnectedAgent,
+        agent_name: &str,
+        degraded: bool,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let collection = datastore.get_collection::<AgentV1>("agents").await?;
-        let filter = doc! { "name": &agent.name };
-        let update = doc! {
-            "$set": {
-            "last_ping": DateTime::now(),
-            "status": AgentStatus::Online as i32, // Update status to Online
+        let filter = doc! { "name": agent_name };
+        let reachable = match degraded {
+            true => AgentStatus::Degraded,
+            false => AgentStatus::Online,
+        };
+        let now = DateTime::now();
+        let recovering_after = DateTime::from_millis(
+            now.timestamp_millis() - get_agent_online_after_seconds() as i64 * 1000,
+        );
+        // A pipeline update, so the status is chosen from the stored `stale` and `draining` flags
+        // atomically.
+        let update = vec![
+            doc! {
+                "$set": {
+                    "last_ping": now,
+                    "heartbeat_since": { "$ifNull": ["$heartbeat_since", now] },
+                }
+            },
+            doc! {
+                "$set": {
+                    "stale": {
+                        "$and": [
+                            { "$eq": ["$stale", true] },
+                            { "$gt": ["$heartbeat_since", recovering_after] },
+                        ]
+                    },
+                }
+            },
+            doc! {
+                "$set": {
+                    "status": {
+                        "$cond": [
+                            "$stale",
+                            AgentStatus::Offline as i32,
+                            {
+                                "$cond": [
+                                    { "$eq": ["$draining", true] },
+                                    AgentStatus::Draining as i32,
+                                    reachable as i32,
+                                ]
+                            },
+                        ]
+                    },
+                }
+            },
+        ];
+        let Some(previous) = collection.find_one_and_update(filter, update).await? else {
+            return Ok(());
+        };
+        if previous.stale && previous.heartbeat_since.unwrap_or(now) > recovering_after {
+            debug!(
+                "Agent {} is answering again, but not yet for {} seconds",
+                agent_name,
+                get_agent_online_after_seconds()
+            );
+            return Ok(());
+        }
+        let status = match previous.draining {
+            true => AgentStatus::Draining,
+            false => reachable,
+        };
+        if !previous.status.is_connected() {
+            Self::record_agent_event(&datastore, agent_name, AgentEventKind::Connected, "").await;
+        } else if previous.status != status {
+            info!(
+                "Agent {} is now {} (was {})",
+                agent_name, status, previous.status
+            );
+        }
+        Ok(())
+    }
+
+    /// Records a `SlaBreached` job warning for each agent still running a cycle that started longer
+    /// ago than its job's `sla.expected_duration`, once per cycle and agent, so the job's owners
+    /// hear about a slow run before it finishes or times out.
+    pub(crate) async fn record_sla_breaches(
+        datastore: &Datastore,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let collection = datastore.get_collection::<JobV1>("jobs").await?;
+        let running: Vec<JobV1> = collection
+            .find(doc! {
+                "status": Status::Running,
+                "cycle_id": { "$ne": null },
+                "sla.expected_duration": { "$exists": true },
+            })
+            .await?
+            .try_collect()
+            .await?;
+        let now = DateTime::now().timestamp_millis();
+        for job in running {
+            let (Some(cycle_id), Some(expected)) = (
+                &job.cycle_id,
+                job.sla.as_ref().and_then(|sla| sla.expected_duration),
+            ) else {
+                continue;
+            };
+            let Some(execution) = JobExecutionV1::find(datastore, cycle_id).await? else {
+                continue;
+            };
+            let elapsed = ((now - execution.started_at.timestamp_millis()) / 1000).max(0) as u32;
+            if elapsed <= expected {
+                continue;
             }
+            for agent_name in &job.agents_running {
+                if job.agents_complete.contains(agent_name) {
+                    continue;
+                }
+                if JobWarningV1::record_sla_breach(datastore, &job, agent_name, elapsed, expected)
+                    .await?
+                {
+                    warn!(
+                        "Job {} has been running on agent {} for {} seconds, longer than the {} seconds its SLA expects",
+                        job.name, agent_name, elapsed, expected
+                    );
+                    AlertV1::raise(
+                        datastore,
+                        AlertKind::SlaBreached,
+                        &job.name,
+                        agent_name,
+                        None,
+                        &format!(
+                            "Running for {} seconds, longer than the {} seconds its SLA expects",
+                            elapsed, expected
+                        ),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records an `Overdue` job warning for each pending job whose `next_run` is further in the
+    /// past than its `sla.max_start_delay`, once per scheduled run, so the job's owners hear about
+    /// a run that did not happen, e.g. because none of its agents is connected.
+    pub(crate) async fn record_overdue_jobs(
+        datastore: &Datastore,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let now = DateTime::now().timestamp_millis() / 1000;
+        let collection = datastore.get_collection::<JobV1>("jobs").await?;
+        let pending: Vec<JobV1> = collection
+            .find(doc! {
+                "status": Status::Pending,
+                "next_run": { "$lt": next_run_datetime(now) },
+                "sla.max_start_delay": { "$exists": true },
+            })
+            .await?
+            .try_collect()
+            .await?;
+        for job in pending {
+            let (Some(overdue), Some(max_delay)) = (
+                job.overdue_seconds(now),
+                job.sla.as_ref().and_then(|sla| sla.max_start_delay),
+            ) else {
+                continue;
+            };
+            if overdue <= max_delay as i64 {
+                continue;
+            }
+            if JobWarningV1::record_overdue(datastore, &job, overdue as u32).await? {
+                warn!(
+                    "Job {} has not started {} seconds after it was scheduled, later than the {} seconds its SLA allows",
+                    job.name, overdue, max_delay
+                );
+                AlertV1::raise(
+                    datastore,
+                    AlertKind::Overdue,
+                    &job.name,
+                    "",
+                    None,
+                    &format!(
+                        "Not started {} seconds after it was scheduled, later than the {} seconds its SLA allows",
+                        overdue, max_delay
+                    ),
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks agents offline whose last heartbeat is older than `AGENT_OFFLINE_AFTER_SECONDS`, even
+    /// though no write to them failed, and ends the run of heartbeats of agents that were
+    /// recovering from it.
+    pub(crate) async fn mark_stale_agents(
+        datastore: &Datastore,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let offline_after = get_agent_offline_after_seconds();
+        if offline_after == 0 {
+            return Ok(());
+        }
+        let collection = datastore.get_collection::<AgentV1>("agents").await?;
+        let now = DateTime::now();
+        let cutoff = DateTime::from_millis(now.timestamp_millis() - offline_after as i64 * 1000);
+        let connected: Vec<i32> = AgentStatus::ALL
+            .into_iter()
+            .filter(AgentStatus::is_connected)
+            .map(i32::from)
+            .collect();
+        let stale = doc! {
+            "last_ping": { "$lt": cutoff },
+            "$or": [
+                { "status": { "$in": connected } },
+                { "heartbeat_since": { "$ne": null } },
+            ],
         };
-        collection.update_one(filter, update).await?;
+        let mut cursor = collection.find(stale.clone()).await?;
+        let mut names = vec![];
+        while let Some(agent) = cursor.try_next().await? {
+            names.push(agent.name);
+        }
+
+        for name in names {
+            // Checked again, in case a heartbeat arrived since the agent was found.
+            let mut filter = stale.clone();
+            filter.insert("name", &name);
+            let update = doc! {
+                "$set": {
+                    "status": AgentStatus::Offline as i32,
+                    "stale": true,
+                    "heartbeat_since": null,
+                }
+            };
+            let Some(previous) = collection.find_one_and_update(filter, update).await? else {
+                continue;
+            };
+            if previous.status.is_connected() {
+                let silent_seconds =
+                    (now.timestamp_millis() - previous.last_ping.timestamp_millis()) / 1000;
+                warn!(
+                    "Agent {} has not answered for {} seconds, marking it offline",
+                    name, silent_seconds
+                );
+                let detail = format!("No heartbeat for {} seconds", silent_seconds);
+                Self::record_agent_event(datastore, &name, AgentEventKind::Disconnected, &detail)
+                    .await;
+            }
+        }
         Ok(())
     }
 
-    /// Run a job
-    /// This function sends a `DispatchJob` message to each required agent and updates the job's `agents_running` list.
-    async fn run_job(&mut self, job: &JobV1) -> Result<(), Box<dyn std::error::Error>> {
-        let datastore = self.datastore.clone();
-        let agents_to_run: &HashSet<String> = &job.agents_required.iter().cloned().collect();
+    /// Names of the agents set to drain or moved to the trash, which are not given new jobs.
+    pub(crate) async fn fetch_draining_agents(
+        datastore: &Datastore,
+    ) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+        let collection = datastore.get_collection::<AgentV1>("agents").await?;
+        let mut cursor = collection
+            .find(doc! { "$or": [{ "draining": true }, { "deleted_at": { "$ne": null } }] })
+            .await?;
+        let mut names = HashSet::new();
+        while let Some(agent) = cursor.try_next().await? {
+            names.insert(agent.name);
+        }
+        Ok(names)
+    }
 
-        for (agent, stream) in self.connected_agents.iter_mut() {
-            if !agents_to_run.contains(&agent.name) {
+    /// Records an agent lifecycle event for its timeline and the webhooks.
+    pub(crate) async fn record_agent_event(
+        datastore: &Datastore,
+        agent_name: &str,
+        kind: AgentEventKind,
+        detail: &str,
+    ) {
+        info!("Agent {} is now {:?}", agent_name, kind);
+        if let Err(e) = AgentEventV1::new(agent_name, kind)
+            .with_detail(detail)
+            .insert_entry(datastore)
+            .await
+        {
+            error!(
+                "Failed to record {:?} event for agent {}: {}",
+                kind, agent_name, e
+            );
+        }
+    }
+
+    /// Push pending updates
+    /// Sends an `UpdateAgent` message to each connected agent that has a `pending_update` recorded,
+    /// clearing it once the agent has acknowledged the message (or, for channel agents, once it
+    /// has been queued).
+    async fn push_pending_updates(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let collection = self.datastore.get_collection::<AgentV1>("agents").await?;
+        let filter = doc! { "pending_update": { "$exists": true, "$ne": null } };
+        let mut cursor = collection.find(filter).await?;
+        let mut agents = vec![];
+        while let Some(agent) = cursor.try_next().await? {
+            agents.push(agent);
+        }
+
+        for agent in agents {
+            let Some(update) = agent.pending_update else {
+                continue;
+            };
+            let stream = self
+                .connected_agents
+                .iter_mut()
+                .find(|(connected_agent, _)| connected_agent.name == agent.name)
+                .map(|(_, stream)| stream);
+            if stream.is_none() && !self.agent_channels.contains(&agent.name).await {
+                debug!(
+                    "Agent {} has a pending update but is not connected.",
+                    agent.name
+                );
                 continue;
             }
 
-            let dispatch_job = DispatchJob {
-                job_name: job.name.clone(),
-                command: job.command.clone(),
-                args: job.args.join(" "),
-                valid_return_codes: Some(job.valid_return_codes.clone()),
-                agent_name: Some(agent.name.clone()),
+            info!(
+                "Sending update to version {} to agent {}",
+                update.version, agent.name
+            );
+            let message = Message::UpdateAgent(update.into());
+            let result = match stream {
+                Some(stream) => {
+                    Self::write_to_agent(stream, &message, &self.connection_metrics).await
+                }
+                None => self.agent_channels.send(&agent.name, message).await,
             };
-            let message = Message::DispatchJob(dispatch_job);
+            if let Err(e) = result {
+                error!("Failed to send update to agent {}: {}", agent.name, e);
+                continue;
+            }
+            collection
+                .update_one(
+                    doc! { "name": &agent.name },
+                    doc! { "$unset": { "pending_update": "" } },
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Answer ping requests
+    /// Sends a `Ping` to each agent that has a `ping_requested_at` recorded and stores the outcome in
+    /// `ping_result`. The round trip is timed for connections central command dialed; agents reached
+    /// through a channel don't acknowledge messages, so only whether the ping was queued is known.
+    async fn answer_ping_requests(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let collection = self.datastore.get_collection::<AgentV1>("agents").await?;
+        let filter = doc! { "ping_requested_at": { "$exists": true, "$ne": null } };
+        let mut cursor = collection.find(filter).await?;
+        let mut agents = vec![];
+        while let Some(agent) = cursor.try_next().await? {
+            agents.push(agent);
+        }
 
-            if let Err(e) = Self::write_to_agent(stream, &message).await {
-                error!("Failed to dispatch job to agent {}: {}", agent.address, e);
+        for agent in agents {
+            let Some(requested_at) = agent.ping_requested_at else {
                 continue;
+            };
+            let stream = self
+                .connected_agents
+                .iter_mut()
+                .find(|(connected_agent, _)| connected_agent.name == agent.name)
+                .map(|(_, stream)| stream);
+
+            let (via, rtt_ms, error) = match stream {
+                Some(stream) => {
+                    let started = Instant::now();
+                    match Self::write_to_agent(stream, &Message::Ping, &self.connection_metrics)
+                        .await
+                    {
+                        Ok(()) => (
+                            "connection",
+                            Some(started.elapsed().as_secs_f64() * 1000.0),
+                            None,
+                        ),
+                        Err(e) => ("connection", None, Some(e.to_string())),
+                    }
+                }
+                None if self.agent_channels.contains(&agent.name).await => {
+                    match self.agent_channels.send(&agent.name, Message::Ping).await {
+                        Ok(()) => ("channel", None, None),
+                        Err(e) => ("channel", None, Some(e.to_string())),
+                    }
+                }
+                // Left for the instance that holds the agent's partition.
+                None if !self.partitions.owns(&agent.name) => continue,
+                None => ("none", None, Some("Agent is not connected".to_string())),
+            };
+            info!(
+                "Ping requested for agent {}: rtt {:?} ms, error {:?}",
+                agent.name, rtt_ms, error
+            );
+
+            let result = PingResult {
+                requested_at,
+                completed_at: DateTime::now(),
+                rtt_ms,
+                via: via.to_string(),
+                error,
+            };
+            collection
+                .update_one(
+                    doc! { "name": &agent.name },
+                    doc! {
+                        "$set": { "ping_result": bson::to_bson(&result)? },
+                        "$unset": { "ping_requested_at": "" },
+                    },
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send cancel requests
+    /// Sends a `CancelJob` message to every agent running a job that has a `cancel_requested_at`
+    /// recorded, then clears the request. The agents report the killed runs as usual, which
+    /// completes the job. Requests for jobs that are not running are cleared without effect.
+    /// When agents are partitioned, each instance cancels the job on the agents it reaches and
+    /// records them in `cancel_sent_to`, and the request is cleared once all were sent to.
+    async fn send_cancel_requests(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let collection = self.datastore.get_collection::<JobV1>("jobs").await?;
+        let filter = doc! { "cancel_requested_at": { "$exists": true, "$ne": null } };
+        let mut cursor = collection.find(filter).await?;
+        let mut jobs = vec![];
+        while let Some(job) = cursor.try_next().await? {
+            jobs.push(job);
+        }
+
+        for job in jobs {
+            let mut sent_to = vec![];
+            for agent_name in &job.agents_running {
+                if job.cancel_sent_to.contains(agent_name) || !self.reaches(agent_name).await {
+                    continue;
+                }
+                info!("Cancelling job {} on agent {}", job.name, agent_name);
+                let message = Message::CancelJob(CancelJob {
+                    job_name: job.name.clone(),
+                });
+                if let Err(e) = self.send_to_agent(agent_name, message).await {
+                    error!(
+                        "Failed to cancel job {} on agent {}: {}",
+                        job.name, agent_name, e
+                    );
+                }
+                sent_to.push(agent_name.clone());
+            }
+            let update = match job.agents_running.iter().all(|agent_name| {
+                job.cancel_sent_to.contains(agent_name) || sent_to.contains(agent_name)
+            }) {
+                true => doc! { "$unset": { "cancel_requested_at": "", "cancel_sent_to": "" } },
+                false if sent_to.is_empty() => continue,
+                false => doc! { "$addToSet": { "cancel_sent_to": { "$each": sent_to } } },
+            };
+            collection
+                .update_one(doc! { "name": &job.name }, update)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends an `ExtendTimeout` message for every timeout extension requested on a job, to the
+    /// agent whose run it extends, then removes the requests. The agent records the extension on
+    /// the run. Requests for agents that are not running the job are removed without effect.
+    /// When agents are partitioned, each instance handles the requests for the agents it reaches.
+    async fn send_timeout_extensions(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let collection = self.datastore.get_collection::<JobV1>("jobs").await?;
+        let filter = doc! { "timeout_extension_requests.0": { "$exists": true } };
+        let jobs: Vec<JobV1> = collection.find(filter).await?.try_collect().await?;
+
+        for job in jobs {
+            let mut requests = vec![];
+            for request in &job.timeout_extension_requests {
+                if self.reaches(&request.agent_name).await {
+                    requests.push(request);
+                }
+            }
+            if requests.is_empty() {
+                continue;
+            }
+            for request in &requests {
+                if !job.agents_running.contains(&request.agent_name) {
+                    warn!(
+                        "Not extending the timeout of job {} on agent {}: it is not running there",
+                        job.name, request.agent_name
+                    );
+                    continue;
+                }
+                info!(
+                    "Extending the timeout of job {} on agent {} by {} seconds",
+                    job.name, request.agent_name, request.seconds
+                );
+                let message = Message::ExtendTimeout(ExtendTimeout {
+                    job_name: job.name.clone(),
+                    seconds: request.seconds,
+                });
+                if let Err(e) = self.send_to_agent(&request.agent_name, message).await {
+                    error!(
+                        "Failed to extend the timeout of job {} on agent {}: {}",
+                        job.name, request.agent_name, e
+                    );
+                }
             }
-            Self::add_agent_to_running_job(datastore.clone(), job, &agent.name).await?;
-            debug!("Dispatched job to agent {}: {:?}", agent.address, message);
+            // Only the requests that were read, in case more were added meanwhile.
+            let sent = bson::to_bson(&requests)?;
+            collection
+                .update_one(
+                    doc! { "name": &job.name },
+                    doc! { "$pullAll": { "timeout_extension_requests": sent } },
+                )
+                .await?;
         }
 
         Ok(())
     }
 
-    async fn write_to_agent(stream: &mut TcpStream, message: &Message) -> Result<(), MessageError> {
+    /// Sends `message` to an agent over its TCP connection, or through its channel.
+    async fn send_to_agent(
+        &mut self,
+        agent_name: &str,
+        message: Message,
+    ) -> Result<(), MessageError> {
+        let stream = self
+            .connected_agents
+            .iter_mut()
+            .find(|(connected_agent, _)| connected_agent.name == agent_name)
+            .map(|(_, stream)| stream);
+        match stream {
+            Some(stream) => Self::write_to_agent(stream, &message, &self.connection_metrics).await,
+            None => self.agent_channels.send(agent_name, message).await,
+        }
+    }
+
+    /// Starts dispatching the cycle of `job`: records its scheduling lag and execution, and picks
+    /// the agents this instance dispatches it to, which are removed from the cycle's
+    /// `agents_pending`. `agents_pending` keeps those that pull their jobs and those held back by
+    /// the dispatch rate limits. When agents are partitioned, or the cycle was already dispatched
+    /// to some agents, only the `agents_pending` this instance reaches are picked, so no agent is
+    /// dispatched to twice. Returns `None` when the job's files cannot be loaded, after recording
+    /// the runs as failed to dispatch.
+    async fn prepare_dispatch<'a>(
+        &mut self,
+        job: &'a JobV1,
+        draining: &HashSet<String>,
+    ) -> Result<Option<CycleDispatch<'a>>, Box<dyn std::error::Error>> {
+        let datastore = self.datastore.clone();
+        Self::record_scheduling_lag(datastore.clone(), job).await?;
+        JobExecutionV1::start(&datastore, job).await?;
+        let partitioned = self.partitions.is_enabled();
+        let reached = self.reached_agents().await;
+        let mut agents_to_run: HashSet<String> = job
+            .target_agents()
+            .iter()
+            .filter(|agent_name| !draining.contains(*agent_name))
+            .filter(|agent_name| {
+                (!partitioned && job.agents_running.is_empty())
+                    || job.agents_pending.contains(*agent_name)
+            })
+            .cloned()
+            .collect();
+        let throttled: HashSet<String> = agents_to_run
+            .iter()
+            .filter(|agent_name| reached.contains(*agent_name))
+            .filter(
+                |agent_name| match self.dispatch_limiter.admit(&job.name, agent_name) {
+                    Ok(()) => false,
+                    Err(reason) => {
+                        info!(
+                            "Holding back job {} on agent {}: {}",
+                            job.name, agent_name, reason
+                        );
+                        true
+                    }
+                },
+            )
+            .cloned()
+            .collect();
+        agents_to_run.retain(|agent_name| !throttled.contains(agent_name));
+        let handled: Vec<&String> = job
+            .agents_pending
+            .iter()
+            .filter(|agent_name| reached.contains(*agent_name) && !throttled.contains(*agent_name))
+            .collect();
+        if !handled.is_empty() {
+            let collection = datastore.get_collection::<JobV1>("jobs").await?;
+            collection
+                .update_one(
+                    doc! { "_id": job.id },
+                    doc! { "$pullAll": { "agents_pending": handled } },
+                )
+                .await?;
+        }
+
+        let files = match PushedFile::load_all(&datastore, &job.files).await {
+            Ok(files) => files,
+            Err(e) => {
+                error!("Not dispatching job {}: {}", job.name, e);
+                for agent_name in agents_to_run.iter().filter(|name| reached.contains(*name)) {
+                    let run_id = Uuid::new_v4().to_string();
+                    Self::record_dispatch_failure(&datastore, job, agent_name, run_id, &e).await;
+                }
+                return Ok(None);
+            }
+        };
+        Ok(Some(CycleDispatch {
+            job,
+            agents_to_run,
+            files,
+            dispatched: HashSet::new(),
+        }))
+    }
+
+    /// Run jobs
+    /// This function dispatches the cycles of `jobs` (see `prepare_dispatch`), sending a `DispatchJob` message to each of
+    /// their agents and updating each job's `agents_running` list. Agents connected over TCP are dispatched to first, then
+    /// agents reachable through a channel. Agents connected over TCP with the `dispatch_batch` feature are sent the runs of
+    /// every job due on them as `DispatchBatch` messages of up to `DISPATCH_BATCH_MAX_BYTES`, each acknowledged once,
+    /// instead of one `DispatchJob` per run.
+    async fn run_jobs(&mut self, jobs: &[JobV1], draining: &HashSet<String>) {
+        let mut cycles = vec![];
+        for job in jobs {
+            match self.prepare_dispatch(job, draining).await {
+                Ok(Some(cycle)) => cycles.push(cycle),
+                Ok(None) => (),
+                Err(e) => error!("Failed to dispatch job {}: {}", job.name, e),
+            }
+        }
+        let datastore = self.datastore.clone();
+        let connection_metrics = &self.connection_metrics;
+
+        for (agent, stream) in self.connected_agents.iter_mut() {
+            let runs: Vec<(usize, String, DispatchJob)> = cycles
+                .iter()
+                .enumerate()
+                .filter(|(_, cycle)| cycle.agents_to_run.contains(&agent.name))
+                .map(|(index, cycle)| {
+                    let run_id = Uuid::new_v4().to_string();
+                    let dispatch =
+                        Self::dispatch_job(cycle.job, &agent.name, run_id.clone(), &cycle.files);
+                    (index, run_id, dispatch)
+                })
+                .collect();
+
+            for batch in Self::dispatch_batches(runs, agent.dispatch_batch) {
+                let delivered =
+                    Self::deliver_runs(stream, &cycles, &batch, connection_metrics).await;
+                for (index, run_id, dispatch) in batch {
+                    let job = cycles[index].job;
+                    let span = logging::run_span(Some(&run_id), &job.name, &agent.name);
+                    if let Err(e) = &delivered {
+                        span.in_scope(|| {
+                            error!("Failed to dispatch job to agent {}: {}", agent.address, e)
+                        });
+                        Self::record_dispatch_failure(
+                            &datastore,
+                            job,
+                            &agent.name,
+                            run_id,
+                            &e.to_string(),
+                        )
+                        .instrument(span)
+                        .await;
+                        continue;
+                    }
+                    if let Err(e) =
+                        Self::add_agent_to_running_job(datastore.clone(), job, &agent.name).await
+                    {
+                        span.in_scope(|| error!("Failed to record dispatch of job: {}", e));
+                        continue;
+                    }
+                    cycles[index].dispatched.insert(agent.name.clone());
+                    span.in_scope(|| {
+                        info!("Dispatched job {} to agent {}", job.name, agent.address);
+                        debug!("Dispatched job to agent {}: {:?}", agent.address, dispatch);
+                    });
+                }
+            }
+        }
+
+        let channel_agents = self.agent_channels.names().await;
+        for cycle in &cycles {
+            let job = cycle.job;
+            for agent_name in &channel_agents {
+                if !cycle.agents_to_run.contains(agent_name)
+                    || cycle.dispatched.contains(agent_name)
+                    || self.agent_channels.pulls(agent_name).await
+                {
+                    continue;
+                }
+
+                let run_id = Uuid::new_v4().to_string();
+                let span = logging::run_span(Some(&run_id), &job.name, agent_name);
+                let message = Message::DispatchJob(Self::dispatch_job(
+                    job,
+                    agent_name,
+                    run_id.clone(),
+                    &cycle.files,
+                ));
+
+                let delivered = async {
+                    for chunk in cycle.files.iter().flat_map(|file| file.chunks(&job.name)) {
+                        self.agent_channels.send(agent_name, chunk).await?;
+                    }
+                    self.agent_channels.send(agent_name, message).await
+                };
+                if let Err(e) = delivered.await {
+                    span.in_scope(|| {
+                        error!("Failed to dispatch job to agent {}: {}", agent_name, e)
+                    });
+                    Self::record_dispatch_failure(
+                        &datastore,
+                        job,
+                        agent_name,
+                        run_id,
+                        &e.to_string(),
+                    )
+                    .instrument(span)
+                    .await;
+                    continue;
+                }
+                if let Err(e) =
+                    Self::add_agent_to_running_job(datastore.clone(), job, agent_name).await
+                {
+                    span.in_scope(|| error!("Failed to record dispatch of job: {}", e));
+                    continue;
+                }
+                span.in_scope(|| {
+                    info!(
+                        "Dispatched job {} to agent {} over channel",
+                        job.name, agent_name
+                    )
+                });
+            }
+        }
+    }
+
+    /// Splits the runs to dispatch to an agent into what is sent at once: each run on its own,
+    /// or, when the agent understands `batches`, as many runs as fit `DISPATCH_BATCH_MAX_BYTES`.
+    /// A run too large to share a batch is sent on its own.
+    fn dispatch_batches(
+        runs: Vec<(usize, String, DispatchJob)>,
+        batches: bool,
+    ) -> Vec<Vec<(usize, String, DispatchJob)>> {
+        if !batches {
+            return runs.into_iter().map(|run| vec![run]).collect();
+        }
+        let mut grouped: Vec<Vec<(usize, String, DispatchJob)>> = vec![];
+        let mut batch_bytes: usize = 0;
+        for run in runs {
+            let bytes = Vec::<u8>::try_from(Message::DispatchJob(run.2.clone()))
+                .map(|serialized| serialized.len())
+                .unwrap_or(usize::MAX);
+            match grouped.last_mut() {
+                Some(batch) if batch_bytes.saturating_add(bytes) <= DISPATCH_BATCH_MAX_BYTES => {
+                    batch_bytes += bytes;
+                    batch.push(run);
+                }
+                _ => {
+                    batch_bytes = bytes;
+                    grouped.push(vec![run]);
+                }
+            }
+        }
+        grouped
+    }
+
+    /// Sends a batch of `runs` over a dialed connection: the files pushed for each run's job in
+    /// `cycles`, then the run as a `DispatchJob`, or the runs as one `DispatchBatch`.
+    async fn deliver_runs(
+        stream: &mut AgentStream,
+        cycles: &[CycleDispatch<'_>],
+        runs: &[(usize, String, DispatchJob)],
+        connection_metrics: &ConnectionMetrics,
+    ) -> Result<(), MessageError> {
+        for (index, _, _) in runs {
+            let cycle = &cycles[*index];
+            for chunk in cycle
+                .files
+                .iter()
+                .flat_map(|file| file.chunks(&cycle.job.name))
+            {
+                Self::write_to_agent(stream, &chunk, connection_metrics).await?;
+            }
+        }
+        let message = match runs {
+            [(_, _, dispatch)] => Message::DispatchJob(dispatch.clone()),
+            _ => Message::DispatchBatch(
+                runs.iter()
+                    .map(|(_, _, dispatch)| dispatch.clone())
+                    .collect(),
+            ),
+        };
+        Self::write_to_agent(stream, &message, connection_metrics).await
+    }
+
+    /// Claims up to `max_jobs` runs waiting for `agent_name`, an agent that pulls its jobs: running
+    /// cycles that have it in `agents_pending`. A run is claimed by removing the agent from
+    /// `agents_pending` only if it is still there, so it is handed out once even when the agent
+    /// polls several instances. Returns each claimed job with the run's id and the `FileChunk`s
+    /// and `DispatchJob` to send. Draining agents are given nothing.
+    pub(crate) async fn claim_runs(
+        datastore: &Datastore,
+        agent_name: &str,
+        max_jobs: u32,
+    ) -> Result<Vec<(JobV1, String, Vec<Message>)>, Box<dyn std::error::Error>> {
+        if max_jobs == 0
+            || Self::fetch_draining_agents(datastore)
+                .await?
+                .contains(agent_name)
+        {
+            return Ok(vec![]);
+        }
+        let collection = datastore.get_collection::<JobV1>("jobs").await?;
+        let waiting: Vec<JobV1> = collection
+            .find(doc! { "status": Status::Running, "agents_pending": agent_name })
+            .sort(doc! { "next_run": 1 })
+            .limit(max_jobs as i64)
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut claimed = vec![];
+        for job in waiting {
+            let filter =
+                doc! { "_id": job.id, "cycle_id": &job.cycle_id, "agents_pending": agent_name };
+            let update = doc! {
+                "$pull": { "agents_pending": agent_name },
+                "$addToSet": { "agents_running": agent_name },
+            };
+            if collection.update_one(filter, update).await?.modified_count == 0 {
+                continue; // Claimed through another connection, or the cycle ended
+            }
+            JobExecutionV1::start(datastore, &job).await?;
+            let run_id = Uuid::new_v4().to_string();
+            let files = match PushedFile::load_all(datastore, &job.files).await {
+                Ok(files) => files,
+                Err(e) => {
+                    error!("Not handing out job {}: {}", job.name, e);
+                    Self::fail_claimed_run(datastore, &job, agent_name, run_id, &e).await;
+                    continue;
+                }
+            };
+            let mut messages: Vec<Message> = files
+                .iter()
+                .flat_map(|file| file.chunks(&job.name))
+                .collect();
+            messages.push(Message::DispatchJob(Self::dispatch_job(
+                &job,
+                agent_name,
+                run_id.clone(),
+                &files,
+            )));
+            claimed.push((job, run_id, messages));
+        }
+        Ok(claimed)
+    }
+
+    /// Records that a run claimed by `agent_name` could not be delivered to it, and takes the agent
+    /// off the job's `agents_running` as if it had never been dispatched to.
+    pub(crate) async fn fail_claimed_run(
+        datastore: &Datastore,
+        job: &JobV1,
+        agent_name: &str,
+        run_id: String,
+        dispatch_error: &str,
+    ) {
+        Self::record_dispatch_failure(datastore, job, agent_name, run_id, dispatch_error).await;
+        let released = async {
+            let collection = datastore.get_collection::<JobV1>("jobs").await?;
+            collection
+                .update_one(
+                    doc! { "_id": job.id },
+                    doc! { "$pull": { "agents_running": agent_name } },
+                )
+                .await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        };
+        if let Err(e) = released.await {
+            error!(
+                "Failed to release the claim of {} on job {}: {}",
+                agent_name, job.name, e
+            );
+        }
+    }
+
+    /// Stores a `DispatchFailed` run, so the failed delivery shows up in the job's run history.
+    async fn record_dispatch_failure(
+        datastore: &Datastore,
+        job: &JobV1,
+        agent_name: &str,
+        run_id: String,
+        dispatch_error: &str,
+    ) {
+        let run = RunsV1::dispatch_failed(job, agent_name, run_id, dispatch_error);
+        if let Err(e) = run.insert_entry(&datastore.get_database()).await {
+            error!(
+                "Failed to record dispatch failure of job {} on {}: {}",
+                job.name, agent_name, e
+            );
+        }
+        let detail = format!("Could not be dispatched: {}", dispatch_error);
+        if let Err(e) = AlertV1::raise(
+            datastore,
+            AlertKind::RunFailed,
+            &job.name,
+            agent_name,
+            run.run_id.as_deref(),
+            &detail,
+        )
+        .await
+        {
+            error!(
+                "Failed to raise an alert for the dispatch failure of job {} on {}: {}",
+                job.name, agent_name, e
+            );
+        }
+        let severity = job.sla.as_ref().and_then(|sla| sla.severity.as_deref());
+        if let Some(severity) = paging::paging_severity(severity)
+            && let Err(e) = AlertV1::request_page(
+                datastore,
+                AlertKind::RunFailed,
+                &job.name,
+                agent_name,
+                &severity,
+            )
+            .await
+        {
+            error!(
+                "Failed to page for the dispatch failure of job {} on {}: {}",
+                job.name, agent_name, e
+            );
+        }
+    }
+
+    /// Records how long after `next_run` the job is being dispatched.
+    async fn record_scheduling_lag(
+        datastore: Arc<Datastore>,
+        job: &JobV1,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let lag_ms = (DateTime::now().timestamp_millis() - job.next_run * 1000).max(0);
+        if lag_ms >= SCHEDULING_LAG_WARNING_MS {
+            warn!(
+                "Job {} is being dispatched {} ms after it was due",
+                job.name, lag_ms
+            );
+        }
+        let collection = datastore.get_collection::<JobV1>("jobs").await?;
+        collection
+            .update_one(
+                doc! { "_id": job.id },
+                doc! { "$set": { "scheduling_lag_ms": lag_ms } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// The `DispatchJob` for the run of `job` on `agent_name`, with the command, arguments and
+    /// environment of the run being repeated when the cycle is a re-run.
+    fn dispatch_job(
+        job: &JobV1,
+        agent_name: &str,
+        run_id: String,
+        files: &[PushedFile],
+    ) -> DispatchJob {
+        let (command, args, env) = match &job.rerun {
+            Some(rerun) => (&rerun.command, &rerun.args, &rerun.env),
+            None => (&job.command, &job.args, &job.env),
+        };
+        DispatchJob {
+            job_name: job.name.clone(),
+            command: command.clone(),
+            args: args.join(" "),
+            valid_return_codes: Some(job.valid_return_codes.clone()),
+            timeout: (job.timeout > 0).then_some(job.timeout),
+            triggered_by: job.triggered_by.clone().unwrap_or_default().into(),
+            agent_name: Some(agent_name.to_string()),
+            redact_patterns: job.redact_patterns.clone(),
+            run_id: Some(run_id),
+            steps: job.steps.iter().map(Into::into).collect(),
+            files: files.iter().map(PushedFile::digest).collect(),
+            script: job.script.as_ref().map(Into::into),
+            container: job.container.as_ref().map(Into::into),
+            env: env.clone(),
+        }
+    }
+
+    /// Sends `message` over a dialed connection and waits for the agent's acknowledgment. An agent
+    /// that does not acknowledge within `AGENT_IDLE_TIMEOUT_SECONDS` fails the write, so a half-open
+    /// connection is dropped by the next ping instead of stalling the manager.
+    async fn write_to_agent(
+        agent_stream: &mut AgentStream,
+        message: &Message,
+        connection_metrics: &ConnectionMetrics,
+    ) -> Result<(), MessageError> {
+        let seconds = get_agent_idle_timeout_seconds();
+        if seconds == 0 {
+            return Self::exchange_with_agent(agent_stream, message, connection_metrics).await;
+        }
+        tokio::time::timeout(
+            Duration::from_secs(seconds),
+            Self::exchange_with_agent(agent_stream, message, connection_metrics),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            Err(MessageError::AcknowledgeError(format!(
+                "No acknowledgment from agent within {} seconds (AGENT_IDLE_TIMEOUT_SECONDS)",
+                seconds
+            )))
+        })
+    }
+
+    async fn exchange_with_agent(
+        agent_stream: &mut AgentStream,
+        message: &Message,
+        connection_metrics: &ConnectionMetrics,
+    ) -> Result<(), MessageError> {
+        let AgentStream {
+            stream,
+            connection_id,
+        } = agent_stream;
         match message.clone().tcp_write(stream).await {
-            Ok(_) => {
+            Ok(bytes) => {
+                connection_metrics
+                    .record_out(connection_id, bytes, Some(message))
+                    .await;
                 // Wait for a response from the agent
                 let mut buf = [0u8; 2]; // Adjust buffer size as needed for your protocol
                 match stream.read_exact(&mut buf).await {
-                    Ok(_) if &buf == b"OK" => Ok(()),
+                    Ok(_) if &buf == b"OK" => {
+                        connection_metrics
+                            .record_in(connection_id, buf.len(), None)
+                            .await;
+                        Ok(())
+                    }
                     _ => Err(MessageError::AcknowledgeError(
                         "Failed to receive acknowledgment from agent".to_string(),
                     )),
@@ -291,52 +1487,291 @@ impl AgentManager {
             }
             Err(e) => {
                 error!("Error writing to agent: {}", e);
-                Err(e.into())
+                Err(e)
             }
         }
     }
 
     /// Get jobs to run
     /// This function retrieves jobs from the database that are ready to run (status 0 and next_run < current time)
-    /// It updates their status to 1 (running) and returns the jobs that are now running without agents.
+    /// and not held back by a blackout window in progress, and offers them to the `scheduler`.
+    /// It updates the status of the jobs the scheduler selects to 1 (running) and returns the jobs that are now
+    /// running without agents, in the order the scheduler selected them.
+    /// Each returned job is given a `cycle_id` identifying this firing, which its runs are tagged with,
+    /// and its `cycle_agents`: the agents the scheduler assigns it from `agents_required` and the connected
+    /// members of its `agent_groups` in the job's namespace that run on one of the job's `platforms`
+    /// and on a node matching its `node_selector`, or for a re-run the agent of the run it repeats.
+    /// Jobs limited to platforms or nodes, or in a namespace, are only offered once an agent they can
+    /// run on is connected. Jobs that `requires_approval` are set to
+    /// `PendingApproval` instead, until an operator approves the run.
+    /// Each cycle also records its `agents_pending`, and cycles still pending on one of `reached`,
+    /// the agents this instance reaches, are returned again, e.g. once the dispatch rate limits let
+    /// them through. When agents are `partitioned`, only cycles pending on one of `reached` are
+    /// returned.
     pub async fn get_jobs_to_run(
         datastore: Arc<Datastore>,
+        agent_cache: &AgentCache,
         connected_agents: Vec<String>,
+        scheduler: &dyn SchedulerStrategy,
+        reached: &[String],
+        partitioned: bool,
     ) -> Result<Vec<JobV1>, Box<dyn std::error::Error>> {
         let timestamp = DateTime::now().to_chrono().timestamp();
         let collection = datastore.clone().get_collection::<JobV1>("jobs").await?;
+        let connected_groups =
+            AgentGroupV1::names_with_members(&datastore, &connected_agents).await?;
+        // Jobs in a blackout window stay pending until it ends.
+        let blackouts = BlackoutWindowV1::active(&datastore, DateTime::now()).await?;
+        let blacked_out_jobs: Vec<&String> =
+            blackouts.iter().flat_map(|window| &window.jobs).collect();
+        let mut targets = vec![
+            doc! { "agents_required": { "$in": &connected_agents } },
+            doc! { "agent_groups": { "$in": connected_groups } },
+        ];
+        for (kind, agent) in EXECUTOR_AGENTS {
+            if connected_agents.iter().any(|connected| connected == agent) {
+                targets.push(doc! { "executor.kind": kind });
+            }
+        }
         // Filter for jobs with status 0 and next_run < current time
         let filter = doc! {
             "$and": [
                 { "status": Status::Pending }, // Jobs with status equal to 0
-                { "next_run": { "$lt": timestamp } },  // Jobs where next_run is LESS THAN current_utc_time
+                { "next_run": { "$lt": next_run_datetime(timestamp) } },  // Jobs where next_run is LESS THAN current_utc_time
                 { "agents_running": [] }, // Jobs that are not currently running with agents
-                { "agents_required": { "$in": connected_agents } }
+                { "name": { "$nin": blacked_out_jobs } },
+                { "$or": targets }
             ]
         };
-        let update = doc! {
-            "$set": {
-                "status": Status::Running
-            },
+        let due: Vec<JobV1> = match blackouts.iter().find(|window| window.is_global()) {
+            Some(window) => {
+                debug!("Not starting jobs during blackout window {}", window.name);
+                vec![]
+            }
+            None => {
+                collection
+                    .find(filter)
+                    .sort(doc! { "next_run": 1 })
+                    .await?
+                    .try_collect()
+                    .await?
+            }
         };
-        // Update the status of the jobs to 1 (running)
-        let _ = collection.update_many(filter, update).await?;
+        // Jobs limited to platforms or nodes, or in a namespace, wait for a connected agent they can
+        // run on, and jobs run by central command's executors for the executor.
+        let mut runnable = Vec::with_capacity(due.len());
+        for job in due {
+            // Jobs that require approval wait for an operator instead of starting their cycle.
+            if job.requires_approval && job.approval.is_none() {
+                let held = collection
+                    .update_one(
+                        doc! { "_id": job.id, "status": Status::Pending, "approval": null },
+                        doc! { "$set": { "status": Status::PendingApproval } },
+                    )
+                    .await?;
+                if held.modified_count == 1 {
+                    info!("Job {} is waiting for approval", job.name);
+                }
+                continue;
+            }
+            if !job.platforms.is_empty()
+                || !job.node_selector.is_empty()
+                || job.namespace.is_some()
+                || job.executor_agent().is_some()
+            {
+                let candidates =
+                    Self::cycle_candidates(&datastore, agent_cache, &job, &connected_agents)
+                        .await?;
+                if !candidates
+                    .iter()
+                    .any(|agent| connected_agents.contains(agent))
+                {
+                    continue;
+                }
+            }
+            runnable.push(job);
+        }
+        let selected: Vec<_> = scheduler
+            .select_jobs(runnable, &connected_agents)
+            .into_iter()
+            .map(|job| job.id)
+            .collect();
+        for id in &selected {
+            // Update the status of the job to 1 (running), unless it changed since it was read. The
+            // cycle uses up the approval of jobs that require one.
+            collection
+                .update_one(
+                    doc! { "_id": id, "status": Status::Pending },
+                    doc! {
+                        "$set": { "status": Status::Running },
+                        "$unset": { "approval": "" },
+                    },
+                )
+                .await?;
+        }
         // Now fetch the jobs that are ready to run
-        let post_filter = doc! {
-            "$and": [
-                { "status": Status::Running  }, // Jobs with status equal to 1
-                { "agents_running": [] }
-            ]
+        let post_filter = match partitioned {
+            false => doc! {
+                "status": Status::Running, // Jobs with status equal to 1
+                "$or": [
+                    { "agents_running": [] },
+                    { "agents_pending": { "$in": reached } },
+                ]
+            },
+            // Cycles still to start, and those pending on agents this instance reaches.
+            true => doc! {
+                "status": Status::Running,
+                "$or": [
+                    { "agents_running": [], "cycle_id": null },
+                    { "agents_pending": { "$in": reached } },
+                ]
+            },
         };
         // Fetch the jobs that are now running without agents
         let mut cursor = collection.find(post_filter).await?;
         let mut jobs = vec![];
-        while let Some(job) = cursor.try_next().await? {
+        while let Some(mut job) = cursor.try_next().await? {
+            if job.cycle_id.is_none() {
+                let cycle_id = Uuid::new_v4().to_string();
+                // Group members are resolved now, so membership changes apply from the next cycle.
+                let candidates =
+                    Self::cycle_candidates(&datastore, agent_cache, &job, &connected_agents)
+                        .await?;
+                // A re-run repeats a run on the agent that ran it.
+                let cycle_agents = match &job.rerun {
+                    Some(rerun) => vec![rerun.agent_name.clone()],
+                    None => scheduler.assign_agents(&job, candidates),
+                };
+                let set = doc! {
+                    "cycle_id": &cycle_id,
+                    "cycle_agents": &cycle_agents,
+                    "agents_pending": &cycle_agents,
+                };
+                let assigned = collection
+                    .update_one(
+                        doc! { "_id": job.id, "cycle_id": null },
+                        doc! { "$set": set },
+                    )
+                    .await?;
+                if assigned.modified_count == 1 {
+                    job.cycle_id = Some(cycle_id);
+                    job.agents_pending = cycle_agents.clone();
+                    job.cycle_agents = cycle_agents;
+                } else {
+                    // Another instance started the cycle in the meantime.
+                    match collection.find_one(doc! { "_id": job.id }).await? {
+                        Some(started) => job = started,
+                        None => continue,
+                    }
+                }
+            }
+            if partitioned
+                && !job
+                    .agents_pending
+                    .iter()
+                    .any(|agent| reached.contains(agent))
+            {
+                continue;
+            }
             jobs.push(job);
         }
+        // Jobs left running without agents by an earlier check come first.
+        jobs.sort_by_key(|job| selected.iter().position(|id| *id == job.id));
         Ok(jobs)
     }
 
+    /// The agents a new cycle of `job` can run on: its `agents_required` and the connected members
+    /// of its `agent_groups`, leaving out agents outside the job's namespace, agents on platforms
+    /// outside the job's `platforms` and agents on nodes not matching its `node_selector`. A job
+    /// run by one of central command's executors only runs on the executor.
+    pub(crate) async fn cycle_candidates(
+        datastore: &Datastore,
+        agent_cache: &AgentCache,
+        job: &JobV1,
+        connected_agents: &[String],
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        if let Some(agent) = job.executor_agent() {
+            return Ok(vec![agent.to_string()]);
+        }
+        let mut candidates = job.agents_required.clone();
+        for member in AgentGroupV1::members_of(datastore, &job.agent_groups).await? {
+            if connected_agents.contains(&member) && !candidates.contains(&member) {
+                candidates.push(member);
+            }
+        }
+        let agents = agent_cache.get_many(&candidates).await;
+        candidates.retain(|name| agents.get(name).is_some_and(|agent| job.runs_on(agent)));
+        Ok(candidates)
+    }
+
+    /// Applies each pending job's misfire policy to the scheduled runs it missed by more than
+    /// `MISFIRE_GRACE_SECONDS`, e.g. while central command was down. Runs that will not be run are
+    /// recorded as `Skipped` runs on each of the job's agents.
+    pub async fn apply_misfire_policies(
+        datastore: Arc<Datastore>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let now = DateTime::now().timestamp_millis() / 1000;
+        let collection = datastore.get_collection::<JobV1>("jobs").await?;
+        let filter = doc! {
+            "status": Status::Pending,
+            "next_run": { "$lt": next_run_datetime(now - get_misfire_grace_seconds()) },
+            "agents_running": [],
+        };
+        let mut cursor = collection.find(filter).await?;
+        let mut jobs = vec![];
+        while let Some(job) = cursor.try_next().await? {
+            jobs.push(job);
+        }
+
+        for job in jobs {
+            let due = job.due_runs(now);
+            let last_due = job.nth_run(due.saturating_sub(1));
+            let (skipped, update) = match job.misfire_policy {
+                MisfirePolicy::RunAll => continue,
+                // Runs the latest missed run; the earlier ones are skipped.
+                MisfirePolicy::RunOnce if due > 1 => (
+                    due - 1,
+                    doc! { "$set": { "next_run": next_run_datetime(last_due) } },
+                ),
+                MisfirePolicy::RunOnce => continue,
+                MisfirePolicy::Skip => match job.next_run_after(now) {
+                    Some(next_run) => (
+                        due,
+                        doc! { "$set": { "next_run": next_run_datetime(next_run) } },
+                    ),
+                    None => {
+                        let status = match job.one_shot {
+                            true => Status::Archived,
+                            false => Status::Completed,
+                        };
+                        (due, doc! { "$set": { "status": status } })
+                    }
+                },
+            };
+            let filter = doc! { "_id": job.id, "status": Status::Pending, "next_run": next_run_datetime(job.next_run) };
+            if collection.update_one(filter, update).await?.modified_count == 0 {
+                continue; // Dispatched or changed in the meantime
+            }
+            warn!(
+                "Job {} missed {} scheduled run(s); skipping {} ({} misfire policy)",
+                job.name, due, skipped, job.misfire_policy
+            );
+            let last_skipped = job.nth_run(skipped.saturating_sub(1));
+            let mut agents = job.agents_required.clone();
+            for member in AgentGroupV1::members_of(&datastore, &job.agent_groups).await? {
+                if !agents.contains(&member) {
+                    agents.push(member);
+                }
+            }
+            for agent_name in &agents {
+                let run =
+                    RunsV1::misfire_skipped(&job, agent_name, skipped, job.next_run, last_skipped);
+                run.insert_entry(&datastore.get_database()).await?;
+            }
+        }
+        Ok(())
+    }
+
     /// Add an agent to the running job
     /// This function updates the job in the database to include the agent in the `agents_running` list
     /// It checks if the agent is already in the list to avoid duplicates.
@@ -347,28 +1782,101 @@ impl AgentManager {
         agent_name: &str,
     ) -> Result<(), Box<dyn std::error::Error>> {
         if !job.agents_running.contains(&agent_name.to_string()) {
-            let mut agents_running = job.agents_running.clone();
-            agents_running.push(agent_name.to_string());
             let collection = datastore.get_collection::<JobV1>("jobs").await?;
             let filter = doc! { "_id": job.id };
-            let update = doc! { "$set": { "agents_running": agents_running } };
+            // Added in place, as other instances may dispatch the same cycle to their agents.
+            let update = doc! { "$addToSet": { "agents_running": agent_name } };
             collection.update_one(filter, update).await?;
         }
         Ok(())
     }
 
     /// Check if connected agents are still reachable
+    /// Waits until this instance leads, see `leader`.
+    async fn wait_for_leadership(leadership: &Leadership) {
+        const LEADERSHIP_CHECK_INTERVAL_SECONDS: u64 = 1;
+
+        while !leadership.is_leader() {
+            sleep(Duration::from_secs(LEADERSHIP_CHECK_INTERVAL_SECONDS)).await;
+        }
+    }
+
     pub async fn start(self) {
         const AGENT_PING_KEEP_ALIVE: u64 = 5; // Interval to ping agents
         const UNCONNECT_CHECK_INTERVAL_SECONDS: u64 = 5; // Interval to check for unconnected agents
         const JOB_DISPATCH_INTERVAL_SECONDS: u64 = 1; // Interval to check for jobs to dispatch
+        const AGENT_UPDATE_CHECK_INTERVAL_SECONDS: u64 = 10; // Interval to check for agent updates
+        const PING_REQUEST_CHECK_INTERVAL_SECONDS: u64 = 1; // Interval to check for requested pings
+        const CANCEL_REQUEST_CHECK_INTERVAL_SECONDS: u64 = 1; // Interval to check for requested cancellations
+        const STALE_AGENT_CHECK_INTERVAL_SECONDS: u64 = 5; // Interval to check for silent agents
+        const SLA_CHECK_INTERVAL_SECONDS: u64 = 5; // Interval to check jobs against their SLA
+
+        let datastore = self.datastore.clone();
+
+        // Agents are dialed and dispatched to from the cache, so it is loaded before anything else.
+        while let Err(e) = self
+            .agent_cache
+            .refresh(&datastore)
+            .await
+            .map_err(|e| e.to_string())
+        {
+            error!("Error loading agents: {}", e);
+            sleep(Duration::from_secs(UNCONNECT_CHECK_INTERVAL_SECONDS)).await;
+        }
+        spawn(self.agent_cache.clone().start(datastore.clone()));
 
+        // With partitioned agents every instance reaches its own, whether or not it leads.
+        let leadership = match self.partitions.is_enabled() {
+            true => Leadership::always(),
+            false => self.leadership.clone(),
+        };
+        let leader_only = self.leadership.clone();
+        // Dry runs leave jobs as they are and send agents nothing but pings.
+        let dry_run = self.planner.is_some();
         let manager = Arc::new(Mutex::new(self)); // Ownership of `self` is moved here
 
+        // Records runs and schedules that breached their job's SLA, without holding up the manager
+        let datastore_clone = datastore.clone();
+        let leadership_clone = leader_only.clone();
+        if !dry_run {
+            spawn(async move {
+                loop {
+                    AgentManager::wait_for_leadership(&leadership_clone).await;
+                    if let Err(e) = AgentManager::record_sla_breaches(&datastore_clone)
+                        .await
+                        .map_err(|e| e.to_string())
+                    {
+                        error!("Error checking runs against their SLA: {}", e);
+                    }
+                    if let Err(e) = AgentManager::record_overdue_jobs(&datastore_clone)
+                        .await
+                        .map_err(|e| e.to_string())
+                    {
+                        error!("Error checking for overdue jobs: {}", e);
+                    }
+                    sleep(Duration::from_secs(SLA_CHECK_INTERVAL_SECONDS)).await;
+                }
+            });
+        }
+
+        // Marks agents offline that stopped answering, without holding up the manager
+        let leadership_clone = leader_only;
+        spawn(async move {
+            loop {
+                AgentManager::wait_for_leadership(&leadership_clone).await;
+                if let Err(e) = AgentManager::mark_stale_agents(&datastore).await {
+                    error!("Error checking for stale agents: {}", e);
+                }
+                sleep(Duration::from_secs(STALE_AGENT_CHECK_INTERVAL_SECONDS)).await;
+            }
+        });
+
         // Pings Agents
         let manager_clone = manager.clone();
+        let leadership_clone = leadership.clone();
         spawn(async move {
             loop {
+                AgentManager::wait_for_leadership(&leadership_clone).await;
                 let mut manager_lock = manager_clone.lock().await;
                 manager_lock.ping_existing_agents().await;
                 drop(manager_lock); // Explicitly drop the lock to avoid holding it while sleeping
@@ -378,8 +1886,10 @@ impl AgentManager {
 
         // Spawn a task to periodically check for unconnected agents
         let manager_clone = manager.clone();
+        let leadership_clone = leadership.clone();
         spawn(async move {
             loop {
+                AgentManager::wait_for_leadership(&leadership_clone).await;
                 let mut manager_lock = manager_clone.lock().await;
                 manager_lock.check_for_unconnected_agents().await;
                 drop(manager_lock); // Explicitly drop the lock to avoid holding it while sleeping
@@ -387,30 +1897,123 @@ impl AgentManager {
             }
         });
 
+        // Spawn a task to periodically push requested updates to agents
+        let manager_clone = manager.clone();
+        let leadership_clone = leadership.clone();
+        if !dry_run {
+            spawn(async move {
+                loop {
+                    AgentManager::wait_for_leadership(&leadership_clone).await;
+                    let mut manager_lock = manager_clone.lock().await;
+                    if let Err(e) = manager_lock.push_pending_updates().await {
+                        error!("Error pushing agent updates: {}", e);
+                    }
+                    drop(manager_lock); // Explicitly drop the lock to avoid holding it while sleeping
+                    sleep(Duration::from_secs(AGENT_UPDATE_CHECK_INTERVAL_SECONDS)).await;
+                }
+            });
+        }
+
+        // Spawn a task to periodically answer pings requested by operators
+        let manager_clone = manager.clone();
+        let leadership_clone = leadership.clone();
+        spawn(async move {
+            loop {
+                AgentManager::wait_for_leadership(&leadership_clone).await;
+                let mut manager_lock = manager_clone.lock().await;
+                if let Err(e) = manager_lock.answer_ping_requests().await {
+                    error!("Error answering ping requests: {}", e);
+                }
+                drop(manager_lock); // Explicitly drop the lock to avoid holding it while sleeping
+                sleep(Duration::from_secs(PING_REQUEST_CHECK_INTERVAL_SECONDS)).await;
+            }
+        });
+
+        // Spawn a task to periodically send cancellations and timeout extensions requested by
+        // operators
+        let manager_clone = manager.clone();
+        let leadership_clone = leadership.clone();
+        if !dry_run {
+            spawn(async move {
+                loop {
+                    AgentManager::wait_for_leadership(&leadership_clone).await;
+                    let mut manager_lock = manager_clone.lock().await;
+                    if let Err(e) = manager_lock.send_cancel_requests().await {
+                        error!("Error sending cancel requests: {}", e);
+                    }
+                    if let Err(e) = manager_lock.send_timeout_extensions().await {
+                        error!("Error sending timeout extensions: {}", e);
+                    }
+                    drop(manager_lock); // Explicitly drop the lock to avoid holding it while sleeping
+                    sleep(Duration::from_secs(CANCEL_REQUEST_CHECK_INTERVAL_SECONDS)).await;
+                }
+            });
+        }
+
         // Spawn a task to periodically check for jobs to dispatch
         let manager_clone = manager.clone();
+        let leadership_clone = leadership.clone();
         spawn(async move {
             loop {
+                AgentManager::wait_for_leadership(&leadership_clone).await;
                 let mut manager_lock = manager_clone.lock().await;
                 debug!("Checking for jobs to dispatch...");
-                let connected_agents = manager_lock
-                    .connected_agents
-                    .keys()
-                    .map(|a| a.name.clone())
-                    .collect::<Vec<_>>();
                 let data_store = manager_lock.datastore.clone();
-                let jobs_to_run =
-                    match AgentManager::get_jobs_to_run(data_store, connected_agents).await {
-                        Ok(jobs) => jobs,
-                        Err(e) => {
-                            error!("Error fetching jobs: {}", e);
-                            continue; // Skip this iteration on error
-                        }
-                    };
+                let draining = manager_lock.agent_cache.draining().await;
+                let reached = manager_lock.reached_agents().await;
+                let partitioned = manager_lock.partitions.is_enabled();
+                let mut connected_agents = reached.clone();
+                if partitioned {
+                    // Agents other instances reach are offered too, so cycles span partitions.
+                    let names = manager_lock.agent_cache.connected().await;
+                    connected_agents
+                        .extend(names.into_iter().filter(|name| !reached.contains(name)));
+                }
+                // Agents that pull their jobs count as connected, so their cycles start.
+                for agent_name in manager_lock.agent_channels.names().await {
+                    if !connected_agents.contains(&agent_name) {
+                        connected_agents.push(agent_name);
+                    }
+                }
+                connected_agents.retain(|agent_name| !draining.contains(agent_name));
+                if let Some(planner) = manager_lock.planner.as_mut() {
+                    if let Err(e) = planner
+                        .plan(&connected_agents)
+                        .await
+                        .map_err(|e| e.to_string())
+                    {
+                        error!("Error planning dispatches: {}", e);
+                    }
+                    drop(manager_lock);
+                    sleep(Duration::from_secs(JOB_DISPATCH_INTERVAL_SECONDS)).await;
+                    continue;
+                }
+                if let Err(e) = AgentManager::apply_misfire_policies(data_store.clone())
+                    .await
+                    .map_err(|e| e.to_string())
+                {
+                    error!("Error applying misfire policies: {}", e);
+                }
+                let jobs_to_run = match AgentManager::get_jobs_to_run(
+                    data_store,
+                    &manager_lock.agent_cache,
+                    connected_agents,
+                    manager_lock.scheduler.as_ref(),
+                    &reached,
+                    partitioned,
+                )
+                .await
+                {
+                    Ok(jobs) => jobs,
+                    Err(e) => {
+                        error!("Error fetching jobs: {}", e);
+                        continue; // Skip this iteration on error
+                    }
+                };
                 for job in jobs_to_run.iter() {
                     info!("Running job: {:?}", job);
-                    let _ = manager_lock.run_job(job).await;
                 }
+                manager_lock.run_jobs(&jobs_to_run, &draining).await;
                 drop(manager_lock); // Explicitly drop the lock to avoid holding it while sleeping
                 sleep(Duration::from_secs(JOB_DISPATCH_INTERVAL_SECONDS)).await;
             }
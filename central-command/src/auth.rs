@@ -0,0 +1,312 @@
+/// Pluggable authentication of agents connecting to central command.
+///
+/// # Overview
+/// - `AGENT_AUTH` selects the backend: `none` (default, agents are trusted), `token` or `spiffe`.
+/// - When a backend is configured, every agent connection must start with an `Authenticate`
+///   message. The connection is closed if the credential is rejected, if the identity it proves
+///   does not belong to the agent, or if it later sends messages on behalf of another agent.
+/// - gRPC agents send their credential with every call, as `authorization: Bearer <credential>`
+///   metadata, for the agent the call names.
+/// - Message bus agents publish an `Authenticate` message before their other messages. Messages
+///   from agents that have not authenticated, or naming another agent than their subject, are
+///   dropped.
+/// - The identity an agent must prove is `AgentV1.identity` when set, otherwise the backend's
+///   `default_identity` for the agent name.
+/// - A credential may belong to a namespace (see `AgentV1.namespace`). Agents registering with it
+///   join that namespace, and known agents are rejected when it is not the namespace they are in,
+///   so an agent of one environment cannot connect with the credential of another.
+///
+/// # Backends
+/// - `token`: Agents present the shared secret in `AGENT_AUTH_TOKEN`, for the default namespace, or
+///   one of the namespace secrets in `AGENT_AUTH_NAMESPACE_TOKENS` (`namespace=token;...`), e.g.
+///   `staging=s3cret;prod=t0ps3cret`, for that namespace.
+/// - `spiffe`: Agents present a JWT-SVID. It is verified against the JWT bundle (a JWKS document,
+///   as written by the SPIFFE helper or Workload API) in `SPIFFE_JWT_BUNDLE_FILE`, which is re-read
+///   for every connection so bundle rotations apply without a restart. The audience must be
+///   `SPIFFE_AUDIENCE` (default: `rust-action-dispatch`) and the SPIFFE ID must belong to
+///   `SPIFFE_TRUST_DOMAIN`. An agent's default SPIFFE ID is `spiffe://<trust domain>/agent/<name>`.
+///
+/// # Notes
+/// - The bus does not tell who published a message, so on a shared NATS server publishing to an
+///   agent's subject (see `core_logic::bus::central_subject`) should be limited to that agent.
+use bson::doc;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use core_logic::datastore::Datastore;
+use core_logic::datastore::agents::AgentV1;
+use core_logic::messages::{Credential, Message};
+
+const DEFAULT_SPIFFE_AUDIENCE: &str = "rust-action-dispatch";
+/// How the default namespace is named in messages.
+const DEFAULT_NAMESPACE: &str = "default";
+
+/// Verifies agent credentials.
+pub trait AgentAuthenticator: Send + Sync {
+    /// Name of the backend, for logging.
+    fn name(&self) -> &'static str;
+
+    /// Verifies `credential`, returning the identity it proves.
+    fn authenticate(&self, credential: &Credential) -> Result<String, Box<dyn Error>>;
+
+    /// Identity expected of `agent_name` when its record does not name one.
+    fn default_identity(&self, agent_name: &str) -> String;
+
+    /// Namespace the verified `credential` belongs to, or `None` for the default namespace.
+    fn namespace(&self, _credential: &Credential) -> Option<String> {
+        None
+    }
+
+    /// The credential sent as a bearer token, e.g. in gRPC metadata.
+    fn bearer_credential(&self, bearer: String) -> Credential {
+        Credential::Token(bearer)
+    }
+}
+
+/// The agent `message` speaks for, if it names one.
+pub fn claimed_agent(message: &Message) -> Option<&str> {
+    match message {
+        Message::Authenticate(request) => Some(&request.agent_name),
+        Message::RegisterAgent(register_agent) => Some(&register_agent.name),
+        Message::JobComplete(job_complete) => Some(&job_complete.agent_name),
+        Message::JobProgress(job_progress) => Some(&job_progress.agent_name),
+        Message::ReverseDispatch(request) => Some(&request.agent_name),
+        Message::PollWork(poll) => Some(&poll.agent_name),
+        _ => None,
+    }
+}
+
+/// Verifies `credential` and checks that it belongs to `agent_name`, see `authorize`. Returns the
+/// namespace of the credential.
+pub async fn verify(
+    authenticator: &dyn AgentAuthenticator,
+    datastore: &Datastore,
+    agent_name: &str,
+    credential: &Credential,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let identity = authenticator.authenticate(credential).map_err(|e| {
+        format!(
+            "failed {} authentication as {}: {}",
+            authenticator.name(),
+            agent_name,
+            e
+        )
+    })?;
+    let namespace = authenticator.namespace(credential);
+    authorize(
+        authenticator,
+        datastore,
+        agent_name,
+        &identity,
+        namespace.as_deref(),
+    )
+    .await
+    .map_err(|e| format!("is not authorized: {}", e))?;
+    Ok(namespace)
+}
+
+/// The backend selected by `AGENT_AUTH`, or `None` when agents are not authenticated.
+pub fn authenticator_from_env() -> Option<Arc<dyn AgentAuthenticator>> {
+    let backend = env::var("AGENT_AUTH").unwrap_or_default().to_lowercase();
+    match backend.as_str() {
+        "" | "none" => None,
+        "token" => {
+            let token = env::var("AGENT_AUTH_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty());
+            let namespace_tokens = parse_namespace_tokens(
+                &env::var("AGENT_AUTH_NAMESPACE_TOKENS").unwrap_or_default(),
+            );
+            if token.is_none() && namespace_tokens.is_empty() {
+                panic!(
+                    "AGENT_AUTH_TOKEN or AGENT_AUTH_NAMESPACE_TOKENS is required when AGENT_AUTH=token"
+                );
+            }
+            Some(Arc::new(TokenAuthenticator {
+                token,
+                namespace_tokens,
+            }))
+        }
+        "spiffe" => Some(Arc::new(SpiffeAuthenticator {
+            trust_domain: env::var("SPIFFE_TRUST_DOMAIN")
+                .expect("SPIFFE_TRUST_DOMAIN is required when AGENT_AUTH=spiffe"),
+            bundle_path: env::var("SPIFFE_JWT_BUNDLE_FILE")
+                .map(PathBuf::from)
+                .expect("SPIFFE_JWT_BUNDLE_FILE is required when AGENT_AUTH=spiffe"),
+            audience: env::var("SPIFFE_AUDIENCE")
+                .unwrap_or_else(|_| DEFAULT_SPIFFE_AUDIENCE.to_string()),
+        })),
+        other => panic!("Invalid AGENT_AUTH {}", other),
+    }
+}
+
+/// Parses `namespace=token;...` into namespaces and their tokens, skipping malformed entries.
+fn parse_namespace_tokens(value: &str) -> Vec<(String, String)> {
+    value
+        .split(';')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(namespace, token)| (namespace.trim().to_string(), token.trim().to_string()))
+        .filter(|(namespace, token)| !namespace.is_empty() && !token.is_empty())
+        .collect()
+}
+
+/// Checks that `identity` belongs to `agent_name`, and that a known agent is in `namespace`, the
+/// namespace of its credential.
+pub async fn authorize(
+    authenticator: &dyn AgentAuthenticator,
+    datastore: &Datastore,
+    agent_name: &str,
+    identity: &str,
+    namespace: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let collection = datastore.get_collection::<AgentV1>("agents").await?;
+    let agent = collection.find_one(doc! { "name": agent_name }).await?;
+    if let Some(agent) = &agent
+        && agent.namespace.as_deref() != namespace
+    {
+        return Err(format!(
+            "Agent {} is in namespace {} but authenticated for namespace {}",
+            agent_name,
+            agent.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE),
+            namespace.unwrap_or(DEFAULT_NAMESPACE)
+        )
+        .into());
+    }
+    let expected = agent
+        .and_then(|agent| agent.identity)
+        .unwrap_or_else(|| authenticator.default_identity(agent_name));
+    if identity != expected {
+        return Err(format!(
+            "Identity {} does not belong to agent {} (expected {})",
+            identity, agent_name, expected
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Compares without returning early, so the time taken does not reveal matching prefixes.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub struct TokenAuthenticator {
+    /// Token of the default namespace.
+    token: Option<String>,
+    /// Namespaces and their tokens.
+    namespace_tokens: Vec<(String, String)>,
+}
+
+impl TokenAuthenticator {
+    /// Namespace of `token`, `Some(None)` for the default namespace, or `None` if it is unknown.
+    /// Every token is compared, so the time taken does not reveal which one matched.
+    fn token_namespace(&self, token: &str) -> Option<Option<String>> {
+        let mut found = None;
+        if let Some(default) = &self.token
+            && constant_time_eq(token.as_bytes(), default.as_bytes())
+        {
+            found = Some(None);
+        }
+        for (namespace, namespace_token) in &self.namespace_tokens {
+            if constant_time_eq(token.as_bytes(), namespace_token.as_bytes()) && found.is_none() {
+                found = Some(Some(namespace.clone()));
+            }
+        }
+        found
+    }
+}
+
+impl AgentAuthenticator for TokenAuthenticator {
+    fn name(&self) -> &'static str {
+        "token"
+    }
+
+    fn authenticate(&self, credential: &Credential) -> Result<String, Box<dyn Error>> {
+        match credential {
+            Credential::Token(token) if self.token_namespace(token).is_some() => {
+                Ok("token".to_string())
+            }
+            Credential::Token(_) => Err("Invalid token".into()),
+            _ => Err("Expected a token".into()),
+        }
+    }
+
+    fn default_identity(&self, _agent_name: &str) -> String {
+        "token".to_string()
+    }
+
+    fn namespace(&self, credential: &Credential) -> Option<String> {
+        match credential {
+            Credential::Token(token) => self.token_namespace(token).flatten(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SvidClaims {
+    sub: String,
+}
+
+pub struct SpiffeAuthenticator {
+    trust_domain: String,
+    bundle_path: PathBuf,
+    audience: String,
+}
+
+impl AgentAuthenticator for SpiffeAuthenticator {
+    fn name(&self) -> &'static str {
+        "spiffe"
+    }
+
+    fn authenticate(&self, credential: &Credential) -> Result<String, Box<dyn Error>> {
+        let Credential::JwtSvid(svid) = credential else {
+            return Err("Expected a JWT-SVID".into());
+        };
+
+        let header = jsonwebtoken::decode_header(svid)?;
+        // JWT-SVIDs are signed with the trust domain's keys; shared secret algorithms are refused.
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(format!("Algorithm {:?} is not allowed for JWT-SVIDs", header.alg).into());
+        }
+        let kid = header.kid.ok_or("JWT-SVID has no key id")?;
+
+        let bundle: JwkSet = serde_json::from_str(&std::fs::read_to_string(&self.bundle_path)?)?;
+        let jwk = bundle
+            .find(&kid)
+            .ok_or_else(|| format!("Key {} is not in the trust bundle", kid))?;
+        let key = DecodingKey::from_jwk(jwk)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<SvidClaims>(svid, &key, &validation)?.claims;
+
+        let prefix = format!("spiffe://{}/", self.trust_domain);
+        if !claims.sub.starts_with(&prefix) {
+            return Err(format!(
+                "SPIFFE ID {} is not in trust domain {}",
+                claims.sub, self.trust_domain
+            )
+            .into());
+        }
+        Ok(claims.sub)
+    }
+
+    fn default_identity(&self, agent_name: &str) -> String {
+        format!("spiffe://{}/agent/{}", self.trust_domain, agent_name)
+    }
+
+    fn bearer_credential(&self, bearer: String) -> Credential {
+        Credential::JwtSvid(bearer)
+    }
+}
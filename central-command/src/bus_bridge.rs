@@ -0,0 +1,239 @@
+/// The `BusBridge` connects central command to agents that communicate over a message bus
+/// (see `core_logic::bus`) instead of accepting connections from central command.
+///
+/// # Overview
+/// - Subscribes to messages published by every agent and handles them exactly like messages
+///   received by the `CommandReceiver`.
+/// - The first message from an agent registers a channel in `AgentChannels`; messages pushed into
+///   it by the `AgentManager` are published to the agent's subject.
+/// - Agents publish a `Ping` heartbeat. An agent that has not been heard from within
+///   `BUS_AGENT_TIMEOUT_SECS` has its channel pruned and is marked offline.
+/// - Every instance subscribes, but only the leader handles messages and marks agents offline
+///   (see `leader`), so a message is not handled once per instance.
+/// - When agents are authenticated (`AGENT_AUTH`, see `auth`), an agent's messages are only
+///   handled after an `Authenticate` message on its subject was accepted, and messages naming
+///   another agent than the subject's are dropped. Agents authenticate before each message they
+///   publish, so a newly elected leader does not have to wait for them.
+///
+/// # Configuration
+/// The bridge is started when `MESSAGE_BUS_URL` is set, e.g. `MESSAGE_BUS_URL=nats://127.0.0.1:4222`.
+use futures::StreamExt;
+use tokio::spawn;
+use tokio::sync::{Mutex, mpsc};
+use tokio::time::{Duration, Instant, interval};
+use tracing::{debug, error, info, warn};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::agent_channels::AgentChannels;
+use crate::agent_manager::AgentManager;
+use crate::auth::{self, AgentAuthenticator};
+use crate::command_receiver::CommandReceiver;
+use crate::leader::Leadership;
+use core_logic::bus::{self, MessageBus};
+use core_logic::datastore::Datastore;
+use core_logic::messages::Message;
+use core_logic::priority::PriorityReceiver;
+
+const BUS_AGENT_TIMEOUT_SECS: u64 = 30;
+const BUS_CHANNEL_CAPACITY: usize = 100;
+
+pub struct BusBridge {
+    bus: Arc<dyn MessageBus>,
+    datastore: Arc<Datastore>,
+    agent_channels: AgentChannels,
+    last_seen: Arc<Mutex<HashMap<String, Instant>>>,
+    leadership: Leadership,
+    authenticator: Option<Arc<dyn AgentAuthenticator>>,
+}
+
+impl BusBridge {
+    pub async fn try_new(
+        url: &str,
+        datastore: Arc<Datastore>,
+        agent_channels: AgentChannels,
+        leadership: Leadership,
+        authenticator: Option<Arc<dyn AgentAuthenticator>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let bus = bus::connect(url).await?;
+        info!("Connected to message bus at {}", url);
+        Ok(Self {
+            bus,
+            datastore,
+            agent_channels,
+            last_seen: Arc::new(Mutex::new(HashMap::new())),
+            leadership,
+            authenticator,
+        })
+    }
+
+    /// Handles messages from agents until the subscription ends.
+    pub async fn run(self) -> Result<(), Box<dyn Error>> {
+        let mut messages = self.bus.subscribe(bus::central_wildcard_subject()).await?;
+        // Bus agents have no socket address of their own.
+        let peer_addr = SocketAddr::from(([0, 0, 0, 0], 0));
+        // Agents whose last `Authenticate` was accepted, with the namespace of their credential.
+        let mut authenticated: HashMap<String, Option<String>> = HashMap::new();
+
+        while let Some(bus_message) = messages.next().await {
+            if !self.leadership.is_leader() {
+                continue;
+            }
+            let Some(agent_name) = bus::agent_name_from_central_subject(&bus_message.subject)
+            else {
+                warn!("Ignoring bus message on {}", bus_message.subject);
+                continue;
+            };
+            debug!(
+                "Received {:?} from {} over bus",
+                bus_message.message, agent_name
+            );
+            if let Some(claimed) = auth::claimed_agent(&bus_message.message)
+                && claimed != agent_name
+            {
+                warn!(
+                    "Ignoring {} for {} published on the subject of {}",
+                    bus_message.message.kind(),
+                    claimed,
+                    agent_name
+                );
+                continue;
+            }
+
+            let namespace = match (&self.authenticator, &bus_message.message) {
+                (None, _) => None,
+                (Some(authenticator), Message::Authenticate(request)) => {
+                    match auth::verify(
+                        authenticator.as_ref(),
+                        &self.datastore,
+                        agent_name,
+                        &request.credential,
+                    )
+                    .await
+                    {
+                        Ok(namespace) => {
+                            authenticated.insert(agent_name.to_string(), namespace);
+                        }
+                        Err(e) => {
+                            warn!("Bus agent {} {}", agent_name, e);
+                            authenticated.remove(agent_name);
+                        }
+                    }
+                    continue;
+                }
+                (Some(_), message) => match authenticated.get(agent_name) {
+                    Some(namespace) => namespace.clone(),
+                    None => {
+                        warn!(
+                            "Ignoring {} from bus agent {}, which has not authenticated",
+                            message.kind(),
+                            agent_name
+                        );
+                        continue;
+                    }
+                },
+            };
+
+            self.touch(agent_name).await;
+            if let Err(e) = CommandReceiver::handle_message(
+                bus_message.message,
+                self.datastore.clone(),
+                peer_addr,
+                namespace.as_deref(),
+            )
+            .await
+            {
+                error!("Error processing bus message from {}: {}", agent_name, e);
+            }
+        }
+
+        warn!("Message bus subscription closed");
+        Ok(())
+    }
+
+    /// Records that `agent_name` is alive, opening its channel if it is not already reachable.
+    async fn touch(&self, agent_name: &str) {
+        self.last_seen
+            .lock()
+            .await
+            .insert(agent_name.to_string(), Instant::now());
+
+        if let Err(e) = AgentManager::update_agent_online(self.datastore.clone(), agent_name).await
+        {
+            error!("Failed to update agent {} to online: {}", agent_name, e);
+        }
+
+        if self.agent_channels.contains(agent_name).await {
+            return;
+        }
+
+        info!("Agent {} is reachable over the message bus", agent_name);
+        let (sender, receiver) = mpsc::channel(BUS_CHANNEL_CAPACITY);
+        self.agent_channels.register(agent_name, sender).await;
+        spawn(Self::forward(
+            agent_name.to_string(),
+            receiver,
+            self.bus.clone(),
+            self.datastore.clone(),
+            self.agent_channels.clone(),
+            self.last_seen.clone(),
+            self.leadership.clone(),
+        ));
+    }
+
+    /// Publishes messages pushed into an agent's channel until the agent stops sending heartbeats.
+    /// Control messages such as `CancelJob` are published ahead of queued dispatches.
+    async fn forward(
+        agent_name: String,
+        receiver: mpsc::Receiver<Message>,
+        bus: Arc<dyn MessageBus>,
+        datastore: Arc<Datastore>,
+        agent_channels: AgentChannels,
+        last_seen: Arc<Mutex<HashMap<String, Instant>>>,
+        leadership: Leadership,
+    ) {
+        let timeout = Duration::from_secs(BUS_AGENT_TIMEOUT_SECS);
+        let mut check_interval = interval(timeout / 3);
+        let subject = bus::agent_subject(&agent_name);
+        let mut receiver = PriorityReceiver::new(receiver);
+
+        loop {
+            tokio::select! {
+                message = receiver.recv() => match message {
+                    Some(message) => {
+                        if let Err(e) = bus.publish(subject.clone(), message).await {
+                            error!("Failed to publish to agent {}: {}", agent_name, e);
+                        }
+                    }
+                    None => break,
+                },
+                _ = check_interval.tick() => {
+                    let expired = last_seen
+                        .lock()
+                        .await
+                        .get(&agent_name)
+                        .is_none_or(|seen| seen.elapsed() > timeout);
+                    if expired {
+                        break;
+                    }
+                }
+            }
+        }
+
+        info!(
+            "Agent {} stopped responding over the message bus",
+            agent_name
+        );
+        last_seen.lock().await.remove(&agent_name);
+        drop(receiver); // Close the channel so it is pruned
+        agent_channels.prune(&agent_name).await;
+        if leadership.is_leader()
+            && let Err(e) = AgentManager::update_agent_offline(datastore, &agent_name).await
+        {
+            error!("Failed to update agent {} to offline: {}", agent_name, e);
+        }
+    }
+}
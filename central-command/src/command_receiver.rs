@@ -2,23 +2,61 @@
 /// and process messages for agent registration and job completion in a distributed system.
 ///
 /// # Overview
-/// - Listens for incoming TCP connections from agents.
+/// - Listens for incoming TCP connections from agents on every address in `LISTEN_ADDRESSES`.
 /// - Processes messages such as agent registration, job completion, and pings.
 /// - Interacts with a MongoDB datastore to register agents and update job statuses.
 ///
 /// # Main Responsibilities
 /// - Accept new agent connections and spawn tasks to handle each connection.
-/// - Register agents in the database upon receiving a `RegisterAgent` message.
+/// - Register agents in the database upon receiving a `RegisterAgent` message, reloading them in
+///   the `AgentCache`.
 /// - Mark jobs as complete for agents and update job status when all agents have completed.
-/// - Respond to agents with acknowledgments (e.g., "OK") after processing messages.
+/// - Record `JobProgress` warnings that a run is close to its timeout, for the `Notifier` to send.
+/// - Mark runs that took longer than their job's `sla.expected_duration` as SLA breached.
+/// - Raise alerts (see `core_logic::datastore::alerts`) for failed runs and timeout warnings,
+///   paging for the failures of jobs whose severity pages, and resolve the failed run alerts of
+///   jobs that ran successfully again.
+/// - Record the state each run of a check job reports, and a warning for the `Notifier` to send
+///   when it changed (see `core_logic::datastore::check_states`).
+/// - Record each agent's receipt key when it first registers, and verify the signature on every
+///   run result against it, storing the outcome with the run (see `core_logic::receipts`).
+/// - Respond to agents with acknowledgments (e.g., "OK") after processing messages, or with an
+///   `Envelope` response of the same id for messages an agent sent in an `Envelope`, so agents can
+///   have many messages in flight on one connection (see Multiplexing in `core_logic::messages`).
+/// - Record bytes and messages per connection in `ConnectionMetrics`.
+/// - Verify the checksum of every frame from agents that send checksummed frames, and answer them
+///   in kind (see `core_logic::framing`). A frame that fails its checksum or does not parse is
+///   counted as a protocol error for the connection and answered with `NO`, so the agent sends
+///   it again; where the agent cannot be told which message was lost (reverse dispatch,
+///   multiplexed and legacy connections) the connection is closed instead, and the agent
+///   reconnects and resends what was not acknowledged.
+/// - Read messages in chunks of `CHUNK_SIZE`, adapted to each connection's throughput when
+///   `ADAPTIVE_CHUNKS` is set (see `core_logic::flow_control`).
+/// - Write acknowledgments and control messages such as `CancelJob` ahead of queued dispatches
+///   (see `core_logic::priority`).
+/// - Turn a connection into a reverse dispatch channel when the agent sends `ReverseDispatch`,
+///   so jobs reach agents whose listen port is not reachable.
+/// - Answer an agent's `PollWork` with the runs waiting for it, claimed on their job documents
+///   and sent over the connection, which becomes the agent's channel for control messages such as
+///   cancellations (see Pull Dispatch in `core_logic::messages`).
+/// - Require an `Authenticate` message first when an authentication backend is configured, and
+///   close connections that fail it or speak for another agent (see `auth`).
+/// - Refuse connections over `MAX_CONNECTIONS` or an address's connect rate limit, and close
+///   connections that are slow to send a message or send one that is too large (see
+///   `connection_limits`).
+/// - Enable TCP keepalive on accepted connections, so agents that vanished without closing their
+///   idle connection are detected (see `core_logic::keepalive`).
 ///
 /// # Key Methods
-/// - `new`: Creates a new `CommandReceiver` bound to a server address.
-/// - `listen`: Accepts incoming TCP connections and processes messages from each agent.
+/// - `new`: Creates a new `CommandReceiver` bound to each configured listen address.
+/// - `listen`: Accepts incoming TCP connections on all listeners and processes messages from each agent.
 /// - `process_messages`: Reads and handles messages from a TCP stream, dispatching logic based on message type.
-/// - `register_agent`: Inserts a new agent into the database.
+/// - `register_agent`: Inserts a new agent into the database, or records the version of a known one.
+/// - `record_receipt_key`: Records an agent's receipt key unless it already has one.
+/// - `verify_receipt`: Checks the signature on a run result against the agent's receipt key.
 /// - `mark_agent_job_complete`: Marks an agent as having completed a job and checks if the job is fully complete.
 /// - `check_job_if_all_agents_complete`: Checks if all required agents have completed a job and updates job status.
+/// - `hand_out_runs`: Sends a polling agent the runs it claimed.
 ///
 /// # Errors
 /// Methods return `Result` types and log errors using the `tracing` crate. Errors may occur during database operations,
@@ -30,71 +68,411 @@
 /// # Example
 /// ```rust
 /// let datastore = Arc::new(Datastore::new(...));
-/// let mut receiver = CommandReceiver::new(datastore).await;
+/// let mut receiver = CommandReceiver::new(
+///     datastore,
+///     agent_channels,
+///     agent_cache,
+///     connection_metrics,
+///     authenticator,
+/// )
+/// .await;
 /// receiver.listen().await?;
 /// ```
-use bson::{Array, Document, doc};
+use bson::{Array, Bson, DateTime, Document, doc};
 use core_logic::{
-    datastore::runs::RunsV1,
-    messages::{JobComplete, Message, RegisterAgent},
+    datastore::job_executions::JobExecutionV1,
+    datastore::runs::{Outcome, RunJobSnapshot, RunsV1, TriggeredBy},
+    flow_control::ChunkSizer,
+    framing::{self, FrameHeader, FrameReader, ProtocolError},
+    keepalive, logging,
+    messages::{
+        ArchivedMessage, Envelope, JobComplete, JobProgress, Message, MessageBuffer, MessageError,
+        PollWork, Priority, REVERSE_DISPATCH_ACK, RegisterAgent,
+    },
+    output_parsing::OutputParser,
+    priority::{PriorityLock, PriorityReceiver},
+    receipts,
 };
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpListener;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::spawn;
-use tracing::{debug, error, info, warn};
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+use tracing::{Instrument, debug, error, info, warn};
 
 use std::error::Error;
+use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::SERVER_ADDRESS;
-use core_logic::datastore::{Datastore, agents::AgentV1, jobs::Status};
+use crate::agent_cache::AgentCache;
+use crate::agent_channels::AgentChannels;
+use crate::agent_manager::AgentManager;
+use crate::auth::{self, AgentAuthenticator};
+use crate::connection_limits::ConnectionLimiter;
+use crate::connection_metrics::ConnectionMetrics;
+use crate::paging;
+use crate::{
+    get_adaptive_chunks, get_chunk_size, get_connect_rate_limit_per_minute, get_listen_addresses,
+    get_max_connections, get_max_message_bytes, get_read_timeout_seconds,
+    get_run_output_offload_bytes, get_tcp_keepalive_seconds,
+};
+use core_logic::datastore::{
+    Datastore,
+    agent_events::AgentEventKind,
+    agents::AgentV1,
+    alerts::{AlertKind, AlertV1},
+    check_states::{CheckState, CheckStateV1},
+    connections::ConnectionKind,
+    job_warnings::JobWarningV1,
+    jobs::{JobSla, JobV1, MisfirePolicy, Status, next_run_datetime},
+};
 use tokio::io::AsyncWriteExt;
 
-const CHUNKS_SIZE: usize = 4096; // Size of each message chunk
+const REVERSE_DISPATCH_CAPACITY: usize = 100;
+
+/// Shared state for a connection an agent opened to central command.
+#[derive(Clone)]
+struct AgentConnection {
+    writer: Arc<PriorityLock<OwnedWriteHalf>>,
+    datastore_client: Arc<Datastore>,
+    agent_channels: AgentChannels,
+    agent_cache: AgentCache,
+    connection_metrics: ConnectionMetrics,
+    connection_id: String,
+    peer_addr: std::net::SocketAddr,
+    authenticator: Option<Arc<dyn AgentAuthenticator>>,
+}
+
+impl AgentConnection {
+    /// Writes `data` to the agent, recording it in the connection metrics. Acknowledgments
+    /// (`message` is `None`) are control traffic.
+    async fn write(&self, data: &[u8], message: Option<&Message>) -> io::Result<()> {
+        let priority = message.map_or(Priority::Control, Message::priority);
+        self.writer.lock(priority).await.write_all(data).await?;
+        self.connection_metrics
+            .record_out(&self.connection_id, data.len(), message)
+            .await;
+        Ok(())
+    }
+}
+
+/// Forwards messages for an agent over the connection it opened to central command, either to
+/// push it jobs or, for an agent that pulls them, to answer its polls.
+struct ReverseDispatchChannel {
+    agent_name: String,
+    forwarder: JoinHandle<()>,
+}
+
+impl ReverseDispatchChannel {
+    /// Opens the channel, framing messages with checksums if the agent's frames have them. Jobs
+    /// are not pushed over the channel of an agent that `pulls` them.
+    async fn open(
+        agent_name: String,
+        connection: &AgentConnection,
+        checksummed: bool,
+        pulls: bool,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel::<Message>(REVERSE_DISPATCH_CAPACITY);
+        let kind = match pulls {
+            true => {
+                connection
+                    .agent_channels
+                    .register_puller(&agent_name, sender)
+                    .await;
+                ConnectionKind::Pull
+            }
+            false => {
+                connection
+                    .agent_channels
+                    .register(&agent_name, sender)
+                    .await;
+                ConnectionKind::ReverseDispatch
+            }
+        };
+        connection
+            .connection_metrics
+            .set_agent(&connection.connection_id, &agent_name, Some(kind))
+            .await;
+        if let Err(e) =
+            AgentManager::update_agent_online(connection.datastore_client.clone(), &agent_name)
+                .await
+        {
+            error!("Failed to update agent {} to online: {}", agent_name, e);
+        }
+        info!(
+            "Agent {} opened a {} connection from {}",
+            agent_name,
+            if pulls { "pull" } else { "reverse dispatch" },
+            connection.peer_addr
+        );
+
+        let connection = connection.clone();
+        let forwarder = spawn(async move {
+            let mut receiver = PriorityReceiver::new(receiver);
+            while let Some(message) = receiver.recv().await {
+                let frame = match Self::frame(message.clone(), checksummed) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        error!(
+                            "Failed to serialize message for {}: {}",
+                            connection.peer_addr, e
+                        );
+                        continue;
+                    }
+                };
+                if let Err(e) = connection.write(&frame, Some(&message)).await {
+                    error!("Failed to dispatch to {}: {}", connection.peer_addr, e);
+                    break;
+                }
+            }
+        });
+
+        Self {
+            agent_name,
+            forwarder,
+        }
+    }
+
+    /// Frames `message` the way the agent frames its own messages.
+    fn frame(message: Message, checksummed: bool) -> Result<Vec<u8>, MessageError> {
+        match checksummed {
+            true => message.to_frame(),
+            false => message.to_legacy_frame(),
+        }
+    }
+
+    /// Stops forwarding and marks the agent offline unless a newer connection replaced this one.
+    async fn close(self, connection: &AgentConnection) {
+        self.forwarder.abort();
+        let _ = self.forwarder.await;
+        connection.agent_channels.prune(&self.agent_name).await;
+        if !connection.agent_channels.contains(&self.agent_name).await
+            && let Err(e) = AgentManager::update_agent_offline(
+                connection.datastore_client.clone(),
+                &self.agent_name,
+            )
+            .await
+        {
+            error!(
+                "Failed to update agent {} to offline: {}",
+                self.agent_name, e
+            );
+        }
+    }
+}
 
 pub struct CommandReceiver {
     datastore_client: Arc<Datastore>,
-    listener: TcpListener,
+    agent_channels: AgentChannels,
+    agent_cache: AgentCache,
+    connection_metrics: ConnectionMetrics,
+    listeners: Vec<TcpListener>,
+    authenticator: Option<Arc<dyn AgentAuthenticator>>,
+    limiter: Arc<ConnectionLimiter>, // Shared by every listener
 }
 
 impl CommandReceiver {
-    pub async fn new(datastore_client: Arc<Datastore>) -> Self {
-        let listener = TcpListener::bind(SERVER_ADDRESS)
-            .await
-            .expect("Failed to bind to address");
+    pub async fn new(
+        datastore_client: Arc<Datastore>,
+        agent_channels: AgentChannels,
+        agent_cache: AgentCache,
+        connection_metrics: ConnectionMetrics,
+        authenticator: Option<Arc<dyn AgentAuthenticator>>,
+    ) -> Self {
+        let mut listeners = Vec::new();
+        for address in get_listen_addresses() {
+            let listener = TcpListener::bind(address)
+                .await
+                .unwrap_or_else(|e| panic!("Failed to bind to address {}: {}", address, e));
+            info!("Listening for agents on {}", address);
+            listeners.push(listener);
+        }
 
         CommandReceiver {
             datastore_client,
-            listener,
+            agent_channels,
+            agent_cache,
+            connection_metrics,
+            listeners,
+            authenticator,
+            limiter: Arc::new(ConnectionLimiter::new(
+                get_max_connections(),
+                get_connect_rate_limit_per_minute(),
+            )),
         }
     }
 
     /// Registers an agent in the database.
     /// This function takes a `RegisterAgent` message, converts it to an `AgentV1` struct,
-    /// and inserts it into the `agents` collection in the MongoDB database.
-    async fn register_agent(datastore_client: Arc<Datastore>, register_agent: RegisterAgent) {
+    /// and upserts it into the `agents` collection in the MongoDB database.
+    /// Agents that are already known only have their reported port, version, timezone, locale,
+    /// platform and container updated. The port can change when the agent's configured port was in use. An agent
+    /// in the trash stays there, so it is not dispatched to until it is restored. New agents join
+    /// `namespace`, the namespace of the token they authenticated with; known agents cannot change
+    /// namespace, see `auth::authorize`.
+    async fn register_agent(
+        datastore_client: Arc<Datastore>,
+        register_agent: RegisterAgent,
+        namespace: Option<&str>,
+    ) {
         let db = datastore_client.get_database();
         let agents_collection = db.collection::<Document>("agents");
-        let agent: AgentV1 = register_agent.into();
+        let mut agent: AgentV1 = register_agent.into();
+        agent.namespace = namespace.map(str::to_string);
 
-        let bson_agent = match bson::to_document(&agent) {
+        let mut bson_agent = match bson::to_document(&agent) {
             Ok(doc) => doc,
             Err(e) => {
                 error!("Failed to convert agent to BSON: {}", e);
                 return;
             }
         };
-        let result = agents_collection.insert_one(bson_agent).await;
+        bson_agent.remove("name");
+        bson_agent.remove("port");
+        bson_agent.remove("agent_version");
+        bson_agent.remove("timezone");
+        bson_agent.remove("locale");
+        for field in ["os", "arch", "kernel", "shells", "features", "container"] {
+            bson_agent.remove(field);
+        }
+        let container = match bson::to_bson(&agent.container) {
+            Ok(container) => container,
+            Err(e) => {
+                error!("Failed to convert agent container to BSON: {}", e);
+                return;
+            }
+        };
+
+        let filter = doc! { "name": &agent.name };
+        let update = doc! {
+            "$set": {
+                "port": agent.port as i32,
+                "agent_version": &agent.agent_version,
+                "timezone": &agent.timezone,
+                "locale": &agent.locale,
+                "os": &agent.os,
+                "arch": &agent.arch,
+                "kernel": &agent.kernel,
+                "shells": &agent.shells,
+                "features": &agent.features,
+                "container": container,
+            },
+            "$setOnInsert": bson_agent,
+        };
+        let result = agents_collection
+            .update_one(filter, update)
+            .upsert(true)
+            .await;
+        let detail = match result {
+            Ok(result) if result.upserted_id.is_some() => {
+                info!("Inserted agent: {:?}", agent);
+                Some(format!("new agent, version {}", agent.agent_version))
+            }
+            Ok(_) => {
+                info!(
+                    "Agent {} re-registered with version {}",
+                    agent.name, agent.agent_version
+                );
+                Some(format!("version {}", agent.agent_version))
+            }
+            Err(e) => {
+                warn!("Failed to register agent: {}, {}", agent, e);
+                None
+            }
+        };
+        if let Some(detail) = detail {
+            AgentManager::record_agent_event(
+                &datastore_client,
+                &agent.name,
+                AgentEventKind::Registered,
+                &detail,
+            )
+            .await;
+        }
+
+        if let Some(key) = &agent.receipt_public_key {
+            Self::record_receipt_key(&agents_collection, &agent.name, key).await;
+        }
+    }
+
+    /// Records `key` as the agent's receipt key if it has none. A different key is not trusted, so
+    /// the agent's results fail verification until an operator clears the recorded key.
+    async fn record_receipt_key(
+        agents_collection: &mongodb::Collection<Document>,
+        agent_name: &str,
+        key: &str,
+    ) {
+        let result = agents_collection
+            .update_one(
+                doc! { "name": agent_name, "receipt_public_key": null },
+                doc! { "$set": { "receipt_public_key": key } },
+            )
+            .await;
         match result {
+            Ok(result) if result.modified_count > 0 => {
+                info!("Recorded receipt key for agent {}", agent_name);
+            }
             Ok(_) => {
-                info!("Inserted agent: {:?}", agent);
+                let recorded = agents_collection
+                    .find_one(doc! { "name": agent_name })
+                    .await
+                    .ok()
+                    .flatten()
+                    .and_then(|agent| agent.get_str("receipt_public_key").ok().map(str::to_string));
+                if recorded.as_deref() != Some(key) {
+                    warn!(
+                        "Agent {} presented a receipt key that differs from the recorded one; its results will not verify until the recorded key is cleared",
+                        agent_name
+                    );
+                }
             }
             Err(e) => {
-                warn!("Failed to insert agent: {}, {}", agent, e);
+                error!("Failed to record receipt key for {}: {}", agent_name, e);
+            }
+        }
+    }
+
+    /// Checks the signature on a run result against the agent's receipt key, logging results that
+    /// cannot be trusted.
+    fn verify_receipt(job_complete: &JobComplete, public_key: Option<&str>) -> bool {
+        let JobComplete {
+            agent_name,
+            job_name,
+            ..
+        } = job_complete;
+        match (&job_complete.signature, public_key) {
+            (Some(signature), Some(public_key)) => {
+                let verified = receipts::verify(public_key, &job_complete.into(), signature);
+                if !verified {
+                    warn!(
+                        "Receipt for {job_name} on {agent_name} does not verify; the result may have been forged or altered"
+                    );
+                }
+                verified
+            }
+            (Some(_), None) => {
+                warn!(
+                    "{agent_name} signed its result for {job_name} but has no recorded receipt key"
+                );
+                false
             }
+            (None, Some(_)) => {
+                warn!(
+                    "{agent_name} sent an unsigned result for {job_name} despite having a receipt key"
+                );
+                false
+            }
+            (None, None) => false,
         }
     }
 
+    /// Once every agent of the cycle has reported, decides the cycle's outcome with the job's success
+    /// rule and sets the job's final status: `Completed` or `Error`, or `Archived` for a one-shot
+    /// job. Recurring jobs are made pending again for their next scheduled run instead. The jobs
+    /// of the job's `on_success` or `on_failure` hooks are then triggered.
     pub async fn check_job_completion(
         datastore_client: Arc<Datastore>,
         job_name: &str,
@@ -110,12 +488,16 @@ impl CommandReceiver {
             return Ok(());
         };
 
-        let agents_required = match job_doc.get_array("agents_required") {
-            Ok(arr) => arr,
-            Err(_) => {
-                debug!("Job {} missing 'agents_required' field.", job_name);
-                return Ok(());
-            }
+        // The cycle's resolved agents, or `agents_required` for cycles started without them.
+        let agents_required = match job_doc.get_array("cycle_agents") {
+            Ok(arr) if !arr.is_empty() => arr,
+            _ => match job_doc.get_array("agents_required") {
+                Ok(arr) => arr,
+                Err(_) => {
+                    debug!("Job {} missing 'agents_required' field.", job_name);
+                    return Ok(());
+                }
+            },
         };
 
         let agents_complete = match job_doc.get_array("agents_complete") {
@@ -127,16 +509,61 @@ impl CommandReceiver {
         };
 
         if agents_required.len() == agents_complete.len() && !agents_required.is_empty() {
-            info!("Completed job {}", job_name);
+            // Jobs dispatched before executions were recorded have no execution and complete.
+            let execution = match job_doc.get_str("cycle_id") {
+                Ok(cycle_id) => JobExecutionV1::finish(&datastore_client, cycle_id).await?,
+                Err(_) => None,
+            };
+            let one_shot = job_doc.get_bool("one_shot").unwrap_or_default();
+            let succeeded = matches!(
+                execution.as_ref().and_then(|execution| execution.outcome),
+                Some(Outcome::Success) | None
+            );
+            let status = match execution.as_ref().and_then(|execution| execution.outcome) {
+                _ if one_shot => Status::Archived,
+                Some(Outcome::Success) | None => Status::Completed,
+                Some(_) => Status::Error,
+            };
+            let next_run = Self::next_scheduled_run(&job_doc);
+            match &execution {
+                Some(execution) => info!(
+                    "Completed job {} as {:?}: {} of {} agents succeeded and the rule is that {}",
+                    job_name,
+                    status,
+                    execution.successes(),
+                    execution.agents_required.len(),
+                    execution.success_rule
+                ),
+                None => info!("Completed job {}", job_name),
+            }
 
+            let mut set = doc! {
+                "status": status,
+                "agents_running": Array::new(),
+                "agents_complete": Array::new(),
+            };
+            if let Some(next_run) = next_run {
+                info!("Job {} is scheduled to run again at {}", job_name, next_run);
+                set.insert("status", Status::Pending);
+                set.insert("next_run", next_run_datetime(next_run));
+            }
             let update = doc! {
-                "$set": {
-                    "status": Status::Completed,
-                    "agents_running": Array::new(),
-                    "agents_complete": Array::new(),
-                }
+                "$set": set,
+                "$unset": {
+                    "triggered_by": "",
+                    "cycle_id": "",
+                    "cycle_agents": "",
+                    "agents_pending": "",
+                    "rerun": "",
+                    "hook_chain": "",
+                },
             };
             jobs_collection.update_one(filter, update).await?;
+
+            match bson::from_document::<JobV1>(job_doc) {
+                Ok(job) => Self::trigger_hooks(&jobs_collection, &job, succeeded).await?,
+                Err(e) => warn!("Failed to read hooks of job {}: {}", job_name, e),
+            }
         } else {
             debug!("Job {} is not yet complete.", job_name);
         }
@@ -144,6 +571,94 @@ impl CommandReceiver {
         Ok(())
     }
 
+    /// Triggers the jobs of the `on_success` hooks of `job`, or of its `on_failure` hooks when the
+    /// cycle did not succeed, recording the hook and the chain of hooks that led to it on them.
+    /// Jobs already in that chain, `job` among them, are not triggered again, so hooks cannot
+    /// loop; neither are jobs that are running, disabled, archived, waiting for approval or in
+    /// another namespace.
+    async fn trigger_hooks(
+        jobs_collection: &mongodb::Collection<Document>,
+        job: &JobV1,
+        succeeded: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let hook = if succeeded {
+            "on_success"
+        } else {
+            "on_failure"
+        };
+        let targets = job.hooks(succeeded);
+        if targets.is_empty() {
+            return Ok(());
+        }
+        let mut chain = job.hook_chain.clone();
+        chain.push(job.name.clone());
+        let triggered_by = bson::to_bson(&TriggeredBy::Hook(format!("{}:{}", job.name, hook)))?;
+        let namespace = job.namespace.as_ref().map(Bson::from).unwrap_or(Bson::Null);
+
+        for target in targets {
+            if chain.contains(target) {
+                warn!(
+                    "Not triggering job {} from the {} hook of {}: it is already in the hook chain {}",
+                    target,
+                    hook,
+                    job.name,
+                    chain.join(" -> ")
+                );
+                continue;
+            }
+            let filter = doc! {
+                "name": target,
+                "namespace": namespace.clone(),
+                "status": { "$nin": [
+                    Status::Running,
+                    Status::Frozen,
+                    Status::Archived,
+                    Status::PendingApproval,
+                ] },
+            };
+            let update = doc! { "$set": {
+                "status": Status::Pending,
+                "next_run": next_run_datetime(DateTime::now().timestamp_millis() / 1000),
+                "triggered_by": triggered_by.clone(),
+                "hook_chain": &chain,
+            } };
+            let result = jobs_collection.update_one(filter, update).await?;
+            if result.matched_count > 0 {
+                info!(
+                    "Triggered job {} from the {} hook of {}",
+                    target, hook, job.name
+                );
+            } else {
+                warn!(
+                    "Not triggering job {} from the {} hook of {}: it is missing, running, disabled, archived, waiting for approval or in another namespace",
+                    target, hook, job.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// When a recurring job is next due: right after the run just completed with the `RunAll`
+    /// misfire policy, so missed runs are caught up one after another, otherwise the first
+    /// scheduled run still ahead. `None` for jobs that do not recur.
+    fn next_scheduled_run(job_doc: &Document) -> Option<i64> {
+        let job = match bson::from_document::<JobV1>(job_doc.clone()) {
+            Ok(job) => job,
+            Err(e) => {
+                warn!("Failed to read schedule of job: {}", e);
+                return None;
+            }
+        };
+        if !job.recurs() {
+            return None;
+        }
+        match job.misfire_policy {
+            MisfirePolicy::RunAll => Some(job.nth_run(1)),
+            _ => job.next_run_after(DateTime::now().timestamp_millis() / 1000),
+        }
+    }
+
     /// Adds an agent to the `agents_complete` list of a job in the database.
     /// This function updates the `jobs` collection in the MongoDB database,
     /// adding the agent's name to the `agents_complete` array for the specified job.
@@ -180,9 +695,157 @@ impl CommandReceiver {
             }
         }
 
+        let job_doc = jobs_collection.find_one(doc! { "name": &job_name }).await?;
+        // The provenance recorded on the job is authoritative; the agent only echoes it back.
+        let recorded_trigger: TriggeredBy = job_doc
+            .as_ref()
+            .and_then(|job_doc| job_doc.get_document("triggered_by").ok().cloned())
+            .and_then(|trigger_doc| bson::from_document(trigger_doc).ok())
+            .unwrap_or_default();
+        let cycle_id = job_doc
+            .as_ref()
+            .and_then(|job_doc| job_doc.get_str("cycle_id").ok())
+            .map(str::to_string);
+        let scheduling_lag_ms = job_doc
+            .as_ref()
+            .and_then(|job_doc| job_doc.get_i64("scheduling_lag_ms").ok());
+        let namespace = job_doc
+            .as_ref()
+            .and_then(|job_doc| job_doc.get_str("namespace").ok())
+            .map(str::to_string);
+        let tags: Vec<String> = job_doc
+            .as_ref()
+            .and_then(|job_doc| job_doc.get_array("tags").ok().cloned())
+            .and_then(|tags| bson::from_bson(Bson::Array(tags)).ok())
+            .unwrap_or_default();
+        let metadata = job_doc
+            .as_ref()
+            .and_then(|job_doc| job_doc.get_document("metadata").ok().cloned())
+            .unwrap_or_default();
+        // What the run was dispatched to do, as recorded when its cycle started, or the job as it
+        // is now for cycles started before snapshots were recorded.
+        let execution = match &cycle_id {
+            Some(cycle_id) => JobExecutionV1::find(&datastore_client, cycle_id).await?,
+            None => None,
+        };
+        let snapshot = execution.and_then(|execution| execution.job).or_else(|| {
+            job_doc
+                .as_ref()
+                .and_then(|job_doc| bson::from_document::<JobV1>(job_doc.clone()).ok())
+                .map(|job| RunJobSnapshot::from_job(&job))
+        });
+        let sla = job_doc
+            .as_ref()
+            .and_then(|job_doc| job_doc.get_document("sla").ok().cloned())
+            .and_then(|sla_doc| bson::from_document::<JobSla>(sla_doc).ok());
+        let expected_duration = sla.as_ref().and_then(|sla| sla.expected_duration);
+        let severity = sla.and_then(|sla| sla.severity);
+        let output_parsers: Vec<OutputParser> = job_doc
+            .as_ref()
+            .and_then(|job_doc| job_doc.get_array("output_parsers").ok().cloned())
+            .and_then(|parsers| bson::from_bson(Bson::Array(parsers)).ok())
+            .unwrap_or_default();
+        let success_statuses: Vec<String> = job_doc
+            .as_ref()
+            .and_then(|job_doc| job_doc.get_array("success_statuses").ok().cloned())
+            .and_then(|statuses| bson::from_bson(Bson::Array(statuses)).ok())
+            .unwrap_or_default();
+        let check = job_doc
+            .as_ref()
+            .and_then(|job_doc| job_doc.get_bool("check").ok())
+            .unwrap_or(false);
+
+        let agent_doc = db
+            .collection::<Document>("agents")
+            .find_one(doc! { "name": &agent_name })
+            .await?;
+        let receipt_public_key = agent_doc
+            .as_ref()
+            .and_then(|agent_doc| agent_doc.get_str("receipt_public_key").ok())
+            .map(str::to_string);
+        let verified = Self::verify_receipt(&job_complete, receipt_public_key.as_deref());
+
         // Mark the agent as having completed the job
-        let run: RunsV1 = job_complete.into();
+        let mut run: RunsV1 = job_complete.into();
+        if let Some(receipt) = run.receipt.as_mut() {
+            receipt.public_key = receipt_public_key;
+            receipt.verified = verified;
+        }
+        if run.triggered_by != recorded_trigger {
+            warn!(
+                "{agent_name} reported {job_name} as triggered by {} but it was triggered by {}",
+                run.triggered_by, recorded_trigger
+            );
+            run.triggered_by = recorded_trigger;
+        }
+        run.cycle_id = cycle_id;
+        run.job = snapshot;
+        run.scheduling_lag_ms = scheduling_lag_ms;
+        run.namespace = namespace;
+        run.tags = tags;
+        run.metadata = metadata;
+        run.sla_breached = expected_duration.is_some_and(|seconds| run.exceeds(seconds));
+        if run.sla_breached {
+            warn!("{agent_name} took longer than the SLA of {job_name} expects");
+        }
+        run.parse_output(&output_parsers, &success_statuses);
+        if run.reported_outcome.is_some() {
+            warn!(
+                "{agent_name} ran {job_name} successfully but its output has status {}",
+                run.parsed
+                    .as_ref()
+                    .and_then(|parsed| parsed.status.as_deref())
+                    .unwrap_or("none")
+            );
+        }
+        // After parsing, which needs the full output.
+        run.offload_output(&datastore_client, get_run_output_offload_bytes())
+            .await?;
         run.insert_entry(&db).await?;
+        if let Some(cycle_id) = &run.cycle_id {
+            JobExecutionV1::record_result(&datastore_client, cycle_id, &(&run).into()).await?;
+        }
+        if matches!(run.outcome, Outcome::Failure | Outcome::TimedOut) {
+            let detail = match run.outcome {
+                Outcome::TimedOut => "Timed out".to_string(),
+                _ => format!("Failed with return code {}", run.return_code),
+            };
+            AlertV1::raise(
+                &datastore_client,
+                AlertKind::RunFailed,
+                &job_name,
+                &agent_name,
+                run.run_id.as_deref(),
+                &detail,
+            )
+            .await?;
+            if let Some(severity) = paging::paging_severity(severity.as_deref()) {
+                AlertV1::request_page(
+                    &datastore_client,
+                    AlertKind::RunFailed,
+                    &job_name,
+                    &agent_name,
+                    &severity,
+                )
+                .await?;
+            }
+        } else if run.outcome == Outcome::Success {
+            AlertV1::resolve_recovered(&datastore_client, &job_name, &agent_name).await?;
+        }
+        let previous_state = match check {
+            true => CheckStateV1::record(&datastore_client, &run).await?,
+            false => None,
+        };
+        if let Some(previous) = previous_state {
+            info!(
+                "Check {job_name} on {agent_name} went from {} to {}",
+                previous,
+                CheckState::from_run(run.outcome, run.return_code)
+            );
+            JobWarningV1::state_changed(&run, previous)
+                .insert_entry(&datastore_client)
+                .await?;
+        }
 
         drop(db);
 
@@ -192,122 +855,653 @@ impl CommandReceiver {
     /// Processes incoming messages from the TCP stream.
     /// This function reads messages from the stream, deserializes them into `Message` enum variants,
     /// and handles each message type accordingly.
-    /// It handles `Ping`, `RegisterAgent`, and `JobComplete` messages.
+    /// It handles `Ping`, `RegisterAgent`, `JobComplete`, `ReverseDispatch` and `PollWork` messages.
     /// If the connection is closed by the client, it logs the event and exits the loop.
     /// If an error occurs while reading from the stream, it logs the error and exits the loop.
     /// Returns `Ok(())` if successful, or an error if something goes wrong.
     pub async fn process_messages(
-        stream: &mut tokio::net::TcpStream,
+        stream: TcpStream,
         datastore_client: Arc<Datastore>,
+        agent_channels: AgentChannels,
+        agent_cache: AgentCache,
+        connection_metrics: ConnectionMetrics,
         peer_addr: std::net::SocketAddr,
+        authenticator: Option<Arc<dyn AgentAuthenticator>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (mut reader, writer) = stream.into_split();
+        let connection_id = connection_metrics
+            .open(ConnectionKind::Inbound, peer_addr, None)
+            .await;
+        let connection = AgentConnection {
+            writer: Arc::new(PriorityLock::new(writer)),
+            datastore_client,
+            agent_channels,
+            agent_cache,
+            connection_metrics,
+            connection_id,
+            peer_addr,
+            authenticator,
+        };
+        let mut reverse_dispatch = None;
+
+        let result = Self::read_messages(&mut reader, &connection, &mut reverse_dispatch)
+            .await
+            .map_err(|e| e.to_string()); // Box<dyn Error> is not Send, so it cannot be held across the close
+
+        if let Some(channel) = reverse_dispatch {
+            channel.close(&connection).await;
+        }
+        connection
+            .connection_metrics
+            .close(&connection.connection_id)
+            .await;
+        result.map_err(Into::into)
+    }
+
+    async fn read_messages<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        connection: &AgentConnection,
+        reverse_dispatch: &mut Option<ReverseDispatchChannel>,
     ) -> Result<(), Box<dyn Error>> {
+        let peer_addr = connection.peer_addr;
+        let datastore_client = &connection.datastore_client;
+        let mut authenticated: Option<String> = None; // Agent the connection authenticated as
+        let mut namespace: Option<String> = None; // Namespace of the token it authenticated with
+        let mut chunk_sizer = ChunkSizer::new(get_chunk_size(), get_adaptive_chunks());
+        let mut frames = FrameReader::new(get_max_message_bytes());
+        let mut body = MessageBuffer::new(); // Reused for every message on the connection
+        let mut multiplexed = false; // Whether the agent sent its messages in envelopes
+        // A new connection must send its first message within the read timeout.
+        let mut deadline = Self::read_deadline();
         loop {
-            let msg_len = match Self::read_message_length(stream, peer_addr).await? {
-                Some(len) => len,
-                None => break, // Connection closed
+            let header = Self::read_message_header(reader, &mut frames, peer_addr, &mut deadline)
+                .await
+                // Box<dyn Error> is not Send, so it cannot be held across recording the error
+                .map_err(|e| (e.downcast_ref::<ProtocolError>().cloned(), e.to_string()));
+            let header = match header {
+                Ok(Some(header)) => header,
+                Ok(None) => break, // Connection closed
+                Err((error, message)) => {
+                    if let Some(error) = error {
+                        Self::record_protocol_error(connection, &error).await;
+                    }
+                    return Err(message.into());
+                }
             };
+            if header.skipped > 0 {
+                let error = ProtocolError::Resynchronized {
+                    skipped: header.skipped,
+                };
+                Self::record_protocol_error(connection, &error).await;
+            }
 
-            let received_data = Self::read_message_body(stream, msg_len, peer_addr).await?;
-            let message: Message = received_data.try_into()?;
+            Self::before_deadline(
+                deadline,
+                Self::read_message_body(reader, header.len, &mut chunk_sizer, &mut body, peer_addr),
+            )
+            .await
+            .map_err(|_| format!("Timed out reading message from {}", peer_addr))??;
+            deadline = None; // Established connections may be idle between messages
+            let frame_len = header.skipped + header.header_len() + header.len;
+            let archived = match Self::parse_frame(&header, &body) {
+                Ok(archived) => archived,
+                Err(error) => {
+                    Self::record_protocol_error(connection, &error).await;
+                    connection
+                        .connection_metrics
+                        .record_in(&connection.connection_id, frame_len, None)
+                        .await;
+                    if !frames.checksummed() || multiplexed || reverse_dispatch.is_some() {
+                        return Err(format!("{} from {}", error, peer_addr).into());
+                    }
+                    if let Err(e) = connection.write(b"NO", None).await {
+                        error!("Failed to send NO reply to {}: {}", peer_addr, e);
+                    }
+                    continue;
+                }
+            };
+            // Pings, the most frequent message, are handled in place without deserializing them.
+            if let ArchivedMessage::Ping = archived
+                && (connection.authenticator.is_none() || authenticated.is_some())
+            {
+                connection
+                    .connection_metrics
+                    .record_in(&connection.connection_id, frame_len, Some(archived.kind()))
+                    .await;
+                Self::acknowledge(connection, None, reverse_dispatch.is_some(), &frames).await?;
+                Self::handle_ping(connection, reverse_dispatch.as_ref()).await;
+                continue;
+            }
+            let opened = Self::open_envelope(Message::from(archived))
+                .map_err(|e| ProtocolError::Malformed(format!("Invalid envelope: {}", e)));
+            let (request_id, message) = match opened {
+                Ok(opened) => opened,
+                Err(error) => {
+                    Self::record_protocol_error(connection, &error).await;
+                    return Err(format!("{} from {}", error, peer_addr).into());
+                }
+            };
+            multiplexed |= request_id.is_some();
+            connection
+                .connection_metrics
+                .record_in(&connection.connection_id, frame_len, Some(message.kind()))
+                .await;
+            if let Some(authenticator) = &connection.authenticator {
+                Self::check_authentication(
+                    authenticator.as_ref(),
+                    datastore_client,
+                    &message,
+                    &mut authenticated,
+                    &mut namespace,
+                    peer_addr,
+                )
+                .await?;
+            }
 
             // Send an OK reply to the agent after job complete
-            if let Err(e) = stream.write_all(b"OK").await {
-                error!("Failed to send OK reply to {}: {}", peer_addr, e);
+            Self::acknowledge(connection, request_id, reverse_dispatch.is_some(), &frames).await?;
+
+            match message {
+                Message::ReverseDispatch(request) => {
+                    if reverse_dispatch.is_some() {
+                        warn!("{} already opened a reverse dispatch connection", peer_addr);
+                        continue;
+                    }
+                    *reverse_dispatch = Some(
+                        ReverseDispatchChannel::open(
+                            request.agent_name,
+                            connection,
+                            frames.checksummed(),
+                            false,
+                        )
+                        .await,
+                    );
+                }
+                Message::PollWork(poll) => {
+                    match reverse_dispatch {
+                        Some(channel) if channel.agent_name != poll.agent_name => {
+                            warn!(
+                                "{} polled for {} over the connection of {}",
+                                peer_addr, poll.agent_name, channel.agent_name
+                            );
+                            continue;
+                        }
+                        Some(_) => {
+                            if let Err(e) = AgentManager::update_agent_online(
+                                datastore_client.clone(),
+                                &poll.agent_name,
+                            )
+                            .await
+                            {
+                                error!(
+                                    "Failed to update agent {} to online: {}",
+                                    poll.agent_name, e
+                                );
+                            }
+                        }
+                        None => {
+                            *reverse_dispatch = Some(
+                                ReverseDispatchChannel::open(
+                                    poll.agent_name.clone(),
+                                    connection,
+                                    frames.checksummed(),
+                                    true,
+                                )
+                                .await,
+                            );
+                        }
+                    }
+                    Self::hand_out_runs(connection, &poll).await;
+                }
+                message => {
+                    let agent_name = match &message {
+                        Message::RegisterAgent(register_agent) => Some(&register_agent.name),
+                        Message::JobComplete(job_complete) => Some(&job_complete.agent_name),
+                        Message::JobProgress(job_progress) => Some(&job_progress.agent_name),
+                        _ => None,
+                    };
+                    if let Some(agent_name) = agent_name {
+                        connection
+                            .connection_metrics
+                            .set_agent(&connection.connection_id, agent_name, None)
+                            .await;
+                    }
+                    let registered = match &message {
+                        Message::RegisterAgent(register_agent) => Some(register_agent.name.clone()),
+                        _ => None,
+                    };
+                    Self::handle_message(
+                        message,
+                        datastore_client.clone(),
+                        peer_addr,
+                        namespace.as_deref(),
+                    )
+                    .await?;
+                    // Reloaded now, so a new agent is dialed without waiting for the change stream.
+                    if let Some(agent_name) = registered {
+                        let reloaded = connection
+                            .agent_cache
+                            .reload(datastore_client, &agent_name)
+                            .await
+                            .map_err(|e| e.to_string());
+                        if let Err(e) = reloaded {
+                            warn!("Failed to reload agent {}: {}", agent_name, e);
+                        }
+                    }
+                }
             }
+        }
+        Ok(())
+    }
 
-            Self::handle_message(message, datastore_client.clone(), peer_addr).await?;
+    /// Claims up to the runs a polling agent asked for and sends them over its channel. A run
+    /// that cannot be sent is recorded as failed to dispatch and its claim released.
+    async fn hand_out_runs(connection: &AgentConnection, poll: &PollWork) {
+        let datastore = &connection.datastore_client;
+        let claimed = AgentManager::claim_runs(datastore, &poll.agent_name, poll.max_jobs)
+            .await
+            .map_err(|e| e.to_string()); // Box<dyn Error> is not Send
+        let claimed = match claimed {
+            Ok(claimed) => claimed,
+            Err(e) => {
+                error!("Failed to claim runs for {}: {}", poll.agent_name, e);
+                return;
+            }
+        };
+        for (job, run_id, messages) in claimed {
+            let span = logging::run_span(Some(&run_id), &job.name, &poll.agent_name);
+            let mut delivered = Ok(());
+            for message in messages {
+                delivered = connection
+                    .agent_channels
+                    .send(&poll.agent_name, message)
+                    .await;
+                if delivered.is_err() {
+                    break;
+                }
+            }
+            match delivered {
+                Ok(()) => span.in_scope(|| {
+                    info!("Agent {} claimed job {}", poll.agent_name, job.name);
+                }),
+                Err(e) => {
+                    span.in_scope(|| {
+                        error!("Failed to hand job to agent {}: {}", poll.agent_name, e)
+                    });
+                    AgentManager::fail_claimed_run(
+                        datastore,
+                        &job,
+                        &poll.agent_name,
+                        run_id,
+                        &e.to_string(),
+                    )
+                    .instrument(span)
+                    .await;
+                }
+            }
+        }
+    }
+
+    /// Checks a frame read into `body` against its header's checksum and reads its message in
+    /// place.
+    fn parse_frame<'a>(
+        header: &FrameHeader,
+        body: &'a MessageBuffer,
+    ) -> Result<&'a ArchivedMessage, ProtocolError> {
+        header.verify(body.bytes(header.len))?;
+        body.message(header.len)
+            .map_err(|e| ProtocolError::Malformed(e.to_string()))
+    }
+
+    /// Acknowledges a message from the agent: with `OK`, in the framing of its reverse dispatch
+    /// connection, or with the response to the envelope `request_id` it came in.
+    async fn acknowledge(
+        connection: &AgentConnection,
+        request_id: Option<u64>,
+        reverse_dispatch: bool,
+        frames: &FrameReader,
+    ) -> Result<(), Box<dyn Error>> {
+        let ack: Vec<u8> = match (request_id, reverse_dispatch) {
+            (Some(id), _) => ReverseDispatchChannel::frame(
+                Message::Envelope(Envelope::response(id)),
+                frames.checksummed(),
+            )?,
+            (None, true) if frames.checksummed() => framing::encode_frame(&[]),
+            (None, true) => REVERSE_DISPATCH_ACK.to_vec(),
+            (None, false) => b"OK".to_vec(),
+        };
+        if let Err(e) = connection.write(&ack, None).await {
+            error!("Failed to send OK reply to {}: {}", connection.peer_addr, e);
         }
         Ok(())
     }
 
-    async fn read_message_length(
-        stream: &mut tokio::net::TcpStream,
+    /// Handles a `Ping` from the agent, which is a heartbeat when the agent pings over its reverse
+    /// dispatch connection.
+    async fn handle_ping(
+        connection: &AgentConnection,
+        reverse_dispatch: Option<&ReverseDispatchChannel>,
+    ) {
+        debug!("Ping received from {}", connection.peer_addr);
+        if let Some(channel) = reverse_dispatch
+            && let Err(e) = AgentManager::update_agent_online(
+                connection.datastore_client.clone(),
+                &channel.agent_name,
+            )
+            .await
+        {
+            error!(
+                "Failed to update agent {} to online: {}",
+                channel.agent_name, e
+            );
+        }
+    }
+
+    /// Counts a frame from the agent that could not be used.
+    async fn record_protocol_error(connection: &AgentConnection, error: &ProtocolError) {
+        warn!("Protocol error from {}: {}", connection.peer_addr, error);
+        connection
+            .connection_metrics
+            .record_protocol_error(&connection.connection_id, error)
+            .await;
+    }
+
+    /// Unwraps a message an agent sent in an `Envelope`, returning the envelope's id to answer
+    /// with. Other messages are returned as they are.
+    fn open_envelope(message: Message) -> Result<(Option<u64>, Message), Box<dyn Error>> {
+        let Message::Envelope(envelope) = message else {
+            return Ok((None, message));
+        };
+        match envelope.message()? {
+            Some(Message::Envelope(_)) => Err("Envelopes cannot be nested".into()),
+            Some(message) => Ok((Some(envelope.id), message)),
+            None => Err(format!("Response {} sent to central command", envelope.id).into()),
+        }
+    }
+
+    /// Authenticates the connection with its first message, which must be `Authenticate`, and
+    /// rejects later messages on behalf of any other agent. An error closes the connection.
+    /// `namespace` is set to the namespace the credential belongs to.
+    async fn check_authentication(
+        authenticator: &dyn AgentAuthenticator,
+        datastore_client: &Datastore,
+        message: &Message,
+        authenticated: &mut Option<String>,
+        namespace: &mut Option<String>,
         peer_addr: std::net::SocketAddr,
-    ) -> Result<Option<usize>, Box<dyn Error>> {
-        let mut len_buf = [0u8; 4];
-        match stream.read_exact(&mut len_buf).await {
-            Ok(_) => {
-                let msg_len = u32::from_be_bytes(len_buf) as usize;
-                if msg_len == 0 {
+    ) -> Result<(), Box<dyn Error>> {
+        match (message, authenticated.as_ref()) {
+            (Message::Authenticate(request), None) => {
+                let credential_namespace = auth::verify(
+                    authenticator,
+                    datastore_client,
+                    &request.agent_name,
+                    &request.credential,
+                )
+                .await
+                .map_err(|e| format!("{} {}", peer_addr, e))?;
+                info!(
+                    "{} authenticated as agent {}",
+                    peer_addr, request.agent_name
+                );
+                *authenticated = Some(request.agent_name.clone());
+                *namespace = credential_namespace;
+                Ok(())
+            }
+            (Message::Authenticate(_), Some(_)) => {
+                Err(format!("{} authenticated more than once", peer_addr).into())
+            }
+            (_, None) => Err(format!(
+                "{} sent {} before authenticating",
+                peer_addr,
+                message.kind()
+            )
+            .into()),
+            (_, Some(agent_name)) => match auth::claimed_agent(message) {
+                Some(claimed) if claimed != agent_name => Err(format!(
+                    "{} authenticated as {} but sent {} for {}",
+                    peer_addr,
+                    agent_name,
+                    message.kind(),
+                    claimed
+                )
+                .into()),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// When a message that starts now must have arrived by, or `None` without a read timeout.
+    fn read_deadline() -> Option<Instant> {
+        let seconds = get_read_timeout_seconds();
+        (seconds > 0).then(|| Instant::now() + Duration::from_secs(seconds))
+    }
+
+    /// Waits for `read` until `deadline`, failing with `TimedOut` after it.
+    async fn before_deadline<F: Future>(
+        deadline: Option<Instant>,
+        read: F,
+    ) -> io::Result<F::Output> {
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, read)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Timed out reading message")),
+            None => Ok(read.await),
+        }
+    }
+
+    /// Reads the header of the next message. Without a `deadline` it waits for the message to
+    /// start as long as it takes, then sets the deadline for the rest of it.
+    async fn read_message_header<R: AsyncRead + Unpin>(
+        stream: &mut R,
+        frames: &mut FrameReader,
+        peer_addr: std::net::SocketAddr,
+        deadline: &mut Option<Instant>,
+    ) -> Result<Option<FrameHeader>, Box<dyn Error>> {
+        let mut first = [0u8; 1];
+        let read = Self::before_deadline(*deadline, stream.read(&mut first))
+            .await
+            .and_then(|read| read);
+        if deadline.is_none() {
+            *deadline = Self::read_deadline();
+        }
+        let result = match read {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Self::before_deadline(*deadline, frames.read_header_after(stream, first[0]))
+                .await
+                .and_then(|header| header),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(header) => {
+                if header.len == 0 {
                     warn!("Received zero-length message from {}", peer_addr);
-                    Ok(None)
-                } else {
-                    Ok(Some(msg_len))
+                    return Ok(None);
                 }
+                Ok(Some(header))
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                Err(format!("Timed out reading message from {}", peer_addr).into())
             }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => match ProtocolError::from_io(&e) {
+                Some(error) => Err(Box::new(error.clone())),
+                None => Err(format!(
+                    "Message from {} refused (MAX_MESSAGE_BYTES): {}",
+                    peer_addr, e
+                )
+                .into()),
+            },
             Err(e) => {
                 if e.kind() == std::io::ErrorKind::UnexpectedEof {
                     info!("Connection with {} closed by peer.", peer_addr);
                     Ok(None)
                 } else {
-                    error!("Failed to read message length from {}: {}", peer_addr, e);
+                    error!("Failed to read message header from {}: {}", peer_addr, e);
                     Ok(None)
                 }
             }
         }
     }
 
-    async fn read_message_body(
-        stream: &mut tokio::net::TcpStream,
+    async fn read_message_body<R: AsyncRead + Unpin>(
+        stream: &mut R,
         msg_len: usize,
+        chunk_sizer: &mut ChunkSizer,
+        body: &mut MessageBuffer,
         peer_addr: std::net::SocketAddr,
-    ) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut received_data = Vec::with_capacity(msg_len);
-        while received_data.len() < msg_len {
-            let to_read = std::cmp::min(CHUNKS_SIZE, msg_len - received_data.len());
-            let mut buffer = vec![0u8; to_read];
-            let n = stream.read(&mut buffer).await?;
-            if n == 0 {
-                info!(
-                    "Connection with {} closed while reading message.",
-                    peer_addr
-                );
-                return Err("Connection closed while reading message".into());
-            }
-            received_data.extend_from_slice(&buffer[..n]);
-        }
-        Ok(received_data)
+    ) -> Result<(), Box<dyn Error>> {
+        framing::read_frame_body_into(stream, msg_len, chunk_sizer, body)
+            .await
+            .inspect_err(|e| {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    info!(
+                        "Connection with {} closed while reading message.",
+                        peer_addr
+                    );
+                }
+            })
+            .map_err(Into::into)
     }
 
-    async fn handle_message(
+    /// Handles a message from an agent at `peer_addr`, which authenticated with a token of
+    /// `namespace`, if any.
+    pub(crate) async fn handle_message(
         message: Message,
         datastore_client: Arc<Datastore>,
         peer_addr: std::net::SocketAddr,
+        namespace: Option<&str>,
     ) -> Result<(), Box<dyn Error>> {
         match message {
             Message::Ping => {
                 debug!("Ping received from {}", peer_addr);
             }
             Message::RegisterAgent(register_agent) => {
-                Self::register_agent(datastore_client, register_agent).await;
+                Self::register_agent(datastore_client, register_agent, namespace).await;
             }
             Message::JobComplete(job_complete) => {
-                Self::complete_agent_run(datastore_client, job_complete, peer_addr).await?;
+                let span = logging::run_span(
+                    job_complete.run_id.as_deref(),
+                    &job_complete.job_name,
+                    &job_complete.agent_name,
+                );
+                Self::complete_agent_run(datastore_client, job_complete, peer_addr)
+                    .instrument(span)
+                    .await?;
+            }
+            Message::JobProgress(job_progress) => {
+                let span = logging::run_span(
+                    job_progress.run_id.as_deref(),
+                    &job_progress.job_name,
+                    &job_progress.agent_name,
+                );
+                Self::record_timeout_warning(&datastore_client, &job_progress)
+                    .instrument(span)
+                    .await?;
             }
             _ => (),
         }
         Ok(())
     }
 
+    /// Records an agent's warning that a run is close to its timeout, so its owners are told
+    /// before the run is killed.
+    async fn record_timeout_warning(
+        datastore_client: &Datastore,
+        job_progress: &JobProgress,
+    ) -> Result<(), Box<dyn Error>> {
+        warn!(
+            "Job {} on agent {} has run for {} of its {} second timeout",
+            job_progress.job_name,
+            job_progress.agent_name,
+            job_progress.elapsed,
+            job_progress.timeout
+        );
+        JobWarningV1::from(job_progress)
+            .insert_entry(datastore_client)
+            .await?;
+        AlertV1::raise(
+            datastore_client,
+            AlertKind::TimeoutWarning,
+            &job_progress.job_name,
+            &job_progress.agent_name,
+            job_progress.run_id.as_deref(),
+            &format!(
+                "Ran for {} of its {} second timeout",
+                job_progress.elapsed, job_progress.timeout
+            ),
+        )
+        .await
+    }
+
     /// Listens for incoming TCP connections and processes messages.
-    /// This function accepts incoming connections, spawns a new task for each connection,
-    /// and processes messages from the stream using `process_messages`.
-    /// It runs indefinitely, accepting connections and processing messages until an error occurs.
-    #[allow(unreachable_code)]
+    /// Each listener accepts connections in its own task, spawning a new task per connection
+    /// that processes messages from the stream using `process_messages`.
+    /// It runs indefinitely, accepting connections and processing messages until a listener fails.
     pub async fn listen(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut accept_tasks = JoinSet::new();
+        for listener in self.listeners.drain(..) {
+            accept_tasks.spawn(Self::accept_connections(
+                listener,
+                self.datastore_client.clone(),
+                self.agent_channels.clone(),
+                self.agent_cache.clone(),
+                self.connection_metrics.clone(),
+                self.authenticator.clone(),
+                self.limiter.clone(),
+            ));
+        }
+
+        while let Some(result) = accept_tasks.join_next().await {
+            result??;
+        }
+
+        Ok(())
+    }
+
+    async fn accept_connections(
+        listener: TcpListener,
+        datastore_client: Arc<Datastore>,
+        agent_channels: AgentChannels,
+        agent_cache: AgentCache,
+        connection_metrics: ConnectionMetrics,
+        authenticator: Option<Arc<dyn AgentAuthenticator>>,
+        limiter: Arc<ConnectionLimiter>,
+    ) -> io::Result<()> {
         loop {
-            let datastore_client = self.datastore_client.clone();
-            let (mut stream, peer_addr) = self.listener.accept().await?;
+            let datastore_client = datastore_client.clone();
+            let agent_channels = agent_channels.clone();
+            let agent_cache = agent_cache.clone();
+            let connection_metrics = connection_metrics.clone();
+            let authenticator = authenticator.clone();
+            let (stream, peer_addr) = listener.accept().await?;
+            let permit = match limiter.admit(peer_addr.ip()) {
+                Ok(permit) => permit,
+                Err(reason) => {
+                    warn!("Refusing connection from {}: {}", peer_addr, reason);
+                    continue; // Dropping the stream closes it
+                }
+            };
+            let keepalive = Duration::from_secs(get_tcp_keepalive_seconds());
+            if let Err(e) = keepalive::enable(&stream, keepalive) {
+                warn!("Failed to enable keepalive to {}: {}", peer_addr, e);
+            }
             spawn(async move {
+                let _permit = permit; // Released when the connection closes
                 info!("Accepted connection from: {}", peer_addr);
-                if let Err(e) =
-                    Self::process_messages(&mut stream, datastore_client, peer_addr).await
+                if let Err(e) = Self::process_messages(
+                    stream,
+                    datastore_client,
+                    agent_channels,
+                    agent_cache,
+                    connection_metrics,
+                    peer_addr,
+                    authenticator,
+                )
+                .await
                 {
                     error!("Error processing messages from {}: {}", peer_addr, e);
                 }
             });
         }
-
-        Ok(())
     }
 }
@@ -0,0 +1,78 @@
+/// Limits on the connections the `CommandReceiver` accepts, so a misbehaving client cannot
+/// exhaust central command with connections or half-sent messages.
+///
+/// # Overview
+/// - At most `MAX_CONNECTIONS` agent connections are open at once across all listeners. Further
+///   connections are closed as soon as they are accepted.
+/// - Each IP address may open `CONNECT_RATE_LIMIT_PER_MINUTE` connections a minute. Agents keep
+///   one connection open, so only clients reconnecting in a loop are refused.
+/// - `READ_TIMEOUT_SECONDS` bounds how long a new connection may take to send its first message,
+///   and how long any message may take to arrive once it has started. Idle connections between
+///   messages are kept, since agents may have nothing to send for hours.
+/// - Messages longer than `MAX_MESSAGE_BYTES` close the connection before anything is read.
+///
+/// A limit of `0` disables it.
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    connections: Option<Arc<Semaphore>>,
+    connects_per_minute: u32,
+    connects: Mutex<HashMap<IpAddr, (Instant, u32)>>, // Start of each address's window and its connects
+}
+
+/// Held for as long as an accepted connection is open.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_connections: usize, connects_per_minute: u32) -> Self {
+        Self {
+            connections: (max_connections > 0).then(|| Arc::new(Semaphore::new(max_connections))),
+            connects_per_minute,
+            connects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admits a connection from `ip`, or explains why it is refused.
+    pub fn admit(&self, ip: IpAddr) -> Result<ConnectionPermit, String> {
+        if !self.within_rate(ip) {
+            return Err(format!(
+                "{} opened more than {} connections in the last minute",
+                ip, self.connects_per_minute
+            ));
+        }
+        let permit = match &self.connections {
+            Some(connections) => {
+                Some(connections.clone().try_acquire_owned().map_err(|_| {
+                    "Too many open connections (MAX_CONNECTIONS reached)".to_string()
+                })?)
+            }
+            None => None,
+        };
+        Ok(ConnectionPermit { _permit: permit })
+    }
+
+    /// Counts a connect from `ip`, returning whether it is within the rate limit.
+    fn within_rate(&self, ip: IpAddr) -> bool {
+        if self.connects_per_minute == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let mut connects = self.connects.lock().unwrap_or_else(|e| e.into_inner());
+        // Forget addresses whose window ended, so the map does not grow with every client seen.
+        connects.retain(|_, (start, _)| now.duration_since(*start) < RATE_WINDOW);
+        let (_, count) = connects.entry(ip).or_insert((now, 0));
+        *count += 1;
+        *count <= self.connects_per_minute
+    }
+}
@@ -0,0 +1,140 @@
+/// `ConnectionMetrics` tracks the TCP connections central command holds with agents: who is on
+/// the other end, since when, how many bytes and messages went each way, what was sent last and
+/// how many frames from the peer were corrupted or could not be parsed.
+///
+/// # Overview
+/// - The `CommandReceiver` records connections agents open, including reverse dispatch ones.
+/// - The `AgentManager` records the connections it dials to agents' listen ports.
+/// - `publish` replaces this instance's connections in the `connections` collection with a
+///   snapshot, which the webui shows on its connection inspector page. Instances sharing the
+///   datastore each publish their own (see `leader`).
+/// - Protocol errors are counted per connection, so operators can tell a corrupted or
+///   incompatible peer from a healthy one.
+///
+/// # Example
+/// ```rust
+/// let metrics = ConnectionMetrics::new("central-1");
+/// let id = metrics.open(ConnectionKind::Inbound, peer_addr, None).await;
+/// metrics.record_in(&id, 128, Some(message.kind())).await;
+/// metrics.close(&id).await;
+/// ```
+use bson::{Bson, DateTime, doc};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use core_logic::datastore::Datastore;
+use core_logic::datastore::connections::{ConnectionKind, ConnectionV1};
+use core_logic::framing::ProtocolError;
+use core_logic::messages::Message;
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionMetrics {
+    central_command: String, // Id of this instance, see `CENTRAL_COMMAND_ID`
+    connections: Arc<Mutex<HashMap<String, ConnectionV1>>>,
+}
+
+impl ConnectionMetrics {
+    pub fn new(central_command: &str) -> Self {
+        Self {
+            central_command: central_command.to_string(),
+            connections: Arc::default(),
+        }
+    }
+
+    /// Starts tracking a connection and returns its id.
+    pub async fn open(
+        &self,
+        kind: ConnectionKind,
+        remote_addr: SocketAddr,
+        agent_name: Option<&str>,
+    ) -> String {
+        let id = Uuid::new_v4().to_string();
+        let connection = ConnectionV1 {
+            id: id.clone(),
+            central_command: self.central_command.clone(),
+            kind,
+            agent_name: agent_name.map(str::to_string),
+            remote_addr: remote_addr.to_string(),
+            connected_since: DateTime::now(),
+            bytes_in: 0,
+            bytes_out: 0,
+            messages_in: 0,
+            messages_out: 0,
+            last_message: None,
+            last_message_at: None,
+            protocol_errors: 0,
+            last_protocol_error: None,
+        };
+        self.connections.lock().await.insert(id.clone(), connection);
+        id
+    }
+
+    pub async fn close(&self, id: &str) {
+        self.connections.lock().await.remove(id);
+    }
+
+    /// Records which agent is on the other end once it identifies itself.
+    pub async fn set_agent(&self, id: &str, agent_name: &str, kind: Option<ConnectionKind>) {
+        if let Some(connection) = self.connections.lock().await.get_mut(id) {
+            connection.agent_name = Some(agent_name.to_string());
+            if let Some(kind) = kind {
+                connection.kind = kind;
+            }
+        }
+    }
+
+    /// Records bytes received of a message of `kind` (see `Message::kind`); `kind` is `None` for
+    /// acknowledgments.
+    pub async fn record_in(&self, id: &str, bytes: usize, kind: Option<&str>) {
+        if let Some(connection) = self.connections.lock().await.get_mut(id) {
+            connection.bytes_in += bytes as i64;
+            if let Some(kind) = kind {
+                connection.messages_in += 1;
+                connection.last_message = Some(kind.to_string());
+                connection.last_message_at = Some(DateTime::now());
+            }
+        }
+    }
+
+    /// Records bytes sent; `message` is `None` for acknowledgments.
+    pub async fn record_out(&self, id: &str, bytes: usize, message: Option<&Message>) {
+        if let Some(connection) = self.connections.lock().await.get_mut(id) {
+            connection.bytes_out += bytes as i64;
+            if let Some(message) = message {
+                connection.messages_out += 1;
+                connection.last_message = Some(message.kind().to_string());
+                connection.last_message_at = Some(DateTime::now());
+            }
+        }
+    }
+
+    /// Records a frame from the peer that could not be used.
+    pub async fn record_protocol_error(&self, id: &str, error: &ProtocolError) {
+        if let Some(connection) = self.connections.lock().await.get_mut(id) {
+            connection.protocol_errors += 1;
+            connection.last_protocol_error = Some(error.to_string());
+        }
+    }
+
+    /// Replaces this instance's connections in the `connections` collection with the current
+    /// ones.
+    pub async fn publish(&self, datastore: Arc<Datastore>) -> Result<(), Box<dyn Error>> {
+        let snapshot: Vec<ConnectionV1> = self.connections.lock().await.values().cloned().collect();
+        let collection = datastore
+            .get_collection::<ConnectionV1>("connections")
+            .await?;
+        // Snapshots written before instances were told apart have no `central_command`.
+        collection
+            .delete_many(doc! { "central_command": { "$in": [&self.central_command, Bson::Null] } })
+            .await?;
+        if !snapshot.is_empty() {
+            collection.insert_many(snapshot).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+/// Limits on how fast the `AgentManager` dispatches runs, so a misconfigured schedule cannot
+/// overwhelm a host or the fleet with jobs.
+///
+/// # Overview
+/// - Each limit is a token bucket refilled continuously at its rate and holding at most a
+///   minute's worth of tokens, so a burst of up to the rate is let through at once.
+/// - `DISPATCH_RATE_LIMIT_PER_MINUTE` limits the dispatches of the instance as a whole,
+///   `JOB_DISPATCH_RATE_LIMIT_PER_MINUTE` those of each job and
+///   `AGENT_DISPATCH_RATE_LIMIT_PER_MINUTE` those to each agent.
+/// - A run is dispatched only when every limit has a token for it. An agent held back stays in
+///   the cycle's `agents_pending` and is dispatched to on a later pass of the dispatch loop.
+/// - When agents are partitioned, each instance applies the limits to its own dispatches. Runs
+///   claimed by agents that pull their jobs are bounded by the agent's poll instead.
+///
+/// A limit of `0` disables it.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(per_minute: u32, now: Instant) -> Self {
+        Self {
+            tokens: per_minute as f64,
+            refilled_at: now,
+        }
+    }
+
+    /// Adds the tokens earned since the last refill, up to a minute's worth.
+    fn refill(&mut self, per_minute: u32, now: Instant) {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        let earned = elapsed / RATE_WINDOW.as_secs_f64() * per_minute as f64;
+        self.tokens = (self.tokens + earned).min(per_minute as f64);
+        self.refilled_at = now;
+    }
+
+    fn is_full(&self, per_minute: u32) -> bool {
+        self.tokens >= per_minute as f64
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    global: Option<TokenBucket>,
+    jobs: HashMap<String, TokenBucket>,
+    agents: HashMap<String, TokenBucket>,
+}
+
+#[derive(Debug)]
+pub struct DispatchLimiter {
+    per_minute: u32,
+    per_job_per_minute: u32,
+    per_agent_per_minute: u32,
+    buckets: Mutex<Buckets>,
+}
+
+impl DispatchLimiter {
+    pub fn new(per_minute: u32, per_job_per_minute: u32, per_agent_per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            per_job_per_minute,
+            per_agent_per_minute,
+            buckets: Mutex::new(Buckets::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.per_minute > 0 || self.per_job_per_minute > 0 || self.per_agent_per_minute > 0
+    }
+
+    /// Admits a dispatch of `job_name` to `agent_name`, taking a token from every limit, or
+    /// explains which limit holds it back. Nothing is taken from a dispatch held back.
+    pub fn admit(&self, job_name: &str, agent_name: &str) -> Result<(), String> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let Buckets {
+            global,
+            jobs,
+            agents,
+        } = &mut *buckets;
+        // Forget buckets that filled up again, so the maps do not grow with every job and agent.
+        Self::forget_full(jobs, self.per_job_per_minute, now);
+        Self::forget_full(agents, self.per_agent_per_minute, now);
+
+        let global = global.get_or_insert_with(|| TokenBucket::full(self.per_minute, now));
+        global.refill(self.per_minute, now);
+        let job = jobs
+            .entry(job_name.to_string())
+            .or_insert_with(|| TokenBucket::full(self.per_job_per_minute, now));
+        let agent = agents
+            .entry(agent_name.to_string())
+            .or_insert_with(|| TokenBucket::full(self.per_agent_per_minute, now));
+
+        let limits = [
+            (global, self.per_minute, "DISPATCH_RATE_LIMIT_PER_MINUTE"),
+            (
+                job,
+                self.per_job_per_minute,
+                "JOB_DISPATCH_RATE_LIMIT_PER_MINUTE",
+            ),
+            (
+                agent,
+                self.per_agent_per_minute,
+                "AGENT_DISPATCH_RATE_LIMIT_PER_MINUTE",
+            ),
+        ];
+        if let Some((_, per_minute, name)) = limits
+            .iter()
+            .find(|(bucket, per_minute, _)| *per_minute > 0 && bucket.tokens < 1.0)
+        {
+            return Err(format!(
+                "more than {} dispatches a minute ({})",
+                per_minute, name
+            ));
+        }
+        for (bucket, per_minute, _) in limits {
+            if per_minute > 0 {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Refills `buckets`, dropping those that are full, as a new bucket would be.
+    fn forget_full(buckets: &mut HashMap<String, TokenBucket>, per_minute: u32, now: Instant) {
+        buckets.retain(|_, bucket| {
+            bucket.refill(per_minute, now);
+            per_minute > 0 && !bucket.is_full(per_minute)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refill_earns_tokens_at_the_rate_up_to_a_minute_s_worth() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(60, start);
+        bucket.tokens = 0.0;
+        bucket.refill(60, start + Duration::from_secs(30));
+        assert_eq!(bucket.tokens, 30.0);
+        assert!(!bucket.is_full(60));
+        bucket.refill(60, start + Duration::from_secs(600));
+        assert_eq!(bucket.tokens, 60.0);
+        assert!(bucket.is_full(60));
+    }
+
+    #[test]
+    fn admit_lets_everything_through_when_disabled() {
+        let limiter = DispatchLimiter::new(0, 0, 0);
+        assert!(!limiter.is_enabled());
+        for _ in 0..1000 {
+            assert!(limiter.admit("job", "agent").is_ok());
+        }
+    }
+
+    #[test]
+    fn admit_holds_back_past_the_global_burst() {
+        let limiter = DispatchLimiter::new(3, 0, 0);
+        for agent in ["a", "b", "c"] {
+            assert!(limiter.admit("job", agent).is_ok());
+        }
+        let held_back = limiter.admit("job", "d").unwrap_err();
+        assert!(held_back.contains("DISPATCH_RATE_LIMIT_PER_MINUTE"));
+    }
+
+    #[test]
+    fn admit_limits_each_job_and_agent_separately() {
+        let limiter = DispatchLimiter::new(0, 1, 2);
+        assert!(limiter.admit("backup", "a").is_ok());
+        let held_back = limiter.admit("backup", "b").unwrap_err();
+        assert!(held_back.contains("JOB_DISPATCH_RATE_LIMIT_PER_MINUTE"));
+        assert!(limiter.admit("report", "a").is_ok());
+        let held_back = limiter.admit("cleanup", "a").unwrap_err();
+        assert!(held_back.contains("AGENT_DISPATCH_RATE_LIMIT_PER_MINUTE"));
+    }
+
+    #[test]
+    fn admit_takes_nothing_from_a_dispatch_held_back() {
+        let limiter = DispatchLimiter::new(2, 1, 0);
+        assert!(limiter.admit("backup", "a").is_ok());
+        // Held back by its job, so the global token it would have taken is left for another.
+        assert!(limiter.admit("backup", "b").is_err());
+        assert!(limiter.admit("report", "b").is_ok());
+        assert!(limiter.admit("cleanup", "c").is_err());
+    }
+}
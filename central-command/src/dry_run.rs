@@ -0,0 +1,205 @@
+/// Central command's dry-run mode, enabled with `--dry-run` or `DRY_RUN=true`, for checking
+/// schedules, agent selectors and platform filters before going live.
+///
+/// # Overview
+/// - On each pass of the dispatch loop the `AgentManager` hands the connected agents to the
+///   `DispatchPlanner` instead of dispatching. It finds the due runs as dispatching would: pending
+///   jobs whose `next_run` passed and that target a connected agent, selected and assigned agents
+///   by the `SchedulerStrategy`.
+/// - Each run is logged and recorded as a `DispatchPlanV1`, with the agents it would run on and
+///   those of them it would be dispatched to now. Runs of jobs that require approval, and runs in
+///   a blackout window, are recorded as held.
+/// - Nothing is sent to agents and jobs are left as they are, so their `next_run` does not move
+///   on. The planner follows the schedule of recurring jobs itself, planning each scheduled run
+///   once as it comes due; one-shot and unscheduled jobs are planned once.
+/// - Agents are still dialed and pinged, so the plan reflects which are connected. Misfire
+///   policies, SLA warnings, agent updates, cancellations and timeout extensions are left alone,
+///   and the instance takes part in neither leader election nor agent partitioning, so it never
+///   keeps another instance from dispatching.
+use bson::{DateTime, doc, oid::ObjectId};
+use futures::stream::TryStreamExt;
+use tracing::info;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::agent_cache::AgentCache;
+use crate::agent_manager::AgentManager;
+use crate::get_central_command_id;
+use crate::scheduler::SchedulerStrategy;
+use core_logic::datastore::{
+    Datastore,
+    agent_groups::AgentGroupV1,
+    blackout_windows::BlackoutWindowV1,
+    dispatch_plans::DispatchPlanV1,
+    jobs::{EXECUTOR_AGENTS, JobV1, Status, next_run_datetime},
+};
+
+#[derive(Debug)]
+pub struct DispatchPlanner {
+    datastore: Arc<Datastore>,
+    agent_cache: AgentCache,
+    scheduler: Arc<dyn SchedulerStrategy>,
+    planned: HashMap<ObjectId, i64>, // The next run to plan of each job planned, Unix seconds
+}
+
+impl DispatchPlanner {
+    pub fn new(
+        datastore: Arc<Datastore>,
+        agent_cache: AgentCache,
+        scheduler: Arc<dyn SchedulerStrategy>,
+    ) -> Self {
+        Self {
+            datastore,
+            agent_cache,
+            scheduler,
+            planned: HashMap::new(),
+        }
+    }
+
+    /// The job's run the planner has yet to plan, Unix seconds.
+    fn scheduled_run(&self, job: &JobV1) -> i64 {
+        match job.id.and_then(|id| self.planned.get(&id)) {
+            Some(planned) => (*planned).max(job.next_run),
+            None => job.next_run,
+        }
+    }
+
+    /// Plans the runs due now on `connected_agents`, returning how many were recorded.
+    pub async fn plan(&mut self, connected_agents: &[String]) -> Result<usize, Box<dyn Error>> {
+        let now = DateTime::now();
+        let timestamp = now.timestamp_millis() / 1000;
+        let collection = self.datastore.get_collection::<JobV1>("jobs").await?;
+        let connected_groups =
+            AgentGroupV1::names_with_members(&self.datastore, connected_agents).await?;
+        let mut targets = vec![
+            doc! { "agents_required": { "$in": connected_agents } },
+            doc! { "agent_groups": { "$in": connected_groups } },
+        ];
+        for (kind, agent) in EXECUTOR_AGENTS {
+            if connected_agents.iter().any(|connected| connected == agent) {
+                targets.push(doc! { "executor.kind": kind });
+            }
+        }
+        let filter = doc! {
+            "status": Status::Pending,
+            "next_run": { "$lt": next_run_datetime(timestamp) },
+            "agents_running": [],
+            "$or": targets,
+        };
+        let pending: Vec<JobV1> = collection
+            .find(filter)
+            .sort(doc! { "next_run": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        let blackouts = BlackoutWindowV1::active(&self.datastore, now).await?;
+
+        let mut recorded = 0;
+        let mut runnable = vec![];
+        for mut job in pending {
+            job.next_run = self.scheduled_run(&job);
+            if job.next_run >= timestamp {
+                continue; // Planned already, and the next scheduled run is not due yet
+            }
+            let held_by = match blackouts
+                .iter()
+                .find(|window| window.holds_back(&job.name, now))
+            {
+                Some(window) => Some(window.name.clone()),
+                None if job.requires_approval && job.approval.is_none() => {
+                    Some("approval".to_string())
+                }
+                None => None,
+            };
+            if held_by.is_some() {
+                recorded += self.record(&job, vec![], vec![], held_by).await? as usize;
+                continue;
+            }
+            if !job.platforms.is_empty()
+                || !job.node_selector.is_empty()
+                || job.namespace.is_some()
+                || job.executor_agent().is_some()
+            {
+                let candidates = AgentManager::cycle_candidates(
+                    &self.datastore,
+                    &self.agent_cache,
+                    &job,
+                    connected_agents,
+                )
+                .await?;
+                if !candidates
+                    .iter()
+                    .any(|agent| connected_agents.contains(agent))
+                {
+                    continue;
+                }
+            }
+            runnable.push(job);
+        }
+
+        for job in self.scheduler.select_jobs(runnable, connected_agents) {
+            let candidates = AgentManager::cycle_candidates(
+                &self.datastore,
+                &self.agent_cache,
+                &job,
+                connected_agents,
+            )
+            .await?;
+            let mut agents = match &job.rerun {
+                Some(rerun) => vec![rerun.agent_name.clone()],
+                None => self.scheduler.assign_agents(&job, candidates),
+            };
+            if agents.is_empty() {
+                agents = job.agents_required.clone();
+            }
+            let dispatched_to = agents
+                .iter()
+                .filter(|agent| connected_agents.contains(agent))
+                .cloned()
+                .collect();
+            recorded += self.record(&job, agents, dispatched_to, None).await? as usize;
+        }
+        Ok(recorded)
+    }
+
+    /// Records the plan for the job's run at `next_run` and moves on to its next scheduled run.
+    async fn record(
+        &mut self,
+        job: &JobV1,
+        agents: Vec<String>,
+        dispatched_to: Vec<String>,
+        held_by: Option<String>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let plan = DispatchPlanV1 {
+            id: None,
+            job_name: job.name.clone(),
+            namespace: job.namespace.clone(),
+            scheduled_at: DateTime::from_millis(job.next_run * 1000),
+            planned_at: DateTime::now(),
+            central_command_id: get_central_command_id().to_string(),
+            agents,
+            dispatched_to,
+            held_by,
+        };
+        let recorded = plan.record(&self.datastore).await?;
+        if recorded {
+            match &plan.held_by {
+                Some(held_by) => info!(
+                    "Dry run: run of {} scheduled at {} would be held by {}",
+                    plan.job_name, plan.scheduled_at, held_by
+                ),
+                None => info!(
+                    "Dry run: run of {} scheduled at {} would be dispatched to {:?} of {:?}",
+                    plan.job_name, plan.scheduled_at, plan.dispatched_to, plan.agents
+                ),
+            }
+        }
+        if let Some(id) = job.id {
+            let next = job.next_run_after(job.next_run).unwrap_or(i64::MAX);
+            self.planned.insert(id, next);
+        }
+        Ok(recorded)
+    }
+}
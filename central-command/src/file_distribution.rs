@@ -0,0 +1,325 @@
+/// File distribution pushes the files a job needs to its agents ahead of the job's dispatch, so
+/// script-based jobs run without provisioning the scripts on every agent first.
+///
+/// # Overview
+/// - `PushedFile::load` reads a `JobFile`'s contents from its source: the upload stored with the
+///   job, the blob store under `job_files/<name>` or a URL, checked against its `sha256` when one
+///   is set. Contents are read again for every cycle, so a changed stored file or URL is picked up.
+/// - `PushedFile::chunks` splits a file into `FileChunk` messages small enough for one read on the
+///   agent's listen port, which the agent reassembles at the file's destination.
+/// - `PushedFile::digest` is listed in the `DispatchJob`, and the agent fails the run if the file
+///   it holds does not match.
+///
+/// # Notes
+/// - Files are sent to agents over the same connection or channel as their dispatches, ahead of
+///   them. gRPC dispatch streams only carry dispatches, so gRPC clients do not receive files.
+/// - Files are limited to `MAX_FILE_BYTES`, as every chunk is held in memory while it is sent.
+///   Downloads stop once they pass it.
+/// - URLs are downloaded over http or https without following redirects. Unless
+///   `FILE_DOWNLOAD_HOSTS` is set, their hosts must resolve to public addresses only, so jobs
+///   cannot reach loopback, private, link-local (cloud metadata) or other internal addresses.
+///
+/// # Configuration
+/// - `FILE_DOWNLOAD_HOSTS`: Comma separated hosts URL files may be downloaded from, whatever
+///   addresses they resolve to (default: any host resolving to public addresses only).
+use base64::Engine;
+use reqwest::Url;
+use reqwest::redirect::Policy;
+use sha2::{Digest, Sha256};
+
+use std::env;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use core_logic::datastore::Datastore;
+use core_logic::datastore::jobs::{JOB_FILES_BUCKET, JobFile, JobFileSource};
+use core_logic::messages::{FileChunk, FileDigest, Message};
+
+/// Largest file pushed to agents.
+const MAX_FILE_BYTES: usize = 64 * 1024 * 1024;
+/// Bytes of file contents per `FileChunk`, well inside the agent's 64 KiB reads.
+const CHUNK_BYTES: usize = 32 * 1024;
+/// Time allowed to download a file from its URL.
+const DOWNLOAD_TIMEOUT_SECONDS: u64 = 60;
+
+static FILE_DOWNLOAD_HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+
+fn get_file_download_hosts() -> &'static [String] {
+    FILE_DOWNLOAD_HOSTS.get_or_init(|| {
+        env::var("FILE_DOWNLOAD_HOSTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|host| host.trim().to_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect()
+    })
+}
+
+/// Downloads `url`, failing once it passes `MAX_FILE_BYTES`. The connection goes to the address
+/// checked by `download_address`, so the host cannot resolve elsewhere in between.
+async fn download(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let parsed = Url::parse(url)?;
+    let address = download_address(&parsed, get_file_download_hosts()).await?;
+    let mut client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(DOWNLOAD_TIMEOUT_SECONDS))
+        .redirect(Policy::none());
+    if let Some(host) = parsed.host_str() {
+        client = client.resolve(host, address);
+    }
+    let mut response = client
+        .build()?
+        .get(parsed)
+        .send()
+        .await?
+        .error_for_status()?;
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_FILE_BYTES as u64)
+    {
+        return Err(format!("{} is larger than {} bytes", url, MAX_FILE_BYTES).into());
+    }
+    let mut contents = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        if contents.len() + chunk.len() > MAX_FILE_BYTES {
+            return Err(format!("{} is larger than {} bytes", url, MAX_FILE_BYTES).into());
+        }
+        contents.extend_from_slice(&chunk);
+    }
+    Ok(contents)
+}
+
+/// The address to download `url` from. Only http and https are allowed. Hosts in `allowed_hosts`
+/// may resolve anywhere, any other host only to public addresses.
+async fn download_address(url: &Url, allowed_hosts: &[String]) -> Result<SocketAddr, String> {
+    check_scheme(url)?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("File URL {} has no host", url))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| format!("File URL {} has no port", url))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .collect();
+    if !allowed_hosts.is_empty() {
+        if !allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+        {
+            return Err(format!("{} is not in FILE_DOWNLOAD_HOSTS", host));
+        }
+    } else if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+        return Err(format!(
+            "{} resolves to non-public address {}, see FILE_DOWNLOAD_HOSTS",
+            host,
+            address.ip()
+        ));
+    }
+    addresses
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("{} has no addresses", host))
+}
+
+fn check_scheme(url: &Url) -> Result<(), String> {
+    match url.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => Err(format!(
+            "File URL {} must be http or https, not {}",
+            url, scheme
+        )),
+    }
+}
+
+/// Whether `ip` is reachable on the internet, rather than loopback, private, link-local, shared,
+/// documentation, multicast or otherwise reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || first == 0
+                || first >= 240
+                || (first == 100 && (64..128).contains(&second)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.segments()[0] == 0x2001 && ip.segments()[1] == 0xdb8)
+            }
+        },
+    }
+}
+
+/// A job's file with its contents, ready to push to agents.
+#[derive(Debug)]
+pub struct PushedFile {
+    destination: String,
+    mode: Option<u32>,
+    contents: Vec<u8>,
+    sha256: String,
+}
+
+impl PushedFile {
+    /// Reads the contents of `file` from its source.
+    pub async fn load(datastore: &Datastore, file: &JobFile) -> Result<Self, Box<dyn Error>> {
+        let contents = match &file.source {
+            JobFileSource::Upload { content } => {
+                base64::engine::general_purpose::STANDARD.decode(content)?
+            }
+            JobFileSource::GridFs { name } => {
+                datastore
+                    .blob_store()
+                    .get(format!("{}/{}", JOB_FILES_BUCKET, name))
+                    .await?
+            }
+            JobFileSource::Url { url, sha256 } => {
+                let contents = download(url).await?;
+                if let Some(expected) = sha256 {
+                    let actual = hex::encode(Sha256::digest(&contents));
+                    if !actual.eq_ignore_ascii_case(expected) {
+                        return Err(format!(
+                            "Checksum mismatch for {}: expected {}, got {}",
+                            url, expected, actual
+                        )
+                        .into());
+                    }
+                }
+                contents
+            }
+        };
+        if contents.len() > MAX_FILE_BYTES {
+            return Err(format!(
+                "{} is larger than {} bytes",
+                file.destination, MAX_FILE_BYTES
+            )
+            .into());
+        }
+        Ok(Self {
+            destination: file.destination.clone(),
+            mode: file.mode_bits()?,
+            sha256: hex::encode(Sha256::digest(&contents)),
+            contents,
+        })
+    }
+
+    /// Reads the contents of every one of `files`, failing with the first file that cannot be read.
+    pub async fn load_all(datastore: &Datastore, files: &[JobFile]) -> Result<Vec<Self>, String> {
+        let mut loaded = Vec::with_capacity(files.len());
+        for file in files {
+            let pushed = Self::load(datastore, file)
+                .await
+                .map_err(|e| format!("Failed to read file {}: {}", file, e))?;
+            loaded.push(pushed);
+        }
+        Ok(loaded)
+    }
+
+    /// The `FileChunk`s that write the file on an agent, in the order they must be sent. An empty
+    /// file is a single empty chunk.
+    pub fn chunks(&self, job_name: &str) -> Vec<Message> {
+        let mut offset = 0;
+        let mut chunks = vec![];
+        for data in self.contents.chunks(CHUNK_BYTES) {
+            chunks.push(self.chunk(job_name, offset, data));
+            offset += data.len() as u64;
+        }
+        if chunks.is_empty() {
+            chunks.push(self.chunk(job_name, 0, &[]));
+        }
+        chunks
+    }
+
+    fn chunk(&self, job_name: &str, offset: u64, data: &[u8]) -> Message {
+        Message::FileChunk(FileChunk {
+            job_name: job_name.to_string(),
+            destination: self.destination.clone(),
+            mode: self.mode,
+            size: self.contents.len() as u64,
+            sha256: self.sha256.clone(),
+            offset,
+            data: data.to_vec(),
+        })
+    }
+
+    pub fn digest(&self) -> FileDigest {
+        FileDigest {
+            destination: self.destination.clone(),
+            sha256: self.sha256.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_public_rejects_internal_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["8.8.8.8", "100.128.0.1", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn download_address_checks_scheme_and_hosts() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        assert!(
+            download_address(&url("file:///etc/passwd"), &[])
+                .await
+                .is_err()
+        );
+        assert!(
+            download_address(&url("http://169.254.169.254/latest"), &[])
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            download_address(&url("https://8.8.8.8/script.sh"), &[])
+                .await
+                .unwrap(),
+            "8.8.8.8:443".parse::<SocketAddr>().unwrap()
+        );
+        let allowed = vec!["127.0.0.1".to_string()];
+        assert!(
+            download_address(&url("http://127.0.0.1:9000/a"), &allowed)
+                .await
+                .is_ok()
+        );
+        assert!(
+            download_address(&url("https://8.8.8.8/a"), &allowed)
+                .await
+                .is_err()
+        );
+    }
+}
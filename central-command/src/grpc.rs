@@ -0,0 +1,408 @@
+/// The `grpc` module exposes an optional tonic-based gRPC service so non-Rust clients can act as
+/// agents. It mirrors the register / dispatch / complete semantics of the rkyv TCP protocol, which
+/// remains the transport used by the Rust agent.
+///
+/// # Overview
+/// - `Register`, `Ping`, `Complete` and `Progress` are converted into `Message`s and handled exactly like
+///   messages received by the `CommandReceiver`.
+/// - `Dispatches` opens a server stream for an agent. While the stream is open the agent is
+///   registered in `AgentChannels`, so the `AgentManager` pushes jobs to it instead of dialing
+///   its port.
+/// - When agents are authenticated (`AGENT_AUTH`, see `auth`), every call carries the agent's
+///   credential as `authorization: Bearer <credential>` metadata and is rejected with
+///   `UNAUTHENTICATED` unless it belongs to the agent the call names.
+///
+/// # Configuration
+/// The service is compiled with the `grpc` feature and started when `GRPC_ADDRESS` is set,
+/// e.g. `GRPC_ADDRESS=0.0.0.0:50051`.
+use tokio::spawn;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status, transport::Server};
+use tracing::{debug, error, info};
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::agent_channels::AgentChannels;
+use crate::agent_manager::AgentManager;
+use crate::auth::{self, AgentAuthenticator};
+use crate::command_receiver::CommandReceiver;
+use core_logic::datastore::Datastore;
+use core_logic::datastore::agents::normalize_arch;
+use core_logic::messages::{
+    ContainerInfo, DispatchJob, JobComplete, JobOutCome, JobProgress, JobStep, Message,
+    RegisterAgent, StepResult, TriggeredBy,
+};
+
+pub mod proto {
+    tonic::include_proto!("rust_action_dispatch");
+}
+
+use proto::agent_service_server::{AgentService, AgentServiceServer};
+
+const DISPATCH_CHANNEL_CAPACITY: usize = 100;
+
+impl From<TriggeredBy> for proto::TriggeredBy {
+    fn from(triggered_by: TriggeredBy) -> Self {
+        let (kind, source) = match triggered_by {
+            TriggeredBy::Scheduler => ("scheduler", String::new()),
+            TriggeredBy::User(name) => ("user", name),
+            TriggeredBy::ApiToken(name) => ("api_token", name),
+            TriggeredBy::Webhook(name) => ("webhook", name),
+            TriggeredBy::Retry(run_id) => ("retry", run_id),
+            TriggeredBy::Hook(hook) => ("hook", hook),
+        };
+        Self {
+            kind: kind.to_string(),
+            source,
+        }
+    }
+}
+
+impl From<proto::TriggeredBy> for TriggeredBy {
+    fn from(triggered_by: proto::TriggeredBy) -> Self {
+        match triggered_by.kind.as_str() {
+            "user" => TriggeredBy::User(triggered_by.source),
+            "api_token" => TriggeredBy::ApiToken(triggered_by.source),
+            "webhook" => TriggeredBy::Webhook(triggered_by.source),
+            "retry" => TriggeredBy::Retry(triggered_by.source),
+            "hook" => TriggeredBy::Hook(triggered_by.source),
+            _ => TriggeredBy::Scheduler,
+        }
+    }
+}
+
+impl From<DispatchJob> for proto::DispatchJob {
+    fn from(job: DispatchJob) -> Self {
+        Self {
+            job_name: job.job_name,
+            command: job.command,
+            args: job.args,
+            valid_return_codes: job.valid_return_codes.unwrap_or_default(),
+            timeout: job.timeout,
+            triggered_by: Some(job.triggered_by.into()),
+            redact_patterns: job.redact_patterns,
+            run_id: job.run_id,
+            steps: job.steps.into_iter().map(Into::into).collect(),
+            script: job.script.map(|script| proto::JobScript {
+                interpreter: script.interpreter,
+                body: script.body,
+            }),
+            container: job.container.map(|container| proto::JobContainer {
+                image: container.image,
+                mounts: container.mounts,
+                env: container.env,
+                workdir: container.workdir,
+            }),
+            env: job.env,
+        }
+    }
+}
+
+impl From<JobStep> for proto::JobStep {
+    fn from(step: JobStep) -> Self {
+        Self {
+            name: step.name,
+            command: step.command,
+            args: step.args,
+            cwd: step.cwd,
+            env: step.env,
+            continue_on_error: step.continue_on_error,
+        }
+    }
+}
+
+impl From<proto::Outcome> for JobOutCome {
+    fn from(outcome: proto::Outcome) -> Self {
+        match outcome {
+            proto::Outcome::Failure => JobOutCome::Failure,
+            proto::Outcome::Success => JobOutCome::Success,
+            proto::Outcome::Unknown => JobOutCome::Unknown,
+            proto::Outcome::TimedOut => JobOutCome::TimedOut,
+            proto::Outcome::Cancelled => JobOutCome::Cancelled,
+            proto::Outcome::Skipped => JobOutCome::Skipped,
+            proto::Outcome::DispatchFailed => JobOutCome::DispatchFailed,
+        }
+    }
+}
+
+impl From<proto::StepResult> for StepResult {
+    fn from(step: proto::StepResult) -> Self {
+        let outcome = step.outcome().into();
+        Self {
+            name: step.name,
+            command: step.command,
+            started_at: step.started_at,
+            completed_at: step.completed_at,
+            return_code: step.return_code,
+            outcome,
+            output: step.output,
+            truncated: step.truncated,
+            artifact: step.artifact,
+        }
+    }
+}
+
+impl From<proto::JobComplete> for JobComplete {
+    fn from(job_complete: proto::JobComplete) -> Self {
+        let outcome = job_complete.outcome().into();
+        Self {
+            started_at: job_complete.started_at,
+            completed_at: job_complete.completed_at,
+            job_name: job_complete.job_name,
+            command: job_complete.command,
+            agent_name: job_complete.agent_name,
+            return_code: job_complete.return_code,
+            outcome,
+            output: job_complete.output,
+            triggered_by: job_complete
+                .triggered_by
+                .map(Into::into)
+                .unwrap_or_default(),
+            truncated: job_complete.truncated,
+            artifact: job_complete.artifact,
+            signature: job_complete.signature,
+            run_id: job_complete.run_id,
+            steps: job_complete.steps.into_iter().map(Into::into).collect(),
+            timeout_extension: job_complete.timeout_extension,
+        }
+    }
+}
+
+impl From<proto::JobProgress> for JobProgress {
+    fn from(job_progress: proto::JobProgress) -> Self {
+        Self {
+            job_name: job_progress.job_name,
+            agent_name: job_progress.agent_name,
+            run_id: job_progress.run_id,
+            elapsed: job_progress.elapsed,
+            timeout: job_progress.timeout,
+        }
+    }
+}
+
+pub struct GrpcAgentService {
+    datastore: Arc<Datastore>,
+    agent_channels: AgentChannels,
+    authenticator: Option<Arc<dyn AgentAuthenticator>>,
+}
+
+impl GrpcAgentService {
+    pub fn new(
+        datastore: Arc<Datastore>,
+        agent_channels: AgentChannels,
+        authenticator: Option<Arc<dyn AgentAuthenticator>>,
+    ) -> Self {
+        Self {
+            datastore,
+            agent_channels,
+            authenticator,
+        }
+    }
+
+    /// Serves the gRPC service on `address` until an error occurs.
+    pub async fn serve(self, address: SocketAddr) -> Result<(), tonic::transport::Error> {
+        info!("gRPC transport listening on {}", address);
+        Server::builder()
+            .add_service(AgentServiceServer::new(self))
+            .serve(address)
+            .await
+    }
+
+    /// Authenticates a call made for `agent_name` with the credential in its metadata, returning
+    /// the namespace of the credential. Calls pass unchecked when agents are not authenticated.
+    async fn authenticate(
+        &self,
+        metadata: &MetadataMap,
+        agent_name: &str,
+    ) -> Result<Option<String>, Status> {
+        let Some(authenticator) = &self.authenticator else {
+            return Ok(None);
+        };
+        let bearer = metadata
+            .get("authorization")
+            .and_then(|authorization| authorization.to_str().ok())
+            .and_then(|authorization| authorization.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, credential)| credential.trim().to_string())
+            .ok_or_else(|| Status::unauthenticated("Agent credential is required"))?;
+        let credential = authenticator.bearer_credential(bearer);
+        auth::verify(
+            authenticator.as_ref(),
+            &self.datastore,
+            agent_name,
+            &credential,
+        )
+        .await
+        .map_err(|e| Status::unauthenticated(format!("Agent {}", e)))
+    }
+
+    async fn handle(
+        &self,
+        message: Message,
+        peer_addr: SocketAddr,
+        namespace: Option<&str>,
+    ) -> Result<Response<proto::Ack>, Status> {
+        CommandReceiver::handle_message(message, self.datastore.clone(), peer_addr, namespace)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(proto::Ack {
+            ok: true,
+            message: "OK".to_string(),
+        }))
+    }
+
+    fn peer_addr<T>(request: &Request<T>) -> SocketAddr {
+        request
+            .remote_addr()
+            .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)))
+    }
+}
+
+#[tonic::async_trait]
+impl AgentService for GrpcAgentService {
+    async fn register(
+        &self,
+        request: Request<proto::RegisterAgent>,
+    ) -> Result<Response<proto::Ack>, Status> {
+        let peer_addr = Self::peer_addr(&request);
+        let namespace = self
+            .authenticate(request.metadata(), &request.get_ref().name)
+            .await?;
+        let register = request.into_inner();
+        let port = u16::try_from(register.port)
+            .map_err(|_| Status::invalid_argument("port must fit in 16 bits"))?;
+        let message = Message::RegisterAgent(RegisterAgent {
+            name: register.name,
+            hostname: register.hostname,
+            port,
+            version: register.version,
+            timezone: register.timezone,
+            locale: register.locale,
+            receipt_public_key: register.receipt_public_key,
+            os: register.os,
+            arch: normalize_arch(&register.arch).to_string(),
+            kernel: register.kernel,
+            shells: register.shells,
+            features: register.features,
+            container: register.container.map(|container| ContainerInfo {
+                runtime: container.runtime,
+                node_name: container.node_name,
+                pod_name: container.pod_name,
+                pod_namespace: container.pod_namespace,
+                node_labels: container.node_labels,
+            }),
+        });
+        self.handle(message, peer_addr, namespace.as_deref()).await
+    }
+
+    async fn ping(
+        &self,
+        request: Request<proto::PingRequest>,
+    ) -> Result<Response<proto::Ack>, Status> {
+        let peer_addr = Self::peer_addr(&request);
+        let namespace = self
+            .authenticate(request.metadata(), &request.get_ref().agent_name)
+            .await?;
+        let agent_name = request.into_inner().agent_name;
+        AgentManager::update_agent_online(self.datastore.clone(), &agent_name)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        self.handle(Message::Ping, peer_addr, namespace.as_deref())
+            .await
+    }
+
+    type DispatchesStream = ReceiverStream<Result<proto::DispatchJob, Status>>;
+
+    async fn dispatches(
+        &self,
+        request: Request<proto::DispatchStreamRequest>,
+    ) -> Result<Response<Self::DispatchesStream>, Status> {
+        let peer_addr = Self::peer_addr(&request);
+        self.authenticate(request.metadata(), &request.get_ref().agent_name)
+            .await?;
+        let agent_name = request.into_inner().agent_name;
+        if agent_name.is_empty() {
+            return Err(Status::invalid_argument("agent_name is required"));
+        }
+
+        let (sender, mut receiver) = mpsc::channel::<Message>(DISPATCH_CHANNEL_CAPACITY);
+        let (stream_sender, stream_receiver) = mpsc::channel(DISPATCH_CHANNEL_CAPACITY);
+        self.agent_channels.register(&agent_name, sender).await;
+        if let Err(e) = AgentManager::update_agent_online(self.datastore.clone(), &agent_name).await
+        {
+            error!("Failed to update agent {} to online: {}", agent_name, e);
+        }
+        info!(
+            "Agent {} opened a gRPC dispatch stream from {}",
+            agent_name, peer_addr
+        );
+
+        let agent_channels = self.agent_channels.clone();
+        let datastore = self.datastore.clone();
+        spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = stream_sender.closed() => break,
+                    message = receiver.recv() => match message {
+                        Some(Message::DispatchJob(job)) => {
+                            if stream_sender.send(Ok(job.into())).await.is_err() {
+                                break;
+                            }
+                        }
+                        Some(message) => {
+                            debug!("Message {:?} is not supported over gRPC", message);
+                        }
+                        None => break,
+                    },
+                }
+            }
+            info!("gRPC dispatch stream for agent {} closed", agent_name);
+            drop(receiver); // Close the channel so it is pruned
+            agent_channels.prune(&agent_name).await;
+            if !agent_channels.contains(&agent_name).await
+                && let Err(e) = AgentManager::update_agent_offline(datastore, &agent_name).await
+            {
+                error!("Failed to update agent {} to offline: {}", agent_name, e);
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(stream_receiver)))
+    }
+
+    async fn complete(
+        &self,
+        request: Request<proto::JobComplete>,
+    ) -> Result<Response<proto::Ack>, Status> {
+        let peer_addr = Self::peer_addr(&request);
+        let namespace = self
+            .authenticate(request.metadata(), &request.get_ref().agent_name)
+            .await?;
+        let job_complete: JobComplete = request.into_inner().into();
+        self.handle(
+            Message::JobComplete(job_complete),
+            peer_addr,
+            namespace.as_deref(),
+        )
+        .await
+    }
+
+    async fn progress(
+        &self,
+        request: Request<proto::JobProgress>,
+    ) -> Result<Response<proto::Ack>, Status> {
+        let peer_addr = Self::peer_addr(&request);
+        let namespace = self
+            .authenticate(request.metadata(), &request.get_ref().agent_name)
+            .await?;
+        let job_progress: JobProgress = request.into_inner().into();
+        self.handle(
+            Message::JobProgress(job_progress),
+            peer_addr,
+            namespace.as_deref(),
+        )
+        .await
+    }
+}
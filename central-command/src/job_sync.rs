@@ -0,0 +1,621 @@
+/// `JobSync` reconciles the `jobs` collection against job definitions kept in YAML files, so an
+/// environment's jobs can be reproduced from version-controlled configuration.
+///
+/// # Overview
+/// - Every `*.yaml` / `*.yml` file in `JOBS_DIR` holds a `jobs:` list of `JobDefinition`s.
+/// - Jobs missing from the collection are created, and jobs whose definition differs are updated.
+/// - Jobs synced from a file but no longer defined in any file are disabled (`Status::Frozen`),
+///   as are definitions with `enabled: false`. Re-enabling a definition makes the job pending.
+/// - One-shot jobs that already ran stay archived; they are not disabled or run again.
+/// - Every change is recorded as a `JobChangeV1`, so it shows up in the run history, and every
+///   created or updated definition as a `JobRevisionV1`.
+/// - Nothing is changed when a file cannot be read or parsed, or a definition is invalid, so a
+///   broken file never disables the jobs it defines.
+///
+/// # Example
+/// ```yaml
+/// jobs:
+///   - name: nightly-backup
+///     description: Back up the primary database
+///     command: /usr/local/bin/backup
+///     args: ["--full"]
+///     agents_required: [db-1]
+///     timeout: 3600
+///     tags: [backup, nightly]
+///     metadata: { ticket: OPS-12 }
+///   - name: cache-warmup
+///     command: /usr/local/bin/warm-cache
+///     agents_required: [web-1, web-2, web-3]
+///     success_rule: { kind: quorum, min_successes: 2 }
+///   - name: rotate-logs
+///     command: /usr/sbin/logrotate
+///     agent_groups: [web]
+///     schedule_interval: 3600
+///     misfire_policy: skip
+///   - name: weekday-report
+///     command: /usr/local/bin/report
+///     agents_required: [db-1]
+///     cron: "30 9 * * MON-FRI"
+///     timezone: Europe/Berlin
+///   - name: apt-upgrade
+///     command: apt-get
+///     args: ["upgrade", "-y"]
+///     agent_groups: [web]
+///     platforms: [linux/amd64, linux/arm64]
+///     node_selector: [disktype=ssd]
+///   - name: report-in-cluster
+///     command: /app/report
+///     executor: { kind: kubernetes, image: "registry.example.com/report:1.4", namespace: batch }
+///   - name: switch-backup
+///     command: "show running-config"
+///     executor: { kind: ssh, host: switch-1.example.com, user: backup, key_secret: switch-key }
+///   - name: deploy
+///     agents_required: [web-1]
+///     steps:
+///       - name: fetch
+///         command: git
+///         args: ["pull"]
+///         cwd: /srv/app
+///       - name: restart
+///         command: systemctl
+///         args: ["restart", "app"]
+///   - name: disk-report
+///     command: /opt/jobs/disk-report.sh
+///     agent_groups: [web]
+///     files:
+///       - destination: /opt/jobs/disk-report.sh
+///         mode: "755"
+///         source: { kind: grid_fs, name: disk-report.sh }
+///       - destination: /opt/jobs/thresholds.conf
+///         source: { kind: url, url: "https://config.example.com/thresholds.conf" }
+///   - name: vacuum
+///     agents_required: [db-1]
+///     args: ["app"]
+///     script:
+///       interpreter: bash
+///       body: |
+///         set -euo pipefail
+///         psql -d "$1" -c 'VACUUM ANALYZE'
+///   - name: lint
+///     agent_groups: [build]
+///     command: ruff
+///     args: ["check", "."]
+///     container:
+///       image: "ghcr.io/astral-sh/ruff:0.6"
+///       mounts: ["/srv/app:/src:ro"]
+///       workdir: /src
+///   - name: disk-check
+///     command: /usr/lib/nagios/plugins/check_disk
+///     args: ["-w", "20%", "-c", "10%", "-p", "/var"]
+///     agent_groups: [web]
+///     schedule_interval: 300
+///     check: true
+/// ```
+use bson::{Bson, Document, doc};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use tracing::{error, info};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::leader::Leadership;
+use core_logic::datastore::Datastore;
+use core_logic::datastore::job_changes::{FieldChange, JobChangeKind, JobChangeV1};
+use core_logic::datastore::job_revisions::JobRevisionV1;
+use core_logic::datastore::jobs::{
+    self, JobContainer, JobExecutor, JobFile, JobScript, JobSla, JobStep, JobV1, MisfirePolicy,
+    Status, SuccessRule,
+};
+use core_logic::output_parsing::OutputParser;
+use core_logic::redaction;
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// One job as written in a jobs file. Fields mirror `JobV1`; runtime state is left out.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// May be left out when the job has `steps`.
+    #[serde(default)]
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub cwd: String,
+    #[serde(default)]
+    pub timeout: u32,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default)]
+    pub valid_return_codes: Vec<i32>,
+    #[serde(default)]
+    pub agents_required: Vec<String>,
+    /// Agent groups whose members also run the job.
+    #[serde(default)]
+    pub agent_groups: Vec<String>,
+    /// Platforms the job runs on, as `os` or `os/arch`; any platform when omitted.
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// Kubernetes node labels, as `key=value`, the node of each agent must have; any node when
+    /// omitted.
+    #[serde(default)]
+    pub node_selector: Vec<String>,
+    /// `{ kind: kubernetes, image: ... }` runs the job as Kubernetes Jobs instead of on agents, and
+    /// `{ kind: ssh, host: ..., user: ..., key_secret: ... }` over SSH on a host without an agent.
+    #[serde(default)]
+    pub executor: JobExecutor,
+    #[serde(default)]
+    pub redact_patterns: Vec<String>,
+    #[serde(default)]
+    pub sla: Option<JobSla>,
+    #[serde(default)]
+    pub steps: Vec<JobStep>,
+    /// Files pushed to each agent before the job is dispatched to it.
+    #[serde(default)]
+    pub files: Vec<JobFile>,
+    /// A script the agent runs instead of `command`.
+    #[serde(default)]
+    pub script: Option<JobScript>,
+    /// A container image the agents run the command in, e.g. `{ image: "alpine:3.20" }`.
+    #[serde(default)]
+    pub container: Option<JobContainer>,
+    /// Extract a status and values from each run's output, e.g.
+    /// `[{ kind: regex, pattern: "processed (?P<records>\\d+)" }]`.
+    #[serde(default)]
+    pub output_parsers: Vec<OutputParser>,
+    #[serde(default)]
+    pub success_statuses: Vec<String>,
+    #[serde(default)]
+    pub check: bool,
+    #[serde(default)]
+    pub success_rule: SuccessRule,
+    #[serde(default)]
+    pub one_shot: bool,
+    /// Whether each run waits for an operator's approval, see `JobV1::requires_approval`.
+    #[serde(default)]
+    pub requires_approval: bool,
+    /// Jobs to trigger when a run of the job succeeds.
+    #[serde(default)]
+    pub on_success: Vec<String>,
+    /// Jobs to trigger when a run of the job fails.
+    #[serde(default)]
+    pub on_failure: Vec<String>,
+    /// Seconds between runs; the job runs once when omitted.
+    #[serde(default)]
+    pub schedule_interval: Option<u32>,
+    /// Cron expression to run on instead of `schedule_interval`.
+    #[serde(default)]
+    pub cron: Option<String>,
+    /// IANA time zone the cron expression is evaluated in; UTC when omitted.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub misfire_policy: MisfirePolicy,
+    /// Unix timestamp of the next run; when omitted, new jobs run as soon as possible, or at the
+    /// first cron occurrence for cron jobs.
+    #[serde(default)]
+    pub next_run: Option<i64>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// The team the job belongs to, see `JobV1::team`.
+    #[serde(default)]
+    pub team: Option<String>,
+    /// Labels organizing the job, e.g. `[backup, nightly]`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Free-form values describing the job, e.g. `{ ticket: OPS-12 }`.
+    #[serde(default)]
+    pub metadata: Document,
+    /// The namespace the job belongs to, see `JobV1::namespace`.
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobsFile {
+    #[serde(default)]
+    jobs: Vec<JobDefinition>,
+}
+
+impl JobDefinition {
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.name.trim().is_empty()
+            || (self.command.trim().is_empty() && self.script.is_none() && self.steps.is_empty())
+        {
+            return Err("Job name and command, script or steps are required".into());
+        }
+        if self
+            .steps
+            .iter()
+            .any(|step| step.name.trim().is_empty() || step.command.trim().is_empty())
+        {
+            return Err("Every step needs a name and command".into());
+        }
+        let on_executor = self.executor.agent_name().is_some();
+        if self.agents_required.is_empty() && self.agent_groups.is_empty() && !on_executor {
+            return Err("Job needs agents_required or agent_groups".into());
+        }
+        self.success_rule.validate(match on_executor {
+            true => Some(1),
+            false => self
+                .agent_groups
+                .is_empty()
+                .then_some(self.agents_required.len()),
+        })?;
+        jobs::validate_platforms(&self.platforms)?;
+        jobs::validate_node_selector(&self.node_selector)?;
+        jobs::validate_executor(
+            &self.executor,
+            &self.steps,
+            self.script.as_ref(),
+            &self.files,
+            &self.node_selector,
+        )?;
+        jobs::validate_hooks(&self.name, &self.on_success, &self.on_failure)?;
+        jobs::validate_tags(&self.tags)?;
+        jobs::validate_files(&self.files)?;
+        jobs::validate_script(self.script.as_ref(), &self.command, &self.steps)?;
+        jobs::validate_container(
+            self.container.as_ref(),
+            self.script.as_ref(),
+            &self.executor,
+        )?;
+        jobs::validate_output_parsers(&self.output_parsers, &self.success_statuses)?;
+        jobs::validate_schedule(
+            self.schedule_interval,
+            self.cron.as_deref(),
+            self.timezone.as_deref(),
+            self.one_shot,
+        )?;
+        for pattern in &self.redact_patterns {
+            redaction::validate_pattern(pattern)?;
+        }
+        Ok(())
+    }
+
+    /// `existing` with its definition replaced by this one, or a new pending job.
+    fn to_job(&self, existing: Option<&JobV1>, source: &str) -> JobV1 {
+        let mut job = existing.cloned().unwrap_or_else(|| JobV1 {
+            id: None,
+            name: self.name.clone(),
+            next_run: now_seconds(),
+            status: Status::Pending,
+            description: String::new(),
+            command: String::new(),
+            args: vec![],
+            env: vec![],
+            cwd: String::new(),
+            timeout: 0,
+            retries: 0,
+            valid_return_codes: vec![],
+            agents_required: vec![],
+            agents_running: vec![],
+            agents_complete: vec![],
+            agent_groups: vec![],
+            platforms: vec![],
+            node_selector: vec![],
+            executor: JobExecutor::default(),
+            cycle_agents: vec![],
+            triggered_by: None,
+            cycle_id: None,
+            agents_pending: vec![],
+            redact_patterns: vec![],
+            template: None,
+            sla: None,
+            managed_by: None,
+            cancel_requested_at: None,
+            cancel_sent_to: vec![],
+            timeout_extension_requests: vec![],
+            scheduling_lag_ms: None,
+            steps: vec![],
+            files: vec![],
+            script: None,
+            container: None,
+            output_parsers: vec![],
+            success_statuses: vec![],
+            check: false,
+            success_rule: SuccessRule::default(),
+            one_shot: false,
+            requires_approval: false,
+            approval: None,
+            on_success: vec![],
+            on_failure: vec![],
+            hook_chain: vec![],
+            schedule_interval: None,
+            cron: None,
+            timezone: None,
+            misfire_policy: MisfirePolicy::default(),
+            deleted_at: None,
+            status_before_delete: None,
+            rerun: None,
+            owner: None,
+            team: None,
+            tags: vec![],
+            metadata: Document::new(),
+            namespace: None,
+        });
+        let rescheduled = existing.is_none_or(|existing| {
+            existing.cron != self.cron || existing.timezone != self.timezone
+        });
+        job.description = self.description.clone();
+        job.command = self.command.clone();
+        job.args = self.args.clone();
+        job.env = self.env.clone();
+        job.cwd = self.cwd.clone();
+        job.timeout = self.timeout;
+        job.retries = self.retries;
+        job.valid_return_codes = self.valid_return_codes.clone();
+        job.agents_required = self.agents_required.clone();
+        job.agent_groups = self.agent_groups.clone();
+        job.platforms = self.platforms.clone();
+        job.node_selector = self.node_selector.clone();
+        job.executor = self.executor.clone();
+        job.redact_patterns = self.redact_patterns.clone();
+        job.sla = self.sla.clone();
+        job.steps = self.steps.clone();
+        job.files = self.files.clone();
+        job.script = self.script.clone();
+        job.container = self.container.clone();
+        job.output_parsers = self.output_parsers.clone();
+        job.success_statuses = self.success_statuses.clone();
+        job.check = self.check;
+        job.success_rule = self.success_rule;
+        job.one_shot = self.one_shot;
+        job.requires_approval = self.requires_approval;
+        job.on_success = self.on_success.clone();
+        job.on_failure = self.on_failure.clone();
+        job.schedule_interval = self.schedule_interval;
+        job.cron = self.cron.clone();
+        job.timezone = self.timezone.clone();
+        job.misfire_policy = self.misfire_policy;
+        job.team = self.team.clone();
+        job.tags = self.tags.clone();
+        job.metadata = self.metadata.clone();
+        job.namespace = self.namespace.clone();
+        job.managed_by = Some(source.to_string());
+        if let Some(next_run) = self.next_run {
+            job.next_run = next_run;
+        } else if rescheduled && let Some((schedule, timezone)) = job.cron_schedule() {
+            job.next_run = schedule
+                .next_after(now_seconds(), timezone)
+                .unwrap_or(job.next_run);
+        }
+        job
+    }
+}
+
+fn now_seconds() -> i64 {
+    bson::DateTime::now().timestamp_millis() / 1000
+}
+
+/// The fields of `job` owned by its jobs file, as a `$set` document.
+fn definition_update(job: &JobV1) -> Result<bson::Document, Box<dyn Error>> {
+    let mut update = job.definition()?;
+    update.insert(
+        "managed_by",
+        job.managed_by
+            .as_ref()
+            .map(Bson::from)
+            .unwrap_or(Bson::Null),
+    );
+    update.insert(
+        "team",
+        job.team.as_ref().map(Bson::from).unwrap_or(Bson::Null),
+    );
+    update.insert(
+        "namespace",
+        job.namespace.as_ref().map(Bson::from).unwrap_or(Bson::Null),
+    );
+    update.insert("next_run", jobs::next_run_datetime(job.next_run));
+    Ok(update)
+}
+
+pub struct JobSync {
+    datastore: Arc<Datastore>,
+    directory: PathBuf,
+}
+
+impl JobSync {
+    pub fn new(datastore: Arc<Datastore>, directory: PathBuf) -> Self {
+        Self {
+            datastore,
+            directory,
+        }
+    }
+
+    /// Reconciles the jobs now and then every `interval`, while leading.
+    pub async fn start(self, interval: Duration, leadership: Leadership) {
+        loop {
+            if leadership.is_leader()
+                && let Err(e) = self.reconcile().await
+            {
+                error!(
+                    "Failed to sync jobs from {}: {}",
+                    self.directory.display(),
+                    e
+                );
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Reads every jobs file in the directory, keyed by job name with the file it came from.
+    async fn load_definitions(
+        &self,
+    ) -> Result<HashMap<String, (String, JobDefinition)>, Box<dyn Error>> {
+        let mut paths: Vec<PathBuf> = vec![];
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let is_yaml = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| extension == "yaml" || extension == "yml");
+            if is_yaml && entry.file_type().await?.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut definitions = HashMap::new();
+        for path in paths {
+            let file_name = file_name(&path);
+            let contents = tokio::fs::read_to_string(&path).await?;
+            let file: JobsFile = serde_yaml::from_str(&contents)
+                .map_err(|e| format!("Invalid jobs file {}: {}", file_name, e))?;
+            for definition in file.jobs {
+                definition.validate().map_err(|e| {
+                    format!("Invalid job {} in {}: {}", definition.name, file_name, e)
+                })?;
+                if let Some((other, _)) = definitions.get(&definition.name) {
+                    return Err(format!(
+                        "Job {} is defined in both {} and {}",
+                        definition.name, other, file_name
+                    )
+                    .into());
+                }
+                definitions.insert(definition.name.clone(), (file_name.clone(), definition));
+            }
+        }
+        Ok(definitions)
+    }
+
+    pub async fn reconcile(&self) -> Result<(), Box<dyn Error>> {
+        let definitions = self.load_definitions().await?;
+
+        let collection = self.datastore.get_collection::<JobV1>("jobs").await?;
+        let names: Vec<&String> = definitions.keys().collect();
+        let existing: Vec<JobV1> = collection
+            .find(doc! { "$or": [
+                { "name": { "$in": names } },
+                { "managed_by": { "$exists": true, "$ne": null } },
+            ] })
+            .await?
+            .try_collect()
+            .await?;
+        let existing: HashMap<String, JobV1> = existing
+            .into_iter()
+            .map(|job| (job.name.clone(), job))
+            .collect();
+
+        let (mut created, mut updated, mut disabled) = (0, 0, 0);
+        let mut changes: Vec<JobChangeV1> = vec![];
+        let mut revisions: Vec<(Option<&JobV1>, JobV1, String)> = vec![];
+        for (name, (file_name, definition)) in &definitions {
+            let source = format!("jobs file {}", file_name);
+            let current = existing.get(name);
+            let mut job = definition.to_job(current, &source);
+
+            let Some(current) = current else {
+                if !definition.enabled {
+                    job.status = Status::Frozen;
+                }
+                collection.insert_one(&job).await?;
+                changes.push(JobChangeV1::new(name, JobChangeKind::Created, &source));
+                revisions.push((None, job, source));
+                created += 1;
+                continue;
+            };
+
+            let mut update = doc! {};
+            if let Some(change) = JobChangeV1::between(current, &job, &source) {
+                changes.push(change);
+                update = definition_update(&job)?;
+                revisions.push((Some(current), job.clone(), source.clone()));
+            } else if current.managed_by != job.managed_by {
+                update.insert("managed_by", &source);
+            }
+            match (definition.enabled, current.status) {
+                (false, Status::Frozen | Status::Running | Status::Archived) => (),
+                (false, _) => {
+                    update.insert("status", Status::Frozen);
+                    changes.push(JobChangeV1::new(name, JobChangeKind::Disabled, &source));
+                }
+                (true, Status::Frozen) => {
+                    update.insert("status", Status::Pending);
+                    changes.push(JobChangeV1 {
+                        changes: vec![FieldChange {
+                            field: "status".to_string(),
+                            old: "disabled".to_string(),
+                            new: "enabled".to_string(),
+                        }],
+                        ..JobChangeV1::new(name, JobChangeKind::Updated, &source)
+                    });
+                }
+                (true, _) => (),
+            }
+            if !update.is_empty() {
+                collection
+                    .update_one(doc! { "name": name }, doc! { "$set": update })
+                    .await?;
+                updated += 1;
+            }
+        }
+
+        // Jobs synced from a file that no longer defines them. Running jobs are left to finish and
+        // disabled by a later pass.
+        for (name, job) in &existing {
+            let Some(source) = job.managed_by.as_ref() else {
+                continue;
+            };
+            if definitions.contains_key(name)
+                || matches!(
+                    job.status,
+                    Status::Frozen | Status::Running | Status::Archived
+                )
+            {
+                continue;
+            }
+            collection
+                .update_one(
+                    doc! { "name": name },
+                    doc! { "$set": { "status": Status::Frozen } },
+                )
+                .await?;
+            changes.push(JobChangeV1::new(name, JobChangeKind::Disabled, source));
+            disabled += 1;
+        }
+
+        for change in changes {
+            if let Err(e) = change.insert_entry(&self.datastore).await {
+                error!("Failed to record job change for {}: {}", change.job_name, e);
+            }
+        }
+        for (previous, job, source) in revisions {
+            if let Err(e) =
+                JobRevisionV1::record(&self.datastore, previous, &job, "central command", &source)
+                    .await
+            {
+                error!("Failed to record job revision for {}: {}", job.name, e);
+            }
+        }
+        if created + updated + disabled > 0 {
+            info!(
+                "Synced jobs from {}: {} created, {} updated, {} disabled",
+                self.directory.display(),
+                created,
+                updated,
+                disabled
+            );
+        }
+        Ok(())
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
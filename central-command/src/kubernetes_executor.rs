@@ -0,0 +1,772 @@
+/// The `KubernetesExecutor` runs the jobs whose `executor` is `kubernetes` (see
+/// `core_logic::datastore::jobs::JobExecutor`) as Kubernetes Jobs instead of dispatching them to
+/// agents.
+///
+/// # Overview
+/// - Central command stands in for an agent named `kubernetes` (`KUBERNETES_EXECUTOR_AGENT`): it
+///   registers it, keeps it online and receives its dispatches through `AgentChannels`, so the
+///   `AgentManager` dispatches the jobs' runs to it like to any channel agent. Cycles, rate limits,
+///   retries, hooks and the run history work as for agents, with each run recorded on the
+///   `kubernetes` agent.
+/// - Each `DispatchJob` becomes a Kubernetes Job in the namespace named by the job's executor, or
+///   `KUBERNETES_NAMESPACE`, running the job's command and arguments in its `image` with the job's
+///   `env` and `cwd`. The job's `node_selector` becomes the pod's `nodeSelector` and its timeout the
+///   Job's `activeDeadlineSeconds`. Kubernetes does not retry the pod; the job's `retries` apply.
+/// - The Job is polled until it finishes. The pod's exit code and its logs, redacted like agent
+///   output and cut at `MAX_OUTPUT_BYTES`, are then recorded as the run's result, exactly like a
+///   `JobComplete` from an agent, and the Job is deleted.
+/// - `CancelJob` deletes the job's running Jobs, recording their runs as cancelled, and
+///   `ExtendTimeout` raises their `activeDeadlineSeconds`.
+/// - The Kubernetes API is called over HTTPS with the token of central command's service account,
+///   which needs to create, get, patch and delete Jobs, and to list pods and read their logs, in
+///   the namespaces jobs run in. Executor namespaces and service accounts must be DNS-1123
+///   labels, and listed in `KUBERNETES_ALLOWED_NAMESPACES` and
+///   `KUBERNETES_ALLOWED_SERVICE_ACCOUNTS`; runs of other jobs fail without creating a Job.
+///
+/// # Configuration
+/// - `KUBERNETES_EXECUTOR`: `true` starts the executor (default: `false`).
+/// - `KUBERNETES_API_URL`: The API server (default: the in-cluster address, from
+///   `KUBERNETES_SERVICE_HOST` and `KUBERNETES_SERVICE_PORT`).
+/// - `KUBERNETES_TOKEN_FILE`: Bearer token for the API server, read for every request so rotated
+///   tokens are picked up (default: the pod's service account token).
+/// - `KUBERNETES_CA_FILE`: CA certificate of the API server (default: the pod's service account
+///   CA).
+/// - `KUBERNETES_NAMESPACE`: Namespace of the Jobs of jobs that do not name one (default: the
+///   pod's namespace, or `default`).
+/// - `KUBERNETES_ALLOWED_NAMESPACES`: Comma separated namespaces jobs may name besides
+///   `KUBERNETES_NAMESPACE` (default: empty, only `KUBERNETES_NAMESPACE`).
+/// - `KUBERNETES_ALLOWED_SERVICE_ACCOUNTS`: Comma separated service accounts jobs may run their
+///   pods as (default: empty, only the namespace's default service account).
+use bson::{DateTime, doc};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Method, StatusCode};
+use serde_json::{Value, json};
+use tokio::spawn;
+use tokio::sync::{Mutex, mpsc};
+use tokio::time::{interval, sleep};
+use tracing::{Instrument, debug, error, info, warn};
+
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::agent_channels::AgentChannels;
+use crate::agent_manager::AgentManager;
+use crate::command_receiver::CommandReceiver;
+use crate::{VERSION, get_central_command_id};
+use core_logic::datastore::{
+    Datastore,
+    jobs::{self, JobExecutor, JobV1, KUBERNETES_EXECUTOR_AGENT},
+};
+use core_logic::logging;
+use core_logic::messages::{
+    ContainerInfo, DispatchJob, JobComplete, JobOutCome, Message, RegisterAgent,
+};
+use core_logic::redaction::Redactor;
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+const CHANNEL_CAPACITY: usize = 100;
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Bytes of a pod's logs kept as the run's output.
+const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+const CONTAINER_NAME: &str = "job";
+
+/// The Kubernetes API server, called with the token of central command's service account.
+#[derive(Debug, Clone)]
+struct KubernetesApi {
+    client: Client,
+    url: String,
+    token_file: String,
+}
+
+impl KubernetesApi {
+    fn from_env() -> Result<Self, Box<dyn Error>> {
+        let url = match env::var("KUBERNETES_API_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                let host = env::var("KUBERNETES_SERVICE_HOST").map_err(|_| {
+                    "KUBERNETES_API_URL is not set and central command is not running in a cluster"
+                })?;
+                let port =
+                    env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_string());
+                match host.contains(':') {
+                    true => format!("https://[{}]:{}", host, port),
+                    false => format!("https://{}:{}", host, port),
+                }
+            }
+        };
+        let token_file = env::var("KUBERNETES_TOKEN_FILE")
+            .unwrap_or_else(|_| format!("{}/token", SERVICE_ACCOUNT_DIR));
+        let ca_file = env::var("KUBERNETES_CA_FILE")
+            .unwrap_or_else(|_| format!("{}/ca.crt", SERVICE_ACCOUNT_DIR));
+        let mut builder = Client::builder();
+        match fs::read(&ca_file) {
+            Ok(pem) => {
+                builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?)
+            }
+            Err(e) => warn!("Not trusting the Kubernetes CA {}: {}", ca_file, e),
+        }
+        Ok(Self {
+            client: builder.build()?,
+            url: url.trim_end_matches('/').to_string(),
+            token_file,
+        })
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<reqwest::Response, Box<dyn Error + Send + Sync>> {
+        let token = fs::read_to_string(&self.token_file)
+            .map_err(|e| format!("Unable to read {}: {}", self.token_file, e))?;
+        let content_type = match method {
+            Method::PATCH => "application/merge-patch+json",
+            _ => "application/json",
+        };
+        let mut request = self
+            .client
+            .request(method, format!("{}{}", self.url, path))
+            .bearer_auth(token.trim());
+        if let Some(body) = body {
+            request = request
+                .header(CONTENT_TYPE, content_type)
+                .body(body.to_string());
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            return Err(KubernetesError { status, message }.into());
+        }
+        Ok(response)
+    }
+
+    async fn json(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        let bytes = self.request(method, path, body).await?.bytes().await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    fn job_path(namespace: &str, name: &str) -> String {
+        format!("/apis/batch/v1/namespaces/{}/jobs/{}", namespace, name)
+    }
+}
+
+/// An error answer of the API server.
+#[derive(Debug)]
+struct KubernetesError {
+    status: StatusCode,
+    message: String,
+}
+
+impl std::fmt::Display for KubernetesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Kubernetes API answered {}: {}",
+            self.status, self.message
+        )
+    }
+}
+
+impl Error for KubernetesError {}
+
+/// A Kubernetes Job running a run.
+#[derive(Debug, Clone)]
+struct RunningJob {
+    namespace: String,
+    name: String,
+    run_id: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct KubernetesExecutor {
+    api: KubernetesApi,
+    datastore: Arc<Datastore>,
+    agent_channels: AgentChannels,
+    default_namespace: String,
+    allowed: AllowList,
+    running: Arc<Mutex<HashMap<String, Vec<RunningJob>>>>, // By job name
+}
+
+/// The namespaces and service accounts executor jobs may name, from
+/// `KUBERNETES_ALLOWED_NAMESPACES` and `KUBERNETES_ALLOWED_SERVICE_ACCOUNTS`.
+#[derive(Debug, Clone)]
+struct AllowList {
+    namespaces: Vec<String>,       // Besides the default namespace
+    service_accounts: Vec<String>, // Besides the namespace's default
+}
+
+impl AllowList {
+    /// Fails unless the Job may be created in `namespace` and run as `service_account`: both
+    /// valid names, and `KUBERNETES_NAMESPACE` or listed in `KUBERNETES_ALLOWED_NAMESPACES`, and
+    /// listed in `KUBERNETES_ALLOWED_SERVICE_ACCOUNTS`. Checked at each run, as jobs may have
+    /// been stored before their executor was validated.
+    fn check(
+        &self,
+        default_namespace: &str,
+        namespace: &str,
+        service_account: Option<&str>,
+    ) -> Result<(), String> {
+        jobs::validate_kubernetes_name("namespace", namespace)?;
+        if namespace != default_namespace
+            && !self.namespaces.iter().any(|allowed| allowed == namespace)
+        {
+            return Err(format!(
+                "Namespace {} is not in KUBERNETES_ALLOWED_NAMESPACES",
+                namespace
+            ));
+        }
+        if let Some(service_account) = service_account {
+            jobs::validate_kubernetes_name("service account", service_account)?;
+            if !self
+                .service_accounts
+                .iter()
+                .any(|allowed| allowed == service_account)
+            {
+                return Err(format!(
+                    "Service account {} is not in KUBERNETES_ALLOWED_SERVICE_ACCOUNTS",
+                    service_account
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The comma separated names in the environment variable `name`.
+fn env_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+impl KubernetesExecutor {
+    pub fn try_new(
+        datastore: Arc<Datastore>,
+        agent_channels: AgentChannels,
+    ) -> Result<Self, Box<dyn Error>> {
+        let default_namespace = env::var("KUBERNETES_NAMESPACE")
+            .ok()
+            .or_else(|| {
+                fs::read_to_string(format!("{}/namespace", SERVICE_ACCOUNT_DIR))
+                    .ok()
+                    .map(|namespace| namespace.trim().to_string())
+            })
+            .filter(|namespace| !namespace.is_empty())
+            .unwrap_or_else(|| "default".to_string());
+        Ok(Self {
+            api: KubernetesApi::from_env()?,
+            datastore,
+            agent_channels,
+            default_namespace,
+            allowed: AllowList {
+                namespaces: env_list("KUBERNETES_ALLOWED_NAMESPACES"),
+                service_accounts: env_list("KUBERNETES_ALLOWED_SERVICE_ACCOUNTS"),
+            },
+            running: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Registers the executor's agent and runs the jobs dispatched to it until central command
+    /// stops.
+    pub async fn run(self) -> Result<(), Box<dyn Error>> {
+        info!(
+            "Running kubernetes executor jobs as Kubernetes Jobs through {}, in {} by default",
+            self.api.url, self.default_namespace
+        );
+        self.handle(Message::RegisterAgent(RegisterAgent {
+            name: KUBERNETES_EXECUTOR_AGENT.to_string(),
+            hostname: KUBERNETES_EXECUTOR_AGENT.to_string(),
+            port: 0,
+            version: VERSION.to_string(),
+            timezone: "UTC".to_string(),
+            locale: String::new(),
+            receipt_public_key: None,
+            os: "linux".to_string(),
+            arch: String::new(),
+            kernel: String::new(),
+            shells: vec![],
+            features: vec![],
+            container: Some(ContainerInfo {
+                runtime: "kubernetes".to_string(),
+                node_name: None,
+                pod_name: None,
+                pod_namespace: Some(self.default_namespace.clone()),
+                node_labels: vec![],
+            }),
+        }))
+        .await;
+
+        let (sender, mut receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        self.agent_channels
+            .register(KUBERNETES_EXECUTOR_AGENT, sender)
+            .await;
+        let mut heartbeat = interval(HEARTBEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                message = receiver.recv() => match message {
+                    Some(message) => self.receive(message).await,
+                    None => break,
+                },
+                _ = heartbeat.tick() => {
+                    if let Err(e) =
+                        AgentManager::update_agent_online(self.datastore.clone(), KUBERNETES_EXECUTOR_AGENT).await
+                    {
+                        error!("Failed to update the kubernetes executor to online: {}", e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a message the `AgentManager` sent the executor's agent.
+    async fn receive(&self, message: Message) {
+        match message {
+            Message::DispatchJob(dispatch) => {
+                let executor = self.clone();
+                let span = logging::run_span(
+                    dispatch.run_id.as_deref(),
+                    &dispatch.job_name,
+                    KUBERNETES_EXECUTOR_AGENT,
+                );
+                spawn(async move { executor.execute(dispatch).await }.instrument(span));
+            }
+            Message::DispatchBatch(dispatches) => {
+                for dispatch in dispatches {
+                    Box::pin(self.receive(Message::DispatchJob(dispatch))).await;
+                }
+            }
+            Message::CancelJob(cancel) => self.cancel(&cancel.job_name).await,
+            Message::ExtendTimeout(extend) => self.extend(&extend.job_name, extend.seconds).await,
+            message => debug!("Kubernetes executor ignores {:?}", message.kind()),
+        }
+    }
+
+    /// Handles a message from the executor's agent as the `CommandReceiver` does those of agents.
+    async fn handle(&self, message: Message) {
+        let peer_addr = SocketAddr::from(([0, 0, 0, 0], 0));
+        if let Err(e) =
+            CommandReceiver::handle_message(message, self.datastore.clone(), peer_addr, None).await
+        {
+            error!("Failed to handle kubernetes executor message: {}", e);
+        }
+    }
+
+    /// Runs `dispatch` as a Kubernetes Job and records its result.
+    async fn execute(&self, dispatch: DispatchJob) {
+        let started_at = DateTime::now().timestamp_millis();
+        let (return_code, outcome, output) = match self.run_job(&dispatch).await {
+            Ok(result) => result,
+            Err(e) => {
+                error!("Kubernetes Job for {} failed: {}", dispatch.job_name, e);
+                (
+                    -1,
+                    JobOutCome::Failure,
+                    format!("Kubernetes executor: {}", e),
+                )
+            }
+        };
+        let redactor = Redactor::with_defaults(&dispatch.redact_patterns).unwrap_or_default();
+        let truncated = output.len() > MAX_OUTPUT_BYTES;
+        let output = match truncated {
+            true => String::from_utf8_lossy(&output.as_bytes()[..MAX_OUTPUT_BYTES]).into_owned(),
+            false => output,
+        };
+        let (command, args) = (
+            redactor.redact(&dispatch.command),
+            redactor.redact(&dispatch.args),
+        );
+        self.handle(Message::JobComplete(JobComplete {
+            started_at,
+            completed_at: DateTime::now().timestamp_millis(),
+            job_name: dispatch.job_name,
+            command: format!("{} {}", command, args).trim().to_string(),
+            agent_name: KUBERNETES_EXECUTOR_AGENT.to_string(),
+            return_code,
+            outcome,
+            output: redactor.redact(&output),
+            triggered_by: dispatch.triggered_by,
+            truncated,
+            artifact: None,
+            signature: None,
+            run_id: dispatch.run_id,
+            steps: vec![],
+            timeout_extension: 0,
+        }))
+        .await;
+    }
+
+    /// Creates the Job for `dispatch`, waits for it to finish and deletes it, returning the
+    /// container's exit code, the run's outcome and the pod's logs.
+    async fn run_job(
+        &self,
+        dispatch: &DispatchJob,
+    ) -> Result<(i32, JobOutCome, String), Box<dyn Error + Send + Sync>> {
+        let jobs = self
+            .datastore
+            .get_collection::<JobV1>("jobs")
+            .await
+            .map_err(|e| e.to_string())?;
+        let job = jobs
+            .find_one(doc! { "name": &dispatch.job_name })
+            .await?
+            .ok_or_else(|| format!("Job {} no longer exists", dispatch.job_name))?;
+        let JobExecutor::Kubernetes {
+            image,
+            namespace,
+            service_account,
+        } = &job.executor
+        else {
+            return Err(format!(
+                "Job {} is no longer run by the kubernetes executor",
+                job.name
+            )
+            .into());
+        };
+        let namespace = namespace
+            .clone()
+            .unwrap_or_else(|| self.default_namespace.clone());
+        self.allowed.check(
+            &self.default_namespace,
+            &namespace,
+            service_account.as_deref(),
+        )?;
+        let manifest = self.manifest(&job, dispatch, image, service_account.as_deref());
+        let created = self
+            .api
+            .json(
+                Method::POST,
+                &format!("/apis/batch/v1/namespaces/{}/jobs", namespace),
+                Some(manifest),
+            )
+            .await?;
+        let name = created["metadata"]["name"]
+            .as_str()
+            .ok_or("Created Job has no name")?
+            .to_string();
+        info!(
+            "Created Kubernetes Job {}/{} for {}",
+            namespace, name, job.name
+        );
+        let running = RunningJob {
+            namespace: namespace.clone(),
+            name: name.clone(),
+            run_id: dispatch.run_id.clone(),
+        };
+        self.running
+            .lock()
+            .await
+            .entry(job.name.clone())
+            .or_default()
+            .push(running);
+
+        let finished = self.wait(&namespace, &name).await;
+        {
+            let mut running = self.running.lock().await;
+            let jobs = running.entry(job.name.clone()).or_default();
+            jobs.retain(|running| running.name != name);
+            if jobs.is_empty() {
+                running.remove(&job.name);
+            }
+        }
+        let reason = match finished? {
+            Some(reason) => reason,
+            None => return Ok((-1, JobOutCome::Cancelled, "Cancelled".to_string())),
+        };
+        let (return_code, output) = self.collect(&namespace, &name).await;
+        self.delete(&namespace, &name).await;
+
+        let valid_return_codes = dispatch.valid_return_codes.clone().unwrap_or(vec![0]);
+        let outcome = match (reason.as_str(), return_code) {
+            ("DeadlineExceeded", _) => JobOutCome::TimedOut,
+            (_, Some(code)) if valid_return_codes.contains(&code) => JobOutCome::Success,
+            _ => JobOutCome::Failure,
+        };
+        let output = match return_code {
+            Some(_) => output,
+            None => format!("{}\nKubernetes Job {}: {}", output, name, reason),
+        };
+        Ok((return_code.unwrap_or(-1), outcome, output))
+    }
+
+    /// The Kubernetes Job running `dispatch` of `job` in `image`.
+    fn manifest(
+        &self,
+        job: &JobV1,
+        dispatch: &DispatchJob,
+        image: &str,
+        service_account: Option<&str>,
+    ) -> Value {
+        let (command, args, env) = match &job.rerun {
+            Some(rerun) => (&rerun.command, &rerun.args, &rerun.env),
+            None => (&job.command, &job.args, &job.env),
+        };
+        let env: Vec<Value> = env
+            .iter()
+            .map(|var| match var.split_once('=') {
+                Some((name, value)) => json!({ "name": name, "value": value }),
+                None => json!({ "name": var, "value": "" }),
+            })
+            .collect();
+        let node_selector: serde_json::Map<String, Value> = job
+            .node_selector
+            .iter()
+            .filter_map(|label| label.split_once('='))
+            .map(|(key, value)| (key.to_string(), Value::from(value)))
+            .collect();
+
+        let mut container = json!({
+            "name": CONTAINER_NAME,
+            "image": image,
+            "args": args,
+            "env": env,
+        });
+        if !command.is_empty() {
+            container["command"] = json!([command]);
+        }
+        if !job.cwd.is_empty() {
+            container["workingDir"] = json!(job.cwd);
+        }
+        let mut pod_spec = json!({
+            "restartPolicy": "Never",
+            "containers": [container],
+            "nodeSelector": node_selector,
+        });
+        if let Some(service_account) = service_account {
+            pod_spec["serviceAccountName"] = json!(service_account);
+        }
+        let mut spec = json!({
+            "backoffLimit": 0,
+            "template": {
+                "metadata": { "labels": { "app.kubernetes.io/managed-by": "rust-action-dispatch" } },
+                "spec": pod_spec,
+            },
+        });
+        if let Some(timeout) = dispatch.timeout {
+            spec["activeDeadlineSeconds"] = json!(timeout);
+        }
+        json!({
+            "apiVersion": "batch/v1",
+            "kind": "Job",
+            "metadata": {
+                "generateName": format!("rad-{}-", Self::name_prefix(&job.name)),
+                "labels": { "app.kubernetes.io/managed-by": "rust-action-dispatch" },
+                "annotations": {
+                    "rust-action-dispatch/job": &job.name,
+                    "rust-action-dispatch/run-id": dispatch.run_id.as_deref().unwrap_or_default(),
+                    "rust-action-dispatch/central-command": get_central_command_id(),
+                },
+            },
+            "spec": spec,
+        })
+    }
+
+    /// `job_name` as the start of a Kubernetes name: lowercase letters, digits and dashes.
+    fn name_prefix(job_name: &str) -> String {
+        let name: String = job_name
+            .to_lowercase()
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c,
+                false => '-',
+            })
+            .take(40)
+            .collect();
+        match name.trim_matches('-') {
+            "" => "job".to_string(),
+            name => name.to_string(),
+        }
+    }
+
+    /// Waits for the Job to finish, returning why: `Complete`, or the reason it failed, e.g.
+    /// `DeadlineExceeded`. `None` means the Job was deleted, e.g. because the job was cancelled.
+    async fn wait(
+        &self,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let path = KubernetesApi::job_path(namespace, name);
+        loop {
+            sleep(POLL_INTERVAL).await;
+            let job = match self.api.json(Method::GET, &path, None).await {
+                Ok(job) => job,
+                Err(e) => match e.downcast_ref::<KubernetesError>() {
+                    Some(e) if e.status == StatusCode::NOT_FOUND => return Ok(None),
+                    _ => {
+                        warn!(
+                            "Failed to check Kubernetes Job {}/{}: {}",
+                            namespace, name, e
+                        );
+                        continue;
+                    }
+                },
+            };
+            let finished = job["status"]["conditions"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|condition| {
+                    condition["status"] == "True"
+                        && matches!(condition["type"].as_str(), Some("Complete" | "Failed"))
+                });
+            if let Some(condition) = finished {
+                let reason = condition["reason"]
+                    .as_str()
+                    .or(condition["type"].as_str())
+                    .unwrap_or_default();
+                return Ok(Some(reason.to_string()));
+            }
+        }
+    }
+
+    /// The exit code and logs of the Job's pod.
+    async fn collect(&self, namespace: &str, name: &str) -> (Option<i32>, String) {
+        let path = format!(
+            "/api/v1/namespaces/{}/pods?labelSelector=job-name%3D{}",
+            namespace, name
+        );
+        let pod = match self.api.json(Method::GET, &path, None).await {
+            Ok(pods) => pods["items"].get(0).cloned(),
+            Err(e) => {
+                warn!(
+                    "Failed to find the pod of Kubernetes Job {}/{}: {}",
+                    namespace, name, e
+                );
+                None
+            }
+        };
+        let Some(pod) = pod else {
+            return (None, String::new());
+        };
+        let return_code = pod["status"]["containerStatuses"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|status| status["name"] == CONTAINER_NAME)
+            .and_then(|status| status["state"]["terminated"]["exitCode"].as_i64())
+            .map(|code| code as i32);
+        let pod_name = pod["metadata"]["name"].as_str().unwrap_or_default();
+        let path = format!(
+            "/api/v1/namespaces/{}/pods/{}/log?container={}&limitBytes={}",
+            namespace,
+            pod_name,
+            CONTAINER_NAME,
+            MAX_OUTPUT_BYTES + 1
+        );
+        let output = match self.api.request(Method::GET, &path, None).await {
+            Ok(response) => response.text().await.unwrap_or_default(),
+            Err(e) => format!("Unable to read the logs of pod {}: {}", pod_name, e),
+        };
+        (return_code, output)
+    }
+
+    /// Deletes the Job and its pod.
+    async fn delete(&self, namespace: &str, name: &str) {
+        let path = format!(
+            "{}?propagationPolicy=Background",
+            KubernetesApi::job_path(namespace, name)
+        );
+        if let Err(e) = self.api.request(Method::DELETE, &path, None).await {
+            warn!(
+                "Failed to delete Kubernetes Job {}/{}: {}",
+                namespace, name, e
+            );
+        }
+    }
+
+    /// Deletes the running Jobs of `job_name`; their runs are recorded as cancelled once `wait`
+    /// notices.
+    async fn cancel(&self, job_name: &str) {
+        let running = self.running.lock().await.get(job_name).cloned();
+        for job in running.into_iter().flatten() {
+            info!(
+                "Cancelling run {} of {}: deleting Kubernetes Job {}/{}",
+                job.run_id.as_deref().unwrap_or_default(),
+                job_name,
+                job.namespace,
+                job.name
+            );
+            self.delete(&job.namespace, &job.name).await;
+        }
+    }
+
+    /// Gives the running Jobs of `job_name` `seconds` more before they are killed.
+    async fn extend(&self, job_name: &str, seconds: u32) {
+        let running = self.running.lock().await.get(job_name).cloned();
+        for job in running.into_iter().flatten() {
+            let path = KubernetesApi::job_path(&job.namespace, &job.name);
+            let extended = async {
+                let current = self.api.json(Method::GET, &path, None).await?;
+                let Some(deadline) = current["spec"]["activeDeadlineSeconds"].as_u64() else {
+                    return Ok(()); // No deadline to extend
+                };
+                let patch =
+                    json!({ "spec": { "activeDeadlineSeconds": deadline + seconds as u64 } });
+                self.api.request(Method::PATCH, &path, Some(patch)).await?;
+                Ok::<_, Box<dyn Error + Send + Sync>>(())
+            };
+            if let Err(e) = extended.await {
+                warn!(
+                    "Failed to extend Kubernetes Job {}/{}: {}",
+                    job.namespace, job.name, e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allow_list(namespaces: &[&str], service_accounts: &[&str]) -> AllowList {
+        AllowList {
+            namespaces: namespaces.iter().map(|name| name.to_string()).collect(),
+            service_accounts: service_accounts
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn check_denies_everything_but_the_default_namespace_when_empty() {
+        let allowed = allow_list(&[], &[]);
+        assert!(allowed.check("jobs", "jobs", None).is_ok());
+        assert!(allowed.check("jobs", "kube-system", None).is_err());
+        assert!(allowed.check("jobs", "jobs", Some("admin")).is_err());
+    }
+
+    #[test]
+    fn check_allows_listed_namespaces_and_service_accounts() {
+        let allowed = allow_list(&["batch"], &["runner"]);
+        assert!(allowed.check("jobs", "batch", Some("runner")).is_ok());
+        assert!(allowed.check("jobs", "jobs", Some("runner")).is_ok());
+        assert!(allowed.check("jobs", "other", Some("runner")).is_err());
+        assert!(allowed.check("jobs", "batch", Some("admin")).is_err());
+    }
+
+    #[test]
+    fn check_rejects_invalid_names() {
+        let allowed = allow_list(&["Batch"], &["run/ner"]);
+        assert!(allowed.check("jobs", "Batch", None).is_err());
+        assert!(allowed.check("jobs", "jobs", Some("run/ner")).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+/// `LeaderElection` lets several central command instances run against the same MongoDB, with
+/// one of them, the leader, dispatching jobs while the others stand by to take over.
+///
+/// # Overview
+/// - Every instance serves the receiver path: it accepts agent connections, records their
+///   registrations, heartbeats and results, and relays remote shells.
+/// - Only the instance holding the `central_command` lease in the `leases` collection leads: it
+///   dials and pings agents, dispatches jobs, marks silent agents offline, handles message bus
+///   agents, sends webhooks, syncs `JOBS_DIR` and deletes expired history.
+/// - The leader renews its lease every third of `LEADER_LEASE_SECONDS`, and standbys try to take
+///   it as often. A leader that died is replaced once its lease expired; one that shuts down
+///   releases it, so a standby takes over at its next attempt.
+/// - A leader that cannot renew stops leading when the lease it last renewed runs out by its own
+///   clock, measured from before the renewal was sent, so it has stepped down before a standby
+///   can take the lease and jobs are not dispatched twice.
+/// - Agents connected to an instance over reverse dispatch or gRPC are only reached by that
+///   instance, so they should connect to the leader, e.g. through a load balancer.
+/// - With `AGENT_PARTITIONS` set, every instance dials, pings and dispatches to the agents of the
+///   partitions it holds instead, and only the fleet-wide tasks are left to the leader (see
+///   `partitions`).
+///
+/// # Configuration
+/// - `LEADER_ELECTION`: When `true`, instances elect a leader (default: `false`, the instance
+///   always leads).
+/// - `LEADER_LEASE_SECONDS`: How long a lease lasts without renewal (default: 15).
+/// - `CENTRAL_COMMAND_ID`: Name of the instance in the lease and in connection snapshots
+///   (default: the hostname). Instances sharing a host need distinct ids.
+///
+/// # Example
+/// ```rust
+/// let election = LeaderElection::new(datastore, get_central_command_id(), lease);
+/// let leadership = election.leadership();
+/// spawn(election.clone().start());
+/// if leadership.is_leader() {
+///     // Dispatch jobs
+/// }
+/// election.resign().await;
+/// ```
+use tokio::time::{Instant, sleep};
+use tracing::{error, info, warn};
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use core_logic::datastore::Datastore;
+use core_logic::datastore::leases::{LEADER_LEASE, LeaseV1};
+
+/// Whether this instance leads, shared by the tasks that only the leader runs.
+#[derive(Debug, Clone)]
+pub struct Leadership {
+    elected: bool,
+    leading_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Leadership {
+    /// Leadership of an instance that does not take part in an election and always leads.
+    pub fn always() -> Self {
+        Self {
+            elected: false,
+            leading_until: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        if !self.elected {
+            return true;
+        }
+        self.leading_until
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    fn set_leading_until(&self, until: Option<Instant>) {
+        *self
+            .leading_until
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = until;
+    }
+}
+
+#[derive(Clone)]
+pub struct LeaderElection {
+    datastore: Arc<Datastore>,
+    instance_id: String,
+    lease: Duration,
+    leadership: Leadership,
+}
+
+impl LeaderElection {
+    pub fn new(datastore: Arc<Datastore>, instance_id: &str, lease: Duration) -> Self {
+        Self {
+            datastore,
+            instance_id: instance_id.to_string(),
+            lease,
+            leadership: Leadership {
+                elected: true,
+                leading_until: Arc::new(Mutex::new(None)),
+            },
+        }
+    }
+
+    pub fn leadership(&self) -> Leadership {
+        self.leadership.clone()
+    }
+
+    /// Takes or renews the lease until central command stops.
+    pub async fn start(self) {
+        info!(
+            "Electing a leader as {} with a {} second lease",
+            self.instance_id,
+            self.lease.as_secs()
+        );
+        let mut leader: Option<String> = None; // Last leader logged while standing by
+        loop {
+            let attempted = Instant::now();
+            let was_leader = self.leadership.is_leader();
+            let acquired =
+                LeaseV1::try_acquire(&self.datastore, LEADER_LEASE, &self.instance_id, self.lease)
+                    .await
+                    .map_err(|e| e.to_string()); // Box<dyn Error> is not Send
+            match acquired {
+                Ok(true) => {
+                    self.leadership
+                        .set_leading_until(Some(attempted + self.lease));
+                    if !was_leader {
+                        info!("{} is now the leader", self.instance_id);
+                    }
+                    leader = None;
+                }
+                Ok(false) => {
+                    self.leadership.set_leading_until(None);
+                    if was_leader {
+                        warn!("{} is no longer the leader", self.instance_id);
+                    }
+                    if let Ok(Some(lease)) = LeaseV1::find(&self.datastore, LEADER_LEASE).await
+                        && leader.as_ref() != Some(&lease.holder)
+                    {
+                        info!("Standing by, {} is the leader", lease.holder);
+                        leader = Some(lease.holder);
+                    }
+                }
+                // The lease is kept until it runs out, in case the next renewal succeeds.
+                Err(e) => error!("Failed to renew the leader lease: {}", e),
+            }
+            sleep(self.lease / 3).await;
+        }
+    }
+
+    /// Stops leading and releases the lease, so a standby takes over without waiting for it to
+    /// expire.
+    pub async fn resign(&self) {
+        self.leadership.set_leading_until(None);
+        if let Err(e) = LeaseV1::release(&self.datastore, LEADER_LEASE, &self.instance_id).await {
+            error!("Failed to release the leader lease: {}", e);
+        }
+    }
+}
@@ -0,0 +1,899 @@
+mod agent_cache;
+mod agent_channels;
+mod agent_manager;
+mod auth;
+mod bus_bridge;
+mod command_receiver;
+mod connection_limits;
+mod connection_metrics;
+mod dispatch_limits;
+mod dry_run;
+mod file_distribution;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod job_sync;
+mod kubernetes_executor;
+mod leader;
+mod notifier;
+mod paging;
+mod partitions;
+mod scheduler;
+mod shell_proxy;
+mod ssh_executor;
+
+use tokio::spawn;
+use tracing::{info, warn};
+
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use agent_cache::AgentCache;
+use agent_channels::AgentChannels;
+use agent_manager::AgentManager;
+use auth::AgentAuthenticator;
+use bson::DateTime;
+use bus_bridge::BusBridge;
+use command_receiver::CommandReceiver;
+use connection_metrics::ConnectionMetrics;
+use core_logic::datastore::Datastore;
+use core_logic::datastore::agent_events::AgentEventV1;
+use core_logic::datastore::job_changes::JobChangeV1;
+use core_logic::health::Health;
+use job_sync::JobSync;
+use kubernetes_executor::KubernetesExecutor;
+use leader::{LeaderElection, Leadership};
+use notifier::Notifier;
+use partitions::{AgentPartitions, PartitionClaims};
+use shell_proxy::ShellProxy;
+use ssh_executor::SshExecutor;
+
+pub const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0:8080";
+pub const VERSION: &str = "0.1.0";
+
+static LISTEN_ADDRESSES: OnceLock<Vec<String>> = OnceLock::new();
+static JOB_CHANGE_RETENTION_DAYS: OnceLock<u32> = OnceLock::new();
+static AGENT_EVENT_RETENTION_DAYS: OnceLock<u32> = OnceLock::new();
+static AGENT_EVENT_WEBHOOK_URLS: OnceLock<Vec<String>> = OnceLock::new();
+static JOB_WARNING_WEBHOOK_URLS: OnceLock<Vec<String>> = OnceLock::new();
+static WEBHOOK_MAX_AGE_SECONDS: OnceLock<u64> = OnceLock::new();
+static PAGERDUTY_ROUTING_KEY: OnceLock<Option<(String, String)>> = OnceLock::new();
+static OPSGENIE_API_KEY: OnceLock<Option<(String, String)>> = OnceLock::new();
+static PAGE_SEVERITIES: OnceLock<Vec<String>> = OnceLock::new();
+static JOBS_SYNC_INTERVAL_SECONDS: OnceLock<u64> = OnceLock::new();
+static AGENT_DEGRADED_PING_MS: OnceLock<u64> = OnceLock::new();
+static AGENT_OFFLINE_AFTER_SECONDS: OnceLock<u64> = OnceLock::new();
+static AGENT_ONLINE_AFTER_SECONDS: OnceLock<u64> = OnceLock::new();
+static MISFIRE_GRACE_SECONDS: OnceLock<i64> = OnceLock::new();
+static CHUNK_SIZE: OnceLock<usize> = OnceLock::new();
+static ADAPTIVE_CHUNKS: OnceLock<bool> = OnceLock::new();
+static MAX_CONNECTIONS: OnceLock<usize> = OnceLock::new();
+static CONNECT_RATE_LIMIT_PER_MINUTE: OnceLock<u32> = OnceLock::new();
+static DISPATCH_RATE_LIMIT_PER_MINUTE: OnceLock<u32> = OnceLock::new();
+static JOB_DISPATCH_RATE_LIMIT_PER_MINUTE: OnceLock<u32> = OnceLock::new();
+static AGENT_DISPATCH_RATE_LIMIT_PER_MINUTE: OnceLock<u32> = OnceLock::new();
+static READ_TIMEOUT_SECONDS: OnceLock<u64> = OnceLock::new();
+static MAX_MESSAGE_BYTES: OnceLock<usize> = OnceLock::new();
+static RUN_OUTPUT_OFFLOAD_BYTES: OnceLock<usize> = OnceLock::new();
+static TCP_KEEPALIVE_SECONDS: OnceLock<u64> = OnceLock::new();
+static AGENT_IDLE_TIMEOUT_SECONDS: OnceLock<u64> = OnceLock::new();
+static AGENT_CACHE_REFRESH_SECONDS: OnceLock<u64> = OnceLock::new();
+static LEADER_ELECTION: OnceLock<bool> = OnceLock::new();
+static LEADER_LEASE_SECONDS: OnceLock<u64> = OnceLock::new();
+static CENTRAL_COMMAND_ID: OnceLock<String> = OnceLock::new();
+static AGENT_PARTITIONS: OnceLock<u32> = OnceLock::new();
+static DRY_RUN: OnceLock<bool> = OnceLock::new();
+static KUBERNETES_EXECUTOR: OnceLock<bool> = OnceLock::new();
+static SSH_EXECUTOR: OnceLock<bool> = OnceLock::new();
+
+/// Addresses the `CommandReceiver` listens on, read from the comma separated `LISTEN_ADDRESSES`
+/// (e.g. `LISTEN_ADDRESSES=127.0.0.1:8080,[::1]:8080`). Defaults to `0.0.0.0:8080`.
+pub fn get_listen_addresses() -> &'static [String] {
+    LISTEN_ADDRESSES.get_or_init(|| {
+        let addresses: Vec<String> = env::var("LISTEN_ADDRESSES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|address| !address.is_empty())
+            .map(str::to_string)
+            .collect();
+        if addresses.is_empty() {
+            vec![DEFAULT_LISTEN_ADDRESS.to_string()]
+        } else {
+            addresses
+        }
+    })
+}
+
+/// Days job definition changes are kept, read from `JOB_CHANGE_RETENTION_DAYS` (default: 90).
+/// `0` keeps them forever.
+pub fn get_job_change_retention_days() -> u32 {
+    *JOB_CHANGE_RETENTION_DAYS.get_or_init(|| {
+        env::var("JOB_CHANGE_RETENTION_DAYS")
+            .unwrap_or("90".to_string())
+            .parse()
+            .expect("Invalid JOB_CHANGE_RETENTION_DAYS")
+    })
+}
+
+/// Days agent lifecycle events are kept, read from `AGENT_EVENT_RETENTION_DAYS` (default: 30).
+/// `0` keeps them forever.
+pub fn get_agent_event_retention_days() -> u32 {
+    *AGENT_EVENT_RETENTION_DAYS.get_or_init(|| {
+        env::var("AGENT_EVENT_RETENTION_DAYS")
+            .unwrap_or("30".to_string())
+            .parse()
+            .expect("Invalid AGENT_EVENT_RETENTION_DAYS")
+    })
+}
+
+fn parse_webhook_urls(var: &str) -> Vec<String> {
+    env::var(var)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// URLs agent lifecycle events are posted to, read from the comma separated
+/// `AGENT_EVENT_WEBHOOK_URLS`. Events are not sent when it is empty (the default).
+pub fn get_agent_event_webhook_urls() -> &'static [String] {
+    AGENT_EVENT_WEBHOOK_URLS.get_or_init(|| parse_webhook_urls("AGENT_EVENT_WEBHOOK_URLS"))
+}
+
+/// URLs warnings that a job is close to its timeout, breached its SLA or did not start in time are
+/// posted to, read from the comma separated `JOB_WARNING_WEBHOOK_URLS`. Warnings are only recorded
+/// when it is empty (the default).
+pub fn get_job_warning_webhook_urls() -> &'static [String] {
+    JOB_WARNING_WEBHOOK_URLS.get_or_init(|| parse_webhook_urls("JOB_WARNING_WEBHOOK_URLS"))
+}
+
+/// Seconds after which an agent event or job warning that could not be sent is dropped instead
+/// of retried, read from `WEBHOOK_MAX_AGE_SECONDS` (default: 3600).
+pub fn get_webhook_max_age_seconds() -> u64 {
+    *WEBHOOK_MAX_AGE_SECONDS.get_or_init(|| {
+        env::var("WEBHOOK_MAX_AGE_SECONDS")
+            .unwrap_or("3600".to_string())
+            .parse()
+            .expect("Invalid WEBHOOK_MAX_AGE_SECONDS")
+    })
+}
+
+/// The integration key of the PagerDuty service failed runs of jobs whose severity pages are sent
+/// to, read from `PAGERDUTY_ROUTING_KEY`, with the Events API v2 endpoint read from
+/// `PAGERDUTY_EVENTS_URL` (default: `https://events.pagerduty.com/v2/enqueue`). See `paging`.
+pub fn get_pagerduty_routing_key() -> Option<(&'static str, &'static str)> {
+    PAGERDUTY_ROUTING_KEY
+        .get_or_init(|| {
+            let key = env::var("PAGERDUTY_ROUTING_KEY")
+                .ok()
+                .filter(|key| !key.is_empty())?;
+            let url = env::var("PAGERDUTY_EVENTS_URL")
+                .unwrap_or_else(|_| "https://events.pagerduty.com/v2/enqueue".to_string());
+            Some((key, url))
+        })
+        .as_ref()
+        .map(|(key, url)| (key.as_str(), url.as_str()))
+}
+
+/// The Opsgenie API key failed runs of jobs whose severity pages are sent with, read from
+/// `OPSGENIE_API_KEY`, with the API read from `OPSGENIE_API_URL` (default:
+/// `https://api.opsgenie.com`). See `paging`.
+pub fn get_opsgenie_api_key() -> Option<(&'static str, &'static str)> {
+    OPSGENIE_API_KEY
+        .get_or_init(|| {
+            let key = env::var("OPSGENIE_API_KEY")
+                .ok()
+                .filter(|key| !key.is_empty())?;
+            let url = env::var("OPSGENIE_API_URL")
+                .unwrap_or_else(|_| "https://api.opsgenie.com".to_string());
+            Some((key, url))
+        })
+        .as_ref()
+        .map(|(key, url)| (key.as_str(), url.as_str()))
+}
+
+/// The job severities (`sla.severity`) whose failed runs page the on-call, read from the comma
+/// separated `PAGE_SEVERITIES` (default: `critical`).
+pub fn get_page_severities() -> &'static [String] {
+    PAGE_SEVERITIES.get_or_init(|| {
+        env::var("PAGE_SEVERITIES")
+            .unwrap_or("critical".to_string())
+            .split(',')
+            .map(|severity| severity.trim().to_lowercase())
+            .filter(|severity| !severity.is_empty())
+            .collect()
+    })
+}
+
+/// Seconds between reconciliations of the jobs collection against `JOBS_DIR`, read from
+/// `JOBS_SYNC_INTERVAL_SECONDS` (default: 60).
+pub fn get_jobs_sync_interval_seconds() -> u64 {
+    *JOBS_SYNC_INTERVAL_SECONDS.get_or_init(|| {
+        env::var("JOBS_SYNC_INTERVAL_SECONDS")
+            .unwrap_or("60".to_string())
+            .parse()
+            .expect("Invalid JOBS_SYNC_INTERVAL_SECONDS")
+    })
+}
+
+/// Milliseconds an agent may take to answer a ping before it is marked degraded, read from
+/// `AGENT_DEGRADED_PING_MS` (default: 1000).
+pub fn get_agent_degraded_ping_ms() -> u64 {
+    *AGENT_DEGRADED_PING_MS.get_or_init(|| {
+        env::var("AGENT_DEGRADED_PING_MS")
+            .unwrap_or("1000".to_string())
+            .parse()
+            .expect("Invalid AGENT_DEGRADED_PING_MS")
+    })
+}
+
+/// Seconds since its last heartbeat after which an agent is marked offline, even though no write
+/// to it failed, read from `AGENT_OFFLINE_AFTER_SECONDS` (default: 60). `0` disables the sweep.
+pub fn get_agent_offline_after_seconds() -> u64 {
+    *AGENT_OFFLINE_AFTER_SECONDS.get_or_init(|| {
+        env::var("AGENT_OFFLINE_AFTER_SECONDS")
+            .unwrap_or("60".to_string())
+            .parse()
+            .expect("Invalid AGENT_OFFLINE_AFTER_SECONDS")
+    })
+}
+
+/// Seconds an agent marked offline for missing heartbeats must keep answering before it is online
+/// again, read from `AGENT_ONLINE_AFTER_SECONDS` (default: 15).
+pub fn get_agent_online_after_seconds() -> u64 {
+    *AGENT_ONLINE_AFTER_SECONDS.get_or_init(|| {
+        env::var("AGENT_ONLINE_AFTER_SECONDS")
+            .unwrap_or("15".to_string())
+            .parse()
+            .expect("Invalid AGENT_ONLINE_AFTER_SECONDS")
+    })
+}
+
+/// Seconds a scheduled run may be overdue before it counts as missed and the job's misfire policy
+/// applies, read from `MISFIRE_GRACE_SECONDS` (default: 60).
+pub fn get_misfire_grace_seconds() -> i64 {
+    *MISFIRE_GRACE_SECONDS.get_or_init(|| {
+        env::var("MISFIRE_GRACE_SECONDS")
+            .unwrap_or("60".to_string())
+            .parse()
+            .expect("Invalid MISFIRE_GRACE_SECONDS")
+    })
+}
+
+/// Bytes read from an agent connection at a time, read from `CHUNK_SIZE` (default: 4096).
+pub fn get_chunk_size() -> usize {
+    *CHUNK_SIZE.get_or_init(|| {
+        env::var("CHUNK_SIZE")
+            .unwrap_or("4096".to_string())
+            .parse()
+            .expect("Invalid CHUNK_SIZE")
+    })
+}
+
+/// Whether the read size adapts to how much data agents send, read from `ADAPTIVE_CHUNKS`
+/// (default: `false`). See `core_logic::flow_control`.
+pub fn get_adaptive_chunks() -> bool {
+    *ADAPTIVE_CHUNKS.get_or_init(|| {
+        env::var("ADAPTIVE_CHUNKS")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+/// Agent connections open at once, across all listeners, read from `MAX_CONNECTIONS`
+/// (default: 1024). `0` is unlimited.
+pub fn get_max_connections() -> usize {
+    *MAX_CONNECTIONS.get_or_init(|| {
+        env::var("MAX_CONNECTIONS")
+            .unwrap_or("1024".to_string())
+            .parse()
+            .expect("Invalid MAX_CONNECTIONS")
+    })
+}
+
+/// Connections an IP address may open a minute, read from `CONNECT_RATE_LIMIT_PER_MINUTE`
+/// (default: 60). `0` is unlimited.
+pub fn get_connect_rate_limit_per_minute() -> u32 {
+    *CONNECT_RATE_LIMIT_PER_MINUTE.get_or_init(|| {
+        env::var("CONNECT_RATE_LIMIT_PER_MINUTE")
+            .unwrap_or("60".to_string())
+            .parse()
+            .expect("Invalid CONNECT_RATE_LIMIT_PER_MINUTE")
+    })
+}
+
+/// Runs dispatched a minute across all jobs and agents, read from `DISPATCH_RATE_LIMIT_PER_MINUTE`
+/// (default: 0, unlimited). See `dispatch_limits`.
+pub fn get_dispatch_rate_limit_per_minute() -> u32 {
+    *DISPATCH_RATE_LIMIT_PER_MINUTE.get_or_init(|| {
+        env::var("DISPATCH_RATE_LIMIT_PER_MINUTE")
+            .unwrap_or("0".to_string())
+            .parse()
+            .expect("Invalid DISPATCH_RATE_LIMIT_PER_MINUTE")
+    })
+}
+
+/// Runs of a job dispatched a minute, read from `JOB_DISPATCH_RATE_LIMIT_PER_MINUTE`
+/// (default: 0, unlimited).
+pub fn get_job_dispatch_rate_limit_per_minute() -> u32 {
+    *JOB_DISPATCH_RATE_LIMIT_PER_MINUTE.get_or_init(|| {
+        env::var("JOB_DISPATCH_RATE_LIMIT_PER_MINUTE")
+            .unwrap_or("0".to_string())
+            .parse()
+            .expect("Invalid JOB_DISPATCH_RATE_LIMIT_PER_MINUTE")
+    })
+}
+
+/// Runs dispatched to an agent a minute, read from `AGENT_DISPATCH_RATE_LIMIT_PER_MINUTE`
+/// (default: 0, unlimited).
+pub fn get_agent_dispatch_rate_limit_per_minute() -> u32 {
+    *AGENT_DISPATCH_RATE_LIMIT_PER_MINUTE.get_or_init(|| {
+        env::var("AGENT_DISPATCH_RATE_LIMIT_PER_MINUTE")
+            .unwrap_or("0".to_string())
+            .parse()
+            .expect("Invalid AGENT_DISPATCH_RATE_LIMIT_PER_MINUTE")
+    })
+}
+
+/// Seconds a new connection may take to send its first message, and any message may take to
+/// arrive once it started, read from `READ_TIMEOUT_SECONDS` (default: 30). `0` waits forever.
+pub fn get_read_timeout_seconds() -> u64 {
+    *READ_TIMEOUT_SECONDS.get_or_init(|| {
+        env::var("READ_TIMEOUT_SECONDS")
+            .unwrap_or("30".to_string())
+            .parse()
+            .expect("Invalid READ_TIMEOUT_SECONDS")
+    })
+}
+
+/// Largest message accepted from an agent, read from `MAX_MESSAGE_BYTES` (default: 64 MiB).
+/// `0` is unlimited.
+pub fn get_max_message_bytes() -> usize {
+    *MAX_MESSAGE_BYTES.get_or_init(|| {
+        env::var("MAX_MESSAGE_BYTES")
+            .unwrap_or((64 * 1024 * 1024).to_string())
+            .parse()
+            .expect("Invalid MAX_MESSAGE_BYTES")
+    })
+}
+
+/// Run outputs longer than this are stored in the blob store (`BLOB_STORE_URL`), with only their
+/// head kept in the run so run listings stay fast, read from
+/// `RUN_OUTPUT_OFFLOAD_BYTES` (default: 0). `0` keeps every output in its run.
+pub fn get_run_output_offload_bytes() -> usize {
+    *RUN_OUTPUT_OFFLOAD_BYTES.get_or_init(|| {
+        env::var("RUN_OUTPUT_OFFLOAD_BYTES")
+            .unwrap_or("0".to_string())
+            .parse()
+            .expect("Invalid RUN_OUTPUT_OFFLOAD_BYTES")
+    })
+}
+
+/// Seconds without traffic after which TCP keepalive probes are sent on agent connections, read
+/// from `TCP_KEEPALIVE_SECONDS` (default: 30). Half-open connections are reset roughly twice this
+/// long after the agent was last heard from (see `core_logic::keepalive`). `0` keeps the OS default.
+pub fn get_tcp_keepalive_seconds() -> u64 {
+    *TCP_KEEPALIVE_SECONDS.get_or_init(|| {
+        env::var("TCP_KEEPALIVE_SECONDS")
+            .unwrap_or("30".to_string())
+            .parse()
+            .expect("Invalid TCP_KEEPALIVE_SECONDS")
+    })
+}
+
+/// Seconds an agent central command dialed may take to acknowledge a message before its
+/// connection is treated as dead and dropped, read from `AGENT_IDLE_TIMEOUT_SECONDS`
+/// (default: 30). `0` waits forever.
+pub fn get_agent_idle_timeout_seconds() -> u64 {
+    *AGENT_IDLE_TIMEOUT_SECONDS.get_or_init(|| {
+        env::var("AGENT_IDLE_TIMEOUT_SECONDS")
+            .unwrap_or("30".to_string())
+            .parse()
+            .expect("Invalid AGENT_IDLE_TIMEOUT_SECONDS")
+    })
+}
+
+/// Seconds between full reloads of the agent cache while it follows the agents change stream,
+/// read from `AGENT_CACHE_REFRESH_SECONDS` (default: 60). See `agent_cache`.
+pub fn get_agent_cache_refresh_seconds() -> u64 {
+    *AGENT_CACHE_REFRESH_SECONDS.get_or_init(|| {
+        env::var("AGENT_CACHE_REFRESH_SECONDS")
+            .unwrap_or("60".to_string())
+            .parse::<u64>()
+            .expect("Invalid AGENT_CACHE_REFRESH_SECONDS")
+            .max(1)
+    })
+}
+
+/// Whether instances sharing the MongoDB elect a leader to dispatch jobs, read from
+/// `LEADER_ELECTION` (default: `false`). See `leader`.
+pub fn get_leader_election() -> bool {
+    *LEADER_ELECTION.get_or_init(|| {
+        env::var("LEADER_ELECTION")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+/// Seconds the leader's lease lasts without renewal, and so how long a standby may wait to take
+/// over from a leader that died, read from `LEADER_LEASE_SECONDS` (default: 15).
+pub fn get_leader_lease_seconds() -> u64 {
+    *LEADER_LEASE_SECONDS.get_or_init(|| {
+        env::var("LEADER_LEASE_SECONDS")
+            .unwrap_or("15".to_string())
+            .parse()
+            .expect("Invalid LEADER_LEASE_SECONDS")
+    })
+}
+
+/// Number of partitions the agents are split into among the instances sharing the MongoDB, read
+/// from `AGENT_PARTITIONS` (default: 0, agents are not partitioned). See `partitions`.
+pub fn get_agent_partitions() -> u32 {
+    *AGENT_PARTITIONS.get_or_init(|| {
+        env::var("AGENT_PARTITIONS")
+            .unwrap_or("0".to_string())
+            .parse()
+            .expect("Invalid AGENT_PARTITIONS")
+    })
+}
+
+/// Whether the instance only records which runs it would dispatch to which agents, without
+/// dispatching them, set by passing `--dry-run` or with `DRY_RUN` (default: `false`). See
+/// `dry_run`.
+pub fn get_dry_run() -> bool {
+    *DRY_RUN.get_or_init(|| {
+        env::args().any(|arg| arg == "--dry-run")
+            || env::var("DRY_RUN")
+                .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false)
+    })
+}
+
+/// Whether central command runs the jobs whose `executor` is `kubernetes` as Kubernetes Jobs, set
+/// with `KUBERNETES_EXECUTOR` (default: `false`). See `kubernetes_executor`.
+pub fn get_kubernetes_executor() -> bool {
+    *KUBERNETES_EXECUTOR.get_or_init(|| {
+        env::var("KUBERNETES_EXECUTOR")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+/// Whether central command runs the jobs whose `executor` is `ssh` over SSH, set with
+/// `SSH_EXECUTOR` (default: `false`). See `ssh_executor`.
+pub fn get_ssh_executor() -> bool {
+    *SSH_EXECUTOR.get_or_init(|| {
+        env::var("SSH_EXECUTOR")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+/// Name of this instance in the leader lease and in connection snapshots, read from
+/// `CENTRAL_COMMAND_ID` (default: the hostname).
+pub fn get_central_command_id() -> &'static str {
+    CENTRAL_COMMAND_ID.get_or_init(|| {
+        env::var("CENTRAL_COMMAND_ID")
+            .ok()
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| {
+                hostname::get()
+                    .map(|hostname| hostname.to_string_lossy().into_owned())
+                    .unwrap_or_else(|_| "central-command".to_string())
+            })
+    })
+}
+
+fn display_central_command_info() {
+    info!("-------------------------------------------------");
+    info!("\tRust Action Dispatch Central Command");
+    info!("-------------------------------------------------");
+    info!(
+        "\tVersion: {} Hosted at {}",
+        VERSION,
+        get_listen_addresses().join(", ")
+    );
+    info!("-------------------------------------------------");
+}
+
+/// Periodically publishes the live connections for the webui's connection inspector.
+fn start_connection_snapshots(datastore: Arc<Datastore>, connection_metrics: ConnectionMetrics) {
+    const CONNECTION_SNAPSHOT_INTERVAL_SECONDS: u64 = 5;
+
+    spawn(async move {
+        loop {
+            if let Err(e) = connection_metrics.publish(datastore.clone()).await {
+                tracing::error!("Failed to publish connection snapshot: {}", e);
+            }
+            tokio::time::sleep(tokio::time::Duration::from_secs(
+                CONNECTION_SNAPSHOT_INTERVAL_SECONDS,
+            ))
+            .await;
+        }
+    });
+}
+
+/// Periodically deletes job definition changes older than `JOB_CHANGE_RETENTION_DAYS`, while
+/// leading.
+fn start_job_change_retention(datastore: Arc<Datastore>, leadership: Leadership) {
+    const JOB_CHANGE_RETENTION_INTERVAL_SECONDS: u64 = 3600;
+
+    let retention_days = get_job_change_retention_days();
+    if retention_days == 0 {
+        return;
+    }
+    spawn(async move {
+        loop {
+            let cutoff = DateTime::from_millis(
+                DateTime::now().timestamp_millis() - retention_days as i64 * 24 * 60 * 60 * 1000,
+            );
+            if leadership.is_leader() {
+                match JobChangeV1::delete_before(&datastore, cutoff).await {
+                    Ok(0) => (),
+                    Ok(deleted) => info!("Deleted {} expired job definition changes", deleted),
+                    Err(e) => {
+                        tracing::error!("Failed to delete expired job definition changes: {}", e)
+                    }
+                }
+            }
+            tokio::time::sleep(tokio::time::Duration::from_secs(
+                JOB_CHANGE_RETENTION_INTERVAL_SECONDS,
+            ))
+            .await;
+        }
+    });
+}
+
+/// Periodically deletes agent lifecycle events older than `AGENT_EVENT_RETENTION_DAYS`, while
+/// leading.
+fn start_agent_event_retention(datastore: Arc<Datastore>, leadership: Leadership) {
+    const AGENT_EVENT_RETENTION_INTERVAL_SECONDS: u64 = 3600;
+
+    let retention_days = get_agent_event_retention_days();
+    if retention_days == 0 {
+        return;
+    }
+    spawn(async move {
+        loop {
+            let cutoff = DateTime::from_millis(
+                DateTime::now().timestamp_millis() - retention_days as i64 * 24 * 60 * 60 * 1000,
+            );
+            if leadership.is_leader() {
+                match AgentEventV1::delete_before(&datastore, cutoff).await {
+                    Ok(0) => (),
+                    Ok(deleted) => info!("Deleted {} expired agent events", deleted),
+                    Err(e) => {
+                        tracing::error!("Failed to delete expired agent events: {}", e)
+                    }
+                }
+            }
+            tokio::time::sleep(tokio::time::Duration::from_secs(
+                AGENT_EVENT_RETENTION_INTERVAL_SECONDS,
+            ))
+            .await;
+        }
+    });
+}
+
+/// Sends agent lifecycle events to `AGENT_EVENT_WEBHOOK_URLS`, job timeout warnings to
+/// `JOB_WARNING_WEBHOOK_URLS` and pages to PagerDuty or Opsgenie when any are set, while leading.
+fn start_notifier(datastore: Arc<Datastore>, leadership: Leadership) {
+    let agent_event_urls = get_agent_event_webhook_urls();
+    let job_warning_urls = get_job_warning_webhook_urls();
+    let paging_channels = paging::channels();
+    if agent_event_urls.is_empty() && job_warning_urls.is_empty() && paging_channels.is_empty() {
+        return;
+    }
+    let max_age = Duration::from_secs(get_webhook_max_age_seconds());
+    match Notifier::try_new(
+        datastore,
+        agent_event_urls.to_vec(),
+        job_warning_urls.to_vec(),
+        paging_channels,
+        max_age,
+    ) {
+        Ok(notifier) => {
+            spawn(notifier.start(leadership));
+        }
+        Err(e) => tracing::error!("Failed to start webhooks: {}", e),
+    }
+}
+
+/// Serves `/healthz` and `/readyz` on `HEALTH_ADDRESS` when it is set, periodically checking
+/// that MongoDB is reachable. The agent listeners report their own status.
+fn start_health(datastore: Arc<Datastore>, health: Health) {
+    const DATASTORE_CHECK_INTERVAL_SECONDS: u64 = 10;
+
+    let Ok(address) = env::var("HEALTH_ADDRESS") else {
+        return;
+    };
+    spawn(health.clone().serve(address));
+    spawn(async move {
+        loop {
+            let check = datastore.check().await;
+            health.set("datastore", check.ok, check.detail);
+            tokio::time::sleep(Duration::from_secs(DATASTORE_CHECK_INTERVAL_SECONDS)).await;
+        }
+    });
+}
+
+/// Syncs the jobs collection from the YAML files in `JOBS_DIR` when it is set, while leading.
+fn start_job_sync(datastore: Arc<Datastore>, leadership: Leadership) {
+    let Ok(directory) = env::var("JOBS_DIR") else {
+        return;
+    };
+    info!("Syncing jobs from {}", directory);
+    let job_sync = JobSync::new(datastore, PathBuf::from(directory));
+    spawn(job_sync.start(
+        Duration::from_secs(get_jobs_sync_interval_seconds()),
+        leadership,
+    ));
+}
+
+/// Relays remote shells from the web UI to agents when `SHELL_PROXY_ADDRESS` is set (e.g.
+/// `127.0.0.1:8090`), requiring the web UI to present `SHELL_PROXY_TOKEN` when that is set.
+fn start_shell_proxy(datastore: Arc<Datastore>) {
+    let Ok(address) = env::var("SHELL_PROXY_ADDRESS") else {
+        return;
+    };
+    let token = env::var("SHELL_PROXY_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty());
+    if token.is_none() {
+        warn!(
+            "SHELL_PROXY_TOKEN is not set, anyone reaching {} can open remote shells",
+            address
+        );
+    }
+    spawn(async move {
+        if let Err(e) = ShellProxy::new(datastore, token).serve(&address).await {
+            tracing::error!("Remote shell proxy failed: {}", e);
+        }
+    });
+}
+
+/// Starts the gRPC transport when `GRPC_ADDRESS` is set.
+#[cfg(feature = "grpc")]
+fn start_grpc(
+    datastore: Arc<Datastore>,
+    agent_channels: AgentChannels,
+    authenticator: Option<Arc<dyn AgentAuthenticator>>,
+) {
+    let Ok(address) = env::var("GRPC_ADDRESS") else {
+        return;
+    };
+    let address = match address.parse() {
+        Ok(address) => address,
+        Err(e) => {
+            warn!("Invalid GRPC_ADDRESS {}: {}", address, e);
+            return;
+        }
+    };
+    spawn(async move {
+        let service = grpc::GrpcAgentService::new(datastore, agent_channels, authenticator);
+        if let Err(e) = service.serve(address).await {
+            tracing::error!("gRPC transport failed: {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "grpc"))]
+fn start_grpc(
+    _datastore: Arc<Datastore>,
+    _agent_channels: AgentChannels,
+    _authenticator: Option<Arc<dyn AgentAuthenticator>>,
+) {
+    if env::var("GRPC_ADDRESS").is_ok() {
+        warn!("GRPC_ADDRESS is set but central command was built without the grpc feature");
+    }
+}
+
+/// Starts the message bus bridge when `MESSAGE_BUS_URL` is set. Only the leader handles bus
+/// messages.
+fn start_bus_bridge(
+    datastore: Arc<Datastore>,
+    agent_channels: AgentChannels,
+    leadership: Leadership,
+    authenticator: Option<Arc<dyn AgentAuthenticator>>,
+) {
+    let Ok(url) = env::var("MESSAGE_BUS_URL") else {
+        return;
+    };
+    spawn(async move {
+        let bridge =
+            match BusBridge::try_new(&url, datastore, agent_channels, leadership, authenticator)
+                .await
+            {
+                Ok(bridge) => bridge,
+                Err(e) => {
+                    tracing::error!("Failed to connect to message bus {}: {}", url, e);
+                    return;
+                }
+            };
+        if let Err(e) = bridge.run().await {
+            tracing::error!("Message bus bridge failed: {}", e);
+        }
+    });
+}
+
+/// Starts the Kubernetes executor when `KUBERNETES_EXECUTOR` is set.
+fn start_kubernetes_executor(datastore: Arc<Datastore>, agent_channels: AgentChannels) {
+    if !get_kubernetes_executor() {
+        return;
+    }
+    let executor = match KubernetesExecutor::try_new(datastore, agent_channels) {
+        Ok(executor) => executor,
+        Err(e) => {
+            tracing::error!("Failed to set up the Kubernetes executor: {}", e);
+            return;
+        }
+    };
+    spawn(async move {
+        if let Err(e) = executor.run().await {
+            tracing::error!("Kubernetes executor failed: {}", e);
+        }
+    });
+}
+
+/// Starts the SSH executor when `SSH_EXECUTOR` is set.
+fn start_ssh_executor(datastore: Arc<Datastore>, agent_channels: AgentChannels) {
+    if !get_ssh_executor() {
+        return;
+    }
+    let executor = SshExecutor::new(datastore, agent_channels);
+    spawn(async move {
+        if let Err(e) = executor.run().await {
+            tracing::error!("SSH executor failed: {}", e);
+        }
+    });
+}
+
+/// Runs central command until it is interrupted. Logging is set up by the caller (see
+/// `core_logic::logging`).
+pub async fn run() -> Result<(), Box<dyn Error>> {
+    // Initialize the datastore
+    let datastore = Arc::new(
+        Datastore::try_new()
+            .await
+            .expect("Failed to create datastore"),
+    );
+
+    let health = Health::new(&["datastore", "listeners"]);
+    start_health(datastore.clone(), health.clone());
+
+    if get_dry_run() {
+        info!("Dry run: recording which runs would be dispatched instead of dispatching them");
+        if get_leader_election() || get_agent_partitions() > 0 {
+            warn!("Dry run: not taking part in leader election or agent partitioning");
+        }
+    }
+    let election = (get_leader_election() && !get_dry_run()).then(|| {
+        LeaderElection::new(
+            datastore.clone(),
+            get_central_command_id(),
+            Duration::from_secs(get_leader_lease_seconds()),
+        )
+    });
+    let leadership = match &election {
+        Some(election) => {
+            spawn(election.clone().start());
+            election.leadership()
+        }
+        None => Leadership::always(),
+    };
+    let claims = (get_agent_partitions() > 0 && !get_dry_run()).then(|| {
+        PartitionClaims::new(
+            datastore.clone(),
+            get_central_command_id(),
+            get_agent_partitions(),
+            Duration::from_secs(get_leader_lease_seconds()),
+        )
+    });
+    let partitions = match &claims {
+        Some(claims) => {
+            spawn(claims.clone().start());
+            claims.partitions()
+        }
+        None => AgentPartitions::all(),
+    };
+
+    let agent_channels = AgentChannels::default();
+
+    let authenticator = auth::authenticator_from_env();
+    if let Some(authenticator) = &authenticator {
+        info!(
+            "Authenticating agents with the {} backend",
+            authenticator.name()
+        );
+    }
+
+    start_grpc(
+        datastore.clone(),
+        agent_channels.clone(),
+        authenticator.clone(),
+    );
+    start_bus_bridge(
+        datastore.clone(),
+        agent_channels.clone(),
+        leadership.clone(),
+        authenticator.clone(),
+    );
+    start_kubernetes_executor(datastore.clone(), agent_channels.clone());
+    start_ssh_executor(datastore.clone(), agent_channels.clone());
+
+    let connection_metrics = ConnectionMetrics::new(get_central_command_id());
+
+    start_connection_snapshots(datastore.clone(), connection_metrics.clone());
+    start_job_change_retention(datastore.clone(), leadership.clone());
+    start_agent_event_retention(datastore.clone(), leadership.clone());
+    start_notifier(datastore.clone(), leadership.clone());
+    start_job_sync(datastore.clone(), leadership.clone());
+    start_shell_proxy(datastore.clone());
+
+    let agent_cache = AgentCache::default();
+
+    let cloned_datastore = datastore.clone();
+    let cloned_agent_channels = agent_channels.clone();
+    let cloned_agent_cache = agent_cache.clone();
+    let cloned_connection_metrics = connection_metrics.clone();
+
+    spawn(async move {
+        let mut command_receiver = CommandReceiver::new(
+            cloned_datastore,
+            cloned_agent_channels,
+            cloned_agent_cache,
+            cloned_connection_metrics,
+            authenticator,
+        )
+        .await;
+        health.set(
+            "listeners",
+            true,
+            format!("listening on {}", get_listen_addresses().join(", ")),
+        );
+        if let Err(e) = command_receiver.listen().await {
+            health.set("listeners", false, e.to_string());
+            panic!("Failed to listen for connections: {}", e);
+        }
+    });
+
+    let scheduler = scheduler::scheduler_from_env();
+    info!("Scheduling jobs with the {} strategy", scheduler.name());
+
+    // Clone the sender for use in the agent manager
+    let cloned_datastore = datastore.clone();
+
+    // Spawn a task to connect to the server and send data
+    spawn(async move {
+        let agent_manager = AgentManager::new(
+            cloned_datastore,
+            agent_channels,
+            agent_cache,
+            connection_metrics,
+            scheduler,
+            leadership,
+            partitions,
+        )
+        .await;
+        agent_manager.start().await;
+    });
+
+    display_central_command_info();
+
+    // Keep the main task alive
+    tokio::signal::ctrl_c().await?;
+    info!("Shutting down.");
+    if let Some(claims) = claims {
+        claims.release().await;
+    }
+    if let Some(election) = election {
+        election.resign().await;
+    }
+
+    Ok(())
+}
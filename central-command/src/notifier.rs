@@ -0,0 +1,333 @@
+/// The `Notifier` sends agent lifecycle events (see `core_logic::datastore::agent_events`) and
+/// warnings that a job is close to its timeout, has breached its SLA or, for check jobs, changed
+/// state (see `core_logic::datastore::job_warnings`) to webhooks, so infrastructure monitoring
+/// hears about an agent registering, going offline, coming back or being drained, and a job's
+/// owners hear about a slow run before it is killed, as soon as central command does.
+///
+/// # Overview
+/// - Events and warnings are recorded with `notification_pending` set, by central command or by
+///   the web UI for drains. The notifier polls for them every `NOTIFY_INTERVAL_SECONDS`.
+/// - Each agent event is `POST`ed as JSON to every URL in `AGENT_EVENT_WEBHOOK_URLS`, and each job
+///   warning to every URL in `JOB_WARNING_WEBHOOK_URLS`, oldest first. Once every webhook accepted
+///   it the event is marked sent.
+/// - A webhook that fails or answers with an error status leaves the event pending, so it is
+///   retried on the next poll. Webhooks that already accepted it receive it again; the `id`
+///   field identifies duplicates.
+/// - Job warnings repeating an alert somebody acknowledged on the alerts page (see
+///   `core_logic::datastore::alerts`) are marked sent without being delivered, until the alert is
+///   resolved.
+/// - Failed run alerts of jobs whose severity pages are sent to PagerDuty or Opsgenie, as
+///   described in `paging`, and retried like events.
+/// - Events older than `WEBHOOK_MAX_AGE_SECONDS` are marked sent without being delivered, so a
+///   webhook that was down for long, or configured later, is not flooded with stale events.
+///
+/// # Payload
+/// ```json
+/// {
+///   "id": "665f1c2e8b3e4a0001a1b2c3",
+///   "event": "agent.disconnected",
+///   "agent_name": "web-1",
+///   "at": "2025-06-04T12:00:00Z",
+///   "detail": ""
+/// }
+/// ```
+/// Job warnings add the run and how much of its timeout it used:
+/// ```json
+/// {
+///   "id": "665f1c2e8b3e4a0001a1b2c4",
+///   "event": "job.timeout_warning",
+///   "job_name": "nightly-backup",
+///   "agent_name": "web-1",
+///   "run_id": "7f9c0a52-8d0e-4d1b-a1f4-3b6e2c9d8e10",
+///   "at": "2025-06-04T12:08:00Z",
+///   "elapsed_seconds": 480,
+///   "timeout_seconds": 600
+/// }
+/// ```
+/// SLA breaches, sent once per cycle and agent when a run goes on for longer than the job's
+/// `sla.expected_duration`, are `job.sla_breached` events that also carry
+/// `expected_duration_seconds`, and no `run_id`. Overdue jobs, sent once per scheduled run that
+/// has not started within the job's `sla.max_start_delay`, are `job.overdue` events with an empty
+/// `agent_name`, the run's `scheduled_at`, and the seconds it is overdue as `elapsed_seconds`.
+/// Check jobs changing state on an agent are `job.state_changed` events that also carry the
+/// check's `state` and `previous_state`, one of `ok`, `warning`, `critical` and `unknown`, and the
+/// first line of the run's output as `summary`.
+use bson::DateTime;
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::leader::Leadership;
+use crate::paging::{PageAction, PagingChannel};
+use core_logic::datastore::Datastore;
+use core_logic::datastore::agent_events::AgentEventV1;
+use core_logic::datastore::alerts::{AlertKind, AlertStatus, AlertV1};
+use core_logic::datastore::check_states::CheckState;
+use core_logic::datastore::job_warnings::JobWarningV1;
+
+const NOTIFY_INTERVAL_SECONDS: u64 = 1;
+const NOTIFY_BATCH_SIZE: i64 = 100;
+const WEBHOOK_TIMEOUT_SECONDS: u64 = 10;
+
+fn rfc3339(at: &DateTime) -> String {
+    at.try_to_rfc3339_string()
+        .unwrap_or_else(|_| at.to_string())
+}
+
+#[derive(Debug, Serialize)]
+struct AgentEventPayload<'a> {
+    id: String,
+    event: String,
+    agent_name: &'a str,
+    at: String,
+    detail: &'a str,
+}
+
+impl<'a> From<&'a AgentEventV1> for AgentEventPayload<'a> {
+    fn from(event: &'a AgentEventV1) -> Self {
+        Self {
+            id: event.id.map(|id| id.to_hex()).unwrap_or_default(),
+            event: format!("agent.{}", event.kind),
+            agent_name: &event.agent_name,
+            at: rfc3339(&event.at),
+            detail: &event.detail,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JobWarningPayload<'a> {
+    id: String,
+    event: String,
+    job_name: &'a str,
+    agent_name: &'a str,
+    run_id: Option<&'a str>,
+    at: String,
+    elapsed_seconds: u32,
+    timeout_seconds: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_duration_seconds: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scheduled_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<CheckState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_state: Option<CheckState>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    summary: &'a str,
+}
+
+impl<'a> From<&'a JobWarningV1> for JobWarningPayload<'a> {
+    fn from(warning: &'a JobWarningV1) -> Self {
+        Self {
+            id: warning.id.map(|id| id.to_hex()).unwrap_or_default(),
+            event: format!("job.{}", warning.kind),
+            job_name: &warning.job_name,
+            agent_name: &warning.agent_name,
+            run_id: warning.run_id.as_deref(),
+            at: rfc3339(&warning.at),
+            elapsed_seconds: warning.elapsed_seconds,
+            timeout_seconds: warning.timeout_seconds,
+            expected_duration_seconds: warning.expected_duration_seconds,
+            scheduled_at: warning.scheduled_at.as_ref().map(rfc3339),
+            state: warning.state,
+            previous_state: warning.previous_state,
+            summary: &warning.summary,
+        }
+    }
+}
+
+pub struct Notifier {
+    datastore: Arc<Datastore>,
+    agent_event_urls: Vec<String>,
+    job_warning_urls: Vec<String>,
+    paging_channels: Vec<PagingChannel>,
+    max_age: Duration,
+    client: reqwest::Client,
+}
+
+impl Notifier {
+    pub fn try_new(
+        datastore: Arc<Datastore>,
+        agent_event_urls: Vec<String>,
+        job_warning_urls: Vec<String>,
+        paging_channels: Vec<PagingChannel>,
+        max_age: Duration,
+    ) -> Result<Self, Box<dyn Error>> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECONDS))
+            .build()?;
+        Ok(Self {
+            datastore,
+            agent_event_urls,
+            job_warning_urls,
+            paging_channels,
+            max_age,
+            client,
+        })
+    }
+
+    /// Sends pending events while leading, until central command stops.
+    pub async fn start(self, leadership: Leadership) {
+        info!(
+            "Sending agent events to {} webhook(s), job warnings to {} webhook(s) and pages to {} paging channel(s)",
+            self.agent_event_urls.len(),
+            self.job_warning_urls.len(),
+            self.paging_channels.len()
+        );
+        loop {
+            if leadership.is_leader()
+                && !self.agent_event_urls.is_empty()
+                && let Err(e) = self.notify_agent_events().await
+            {
+                error!("Failed to send agent events: {}", e);
+            }
+            if leadership.is_leader()
+                && !self.job_warning_urls.is_empty()
+                && let Err(e) = self.notify_job_warnings().await
+            {
+                error!("Failed to send job warnings: {}", e);
+            }
+            if leadership.is_leader()
+                && !self.paging_channels.is_empty()
+                && let Err(e) = self.notify_pages().await
+            {
+                error!("Failed to send pages: {}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(NOTIFY_INTERVAL_SECONDS)).await;
+        }
+    }
+
+    fn cutoff(&self) -> i64 {
+        DateTime::now().timestamp_millis() - self.max_age.as_millis() as i64
+    }
+
+    /// Sends the pending events in order, stopping at the first one a webhook did not accept so
+    /// that later events are not delivered ahead of it.
+    async fn notify_agent_events(&self) -> Result<(), Box<dyn Error>> {
+        let events =
+            AgentEventV1::pending_notifications(&self.datastore, NOTIFY_BATCH_SIZE).await?;
+        let cutoff = self.cutoff();
+
+        for event in events {
+            if event.at.timestamp_millis() < cutoff {
+                warn!(
+                    "Not sending {} event for agent {} from {}: it is too old",
+                    event.kind, event.agent_name, event.at
+                );
+            } else if let Err(e) = self
+                .send(&self.agent_event_urls, &AgentEventPayload::from(&event))
+                .await
+            {
+                warn!(
+                    "Failed to send {} event for agent {}, retrying: {}",
+                    event.kind, event.agent_name, e
+                );
+                return Ok(());
+            }
+            event.mark_notified(&self.datastore).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the pending job warnings in order, like `notify_agent_events`.
+    async fn notify_job_warnings(&self) -> Result<(), Box<dyn Error>> {
+        let warnings =
+            JobWarningV1::pending_notifications(&self.datastore, NOTIFY_BATCH_SIZE).await?;
+        let cutoff = self.cutoff();
+
+        for warning in warnings {
+            if warning.at.timestamp_millis() < cutoff {
+                warn!(
+                    "Not sending {} warning for job {} on agent {} from {}: it is too old",
+                    warning.kind, warning.job_name, warning.agent_name, warning.at
+                );
+            } else if self.is_acknowledged(&warning).await? {
+                info!(
+                    "Not sending {} warning for job {} on agent {}: its alert is acknowledged",
+                    warning.kind, warning.job_name, warning.agent_name
+                );
+            } else if let Err(e) = self
+                .send(&self.job_warning_urls, &JobWarningPayload::from(&warning))
+                .await
+            {
+                warn!(
+                    "Failed to send {} warning for job {} on agent {}, retrying: {}",
+                    warning.kind, warning.job_name, warning.agent_name, e
+                );
+                return Ok(());
+            }
+            warning.mark_notified(&self.datastore).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the status of the alerts that page to every paging channel, like
+    /// `notify_agent_events`: triggers an incident for an open alert, and acknowledges or resolves
+    /// the incident of an alert that triggered one.
+    async fn notify_pages(&self) -> Result<(), Box<dyn Error>> {
+        let alerts = AlertV1::pending_pages(&self.datastore, NOTIFY_BATCH_SIZE).await?;
+        let cutoff = self.cutoff();
+
+        for alert in alerts {
+            let action = match alert.status {
+                AlertStatus::Open if alert.last_at.timestamp_millis() < cutoff => {
+                    warn!(
+                        "Not paging for job {} on agent {} from {}: it is too old",
+                        alert.job_name, alert.agent_name, alert.last_at
+                    );
+                    None
+                }
+                AlertStatus::Open => Some(PageAction::Trigger),
+                AlertStatus::Acknowledged if alert.paged => Some(PageAction::Acknowledge),
+                AlertStatus::Resolved if alert.paged => Some(PageAction::Resolve),
+                AlertStatus::Acknowledged | AlertStatus::Resolved => None,
+            };
+            if let Some(action) = action {
+                for channel in &self.paging_channels {
+                    if let Err(e) = channel.send(&self.client, &alert, action).await {
+                        warn!(
+                            "Failed to {} the {} incident of job {} on agent {}, retrying: {}",
+                            action, channel, alert.job_name, alert.agent_name, e
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+            let paged = alert.paged || action == Some(PageAction::Trigger);
+            alert.mark_paged(&self.datastore, paged).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether the alert the warning repeats is acknowledged, so it is not sent again.
+    async fn is_acknowledged(&self, warning: &JobWarningV1) -> Result<bool, Box<dyn Error>> {
+        let Some(kind) = AlertKind::from_warning(warning.kind) else {
+            return Ok(false);
+        };
+        AlertV1::is_acknowledged(
+            &self.datastore,
+            kind,
+            &warning.job_name,
+            &warning.agent_name,
+        )
+        .await
+    }
+
+    async fn send(&self, urls: &[String], payload: &impl Serialize) -> Result<(), Box<dyn Error>> {
+        let body = serde_json::to_vec(payload)?;
+        for url in urls {
+            self.client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .body(body.clone())
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        Ok(())
+    }
+}
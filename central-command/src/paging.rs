@@ -0,0 +1,200 @@
+/// Paging channels page the on-call through PagerDuty or Opsgenie when runs of important jobs
+/// fail, as the `Notifier` sends the failed run alerts (see `core_logic::datastore::alerts`) of
+/// jobs whose severity pages.
+///
+/// # Overview
+/// - A failed run of a job whose `sla.severity` is one of `PAGE_SEVERITIES` triggers an incident,
+///   once per alert. Further failures are counted on the alert without paging again.
+/// - Acknowledging the alert on the alerts page acknowledges the incident, and resolving it, or
+///   a successful run of the job on the agent, resolves the incident.
+/// - The alert's id is the incident's dedup key (PagerDuty) or alias (Opsgenie), so an event that
+///   is retried after a failure does not open a second incident.
+///
+/// # Configuration
+/// - `PAGERDUTY_ROUTING_KEY`: The integration key of a PagerDuty service using the Events API v2.
+/// - `PAGERDUTY_EVENTS_URL`: The Events API v2 endpoint (default:
+///   `https://events.pagerduty.com/v2/enqueue`).
+/// - `OPSGENIE_API_KEY`: An Opsgenie API integration key.
+/// - `OPSGENIE_API_URL`: The Opsgenie API (default: `https://api.opsgenie.com`, or
+///   `https://api.eu.opsgenie.com` for EU accounts).
+/// - `PAGE_SEVERITIES`: The comma separated job severities that page (default: `critical`). Jobs
+///   without a severity are `warning`.
+///
+/// The job's severity is the PagerDuty event severity when it is one PagerDuty knows (`critical`,
+/// `error`, `warning` or `info`), and `critical` otherwise. Opsgenie priorities are `P1` for
+/// `critical`, `P2` for `error`, `P3` for `warning` and others, and `P5` for `info`.
+use serde_json::json;
+
+use std::error::Error;
+
+use crate::{get_opsgenie_api_key, get_page_severities, get_pagerduty_routing_key};
+use core_logic::datastore::alerts::AlertV1;
+
+/// Severity of the jobs whose SLA sets none.
+pub const DEFAULT_SEVERITY: &str = "warning";
+/// Longest Opsgenie alert message, in characters.
+const OPSGENIE_MAX_MESSAGE_CHARS: usize = 130;
+
+/// What a page tells the paging service about an alert's incident.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageAction {
+    Trigger,
+    Acknowledge,
+    Resolve,
+}
+
+impl PageAction {
+    fn name(&self) -> &'static str {
+        match self {
+            PageAction::Trigger => "trigger",
+            PageAction::Acknowledge => "acknowledge",
+            PageAction::Resolve => "resolve",
+        }
+    }
+}
+
+impl std::fmt::Display for PageAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PagingChannel {
+    PagerDuty { routing_key: String, url: String },
+    Opsgenie { api_key: String, url: String },
+}
+
+impl std::fmt::Display for PagingChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PagingChannel::PagerDuty { .. } => write!(f, "PagerDuty"),
+            PagingChannel::Opsgenie { .. } => write!(f, "Opsgenie"),
+        }
+    }
+}
+
+/// The configured paging channels.
+pub fn channels() -> Vec<PagingChannel> {
+    let mut channels = vec![];
+    if let Some((routing_key, url)) = get_pagerduty_routing_key() {
+        channels.push(PagingChannel::PagerDuty {
+            routing_key: routing_key.to_string(),
+            url: url.to_string(),
+        });
+    }
+    if let Some((api_key, url)) = get_opsgenie_api_key() {
+        channels.push(PagingChannel::Opsgenie {
+            api_key: api_key.to_string(),
+            url: url.trim_end_matches('/').to_string(),
+        });
+    }
+    channels
+}
+
+/// The severity of a job with the SLA severity `severity`, when its failures page, which needs a
+/// paging channel.
+pub fn paging_severity(severity: Option<&str>) -> Option<String> {
+    let severity = severity.unwrap_or(DEFAULT_SEVERITY).to_lowercase();
+    let configured = get_pagerduty_routing_key().is_some() || get_opsgenie_api_key().is_some();
+    (configured && get_page_severities().contains(&severity)).then_some(severity)
+}
+
+fn summary(alert: &AlertV1) -> String {
+    format!(
+        "Job {} failed on {}: {}",
+        alert.job_name, alert.agent_name, alert.detail
+    )
+}
+
+impl PagingChannel {
+    /// Sends `action` for the incident of `alert`.
+    pub async fn send(
+        &self,
+        client: &reqwest::Client,
+        alert: &AlertV1,
+        action: PageAction,
+    ) -> Result<(), Box<dyn Error>> {
+        let id = alert.id.map(|id| id.to_hex()).unwrap_or_default();
+        let severity = alert.severity.as_deref().unwrap_or(DEFAULT_SEVERITY);
+        let (request, body) = match self {
+            PagingChannel::PagerDuty { routing_key, url } => {
+                let mut event = json!({
+                    "routing_key": routing_key,
+                    "event_action": action.name(),
+                    "dedup_key": id,
+                });
+                if action == PageAction::Trigger {
+                    let severity = match severity {
+                        "critical" | "error" | "warning" | "info" => severity,
+                        _ => "critical",
+                    };
+                    event["payload"] = json!({
+                        "summary": summary(alert),
+                        "source": alert.agent_name,
+                        "severity": severity,
+                        "component": alert.job_name,
+                        "class": alert.kind.name(),
+                        "timestamp": alert.last_at.try_to_rfc3339_string().ok(),
+                        "custom_details": {
+                            "run_id": alert.run_id,
+                            "occurrences": alert.occurrences,
+                        },
+                    });
+                }
+                (client.post(url), event)
+            }
+            PagingChannel::Opsgenie { api_key, url } => {
+                let (request, body) = match action {
+                    PageAction::Trigger => {
+                        let priority = match severity {
+                            "critical" => "P1",
+                            "error" => "P2",
+                            "info" => "P5",
+                            _ => "P3",
+                        };
+                        let opsgenie_alert = json!({
+                            "message": summary(alert)
+                                .chars()
+                                .take(OPSGENIE_MAX_MESSAGE_CHARS)
+                                .collect::<String>(),
+                            "alias": id,
+                            "description": alert.detail,
+                            "priority": priority,
+                            "source": "rust-action-dispatch",
+                            "entity": alert.job_name,
+                            "details": {
+                                "agent_name": alert.agent_name,
+                                "run_id": alert.run_id.as_deref().unwrap_or_default(),
+                            },
+                        });
+                        (client.post(format!("{}/v2/alerts", url)), opsgenie_alert)
+                    }
+                    PageAction::Acknowledge | PageAction::Resolve => {
+                        let endpoint = match action {
+                            PageAction::Acknowledge => "acknowledge",
+                            _ => "close",
+                        };
+                        let url =
+                            format!("{}/v2/alerts/{}/{}?identifierType=alias", url, id, endpoint);
+                        (
+                            client.post(url),
+                            json!({ "source": "rust-action-dispatch" }),
+                        )
+                    }
+                };
+                (
+                    request.header("Authorization", format!("GenieKey {}", api_key)),
+                    body,
+                )
+            }
+        };
+        request
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_vec(&body)?)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
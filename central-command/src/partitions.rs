@@ -0,0 +1,225 @@
+/// `PartitionClaims` spreads the agents of a large fleet across the central command instances
+/// that share a MongoDB, so each instance dials, pings and dispatches to a share of them.
+///
+/// # Overview
+/// - Agents belong to one of `AGENT_PARTITIONS` partitions, from a hash of their name.
+/// - Each instance holds a `dispatcher:<id>` lease in the `leases` collection while it runs, and
+///   claims `agent_partition:<n>` leases up to its share: the partitions divided by the instances
+///   holding a dispatcher lease, rounded up.
+/// - Claims are renewed every third of `LEADER_LEASE_SECONDS`. An instance holding more than its
+///   share, e.g. because another one started, releases the extra partitions for it to claim.
+/// - When an instance disappears its leases expire, the share of the others grows and they claim
+///   its partitions, so its agents are dialed again by their new holders.
+/// - An instance only dials agents in the partitions it holds, closing its connections to agents
+///   of partitions it gave up, and dispatches each job cycle to those agents. Every holder of a
+///   partition among the cycle's agents dispatches to its own, so a cycle may span instances.
+/// - A partition the instance could not renew is given up when the lease it last renewed runs
+///   out by its own clock, before another instance can claim it.
+/// - Agents connected over reverse dispatch or gRPC are reached by the instance they connected
+///   to, whatever their partition.
+/// - Tasks that cover the whole fleet, such as marking silent agents offline, webhooks and
+///   retention, are still only run by the leader (see `leader`).
+///
+/// # Configuration
+/// - `AGENT_PARTITIONS`: Number of partitions, the same on every instance and best a few times
+///   the number of instances (default: 0, agents are not partitioned and the leader reaches all).
+/// - `LEADER_LEASE_SECONDS`: How long partition and dispatcher leases last without renewal.
+/// - `CENTRAL_COMMAND_ID`: Name of the instance in its leases. Instances need distinct ids.
+///
+/// # Example
+/// ```rust
+/// let claims = PartitionClaims::new(datastore, get_central_command_id(), 64, lease);
+/// let partitions = claims.partitions();
+/// spawn(claims.clone().start());
+/// if partitions.owns("web-1") {
+///     // Dial web-1
+/// }
+/// claims.release().await;
+/// ```
+use tokio::time::{Instant, sleep};
+use tracing::{error, info, warn};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use core_logic::datastore::Datastore;
+use core_logic::datastore::leases::{
+    DISPATCHER_LEASE_PREFIX, LeaseV1, PARTITION_LEASE_PREFIX, agent_partition, dispatcher_lease,
+    partition_lease,
+};
+
+/// The agent partitions this instance holds, shared by the tasks that reach agents.
+#[derive(Debug, Clone)]
+pub struct AgentPartitions {
+    count: u32,
+    held_until: Arc<Mutex<HashMap<u32, Instant>>>,
+}
+
+impl AgentPartitions {
+    /// Partitions of an instance that does not partition agents and reaches all of them.
+    pub fn all() -> Self {
+        Self {
+            count: 0,
+            held_until: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.count > 0
+    }
+
+    /// The partition `agent_name` belongs to.
+    pub fn of(&self, agent_name: &str) -> u32 {
+        agent_partition(agent_name, self.count)
+    }
+
+    /// Whether this instance reaches `agent_name`, i.e. holds its partition.
+    pub fn owns(&self, agent_name: &str) -> bool {
+        !self.is_enabled() || self.held().contains(&self.of(agent_name))
+    }
+
+    /// The partitions this instance holds, in order.
+    pub fn held(&self) -> Vec<u32> {
+        let now = Instant::now();
+        let mut held: Vec<u32> = self
+            .lock()
+            .iter()
+            .filter(|(_, until)| now < **until)
+            .map(|(partition, _)| *partition)
+            .collect();
+        held.sort();
+        held
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u32, Instant>> {
+        self.held_until
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[derive(Clone)]
+pub struct PartitionClaims {
+    datastore: Arc<Datastore>,
+    instance_id: String,
+    lease: Duration,
+    partitions: AgentPartitions,
+}
+
+impl PartitionClaims {
+    pub fn new(datastore: Arc<Datastore>, instance_id: &str, count: u32, lease: Duration) -> Self {
+        Self {
+            datastore,
+            instance_id: instance_id.to_string(),
+            lease,
+            partitions: AgentPartitions {
+                count,
+                held_until: Arc::new(Mutex::new(HashMap::new())),
+            },
+        }
+    }
+
+    pub fn partitions(&self) -> AgentPartitions {
+        self.partitions.clone()
+    }
+
+    /// Claims, renews and rebalances partitions until central command stops.
+    pub async fn start(self) {
+        info!(
+            "Sharing {} agent partitions as {} with a {} second lease",
+            self.partitions.count,
+            self.instance_id,
+            self.lease.as_secs()
+        );
+        loop {
+            let claimed = self.claim().await.map_err(|e| e.to_string()); // Box<dyn Error> is not Send
+            if let Err(e) = claimed {
+                error!("Failed to claim agent partitions: {}", e);
+            }
+            sleep(self.lease / 3).await;
+        }
+    }
+
+    /// Renews the partitions held, then releases those above this instance's share or claims
+    /// free ones up to it.
+    async fn claim(&self) -> Result<(), Box<dyn Error>> {
+        let attempted = Instant::now();
+        let until = attempted + self.lease;
+        let datastore = &self.datastore;
+        let id = &self.instance_id;
+        LeaseV1::try_acquire(datastore, &dispatcher_lease(id), id, self.lease).await?;
+        let dispatchers = LeaseV1::live(datastore, DISPATCHER_LEASE_PREFIX)
+            .await?
+            .len()
+            .max(1);
+        let share = self.partitions.count.div_ceil(dispatchers as u32) as usize;
+
+        let mut held = vec![];
+        let previously_held: Vec<u32> = self.partitions.lock().keys().copied().collect();
+        for partition in previously_held {
+            if LeaseV1::try_acquire(datastore, &partition_lease(partition), id, self.lease).await? {
+                held.push(partition);
+            } else {
+                warn!(
+                    "Agent partition {} was taken over by another instance",
+                    partition
+                );
+                self.partitions.lock().remove(&partition);
+            }
+        }
+        held.sort();
+
+        // Agents of released partitions are no longer reached before their leases are given up.
+        let released = held.split_off(share.min(held.len()));
+        self.set_held(&held, until);
+        for partition in released {
+            LeaseV1::release(datastore, &partition_lease(partition), id).await?;
+            info!("Released agent partition {} to rebalance", partition);
+        }
+
+        if held.len() < share {
+            let taken: Vec<String> = LeaseV1::live(datastore, PARTITION_LEASE_PREFIX)
+                .await?
+                .into_iter()
+                .map(|lease| lease.name)
+                .collect();
+            for partition in 0..self.partitions.count {
+                if held.len() >= share {
+                    break;
+                }
+                let name = partition_lease(partition);
+                if held.contains(&partition) || taken.contains(&name) {
+                    continue;
+                }
+                if LeaseV1::try_acquire(datastore, &name, id, self.lease).await? {
+                    info!("Claimed agent partition {}", partition);
+                    held.push(partition);
+                }
+            }
+            self.set_held(&held, until);
+        }
+        Ok(())
+    }
+
+    fn set_held(&self, held: &[u32], until: Instant) {
+        *self.partitions.lock() = held.iter().map(|partition| (*partition, until)).collect();
+    }
+
+    /// Stops reaching agents and releases the partitions and the dispatcher lease, so the other
+    /// instances claim them without waiting for them to expire.
+    pub async fn release(&self) {
+        let held: Vec<u32> = self.partitions.lock().drain().map(|(p, _)| p).collect();
+        let id = &self.instance_id;
+        for partition in held {
+            if let Err(e) = LeaseV1::release(&self.datastore, &partition_lease(partition), id).await
+            {
+                error!("Failed to release agent partition {}: {}", partition, e);
+            }
+        }
+        if let Err(e) = LeaseV1::release(&self.datastore, &dispatcher_lease(id), id).await {
+            error!("Failed to release the dispatcher lease: {}", e);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+/// Pluggable choice of which due jobs the `AgentManager` starts and which agents they run on.
+///
+/// # Overview
+/// - `SCHEDULER_STRATEGY` selects the strategy: `default` is the only one built in.
+/// - Each dispatch check, the pending jobs that are due, not held back by a blackout window and
+///   targeting a connected agent are offered to `select_jobs`, oldest `next_run` first. The jobs
+///   it returns are started, in its order; the others stay pending and are offered again on the
+///   next check.
+/// - When a cycle starts, `assign_agents` picks the agents it runs on from the job's
+///   `agents_required` and the connected members of its `agent_groups`. The result is recorded as
+///   the job's `cycle_agents`. Draining agents are skipped after assignment, whatever the strategy.
+///
+/// # Strategies
+/// - `default`: Starts every due job and runs each on all of its candidate agents.
+///
+/// # Adding a strategy
+/// Implement `SchedulerStrategy` and add it to `scheduler_from_env` under a new name. Strategies
+/// such as priority ordering, bin-packing or fair-share only need to reorder or filter the jobs
+/// and agents they are given.
+use std::env;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use core_logic::datastore::jobs::JobV1;
+
+/// Decides which due jobs start and where they run.
+pub trait SchedulerStrategy: Send + Sync + Debug {
+    /// Name of the strategy, for logging.
+    fn name(&self) -> &'static str;
+
+    /// The jobs of `due` to start now, in the order they are dispatched. `connected_agents` are
+    /// the agents that can be dispatched to.
+    fn select_jobs(&self, due: Vec<JobV1>, connected_agents: &[String]) -> Vec<JobV1>;
+
+    /// The agents of `candidates` a new cycle of `job` runs on. An empty list falls back to the
+    /// job's `agents_required`.
+    fn assign_agents(&self, job: &JobV1, candidates: Vec<String>) -> Vec<String>;
+}
+
+/// The strategy selected by `SCHEDULER_STRATEGY`.
+pub fn scheduler_from_env() -> Arc<dyn SchedulerStrategy> {
+    let strategy = env::var("SCHEDULER_STRATEGY")
+        .unwrap_or_default()
+        .to_lowercase();
+    match strategy.as_str() {
+        "" | "default" => Arc::new(DefaultScheduler),
+        other => panic!("Invalid SCHEDULER_STRATEGY {}", other),
+    }
+}
+
+/// Starts every due job on every candidate agent.
+#[derive(Debug, Default)]
+pub struct DefaultScheduler;
+
+impl SchedulerStrategy for DefaultScheduler {
+    fn name(&self) -> &'static str {
+        "default"
+    }
+
+    fn select_jobs(&self, due: Vec<JobV1>, _connected_agents: &[String]) -> Vec<JobV1> {
+        due
+    }
+
+    fn assign_agents(&self, _job: &JobV1, candidates: Vec<String>) -> Vec<String> {
+        candidates
+    }
+}
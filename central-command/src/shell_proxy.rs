@@ -0,0 +1,167 @@
+/// The `ShellProxy` relays operators' remote shells between the web UI and agents, so operators
+/// can debug an agent's host without SSH access to it.
+///
+/// # Overview
+/// - Listens on `SHELL_PROXY_ADDRESS`. The web UI opens one connection per session and sends
+///   `OpenShell` as a frame, preceded by `Authenticate` carrying `SHELL_PROXY_TOKEN` when it is
+///   set.
+/// - Dials the agent's listen port, writes `OpenShell` and waits for the agent's `OK`. From then
+///   on bytes are copied unchanged both ways until either side closes its connection (see the
+///   Remote Shells section of `core_logic::messages`).
+/// - A session that cannot be opened is answered with a `CloseShell` saying why.
+/// - Every session is logged with its id, the operator, the agent, how long it lasted and the
+///   bytes sent each way. Which operators may open shells is decided and audited by the web UI.
+///
+/// # Notes
+/// - Agents only run shells when started with `AGENT_REMOTE_SHELL`.
+/// - Agents using reverse dispatch or the message bus cannot be dialed, so they cannot run remote
+///   shells.
+/// - Anyone who can reach `SHELL_PROXY_ADDRESS` can ask for a shell on any agent that allows
+///   them, so bind it to loopback or a private network and set `SHELL_PROXY_TOKEN`.
+use bson::doc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::spawn;
+use tokio::time::timeout;
+use tracing::{error, info, warn};
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::auth::constant_time_eq;
+use crate::get_tcp_keepalive_seconds;
+use core_logic::datastore::{Datastore, agents::AgentV1};
+use core_logic::framing;
+use core_logic::keepalive;
+use core_logic::messages::{CloseShell, Credential, Message, OpenShell};
+
+/// Time allowed to open the session: reading the web UI's request, dialing the agent and its `OK`.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest frame accepted from the web UI before the session is open.
+const MAX_OPEN_FRAME: usize = 64 * 1024;
+
+pub struct ShellProxy {
+    datastore: Arc<Datastore>,
+    token: Option<String>,
+}
+
+impl ShellProxy {
+    pub fn new(datastore: Arc<Datastore>, token: Option<String>) -> Self {
+        Self { datastore, token }
+    }
+
+    /// Accepts sessions from the web UI on `address` until the listener fails.
+    pub async fn serve(self, address: &str) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(address).await?;
+        info!("Relaying remote shells on {}", listener.local_addr()?);
+        let proxy = Arc::new(self);
+        loop {
+            let (stream, peer_addr) = listener.accept().await?;
+            let proxy = proxy.clone();
+            spawn(async move { proxy.relay(stream, peer_addr).await });
+        }
+    }
+
+    async fn relay(&self, mut webui: TcpStream, peer_addr: SocketAddr) {
+        let opened = timeout(OPEN_TIMEOUT, self.open(&mut webui))
+            .await
+            .map_err(|_| "Timed out opening the session".to_string())
+            .and_then(|opened| opened.map_err(|e| e.to_string()));
+        let (open, mut agent) = match opened {
+            Ok(opened) => opened,
+            Err(reason) => return Self::refuse(webui, peer_addr, reason).await,
+        };
+        info!(
+            session_id = %open.session_id,
+            "Remote shell on {} opened for {} from {}", open.agent_name, open.user, peer_addr
+        );
+
+        let started = Instant::now();
+        let result = tokio::io::copy_bidirectional(&mut webui, &mut agent).await;
+        let elapsed = started.elapsed().as_secs();
+        match result {
+            Ok((to_agent, from_agent)) => info!(
+                session_id = %open.session_id,
+                "Remote shell on {} for {} closed after {} seconds ({} bytes in, {} bytes out)",
+                open.agent_name,
+                open.user,
+                elapsed,
+                to_agent,
+                from_agent
+            ),
+            Err(e) => warn!(
+                session_id = %open.session_id,
+                "Remote shell on {} for {} failed after {} seconds: {}",
+                open.agent_name,
+                open.user,
+                elapsed,
+                e
+            ),
+        }
+    }
+
+    /// Reads the web UI's request and opens the shell on the agent.
+    async fn open(&self, webui: &mut TcpStream) -> Result<(OpenShell, TcpStream), Box<dyn Error>> {
+        let mut message = Self::read_message(webui).await?;
+        if let Some(token) = &self.token {
+            let authenticated = match &message {
+                Message::Authenticate(authenticate) => match &authenticate.credential {
+                    Credential::Token(presented) => {
+                        constant_time_eq(presented.as_bytes(), token.as_bytes())
+                    }
+                    Credential::JwtSvid(_) => false,
+                },
+                _ => false,
+            };
+            if !authenticated {
+                return Err("Invalid or missing SHELL_PROXY_TOKEN".into());
+            }
+            message = Self::read_message(webui).await?;
+        }
+        let Message::OpenShell(open) = message else {
+            return Err(format!("Expected OpenShell, got {}", message.kind()).into());
+        };
+
+        let collection = self.datastore.get_collection::<AgentV1>("agents").await?;
+        let agent = collection
+            .find_one(doc! { "name": &open.agent_name })
+            .await?
+            .ok_or_else(|| format!("Agent {} not found", open.agent_name))?;
+        let address = format!("{}:{}", agent.hostname, agent.port);
+        let mut stream = TcpStream::connect(&address)
+            .await
+            .map_err(|e| format!("Failed to connect to {} at {}: {}", agent.name, address, e))?;
+        let idle = Duration::from_secs(get_tcp_keepalive_seconds());
+        if let Err(e) = keepalive::enable(&stream, idle) {
+            warn!("Failed to enable TCP keepalive: {}", e);
+        }
+        Message::OpenShell(open.clone())
+            .tcp_write(&mut stream)
+            .await?;
+        let mut ok = [0u8; 2];
+        stream.read_exact(&mut ok).await?;
+        if &ok != b"OK" {
+            return Err(format!("Agent {} did not acknowledge OpenShell", agent.name).into());
+        }
+        Ok((open, stream))
+    }
+
+    async fn read_message(stream: &mut TcpStream) -> Result<Message, Box<dyn Error>> {
+        let frame = framing::read_frame(stream, MAX_OPEN_FRAME)
+            .await?
+            .ok_or("Connection closed before the session was opened")?;
+        Ok(Message::try_from(frame)?)
+    }
+
+    /// Tells the web UI why its session could not be opened and closes the connection.
+    async fn refuse(mut webui: TcpStream, peer_addr: SocketAddr, reason: String) {
+        error!("Refused remote shell from {}: {}", peer_addr, reason);
+        let close = Message::CloseShell(CloseShell { reason });
+        if let Ok(frame) = close.to_frame() {
+            let _ = webui.write_all(&frame).await;
+        }
+        let _ = webui.shutdown().await;
+    }
+}
@@ -0,0 +1,438 @@
+/// The `SshExecutor` runs the jobs whose `executor` is `ssh` (see
+/// `core_logic::datastore::jobs::JobExecutor`) over SSH, on hosts where the agent cannot be
+/// installed.
+///
+/// # Overview
+/// - Central command stands in for an agent named `ssh` (`SSH_EXECUTOR_AGENT`), as for the
+///   Kubernetes executor (see `kubernetes_executor`): the `AgentManager` dispatches the jobs' runs
+///   to it like to any channel agent, and their results are recorded as runs of the `ssh` agent.
+/// - Each run connects to the job's `host` as its `user` with the system's `ssh` client,
+///   authenticating with the private key in the job's `key_secret`, and runs the job's command line
+///   through the user's login shell, like agents with `AGENT_SHELL=sh`. The job's `cwd` and `env`
+///   are applied with `cd` and `export`, so the remote shell has to be POSIX.
+/// - The remote command's exit code and its output, stdout then stderr, redacted like agent output
+///   and cut at `MAX_OUTPUT_BYTES`, are recorded as the run's result, exactly like a `JobComplete`
+///   from an agent. Exit code 255 means `ssh` itself failed, e.g. the host was unreachable.
+/// - A run still going at the job's timeout, or whose job is cancelled, has its connection closed
+///   and is recorded as timed out or cancelled. `ExtendTimeout` moves its deadline.
+/// - Host keys are checked against `SSH_KNOWN_HOSTS_FILE`: hosts have to be known before jobs run
+///   on them, unless `SSH_ACCEPT_NEW_HOST_KEYS` is set.
+///
+/// # Secrets
+/// A `key_secret` names a file in `SECRETS_DIR`, where Docker and Kubernetes mount secrets, e.g.
+///
+/// ```yaml
+/// volumes:
+///   - name: ssh-keys
+///     secret: { secretName: ssh-keys } # Keys of the secret become file names, e.g. switch-key
+/// ```
+///
+/// The key is read for every run, so rotated keys are picked up, and copied to a file only
+/// central command can read for the run's duration. Its contents never reach the logs or runs.
+///
+/// # Configuration
+/// - `SSH_EXECUTOR`: `true` starts the executor (default: `false`).
+/// - `SECRETS_DIR`: Directory of the secrets jobs name (default: `/run/secrets`).
+/// - `SSH_KNOWN_HOSTS_FILE`: Known host keys (default: those of the user central command runs as).
+/// - `SSH_ACCEPT_NEW_HOST_KEYS`: `true` trusts the key of a host seen for the first time and adds
+///   it to the known hosts (default: `false`).
+use bson::{DateTime, doc};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::spawn;
+use tokio::sync::{Mutex, Notify, mpsc};
+use tokio::time::{Instant, interval, sleep};
+use tracing::{Instrument, debug, error, info, warn};
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::VERSION;
+use crate::agent_channels::AgentChannels;
+use crate::agent_manager::AgentManager;
+use crate::command_receiver::CommandReceiver;
+use core_logic::datastore::{
+    Datastore,
+    jobs::{JobExecutor, JobV1, SSH_EXECUTOR_AGENT},
+};
+use core_logic::logging;
+use core_logic::messages::{DispatchJob, JobComplete, JobOutCome, Message, RegisterAgent};
+use core_logic::redaction::Redactor;
+
+const DEFAULT_SECRETS_DIR: &str = "/run/secrets";
+const CHANNEL_CAPACITY: usize = 100;
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const CONNECT_TIMEOUT_SECONDS: u32 = 10;
+/// Bytes of a run's output kept as the run's output.
+const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// A run on a host, stopped when its job is cancelled or its deadline passes.
+#[derive(Debug, Clone)]
+struct RunningCommand {
+    run_id: Option<String>,
+    cancel: Arc<Notify>,
+    deadline: Arc<Mutex<Option<Instant>>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SshExecutor {
+    datastore: Arc<Datastore>,
+    agent_channels: AgentChannels,
+    secrets_dir: PathBuf,
+    known_hosts_file: Option<String>,
+    accept_new_host_keys: bool,
+    running: Arc<Mutex<HashMap<String, Vec<RunningCommand>>>>, // By job name
+}
+
+impl SshExecutor {
+    pub fn new(datastore: Arc<Datastore>, agent_channels: AgentChannels) -> Self {
+        Self {
+            datastore,
+            agent_channels,
+            secrets_dir: env::var("SECRETS_DIR")
+                .unwrap_or_else(|_| DEFAULT_SECRETS_DIR.to_string())
+                .into(),
+            known_hosts_file: env::var("SSH_KNOWN_HOSTS_FILE").ok(),
+            accept_new_host_keys: env::var("SSH_ACCEPT_NEW_HOST_KEYS")
+                .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false),
+            running: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Registers the executor's agent and runs the jobs dispatched to it until central command
+    /// stops.
+    pub async fn run(self) -> Result<(), Box<dyn Error>> {
+        info!(
+            "Running ssh executor jobs over SSH with the keys in {}",
+            self.secrets_dir.display()
+        );
+        self.handle(Message::RegisterAgent(RegisterAgent {
+            name: SSH_EXECUTOR_AGENT.to_string(),
+            hostname: SSH_EXECUTOR_AGENT.to_string(),
+            port: 0,
+            version: VERSION.to_string(),
+            timezone: "UTC".to_string(),
+            locale: String::new(),
+            receipt_public_key: None,
+            os: String::new(),
+            arch: String::new(),
+            kernel: String::new(),
+            shells: vec!["sh".to_string()],
+            features: vec![],
+            container: None,
+        }))
+        .await;
+
+        let (sender, mut receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        self.agent_channels
+            .register(SSH_EXECUTOR_AGENT, sender)
+            .await;
+        let mut heartbeat = interval(HEARTBEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                message = receiver.recv() => match message {
+                    Some(message) => self.receive(message).await,
+                    None => break,
+                },
+                _ = heartbeat.tick() => {
+                    if let Err(e) =
+                        AgentManager::update_agent_online(self.datastore.clone(), SSH_EXECUTOR_AGENT).await
+                    {
+                        error!("Failed to update the ssh executor to online: {}", e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a message the `AgentManager` sent the executor's agent.
+    async fn receive(&self, message: Message) {
+        match message {
+            Message::DispatchJob(dispatch) => {
+                let executor = self.clone();
+                let span = logging::run_span(
+                    dispatch.run_id.as_deref(),
+                    &dispatch.job_name,
+                    SSH_EXECUTOR_AGENT,
+                );
+                spawn(async move { executor.execute(dispatch).await }.instrument(span));
+            }
+            Message::DispatchBatch(dispatches) => {
+                for dispatch in dispatches {
+                    Box::pin(self.receive(Message::DispatchJob(dispatch))).await;
+                }
+            }
+            Message::CancelJob(cancel) => {
+                let running = self.running.lock().await.get(&cancel.job_name).cloned();
+                for command in running.into_iter().flatten() {
+                    info!(
+                        "Cancelling run {} of {}",
+                        command.run_id.as_deref().unwrap_or_default(),
+                        cancel.job_name
+                    );
+                    command.cancel.notify_one();
+                }
+            }
+            Message::ExtendTimeout(extend) => {
+                let running = self.running.lock().await.get(&extend.job_name).cloned();
+                for command in running.into_iter().flatten() {
+                    if let Some(deadline) = command.deadline.lock().await.as_mut() {
+                        *deadline += Duration::from_secs(extend.seconds as u64);
+                    }
+                }
+            }
+            message => debug!("Ssh executor ignores {:?}", message.kind()),
+        }
+    }
+
+    /// Handles a message from the executor's agent as the `CommandReceiver` does those of agents.
+    async fn handle(&self, message: Message) {
+        let peer_addr = SocketAddr::from(([0, 0, 0, 0], 0));
+        if let Err(e) =
+            CommandReceiver::handle_message(message, self.datastore.clone(), peer_addr, None).await
+        {
+            error!("Failed to handle ssh executor message: {}", e);
+        }
+    }
+
+    /// Runs `dispatch` over SSH and records its result.
+    async fn execute(&self, dispatch: DispatchJob) {
+        let started_at = DateTime::now().timestamp_millis();
+        let (return_code, outcome, output, truncated) = match self.run_command(&dispatch).await {
+            Ok(result) => result,
+            Err(e) => {
+                error!("SSH run of {} failed: {}", dispatch.job_name, e);
+                let output = format!("Ssh executor: {}", e);
+                (-1, JobOutCome::Failure, output, false)
+            }
+        };
+        let redactor = Redactor::with_defaults(&dispatch.redact_patterns).unwrap_or_default();
+        let (command, args) = (
+            redactor.redact(&dispatch.command),
+            redactor.redact(&dispatch.args),
+        );
+        self.handle(Message::JobComplete(JobComplete {
+            started_at,
+            completed_at: DateTime::now().timestamp_millis(),
+            job_name: dispatch.job_name,
+            command: format!("{} {}", command, args).trim().to_string(),
+            agent_name: SSH_EXECUTOR_AGENT.to_string(),
+            return_code,
+            outcome,
+            output: redactor.redact(&output),
+            triggered_by: dispatch.triggered_by,
+            truncated,
+            artifact: None,
+            signature: None,
+            run_id: dispatch.run_id,
+            steps: vec![],
+            timeout_extension: 0,
+        }))
+        .await;
+    }
+
+    /// Runs the command of `dispatch` on the job's host, returning its exit code, the run's
+    /// outcome, its output and whether the output was cut.
+    async fn run_command(
+        &self,
+        dispatch: &DispatchJob,
+    ) -> Result<(i32, JobOutCome, String, bool), Box<dyn Error + Send + Sync>> {
+        let jobs = self
+            .datastore
+            .get_collection::<JobV1>("jobs")
+            .await
+            .map_err(|e| e.to_string())?;
+        let job = jobs
+            .find_one(doc! { "name": &dispatch.job_name })
+            .await?
+            .ok_or_else(|| format!("Job {} no longer exists", dispatch.job_name))?;
+        let JobExecutor::Ssh {
+            host,
+            port,
+            user,
+            key_secret,
+        } = &job.executor
+        else {
+            return Err(format!("Job {} is no longer run by the ssh executor", job.name).into());
+        };
+        let key = tokio::fs::read(self.secrets_dir.join(key_secret))
+            .await
+            .map_err(|e| format!("Unable to read secret {}: {}", key_secret, e))?;
+        let key_file = KeyFile::write(&key)?;
+
+        let mut command = Command::new("ssh");
+        command
+            .arg("-i")
+            .arg(&key_file.path)
+            .args(["-o", "BatchMode=yes", "-o", "IdentitiesOnly=yes"])
+            .arg("-o")
+            .arg(format!("ConnectTimeout={}", CONNECT_TIMEOUT_SECONDS))
+            .arg("-o")
+            .arg(match self.accept_new_host_keys {
+                true => "StrictHostKeyChecking=accept-new",
+                false => "StrictHostKeyChecking=yes",
+            });
+        if let Some(known_hosts_file) = &self.known_hosts_file {
+            command
+                .arg("-o")
+                .arg(format!("UserKnownHostsFile={}", known_hosts_file));
+        }
+        if let Some(port) = port {
+            command.arg("-p").arg(port.to_string());
+        }
+        command
+            .arg("-l")
+            .arg(user)
+            .arg("--")
+            .arg(host)
+            .arg(Self::remote_command_line(&job))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let mut child = command.spawn()?;
+        let stdout = spawn(read_capped(child.stdout.take().ok_or("No stdout")?));
+        let stderr = spawn(read_capped(child.stderr.take().ok_or("No stderr")?));
+
+        let running = RunningCommand {
+            run_id: dispatch.run_id.clone(),
+            cancel: Arc::new(Notify::new()),
+            deadline: Arc::new(Mutex::new(
+                dispatch
+                    .timeout
+                    .map(|timeout| Instant::now() + Duration::from_secs(timeout as u64)),
+            )),
+        };
+        self.running
+            .lock()
+            .await
+            .entry(job.name.clone())
+            .or_default()
+            .push(running.clone());
+        let stopped = loop {
+            tokio::select! {
+                status = child.wait() => break Ok(status?),
+                _ = running.cancel.notified() => break Err(JobOutCome::Cancelled),
+                _ = sleep(Duration::from_secs(1)) => {
+                    if running.deadline.lock().await.is_some_and(|deadline| deadline <= Instant::now()) {
+                        break Err(JobOutCome::TimedOut);
+                    }
+                }
+            }
+        };
+        {
+            let mut by_job = self.running.lock().await;
+            let commands = by_job.entry(job.name.clone()).or_default();
+            commands.retain(|command| !Arc::ptr_eq(&command.cancel, &running.cancel));
+            if commands.is_empty() {
+                by_job.remove(&job.name);
+            }
+        }
+        if stopped.is_err() {
+            child.kill().await?;
+        }
+        let (mut output, stdout_cut) = stdout.await?;
+        let (stderr, stderr_cut) = stderr.await?;
+        output.extend_from_slice(&stderr);
+        let truncated = stdout_cut || stderr_cut || output.len() > MAX_OUTPUT_BYTES;
+        output.truncate(MAX_OUTPUT_BYTES);
+        let output = String::from_utf8_lossy(&output).into_owned();
+
+        let valid_return_codes = dispatch.valid_return_codes.clone().unwrap_or(vec![0]);
+        Ok(match stopped {
+            Err(outcome) => (-1, outcome, output, truncated),
+            Ok(status) => {
+                let return_code = status.code().unwrap_or(-1);
+                let outcome = match valid_return_codes.contains(&return_code) {
+                    true => JobOutCome::Success,
+                    false => JobOutCome::Failure,
+                };
+                (return_code, outcome, output, truncated)
+            }
+        })
+    }
+
+    /// The job's command line with its `cwd` and `env` applied, for a POSIX shell.
+    fn remote_command_line(job: &JobV1) -> String {
+        let (command, args, env) = match &job.rerun {
+            Some(rerun) => (&rerun.command, &rerun.args, &rerun.env),
+            None => (&job.command, &job.args, &job.env),
+        };
+        let mut line = String::new();
+        if !job.cwd.is_empty() {
+            line.push_str(&format!("cd {} && ", shell_quote(&job.cwd)));
+        }
+        for var in env {
+            line.push_str(&format!("export {} && ", shell_quote(var)));
+        }
+        line.push_str(&format!("{} {}", command, args.join(" ")));
+        line.trim().to_string()
+    }
+}
+
+/// `value` single quoted for a POSIX shell.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Reads `reader` to its end, keeping its first `MAX_OUTPUT_BYTES` and whether there was more.
+/// The rest is read and dropped so the remote command is never blocked on a full pipe.
+async fn read_capped(mut reader: impl AsyncRead + Unpin) -> (Vec<u8>, bool) {
+    let mut kept = Vec::new();
+    let mut cut = false;
+    let mut buffer = [0u8; 8192];
+    while let Ok(read) = reader.read(&mut buffer).await {
+        if read == 0 {
+            break;
+        }
+        let room = MAX_OUTPUT_BYTES.saturating_sub(kept.len());
+        kept.extend_from_slice(&buffer[..read.min(room)]);
+        cut |= read > room;
+    }
+    (kept, cut)
+}
+
+/// A private key copied to a file only central command can read, as `ssh` refuses keys others
+/// can read. The file is removed when dropped.
+struct KeyFile {
+    path: PathBuf,
+}
+
+impl KeyFile {
+    fn write(key: &[u8]) -> Result<Self, std::io::Error> {
+        use std::io::Write;
+
+        let path = env::temp_dir().join(format!("rad-ssh-{}", Uuid::new_v4()));
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&path)?;
+        file.write_all(key)?;
+        // OpenSSH refuses keys without a final newline.
+        if !key.ends_with(b"\n") {
+            file.write_all(b"\n")?;
+        }
+        Ok(Self { path })
+    }
+}
+
+impl Drop for KeyFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
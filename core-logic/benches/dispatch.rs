@@ -0,0 +1,148 @@
+//! Benchmarks of the message handling central command and the agents do for every dispatch:
+//! serializing `DispatchJob`s and `DispatchBatch`es, framing them, and receiving `JobComplete`s,
+//! both copied and deserialized and read in place.
+//!
+//! Run with `cargo bench -p core-logic`, optionally followed by a substring of the names of the
+//! benchmarks to run, e.g. `cargo bench -p core-logic -- receive`. Use `loadgen` to measure the
+//! pipeline end to end.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use core_logic::flow_control::{ChunkSizer, MIN_CHUNK_SIZE};
+use core_logic::framing::{self, FrameReader};
+use core_logic::messages::{
+    DispatchJob, JobComplete, JobOutCome, Message, MessageBuffer, TriggeredBy,
+};
+
+const WARM_UP: Duration = Duration::from_millis(200);
+const MEASURE: Duration = Duration::from_secs(1);
+const BATCH_SIZE: usize = 50;
+const OUTPUT_BYTES: usize = 4096;
+
+/// Runs `f` repeatedly for `MEASURE`, after warming up, and prints the time each run took.
+fn bench<T>(filter: Option<&str>, name: &str, mut f: impl FnMut() -> T) {
+    if filter.is_some_and(|filter| !name.contains(filter)) {
+        return;
+    }
+    let warm_up = Instant::now();
+    while warm_up.elapsed() < WARM_UP {
+        black_box(f());
+    }
+    let mut iterations: u32 = 0;
+    let started = Instant::now();
+    while started.elapsed() < MEASURE {
+        black_box(f());
+        iterations += 1;
+    }
+    let per_iteration = started.elapsed() / iterations.max(1);
+    println!(
+        "{:<36} {:>12?}/iter ({} iterations)",
+        name, per_iteration, iterations
+    );
+}
+
+fn dispatch_job(i: usize) -> DispatchJob {
+    DispatchJob {
+        job_name: format!("job-{}", i),
+        command: "/usr/local/bin/backup".to_string(),
+        args: "--incremental --target /var/backups".to_string(),
+        agent_name: Some(format!("agent-{}", i)),
+        valid_return_codes: Some(vec![0]),
+        timeout: Some(3600),
+        triggered_by: TriggeredBy::Scheduler,
+        redact_patterns: vec![],
+        run_id: Some(format!("{:024x}", i)),
+        steps: vec![],
+        files: vec![],
+        script: None,
+        container: None,
+        env: vec![],
+    }
+}
+
+fn job_complete() -> Message {
+    Message::JobComplete(JobComplete {
+        started_at: 1_700_000_000_000,
+        completed_at: 1_700_000_060_000,
+        job_name: "job-0".to_string(),
+        command: "/usr/local/bin/backup".to_string(),
+        agent_name: "agent-0".to_string(),
+        return_code: 0,
+        outcome: JobOutCome::Success,
+        output: "x".repeat(OUTPUT_BYTES),
+        triggered_by: TriggeredBy::Scheduler,
+        truncated: false,
+        artifact: None,
+        signature: None,
+        run_id: Some(format!("{:024x}", 0)),
+        steps: vec![],
+        timeout_extension: 0,
+    })
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; the first other argument filters the benchmarks.
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let filter = filter.as_deref();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("Failed to build runtime");
+
+    let job = Message::DispatchJob(dispatch_job(0));
+    bench(filter, "serialize DispatchJob", || {
+        Vec::<u8>::try_from(job.clone()).unwrap()
+    });
+    let batch = Message::DispatchBatch((0..BATCH_SIZE).map(dispatch_job).collect());
+    bench(filter, "serialize DispatchBatch of 50", || {
+        Vec::<u8>::try_from(batch.clone()).unwrap()
+    });
+
+    let serialized: Vec<u8> = job_complete().try_into().unwrap();
+    bench(filter, "frame JobComplete", || {
+        framing::encode_frame(&serialized)
+    });
+    bench(filter, "receive JobComplete, deserialized", || {
+        Message::try_from(serialized.clone()).unwrap()
+    });
+    let mut buffer = MessageBuffer::new();
+    buffer
+        .slice_mut(serialized.len())
+        .copy_from_slice(&serialized);
+    bench(filter, "receive JobComplete, in place", || {
+        buffer.message(serialized.len()).unwrap().kind()
+    });
+
+    // Central command's read loop: the header, then the body in adaptive chunks.
+    let frame = framing::encode_frame(&serialized);
+    let mut chunk_sizer = ChunkSizer::new(MIN_CHUNK_SIZE, true);
+    bench(filter, "read JobComplete frame, deserialized", || {
+        runtime.block_on(async {
+            let mut reader = frame.as_slice();
+            let header = FrameReader::new(usize::MAX)
+                .read_header(&mut reader)
+                .await
+                .unwrap()
+                .unwrap();
+            let body = framing::read_frame_body(&mut reader, header.len, &mut chunk_sizer)
+                .await
+                .unwrap();
+            header.verify(&body).unwrap();
+            Message::try_from(body).unwrap()
+        })
+    });
+    bench(filter, "read JobComplete frame, in place", || {
+        runtime.block_on(async {
+            let mut reader = frame.as_slice();
+            let header = FrameReader::new(usize::MAX)
+                .read_header(&mut reader)
+                .await
+                .unwrap()
+                .unwrap();
+            framing::read_frame_body_into(&mut reader, header.len, &mut chunk_sizer, &mut buffer)
+                .await
+                .unwrap();
+            header.verify(buffer.bytes(header.len)).unwrap();
+            buffer.message(header.len).unwrap().kind()
+        })
+    });
+}
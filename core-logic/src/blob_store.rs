@@ -0,0 +1,451 @@
+//! This module defines pluggable storage for bulky data kept out of MongoDB documents, such as
+//! large run outputs and the files jobs push to their agents. Every central command and web UI
+//! instance must be configured with the same store, which `Datastore::try_new` opens from
+//! `BLOB_STORE_URL`.
+//!
+//! # Keys
+//!
+//! Blobs are stored under `/` separated keys whose first segment names what they hold, e.g.
+//! `run_outputs/<id>` or `job_files/<name>`. Keys may not contain empty, `.` or `..` segments.
+//! Putting a blob under an existing key replaces it.
+//!
+//! # Backends
+//!
+//! - GridFS (`gridfs://`, the default), in the datastore's database. The first segment of a key
+//!   is the bucket, e.g. `run_outputs`, and the rest the file name; the latest revision is read.
+//! - Local disk (`file:///var/lib/rust_action_dispatch/blobs`), with a file per key under the
+//!   directory. Instances on several hosts must share it, e.g. over NFS.
+//! - S3 and compatible stores (`s3://<bucket>/<prefix>`), with path-style requests signed with
+//!   AWS Signature Version 4. Configured with `S3_ENDPOINT` (default:
+//!   `https://s3.<region>.amazonaws.com`), `S3_REGION` or `AWS_REGION` (default: `us-east-1`),
+//!   `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and, for temporary credentials,
+//!   `AWS_SESSION_TOKEN`.
+//!
+//! Other stores can be supported by implementing `BlobStore` and adding a scheme to `open`.
+//! Blobs are not moved when the store changes; copy them to the new store under the same keys.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use core_logic::blob_store::BlobError;
+//! use core_logic::datastore::Datastore;
+//!
+//! async fn copy(datastore: &Datastore, from: &str, to: &str) -> Result<(), BlobError> {
+//!     let blobs = datastore.blob_store();
+//!     let contents = blobs.get(from.to_string()).await?;
+//!     blobs.put(to.to_string(), contents).await
+//! }
+//! ```
+use futures::future::BoxFuture;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum BlobError {
+    InvalidKey(String),
+    NotFound(String),
+    Storage(String),
+    UnsupportedScheme(String),
+}
+
+impl std::fmt::Display for BlobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlobError::InvalidKey(key) => write!(f, "Invalid blob key: {}", key),
+            BlobError::NotFound(key) => write!(f, "Blob not found: {}", key),
+            BlobError::Storage(e) => write!(f, "Blob store error: {}", e),
+            BlobError::UnsupportedScheme(url) => write!(f, "Unsupported blob store URL: {}", url),
+        }
+    }
+}
+
+impl std::error::Error for BlobError {}
+
+/// Storage for blobs addressed by key.
+pub trait BlobStore: Send + Sync + Debug {
+    /// Name of the backend, for logging.
+    fn name(&self) -> &'static str;
+
+    /// Stores `contents` under `key`, replacing any blob already stored there.
+    fn put(&self, key: String, contents: Vec<u8>) -> BoxFuture<'_, Result<(), BlobError>>;
+
+    /// The contents stored under `key`.
+    fn get(&self, key: String) -> BoxFuture<'_, Result<Vec<u8>, BlobError>>;
+}
+
+/// Opens the store at `url`, picking the backend from the URL scheme. `db` holds the GridFS
+/// buckets.
+pub fn open(url: &str, db: mongodb::Database) -> Result<Arc<dyn BlobStore>, BlobError> {
+    if url.is_empty() || url == "gridfs://" {
+        return Ok(Arc::new(gridfs::GridFsStore { db }));
+    }
+    if let Some(root) = url.strip_prefix("file://") {
+        if root.is_empty() {
+            return Err(BlobError::UnsupportedScheme(url.to_string()));
+        }
+        return Ok(Arc::new(local::LocalStore { root: root.into() }));
+    }
+    if let Some(location) = url.strip_prefix("s3://") {
+        return Ok(Arc::new(s3::S3Store::from_env(location)?));
+    }
+    Err(BlobError::UnsupportedScheme(url.to_string()))
+}
+
+/// The segments of `key`, refusing keys that could escape their store.
+fn key_segments(key: &str) -> Result<Vec<&str>, BlobError> {
+    let segments: Vec<&str> = key.split('/').collect();
+    if segments.len() < 2
+        || segments
+            .iter()
+            .any(|segment| segment.is_empty() || *segment == "." || *segment == "..")
+    {
+        return Err(BlobError::InvalidKey(key.to_string()));
+    }
+    Ok(segments)
+}
+
+mod gridfs {
+    use futures::{AsyncReadExt, AsyncWriteExt, FutureExt};
+    use mongodb::error::{ErrorKind, GridFsErrorKind};
+    use mongodb::gridfs::GridFsBucket;
+    use mongodb::options::GridFsBucketOptions;
+
+    use super::*;
+
+    #[derive(Debug)]
+    pub struct GridFsStore {
+        pub db: mongodb::Database,
+    }
+
+    impl GridFsStore {
+        /// The bucket and file name `key` is stored under.
+        fn locate(&self, key: &str) -> Result<(GridFsBucket, String), BlobError> {
+            let segments = key_segments(key)?;
+            let options = GridFsBucketOptions::builder()
+                .bucket_name(segments[0].to_string())
+                .build();
+            Ok((self.db.gridfs_bucket(options), segments[1..].join("/")))
+        }
+    }
+
+    fn storage_error(e: impl std::fmt::Display) -> BlobError {
+        BlobError::Storage(e.to_string())
+    }
+
+    impl BlobStore for GridFsStore {
+        fn name(&self) -> &'static str {
+            "gridfs"
+        }
+
+        fn put(&self, key: String, contents: Vec<u8>) -> BoxFuture<'_, Result<(), BlobError>> {
+            async move {
+                let (bucket, name) = self.locate(&key)?;
+                let mut upload = bucket
+                    .open_upload_stream(name)
+                    .await
+                    .map_err(storage_error)?;
+                upload.write_all(&contents).await.map_err(storage_error)?;
+                upload.close().await.map_err(storage_error)
+            }
+            .boxed()
+        }
+
+        fn get(&self, key: String) -> BoxFuture<'_, Result<Vec<u8>, BlobError>> {
+            async move {
+                let (bucket, name) = self.locate(&key)?;
+                let mut download = match bucket.open_download_stream_by_name(name).await {
+                    Ok(download) => download,
+                    Err(e) => {
+                        return Err(match *e.kind {
+                            ErrorKind::GridFs(GridFsErrorKind::FileNotFound { .. }) => {
+                                BlobError::NotFound(key)
+                            }
+                            _ => storage_error(e),
+                        });
+                    }
+                };
+                let mut contents = vec![];
+                download
+                    .read_to_end(&mut contents)
+                    .await
+                    .map_err(storage_error)?;
+                Ok(contents)
+            }
+            .boxed()
+        }
+    }
+}
+
+mod local {
+    use futures::FutureExt;
+
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[derive(Debug)]
+    pub struct LocalStore {
+        pub root: PathBuf,
+    }
+
+    impl LocalStore {
+        fn path(&self, key: &str) -> Result<PathBuf, BlobError> {
+            Ok(key_segments(key)?
+                .into_iter()
+                .fold(self.root.clone(), |path, segment| path.join(segment)))
+        }
+    }
+
+    impl BlobStore for LocalStore {
+        fn name(&self) -> &'static str {
+            "file"
+        }
+
+        fn put(&self, key: String, contents: Vec<u8>) -> BoxFuture<'_, Result<(), BlobError>> {
+            async move {
+                let path = self.path(&key)?;
+                let storage_error =
+                    |e: std::io::Error| BlobError::Storage(format!("{}: {}", path.display(), e));
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent)
+                        .await
+                        .map_err(storage_error)?;
+                }
+                // Written aside and renamed, so readers never see part of a blob.
+                let mut partial = path.clone().into_os_string();
+                partial.push(".partial");
+                tokio::fs::write(&partial, &contents)
+                    .await
+                    .map_err(storage_error)?;
+                tokio::fs::rename(&partial, &path)
+                    .await
+                    .map_err(storage_error)
+            }
+            .boxed()
+        }
+
+        fn get(&self, key: String) -> BoxFuture<'_, Result<Vec<u8>, BlobError>> {
+            async move {
+                let path = self.path(&key)?;
+                tokio::fs::read(&path).await.map_err(|e| match e.kind() {
+                    ErrorKind::NotFound => BlobError::NotFound(key.clone()),
+                    _ => BlobError::Storage(format!("{}: {}", path.display(), e)),
+                })
+            }
+            .boxed()
+        }
+    }
+}
+
+mod s3 {
+    use futures::FutureExt;
+    use reqwest::{Method, StatusCode, Url};
+    use sha2::{Digest, Sha256};
+
+    use std::env;
+
+    use super::*;
+
+    const DEFAULT_REGION: &str = "us-east-1";
+
+    pub struct S3Store {
+        client: reqwest::Client,
+        endpoint: Url,
+        region: String,
+        bucket: String,
+        prefix: String,
+        access_key_id: String,
+        secret_access_key: String,
+        session_token: Option<String>,
+    }
+
+    impl Debug for S3Store {
+        // Leaves the credentials out.
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("S3Store")
+                .field("endpoint", &self.endpoint.as_str())
+                .field("region", &self.region)
+                .field("bucket", &self.bucket)
+                .field("prefix", &self.prefix)
+                .finish()
+        }
+    }
+
+    fn storage_error(e: impl std::fmt::Display) -> BlobError {
+        BlobError::Storage(e.to_string())
+    }
+
+    /// `value` percent-encoded as SigV4 expects, keeping `/` when `keep_slash` is set.
+    fn uri_encode(value: &str, keep_slash: bool) -> String {
+        value
+            .bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (byte as char).to_string()
+                }
+                b'/' if keep_slash => "/".to_string(),
+                _ => format!("%{:02X}", byte),
+            })
+            .collect()
+    }
+
+    fn hmac(key: &[u8], message: &str) -> Vec<u8> {
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key);
+        ring::hmac::sign(&key, message.as_bytes()).as_ref().to_vec()
+    }
+
+    impl S3Store {
+        /// The store for `location`, `<bucket>/<prefix>`, with the endpoint and credentials set in
+        /// the environment.
+        pub fn from_env(location: &str) -> Result<Self, BlobError> {
+            let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+            if bucket.is_empty() {
+                return Err(BlobError::UnsupportedScheme(format!("s3://{}", location)));
+            }
+            let required = |name: &str| {
+                env::var(name)
+                    .ok()
+                    .filter(|value| !value.is_empty())
+                    .ok_or_else(|| {
+                        BlobError::Storage(format!("{} is required for S3 blob stores", name))
+                    })
+            };
+            let region = env::var("S3_REGION")
+                .or_else(|_| env::var("AWS_REGION"))
+                .unwrap_or_else(|_| DEFAULT_REGION.to_string());
+            let endpoint = env::var("S3_ENDPOINT")
+                .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region));
+            Ok(Self {
+                client: reqwest::Client::new(),
+                endpoint: Url::parse(&endpoint).map_err(storage_error)?,
+                region,
+                bucket: bucket.to_string(),
+                prefix: prefix.trim_matches('/').to_string(),
+                access_key_id: required("AWS_ACCESS_KEY_ID")?,
+                secret_access_key: required("AWS_SECRET_ACCESS_KEY")?,
+                session_token: env::var("AWS_SESSION_TOKEN")
+                    .ok()
+                    .filter(|token| !token.is_empty()),
+            })
+        }
+
+        /// Sends a request for the object under `key`, signed with SigV4.
+        async fn send(
+            &self,
+            method: Method,
+            key: &str,
+            body: Vec<u8>,
+        ) -> Result<reqwest::Response, BlobError> {
+            key_segments(key)?;
+            let object = match self.prefix.is_empty() {
+                true => key.to_string(),
+                false => format!("{}/{}", self.prefix, key),
+            };
+            let path = format!(
+                "{}/{}/{}",
+                self.endpoint.path().trim_end_matches('/'),
+                self.bucket,
+                object
+            );
+            let path = uri_encode(&path, true);
+            let mut url = self.endpoint.clone();
+            url.set_path(&path);
+            let host = match url.port() {
+                Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+                None => url.host_str().unwrap_or_default().to_string(),
+            };
+
+            let now = chrono::Utc::now();
+            let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+            let date = now.format("%Y%m%d").to_string();
+            let payload_hash = hex::encode(Sha256::digest(&body));
+            let mut headers = vec![
+                ("host", host),
+                ("x-amz-content-sha256", payload_hash.clone()),
+                ("x-amz-date", amz_date.clone()),
+            ];
+            if let Some(token) = &self.session_token {
+                headers.push(("x-amz-security-token", token.clone()));
+            }
+            let canonical_headers: String = headers
+                .iter()
+                .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+                .collect();
+            let signed_headers = headers
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(";");
+            let canonical_request = format!(
+                "{}\n{}\n\n{}\n{}\n{}",
+                method, path, canonical_headers, signed_headers, payload_hash
+            );
+            let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+            let string_to_sign = format!(
+                "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+                amz_date,
+                scope,
+                hex::encode(Sha256::digest(canonical_request.as_bytes()))
+            );
+            let signing_key = ["s3", "aws4_request"].iter().fold(
+                hmac(
+                    &hmac(format!("AWS4{}", self.secret_access_key).as_bytes(), &date),
+                    &self.region,
+                ),
+                |key, part| hmac(&key, part),
+            );
+            let authorization = format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key_id,
+                scope,
+                signed_headers,
+                hex::encode(hmac(&signing_key, &string_to_sign))
+            );
+
+            let mut request = self
+                .client
+                .request(method, url)
+                .header("authorization", authorization);
+            for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+                request = request.header(name, value);
+            }
+            request.body(body).send().await.map_err(storage_error)
+        }
+    }
+
+    impl BlobStore for S3Store {
+        fn name(&self) -> &'static str {
+            "s3"
+        }
+
+        fn put(&self, key: String, contents: Vec<u8>) -> BoxFuture<'_, Result<(), BlobError>> {
+            async move {
+                self.send(Method::PUT, &key, contents)
+                    .await?
+                    .error_for_status()
+                    .map_err(storage_error)?;
+                Ok(())
+            }
+            .boxed()
+        }
+
+        fn get(&self, key: String) -> BoxFuture<'_, Result<Vec<u8>, BlobError>> {
+            async move {
+                let response = self.send(Method::GET, &key, vec![]).await?;
+                if response.status() == StatusCode::NOT_FOUND {
+                    return Err(BlobError::NotFound(key));
+                }
+                let contents = response
+                    .error_for_status()
+                    .map_err(storage_error)?
+                    .bytes()
+                    .await
+                    .map_err(storage_error)?;
+                Ok(contents.to_vec())
+            }
+            .boxed()
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! This module defines a pluggable message bus transport for deployments where central command
+//! cannot dial agents (e.g. agents behind NAT). Agents and central command both make outbound
+//! connections to a broker and exchange the same rkyv serialized `Message`s as the TCP protocol.
+//!
+//! # Subjects
+//!
+//! - `rust_action_dispatch.agent.<agent_name>`: Messages from central command to an agent
+//!   (`DispatchJob`, `CancelJob`, `UpdateAgent`, ...).
+//! - `rust_action_dispatch.central.<agent_name>`: Messages from an agent to central command
+//!   (`RegisterAgent`, `Ping`, `JobComplete`). The agent name is taken from the subject.
+//!
+//! Agent names used with the bus must not contain whitespace or the `*` and `>` wildcards.
+//!
+//! # Backends
+//!
+//! - NATS (`nats://` URLs), enabled with the `nats` feature.
+//! - In-process (`memory://<name>` URLs), for central command and an agent running in the same
+//!   process, such as the all-in-one binary. Every `memory://` URL with the same name connects to
+//!   the same bus within the process.
+//!
+//! Other brokers can be supported by implementing `MessageBus` and adding a scheme to `connect`.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use core_logic::bus::{self, agent_subject};
+//! use core_logic::messages::Message;
+//!
+//! async fn ping(agent_name: &str) -> Result<(), bus::BusError> {
+//!     let bus = bus::connect("nats://127.0.0.1:4222").await?;
+//!     bus.publish(agent_subject(agent_name), Message::Ping).await
+//! }
+//! ```
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+
+use std::sync::Arc;
+
+use crate::messages::Message;
+
+pub const SUBJECT_PREFIX: &str = "rust_action_dispatch";
+
+/// Subject central command publishes to in order to reach `agent_name`.
+pub fn agent_subject(agent_name: &str) -> String {
+    format!("{}.agent.{}", SUBJECT_PREFIX, agent_name)
+}
+
+/// Subject `agent_name` publishes to in order to reach central command.
+pub fn central_subject(agent_name: &str) -> String {
+    format!("{}.central.{}", SUBJECT_PREFIX, agent_name)
+}
+
+/// Subject matching messages from every agent to central command.
+pub fn central_wildcard_subject() -> String {
+    format!("{}.central.>", SUBJECT_PREFIX)
+}
+
+/// Extracts the agent name from a subject built by `central_subject`.
+pub fn agent_name_from_central_subject(subject: &str) -> Option<&str> {
+    subject
+        .strip_prefix(SUBJECT_PREFIX)
+        .and_then(|rest| rest.strip_prefix(".central."))
+        .filter(|name| !name.is_empty())
+}
+
+/// A message received from the bus along with the subject it was published on.
+#[derive(Debug, Clone)]
+pub struct BusMessage {
+    pub subject: String,
+    pub message: Message,
+}
+
+#[derive(Debug)]
+pub enum BusError {
+    Connect(String),
+    Publish(String),
+    Subscribe(String),
+    Serialization(String),
+    UnsupportedScheme(String),
+}
+
+impl std::fmt::Display for BusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BusError::Connect(e) => write!(f, "Bus connect error: {}", e),
+            BusError::Publish(e) => write!(f, "Bus publish error: {}", e),
+            BusError::Subscribe(e) => write!(f, "Bus subscribe error: {}", e),
+            BusError::Serialization(e) => write!(f, "Bus serialization error: {}", e),
+            BusError::UnsupportedScheme(url) => write!(f, "Unsupported message bus URL: {}", url),
+        }
+    }
+}
+
+impl std::error::Error for BusError {}
+
+/// A publish/subscribe broker carrying `Message`s between central command and agents.
+pub trait MessageBus: Send + Sync {
+    /// Publishes `message` on `subject`.
+    fn publish(&self, subject: String, message: Message) -> BoxFuture<'_, Result<(), BusError>>;
+
+    /// Subscribes to `subject`, which may contain broker specific wildcards.
+    /// Payloads that fail to deserialize are logged and skipped.
+    fn subscribe(
+        &self,
+        subject: String,
+    ) -> BoxFuture<'_, Result<BoxStream<'static, BusMessage>, BusError>>;
+}
+
+/// Connects to the broker at `url`, picking the backend from the URL scheme.
+pub async fn connect(url: &str) -> Result<Arc<dyn MessageBus>, BusError> {
+    if let Some(name) = url.strip_prefix("memory://") {
+        return Ok(memory::MemoryBus::named(name));
+    }
+    #[cfg(feature = "nats")]
+    if url.starts_with("nats://") || url.starts_with("tls://") {
+        return Ok(Arc::new(nats::NatsBus::connect(url).await?));
+    }
+    Err(BusError::UnsupportedScheme(url.to_string()))
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use futures::{FutureExt, StreamExt};
+    use tracing::error;
+
+    use super::*;
+
+    pub struct NatsBus {
+        client: async_nats::Client,
+    }
+
+    impl NatsBus {
+        pub async fn connect(url: &str) -> Result<Self, BusError> {
+            let client = async_nats::connect(url)
+                .await
+                .map_err(|e| BusError::Connect(e.to_string()))?;
+            Ok(Self { client })
+        }
+    }
+
+    impl MessageBus for NatsBus {
+        fn publish(
+            &self,
+            subject: String,
+            message: Message,
+        ) -> BoxFuture<'_, Result<(), BusError>> {
+            async move {
+                let payload: Vec<u8> = message
+                    .try_into()
+                    .map_err(|e: rkyv::rancor::Error| BusError::Serialization(e.to_string()))?;
+                self.client
+                    .publish(subject, payload.into())
+                    .await
+                    .map_err(|e| BusError::Publish(e.to_string()))?;
+                self.client
+                    .flush()
+                    .await
+                    .map_err(|e| BusError::Publish(e.to_string()))
+            }
+            .boxed()
+        }
+
+        fn subscribe(
+            &self,
+            subject: String,
+        ) -> BoxFuture<'_, Result<BoxStream<'static, BusMessage>, BusError>> {
+            async move {
+                let subscriber = self
+                    .client
+                    .subscribe(subject)
+                    .await
+                    .map_err(|e| BusError::Subscribe(e.to_string()))?;
+                let messages = subscriber.filter_map(|nats_message| async move {
+                    let subject = nats_message.subject.to_string();
+                    match Message::try_from(nats_message.payload.to_vec()) {
+                        Ok(message) => Some(BusMessage { subject, message }),
+                        Err(e) => {
+                            error!("Failed to parse bus message on {}: {}", subject, e);
+                            None
+                        }
+                    }
+                });
+                Ok(messages.boxed())
+            }
+            .boxed()
+        }
+    }
+}
+
+mod memory {
+    use futures::{FutureExt, StreamExt};
+    use tokio::sync::mpsc;
+
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Mutex, OnceLock};
+
+    use super::*;
+
+    static BUSES: OnceLock<Mutex<HashMap<String, Arc<MemoryBus>>>> = OnceLock::new();
+
+    /// Messages kept for subscribers yet to subscribe; the oldest are dropped beyond it.
+    const MAX_UNDELIVERED: usize = 1024;
+
+    /// A bus within the process. Messages are handed to the subscribers whose subject matches
+    /// without being serialized. Unlike NATS, messages no subscriber matched are kept for the
+    /// first that does, so components sharing the process can start in any order.
+    #[derive(Default)]
+    pub struct MemoryBus {
+        state: Mutex<MemoryBusState>,
+    }
+
+    #[derive(Default)]
+    struct MemoryBusState {
+        subscribers: Vec<(String, mpsc::UnboundedSender<BusMessage>)>,
+        undelivered: VecDeque<BusMessage>,
+    }
+
+    impl MemoryBus {
+        /// The process's bus called `name`, created on first use.
+        pub fn named(name: &str) -> Arc<MemoryBus> {
+            let mut buses = BUSES
+                .get_or_init(Default::default)
+                .lock()
+                .expect("Memory bus registry poisoned");
+            buses.entry(name.to_string()).or_default().clone()
+        }
+    }
+
+    /// Whether `subject` matches `pattern`, with NATS wildcards: `*` matches one token and a
+    /// trailing `>` one or more.
+    fn subject_matches(pattern: &str, subject: &str) -> bool {
+        let mut tokens = subject.split('.');
+        for wildcard in pattern.split('.') {
+            match (wildcard, tokens.next()) {
+                (">", Some(_)) => return true,
+                ("*", Some(_)) => {}
+                (wildcard, Some(token)) if wildcard == token => {}
+                _ => return false,
+            }
+        }
+        tokens.next().is_none()
+    }
+
+    impl MessageBus for MemoryBus {
+        fn publish(
+            &self,
+            subject: String,
+            message: Message,
+        ) -> BoxFuture<'_, Result<(), BusError>> {
+            let mut state = self.state.lock().expect("Memory bus poisoned");
+            let mut delivered = false;
+            state.subscribers.retain(|(pattern, sender)| {
+                if !subject_matches(pattern, &subject) {
+                    return true;
+                }
+                let message = BusMessage {
+                    subject: subject.clone(),
+                    message: message.clone(),
+                };
+                let open = sender.send(message).is_ok();
+                delivered |= open;
+                open
+            });
+            if !delivered {
+                if state.undelivered.len() == MAX_UNDELIVERED {
+                    state.undelivered.pop_front();
+                }
+                state.undelivered.push_back(BusMessage { subject, message });
+            }
+            async { Ok(()) }.boxed()
+        }
+
+        fn subscribe(
+            &self,
+            subject: String,
+        ) -> BoxFuture<'_, Result<BoxStream<'static, BusMessage>, BusError>> {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let mut state = self.state.lock().expect("Memory bus poisoned");
+            let (matching, undelivered) = std::mem::take(&mut state.undelivered)
+                .into_iter()
+                .partition(|message| subject_matches(&subject, &message.subject));
+            state.undelivered = undelivered;
+            for message in matching {
+                let _ = sender.send(message);
+            }
+            state.subscribers.push((subject, sender));
+            drop(state);
+            let messages = futures::stream::unfold(receiver, |mut receiver| async move {
+                receiver.recv().await.map(|message| (message, receiver))
+            });
+            async move { Ok(messages.boxed()) }.boxed()
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! This module parses cron expressions and finds when they next fire in a given time zone, so jobs
+//! can be scheduled by wall-clock time (e.g. every weekday at 09:00 in `Europe/Berlin`) rather than
+//! every `schedule_interval` seconds.
+//!
+//! # Syntax
+//!
+//! The five standard fields, separated by whitespace:
+//!
+//! | Field        | Values                        |
+//! |--------------|-------------------------------|
+//! | minute       | `0-59`                        |
+//! | hour         | `0-23`                        |
+//! | day of month | `1-31`                        |
+//! | month        | `1-12` or `JAN`-`DEC`         |
+//! | day of week  | `0-7` (`0` and `7` are Sunday) or `SUN`-`SAT` |
+//!
+//! Each field is `*`, a value, a range `a-b`, a step `*/n`, `a/n` or `a-b/n`, or a comma separated
+//! list of these. As in classic cron, when both the day of month and the day of week are
+//! restricted, a day matching either fires.
+//!
+//! # Daylight saving time
+//!
+//! Times are wall-clock times in the schedule's zone (see `chrono_tz`):
+//! - A time that occurs twice when clocks go back fires once, at its first occurrence.
+//! - A time skipped when clocks go forward fires at the first moment after the gap, so a daily
+//!   02:30 job still runs on the night the clocks change.
+//!
+//! # Example
+//!
+//! ```rust
+//! use core_logic::cron::CronSchedule;
+//!
+//! let schedule = CronSchedule::parse("30 9 * * MON-FRI").unwrap();
+//! let berlin: chrono_tz::Tz = "Europe/Berlin".parse().unwrap();
+//! // Friday 2025-06-06 10:00 UTC, after that day's 09:30 in Berlin (07:30 UTC)
+//! let next = schedule.next_after(1_749_204_000, berlin).unwrap();
+//! // Monday 2025-06-09 09:30 in Berlin, 07:30 UTC
+//! assert_eq!(next, 1_749_454_200);
+//!
+//! // Clocks go forward at 02:00 on 2025-03-30 in Berlin; 02:30 runs at 03:00 (01:00 UTC)
+//! let nightly = CronSchedule::parse("30 2 * * *").unwrap();
+//! assert_eq!(nightly.next_after(1_743_285_600, berlin), Some(1_743_296_400));
+//!
+//! assert!(CronSchedule::parse("60 * * * *").is_err());
+//! ```
+use chrono::{Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+
+/// Days searched for the next firing before giving up, enough for any valid schedule (e.g.
+/// February 29th on a Monday) to fire at least once.
+const MAX_SEARCH_DAYS: i64 = 366 * 28;
+const MONTH_NAMES: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// A parsed cron expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days_of_month: Vec<u32>,
+    months: Vec<u32>,
+    days_of_week: Vec<u32>, // 0 is Sunday
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err(format!(
+                "Cron expression {:?} needs 5 fields: minute hour day-of-month month day-of-week",
+                expression
+            ));
+        };
+        let mut days_of_week = parse_field(day_of_week, "day of week", 0, 7, &WEEKDAY_NAMES, 0)?;
+        if days_of_week.contains(&7) {
+            days_of_week.retain(|day| *day != 7);
+            if !days_of_week.contains(&0) {
+                days_of_week.insert(0, 0);
+            }
+        }
+        Ok(Self {
+            minutes: parse_field(minute, "minute", 0, 59, &[], 0)?,
+            hours: parse_field(hour, "hour", 0, 23, &[], 0)?,
+            days_of_month: parse_field(day_of_month, "day of month", 1, 31, &[], 0)?,
+            months: parse_field(month, "month", 1, 12, &MONTH_NAMES, 1)?,
+            days_of_week,
+            day_of_month_restricted: day_of_month != "*",
+            day_of_week_restricted: day_of_week != "*",
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        if !self.months.contains(&date.month()) {
+            return false;
+        }
+        let day_of_month = self.days_of_month.contains(&date.day());
+        let day_of_week = self
+            .days_of_week
+            .contains(&date.weekday().num_days_from_sunday());
+        match (self.day_of_month_restricted, self.day_of_week_restricted) {
+            (true, true) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+
+    /// When the schedule next fires after `after` (Unix seconds), evaluated in `timezone`, or
+    /// `None` if it never does (e.g. February 30th).
+    pub fn next_after(&self, after: i64, timezone: Tz) -> Option<i64> {
+        let start = timezone.timestamp_opt(after, 0).single()?.date_naive();
+        // Start a day early: the local time `after` falls in may be the end of a DST gap that
+        // times of the previous day resolve to.
+        for offset in -1..MAX_SEARCH_DAYS {
+            let date = start + Duration::days(offset);
+            if !self.matches_day(date) {
+                continue;
+            }
+            for hour in &self.hours {
+                for minute in &self.minutes {
+                    let local = date.and_hms_opt(*hour, *minute, 0)?;
+                    if let Some(at) = resolve(local, timezone)
+                        && at > after
+                    {
+                        return Some(at);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The instant a local time happens in `timezone`: its first occurrence if clocks went back, or
+/// the end of the gap if clocks went forward over it.
+fn resolve(local: NaiveDateTime, timezone: Tz) -> Option<i64> {
+    let mut candidate = local;
+    // Gaps are at most a few hours long.
+    for _ in 0..=24 * 60 {
+        match timezone.from_local_datetime(&candidate) {
+            LocalResult::Single(at) | LocalResult::Ambiguous(at, _) => {
+                return Some(at.timestamp());
+            }
+            LocalResult::None => candidate += Duration::minutes(1),
+        }
+    }
+    None
+}
+
+/// Parses one field into its sorted values. `names` are accepted for values from `name_base` on.
+fn parse_field(
+    field: &str,
+    label: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    name_base: u32,
+) -> Result<Vec<u32>, String> {
+    let value = |text: &str| -> Result<u32, String> {
+        if let Some(index) = names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(text))
+        {
+            return Ok(index as u32 + name_base);
+        }
+        match text.parse::<u32>() {
+            Ok(value) if (min..=max).contains(&value) => Ok(value),
+            _ => Err(format!(
+                "Invalid {} {:?}: expected {} to {}",
+                label, text, min, max
+            )),
+        }
+    };
+
+    let mut values = vec![];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("Invalid {} step {:?}", label, step)),
+            },
+            None => (part, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            // `a/n` runs from `a` to the end of the range.
+            None if part.contains('/') => (value(range)?, max),
+            None => {
+                let value = value(range)?;
+                (value, value)
+            }
+        };
+        if first > last {
+            return Err(format!("Invalid {} range {:?}", label, range));
+        }
+        values.extend((first..=last).step_by(step as usize));
+    }
+    values.sort_unstable();
+    values.dedup();
+    Ok(values)
+}
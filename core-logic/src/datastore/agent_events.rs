@@ -0,0 +1,275 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::datastore::Datastore;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentEventKind {
+    Connected,    // Came online, or back online after being offline
+    Disconnected, // Stopped answering central command
+    Registered,   // Sent `RegisterAgent`, e.g. after starting up
+    Drained,      // An operator set it to drain
+    Undrained,    // An operator set it to accept jobs again
+}
+
+impl AgentEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AgentEventKind::Connected => "connected",
+            AgentEventKind::Disconnected => "disconnected",
+            AgentEventKind::Registered => "registered",
+            AgentEventKind::Drained => "drained",
+            AgentEventKind::Undrained => "undrained",
+        }
+    }
+
+    /// Whether the event changes the agent's connectivity, as opposed to its registration or
+    /// whether it is draining.
+    pub fn is_connectivity(&self) -> bool {
+        matches!(
+            self,
+            AgentEventKind::Connected | AgentEventKind::Disconnected
+        )
+    }
+}
+
+impl std::fmt::Display for AgentEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Agents online during one bucket of `AgentEventV1::online_series`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OnlineBucket {
+    pub start: DateTime,
+    /// The average number of agents online over the bucket, e.g. `1.5` when two agents were
+    /// online for half of it and a third for all of it. For a single agent this is the fraction of
+    /// the bucket it was online.
+    pub online: f64,
+}
+
+/// A change in an agent's lifecycle: going online or offline, registering, or being drained.
+/// Recorded by central command, or by the web UI for drains, so that intermittent connectivity
+/// shows up as a timeline and can be sent to `AGENT_EVENT_WEBHOOK_URLS`. Kept for
+/// `AGENT_EVENT_RETENTION_DAYS` by central command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentEventV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub agent_name: String,
+    pub kind: AgentEventKind,
+    pub at: DateTime,
+    /// Context for the event, e.g. the version an agent registered with.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+    /// Set until central command has sent the event to its webhooks.
+    #[serde(default)]
+    pub notification_pending: bool,
+}
+
+impl AgentEventV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "agent_name": 1, "at": 1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "notification_pending": 1, "at": 1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    pub fn new(agent_name: &str, kind: AgentEventKind) -> Self {
+        Self {
+            id: None,
+            agent_name: agent_name.to_string(),
+            kind,
+            at: DateTime::now(),
+            detail: String::new(),
+            notification_pending: true,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = detail.into();
+        self
+    }
+
+    pub async fn insert_entry(&self, datastore: &Datastore) -> Result<(), Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<AgentEventV1>("agent_events")
+            .await?;
+        collection.insert_one(self).await?;
+        Ok(())
+    }
+
+    /// Events not yet sent to the webhooks, oldest first.
+    pub async fn pending_notifications(
+        datastore: &Datastore,
+        limit: i64,
+    ) -> Result<Vec<AgentEventV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<AgentEventV1>("agent_events")
+            .await?;
+        let events = collection
+            .find(doc! { "notification_pending": true })
+            .sort(doc! { "at": 1 })
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(events)
+    }
+
+    pub async fn mark_notified(&self, datastore: &Datastore) -> Result<(), Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<AgentEventV1>("agent_events")
+            .await?;
+        collection
+            .update_one(
+                doc! { "_id": self.id },
+                doc! { "$set": { "notification_pending": false } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Events matching `filter` from `from` until `to`, oldest first.
+    pub async fn find_between(
+        datastore: &Datastore,
+        mut filter: Document,
+        from: DateTime,
+        to: DateTime,
+        limit: i64,
+    ) -> Result<Vec<AgentEventV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<AgentEventV1>("agent_events")
+            .await?;
+        filter.insert("at", doc! { "$gte": from, "$lt": to });
+        let events = collection
+            .find(filter)
+            .sort(doc! { "at": 1 })
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(events)
+    }
+
+    /// How many of the agents whose events match `filter`, e.g. by `agent_name`, were online from
+    /// `from` until `to`, in buckets of `interval_ms` counted from `from`. Rebuilt from the
+    /// connected and disconnected events, so agents count from the first such event still kept.
+    pub async fn online_series(
+        datastore: &Datastore,
+        mut filter: Document,
+        from: DateTime,
+        to: DateTime,
+        interval_ms: i64,
+    ) -> Result<Vec<OnlineBucket>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<AgentEventV1>("agent_events")
+            .await?;
+        let connectivity = vec![
+            AgentEventKind::Connected.name(),
+            AgentEventKind::Disconnected.name(),
+        ];
+        filter.insert("kind", doc! { "$in": connectivity });
+
+        // Whether each agent was online at `from`, from its last event before then.
+        let mut before = filter.clone();
+        before.insert("at", doc! { "$lt": from });
+        let mut online: HashMap<String, bool> = HashMap::new();
+        let mut cursor = collection
+            .aggregate(vec![
+                doc! { "$match": before },
+                doc! { "$sort": { "agent_name": 1, "at": 1 } },
+                doc! { "$group": { "_id": "$agent_name", "kind": { "$last": "$kind" } } },
+            ])
+            .await?;
+        while let Some(item) = cursor.try_next().await? {
+            online.insert(
+                item.get_str("_id").unwrap_or_default().to_string(),
+                item.get_str("kind") == Ok(AgentEventKind::Connected.name()),
+            );
+        }
+
+        filter.insert("at", doc! { "$gte": from, "$lt": to });
+        let events: Vec<AgentEventV1> = collection
+            .find(filter)
+            .sort(doc! { "at": 1 })
+            .await?
+            .try_collect()
+            .await?;
+
+        let (from, to) = (from.timestamp_millis(), to.timestamp_millis());
+        let interval_ms = interval_ms.max(1);
+        let buckets = ((to - from + interval_ms - 1) / interval_ms).max(0) as usize;
+        let mut online_ms = vec![0i64; buckets];
+        // Adds `count` agents online from `start` until `end` to the buckets they overlap.
+        let mut add = |start: i64, end: i64, count: i64| {
+            let mut at = start;
+            while at < end && count > 0 {
+                let index = ((at - from) / interval_ms) as usize;
+                let bucket_end = (from + (index as i64 + 1) * interval_ms).min(end);
+                if let Some(bucket) = online_ms.get_mut(index) {
+                    *bucket += (bucket_end - at) * count;
+                }
+                at = bucket_end;
+            }
+        };
+
+        let mut at = from;
+        for event in events {
+            let event_at = event.at.timestamp_millis();
+            add(
+                at,
+                event_at,
+                online.values().filter(|online| **online).count() as i64,
+            );
+            online.insert(event.agent_name, event.kind == AgentEventKind::Connected);
+            at = event_at;
+        }
+        add(
+            at,
+            to,
+            online.values().filter(|online| **online).count() as i64,
+        );
+
+        Ok(online_ms
+            .into_iter()
+            .enumerate()
+            .map(|(index, online_ms)| {
+                let start = from + index as i64 * interval_ms;
+                let length = (start + interval_ms).min(to) - start;
+                OnlineBucket {
+                    start: DateTime::from_millis(start),
+                    online: online_ms as f64 / length as f64,
+                }
+            })
+            .collect())
+    }
+
+    /// Deletes events recorded before `cutoff`, returning how many were removed.
+    pub async fn delete_before(
+        datastore: &Datastore,
+        cutoff: DateTime,
+    ) -> Result<u64, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<AgentEventV1>("agent_events")
+            .await?;
+        let result = collection
+            .delete_many(doc! { "at": { "$lt": cutoff } })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
@@ -0,0 +1,114 @@
+use bson::{Document, doc, oid::ObjectId};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+
+/// A named set of agents that jobs can target through `agent_groups`. Central command expands a
+/// job's groups to their members each time it dispatches a cycle, so membership changes apply from
+/// the next cycle without editing the jobs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentGroupV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub members: Vec<String>, // Agent names
+}
+
+impl AgentGroupV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        Datastore::create_unique_index(collection, doc! { "name": 1 }).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "members": 1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    /// The members of `groups`, in group name order and without duplicates. Groups that do not
+    /// exist have no members.
+    pub async fn members_of(
+        datastore: &Datastore,
+        groups: &[String],
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        if groups.is_empty() {
+            return Ok(vec![]);
+        }
+        let collection = datastore
+            .get_collection::<AgentGroupV1>("agent_groups")
+            .await?;
+        let groups: Vec<AgentGroupV1> = collection
+            .find(doc! { "name": { "$in": groups } })
+            .sort(doc! { "name": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        let mut members: Vec<String> = vec![];
+        for member in groups.into_iter().flat_map(|group| group.members) {
+            if !members.contains(&member) {
+                members.push(member);
+            }
+        }
+        Ok(members)
+    }
+
+    /// Names of the groups with at least one of `agents` as a member.
+    pub async fn names_with_members(
+        datastore: &Datastore,
+        agents: &[String],
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<AgentGroupV1>("agent_groups")
+            .await?;
+        let groups: Vec<AgentGroupV1> = collection
+            .find(doc! { "members": { "$in": agents } })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(groups.into_iter().map(|group| group.name).collect())
+    }
+
+    /// Removes a deleted agent from every group, returning how many groups it was in.
+    pub async fn remove_member_everywhere(
+        datastore: &Datastore,
+        agent_name: &str,
+    ) -> Result<u64, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<AgentGroupV1>("agent_groups")
+            .await?;
+        let result = collection
+            .update_many(
+                doc! { "members": agent_name },
+                doc! { "$pull": { "members": agent_name } },
+            )
+            .await?;
+        Ok(result.modified_count)
+    }
+
+    /// Adds a restored agent back to those of `groups` that still exist, returning how many it
+    /// was added to.
+    pub async fn add_member_to(
+        datastore: &Datastore,
+        groups: &[String],
+        agent_name: &str,
+    ) -> Result<u64, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<AgentGroupV1>("agent_groups")
+            .await?;
+        let result = collection
+            .update_many(
+                doc! { "name": { "$in": groups } },
+                doc! { "$addToSet": { "members": agent_name } },
+            )
+            .await?;
+        Ok(result.modified_count)
+    }
+}
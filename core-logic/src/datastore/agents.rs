@@ -1,6 +1,6 @@
 use bson::{Bson, oid::ObjectId};
 use mongodb::{
-    Collection,
+    Collection, IndexModel,
     bson::{DateTime, Document, doc},
 };
 use serde::{Deserialize, Serialize};
@@ -10,8 +10,9 @@ use tracing::error;
 use std::error::Error;
 
 use crate::datastore::Datastore;
-use crate::messages::RegisterAgent;
+use crate::messages::{ContainerInfo, RegisterAgent, UpdateAgent};
 
+/// An agent's state as last observed by central command.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(i32)]
 #[serde(from = "i32")]
@@ -19,6 +20,105 @@ use crate::messages::RegisterAgent;
 pub enum Status {
     Offline = 0,
     Online = 1,
+    Draining = 2, // Reachable, but `draining` keeps new jobs from being dispatched to it
+    Degraded = 3, // Reachable, but slow to answer central command's heartbeat
+    Unknown = 4,  // Not heard from since it was added
+}
+
+impl Status {
+    pub const ALL: [Status; 5] = [
+        Status::Online,
+        Status::Degraded,
+        Status::Draining,
+        Status::Offline,
+        Status::Unknown,
+    ];
+
+    /// Whether central command can currently reach the agent.
+    pub fn is_connected(&self) -> bool {
+        matches!(self, Status::Online | Status::Draining | Status::Degraded)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Offline => "offline",
+            Status::Online => "online",
+            Status::Draining => "draining",
+            Status::Degraded => "degraded",
+            Status::Unknown => "unknown",
+        }
+    }
+
+    /// The status called `name`, e.g. `draining`.
+    pub fn from_name(name: &str) -> Option<Status> {
+        Status::ALL
+            .into_iter()
+            .find(|status| status.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// An update requested by an operator, pushed to the agent by central command.
+#[derive(Debug, Serialize, Clone, Deserialize, PartialEq, Eq)]
+pub struct AgentUpdate {
+    pub version: String,
+    pub url: String,
+    pub checksum: String,
+}
+
+impl From<AgentUpdate> for UpdateAgent {
+    fn from(update: AgentUpdate) -> Self {
+        Self {
+            version: update.version,
+            url: update.url,
+            checksum: update.checksum,
+        }
+    }
+}
+
+/// Outcome of an operator requested ping, recorded by central command.
+#[derive(Debug, Serialize, Clone, Deserialize, PartialEq)]
+pub struct PingResult {
+    pub requested_at: DateTime, // Matches the `ping_requested_at` the result answers
+    pub completed_at: DateTime,
+    #[serde(default)]
+    pub rtt_ms: Option<f64>, // `None` when the ping failed or the round trip can't be timed
+    #[serde(default)]
+    pub via: String, // How the agent was reached, e.g. `connection` or `channel`
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Where an agent running in a container runs, as reported at registration.
+#[derive(Debug, Serialize, Clone, Deserialize, PartialEq, Eq, Default)]
+pub struct AgentContainer {
+    pub runtime: String, // e.g. `kubernetes` or `docker`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod_namespace: Option<String>,
+    /// Labels of the Kubernetes node as `key=value`, matched against `JobV1::node_selector`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub node_labels: Vec<String>,
+}
+
+impl From<ContainerInfo> for AgentContainer {
+    fn from(container: ContainerInfo) -> Self {
+        Self {
+            runtime: container.runtime,
+            node_name: container.node_name,
+            pod_name: container.pod_name,
+            pod_namespace: container.pod_namespace,
+            node_labels: container.node_labels,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Clone, Deserialize)]
@@ -31,6 +131,74 @@ pub struct AgentV1 {
     pub status: Status,
     pub port: u16,
     pub version: u32,
+    #[serde(default)]
+    pub agent_version: String, // Version reported by the agent at registration
+    #[serde(default)]
+    pub timezone: String, // IANA timezone reported by the agent at registration
+    #[serde(default)]
+    pub locale: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_update: Option<AgentUpdate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ping_requested_at: Option<DateTime>, // Set by an operator; cleared once central command pings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ping_result: Option<PingResult>,
+    /// Identity the agent must authenticate as, e.g. a SPIFFE ID. When unset, central command's
+    /// authentication backend derives it from the agent name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// Hex encoded key the agent signs run results with, recorded the first time it registers.
+    /// Clearing it makes central command accept the next key the agent presents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt_public_key: Option<String>,
+    /// Set by an operator to stop new jobs being dispatched to the agent, e.g. before taking it
+    /// down. Jobs already running finish and can still be cancelled.
+    #[serde(default)]
+    pub draining: bool,
+    /// Platform reported by the agent at registration; empty for agents that have not registered
+    /// since it was recorded. Jobs can be limited to platforms with `JobV1::platforms`.
+    #[serde(default)]
+    pub os: String,
+    #[serde(default)]
+    pub arch: String,
+    #[serde(default)]
+    pub kernel: String,
+    #[serde(default)]
+    pub shells: Vec<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// The container the agent runs in, or `None` when it runs on a host or has not registered
+    /// since it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<AgentContainer>,
+    /// Set when central command marked the agent offline because its heartbeats stopped. Such an
+    /// agent only counts as online again once it has kept answering for a while, so an agent
+    /// with intermittent heartbeats does not flap between online and offline.
+    #[serde(default)]
+    pub stale: bool,
+    /// When the agent's current run of heartbeats started; cleared when it goes offline.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heartbeat_since: Option<DateTime>,
+    /// When the agent was moved to the trash. Trashed agents are not dispatched to and are left
+    /// out of listings until they are restored or purged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime>,
+    /// The agent groups the agent was taken out of when it was trashed, to put it back in when
+    /// it is restored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deleted_groups: Vec<String>,
+    /// Who looks after the agent, set by an operator.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// The team the agent belongs to. With team scoping on (see the web UI's `WEBUI_TEAMS`), only
+    /// the team's members and admins see and modify it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    /// The namespace the agent serves, e.g. `staging`, or the default namespace when `None`. It is
+    /// assigned at registration from the token the agent authenticated with, and the agent only
+    /// runs jobs of the same namespace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
 }
 
 impl Default for AgentV1 {
@@ -40,9 +208,31 @@ impl Default for AgentV1 {
             name: String::new(),
             hostname: String::new(),
             last_ping: DateTime::from_millis(0),
-            status: Status::Offline,
+            status: Status::Unknown,
             port: 0,
             version: 1,
+            agent_version: String::new(),
+            timezone: String::new(),
+            locale: String::new(),
+            pending_update: None,
+            ping_requested_at: None,
+            ping_result: None,
+            identity: None,
+            receipt_public_key: None,
+            draining: false,
+            os: String::new(),
+            arch: String::new(),
+            kernel: String::new(),
+            shells: vec![],
+            features: vec![],
+            container: None,
+            stale: false,
+            heartbeat_since: None,
+            deleted_at: None,
+            deleted_groups: vec![],
+            owner: None,
+            team: None,
+            namespace: None,
         }
     }
 }
@@ -58,9 +248,12 @@ impl From<i32> for Status {
         match value {
             0 => Status::Offline,
             1 => Status::Online,
+            2 => Status::Draining,
+            3 => Status::Degraded,
+            4 => Status::Unknown,
             _ => {
                 error!("Warning: Unknown Status value encountered: {}", value);
-                Status::Offline // Default to Offline for unknown values
+                Status::Unknown
             }
         }
     }
@@ -78,17 +271,57 @@ impl AgentV1 {
         Datastore::create_unique_index(collection, index_doc).await?;
         let index_doc = doc! { "name": 1, };
         Datastore::create_unique_index(collection, index_doc).await?;
+        // Central command looks for connected agents, and for those whose heartbeat is stale.
+        let index = IndexModel::builder().keys(doc! { "status": 1 }).build();
+        collection.create_index(index).await?;
+        let index = IndexModel::builder().keys(doc! { "last_ping": 1 }).build();
+        collection.create_index(index).await?;
 
         Ok(())
     }
+
+    /// The agent's platform as `os/arch`, e.g. `linux/amd64`, or `None` if it never reported one.
+    pub fn platform(&self) -> Option<String> {
+        (!self.os.is_empty()).then(|| format!("{}/{}", self.os, self.arch))
+    }
+
+    /// Whether the agent runs on `platform`, given as `os` or `os/arch`.
+    pub fn is_platform(&self, platform: &str) -> bool {
+        match platform.split_once('/') {
+            Some((os, arch)) => self.os == os && self.arch == normalize_arch(arch),
+            None => self.os == platform,
+        }
+    }
+
+    /// Whether the agent's Kubernetes node has every `key=value` label of `selector`. Agents that
+    /// do not run on Kubernetes have no node labels.
+    pub fn matches_node_selector(&self, selector: &[String]) -> bool {
+        let labels = self
+            .container
+            .as_ref()
+            .map(|container| container.node_labels.as_slice())
+            .unwrap_or_default();
+        selector.iter().all(|label| labels.contains(label))
+    }
+}
+
+/// The architecture name agents report for `arch`, so `x86_64` and `amd64`, or `aarch64` and
+/// `arm64`, name the same platform.
+pub fn normalize_arch(arch: &str) -> &str {
+    match arch {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" | "i386" | "i686" => "386",
+        other => other,
+    }
 }
 
 impl std::fmt::Display for AgentV1 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "AgentV1 {{ id: {:?}, name: {}, hostname: {}, port: {}, version: {} }}",
-            self.id, self.name, self.hostname, self.port, self.version
+            "AgentV1 {{ id: {:?}, name: {}, hostname: {}, port: {}, version: {}, agent_version: {} }}",
+            self.id, self.name, self.hostname, self.port, self.version, self.agent_version
         )
     }
 }
@@ -100,9 +333,31 @@ impl From<RegisterAgent> for AgentV1 {
             name: register_agent.name,
             hostname: register_agent.hostname,
             last_ping: DateTime::from_millis(0), // Default to 0, will be updated on next ping
-            status: Status::Offline,             // Default to Offline, will be updated on next ping
+            status: Status::Unknown,             // Will be updated on next ping
             port: register_agent.port,
             version: 1,
+            agent_version: register_agent.version,
+            timezone: register_agent.timezone,
+            locale: register_agent.locale,
+            pending_update: None,
+            ping_requested_at: None,
+            ping_result: None,
+            identity: None,
+            receipt_public_key: register_agent.receipt_public_key,
+            draining: false,
+            os: register_agent.os,
+            arch: register_agent.arch,
+            kernel: register_agent.kernel,
+            shells: register_agent.shells,
+            features: register_agent.features,
+            container: register_agent.container.map(AgentContainer::from),
+            stale: false,
+            heartbeat_since: None,
+            deleted_at: None,
+            deleted_groups: vec![],
+            owner: None,
+            team: None,
+            namespace: None,
         }
     }
 }
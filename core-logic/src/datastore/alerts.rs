@@ -0,0 +1,322 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use futures::TryStreamExt;
+use mongodb::options::ReturnDocument;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+use crate::datastore::job_warnings::JobWarningKind;
+
+/// Who resolves the failed run alerts of jobs that ran successfully again.
+pub const RESOLVED_BY_RECOVERY: &str = "central-command";
+
+/// What an alert is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    RunFailed,      // A run failed, timed out or could not be dispatched
+    SlaBreached,    // A run went on for longer than the job's `sla.expected_duration`
+    Overdue,        // A scheduled run did not start within the job's `sla.max_start_delay`
+    TimeoutWarning, // A run used most of its timeout, e.g. because it is stuck
+}
+
+impl AlertKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertKind::RunFailed => "run_failed",
+            AlertKind::SlaBreached => "sla_breached",
+            AlertKind::Overdue => "overdue",
+            AlertKind::TimeoutWarning => "timeout_warning",
+        }
+    }
+
+    /// The kind of alert a job warning raises, if any.
+    pub fn from_warning(kind: JobWarningKind) -> Option<Self> {
+        match kind {
+            JobWarningKind::TimeoutWarning => Some(AlertKind::TimeoutWarning),
+            JobWarningKind::SlaBreached => Some(AlertKind::SlaBreached),
+            JobWarningKind::Overdue => Some(AlertKind::Overdue),
+            JobWarningKind::StateChanged => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Open,
+    Acknowledged, // Somebody is looking into it; repeats are counted but not notified
+    Resolved,     // Closed; the next occurrence opens a new alert
+}
+
+impl AlertStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertStatus::Open => "open",
+            AlertStatus::Acknowledged => "acknowledged",
+            AlertStatus::Resolved => "resolved",
+        }
+    }
+}
+
+impl std::fmt::Display for AlertStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A problem with a job on an agent, raised by central command when a run fails, breaches its
+/// SLA, is overdue or nears its timeout. Repeats of the same kind of problem with the same job
+/// and agent are aggregated into the alert until it is resolved, counting `occurrences`, so the
+/// alerts page shows each problem once. While an alert is acknowledged the `Notifier` does not
+/// send the job warnings it aggregates to `JOB_WARNING_WEBHOOK_URLS`.
+///
+/// Failed runs of jobs whose severity pages also page the on-call through PagerDuty or Opsgenie:
+/// the `Notifier` triggers an incident for the alert, and acknowledges or resolves it with the
+/// alert. A successful run of the job on the agent resolves its failed run alert.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub kind: AlertKind,
+    pub job_name: String,
+    pub agent_name: String, // Empty for overdue jobs, which concern no agent
+    pub status: AlertStatus,
+    pub first_at: DateTime,
+    pub last_at: DateTime,
+    pub occurrences: u32,
+    /// What happened the last time, e.g. `Timed out after 600 seconds`.
+    #[serde(default)]
+    pub detail: String,
+    /// The run that last raised the alert, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<DateTime>,
+    /// The job's `sla.severity`, for alerts that page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<String>,
+    /// Set until the `Notifier` has sent the alert's status to the paging services.
+    #[serde(default)]
+    pub page_pending: bool,
+    /// Whether an incident was triggered for the alert.
+    #[serde(default)]
+    pub paged: bool,
+}
+
+impl AlertV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "kind": 1, "job_name": 1, "agent_name": 1, "status": 1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "status": 1, "last_at": -1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "page_pending": 1, "last_at": 1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    /// Opens an alert of `kind` for `job_name` on `agent_name`, or counts another occurrence of
+    /// the one not yet resolved.
+    pub async fn raise(
+        datastore: &Datastore,
+        kind: AlertKind,
+        job_name: &str,
+        agent_name: &str,
+        run_id: Option<&str>,
+        detail: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let collection = datastore.get_collection::<AlertV1>("alerts").await?;
+        let now = DateTime::now();
+        collection
+            .update_one(
+                doc! {
+                    "kind": kind.name(),
+                    "job_name": job_name,
+                    "agent_name": agent_name,
+                    "status": { "$ne": AlertStatus::Resolved.name() },
+                },
+                doc! {
+                    "$set": { "last_at": now, "detail": detail, "run_id": run_id },
+                    "$inc": { "occurrences": 1 },
+                    "$setOnInsert": { "status": AlertStatus::Open.name(), "first_at": now },
+                },
+            )
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    /// Whether the alert of `kind` for `job_name` on `agent_name` is acknowledged, so repeats
+    /// are not notified.
+    pub async fn is_acknowledged(
+        datastore: &Datastore,
+        kind: AlertKind,
+        job_name: &str,
+        agent_name: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let collection = datastore.get_collection::<AlertV1>("alerts").await?;
+        let count = collection
+            .count_documents(doc! {
+                "kind": kind.name(),
+                "job_name": job_name,
+                "agent_name": agent_name,
+                "status": AlertStatus::Acknowledged.name(),
+            })
+            .await?;
+        Ok(count > 0)
+    }
+
+    /// Acknowledges the open alert `id` for `user`. Returns `None` unless it was open.
+    pub async fn acknowledge(
+        datastore: &Datastore,
+        id: ObjectId,
+        user: &str,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        let collection = datastore.get_collection::<AlertV1>("alerts").await?;
+        Ok(collection
+            .find_one_and_update(
+                doc! { "_id": id, "status": AlertStatus::Open.name() },
+                // A pipeline update, so the incident is acknowledged only if one was triggered.
+                vec![doc! { "$set": {
+                    "status": AlertStatus::Acknowledged.name(),
+                    "acknowledged_by": { "$literal": user },
+                    "acknowledged_at": "$$NOW",
+                    "page_pending": { "$ifNull": ["$paged", false] },
+                } }],
+            )
+            .return_document(ReturnDocument::After)
+            .await?)
+    }
+
+    /// Resolves the alert `id` for `user`. Returns `None` if it was already resolved.
+    pub async fn resolve(
+        datastore: &Datastore,
+        id: ObjectId,
+        user: &str,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        let collection = datastore.get_collection::<AlertV1>("alerts").await?;
+        Ok(collection
+            .find_one_and_update(
+                doc! { "_id": id, "status": { "$ne": AlertStatus::Resolved.name() } },
+                Self::resolution(user),
+            )
+            .return_document(ReturnDocument::After)
+            .await?)
+    }
+
+    /// A pipeline update resolving an alert for `user`, resolving its incident if one was
+    /// triggered.
+    fn resolution(user: &str) -> Vec<Document> {
+        vec![doc! { "$set": {
+            "status": AlertStatus::Resolved.name(),
+            "resolved_by": { "$literal": user },
+            "resolved_at": "$$NOW",
+            "page_pending": { "$ifNull": ["$paged", false] },
+        } }]
+    }
+
+    /// Resolves the failed run alert of `job_name` on `agent_name`, after a run succeeded.
+    pub async fn resolve_recovered(
+        datastore: &Datastore,
+        job_name: &str,
+        agent_name: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let collection = datastore.get_collection::<AlertV1>("alerts").await?;
+        collection
+            .update_many(
+                doc! {
+                    "kind": AlertKind::RunFailed.name(),
+                    "job_name": job_name,
+                    "agent_name": agent_name,
+                    "status": { "$ne": AlertStatus::Resolved.name() },
+                },
+                Self::resolution(RESOLVED_BY_RECOVERY),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Pages the on-call for the unresolved alert of `kind` for `job_name` on `agent_name`, a job
+    /// of `severity`, unless it already did.
+    pub async fn request_page(
+        datastore: &Datastore,
+        kind: AlertKind,
+        job_name: &str,
+        agent_name: &str,
+        severity: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let collection = datastore.get_collection::<AlertV1>("alerts").await?;
+        collection
+            .update_one(
+                doc! {
+                    "kind": kind.name(),
+                    "job_name": job_name,
+                    "agent_name": agent_name,
+                    "status": AlertStatus::Open.name(),
+                    "paged": { "$ne": true },
+                },
+                doc! { "$set": { "page_pending": true, "severity": severity } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Alerts whose status was not yet sent to the paging services, oldest first.
+    pub async fn pending_pages(
+        datastore: &Datastore,
+        limit: i64,
+    ) -> Result<Vec<AlertV1>, Box<dyn Error>> {
+        let collection = datastore.get_collection::<AlertV1>("alerts").await?;
+        let alerts = collection
+            .find(doc! { "page_pending": true })
+            .sort(doc! { "last_at": 1 })
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(alerts)
+    }
+
+    /// Records that the alert's status was sent to the paging services, as a triggered incident
+    /// when `paged`. Leaves it pending if its status changed in the meantime, so the change is
+    /// sent too.
+    pub async fn mark_paged(
+        &self,
+        datastore: &Datastore,
+        paged: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let collection = datastore.get_collection::<AlertV1>("alerts").await?;
+        collection
+            .update_one(
+                doc! { "_id": self.id },
+                vec![doc! { "$set": {
+                    "paged": paged,
+                    "page_pending": { "$ne": ["$status", self.status.name()] },
+                } }],
+            )
+            .await?;
+        Ok(())
+    }
+}
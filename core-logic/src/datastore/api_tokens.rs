@@ -0,0 +1,161 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use futures::TryStreamExt;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+
+/// Prefix of every token, so leaked tokens are easy to recognize, e.g. by secret scanners.
+pub const TOKEN_PREFIX: &str = "rad_";
+/// Random bytes in a token, hex encoded after the prefix.
+const TOKEN_BYTES: usize = 32;
+/// Characters of the token, after its prefix, kept to tell tokens apart in listings.
+const HINT_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiTokenKind {
+    Personal, // Acts as the user who created it
+    Service,  // Acts as `service:<name>`, e.g. for a CI system
+}
+
+/// A token programs send in an `Authorization: Bearer` header to call the web UI's API. Only its
+/// SHA-256 is stored; the token itself is shown once, when it is created. Revoked tokens are
+/// kept, so the audit log's references to them stay meaningful.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiTokenV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    pub kind: ApiTokenKind,
+    pub created_by: String,
+    pub created_at: DateTime,
+    /// Hex encoded SHA-256 of the token.
+    pub token_sha256: String,
+    /// The start of the token, e.g. `rad_1a2b3c4d`.
+    pub hint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<DateTime>,
+}
+
+impl ApiTokenV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        Datastore::create_unique_index(collection, doc! { "token_sha256": 1 }).await?;
+
+        Ok(())
+    }
+
+    /// A new token named `name`, returned with its record. The token cannot be recovered from the
+    /// record, so it has to be handed to its user right away.
+    pub fn generate(
+        name: &str,
+        kind: ApiTokenKind,
+        created_by: &str,
+        expires_at: Option<DateTime>,
+    ) -> Result<(String, Self), Box<dyn Error>> {
+        let mut bytes = [0u8; TOKEN_BYTES];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| "Failed to generate a random token")?;
+        let token = format!("{}{}", TOKEN_PREFIX, hex::encode(bytes));
+        let record = Self {
+            id: None,
+            name: name.to_string(),
+            kind,
+            created_by: created_by.to_string(),
+            created_at: DateTime::now(),
+            token_sha256: Self::hash(&token),
+            hint: token[..TOKEN_PREFIX.len() + HINT_LENGTH].to_string(),
+            expires_at,
+            last_used_at: None,
+            revoked_at: None,
+        };
+        Ok((token, record))
+    }
+
+    pub fn hash(token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
+
+    /// Who requests made with the token are recorded as made by.
+    pub fn actor(&self) -> String {
+        match self.kind {
+            ApiTokenKind::Personal => self.created_by.clone(),
+            ApiTokenKind::Service => format!("service:{}", self.name),
+        }
+    }
+
+    pub async fn insert_entry(&self, datastore: &Datastore) -> Result<ObjectId, Box<dyn Error>> {
+        let collection = datastore.get_collection::<ApiTokenV1>("api_tokens").await?;
+        let result = collection.insert_one(self).await?;
+        result
+            .inserted_id
+            .as_object_id()
+            .ok_or_else(|| "Inserted token has no object id".into())
+    }
+
+    /// The token `token` is, unless it is unknown, revoked or expired, recording that it was used.
+    pub async fn authenticate(
+        datastore: &Datastore,
+        token: &str,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        let collection = datastore.get_collection::<ApiTokenV1>("api_tokens").await?;
+        let now = DateTime::now();
+        Ok(collection
+            .find_one_and_update(
+                doc! {
+                    "token_sha256": Self::hash(token),
+                    "revoked_at": null,
+                    "$or": [{ "expires_at": null }, { "expires_at": { "$gt": now } }],
+                },
+                doc! { "$set": { "last_used_at": now } },
+            )
+            .await?)
+    }
+
+    /// Every token, or those created by `created_by` when given, newest first.
+    pub async fn list(
+        datastore: &Datastore,
+        created_by: Option<&str>,
+    ) -> Result<Vec<Self>, Box<dyn Error>> {
+        let collection = datastore.get_collection::<ApiTokenV1>("api_tokens").await?;
+        let filter = match created_by {
+            Some(created_by) => doc! { "created_by": created_by },
+            None => doc! {},
+        };
+        Ok(collection
+            .find(filter)
+            .sort(doc! { "created_at": -1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    pub async fn find(datastore: &Datastore, id: ObjectId) -> Result<Option<Self>, Box<dyn Error>> {
+        let collection = datastore.get_collection::<ApiTokenV1>("api_tokens").await?;
+        Ok(collection.find_one(doc! { "_id": id }).await?)
+    }
+
+    /// Revokes the token, returning it as it was, or `None` if it is unknown or already revoked.
+    pub async fn revoke(
+        datastore: &Datastore,
+        id: ObjectId,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        let collection = datastore.get_collection::<ApiTokenV1>("api_tokens").await?;
+        Ok(collection
+            .find_one_and_update(
+                doc! { "_id": id, "revoked_at": null },
+                doc! { "$set": { "revoked_at": DateTime::now() } },
+            )
+            .await?)
+    }
+}
@@ -0,0 +1,138 @@
+use bson::{Bson, DateTime, Document, doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+use crate::datastore::job_changes::FieldChange;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,  // Moved to the trash, or deleted outright for resources without one
+    Restore, // Taken back out of the trash
+    Purge,   // Deleted from the trash for good
+    Trigger,
+    Cancel,
+    ExtendTimeout,
+    OpenShell,  // An operator opened a remote shell on an agent
+    CloseShell, // The remote shell ended
+    Approve,
+    Reject,
+    Acknowledge,
+    Resolve,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResource {
+    Job,
+    Agent,
+    JobTemplate,
+    AgentGroup,
+    BlackoutWindow,
+    JobFile,
+    ApiToken,
+    JobPromotion,
+    Alert,
+}
+
+/// A mutating action taken through the web UI or its API, with who took it and the fields it
+/// changed. Entries are only ever inserted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntryV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub at: DateTime,
+    pub user: String,
+    pub action: AuditAction,
+    pub resource_kind: AuditResource,
+    pub resource: String, // Job, agent, template, agent group, blackout window, job file or token name, or promotion or alert id
+    #[serde(default)]
+    pub changes: Vec<FieldChange>,
+}
+
+impl AuditEntryV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "at": -1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "resource_kind": 1, "resource": 1, "at": -1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    pub fn new(
+        user: &str,
+        action: AuditAction,
+        resource_kind: AuditResource,
+        resource: &str,
+    ) -> Self {
+        Self {
+            id: None,
+            at: DateTime::now(),
+            user: user.to_string(),
+            action,
+            resource_kind,
+            resource: resource.to_string(),
+            changes: vec![],
+        }
+    }
+
+    /// Records the top level fields that differ between `before` and `after`. A missing side is
+    /// a creation or deletion, so every field of the other side is recorded.
+    pub fn with_diff<T: Serialize>(mut self, before: Option<&T>, after: Option<&T>) -> Self {
+        let to_document = |value: Option<&T>| value.and_then(|value| bson::to_document(value).ok());
+        let before = to_document(before).unwrap_or_default();
+        let after = to_document(after).unwrap_or_default();
+
+        let fields = after
+            .keys()
+            .chain(before.keys().filter(|field| !after.contains_key(*field)));
+        for field in fields.filter(|field| *field != "_id") {
+            let (old, new) = (before.get(field), after.get(field));
+            if old != new {
+                self.changes.push(FieldChange {
+                    field: field.clone(),
+                    old: old.map(display_value).unwrap_or_default(),
+                    new: new.map(display_value).unwrap_or_default(),
+                });
+            }
+        }
+        self
+    }
+
+    /// Records a detail of an action that changes no field, e.g. the id of a remote shell session.
+    pub fn with_field(mut self, field: &str, value: impl Into<String>) -> Self {
+        self.changes.push(FieldChange {
+            field: field.to_string(),
+            old: String::new(),
+            new: value.into(),
+        });
+        self
+    }
+
+    pub async fn insert_entry(&self, datastore: &Datastore) -> Result<(), Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<AuditEntryV1>("audit_log")
+            .await?;
+        collection.insert_one(self).await?;
+        Ok(())
+    }
+}
+
+fn display_value(value: &Bson) -> String {
+    match value {
+        Bson::String(value) => value.clone(),
+        Bson::Null => String::new(),
+        value => value.clone().into_relaxed_extjson().to_string(),
+    }
+}
@@ -0,0 +1,94 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+
+/// A period, e.g. planned maintenance, during which central command starts no runs of the listed
+/// `jobs`, or of any job when `jobs` is empty. Runs that fall due during a window wait for it to
+/// end and are then handled by their job's misfire policy, like runs missed while central command
+/// was down. Runs already started when a window opens are left to finish.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlackoutWindowV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub start: DateTime,
+    pub end: DateTime,
+    #[serde(default)]
+    pub jobs: Vec<String>, // Job names; empty for every job
+}
+
+impl BlackoutWindowV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        Datastore::create_unique_index(collection, doc! { "name": 1 }).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "end": 1, "start": 1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    /// Checks that the window has a name and ends after it starts.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Blackout window name is required".to_string());
+        }
+        if self.end <= self.start {
+            return Err("A blackout window must end after it starts".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether the window holds back every job rather than only the listed ones.
+    pub fn is_global(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Whether the window holds back a run of `job_name` due at `at`.
+    pub fn holds_back(&self, job_name: &str, at: DateTime) -> bool {
+        self.start <= at
+            && at < self.end
+            && (self.is_global() || self.jobs.iter().any(|job| job == job_name))
+    }
+
+    /// Windows that have not ended by `at`, including those in progress, by start time.
+    pub async fn upcoming(
+        datastore: &Datastore,
+        at: DateTime,
+    ) -> Result<Vec<BlackoutWindowV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<BlackoutWindowV1>("blackout_windows")
+            .await?;
+        let windows = collection
+            .find(doc! { "end": { "$gt": at } })
+            .sort(doc! { "start": 1, "name": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(windows)
+    }
+
+    /// Windows in progress at `at`.
+    pub async fn active(
+        datastore: &Datastore,
+        at: DateTime,
+    ) -> Result<Vec<BlackoutWindowV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<BlackoutWindowV1>("blackout_windows")
+            .await?;
+        let windows = collection
+            .find(doc! { "start": { "$lte": at }, "end": { "$gt": at } })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(windows)
+    }
+}
@@ -0,0 +1,152 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use futures::TryStreamExt;
+use mongodb::options::ReturnDocument;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+use crate::datastore::runs::{Outcome, RunsV1};
+
+/// Longest summary kept from the first line of a check's output, in characters.
+const MAX_SUMMARY_CHARS: usize = 200;
+
+/// The state of a check job on an agent, from the exit code of its run as with Nagios plugins:
+/// 0 is OK, 1 WARNING, 2 CRITICAL, and anything else, or a run that did not exit by itself,
+/// UNKNOWN.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckState {
+    #[default]
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl CheckState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CheckState::Ok => "ok",
+            CheckState::Warning => "warning",
+            CheckState::Critical => "critical",
+            CheckState::Unknown => "unknown",
+        }
+    }
+
+    /// The state a run of a check job reports.
+    pub fn from_run(outcome: Outcome, return_code: i32) -> Self {
+        match (outcome, return_code) {
+            (Outcome::Success | Outcome::Failure, 0) => CheckState::Ok,
+            (Outcome::Success | Outcome::Failure, 1) => CheckState::Warning,
+            (Outcome::Success | Outcome::Failure, 2) => CheckState::Critical,
+            _ => CheckState::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for CheckState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The first line of a check's output, which Nagios plugins use for a one line status, e.g.
+/// `DISK WARNING - 12% free on /var`.
+pub fn summary(output: &str) -> String {
+    output
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_SUMMARY_CHARS)
+        .collect()
+}
+
+/// The current state of a check job (see `JobV1::check`) on an agent, updated by central command
+/// with every run so the dashboard can show a service status grid and only changes of state are
+/// sent to `JOB_WARNING_WEBHOOK_URLS`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckStateV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub job_name: String,
+    pub agent_name: String,
+    pub state: CheckState,
+    /// When the check entered its state.
+    pub since: DateTime,
+    /// When the run that last reported the state completed.
+    pub checked_at: DateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// The first line of the run's output, e.g. `DISK WARNING - 12% free on /var`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub summary: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+impl CheckStateV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        Datastore::create_unique_index(collection, doc! { "job_name": 1, "agent_name": 1 }).await?;
+
+        Ok(())
+    }
+
+    /// Records the state `run` of a check job reports. Returns the check's previous state when
+    /// the run changed it; a check seen for the first time was OK before.
+    pub async fn record(
+        datastore: &Datastore,
+        run: &RunsV1,
+    ) -> Result<Option<CheckState>, Box<dyn Error>> {
+        let state = CheckState::from_run(run.outcome, run.return_code);
+        let summary = summary(&run.output);
+        let collection = datastore
+            .get_collection::<CheckStateV1>("check_states")
+            .await?;
+        // A pipeline update, so `since` is kept while the state does not change.
+        let update = vec![doc! {
+            "$set": {
+                "since": {
+                    "$cond": [{ "$eq": ["$state", state.name()] }, "$since", run.completed_at]
+                },
+                "state": state.name(),
+                "checked_at": run.completed_at,
+                "run_id": &run.run_id,
+                "summary": summary,
+                "namespace": &run.namespace,
+            }
+        }];
+        let previous = collection
+            .find_one_and_update(
+                doc! { "job_name": &run.job_name, "agent_name": &run.agent_name },
+                update,
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .await?
+            .map(|previous| previous.state)
+            .unwrap_or_default();
+        Ok((previous != state).then_some(previous))
+    }
+
+    /// The current states of every check, by job and agent.
+    pub async fn find_all(
+        datastore: &Datastore,
+        filter: Document,
+    ) -> Result<Vec<CheckStateV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<CheckStateV1>("check_states")
+            .await?;
+        let states = collection
+            .find(filter)
+            .sort(doc! { "job_name": 1, "agent_name": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(states)
+    }
+}
@@ -0,0 +1,44 @@
+use bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionKind {
+    /// Opened by an agent to send messages to central command.
+    #[default]
+    Inbound,
+    /// Opened by an agent that receives dispatches over it (see `ReverseDispatch`).
+    ReverseDispatch,
+    /// Opened by central command to dispatch to an agent's listen port.
+    Outbound,
+    /// Opened by an agent that polls for its jobs over it (see `PollWork`).
+    Pull,
+}
+
+/// Snapshot of a connection held by central command.
+/// Each central command instance periodically replaces its own connections in the `connections`
+/// collection with its live ones so operators can inspect them from the webui.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionV1 {
+    #[serde(rename = "_id")]
+    pub id: String,
+    /// Id of the central command instance holding the connection, see `CENTRAL_COMMAND_ID`.
+    #[serde(default)]
+    pub central_command: String,
+    pub kind: ConnectionKind,
+    pub agent_name: Option<String>,
+    pub remote_addr: String,
+    pub connected_since: DateTime,
+    pub bytes_in: i64,
+    pub bytes_out: i64,
+    pub messages_in: i64,
+    pub messages_out: i64,
+    pub last_message: Option<String>, // Kind of the last message sent or received, e.g. "DispatchJob"
+    pub last_message_at: Option<DateTime>,
+    /// Frames from the peer that failed their checksum, did not parse or had to be skipped to
+    /// get back in step with the stream (see `framing::ProtocolError`).
+    #[serde(default)]
+    pub protocol_errors: i64,
+    #[serde(default)]
+    pub last_protocol_error: Option<String>,
+}
@@ -0,0 +1,59 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+
+/// What central command would have done about a due run of a job in dry-run mode (`--dry-run`),
+/// recorded instead of dispatching it, so schedules, agent selectors and platform filters can be
+/// checked before going live. Each scheduled run of a job is recorded once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DispatchPlanV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub job_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// The scheduled run: the job's `next_run`, or a later run of a recurring job.
+    pub scheduled_at: DateTime,
+    pub planned_at: DateTime,
+    pub central_command_id: String,
+    /// The agents the cycle would run on; those not connected would wait for the cycle.
+    pub agents: Vec<String>,
+    /// The agents of `agents` that are connected, so the run would be dispatched to them now.
+    pub dispatched_to: Vec<String>,
+    /// Why the run would not start, e.g. `approval` for a job that requires one or the name of
+    /// the blackout window holding it back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held_by: Option<String>,
+}
+
+impl DispatchPlanV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        Datastore::create_unique_index(collection, doc! { "job_name": 1, "scheduled_at": 1 })
+            .await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "planned_at": -1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    /// Records the plan unless the scheduled run was planned already, e.g. before a restart.
+    /// Returns whether it was recorded now.
+    pub async fn record(&self, datastore: &Datastore) -> Result<bool, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<Document>("dispatch_plans")
+            .await?;
+        let filter = doc! { "job_name": &self.job_name, "scheduled_at": self.scheduled_at };
+        let result = collection
+            .update_one(filter, doc! { "$setOnInsert": bson::to_document(self)? })
+            .upsert(true)
+            .await?;
+        Ok(result.upserted_id.is_some())
+    }
+}
@@ -0,0 +1,300 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+use crate::datastore::jobs::{self, JobV1};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobChangeKind {
+    Created,
+    Updated,
+    Disabled,
+    Deleted,
+}
+
+/// A single definition field that changed, rendered as text for display.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// A change to a job's definition, shown next to its runs so outcome changes can be correlated
+/// with configuration changes. Kept for `JOB_CHANGE_RETENTION_DAYS` by central command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobChangeV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub job_name: String,
+    pub kind: JobChangeKind,
+    pub changed_at: DateTime,
+    pub changed_by: String, // e.g. `template nightly-backup` or `jobs file backups.yaml`
+    #[serde(default)]
+    pub changes: Vec<FieldChange>,
+}
+
+impl JobChangeV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "job_name": 1, "changed_at": 1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    pub fn new(job_name: &str, kind: JobChangeKind, changed_by: &str) -> Self {
+        Self {
+            id: None,
+            job_name: job_name.to_string(),
+            kind,
+            changed_at: DateTime::now(),
+            changed_by: changed_by.to_string(),
+            changes: vec![],
+        }
+    }
+
+    /// The `Updated` change from `old` to `new`, or `None` if no definition field differs.
+    /// Runtime state such as status and running agents is ignored.
+    pub fn between(old: &JobV1, new: &JobV1, changed_by: &str) -> Option<Self> {
+        let mut changes = vec![];
+        let mut compare = |field: &str, old: String, new: String| {
+            if old != new {
+                changes.push(FieldChange {
+                    field: field.to_string(),
+                    old,
+                    new,
+                });
+            }
+        };
+        compare(
+            "description",
+            old.description.clone(),
+            new.description.clone(),
+        );
+        compare("command", old.command.clone(), new.command.clone());
+        compare("args", old.args.join(" "), new.args.join(" "));
+        compare("env", old.env.join(" "), new.env.join(" "));
+        compare("cwd", old.cwd.clone(), new.cwd.clone());
+        let next_run = |job: &JobV1| {
+            jobs::next_run_datetime(job.next_run)
+                .try_to_rfc3339_string()
+                .unwrap_or_else(|_| job.next_run.to_string())
+        };
+        compare("next_run", next_run(old), next_run(new));
+        compare("timeout", old.timeout.to_string(), new.timeout.to_string());
+        compare("retries", old.retries.to_string(), new.retries.to_string());
+        compare(
+            "valid_return_codes",
+            format!("{:?}", old.valid_return_codes),
+            format!("{:?}", new.valid_return_codes),
+        );
+        compare(
+            "agents_required",
+            old.agents_required.join(", "),
+            new.agents_required.join(", "),
+        );
+        compare(
+            "agent_groups",
+            old.agent_groups.join(", "),
+            new.agent_groups.join(", "),
+        );
+        compare(
+            "platforms",
+            old.platforms.join(", "),
+            new.platforms.join(", "),
+        );
+        compare(
+            "executor",
+            old.executor.to_string(),
+            new.executor.to_string(),
+        );
+        compare(
+            "node_selector",
+            old.node_selector.join(", "),
+            new.node_selector.join(", "),
+        );
+        compare(
+            "one_shot",
+            old.one_shot.to_string(),
+            new.one_shot.to_string(),
+        );
+        compare(
+            "requires_approval",
+            old.requires_approval.to_string(),
+            new.requires_approval.to_string(),
+        );
+        compare(
+            "on_success",
+            old.on_success.join(", "),
+            new.on_success.join(", "),
+        );
+        compare(
+            "on_failure",
+            old.on_failure.join(", "),
+            new.on_failure.join(", "),
+        );
+        compare(
+            "schedule_interval",
+            old.schedule_interval
+                .map(|interval| interval.to_string())
+                .unwrap_or_default(),
+            new.schedule_interval
+                .map(|interval| interval.to_string())
+                .unwrap_or_default(),
+        );
+        compare(
+            "cron",
+            old.cron.clone().unwrap_or_default(),
+            new.cron.clone().unwrap_or_default(),
+        );
+        compare(
+            "timezone",
+            old.timezone.clone().unwrap_or_default(),
+            new.timezone.clone().unwrap_or_default(),
+        );
+        compare(
+            "misfire_policy",
+            old.misfire_policy.to_string(),
+            new.misfire_policy.to_string(),
+        );
+        compare(
+            "success_rule",
+            old.success_rule.to_string(),
+            new.success_rule.to_string(),
+        );
+        compare(
+            "steps",
+            old.steps
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; "),
+            new.steps
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; "),
+        );
+        compare(
+            "files",
+            old.files
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; "),
+            new.files
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; "),
+        );
+        compare(
+            "script",
+            old.script
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            new.script
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+        );
+        compare(
+            "container",
+            old.container
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            new.container
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+        );
+        compare(
+            "output_parsers",
+            old.output_parsers
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; "),
+            new.output_parsers
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; "),
+        );
+        compare(
+            "success_statuses",
+            old.success_statuses.join(", "),
+            new.success_statuses.join(", "),
+        );
+        compare("check", old.check.to_string(), new.check.to_string());
+        compare(
+            "redact_patterns",
+            old.redact_patterns.join(", "),
+            new.redact_patterns.join(", "),
+        );
+
+        compare(
+            "sla",
+            old.sla
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            new.sla
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+        );
+        compare(
+            "team",
+            old.team.clone().unwrap_or_default(),
+            new.team.clone().unwrap_or_default(),
+        );
+        compare("tags", old.tags.join(", "), new.tags.join(", "));
+        compare(
+            "metadata",
+            old.metadata.to_string(),
+            new.metadata.to_string(),
+        );
+        compare(
+            "namespace",
+            old.namespace.clone().unwrap_or_default(),
+            new.namespace.clone().unwrap_or_default(),
+        );
+
+        (!changes.is_empty()).then(|| Self {
+            changes,
+            ..Self::new(&new.name, JobChangeKind::Updated, changed_by)
+        })
+    }
+
+    pub async fn insert_entry(&self, datastore: &Datastore) -> Result<(), Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobChangeV1>("job_changes")
+            .await?;
+        collection.insert_one(self).await?;
+        Ok(())
+    }
+
+    /// Deletes changes recorded before `cutoff`, returning how many were removed.
+    pub async fn delete_before(
+        datastore: &Datastore,
+        cutoff: DateTime,
+    ) -> Result<u64, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobChangeV1>("job_changes")
+            .await?;
+        let result = collection
+            .delete_many(doc! { "changed_at": { "$lt": cutoff } })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
@@ -0,0 +1,173 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use mongodb::options::ReturnDocument;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+use crate::datastore::jobs::{JobV1, SuccessRule};
+use crate::datastore::runs::{Outcome, RunJobSnapshot, RunsV1};
+
+/// The result one agent reported for a cycle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentResult {
+    pub agent_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    pub outcome: Outcome,
+    pub return_code: i32,
+    pub completed_at: DateTime,
+}
+
+impl From<&RunsV1> for AgentResult {
+    fn from(run: &RunsV1) -> Self {
+        Self {
+            agent_name: run.agent_name.clone(),
+            run_id: run.run_id.clone(),
+            outcome: run.outcome,
+            return_code: run.return_code,
+            completed_at: run.completed_at,
+        }
+    }
+}
+
+/// One dispatch cycle of a job across all of its agents. Central command creates it when the job
+/// is dispatched, adds each agent's result as it reports, and once every required agent has
+/// reported applies the job's `success_rule` to decide the cycle's outcome.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobExecutionV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub cycle_id: String,
+    pub job_name: String,
+    pub started_at: DateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<DateTime>,
+    pub agents_required: Vec<String>,
+    pub success_rule: SuccessRule,
+    #[serde(default)]
+    pub results: Vec<AgentResult>,
+    /// `Success` or `Failure` once every required agent has reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Outcome>,
+    /// The job as it was dispatched in this cycle, recorded on each of its runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<RunJobSnapshot>,
+}
+
+impl JobExecutionV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        Datastore::create_unique_index(collection, doc! { "cycle_id": 1 }).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "job_name": 1, "started_at": -1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    /// Records the start of the job's current cycle with a snapshot of the job, unless it was
+    /// already recorded by an earlier dispatch attempt of the same cycle.
+    pub async fn start(datastore: &Datastore, job: &JobV1) -> Result<(), Box<dyn Error>> {
+        let Some(cycle_id) = &job.cycle_id else {
+            return Ok(());
+        };
+        let collection = datastore
+            .get_collection::<Document>("job_executions")
+            .await?;
+        collection
+            .update_one(
+                doc! { "cycle_id": cycle_id },
+                doc! { "$setOnInsert": {
+                    "job_name": &job.name,
+                    "started_at": DateTime::now(),
+                    "agents_required": job.target_agents(),
+                    "success_rule": bson::to_bson(&job.success_rule)?,
+                    "results": [],
+                    "job": bson::to_bson(&RunJobSnapshot::from_job(job))?,
+                } },
+            )
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    /// The cycle `cycle_id`, if it was recorded.
+    pub async fn find(
+        datastore: &Datastore,
+        cycle_id: &str,
+    ) -> Result<Option<JobExecutionV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobExecutionV1>("job_executions")
+            .await?;
+        Ok(collection.find_one(doc! { "cycle_id": cycle_id }).await?)
+    }
+
+    /// Adds an agent's result to the cycle. Only the first result an agent reports is kept.
+    pub async fn record_result(
+        datastore: &Datastore,
+        cycle_id: &str,
+        result: &AgentResult,
+    ) -> Result<(), Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<Document>("job_executions")
+            .await?;
+        collection
+            .update_one(
+                doc! {
+                    "cycle_id": cycle_id,
+                    "results.agent_name": { "$ne": &result.agent_name },
+                },
+                doc! { "$push": { "results": bson::to_bson(result)? } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Decides the cycle's outcome from the results reported so far and records it, returning the
+    /// completed execution, or `None` if the cycle was not recorded.
+    pub async fn finish(
+        datastore: &Datastore,
+        cycle_id: &str,
+    ) -> Result<Option<JobExecutionV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobExecutionV1>("job_executions")
+            .await?;
+        let Some(execution) = collection.find_one(doc! { "cycle_id": cycle_id }).await? else {
+            return Ok(None);
+        };
+        let outcome = execution.aggregate_outcome();
+        let execution = collection
+            .find_one_and_update(
+                doc! { "cycle_id": cycle_id },
+                doc! { "$set": {
+                    "outcome": i32::from(outcome),
+                    "completed_at": DateTime::now(),
+                } },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+        Ok(execution)
+    }
+
+    /// Agents that reported success.
+    pub fn successes(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.outcome == Outcome::Success)
+            .count()
+    }
+
+    /// `Success` when the results satisfy the success rule, otherwise `Failure`.
+    pub fn aggregate_outcome(&self) -> Outcome {
+        match self
+            .success_rule
+            .is_met(self.successes(), self.agents_required.len())
+        {
+            true => Outcome::Success,
+            false => Outcome::Failure,
+        }
+    }
+}
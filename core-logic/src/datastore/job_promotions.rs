@@ -0,0 +1,153 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+use crate::datastore::jobs::JobV1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromotionStatus {
+    Pending,  // Waiting for a reviewer
+    Approved, // Applied to the target job
+    Rejected,
+}
+
+/// A request to copy a job's definition from one namespace to another, e.g. from staging to prod,
+/// which is applied once somebody other than its requester approves it. The definition is
+/// captured when the promotion is requested, so what is approved is exactly what was validated,
+/// whatever happens to the source job in the meantime.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobPromotionV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub source_job: String,
+    /// The namespace of the source job, or the default namespace when `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_namespace: Option<String>,
+    /// The source job's latest revision when the promotion was requested, if it has any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_revision: Option<u32>,
+    pub target_job: String,
+    /// The namespace promoted to, or the default namespace when `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_namespace: Option<String>,
+    /// Why the definition is promoted, recorded with the change to the target job.
+    pub note: String,
+    pub requested_by: String,
+    pub requested_at: DateTime,
+    /// The definition to give the target job, named and namespaced as the target.
+    pub job: JobV1,
+    pub status: PromotionStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reviewed_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reviewed_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review_comment: Option<String>,
+}
+
+impl JobPromotionV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "status": 1, "requested_at": -1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "target_job": 1, "status": 1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    /// How the change to the target job is recorded, e.g. in its revisions.
+    pub fn source(&self) -> String {
+        format!(
+            "promotion of {} from namespace {}: {}",
+            self.source_job,
+            self.source_namespace.as_deref().unwrap_or("default"),
+            self.note
+        )
+    }
+
+    pub async fn insert_entry(&self, datastore: &Datastore) -> Result<ObjectId, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobPromotionV1>("job_promotions")
+            .await?;
+        let result = collection.insert_one(self).await?;
+        result
+            .inserted_id
+            .as_object_id()
+            .ok_or_else(|| "Inserted promotion has no object id".into())
+    }
+
+    pub async fn find(datastore: &Datastore, id: ObjectId) -> Result<Option<Self>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobPromotionV1>("job_promotions")
+            .await?;
+        Ok(collection.find_one(doc! { "_id": id }).await?)
+    }
+
+    /// Whether a promotion to `target_job` is waiting for a reviewer.
+    pub async fn pending_for(
+        datastore: &Datastore,
+        target_job: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobPromotionV1>("job_promotions")
+            .await?;
+        Ok(collection
+            .find_one(doc! { "target_job": target_job, "status": "pending" })
+            .await?
+            .is_some())
+    }
+
+    /// Up to `limit` of the promotions matching `filter`, newest first.
+    pub async fn list(
+        datastore: &Datastore,
+        filter: Document,
+        limit: i64,
+    ) -> Result<Vec<Self>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobPromotionV1>("job_promotions")
+            .await?;
+        Ok(collection
+            .find(filter)
+            .sort(doc! { "requested_at": -1 })
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    /// Approves or rejects the promotion as `reviewer`, returning it as decided, or `None` if it
+    /// is unknown or already decided. Only one of several reviewers deciding at once succeeds.
+    pub async fn decide(
+        datastore: &Datastore,
+        id: ObjectId,
+        status: PromotionStatus,
+        reviewer: &str,
+        comment: Option<&str>,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobPromotionV1>("job_promotions")
+            .await?;
+        Ok(collection
+            .find_one_and_update(
+                doc! { "_id": id, "status": "pending" },
+                doc! { "$set": {
+                    "status": bson::to_bson(&status)?,
+                    "reviewed_by": reviewer,
+                    "reviewed_at": DateTime::now(),
+                    "review_comment": comment,
+                } },
+            )
+            .return_document(mongodb::options::ReturnDocument::After)
+            .await?)
+    }
+}
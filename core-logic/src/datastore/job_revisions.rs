@@ -0,0 +1,193 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use futures::TryStreamExt;
+use mongodb::error::{ErrorKind, WriteFailure};
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+use crate::datastore::job_changes::{FieldChange, JobChangeV1};
+use crate::datastore::jobs::JobV1;
+
+/// Code MongoDB fails an insert with when the revision number is taken.
+const DUPLICATE_KEY: i32 = 11000;
+/// Attempts at numbering a revision when another one is recorded for the same job at once.
+const RECORD_ATTEMPTS: usize = 3;
+
+/// One version of a job's definition, recorded each time it is created or edited so its history
+/// can be reviewed and an earlier version restored. Unlike `JobChangeV1`s, revisions are kept as
+/// long as the job is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRevisionV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub job_name: String,
+    /// Numbered from 1 for each job.
+    pub revision: u32,
+    pub created_at: DateTime,
+    /// The web UI user, or the process for edits not made by a user.
+    pub author: String,
+    pub source: String, // e.g. `web UI`, `template nightly-backup` or `jobs file backups.yaml`
+    /// The job as it was defined, without the state of its runs.
+    pub job: JobV1,
+}
+
+impl JobRevisionV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        Datastore::create_unique_index(collection, doc! { "job_name": 1, "revision": 1 }).await?;
+
+        Ok(())
+    }
+
+    /// `job` without the state of its runs, as it is stored in a revision.
+    pub fn snapshot(job: &JobV1) -> JobV1 {
+        JobV1 {
+            id: None,
+            agents_running: vec![],
+            agents_complete: vec![],
+            cycle_agents: vec![],
+            triggered_by: None,
+            cycle_id: None,
+            agents_pending: vec![],
+            cancel_requested_at: None,
+            cancel_sent_to: vec![],
+            timeout_extension_requests: vec![],
+            scheduling_lag_ms: None,
+            deleted_at: None,
+            status_before_delete: None,
+            rerun: None,
+            approval: None,
+            hook_chain: vec![],
+            ..job.clone()
+        }
+    }
+
+    /// Records `job` as the next revision of its definition by `author` through `source`. When
+    /// the job has no revisions yet but had a `previous` definition, e.g. because it was created
+    /// before revisions were recorded, that definition is recorded first, so the edit has
+    /// something to be compared with and rolled back to. Returns the revision number.
+    pub async fn record(
+        datastore: &Datastore,
+        previous: Option<&JobV1>,
+        job: &JobV1,
+        author: &str,
+        source: &str,
+    ) -> Result<u32, Box<dyn Error>> {
+        if let Some(previous) = previous
+            && Self::latest(datastore, &job.name).await?.is_none()
+        {
+            Self::insert_next(datastore, previous, "unknown", "before revision history").await?;
+        }
+        Self::insert_next(datastore, job, author, source).await
+    }
+
+    async fn insert_next(
+        datastore: &Datastore,
+        job: &JobV1,
+        author: &str,
+        source: &str,
+    ) -> Result<u32, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobRevisionV1>("job_revisions")
+            .await?;
+        let mut attempt = 0;
+        loop {
+            let revision = Self::latest(datastore, &job.name)
+                .await?
+                .map_or(1, |latest| latest.revision + 1);
+            let entry = JobRevisionV1 {
+                id: None,
+                job_name: job.name.clone(),
+                revision,
+                created_at: DateTime::now(),
+                author: author.to_string(),
+                source: source.to_string(),
+                job: Self::snapshot(job),
+            };
+            match collection.insert_one(&entry).await {
+                Ok(_) => return Ok(revision),
+                // Another edit took the number in the meantime.
+                Err(e) => match *e.kind {
+                    ErrorKind::Write(WriteFailure::WriteError(ref failure))
+                        if failure.code == DUPLICATE_KEY && attempt + 1 < RECORD_ATTEMPTS =>
+                    {
+                        attempt += 1;
+                    }
+                    _ => return Err(e.into()),
+                },
+            }
+        }
+    }
+
+    /// The job's latest revision.
+    pub async fn latest(
+        datastore: &Datastore,
+        job_name: &str,
+    ) -> Result<Option<JobRevisionV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobRevisionV1>("job_revisions")
+            .await?;
+        Ok(collection
+            .find_one(doc! { "job_name": job_name })
+            .sort(doc! { "revision": -1 })
+            .await?)
+    }
+
+    pub async fn find(
+        datastore: &Datastore,
+        job_name: &str,
+        revision: u32,
+    ) -> Result<Option<JobRevisionV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobRevisionV1>("job_revisions")
+            .await?;
+        Ok(collection
+            .find_one(doc! { "job_name": job_name, "revision": revision as i64 })
+            .await?)
+    }
+
+    /// Up to `limit` of the job's revisions, newest first.
+    pub async fn history(
+        datastore: &Datastore,
+        job_name: &str,
+        limit: i64,
+    ) -> Result<Vec<JobRevisionV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobRevisionV1>("job_revisions")
+            .await?;
+        Ok(collection
+            .find(doc! { "job_name": job_name })
+            .sort(doc! { "revision": -1 })
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    /// The definition fields that differ from `previous` to this revision. `next_run` is left
+    /// out, as it moves with every run rather than with edits.
+    pub fn changes_since(&self, previous: &JobRevisionV1) -> Vec<FieldChange> {
+        JobChangeV1::between(&previous.job, &self.job, &self.source)
+            .map(|change| change.changes)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|change| change.field != "next_run")
+            .collect()
+    }
+
+    /// Deletes every revision of the job, when it is purged. Returns how many were deleted.
+    pub async fn delete_for_job(
+        datastore: &Datastore,
+        job_name: &str,
+    ) -> Result<u64, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobRevisionV1>("job_revisions")
+            .await?;
+        let result = collection
+            .delete_many(doc! { "job_name": job_name })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
@@ -0,0 +1,399 @@
+use bson::{Document, doc, oid::ObjectId};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+
+use crate::datastore::jobs::{
+    JobExecutor, JobSla, JobTemplateRef, JobV1, MisfirePolicy, Status, SuccessRule,
+};
+use crate::redaction;
+
+/// Characters a value may not contain when substituted into a job's command or arguments.
+/// Arguments are split on whitespace and may be run through a shell, so these could inject
+/// extra arguments or commands.
+const UNSAFE_COMMAND_CHARACTERS: &[char] = &[
+    ';', '&', '|', '$', '`', '<', '>', '(', ')', '{', '}', '[', ']', '*', '?', '!', '~', '\'', '"',
+    '\\', '#', '%', '^',
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterKind {
+    #[default]
+    String,
+    Integer,
+    Boolean,
+    /// One of `allowed_values`.
+    Choice,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TemplateParameter {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub kind: ParameterKind,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub allowed_values: Vec<String>, // Values accepted by `Choice` parameters
+    #[serde(default)]
+    pub pattern: Option<String>, // Regular expression the whole value must match
+}
+
+/// A reusable job definition whose command, args, env and cwd may contain `{{placeholders}}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobTemplateV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub cwd: String,
+    #[serde(default)]
+    pub timeout: u32,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default)]
+    pub valid_return_codes: Vec<i32>,
+    #[serde(default)]
+    pub redact_patterns: Vec<String>, // Copied to jobs created from the template
+    #[serde(default)]
+    pub sla: Option<JobSla>, // Copied to jobs created from the template
+    #[serde(default)]
+    pub parameters: Vec<TemplateParameter>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    InvalidName(String),
+    DuplicateParameter(String),
+    UndeclaredPlaceholder(String),
+    UnterminatedPlaceholder(String),
+    InvalidPattern { parameter: String, error: String },
+    MissingChoices(String),
+    MissingParameter(String),
+    UnknownParameter(String),
+    InvalidValue { parameter: String, reason: String },
+    InvalidRedactPattern(String),
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::InvalidName(name) => write!(f, "Invalid parameter name: {}", name),
+            TemplateError::DuplicateParameter(name) => {
+                write!(f, "Parameter {} is declared more than once", name)
+            }
+            TemplateError::UndeclaredPlaceholder(name) => {
+                write!(f, "Placeholder {{{{{}}}}} has no matching parameter", name)
+            }
+            TemplateError::UnterminatedPlaceholder(text) => {
+                write!(f, "Unterminated placeholder in: {}", text)
+            }
+            TemplateError::InvalidPattern { parameter, error } => {
+                write!(f, "Invalid pattern for parameter {}: {}", parameter, error)
+            }
+            TemplateError::MissingChoices(name) => {
+                write!(f, "Choice parameter {} has no allowed values", name)
+            }
+            TemplateError::MissingParameter(name) => {
+                write!(f, "Missing required parameter: {}", name)
+            }
+            TemplateError::UnknownParameter(name) => write!(f, "Unknown parameter: {}", name),
+            TemplateError::InvalidValue { parameter, reason } => {
+                write!(f, "Invalid value for parameter {}: {}", parameter, reason)
+            }
+            TemplateError::InvalidRedactPattern(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// Names of the `{{placeholders}}` in `text`, in order of appearance.
+fn placeholders(text: &str) -> Result<Vec<String>, TemplateError> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let after_open = &rest[start + 2..];
+        let end = after_open
+            .find("}}")
+            .ok_or_else(|| TemplateError::UnterminatedPlaceholder(text.to_string()))?;
+        names.push(after_open[..end].trim().to_string());
+        rest = &after_open[end + 2..];
+    }
+    Ok(names)
+}
+
+/// Replaces every `{{placeholder}}` in `text` with its value.
+fn substitute(text: &str, values: &HashMap<String, String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let after_open = &rest[start + 2..];
+        // Templates are validated before use, so every placeholder is terminated and declared.
+        let end = after_open.find("}}").unwrap_or(after_open.len());
+        let name = after_open[..end].trim();
+        result.push_str(values.get(name).map(String::as_str).unwrap_or_default());
+        rest = after_open.get(end + 2..).unwrap_or_default();
+    }
+    result.push_str(rest);
+    result
+}
+
+impl TemplateParameter {
+    /// Checks `value` against the parameter's kind, choices and pattern.
+    fn validate(&self, value: &str, in_command: bool) -> Result<(), TemplateError> {
+        let invalid = |reason: &str| TemplateError::InvalidValue {
+            parameter: self.name.clone(),
+            reason: reason.to_string(),
+        };
+
+        if value.chars().any(char::is_control) {
+            return Err(invalid("control characters are not allowed"));
+        }
+        if in_command
+            && value
+                .chars()
+                .any(|c| c.is_whitespace() || UNSAFE_COMMAND_CHARACTERS.contains(&c))
+        {
+            return Err(invalid(
+                "whitespace and shell metacharacters are not allowed in commands or arguments",
+            ));
+        }
+
+        match self.kind {
+            ParameterKind::String => {}
+            ParameterKind::Integer => {
+                value
+                    .parse::<i64>()
+                    .map_err(|_| invalid("expected an integer"))?;
+            }
+            ParameterKind::Boolean => {
+                if value != "true" && value != "false" {
+                    return Err(invalid("expected true or false"));
+                }
+            }
+            ParameterKind::Choice => {
+                if !self.allowed_values.iter().any(|allowed| allowed == value) {
+                    return Err(invalid(&format!(
+                        "expected one of {}",
+                        self.allowed_values.join(", ")
+                    )));
+                }
+            }
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let regex = Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| {
+                TemplateError::InvalidPattern {
+                    parameter: self.name.clone(),
+                    error: e.to_string(),
+                }
+            })?;
+            if !regex.is_match(value) {
+                return Err(invalid(&format!("does not match pattern {}", pattern)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl JobTemplateV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let index_doc = doc! { "name": 1, };
+        crate::datastore::Datastore::create_unique_index(collection, index_doc).await?;
+
+        Ok(())
+    }
+
+    /// Placeholders used in the command and args, where values are split on whitespace.
+    fn command_placeholders(&self) -> Result<HashSet<String>, TemplateError> {
+        let mut names = HashSet::new();
+        for text in std::iter::once(&self.command).chain(self.args.iter()) {
+            names.extend(placeholders(text)?);
+        }
+        Ok(names)
+    }
+
+    /// Checks that parameters are well formed and every placeholder is declared.
+    pub fn validate(&self) -> Result<(), TemplateError> {
+        let mut declared = HashSet::new();
+        for parameter in &self.parameters {
+            let valid_name = !parameter.name.is_empty()
+                && parameter
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid_name {
+                return Err(TemplateError::InvalidName(parameter.name.clone()));
+            }
+            if !declared.insert(parameter.name.as_str()) {
+                return Err(TemplateError::DuplicateParameter(parameter.name.clone()));
+            }
+            if parameter.kind == ParameterKind::Choice && parameter.allowed_values.is_empty() {
+                return Err(TemplateError::MissingChoices(parameter.name.clone()));
+            }
+        }
+
+        for pattern in &self.redact_patterns {
+            redaction::validate_pattern(pattern)
+                .map_err(|e| TemplateError::InvalidRedactPattern(e.to_string()))?;
+        }
+
+        let command_placeholders = self.command_placeholders()?;
+        for text in std::iter::once(&self.command)
+            .chain(self.args.iter())
+            .chain(self.env.iter())
+            .chain(std::iter::once(&self.cwd))
+        {
+            for name in placeholders(text)? {
+                if !declared.contains(name.as_str()) {
+                    return Err(TemplateError::UndeclaredPlaceholder(name));
+                }
+            }
+        }
+
+        for parameter in &self.parameters {
+            if let Some(default) = &parameter.default {
+                parameter.validate(default, command_placeholders.contains(&parameter.name))?;
+            } else if let Some(pattern) = &parameter.pattern {
+                Regex::new(pattern).map_err(|e| TemplateError::InvalidPattern {
+                    parameter: parameter.name.clone(),
+                    error: e.to_string(),
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the value of every parameter from `values`, falling back to defaults.
+    pub fn resolve_parameters(
+        &self,
+        values: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, TemplateError> {
+        if let Some(unknown) = values
+            .keys()
+            .find(|name| !self.parameters.iter().any(|p| &p.name == *name))
+        {
+            return Err(TemplateError::UnknownParameter(unknown.clone()));
+        }
+
+        let command_placeholders = self.command_placeholders()?;
+        let mut resolved = HashMap::new();
+        for parameter in &self.parameters {
+            let value = match (values.get(&parameter.name), &parameter.default) {
+                (Some(value), _) => value.clone(),
+                (None, Some(default)) => default.clone(),
+                (None, None) if parameter.required => {
+                    return Err(TemplateError::MissingParameter(parameter.name.clone()));
+                }
+                (None, None) => String::new(),
+            };
+            if !value.is_empty() || parameter.required {
+                parameter.validate(&value, command_placeholders.contains(&parameter.name))?;
+            }
+            resolved.insert(parameter.name.clone(), value);
+        }
+        Ok(resolved)
+    }
+
+    /// Builds a pending job named `job_name` from the template and parameter `values`.
+    pub fn instantiate(
+        &self,
+        job_name: &str,
+        values: &HashMap<String, String>,
+        agents_required: Vec<String>,
+        next_run: i64,
+    ) -> Result<JobV1, TemplateError> {
+        self.validate()?;
+        let parameters = self.resolve_parameters(values)?;
+
+        Ok(JobV1 {
+            id: None,
+            name: job_name.to_string(),
+            next_run,
+            status: Status::Pending,
+            description: self.description.clone(),
+            command: substitute(&self.command, &parameters),
+            args: self
+                .args
+                .iter()
+                .map(|arg| substitute(arg, &parameters))
+                .collect(),
+            env: self
+                .env
+                .iter()
+                .map(|env| substitute(env, &parameters))
+                .collect(),
+            cwd: substitute(&self.cwd, &parameters),
+            timeout: self.timeout,
+            retries: self.retries,
+            valid_return_codes: self.valid_return_codes.clone(),
+            agents_required,
+            agents_running: vec![],
+            agents_complete: vec![],
+            agent_groups: vec![],
+            platforms: vec![],
+            node_selector: vec![],
+            executor: JobExecutor::default(),
+            cycle_agents: vec![],
+            triggered_by: None,
+            cycle_id: None,
+            agents_pending: vec![],
+            redact_patterns: self.redact_patterns.clone(),
+            sla: self.sla.clone(),
+            managed_by: None,
+            cancel_requested_at: None,
+            cancel_sent_to: vec![],
+            timeout_extension_requests: vec![],
+            scheduling_lag_ms: None,
+            steps: vec![],
+            files: vec![],
+            script: None,
+            container: None,
+            output_parsers: vec![],
+            success_statuses: vec![],
+            check: false,
+            success_rule: SuccessRule::default(),
+            one_shot: false,
+            requires_approval: false,
+            approval: None,
+            on_success: vec![],
+            on_failure: vec![],
+            hook_chain: vec![],
+            schedule_interval: None,
+            cron: None,
+            timezone: None,
+            misfire_policy: MisfirePolicy::default(),
+            deleted_at: None,
+            status_before_delete: None,
+            rerun: None,
+            owner: None,
+            team: None,
+            tags: vec![],
+            metadata: Document::new(),
+            namespace: None,
+            template: Some(JobTemplateRef {
+                name: self.name.clone(),
+                parameters,
+            }),
+        })
+    }
+}
@@ -0,0 +1,242 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+use crate::datastore::check_states::{self, CheckState};
+use crate::datastore::jobs::JobV1;
+use crate::datastore::runs::RunsV1;
+use crate::messages::JobProgress;
+
+/// What a job warning is about.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobWarningKind {
+    /// The agent warned that the run used most of its timeout.
+    #[default]
+    TimeoutWarning,
+    /// The run has been going for longer than the job's `sla.expected_duration`.
+    SlaBreached,
+    /// The job's scheduled run has not started within the job's `sla.max_start_delay`.
+    Overdue,
+    /// A run of a check job changed the check's state on its agent, e.g. from OK to CRITICAL.
+    StateChanged,
+}
+
+impl std::fmt::Display for JobWarningKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobWarningKind::TimeoutWarning => write!(f, "timeout_warning"),
+            JobWarningKind::SlaBreached => write!(f, "sla_breached"),
+            JobWarningKind::Overdue => write!(f, "overdue"),
+            JobWarningKind::StateChanged => write!(f, "state_changed"),
+        }
+    }
+}
+
+/// A warning about a run, recorded by central command so the job's owners can be told through
+/// `JOB_WARNING_WEBHOOK_URLS`: from an agent that a run has used most of its timeout, when it
+/// receives `JobProgress`, so they hear before the run is killed, that a run has gone on for
+/// longer than the job's SLA expects, that a scheduled run has not started in time, or that a
+/// check job changed state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobWarningV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(default)]
+    pub kind: JobWarningKind,
+    pub job_name: String,
+    pub agent_name: String, // Empty for overdue jobs, which concern no agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// The cycle of the run, for SLA breaches, which are recorded once per cycle and agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cycle_id: Option<String>,
+    pub at: DateTime,
+    pub elapsed_seconds: u32,
+    pub timeout_seconds: u32,
+    /// The job's `sla.expected_duration`, for SLA breaches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_duration_seconds: Option<u32>,
+    /// The `next_run` that did not start, for overdue jobs, which are recorded once per run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduled_at: Option<DateTime>,
+    /// The check's new state, for changes of state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<CheckState>,
+    /// The check's state before the run, for changes of state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_state: Option<CheckState>,
+    /// The first line of the run's output, for changes of state.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub summary: String,
+    /// Set until central command has sent the warning to its webhooks.
+    #[serde(default)]
+    pub notification_pending: bool,
+}
+
+impl From<&JobProgress> for JobWarningV1 {
+    fn from(progress: &JobProgress) -> Self {
+        Self {
+            id: None,
+            kind: JobWarningKind::TimeoutWarning,
+            job_name: progress.job_name.clone(),
+            agent_name: progress.agent_name.clone(),
+            run_id: progress.run_id.clone(),
+            cycle_id: None,
+            at: DateTime::now(),
+            elapsed_seconds: progress.elapsed,
+            timeout_seconds: progress.timeout,
+            expected_duration_seconds: None,
+            scheduled_at: None,
+            state: None,
+            previous_state: None,
+            summary: String::new(),
+            notification_pending: true,
+        }
+    }
+}
+
+impl JobWarningV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "job_name": 1, "at": 1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "notification_pending": 1, "at": 1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "cycle_id": 1, "agent_name": 1, "kind": 1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "job_name": 1, "scheduled_at": 1, "kind": 1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    /// A warning that `run` of a check job changed its state from `previous_state`.
+    pub fn state_changed(run: &RunsV1, previous_state: CheckState) -> Self {
+        Self {
+            id: None,
+            kind: JobWarningKind::StateChanged,
+            job_name: run.job_name.clone(),
+            agent_name: run.agent_name.clone(),
+            run_id: run.run_id.clone(),
+            cycle_id: run.cycle_id.clone(),
+            at: run.completed_at,
+            elapsed_seconds: ((run.completed_at.timestamp_millis()
+                - run.started_at.timestamp_millis())
+                / 1000)
+                .max(0) as u32,
+            timeout_seconds: run.job.as_ref().map(|job| job.timeout).unwrap_or_default(),
+            expected_duration_seconds: None,
+            scheduled_at: None,
+            state: Some(CheckState::from_run(run.outcome, run.return_code)),
+            previous_state: Some(previous_state),
+            summary: check_states::summary(&run.output),
+            notification_pending: true,
+        }
+    }
+
+    pub async fn insert_entry(&self, datastore: &Datastore) -> Result<(), Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobWarningV1>("job_warnings")
+            .await?;
+        collection.insert_one(self).await?;
+        Ok(())
+    }
+
+    /// Records that the run of `job`'s current cycle on `agent_name` breached the job's SLA after
+    /// `elapsed_seconds`, unless it was already recorded. Returns whether it was recorded now.
+    pub async fn record_sla_breach(
+        datastore: &Datastore,
+        job: &JobV1,
+        agent_name: &str,
+        elapsed_seconds: u32,
+        expected_duration_seconds: u32,
+    ) -> Result<bool, Box<dyn Error>> {
+        let Some(cycle_id) = &job.cycle_id else {
+            return Ok(false);
+        };
+        let collection = datastore.get_collection::<Document>("job_warnings").await?;
+        let filter = doc! {
+            "cycle_id": cycle_id,
+            "agent_name": agent_name,
+            "kind": JobWarningKind::SlaBreached.to_string(),
+        };
+        let update = doc! { "$setOnInsert": {
+            "job_name": &job.name,
+            "at": DateTime::now(),
+            "elapsed_seconds": elapsed_seconds as i64,
+            "timeout_seconds": job.timeout as i64,
+            "expected_duration_seconds": expected_duration_seconds as i64,
+            "notification_pending": true,
+        } };
+        let result = collection.update_one(filter, update).upsert(true).await?;
+        Ok(result.upserted_id.is_some())
+    }
+
+    /// Records that `job`'s run scheduled at `next_run` has not started `overdue_seconds` later,
+    /// unless it was already recorded. Returns whether it was recorded now.
+    pub async fn record_overdue(
+        datastore: &Datastore,
+        job: &JobV1,
+        overdue_seconds: u32,
+    ) -> Result<bool, Box<dyn Error>> {
+        let collection = datastore.get_collection::<Document>("job_warnings").await?;
+        let filter = doc! {
+            "job_name": &job.name,
+            "scheduled_at": DateTime::from_millis(job.next_run.saturating_mul(1000)),
+            "kind": JobWarningKind::Overdue.to_string(),
+        };
+        let update = doc! { "$setOnInsert": {
+            "agent_name": "",
+            "at": DateTime::now(),
+            "elapsed_seconds": overdue_seconds as i64,
+            "timeout_seconds": job.timeout as i64,
+            "notification_pending": true,
+        } };
+        let result = collection.update_one(filter, update).upsert(true).await?;
+        Ok(result.upserted_id.is_some())
+    }
+
+    /// Warnings not yet sent to the webhooks, oldest first.
+    pub async fn pending_notifications(
+        datastore: &Datastore,
+        limit: i64,
+    ) -> Result<Vec<JobWarningV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobWarningV1>("job_warnings")
+            .await?;
+        let warnings = collection
+            .find(doc! { "notification_pending": true })
+            .sort(doc! { "at": 1 })
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(warnings)
+    }
+
+    pub async fn mark_notified(&self, datastore: &Datastore) -> Result<(), Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<JobWarningV1>("job_warnings")
+            .await?;
+        collection
+            .update_one(
+                doc! { "_id": self.id },
+                doc! { "$set": { "notification_pending": false } },
+            )
+            .await?;
+        Ok(())
+    }
+}
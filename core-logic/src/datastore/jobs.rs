@@ -1,7 +1,17 @@
-use bson::{Document, doc, oid::ObjectId};
+use bson::{DateTime, Document, doc, oid::ObjectId};
 use mongodb::bson::Bson;
 use serde::{Deserialize, Serialize};
 
+use chrono_tz::Tz;
+
+use std::collections::HashMap;
+
+use crate::cron::CronSchedule;
+use crate::datastore::agents::AgentV1;
+use crate::datastore::runs::TriggeredBy;
+use crate::messages;
+use crate::output_parsing::{self, OutputParser};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(i32)]
 #[serde(from = "i32")]
@@ -12,6 +22,8 @@ pub enum Status {
     Completed = 2,
     Frozen = 3,
     Error = 4,
+    Archived = 5, // A one-shot job that ran; hidden from the default views and never run again
+    PendingApproval = 6, // A job that `requires_approval` whose run is due, waiting for an operator
 }
 
 // Implementation to convert from i32 to Status
@@ -23,6 +35,8 @@ impl From<i32> for Status {
             2 => Status::Completed,
             3 => Status::Frozen,
             4 => Status::Error,
+            5 => Status::Archived,
+            6 => Status::PendingApproval,
             _ => {
                 // Handle unknown values gracefully (e.g., default to Error or Pending)
                 // Or panic if an invalid status is truly an unrecoverable error.
@@ -45,12 +59,46 @@ impl From<Status> for Bson {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// `next_run` as stored and queried: a BSON DateTime at the second.
+pub fn next_run_datetime(seconds: i64) -> DateTime {
+    DateTime::from_millis(seconds.saturating_mul(1000))
+}
+
+/// Keeps `next_run` in Unix seconds for the schedule arithmetic, stored as a BSON DateTime like
+/// every other time field. Jobs stored before that, with `next_run` in seconds, still read; the
+/// startup migrations convert them.
+mod next_run_format {
+    use bson::{Bson, DateTime};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(seconds: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        super::next_run_datetime(*seconds).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        match Bson::deserialize(deserializer)? {
+            Bson::DateTime(at) => Ok(at.timestamp_millis().div_euclid(1000)),
+            Bson::Int64(seconds) => Ok(seconds),
+            Bson::Int32(seconds) => Ok(seconds as i64),
+            Bson::Double(seconds) => Ok(seconds as i64),
+            Bson::String(at) => DateTime::parse_rfc3339_str(&at)
+                .map(|at| at.timestamp_millis().div_euclid(1000))
+                .map_err(D::Error::custom),
+            other => Err(D::Error::custom(format!(
+                "expected a date for next_run, found {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JobV1 {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub name: String,
-    pub next_run: i64,
+    #[serde(with = "next_run_format")]
+    pub next_run: i64, // Unix seconds; a BSON DateTime in the datastore, see `next_run_datetime`
     pub status: Status,
     pub description: String,
     pub command: String,
@@ -63,6 +111,1000 @@ pub struct JobV1 {
     pub agents_required: Vec<String>,
     pub agents_running: Vec<String>,
     pub agents_complete: Vec<String>,
+    /// Agent groups whose members run the job alongside `agents_required`. Expanded when each
+    /// cycle is dispatched, so it follows membership changes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub agent_groups: Vec<String>,
+    /// Platforms the job runs on, each `os` or `os/arch` (e.g. `linux/amd64`). Targeted agents on
+    /// other platforms are left out of each cycle; empty runs on any platform.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub platforms: Vec<String>,
+    /// Kubernetes node labels, each `key=value`, the node of an agent must all have for the agent
+    /// to run the job, like a pod's `nodeSelector`. Targeted agents on other nodes, or not on
+    /// Kubernetes, are left out of each cycle; empty runs on any node. With the Kubernetes
+    /// executor it is the `nodeSelector` of the job's pods.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub node_selector: Vec<String>,
+    /// What runs the job: its agents, or central command's Kubernetes executor.
+    #[serde(default)]
+    pub executor: JobExecutor,
+    /// The agents the pending or running cycle targets: `agents_required` and the members of
+    /// `agent_groups` when the cycle started.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cycle_agents: Vec<String>,
+    /// Who triggered the pending or running cycle; `None` means the scheduler.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triggered_by: Option<TriggeredBy>,
+    /// Identifies the pending or running cycle; every run it produces is tagged with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cycle_id: Option<String>,
+    /// Agents of the running cycle it was not dispatched to yet: those of partitions other central
+    /// command instances hold (see `AGENT_PARTITIONS`), and agents that pull their jobs until they
+    /// claim the run (see `PollWork`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub agents_pending: Vec<String>,
+    /// Regular expressions redacted from the output by the agent, in addition to its defaults.
+    #[serde(default)]
+    pub redact_patterns: Vec<String>,
+    /// The template and parameter values the job was created from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<JobTemplateRef>,
+    /// Service level expectations, exported by the web UI as Prometheus alerting rules.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sla: Option<JobSla>,
+    /// The jobs file the definition is synced from; central command disables the job when it is
+    /// removed from the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed_by: Option<String>,
+    /// When an operator asked to cancel the running cycle; central command sends `CancelJob` to
+    /// the agents running it and clears the request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cancel_requested_at: Option<bson::DateTime>,
+    /// Agents `CancelJob` was sent to for the current request, when central command instances
+    /// share the agents and each cancels the job on its own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cancel_sent_to: Vec<String>,
+    /// Timeouts an operator asked to extend for runs of the current cycle; central command sends
+    /// `ExtendTimeout` to the agents running them and removes the requests.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timeout_extension_requests: Vec<TimeoutExtensionRequest>,
+    /// Milliseconds between `next_run` and central command dispatching the current or latest
+    /// cycle, i.e. how far the dispatcher was behind schedule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduling_lag_ms: Option<i64>,
+    /// Commands run in order on each agent instead of `command`, when not empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<JobStep>,
+    /// Files pushed to each agent before the job is dispatched to it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<JobFile>,
+    /// A script run instead of `command`, with `args` as its arguments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<JobScript>,
+    /// A container image the agents run the command, or each step, in instead of on their host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<JobContainer>,
+    /// Extract a status and values such as `records_processed` from each run's output, stored on
+    /// the run (see `output_parsing`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub output_parsers: Vec<OutputParser>,
+    /// The parsed statuses of a successful run, e.g. `complete`. When set, a run with a valid return
+    /// code whose status is missing or another one fails.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub success_statuses: Vec<String>,
+    /// Runs as a monitoring check: the exit code of each run, 0, 1 or 2 as with Nagios plugins,
+    /// sets the check's state on its agent to OK, WARNING or CRITICAL (see `check_states`), and
+    /// changes of state are sent to `JOB_WARNING_WEBHOOK_URLS`.
+    #[serde(default)]
+    pub check: bool,
+    /// How the results of the job's agents decide whether a cycle succeeded, which sets the job's
+    /// final status.
+    #[serde(default)]
+    pub success_rule: SuccessRule,
+    /// Runs once, at `next_run`, and is archived when the run completes instead of staying in the
+    /// jobs list.
+    #[serde(default)]
+    pub one_shot: bool,
+    /// Whether each run waits, as `Status::PendingApproval`, for an operator to approve it before
+    /// it is dispatched, e.g. for jobs that change production systems.
+    #[serde(default)]
+    pub requires_approval: bool,
+    /// The approval of the due run of a job that `requires_approval`, consumed when its cycle
+    /// starts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<JobApproval>,
+    /// Jobs triggered when a cycle of the job succeeds, e.g. a follow-up report.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_success: Vec<String>,
+    /// Jobs triggered when a cycle of the job fails, e.g. a cleanup or rollback job.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<String>,
+    /// The jobs whose hooks triggered the pending or running cycle, first to last. A hook never
+    /// triggers a job already in the chain, so hooks cannot loop.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hook_chain: Vec<String>,
+    /// Seconds between scheduled runs. Each completed cycle makes the job pending again for its
+    /// next run; `None` runs it only at `next_run`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule_interval: Option<u32>,
+    /// A cron expression (see `core_logic::cron`) scheduling the job by wall-clock time instead of
+    /// every `schedule_interval`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    /// The IANA time zone `cron` is evaluated in, e.g. `America/New_York`; UTC when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// What central command does about scheduled runs it missed, e.g. while it was down.
+    #[serde(default)]
+    pub misfire_policy: MisfirePolicy,
+    /// When the job was moved to the trash. A trashed job is frozen, so it is not scheduled, and
+    /// is left out of listings until it is restored or purged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<bson::DateTime>,
+    /// The status the job had when it was trashed, given back to it when it is restored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_before_delete: Option<Status>,
+    /// An earlier run to repeat in the next cycle instead of running the job as defined; cleared
+    /// when the cycle completes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerun: Option<JobRerun>,
+    /// The user who created the job, when it was created by a known user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// The team the job belongs to. With team scoping on (see the web UI's `WEBUI_TEAMS`), only
+    /// the team's members and admins see and modify it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    /// Labels organizing the job, e.g. `backup` or `etl`, that the jobs and runs pages filter by.
+    /// Copied to each run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Free-form values describing the job, e.g. `{"owner_email": "...", "ticket": "OPS-12"}`.
+    /// Copied to each run.
+    #[serde(default, skip_serializing_if = "Document::is_empty")]
+    pub metadata: Document,
+    /// The namespace the job belongs to, e.g. `staging`, or the default namespace when `None`. It
+    /// only runs on agents of the same namespace, and its runs are recorded in it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+/// The agent name central command's Kubernetes executor registers as. Runs of jobs with the
+/// `kubernetes` executor are dispatched to it, and recorded on it.
+pub const KUBERNETES_EXECUTOR_AGENT: &str = "kubernetes";
+
+/// The agent name central command's SSH executor registers as. Runs of jobs with the `ssh`
+/// executor are dispatched to it, and recorded on it.
+pub const SSH_EXECUTOR_AGENT: &str = "ssh";
+
+/// The `kind` of each executor central command runs jobs with, and the agent it registers as.
+pub const EXECUTOR_AGENTS: [(&str, &str); 2] = [
+    ("kubernetes", KUBERNETES_EXECUTOR_AGENT),
+    ("ssh", SSH_EXECUTOR_AGENT),
+];
+
+/// What runs a job's cycles.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobExecutor {
+    /// The job's `agents_required` and the members of its `agent_groups`.
+    #[default]
+    Agent,
+    /// A Kubernetes Job central command creates for each run, running the job's command in
+    /// `image` instead of on an agent. The job's `node_selector` becomes the pod's.
+    Kubernetes {
+        image: String,
+        /// The namespace the Job is created in; central command's default when unset.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        namespace: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        service_account: Option<String>,
+    },
+    /// A host without an agent central command runs the job's command on over SSH, e.g. an
+    /// appliance or a host agents cannot be installed on.
+    Ssh {
+        host: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u16>,
+        user: String,
+        /// Name of the secret holding the user's private key (see `SECRETS_DIR` of central
+        /// command).
+        key_secret: String,
+    },
+}
+
+impl JobExecutor {
+    /// The agent central command registers for the executor, or `None` when the job runs on its
+    /// agents.
+    pub fn agent_name(&self) -> Option<&'static str> {
+        match self {
+            JobExecutor::Agent => None,
+            JobExecutor::Kubernetes { .. } => Some(KUBERNETES_EXECUTOR_AGENT),
+            JobExecutor::Ssh { .. } => Some(SSH_EXECUTOR_AGENT),
+        }
+    }
+
+    /// The `kind` the executor is stored with.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobExecutor::Agent => "agent",
+            JobExecutor::Kubernetes { .. } => "kubernetes",
+            JobExecutor::Ssh { .. } => "ssh",
+        }
+    }
+}
+
+impl std::fmt::Display for JobExecutor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobExecutor::Agent => write!(f, "agent"),
+            JobExecutor::Kubernetes {
+                image, namespace, ..
+            } => match namespace {
+                Some(namespace) => write!(f, "kubernetes ({} in {})", image, namespace),
+                None => write!(f, "kubernetes ({})", image),
+            },
+            JobExecutor::Ssh {
+                host, port, user, ..
+            } => match port {
+                Some(port) => write!(f, "ssh ({}@{}:{})", user, host, port),
+                None => write!(f, "ssh ({}@{})", user, host),
+            },
+        }
+    }
+}
+
+/// Checks that a job run by the Kubernetes executor names an image and one run by the SSH
+/// executor a host, user and key, and that neither has the steps, script or files only agents
+/// support. Node selectors only apply to agents and Kubernetes pods.
+pub fn validate_executor(
+    executor: &JobExecutor,
+    steps: &[JobStep],
+    script: Option<&JobScript>,
+    files: &[JobFile],
+    node_selector: &[String],
+) -> Result<(), String> {
+    match executor {
+        JobExecutor::Agent => return Ok(()),
+        JobExecutor::Kubernetes {
+            image,
+            namespace,
+            service_account,
+        } => {
+            if image.trim().is_empty() {
+                return Err("The kubernetes executor needs an image".to_string());
+            }
+            if let Some(namespace) = namespace {
+                validate_kubernetes_name("namespace", namespace)?;
+            }
+            if let Some(service_account) = service_account {
+                validate_kubernetes_name("service account", service_account)?;
+            }
+        }
+        JobExecutor::Ssh {
+            host,
+            user,
+            key_secret,
+            ..
+        } => {
+            if host.trim().is_empty() || user.trim().is_empty() || key_secret.trim().is_empty() {
+                return Err("The ssh executor needs a host, user and key_secret".to_string());
+            }
+            if host.starts_with('-') || user.starts_with('-') || user.contains('@') {
+                return Err(format!("Invalid ssh host {} or user {}", host, user));
+            }
+            if key_secret.contains(['/', '\\']) || key_secret.starts_with('.') {
+                return Err(format!("Invalid key_secret {}", key_secret));
+            }
+            if !node_selector.is_empty() {
+                return Err("Node selectors are not supported by the ssh executor".to_string());
+            }
+        }
+    }
+    if !steps.is_empty() || script.is_some() || !files.is_empty() {
+        return Err(format!(
+            "Steps, scripts and files are not supported by the {} executor",
+            executor.kind()
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `name` is a DNS-1123 label, as Kubernetes namespaces are: at most 63 lowercase
+/// letters, digits and `-`, starting and ending with a letter or digit. Such names are safe in the
+/// paths of Kubernetes API requests.
+pub fn validate_kubernetes_name(kind: &str, name: &str) -> Result<(), String> {
+    let alphanumeric = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    let valid = !name.is_empty()
+        && name.len() <= 63
+        && name.chars().all(|c| alphanumeric(c) || c == '-')
+        && name.starts_with(alphanumeric)
+        && name.ends_with(alphanumeric);
+    match valid {
+        true => Ok(()),
+        false => Err(format!(
+            "Invalid kubernetes {} {:?}, expected lowercase letters, digits and -, e.g. batch-jobs",
+            kind, name
+        )),
+    }
+}
+
+/// A re-run of one run: the cycle runs only on the agent that ran it, with the command, arguments
+/// and environment the run had. Steps, script and files still come from the job's definition.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRerun {
+    pub run_id: String, // Hex `_id` of the run being repeated
+    pub agent_name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<String>,
+}
+
+/// An operator's approval of the due run of a job that `requires_approval`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobApproval {
+    pub approved_by: String,
+    pub approved_at: bson::DateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// A request to give the run of a job on one agent `seconds` more before it is killed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeoutExtensionRequest {
+    pub agent_name: String,
+    pub seconds: u32,
+    pub requested_at: bson::DateTime,
+}
+
+/// What happens to scheduled runs that were due more than `MISFIRE_GRACE_SECONDS` before central
+/// command got to dispatch them. Missed runs that are not run are recorded as `Skipped` runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MisfirePolicy {
+    /// Run once straight away in place of all the missed runs, then continue on schedule.
+    #[default]
+    RunOnce,
+    /// Run none of the missed runs and wait for the next scheduled run.
+    Skip,
+    /// Run every missed run, one cycle after another, until the job is back on schedule.
+    RunAll,
+}
+
+impl std::fmt::Display for MisfirePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MisfirePolicy::RunOnce => write!(f, "run once"),
+            MisfirePolicy::Skip => write!(f, "skip"),
+            MisfirePolicy::RunAll => write!(f, "run all"),
+        }
+    }
+}
+
+/// Checks that a job's schedule can be followed: recurring jobs need an interval of at least a
+/// second or a cron expression that fires, not both, and cannot be one-shot.
+pub fn validate_schedule(
+    schedule_interval: Option<u32>,
+    cron: Option<&str>,
+    timezone: Option<&str>,
+    one_shot: bool,
+) -> Result<(), String> {
+    let timezone = parse_timezone(timezone)?;
+    match (schedule_interval, cron) {
+        (Some(0), _) => Err("schedule_interval must be at least 1 second".to_string()),
+        (Some(_), Some(_)) => {
+            Err("A job cannot have both a schedule_interval and a cron schedule".to_string())
+        }
+        (Some(_), None) if one_shot => {
+            Err("A one-shot job cannot have a schedule_interval".to_string())
+        }
+        (None, Some(_)) if one_shot => {
+            Err("A one-shot job cannot have a cron schedule".to_string())
+        }
+        (None, Some(cron)) => {
+            let schedule = CronSchedule::parse(cron)?;
+            match schedule.next_after(chrono::Utc::now().timestamp(), timezone) {
+                Some(_) => Ok(()),
+                None => Err(format!("Cron schedule {:?} never fires", cron)),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that each of a job's `platforms` is `os` or `os/arch`, e.g. `linux` or `linux/amd64`.
+pub fn validate_platforms(platforms: &[String]) -> Result<(), String> {
+    let is_name = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    };
+    for platform in platforms {
+        let valid = match platform.split_once('/') {
+            Some((os, arch)) => is_name(os) && is_name(arch),
+            None => is_name(platform),
+        };
+        if !valid {
+            return Err(format!(
+                "Invalid platform {:?}, expected os or os/arch, e.g. linux/amd64",
+                platform
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that each label of a job's `node_selector` is `key=value`, e.g. `disktype=ssd`.
+pub fn validate_node_selector(node_selector: &[String]) -> Result<(), String> {
+    for label in node_selector {
+        let valid = match label.split_once('=') {
+            Some((key, value)) => {
+                !key.is_empty() && !label.contains(char::is_whitespace) && !value.contains('=')
+            }
+            None => false,
+        };
+        if !valid {
+            return Err(format!(
+                "Invalid node label {:?}, expected key=value, e.g. disktype=ssd",
+                label
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that each of a job's `tags` is a non-empty word of ASCII letters, digits and `_.:/=-`,
+/// so that tags split cleanly out of the comma separated tag filters and are safe to show.
+pub fn validate_tags(tags: &[String]) -> Result<(), String> {
+    for tag in tags {
+        let allowed = |c: char| c.is_ascii_alphanumeric() || "_.:/=-".contains(c);
+        if tag.is_empty() || !tag.chars().all(allowed) {
+            return Err(format!(
+                "Invalid tag {:?}, expected letters, digits and _.:/=- only, e.g. backup or env=prod",
+                tag
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The filter matching documents with every tag of `tag_filter`, comma separated tags such as
+/// `backup,nightly`, or `None` when it names none.
+pub fn tags_filter(tag_filter: &str) -> Option<Document> {
+    let tags: Vec<&str> = tag_filter
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    (!tags.is_empty()).then(|| doc! { "tags": { "$all": tags } })
+}
+
+/// Checks that a job's `on_success` and `on_failure` hooks name jobs other than the job `name`.
+/// The jobs they name need not exist yet; hooks naming missing jobs are skipped when they fire.
+pub fn validate_hooks(
+    name: &str,
+    on_success: &[String],
+    on_failure: &[String],
+) -> Result<(), String> {
+    for target in on_success.iter().chain(on_failure) {
+        if target.trim().is_empty() {
+            return Err("Every hook needs the name of the job to trigger".to_string());
+        }
+        if target == name {
+            return Err(format!("Job {} cannot trigger itself from its hooks", name));
+        }
+    }
+    Ok(())
+}
+
+/// Parses a job's `timezone`, which defaults to UTC.
+pub fn parse_timezone(timezone: Option<&str>) -> Result<Tz, String> {
+    match timezone {
+        None => Ok(Tz::UTC),
+        Some(name) => name
+            .parse()
+            .map_err(|_| format!("Unknown time zone {:?}", name)),
+    }
+}
+
+/// How the results of a cycle's agents combine into its outcome (see `job_executions`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SuccessRule {
+    /// Every required agent succeeded.
+    #[default]
+    AllSuccess,
+    /// At least one agent succeeded.
+    AnySuccess,
+    /// At least `min_successes` agents succeeded.
+    Quorum { min_successes: u32 },
+}
+
+impl SuccessRule {
+    /// Whether `successes` out of `agents` successful results satisfy the rule.
+    pub fn is_met(&self, successes: usize, agents: usize) -> bool {
+        match self {
+            SuccessRule::AllSuccess => successes == agents,
+            SuccessRule::AnySuccess => successes > 0,
+            SuccessRule::Quorum { min_successes } => successes >= *min_successes as usize,
+        }
+    }
+
+    /// Checks that a job with `agents` required agents can meet the rule. `None` means the count
+    /// is only known at dispatch, as with jobs that target agent groups.
+    pub fn validate(&self, agents: Option<usize>) -> Result<(), String> {
+        match (self, agents) {
+            (SuccessRule::Quorum { min_successes }, Some(agents))
+                if *min_successes == 0 || *min_successes as usize > agents =>
+            {
+                Err(format!("A quorum needs between 1 and {} successes", agents))
+            }
+            (SuccessRule::Quorum { min_successes: 0 }, None) => {
+                Err("A quorum needs at least 1 success".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for SuccessRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SuccessRule::AllSuccess => write!(f, "all agents succeed"),
+            SuccessRule::AnySuccess => write!(f, "any agent succeeds"),
+            SuccessRule::Quorum { min_successes } => {
+                write!(f, "at least {} agents succeed", min_successes)
+            }
+        }
+    }
+}
+
+/// One command of a multi-step job. A step starts once the previous one finished, and a failed
+/// step stops the job unless it is marked `continue_on_error`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobStep {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Working directory, the agent's own when empty.
+    #[serde(default)]
+    pub cwd: String,
+    /// `KEY=VALUE` pairs added to the agent's environment.
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub continue_on_error: bool,
+}
+
+impl std::fmt::Display for JobStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.command)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        if self.continue_on_error {
+            write!(f, " (continue on error)")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&JobStep> for messages::JobStep {
+    fn from(step: &JobStep) -> Self {
+        messages::JobStep {
+            name: step.name.clone(),
+            command: step.command.clone(),
+            args: step.args.join(" "),
+            cwd: (!step.cwd.is_empty()).then(|| step.cwd.clone()),
+            env: step.env.clone(),
+            continue_on_error: step.continue_on_error,
+        }
+    }
+}
+
+/// Largest script body a job may carry; it travels inside the job's dispatch. Larger scripts can
+/// be pushed as one of the job's `files` instead.
+pub const MAX_SCRIPT_BYTES: usize = 32 * 1024;
+
+/// The interpreters agents can run a `JobScript` with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptInterpreter {
+    Bash,
+    Python,
+    #[serde(rename = "powershell")]
+    PowerShell,
+}
+
+impl std::fmt::Display for ScriptInterpreter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptInterpreter::Bash => write!(f, "bash"),
+            ScriptInterpreter::Python => write!(f, "python"),
+            ScriptInterpreter::PowerShell => write!(f, "powershell"),
+        }
+    }
+}
+
+/// A script carried by the job itself. The agent writes it to a temporary file only its own user
+/// can read, runs it with the interpreter and removes it once the run finished.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobScript {
+    pub interpreter: ScriptInterpreter,
+    pub body: String,
+}
+
+impl std::fmt::Display for JobScript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use sha2::{Digest, Sha256};
+
+        // The digest tells scripts apart in job changes without printing them.
+        let digest = hex::encode(Sha256::digest(self.body.as_bytes()));
+        write!(
+            f,
+            "{} script of {} lines, sha256 {}",
+            self.interpreter,
+            self.body.lines().count(),
+            &digest[..12]
+        )
+    }
+}
+
+impl From<&JobScript> for messages::JobScript {
+    fn from(script: &JobScript) -> Self {
+        messages::JobScript {
+            interpreter: script.interpreter.to_string(),
+            body: script.body.clone(),
+        }
+    }
+}
+
+/// Checks that a job's `script` is not empty or too large, and that it is the job's only
+/// command: a job has either a `command`, a `script` or `steps`.
+pub fn validate_script(
+    script: Option<&JobScript>,
+    command: &str,
+    steps: &[JobStep],
+) -> Result<(), String> {
+    let Some(script) = script else {
+        return Ok(());
+    };
+    if script.body.trim().is_empty() {
+        return Err("Script body is empty".to_string());
+    }
+    if script.body.len() > MAX_SCRIPT_BYTES {
+        return Err(format!(
+            "Script body is larger than {} bytes, push it as a file instead",
+            MAX_SCRIPT_BYTES
+        ));
+    }
+    if !command.trim().is_empty() {
+        return Err("A job cannot have both a command and a script".to_string());
+    }
+    if !steps.is_empty() {
+        return Err("A job cannot have both steps and a script".to_string());
+    }
+    Ok(())
+}
+
+/// Checks the job's `output_parsers` (see `output_parsing::validate_parsers`), and that
+/// `success_statuses` are only set when a parser can extract a status.
+pub fn validate_output_parsers(
+    parsers: &[OutputParser],
+    success_statuses: &[String],
+) -> Result<(), String> {
+    output_parsing::validate_parsers(parsers)?;
+    if success_statuses.is_empty() {
+        return Ok(());
+    }
+    let extracts_status = parsers.iter().any(|parser| {
+        parser.names().is_ok_and(|names| {
+            names
+                .iter()
+                .any(|name| name == output_parsing::STATUS_FIELD)
+        })
+    });
+    if !extracts_status {
+        return Err("success_statuses need an output parser extracting a status".to_string());
+    }
+    Ok(())
+}
+
+/// A container the agent runs a job's command in, with `docker run --rm` or `podman run --rm`
+/// (see `AGENT_CONTAINER_RUNTIME` of the agent), so the job runs in the environment of its image
+/// and leaves nothing behind on the host but what it writes to its mounts. Each step of a
+/// multi-step job runs in a container of its own, with the step's `cwd` and `env`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobContainer {
+    pub image: String,
+    /// Bind mounts, each `host path:container path`, optionally followed by `:ro`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<String>,
+    /// `KEY=VALUE` pairs set in the container.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    /// Working directory in the container, the image's when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+}
+
+impl std::fmt::Display for JobContainer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Environment values are left out, as they may hold secrets.
+        write!(f, "{}", self.image)?;
+        if !self.mounts.is_empty() {
+            write!(f, ", mounts {}", self.mounts.join(", "))?;
+        }
+        if let Some(workdir) = &self.workdir {
+            write!(f, ", in {}", workdir)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&JobContainer> for messages::JobContainer {
+    fn from(container: &JobContainer) -> Self {
+        messages::JobContainer {
+            image: container.image.clone(),
+            mounts: container.mounts.clone(),
+            env: container.env.clone(),
+            workdir: container.workdir.clone(),
+        }
+    }
+}
+
+/// Checks that a job's container names an image, that its mounts are `host:container[:ro|:rw]`
+/// with absolute container paths and its environment `KEY=VALUE` pairs, and that it is neither
+/// combined with a script, which the agent writes to its host, nor with another executor.
+pub fn validate_container(
+    container: Option<&JobContainer>,
+    script: Option<&JobScript>,
+    executor: &JobExecutor,
+) -> Result<(), String> {
+    let Some(container) = container else {
+        return Ok(());
+    };
+    let image = container.image.trim();
+    if image.is_empty() || image.starts_with('-') || image.contains(char::is_whitespace) {
+        return Err(format!("Invalid container image {:?}", container.image));
+    }
+    for mount in &container.mounts {
+        let mut parts = mount.split(':');
+        let (host, target, options) = (parts.next(), parts.next(), parts.next());
+        let valid = host.is_some_and(|host| !host.is_empty() && !host.starts_with('-'))
+            && target.is_some_and(|target| target.starts_with('/'))
+            && options.is_none_or(|options| matches!(options, "ro" | "rw"))
+            && parts.next().is_none();
+        if !valid {
+            return Err(format!(
+                "Invalid mount {}, expected host path:container path[:ro]",
+                mount
+            ));
+        }
+    }
+    if let Some(var) = container
+        .env
+        .iter()
+        .find(|var| var.split_once('=').is_none_or(|(key, _)| key.is_empty()))
+    {
+        return Err(format!(
+            "Invalid container environment variable {}, expected KEY=VALUE",
+            var
+        ));
+    }
+    if container
+        .workdir
+        .as_ref()
+        .is_some_and(|dir| !dir.starts_with('/'))
+    {
+        return Err("The container workdir must be an absolute path".to_string());
+    }
+    if script.is_some() {
+        return Err("A job cannot have both a container and a script".to_string());
+    }
+    if *executor != JobExecutor::Agent {
+        return Err(format!(
+            "Containers are run by agents, not the {} executor",
+            executor.kind()
+        ));
+    }
+    Ok(())
+}
+
+/// First segment of the blob store keys of the files for `JobFileSource::GridFs`, and so the
+/// GridFS bucket holding them with the default store.
+pub const JOB_FILES_BUCKET: &str = "job_files";
+
+/// A file central command pushes to each of a job's agents before dispatching the job, so scripts
+/// can be run without provisioning them on the agents first. The run fails without running
+/// anything if the file did not arrive intact.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobFile {
+    pub source: JobFileSource,
+    /// Absolute path the file is written to on the agent; missing directories are created.
+    pub destination: String,
+    /// Octal permission bits set on Unix agents, e.g. `"755"`; the agent's umask applies when
+    /// unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+}
+
+/// Where central command reads a `JobFile`'s contents from. They are read again for every cycle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobFileSource {
+    /// Base64 encoded contents uploaded with the job definition, for small files.
+    Upload { content: String },
+    /// The latest revision of a file named `name` uploaded through the web UI, kept in the blob
+    /// store under `JOB_FILES_BUCKET`. Named after the default store, GridFS.
+    GridFs { name: String },
+    /// Downloaded by central command, and checked against the hex encoded `sha256` when set.
+    Url {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+    },
+}
+
+impl JobFile {
+    /// The permission bits `mode` stands for.
+    pub fn mode_bits(&self) -> Result<Option<u32>, String> {
+        let Some(mode) = &self.mode else {
+            return Ok(None);
+        };
+        match u32::from_str_radix(mode.trim_start_matches("0o"), 8) {
+            Ok(bits) if bits <= 0o7777 => Ok(Some(bits)),
+            _ => Err(format!(
+                "Invalid mode {:?} for {}, expected octal permission bits, e.g. 755",
+                mode, self.destination
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for JobFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.destination)?;
+        match &self.source {
+            JobFileSource::Upload { content } => {
+                use base64::Engine;
+                use sha2::{Digest, Sha256};
+
+                // The digest tells uploads apart in job changes without printing the contents.
+                let contents = base64::engine::general_purpose::STANDARD
+                    .decode(content)
+                    .unwrap_or_default();
+                let digest = hex::encode(Sha256::digest(&contents));
+                write!(f, " (uploaded, sha256 {})", &digest[..12])?
+            }
+            JobFileSource::GridFs { name } => write!(f, " from {} in the blob store", name)?,
+            JobFileSource::Url { url, .. } => write!(f, " from {}", url)?,
+        }
+        if let Some(mode) = &self.mode {
+            write!(f, " mode {}", mode)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `path` is absolute on Unix (`/srv/run.sh`) or Windows (`C:\run.ps1`) agents.
+fn is_absolute_path(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    path.starts_with('/')
+        || path.starts_with("\\\\")
+        || (bytes.len() > 2
+            && bytes[0].is_ascii_alphabetic()
+            && bytes[1] == b':'
+            && (bytes[2] == b'\\' || bytes[2] == b'/'))
+}
+
+/// Checks that each of a job's `files` goes to its own absolute path, with a valid mode and a
+/// source that can be read: decodable uploads, named GridFS files and HTTP(S) URLs.
+pub fn validate_files(files: &[JobFile]) -> Result<(), String> {
+    use base64::Engine;
+
+    let mut destinations = std::collections::HashSet::new();
+    for file in files {
+        if !is_absolute_path(&file.destination) {
+            return Err(format!(
+                "File destination {:?} must be an absolute path",
+                file.destination
+            ));
+        }
+        if !destinations.insert(file.destination.as_str()) {
+            return Err(format!("More than one file goes to {}", file.destination));
+        }
+        file.mode_bits()?;
+        match &file.source {
+            JobFileSource::Upload { content } => {
+                base64::engine::general_purpose::STANDARD
+                    .decode(content)
+                    .map_err(|e| {
+                        format!(
+                            "Uploaded content of {} is not base64: {}",
+                            file.destination, e
+                        )
+                    })?;
+            }
+            JobFileSource::GridFs { name } if name.trim().is_empty() => {
+                return Err(format!(
+                    "GridFS file name for {} is empty",
+                    file.destination
+                ));
+            }
+            JobFileSource::GridFs { .. } => (),
+            JobFileSource::Url { url, sha256 } => {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(format!("File URL {:?} must be http or https", url));
+                }
+                if let Some(sha256) = sha256
+                    && (sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()))
+                {
+                    return Err(format!(
+                        "sha256 of {} must be 64 hex characters",
+                        file.destination
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What a job is expected to achieve. Each limit that is set becomes one alerting rule.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobSla {
+    /// Longest a run should take, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_duration: Option<u32>,
+    /// Longest the job may go without a successful run, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_success_interval: Option<u32>,
+    /// Longest a scheduled run may go without starting, in seconds, before the job is overdue. A
+    /// job that skips missed runs is no longer overdue once `MISFIRE_GRACE_SECONDS` skipped it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_start_delay: Option<u32>,
+    /// `severity` label of the generated alerts; `warning` when unset. Failed runs of jobs whose
+    /// severity is one of central command's `PAGE_SEVERITIES` page the on-call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<String>,
+}
+
+impl JobSla {
+    pub fn is_empty(&self) -> bool {
+        self.expected_duration.is_none()
+            && self.max_success_interval.is_none()
+            && self.max_start_delay.is_none()
+    }
+}
+
+impl std::fmt::Display for JobSla {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut limits = vec![];
+        if let Some(seconds) = self.expected_duration {
+            limits.push(format!("runs within {}s", seconds));
+        }
+        if let Some(seconds) = self.max_success_interval {
+            limits.push(format!("succeeds every {}s", seconds));
+        }
+        if let Some(seconds) = self.max_start_delay {
+            limits.push(format!("starts within {}s", seconds));
+        }
+        if let Some(severity) = &self.severity {
+            limits.push(format!("severity {}", severity));
+        }
+        write!(f, "{}", limits.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobTemplateRef {
+    pub name: String,
+    pub parameters: HashMap<String, String>,
 }
 
 impl JobV1 {
@@ -71,7 +1113,195 @@ impl JobV1 {
     ) -> Result<(), Box<dyn std::error::Error>> {
         let index_doc = doc! { "name": 1, };
         crate::datastore::Datastore::create_unique_index(collection, index_doc).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "agent_groups": 1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "team": 1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "tags": 1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "namespace": 1, "status": 1 })
+            .build();
+        collection.create_index(index).await?;
+        // The dispatch loop looks for pending jobs whose `next_run` has passed.
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "status": 1, "next_run": 1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "agents_running": 1 })
+            .build();
+        collection.create_index(index).await?;
 
         Ok(())
     }
+
+    /// The fields that define the job, as opposed to its schedule position `next_run`, who manages
+    /// it and the state of its runs, as a `$set` document.
+    pub fn definition(&self) -> Result<Document, Box<dyn std::error::Error>> {
+        Ok(doc! {
+            "description": &self.description,
+            "command": &self.command,
+            "args": &self.args,
+            "env": &self.env,
+            "cwd": &self.cwd,
+            "timeout": self.timeout as i64,
+            "retries": self.retries as i64,
+            "valid_return_codes": &self.valid_return_codes,
+            "agents_required": &self.agents_required,
+            "agent_groups": &self.agent_groups,
+            "platforms": &self.platforms,
+            "node_selector": &self.node_selector,
+            "executor": bson::to_bson(&self.executor)?,
+            "redact_patterns": &self.redact_patterns,
+            "sla": bson::to_bson(&self.sla)?,
+            "steps": bson::to_bson(&self.steps)?,
+            "files": bson::to_bson(&self.files)?,
+            "script": bson::to_bson(&self.script)?,
+            "container": bson::to_bson(&self.container)?,
+            "output_parsers": bson::to_bson(&self.output_parsers)?,
+            "success_statuses": &self.success_statuses,
+            "check": self.check,
+            "success_rule": bson::to_bson(&self.success_rule)?,
+            "one_shot": self.one_shot,
+            "requires_approval": self.requires_approval,
+            "on_success": &self.on_success,
+            "on_failure": &self.on_failure,
+            "schedule_interval": self.schedule_interval.map(|interval| interval as i64),
+            "cron": self.cron.clone(),
+            "timezone": self.timezone.clone(),
+            "misfire_policy": bson::to_bson(&self.misfire_policy)?,
+            "tags": &self.tags,
+            "metadata": &self.metadata,
+        })
+    }
+
+    /// The jobs a cycle of the job triggers: its `on_success` hooks when the cycle succeeded,
+    /// otherwise its `on_failure` hooks.
+    pub fn hooks(&self, succeeded: bool) -> &[String] {
+        match succeeded {
+            true => &self.on_success,
+            false => &self.on_failure,
+        }
+    }
+
+    /// Whether the job can run on `agent`: the agent is in the job's namespace, runs on one of the
+    /// job's `platforms` or the job runs on any platform, and its node matches the job's
+    /// `node_selector`.
+    pub fn runs_on(&self, agent: &AgentV1) -> bool {
+        self.namespace == agent.namespace
+            && (self.platforms.is_empty()
+                || self
+                    .platforms
+                    .iter()
+                    .any(|platform| agent.is_platform(platform)))
+            && agent.matches_node_selector(&self.node_selector)
+    }
+
+    /// The agent of the central command executor running the job, e.g. Kubernetes Jobs created by
+    /// the Kubernetes executor, or `None` when the job runs on its agents.
+    pub fn executor_agent(&self) -> Option<&'static str> {
+        self.executor.agent_name()
+    }
+
+    /// The job's cron schedule and the zone it is evaluated in, for jobs scheduled by `cron`.
+    pub fn cron_schedule(&self) -> Option<(CronSchedule, Tz)> {
+        let schedule = CronSchedule::parse(self.cron.as_deref()?).ok()?;
+        Some((schedule, parse_timezone(self.timezone.as_deref()).ok()?))
+    }
+
+    /// Whether the job runs on a schedule rather than only at `next_run`.
+    pub fn recurs(&self) -> bool {
+        !self.one_shot && (self.schedule_interval.is_some_and(|i| i > 0) || self.cron.is_some())
+    }
+
+    /// The scheduled runs from `next_run` on, up to and including `until` (Unix seconds), or just
+    /// `next_run` for jobs that do not recur.
+    pub fn runs_until(&self, until: i64) -> impl Iterator<Item = i64> + '_ {
+        let interval = self.schedule_interval.filter(|interval| *interval > 0);
+        let cron = self.cron_schedule();
+        std::iter::successors(Some(self.next_run), move |at| match (interval, &cron) {
+            (Some(interval), _) => Some(at + interval as i64),
+            (None, Some((schedule, timezone))) => schedule.next_after(*at, *timezone),
+            (None, None) => None,
+        })
+        .take_while(move |at| *at <= until)
+    }
+
+    /// How many scheduled runs have been due by `now` (Unix seconds): every `schedule_interval`
+    /// or cron occurrence from `next_run` on, or just `next_run` for jobs that do not recur.
+    pub fn due_runs(&self, now: i64) -> u64 {
+        match self.schedule_interval {
+            _ if self.next_run > now => 0,
+            Some(interval) if interval > 0 => ((now - self.next_run) / interval as i64) as u64 + 1,
+            _ => self.runs_until(now).count() as u64,
+        }
+    }
+
+    /// The `n`th scheduled run from `next_run` on, counting from 0.
+    pub fn nth_run(&self, n: u64) -> i64 {
+        match self.schedule_interval {
+            Some(interval) if interval > 0 => self.next_run + n as i64 * interval as i64,
+            _ => self
+                .runs_until(i64::MAX)
+                .take(n as usize + 1)
+                .last()
+                .unwrap_or(self.next_run),
+        }
+    }
+
+    /// The first scheduled run after `at`, or `None` for jobs that do not recur. Cron jobs follow
+    /// their schedule in the job's time zone, so a daily run stays at the same local time across
+    /// daylight saving changes.
+    pub fn next_run_after(&self, at: i64) -> Option<i64> {
+        if let Some(interval) = self.schedule_interval.filter(|interval| *interval > 0) {
+            return Some(self.next_run + self.due_runs(at) as i64 * interval as i64);
+        }
+        let (schedule, timezone) = self.cron_schedule()?;
+        match self.next_run > at {
+            true => Some(self.next_run),
+            false => schedule.next_after(at, timezone),
+        }
+    }
+
+    /// Seconds since `next_run` for a pending job whose scheduled run has not started by `now`
+    /// (Unix seconds), e.g. because no agent it runs on is connected or no dispatcher is running.
+    pub fn overdue_seconds(&self, now: i64) -> Option<i64> {
+        (self.status == Status::Pending && self.next_run < now).then(|| now - self.next_run)
+    }
+
+    /// The agents the current cycle runs on. Cycles started before `cycle_agents` was recorded
+    /// fall back to `agents_required`.
+    pub fn target_agents(&self) -> &[String] {
+        match self.cycle_agents.is_empty() {
+            true => &self.agents_required,
+            false => &self.cycle_agents,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn success_statuses_need_a_parser_extracting_a_status() {
+        let with_status = [OutputParser::Regex {
+            pattern: r"status: (?P<status>\w+)".to_string(),
+        }];
+        let without_status = [OutputParser::Regex {
+            pattern: r"records: (?P<records>\d+)".to_string(),
+        }];
+        let complete = ["complete".to_string()];
+        assert!(validate_output_parsers(&with_status, &complete).is_ok());
+        assert!(validate_output_parsers(&without_status, &[]).is_ok());
+        assert!(validate_output_parsers(&without_status, &complete).is_err());
+        assert!(validate_output_parsers(&[], &complete).is_err());
+    }
 }
@@ -0,0 +1,141 @@
+use bson::{DateTime, doc};
+use futures::TryStreamExt;
+use mongodb::error::{ErrorKind, WriteFailure};
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::time::Duration;
+
+use crate::datastore::Datastore;
+use crate::framing::crc32;
+
+/// Name of the lease held by the central command instance that leads.
+pub const LEADER_LEASE: &str = "central_command";
+
+/// Prefix of the leases central command instances hold while they take agent partitions, followed
+/// by the instance id.
+pub const DISPATCHER_LEASE_PREFIX: &str = "dispatcher:";
+
+/// Prefix of the leases on agent partitions, followed by the partition number.
+pub const PARTITION_LEASE_PREFIX: &str = "agent_partition:";
+
+/// Code MongoDB fails an insert with when the `_id` is taken.
+const DUPLICATE_KEY: i32 = 11000;
+
+/// A named lease that one central command instance holds at a time, such as the leadership that
+/// lets an instance dispatch jobs. Times are MongoDB's, so instances whose clocks disagree still
+/// agree on when a lease expired.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaseV1 {
+    #[serde(rename = "_id")]
+    pub name: String,
+    pub holder: String, // Id of the central command instance, see `CENTRAL_COMMAND_ID`
+    pub acquired_at: DateTime,
+    pub renewed_at: DateTime,
+    pub expires_at: DateTime,
+}
+
+impl LeaseV1 {
+    /// Takes the lease `name` for `holder` until `duration` from now if it is free or expired,
+    /// or extends it if `holder` already holds it. Returns whether `holder` holds it.
+    pub async fn try_acquire(
+        datastore: &Datastore,
+        name: &str,
+        holder: &str,
+        duration: Duration,
+    ) -> Result<bool, Box<dyn Error>> {
+        let collection = datastore.get_collection::<LeaseV1>("leases").await?;
+        let filter = doc! {
+            "_id": name,
+            "$or": [
+                { "holder": holder },
+                { "$expr": { "$lt": ["$expires_at", "$$NOW"] } },
+            ],
+        };
+        // A pipeline update, so the times are MongoDB's and `acquired_at` is kept on renewal.
+        let update = vec![doc! {
+            "$set": {
+                "acquired_at": {
+                    "$cond": [{ "$eq": ["$holder", holder] }, "$acquired_at", "$$NOW"]
+                },
+                "holder": holder,
+                "renewed_at": "$$NOW",
+                "expires_at": { "$add": ["$$NOW", duration.as_millis() as i64] },
+            }
+        }];
+        match collection.update_one(filter, update).upsert(true).await {
+            Ok(_) => Ok(true),
+            // Another holder's lease has not expired, so the upsert tried to insert it again.
+            Err(e) => match *e.kind {
+                ErrorKind::Write(WriteFailure::WriteError(ref failure))
+                    if failure.code == DUPLICATE_KEY =>
+                {
+                    Ok(false)
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    /// Gives up the lease `name` if `holder` holds it, so another instance can take it at once.
+    pub async fn release(
+        datastore: &Datastore,
+        name: &str,
+        holder: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let collection = datastore.get_collection::<LeaseV1>("leases").await?;
+        collection
+            .delete_one(doc! { "_id": name, "holder": holder })
+            .await?;
+        Ok(())
+    }
+
+    /// The leases whose name starts with `prefix` that have not expired, ordered by name.
+    pub async fn live(datastore: &Datastore, prefix: &str) -> Result<Vec<LeaseV1>, Box<dyn Error>> {
+        let collection = datastore.get_collection::<LeaseV1>("leases").await?;
+        let filter = doc! {
+            "_id": { "$regex": format!("^{}", prefix) },
+            "$expr": { "$gt": ["$expires_at", "$$NOW"] },
+        };
+        Ok(collection
+            .find(filter)
+            .sort(doc! { "_id": 1 })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    /// The lease `name`, whether or not it expired.
+    pub async fn find(
+        datastore: &Datastore,
+        name: &str,
+    ) -> Result<Option<LeaseV1>, Box<dyn Error>> {
+        let collection = datastore.get_collection::<LeaseV1>("leases").await?;
+        Ok(collection.find_one(doc! { "_id": name }).await?)
+    }
+}
+
+/// Name of the lease an instance holds while it takes agent partitions.
+pub fn dispatcher_lease(instance_id: &str) -> String {
+    format!("{}{}", DISPATCHER_LEASE_PREFIX, instance_id)
+}
+
+/// Name of the lease on agent partition `partition`.
+pub fn partition_lease(partition: u32) -> String {
+    format!("{}{}", PARTITION_LEASE_PREFIX, partition)
+}
+
+/// The partition out of `partitions` that an agent belongs to. It is a hash of the agent's name
+/// that does not depend on the process, so every central command instance agrees on it.
+///
+/// ```rust
+/// use core_logic::datastore::leases::agent_partition;
+///
+/// let partition = agent_partition("web-1", 8);
+/// assert!(partition < 8);
+/// assert_eq!(partition, agent_partition("web-1", 8));
+/// assert_eq!(agent_partition("web-1", 1), 0);
+/// ```
+pub fn agent_partition(agent_name: &str, partitions: u32) -> u32 {
+    crc32(agent_name.as_bytes()) % partitions.max(1)
+}
@@ -0,0 +1,59 @@
+//! Conversions of stored documents to the current format, run by [`Datastore::try_new`] at
+//! startup. Each one only matches documents still in the old format, so running them again, or
+//! from several instances at once, changes nothing.
+//!
+//! # Migrations
+//! - Time fields stored as Unix seconds, such as `next_run` of jobs and of the job definitions
+//!   kept with revisions and promotions, become BSON DateTimes like every other time field, so
+//!   they sort, compare and range filter the same way.
+use bson::doc;
+use mongodb::Database;
+
+use std::error::Error;
+
+use tracing::info;
+
+use crate::datastore::Datastore;
+
+/// Fields once stored as Unix seconds, by collection.
+const SECONDS_FIELDS: &[(&str, &str)] = &[
+    ("jobs", "next_run"),
+    ("job_revisions", "job.next_run"),
+    ("job_promotions", "job.next_run"),
+];
+
+impl Datastore {
+    /// Runs the migrations, logging how many documents each converted.
+    pub async fn migrate(&self) -> Result<(), Box<dyn Error>> {
+        let db = self.get_database();
+        for (collection, field) in SECONDS_FIELDS {
+            let converted = seconds_to_datetime(&db, collection, field).await?;
+            if converted > 0 {
+                info!(
+                    "Converted {} of {} {} from Unix seconds to dates",
+                    field, converted, collection
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts `field` of the documents in `collection` that hold a number to the date that many
+/// seconds after the epoch. Returns the number of documents converted.
+async fn seconds_to_datetime(
+    db: &Database,
+    collection: &str,
+    field: &str,
+) -> Result<u64, Box<dyn Error>> {
+    let result = db
+        .collection::<bson::Document>(collection)
+        .update_many(
+            doc! { field: { "$type": "number" } },
+            vec![doc! { "$set": {
+                field: { "$toDate": { "$multiply": [{ "$toLong": format!("${}", field) }, 1000] } },
+            } }],
+        )
+        .await?;
+    Ok(result.modified_count)
+}
@@ -2,8 +2,40 @@
 //! including initialization, index creation, and collection access for the application.
 //!
 //! # Modules
+//! - `agent_events`: Agents going online and offline, registering and being drained, shown as a
+//!   connectivity timeline and sent to webhooks.
+//! - `agent_groups`: Named sets of agents that jobs can target instead of listing agents.
+//! - `alerts`: Failures, SLA breaches and stuck jobs, aggregated per job and agent until they
+//!   are acknowledged and resolved.
 //! - `agents`: Contains logic and data structures related to agents.
+//! - `api_tokens`: Hashed tokens programs authenticate to the web UI's API with.
+//! - `audit_log`: Mutating actions taken through the web UI, with the user who took them.
+//! - `blackout_windows`: Periods during which central command starts no runs, of every job or
+//!   of chosen jobs.
+//! - `check_states`: The current state of each check job on each agent, for the service status
+//!   grid and alerts on changes of state.
+//! - `connections`: Snapshots of the connections held by central command.
+//! - `dispatch_plans`: The runs central command would have dispatched, and to which agents, in
+//!   dry-run mode.
+//! - `job_changes`: Changes to job definitions, shown alongside run history.
+//! - `job_revisions`: Every version of each job's definition, with who made it, for review and
+//!   rollback.
+//! - `job_promotions`: Requests to copy a job's definition to another namespace, applied once
+//!   approved.
+//! - `job_executions`: Each dispatch cycle of a job with the result of every agent, aggregated by
+//!   the job's success rule.
+//! - `job_templates`: Reusable, parameterized job definitions.
+//! - `job_warnings`: Warnings that a run is close to its timeout or breached its SLA, or that a
+//!   check changed state, sent to webhooks.
 //! - `jobs`: Contains logic and data structures related to jobs.
+//! - `leases`: Leases held by one central command instance at a time, such as the leadership
+//!   that decides which instance dispatches jobs and the agent partitions instances share.
+//! - `migrations`: Conversions of stored documents to the current format, run at startup.
+//! - `run_stats`: Run counts by outcome over time and per job and agent, for charts.
+//! - `runs`: The stored result of each job run on an agent. Large outputs may be kept in the
+//!   blob store instead.
+//! - `saved_filters`: Filters, sort orders and time ranges users saved on the runs and agents
+//!   pages, opened by their owner from the page or by anyone through a stable URL.
 //!
 //! # Structs
 //! - [`Datastore`]: Represents a connection to the MongoDB database and provides methods
@@ -15,20 +47,63 @@
 //! # Constants
 //! - `MONGODB_URI`: Default MongoDB connection string used if the environment variable is not set.
 //!
+//! # Configuration
+//! - `MONGODB_URI`: The MongoDB connection string (default: `mongodb://localhost:27017`).
+//! - `MONGODB_MAX_POOL_SIZE`, `MONGODB_MIN_POOL_SIZE`: The most and fewest connections kept to
+//!   each server, overriding `maxPoolSize` and `minPoolSize` in `MONGODB_URI`.
+//! - `MONGODB_CONNECT_TIMEOUT_SECONDS`: How long opening a connection may take.
+//! - `MONGODB_SERVER_SELECTION_TIMEOUT_SECONDS`: How long an operation waits for a reachable
+//!   server before failing (driver default: 30).
+//! - `MONGODB_MAX_IDLE_TIME_SECONDS`: How long an unused connection is kept.
+//! - `MONGODB_STARTUP_RETRIES`: How many more times to try reaching MongoDB at startup, backing
+//!   off from 1 up to 30 seconds between tries, before giving up (default: 5).
+//! - `BLOB_STORE_URL`: Where bulky data such as large run outputs and job files is kept, e.g.
+//!   `file:///var/lib/rust_action_dispatch/blobs` or `s3://bucket/prefix` (default: `gridfs://`,
+//!   GridFS in this database). See [`crate::blob_store`].
+//!
 //! # Usage
-//! - Use [`Datastore::try_new`] to initialize a new datastore connection.
+//! - Use [`Datastore::try_new`] to initialize a new datastore connection. It fails only if MongoDB
+//!   stays unreachable through the startup retries; indices that cannot be created then are
+//!   created later, on first use of a collection or health check.
+//! - Use [`Datastore::migrate`] to convert stored documents to the current format; `try_new` runs
+//!   it at startup.
 //! - Use [`Datastore::get_collection`] to access specific collections.
+//! - Use [`Datastore::blob_store`] to store and read blobs kept out of the collections.
 //! - Use [`Datastore::create_unique_index`] to create unique indices on collections.
+//! - Use [`Datastore::missing_indices`] to find the frequently queried fields no index covers;
+//!   `try_new` logs them at startup.
+//! - Use [`Datastore::ping`] to check that MongoDB is reachable, or [`Datastore::check`] for a
+//!   health check that also reports the latency and missing indices.
 //!
 //! # Errors
 //! - Most methods return a `Result` type and may return errors related to MongoDB operations.
 //!
 //! # Logging
 //! - Uses the `tracing` crate for logging connection and configuration information.
+pub mod agent_events;
+pub mod agent_groups;
 pub mod agents;
+pub mod alerts;
+pub mod api_tokens;
+pub mod audit_log;
+pub mod blackout_windows;
+pub mod check_states;
+pub mod connections;
+pub mod dispatch_plans;
+pub mod job_changes;
+pub mod job_executions;
+pub mod job_promotions;
+pub mod job_revisions;
+pub mod job_templates;
+pub mod job_warnings;
 pub mod jobs;
+pub mod leases;
+pub mod migrations;
+pub mod run_stats;
 pub mod runs;
+pub mod saved_filters;
 
+use futures::TryStreamExt;
 use mongodb::{
     Client, Collection, IndexModel,
     bson::Document,
@@ -36,24 +111,84 @@ use mongodb::{
     options::{ClientOptions, IndexOptions},
 };
 
+use std::collections::HashMap;
 use std::env;
 use std::error::Error;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use tracing::{info, warn};
 
+use crate::blob_store::{self, BlobStore};
+use crate::health::CheckStatus;
+
+use agent_events::AgentEventV1;
+use agent_groups::AgentGroupV1;
 use agents::AgentV1;
+use alerts::AlertV1;
+use api_tokens::ApiTokenV1;
+use audit_log::AuditEntryV1;
+use blackout_windows::BlackoutWindowV1;
+use check_states::CheckStateV1;
+use dispatch_plans::DispatchPlanV1;
+use job_changes::JobChangeV1;
+use job_executions::JobExecutionV1;
+use job_promotions::JobPromotionV1;
+use job_revisions::JobRevisionV1;
+use job_templates::JobTemplateV1;
+use job_warnings::JobWarningV1;
 use jobs::JobV1;
+use runs::RunsV1;
+use saved_filters::SavedFilterV1;
 
 const MONGODB_URI: &str = "mongodb://localhost:27017";
 const DATABASE_NAME: &str = "rust-action-dispatch";
+const DEFAULT_STARTUP_RETRIES: u32 = 5;
+const STARTUP_BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+const STARTUP_BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// How long to wait before creating the indices again after that failed.
+const INDEX_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Fields the frequent queries filter on, by collection, each of which should lead an index. Checked
+/// at startup by `Datastore::missing_indices`, as an index may be missing although creating the
+/// indices succeeded, e.g. because it was dropped by hand or an older index has different options.
+const QUERY_INDICES: &[(&str, &[&str])] = &[
+    ("agents", &["name"]),
+    ("agents", &["status"]),
+    ("agents", &["last_ping"]),
+    ("jobs", &["name"]),
+    ("jobs", &["status", "next_run"]),
+    ("jobs", &["agents_running"]),
+    ("runs", &["started_at"]),
+    ("runs", &["job_name", "started_at"]),
+    ("runs", &["agent_name", "started_at"]),
+];
+
+/// The environment variable `name` parsed, if it is set and valid.
+fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    let value = env::var(name).ok()?;
+    match value.trim().parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            warn!("Ignoring invalid {}: {}", name, value);
+            None
+        }
+    }
+}
 
 pub enum DataStoreTypes {
     Agent(AgentV1),
 }
 
-#[derive(Debug)]
+/// A handle on the MongoDB database. Clones share the client's connection pool.
+#[derive(Debug, Clone)]
 pub struct Datastore {
     pub client: Client,
+    /// When index creation may be attempted again; `None` once the indices exist.
+    indices_retry_at: Arc<Mutex<Option<Instant>>>,
+    /// The store selected by `BLOB_STORE_URL`.
+    blobs: Arc<dyn BlobStore>,
 }
 
 impl Datastore {
@@ -78,6 +213,19 @@ impl Datastore {
         self.client.database(DATABASE_NAME)
     }
 
+    /// The store bulky data is kept in instead of the collections.
+    pub fn blob_store(&self) -> &dyn BlobStore {
+        self.blobs.as_ref()
+    }
+
+    /// Checks that MongoDB is reachable.
+    pub async fn ping(&self) -> Result<(), MongoError> {
+        self.get_database()
+            .run_command(bson::doc! { "ping": 1 })
+            .await
+            .map(|_| ())
+    }
+
     pub async fn try_new() -> Result<Self, MongoError> {
         // Load the MongoDB connection string from an environment variable:
         let client_uri = match env::var("MONGODB_URI") {
@@ -92,27 +240,208 @@ impl Datastore {
         };
         info!("Connecting to MongoDB at {}", client_uri);
 
-        let options = ClientOptions::parse(&client_uri).await?;
+        let mut options = ClientOptions::parse(&client_uri).await?;
+        Self::apply_env_options(&mut options);
 
         let client = Client::with_options(options)?;
-        let db = client.database(DATABASE_NAME);
+        let blob_store_url = env::var("BLOB_STORE_URL").unwrap_or_default();
+        let blobs = blob_store::open(&blob_store_url, client.database(DATABASE_NAME))
+            .unwrap_or_else(|e| panic!("Invalid BLOB_STORE_URL: {}", e));
+        info!("Storing blobs in {}", blobs.name());
+        let datastore = Datastore {
+            client,
+            indices_retry_at: Arc::new(Mutex::new(Some(Instant::now()))),
+            blobs,
+        };
+
+        let retries = env_var("MONGODB_STARTUP_RETRIES").unwrap_or(DEFAULT_STARTUP_RETRIES);
+        let mut backoff = STARTUP_BACKOFF_INITIAL;
+        let mut attempt = 0;
+        while let Err(e) = datastore.ping().await {
+            if attempt >= retries {
+                return Err(e);
+            }
+            attempt += 1;
+            warn!(
+                "MongoDB is not reachable ({}), retrying in {} seconds ({}/{})",
+                e,
+                backoff.as_secs(),
+                attempt,
+                retries
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(STARTUP_BACKOFF_MAX);
+        }
+
+        if let Err(e) = datastore.migrate().await {
+            warn!("Failed to migrate stored documents: {}", e);
+        }
+        datastore.ensure_indices().await;
+        match datastore.missing_indices().await {
+            Ok(missing) if missing.is_empty() => info!("Indices of the queried fields are present"),
+            Ok(missing) => {
+                for index in missing {
+                    warn!("Missing mongodb index on {}, queries will scan", index);
+                }
+            }
+            Err(e) => warn!("Failed to list mongodb indices: {}", e),
+        }
+        Ok(datastore)
+    }
 
+    /// Overrides the connection pool and timeout options of `MONGODB_URI` with those set in the
+    /// environment.
+    fn apply_env_options(options: &mut ClientOptions) {
+        if let Some(size) = env_var("MONGODB_MAX_POOL_SIZE") {
+            options.max_pool_size = Some(size);
+        }
+        if let Some(size) = env_var("MONGODB_MIN_POOL_SIZE") {
+            options.min_pool_size = Some(size);
+        }
+        if let Some(seconds) = env_var("MONGODB_CONNECT_TIMEOUT_SECONDS") {
+            options.connect_timeout = Some(Duration::from_secs(seconds));
+        }
+        if let Some(seconds) = env_var("MONGODB_SERVER_SELECTION_TIMEOUT_SECONDS") {
+            options.server_selection_timeout = Some(Duration::from_secs(seconds));
+        }
+        if let Some(seconds) = env_var("MONGODB_MAX_IDLE_TIME_SECONDS") {
+            options.max_idle_time = Some(Duration::from_secs(seconds));
+        }
+    }
+
+    async fn create_indicies(db: &mongodb::Database) -> Result<(), Box<dyn Error>> {
         let agents = db.collection::<bson::Document>("agents");
-        AgentV1::create_indicies(&agents)
-            .await
-            .expect("Failed to create mongodb indices");
+        AgentV1::create_indicies(&agents).await?;
+        let agent_events = db.collection::<bson::Document>("agent_events");
+        AgentEventV1::create_indicies(&agent_events).await?;
+        let agent_groups = db.collection::<bson::Document>("agent_groups");
+        AgentGroupV1::create_indicies(&agent_groups).await?;
+        let alerts = db.collection::<bson::Document>("alerts");
+        AlertV1::create_indicies(&alerts).await?;
+        let api_tokens = db.collection::<bson::Document>("api_tokens");
+        ApiTokenV1::create_indicies(&api_tokens).await?;
         let jobs = db.collection::<bson::Document>("jobs");
-        JobV1::create_indicies(&jobs)
-            .await
-            .expect("Failed to create mongodb indices");
+        JobV1::create_indicies(&jobs).await?;
+        let job_changes = db.collection::<bson::Document>("job_changes");
+        JobChangeV1::create_indicies(&job_changes).await?;
+        let job_executions = db.collection::<bson::Document>("job_executions");
+        JobExecutionV1::create_indicies(&job_executions).await?;
+        let job_promotions = db.collection::<bson::Document>("job_promotions");
+        JobPromotionV1::create_indicies(&job_promotions).await?;
+        let job_revisions = db.collection::<bson::Document>("job_revisions");
+        JobRevisionV1::create_indicies(&job_revisions).await?;
+        let job_warnings = db.collection::<bson::Document>("job_warnings");
+        JobWarningV1::create_indicies(&job_warnings).await?;
+        let audit_log = db.collection::<bson::Document>("audit_log");
+        AuditEntryV1::create_indicies(&audit_log).await?;
+        let blackout_windows = db.collection::<bson::Document>("blackout_windows");
+        BlackoutWindowV1::create_indicies(&blackout_windows).await?;
+        let check_states = db.collection::<bson::Document>("check_states");
+        CheckStateV1::create_indicies(&check_states).await?;
+        let dispatch_plans = db.collection::<bson::Document>("dispatch_plans");
+        DispatchPlanV1::create_indicies(&dispatch_plans).await?;
+        let runs = db.collection::<bson::Document>("runs");
+        RunsV1::create_indicies(&runs).await?;
+        RunsV1::create_output_bucket(db).await?;
+        let saved_filters = db.collection::<bson::Document>("saved_filters");
+        SavedFilterV1::create_indicies(&saved_filters).await?;
+        let job_templates = db.collection::<bson::Document>("job_templates");
+        JobTemplateV1::create_indicies(&job_templates).await?;
+
+        Ok(())
+    }
+
+    /// Creates the indices unless they exist, at most every `INDEX_RETRY_INTERVAL` while that
+    /// fails, e.g. because MongoDB was unreachable at startup. Failures are logged rather than
+    /// returned, as the collections work without indices, only slower.
+    pub async fn ensure_indices(&self) {
+        {
+            let Ok(mut retry_at) = self.indices_retry_at.lock() else {
+                return;
+            };
+            match *retry_at {
+                Some(at) if at <= Instant::now() => {
+                    *retry_at = Some(Instant::now() + INDEX_RETRY_INTERVAL);
+                }
+                _ => return,
+            }
+        }
+        match Self::create_indicies(&self.get_database()).await {
+            Ok(()) => {
+                if let Ok(mut retry_at) = self.indices_retry_at.lock() {
+                    *retry_at = None;
+                }
+            }
+            Err(e) => warn!(
+                "Failed to create mongodb indices, retrying in {} seconds: {}",
+                INDEX_RETRY_INTERVAL.as_secs(),
+                e
+            ),
+        }
+    }
+
+    /// The `QUERY_INDICES` no index starts with, e.g. `runs (job_name, started_at)`.
+    pub async fn missing_indices(&self) -> Result<Vec<String>, MongoError> {
+        let db = self.get_database();
+        let mut missing = vec![];
+        let mut indexed: HashMap<&str, Vec<Vec<String>>> = HashMap::new();
+        for (collection, fields) in QUERY_INDICES {
+            if !indexed.contains_key(collection) {
+                let keys = db
+                    .collection::<Document>(collection)
+                    .list_indexes()
+                    .await?
+                    .map_ok(|index| index.keys.keys().cloned().collect())
+                    .try_collect()
+                    .await?;
+                indexed.insert(collection, keys);
+            }
+            let covered = indexed[collection].iter().any(|keys| {
+                keys.len() >= fields.len()
+                    && keys.iter().zip(*fields).all(|(key, field)| key == field)
+            });
+            if !covered {
+                missing.push(format!("{} ({})", collection, fields.join(", ")));
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Whether the indices were created.
+    pub fn indices_ready(&self) -> bool {
+        self.indices_retry_at
+            .lock()
+            .is_ok_and(|retry_at| retry_at.is_none())
+    }
 
-        Ok(Datastore { client })
+    /// Pings MongoDB for health checks, creating the indices if they are still missing.
+    pub async fn check(&self) -> CheckStatus {
+        let started = Instant::now();
+        if let Err(e) = self.ping().await {
+            return CheckStatus {
+                ok: false,
+                detail: e.to_string(),
+            };
+        }
+        let elapsed_ms = started.elapsed().as_millis();
+        self.ensure_indices().await;
+        CheckStatus {
+            ok: true,
+            detail: match self.indices_ready() {
+                true => format!("ping succeeded in {} ms", elapsed_ms),
+                false => format!(
+                    "ping succeeded in {} ms, indices not created yet",
+                    elapsed_ms
+                ),
+            },
+        }
     }
 
     pub async fn get_collection<T: Sync + std::marker::Send + serde::de::DeserializeOwned>(
         &self,
         collection_name: &str,
     ) -> Result<Collection<T>, Box<dyn Error>> {
+        self.ensure_indices().await;
         let collection = self.get_database().collection::<T>(collection_name);
         Ok(collection)
     }
@@ -0,0 +1,324 @@
+use bson::{DateTime, Document, doc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+use crate::datastore::runs::Outcome;
+
+/// The most buckets a timeline may have, so a fine `group_by` over a long window is rejected
+/// rather than producing an unchartable response.
+pub const MAX_STATS_BUCKETS: i64 = 1000;
+
+/// Width of each bucket in a run statistics timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsInterval {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl StatsInterval {
+    pub fn millis(&self) -> i64 {
+        match self {
+            StatsInterval::Minute => 60 * 1000,
+            StatsInterval::Hour => 60 * 60 * 1000,
+            StatsInterval::Day => 24 * 60 * 60 * 1000,
+        }
+    }
+
+    /// The `$dateTrunc` unit.
+    fn unit(&self) -> &'static str {
+        match self {
+            StatsInterval::Minute => "minute",
+            StatsInterval::Hour => "hour",
+            StatsInterval::Day => "day",
+        }
+    }
+}
+
+impl TryFrom<&str> for StatsInterval {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "minute" => Ok(StatsInterval::Minute),
+            "hour" => Ok(StatsInterval::Hour),
+            "day" => Ok(StatsInterval::Day),
+            _ => Err(format!(
+                "Unknown interval {}, expected minute, hour or day",
+                value
+            )),
+        }
+    }
+}
+
+/// Parses a window such as `90m`, `24h`, `7d` or `2w` into milliseconds.
+pub fn parse_window(window: &str) -> Result<i64, String> {
+    let invalid = || {
+        format!(
+            "Invalid window {}, expected a number followed by m, h, d or w",
+            window
+        )
+    };
+    let unit = window.chars().last().ok_or_else(invalid)?;
+    let value: i64 = window[..window.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let unit_millis = match unit {
+        'm' => StatsInterval::Minute.millis(),
+        'h' => StatsInterval::Hour.millis(),
+        'd' => StatsInterval::Day.millis(),
+        'w' => 7 * StatsInterval::Day.millis(),
+        _ => return Err(invalid()),
+    };
+    match value {
+        1.. => value.checked_mul(unit_millis).ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+/// Run counts by outcome. `failed` counts every outcome in `Outcome::FAILED`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutcomeCounts {
+    pub total: i64,
+    pub succeeded: i64,
+    pub failed: i64,
+}
+
+/// Runs started within one bucket of the timeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsBucket {
+    pub start: DateTime,
+    #[serde(flatten)]
+    pub counts: OutcomeCounts,
+}
+
+/// Runs started within one bucket of `RunStats::series`. The durations are those of the runs
+/// that completed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeriesBucket {
+    pub start: DateTime,
+    #[serde(flatten)]
+    pub counts: OutcomeCounts,
+    pub average_duration_ms: Option<f64>,
+    pub max_duration_ms: Option<f64>,
+}
+
+/// Runs of one job, or on one agent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NameStats {
+    pub name: String,
+    #[serde(flatten)]
+    pub counts: OutcomeCounts,
+    pub average_duration_ms: f64,
+}
+
+/// Runs started since `since`, counted by outcome over time and broken down per job and per
+/// agent by a single aggregation, so charts do not need the run documents themselves.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunStats {
+    pub since: DateTime,
+    pub group_by: StatsInterval,
+    pub totals: OutcomeCounts,
+    /// Oldest first. Buckets without runs are left out.
+    pub timeline: Vec<StatsBucket>,
+    /// Most runs first.
+    pub jobs: Vec<NameStats>,
+    /// Most runs first.
+    pub agents: Vec<NameStats>,
+}
+
+impl RunStats {
+    /// Runs matching `filter`, e.g. of the jobs a user may see.
+    pub async fn query(
+        datastore: &Datastore,
+        filter: Document,
+        since: DateTime,
+        group_by: StatsInterval,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::query_matching(datastore, filter, since, group_by).await
+    }
+
+    /// As `query`, for the runs on one agent only, e.g. for its health charts.
+    pub async fn for_agent(
+        datastore: &Datastore,
+        agent_name: &str,
+        since: DateTime,
+        group_by: StatsInterval,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::query_matching(
+            datastore,
+            doc! { "agent_name": agent_name },
+            since,
+            group_by,
+        )
+        .await
+    }
+
+    /// Runs matching `filter` started from `from` until `to`, in buckets of `interval_ms` counted
+    /// from `from`, for dashboards that pick their own range and resolution. Oldest first;
+    /// buckets without runs are left out.
+    pub async fn series(
+        datastore: &Datastore,
+        mut filter: Document,
+        from: DateTime,
+        to: DateTime,
+        interval_ms: i64,
+    ) -> Result<Vec<SeriesBucket>, Box<dyn Error>> {
+        let collection = datastore.get_collection::<Document>("runs").await?;
+        let failed: Vec<i32> = Outcome::FAILED.into_iter().map(i32::from).collect();
+        let duration = doc! { "$subtract": ["$completed_at", "$started_at"] };
+        let bucket = doc! { "$add": [
+            from,
+            { "$multiply": [
+                { "$floor": { "$divide": [{ "$subtract": ["$started_at", from] }, interval_ms] } },
+                interval_ms,
+            ] },
+        ] };
+
+        filter.insert("started_at", doc! { "$gte": from, "$lt": to });
+        let mut cursor = collection
+            .aggregate(vec![
+                doc! { "$match": filter },
+                doc! { "$group": {
+                    "_id": bucket,
+                    "total": { "$sum": 1 },
+                    "succeeded": { "$sum": { "$cond": [{ "$eq": ["$outcome", i32::from(Outcome::Success)] }, 1, 0] } },
+                    "failed": { "$sum": { "$cond": [{ "$in": ["$outcome", &failed] }, 1, 0] } },
+                    "average_duration_ms": { "$avg": &duration },
+                    "max_duration_ms": { "$max": &duration },
+                } },
+                doc! { "$sort": { "_id": 1 } },
+            ])
+            .await?;
+
+        let mut buckets = vec![];
+        while let Some(item) = cursor.next().await {
+            let item = item?;
+            let Ok(start) = item.get_datetime("_id") else {
+                continue;
+            };
+            // `$max` of a date difference is a `Int64`, `$avg` a `Double`.
+            let duration = |field: &str| {
+                item.get_f64(field)
+                    .ok()
+                    .or_else(|| item.get_i64(field).ok().map(|value| value as f64))
+            };
+            buckets.push(SeriesBucket {
+                start: *start,
+                counts: Self::counts(&item),
+                average_duration_ms: duration("average_duration_ms"),
+                max_duration_ms: duration("max_duration_ms"),
+            });
+        }
+        Ok(buckets)
+    }
+
+    async fn query_matching(
+        datastore: &Datastore,
+        filter: Document,
+        since: DateTime,
+        group_by: StatsInterval,
+    ) -> Result<Self, Box<dyn Error>> {
+        let collection = datastore.get_collection::<Document>("runs").await?;
+        let result = collection
+            .aggregate(Self::pipeline(filter, since, group_by))
+            .await?
+            .next()
+            .await
+            .transpose()?
+            .unwrap_or_default();
+
+        let read = |facet: &str| -> Vec<Document> {
+            result
+                .get_array(facet)
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter_map(|item| item.as_document().cloned())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let timeline = read("timeline")
+            .into_iter()
+            .filter_map(|item| {
+                Some(StatsBucket {
+                    start: item.get_datetime("_id").ok().copied()?,
+                    counts: Self::counts(&item),
+                })
+            })
+            .collect();
+        let by_name = |facet: &str| -> Vec<NameStats> {
+            read(facet)
+                .into_iter()
+                .map(|item| NameStats {
+                    name: item.get_str("_id").unwrap_or_default().to_string(),
+                    counts: Self::counts(&item),
+                    average_duration_ms: item.get_f64("average_duration_ms").unwrap_or_default(),
+                })
+                .collect()
+        };
+        let totals = read("totals").first().map(Self::counts).unwrap_or_default();
+
+        Ok(Self {
+            since,
+            group_by,
+            totals,
+            timeline,
+            jobs: by_name("jobs"),
+            agents: by_name("agents"),
+        })
+    }
+
+    fn pipeline(mut filter: Document, since: DateTime, group_by: StatsInterval) -> Vec<Document> {
+        let failed: Vec<i32> = Outcome::FAILED.into_iter().map(i32::from).collect();
+        let counts = |id: bson::Bson| {
+            doc! {
+                "_id": id,
+                "total": { "$sum": 1 },
+                "succeeded": { "$sum": { "$cond": [{ "$eq": ["$outcome", i32::from(Outcome::Success)] }, 1, 0] } },
+                "failed": { "$sum": { "$cond": [{ "$in": ["$outcome", &failed] }, 1, 0] } },
+                "average_duration_ms": { "$avg": { "$subtract": ["$completed_at", "$started_at"] } },
+            }
+        };
+        let bucket = doc! { "$dateTrunc": { "date": "$started_at", "unit": group_by.unit() } };
+
+        filter.insert("started_at", doc! { "$gte": since });
+        vec![
+            doc! { "$match": filter },
+            doc! { "$facet": {
+                "totals": [{ "$group": counts(bson::Bson::Null) }],
+                "timeline": [
+                    { "$group": counts(bucket.into()) },
+                    { "$sort": { "_id": 1 } },
+                ],
+                "jobs": [
+                    { "$group": counts("$job_name".into()) },
+                    { "$sort": { "total": -1, "_id": 1 } },
+                ],
+                "agents": [
+                    { "$group": counts("$agent_name".into()) },
+                    { "$sort": { "total": -1, "_id": 1 } },
+                ],
+            } },
+        ]
+    }
+
+    /// `$sum` yields an `Int32` or `Int64` depending on the count.
+    fn counts(item: &Document) -> OutcomeCounts {
+        let count = |field: &str| {
+            item.get_i64(field)
+                .or_else(|_| item.get_i32(field).map(i64::from))
+                .unwrap_or_default()
+        };
+        OutcomeCounts {
+            total: count("total"),
+            succeeded: count("succeeded"),
+            failed: count("failed"),
+        }
+    }
+}
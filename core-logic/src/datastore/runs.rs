@@ -1,10 +1,35 @@
 use bson::{DateTime, oid::ObjectId};
 use mongodb::bson::{Document, doc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use std::error::Error;
 
-use crate::messages::{JobComplete, JobOutCome};
+use crate::datastore::Datastore;
+use crate::datastore::jobs::{JobStep, JobV1};
+use crate::messages::{self, JobComplete, JobOutCome, StepResult};
+use crate::output_parsing::{self, OutputParser, ParsedOutput};
+use crate::receipts;
+use crate::redaction::{REDACTED, Redactor};
+
+/// First segment of the blob store keys of the outputs moved out of run documents by
+/// `RunsV1::offload_output`, and so the GridFS bucket holding them with the default store.
+pub const RUN_OUTPUTS_BUCKET: &str = "run_outputs";
+/// Bytes of an offloaded output kept in the run document, for listings and text search.
+const OFFLOADED_PREVIEW_BYTES: usize = 4 * 1024;
+
+/// Parts of environment variable names whose values are left out of run snapshots, e.g.
+/// `DB_PASSWORD` or `GITHUB_TOKEN`.
+const SECRET_ENV_NAMES: &[&str] = &[
+    "PASSWORD",
+    "PASSWD",
+    "SECRET",
+    "TOKEN",
+    "API_KEY",
+    "APIKEY",
+    "PRIVATE_KEY",
+    "CREDENTIAL",
+];
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(i32)]
@@ -13,7 +38,56 @@ use crate::messages::{JobComplete, JobOutCome};
 pub enum Outcome {
     Failure = 0,
     Success = 1,
-    Unknown,
+    Unknown = 2,
+    TimedOut = 3,       // Killed for exceeding the job's timeout
+    Cancelled = 4,      // Killed because the job was cancelled
+    Skipped = 5,        // A step not run because an earlier step ended the job
+    DispatchFailed = 6, // Central command could not deliver the job to the agent
+}
+
+impl Outcome {
+    pub const ALL: [Outcome; 7] = [
+        Outcome::Success,
+        Outcome::Failure,
+        Outcome::TimedOut,
+        Outcome::Cancelled,
+        Outcome::Skipped,
+        Outcome::DispatchFailed,
+        Outcome::Unknown,
+    ];
+
+    /// Outcomes counted as failed runs: the job ran, or should have, and did not succeed.
+    pub const FAILED: [Outcome; 4] = [
+        Outcome::Failure,
+        Outcome::TimedOut,
+        Outcome::Cancelled,
+        Outcome::DispatchFailed,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Failure => "failure",
+            Outcome::Success => "success",
+            Outcome::Unknown => "unknown",
+            Outcome::TimedOut => "timed_out",
+            Outcome::Cancelled => "cancelled",
+            Outcome::Skipped => "skipped",
+            Outcome::DispatchFailed => "dispatch_failed",
+        }
+    }
+
+    /// The outcome called `name`, e.g. `timed_out`.
+    pub fn from_name(name: &str) -> Option<Outcome> {
+        Outcome::ALL
+            .into_iter()
+            .find(|outcome| outcome.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
 }
 
 impl From<Outcome> for i32 {
@@ -27,6 +101,11 @@ impl From<i32> for Outcome {
         match value {
             0 => Outcome::Failure,
             1 => Outcome::Success,
+            2 => Outcome::Unknown,
+            3 => Outcome::TimedOut,
+            4 => Outcome::Cancelled,
+            5 => Outcome::Skipped,
+            6 => Outcome::DispatchFailed,
             _ => {
                 // Log a warning for unknown outcome
                 tracing::error!("Warning: Unknown JobOutCome value encountered: {}", value);
@@ -42,6 +121,65 @@ impl From<JobOutCome> for Outcome {
             JobOutCome::Failure => Outcome::Failure,
             JobOutCome::Success => Outcome::Success,
             JobOutCome::Unknown => Outcome::Unknown,
+            JobOutCome::TimedOut => Outcome::TimedOut,
+            JobOutCome::Cancelled => Outcome::Cancelled,
+            JobOutCome::Skipped => Outcome::Skipped,
+            JobOutCome::DispatchFailed => Outcome::DispatchFailed,
+        }
+    }
+}
+
+/// Provenance of a run, stored as `{ "kind": ..., "source": ... }` so runs can be filtered by kind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(tag = "kind", content = "source", rename_all = "snake_case")]
+pub enum TriggeredBy {
+    #[default]
+    Scheduler,
+    User(String),
+    ApiToken(String),
+    Webhook(String),
+    Retry(String),
+    Hook(String),
+}
+
+impl From<messages::TriggeredBy> for TriggeredBy {
+    fn from(triggered_by: messages::TriggeredBy) -> Self {
+        match triggered_by {
+            messages::TriggeredBy::Scheduler => TriggeredBy::Scheduler,
+            messages::TriggeredBy::User(name) => TriggeredBy::User(name),
+            messages::TriggeredBy::ApiToken(name) => TriggeredBy::ApiToken(name),
+            messages::TriggeredBy::Webhook(name) => TriggeredBy::Webhook(name),
+            messages::TriggeredBy::Retry(run_id) => TriggeredBy::Retry(run_id),
+            messages::TriggeredBy::Hook(hook) => TriggeredBy::Hook(hook),
+        }
+    }
+}
+
+impl From<TriggeredBy> for messages::TriggeredBy {
+    fn from(triggered_by: TriggeredBy) -> Self {
+        match triggered_by {
+            TriggeredBy::Scheduler => messages::TriggeredBy::Scheduler,
+            TriggeredBy::User(name) => messages::TriggeredBy::User(name),
+            TriggeredBy::ApiToken(name) => messages::TriggeredBy::ApiToken(name),
+            TriggeredBy::Webhook(name) => messages::TriggeredBy::Webhook(name),
+            TriggeredBy::Retry(run_id) => messages::TriggeredBy::Retry(run_id),
+            TriggeredBy::Hook(hook) => messages::TriggeredBy::Hook(hook),
+        }
+    }
+}
+
+impl std::fmt::Display for TriggeredBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriggeredBy::Scheduler => write!(f, "scheduler"),
+            TriggeredBy::User(name) => write!(f, "user {}", name),
+            TriggeredBy::ApiToken(name) => write!(f, "API token {}", name),
+            TriggeredBy::Webhook(name) => write!(f, "webhook {}", name),
+            TriggeredBy::Retry(run_id) => write!(f, "retry of run {}", run_id),
+            TriggeredBy::Hook(hook) => match hook.rsplit_once(':') {
+                Some((job_name, hook)) => write!(f, "{} hook of job {}", hook, job_name),
+                None => write!(f, "hook {}", hook),
+            },
         }
     }
 }
@@ -54,19 +192,482 @@ pub struct RunsV1 {
     pub completed_at: DateTime,
     pub job_name: String,
     pub command: String,
+    /// The job as it was dispatched for the run, taken by central command when the cycle started,
+    /// so later edits of the job do not change what the run is recorded to have run. Missing on
+    /// runs stored before snapshots were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<RunJobSnapshot>,
     pub outcome: Outcome,
+    /// The outcome the agent reported, when the job's `success_statuses` turned the run into a
+    /// failure. Its receipt covers this one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reported_outcome: Option<Outcome>,
     pub agent_name: String,
     pub return_code: i32,
     pub output: String,
+    /// The status and values the job's `output_parsers` extracted from the output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parsed: Option<ParsedOutput>,
+    #[serde(default)]
+    pub triggered_by: TriggeredBy,
+    /// Shared by every run produced by one firing of the job, across all of its agents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cycle_id: Option<String>,
+    /// Whether the agent kept only the head and tail of the output.
+    #[serde(default)]
+    pub truncated: bool,
+    /// Path of the full output on the agent host, when it was spilled to an artifact.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_artifact: Option<String>,
+    /// Id given to the run when it was dispatched; its log lines carry the same `run_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// Hex encoded SHA-256 of `output`, computed when the run was stored, so later corruption of
+    /// the stored output can be detected. Missing on runs stored before checksums were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_sha256: Option<String>,
+    /// Blob store key of the full output when it was too large to keep in the run, which then
+    /// only keeps its head in `output`. `RunsV1::load_output` reads it back; text search only sees
+    /// the head.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_blob: Option<String>,
+    /// The agent's signature of the result, when it sent one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<RunReceipt>,
+    /// The job's `scheduling_lag_ms` for the cycle that produced the run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduling_lag_ms: Option<i64>,
+    /// The result of each step of a multi-step job that ran, in order. `output` then summarizes
+    /// the steps and `return_code` is the last step's.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<RunStep>,
+    /// Seconds an operator added to the run's timeout while it was in flight.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_extension_seconds: Option<u32>,
+    /// Whether the run took longer than the job's `sla.expected_duration`.
+    #[serde(default)]
+    pub sla_breached: bool,
+    /// The namespace of the job, or the default namespace when `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// The job's `tags` when the run was stored.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The job's `metadata` when the run was stored.
+    #[serde(default, skip_serializing_if = "Document::is_empty")]
+    pub metadata: Document,
+}
+
+/// How one step of a multi-step run finished.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunStep {
+    pub name: String,
+    pub command: String,
+    pub started_at: DateTime,
+    pub completed_at: DateTime,
+    pub outcome: Outcome,
+    pub return_code: i32,
+    pub output: String,
+    #[serde(default)]
+    pub truncated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_artifact: Option<String>,
+}
+
+impl From<StepResult> for RunStep {
+    fn from(step: StepResult) -> Self {
+        Self {
+            name: step.name,
+            command: step.command,
+            started_at: DateTime::from_millis(step.started_at),
+            completed_at: DateTime::from_millis(step.completed_at),
+            outcome: step.outcome.into(),
+            return_code: step.return_code,
+            output: step.output,
+            truncated: step.truncated,
+            output_artifact: step.artifact,
+        }
+    }
+}
+
+/// What a run was dispatched to do: the job's command line, environment, limits and the agents
+/// it selected, with the re-run's command and arguments for re-runs. Secrets are left out: the
+/// job's redaction patterns are applied to the command line and environment, as agents apply
+/// them to output, and the values of environment variables named like secrets are replaced.
+/// Scripts are recorded by digest only, and containers without their environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunJobSnapshot {
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub cwd: String,
+    #[serde(default)]
+    pub timeout: u32,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default)]
+    pub valid_return_codes: Vec<i32>,
+    #[serde(default)]
+    pub agents_required: Vec<String>,
+    #[serde(default)]
+    pub agent_groups: Vec<String>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub node_selector: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<JobStep>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+}
+
+impl RunJobSnapshot {
+    pub fn from_job(job: &JobV1) -> Self {
+        // Jobs are validated when saved, so only the defaults are left for an invalid pattern.
+        let redactor = Redactor::with_defaults(&job.redact_patterns)
+            .or_else(|_| Redactor::with_defaults(&[]))
+            .unwrap_or_default();
+        let redact_all = |values: &[String]| -> Vec<String> {
+            values.iter().map(|value| redactor.redact(value)).collect()
+        };
+        let (command, args) = match &job.rerun {
+            Some(rerun) => (&rerun.command, &rerun.args),
+            None => (&job.command, &job.args),
+        };
+        Self {
+            command: redactor.redact(command),
+            args: redact_all(args),
+            env: Self::env_without_secrets(&job.env, &redactor),
+            cwd: job.cwd.clone(),
+            timeout: job.timeout,
+            retries: job.retries,
+            valid_return_codes: job.valid_return_codes.clone(),
+            agents_required: job.agents_required.clone(),
+            agent_groups: job.agent_groups.clone(),
+            platforms: job.platforms.clone(),
+            node_selector: job.node_selector.clone(),
+            steps: job
+                .steps
+                .iter()
+                .map(|step| JobStep {
+                    command: redactor.redact(&step.command),
+                    args: redact_all(&step.args),
+                    env: Self::env_without_secrets(&step.env, &redactor),
+                    ..step.clone()
+                })
+                .collect(),
+            script: job.script.as_ref().map(ToString::to_string),
+            container: job.container.as_ref().map(ToString::to_string),
+        }
+    }
+
+    /// `KEY=VALUE` pairs with the values of variables named like secrets replaced and the
+    /// redaction patterns applied to the rest.
+    fn env_without_secrets(env: &[String], redactor: &Redactor) -> Vec<String> {
+        env.iter()
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _))
+                    if SECRET_ENV_NAMES
+                        .iter()
+                        .any(|secret| name.to_ascii_uppercase().contains(secret)) =>
+                {
+                    format!("{}={}", name, REDACTED)
+                }
+                _ => redactor.redact(pair),
+            })
+            .collect()
+    }
+
+    /// Whether secrets were left out of the command line or the environment, so it cannot be run
+    /// again exactly as recorded.
+    pub fn is_redacted(&self) -> bool {
+        self.is_command_line_redacted() || self.env.iter().any(|pair| pair.contains(REDACTED))
+    }
+
+    /// Whether secrets were left out of the command line, which cannot be recovered.
+    pub fn is_command_line_redacted(&self) -> bool {
+        self.command.contains(REDACTED) || self.args.iter().any(|arg| arg.contains(REDACTED))
+    }
+
+    /// The recorded environment with each redacted variable taken from `current`, the job's
+    /// environment now. Fails with the name of a redacted variable `current` no longer sets.
+    pub fn env_with_secrets(&self, current: &[String]) -> Result<Vec<String>, String> {
+        self.env
+            .iter()
+            .map(|pair| {
+                if !pair.contains(REDACTED) {
+                    return Ok(pair.clone());
+                }
+                let name = pair.split_once('=').map_or(pair.as_str(), |(name, _)| name);
+                current
+                    .iter()
+                    .find(|current| current.split_once('=').is_some_and(|(n, _)| n == name))
+                    .cloned()
+                    .ok_or_else(|| name.to_string())
+            })
+            .collect()
+    }
+}
+
+/// A signed run result, see `receipts`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunReceipt {
+    /// Hex encoded Ed25519 signature of the result's digest.
+    pub signature: String,
+    /// The agent's registered key the signature was checked against, if it had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Whether the signature was valid when central command received the result.
+    pub verified: bool,
 }
 
 impl RunsV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "started_at": -1 })
+            .build();
+        collection.create_index(index).await?;
+        // Lets runs be searched by the words of their output with `$text`. A collection can have
+        // only one text index, so it covers the output of each step as well.
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "output": "text", "steps.output": "text" })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "namespace": 1, "started_at": -1 })
+            .build();
+        collection.create_index(index).await?;
+        // The run history of a job or an agent, newest first.
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "job_name": 1, "started_at": -1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "agent_name": 1, "started_at": -1 })
+            .build();
+        collection.create_index(index).await?;
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "tags": 1, "started_at": -1 })
+            .build();
+        collection.create_index(index).await?;
+
+        Ok(())
+    }
+
+    /// Creates the `RUN_OUTPUTS_BUCKET` chunks collection compressed with zstd rather than
+    /// MongoDB's default snappy, as run outputs are mostly text, for the default GridFS blob store.
+    /// Existing collections keep the compressor they were created with.
+    pub async fn create_output_bucket(db: &mongodb::Database) -> Result<(), Box<dyn Error>> {
+        let chunks = format!("{}.chunks", RUN_OUTPUTS_BUCKET);
+        let exists = db
+            .list_collection_names()
+            .filter(doc! { "name": &chunks })
+            .await?
+            .contains(&chunks);
+        if !exists {
+            db.create_collection(&chunks)
+                .storage_engine(doc! {
+                    "wiredTiger": { "configString": "block_compressor=zstd" }
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Moves an output longer than `threshold_bytes` to the blob store, keeping its head in
+    /// `output` so run documents stay small. Called before the run is stored, after its checksum
+    /// and receipt were taken over the full output. `0` keeps every output in the run.
+    pub async fn offload_output(
+        &mut self,
+        datastore: &Datastore,
+        threshold_bytes: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        if threshold_bytes == 0 || self.output.len() <= threshold_bytes {
+            return Ok(());
+        }
+        let key = format!("{}/{}", RUN_OUTPUTS_BUCKET, ObjectId::new().to_hex());
+        datastore
+            .blob_store()
+            .put(key.clone(), self.output.as_bytes().to_vec())
+            .await?;
+
+        let mut preview = OFFLOADED_PREVIEW_BYTES.min(threshold_bytes);
+        while !self.output.is_char_boundary(preview) {
+            preview -= 1;
+        }
+        self.output.truncate(preview);
+        self.output_blob = Some(key);
+        Ok(())
+    }
+
+    /// Reads an output moved to the blob store back into `output`.
+    pub async fn load_output(&mut self, datastore: &Datastore) -> Result<(), Box<dyn Error>> {
+        let Some(key) = &self.output_blob else {
+            return Ok(());
+        };
+        let output = datastore.blob_store().get(key.clone()).await?;
+        self.output = String::from_utf8(output)?;
+        Ok(())
+    }
+
     pub async fn insert_entry(&self, db: &mongodb::Database) -> Result<(), Box<dyn Error>> {
         let runs_collection = db.collection::<Document>("runs");
         let doc = bson::to_document(self)?;
         runs_collection.insert_one(doc).await?;
         Ok(())
     }
+
+    /// The run of `job` on `agent_name` that central command could not dispatch, e.g. because the
+    /// connection to the agent failed.
+    pub fn dispatch_failed(job: &JobV1, agent_name: &str, run_id: String, error: &str) -> Self {
+        let now = DateTime::now();
+        let output = format!("Failed to dispatch job to agent: {}", error);
+        Self {
+            id: None,
+            started_at: now,
+            completed_at: now,
+            job_name: job.name.clone(),
+            command: job.command.clone(),
+            job: Some(RunJobSnapshot::from_job(job)),
+            outcome: Outcome::DispatchFailed,
+            reported_outcome: None,
+            agent_name: agent_name.to_string(),
+            return_code: -1,
+            output_sha256: Some(Self::output_checksum(&output)),
+            output,
+            parsed: None,
+            triggered_by: job.triggered_by.clone().unwrap_or_default(),
+            cycle_id: job.cycle_id.clone(),
+            truncated: false,
+            output_artifact: None,
+            output_blob: None,
+            run_id: Some(run_id),
+            receipt: None,
+            scheduling_lag_ms: None,
+            steps: vec![],
+            timeout_extension_seconds: None,
+            sla_breached: false,
+            namespace: job.namespace.clone(),
+            tags: job.tags.clone(),
+            metadata: job.metadata.clone(),
+        }
+    }
+
+    /// A `Skipped` run of `job` on `agent_name` standing for `missed` scheduled runs, the first
+    /// due at `first_due` and the last at `last_due` (Unix seconds), that central command missed
+    /// and its misfire policy did not run.
+    pub fn misfire_skipped(
+        job: &JobV1,
+        agent_name: &str,
+        missed: u64,
+        first_due: i64,
+        last_due: i64,
+    ) -> Self {
+        let now = DateTime::now();
+        let format_due = |due: i64| {
+            DateTime::from_millis(due * 1000)
+                .try_to_rfc3339_string()
+                .unwrap_or_else(|_| due.to_string())
+        };
+        let output = match missed {
+            1 => format!(
+                "Skipped the run due at {} ({} misfire policy)",
+                format_due(first_due),
+                job.misfire_policy
+            ),
+            _ => format!(
+                "Skipped {} runs due between {} and {} ({} misfire policy)",
+                missed,
+                format_due(first_due),
+                format_due(last_due),
+                job.misfire_policy
+            ),
+        };
+        Self {
+            id: None,
+            started_at: now,
+            completed_at: now,
+            job_name: job.name.clone(),
+            command: job.command.clone(),
+            job: Some(RunJobSnapshot::from_job(job)),
+            outcome: Outcome::Skipped,
+            reported_outcome: None,
+            agent_name: agent_name.to_string(),
+            return_code: -1,
+            output_sha256: Some(Self::output_checksum(&output)),
+            output,
+            parsed: None,
+            triggered_by: TriggeredBy::Scheduler,
+            cycle_id: None,
+            truncated: false,
+            output_artifact: None,
+            output_blob: None,
+            run_id: None,
+            receipt: None,
+            scheduling_lag_ms: None,
+            steps: vec![],
+            timeout_extension_seconds: None,
+            sla_breached: false,
+            namespace: job.namespace.clone(),
+            tags: job.tags.clone(),
+            metadata: job.metadata.clone(),
+        }
+    }
+
+    /// Parses the output with the job's `parsers` and fails a successful run whose parsed status
+    /// is not one of the job's `success_statuses`, when it has any.
+    pub fn parse_output(&mut self, parsers: &[OutputParser], success_statuses: &[String]) {
+        self.parsed = output_parsing::parse(parsers, &self.output);
+        let status = self
+            .parsed
+            .as_ref()
+            .and_then(|parsed| parsed.status.as_ref());
+        if self.outcome == Outcome::Success
+            && !success_statuses.is_empty()
+            && !status.is_some_and(|status| success_statuses.contains(status))
+        {
+            self.reported_outcome = Some(self.outcome);
+            self.outcome = Outcome::Failure;
+        }
+    }
+
+    /// Whether the run took longer than `expected_seconds`.
+    pub fn exceeds(&self, expected_seconds: u32) -> bool {
+        self.completed_at.timestamp_millis() - self.started_at.timestamp_millis()
+            > expected_seconds as i64 * 1000
+    }
+
+    /// Hex encoded SHA-256 of `output`.
+    pub fn output_checksum(output: &str) -> String {
+        hex::encode(Sha256::digest(output.as_bytes()))
+    }
+
+    /// Whether `output` still matches the checksum recorded when the run was stored, or `None`
+    /// when no checksum was recorded.
+    pub fn verify_output(&self) -> Option<bool> {
+        self.output_sha256
+            .as_ref()
+            .map(|checksum| *checksum == Self::output_checksum(&self.output))
+    }
+
+    /// Checks the stored result against its receipt, e.g. to detect changes made after storage.
+    pub fn verify_receipt(&self) -> bool {
+        match &self.receipt {
+            Some(RunReceipt {
+                signature,
+                public_key: Some(public_key),
+                ..
+            }) => receipts::verify(public_key, &self.into(), signature),
+            _ => false,
+        }
+    }
 }
 
 impl From<JobComplete> for RunsV1 {
@@ -77,10 +678,136 @@ impl From<JobComplete> for RunsV1 {
             completed_at: DateTime::from_millis(job_complete.completed_at),
             job_name: job_complete.job_name,
             command: job_complete.command,
+            job: None, // Taken from the cycle by central command
             agent_name: job_complete.agent_name,
             outcome: job_complete.outcome.into(),
+            reported_outcome: None, // Set by central command if the output changes it
             return_code: job_complete.return_code,
+            run_id: job_complete.run_id,
+            output_sha256: Some(Self::output_checksum(&job_complete.output)),
             output: job_complete.output,
+            parsed: None, // Parsed by central command with the job's parsers
+            triggered_by: job_complete.triggered_by.into(),
+            cycle_id: None, // Taken from the job by central command
+            truncated: job_complete.truncated,
+            output_artifact: job_complete.artifact,
+            output_blob: None, // Offloaded by central command when the output is large
+            receipt: job_complete.signature.map(|signature| RunReceipt {
+                signature,
+                public_key: None, // Verified by central command
+                verified: false,
+            }),
+            scheduling_lag_ms: None, // Taken from the job by central command
+            steps: job_complete.steps.into_iter().map(Into::into).collect(),
+            timeout_extension_seconds: (job_complete.timeout_extension > 0)
+                .then_some(job_complete.timeout_extension),
+            sla_breached: false, // Checked against the job by central command
+            namespace: None,     // Taken from the job by central command
+            tags: vec![],        // Taken from the job by central command
+            metadata: Document::new(), // Taken from the job by central command
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(env: &[&str]) -> RunJobSnapshot {
+        RunJobSnapshot {
+            command: "deploy".to_string(),
+            env: env.iter().map(ToString::to_string).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn redacted_env_is_redacted() {
+        assert!(!snapshot(&["REGION=eu"]).is_redacted());
+        let redacted = snapshot(&["REGION=eu", &format!("API_TOKEN={}", REDACTED)]);
+        assert!(redacted.is_redacted());
+        assert!(!redacted.is_command_line_redacted());
+    }
+
+    #[test]
+    fn redacted_command_line_is_redacted() {
+        let redacted = RunJobSnapshot {
+            args: vec![format!("--password={}", REDACTED)],
+            ..snapshot(&[])
+        };
+        assert!(redacted.is_redacted());
+        assert!(redacted.is_command_line_redacted());
+    }
+
+    #[test]
+    fn redacted_env_is_filled_from_the_job() {
+        let recorded = snapshot(&["REGION=eu", &format!("API_TOKEN={}", REDACTED)]);
+        let current = ["API_TOKEN=s3cret".to_string(), "REGION=us".to_string()];
+        assert_eq!(
+            recorded.env_with_secrets(&current),
+            Ok(vec![
+                "REGION=eu".to_string(),
+                "API_TOKEN=s3cret".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn redacted_env_missing_from_the_job_fails() {
+        let recorded = snapshot(&[&format!("API_TOKEN={}", REDACTED)]);
+        assert_eq!(
+            recorded.env_with_secrets(&["REGION=us".to_string()]),
+            Err("API_TOKEN".to_string())
+        );
+    }
+
+    fn run(outcome: Outcome, output: &str) -> RunsV1 {
+        bson::from_document(doc! {
+            "started_at": DateTime::now(),
+            "completed_at": DateTime::now(),
+            "job_name": "import",
+            "command": "import.sh",
+            "outcome": bson::to_bson(&outcome).unwrap(),
+            "agent_name": "local",
+            "return_code": 0,
+            "output": output,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_output_fails_a_success_without_a_success_status() {
+        let parsers = [OutputParser::Regex {
+            pattern: r"status: (?P<status>\w+)".to_string(),
+        }];
+        let success_statuses = ["complete".to_string()];
+
+        let mut complete = run(Outcome::Success, "status: complete");
+        complete.parse_output(&parsers, &success_statuses);
+        assert_eq!(complete.outcome, Outcome::Success);
+        assert_eq!(complete.reported_outcome, None);
+
+        for output in ["status: partial", "no status"] {
+            let mut failed = run(Outcome::Success, output);
+            failed.parse_output(&parsers, &success_statuses);
+            assert_eq!(failed.outcome, Outcome::Failure);
+            assert_eq!(failed.reported_outcome, Some(Outcome::Success));
         }
     }
+
+    #[test]
+    fn parse_output_leaves_other_outcomes_and_jobs_without_success_statuses() {
+        let parsers = [OutputParser::Regex {
+            pattern: r"status: (?P<status>\w+)".to_string(),
+        }];
+        let mut timed_out = run(Outcome::TimedOut, "status: partial");
+        timed_out.parse_output(&parsers, &["complete".to_string()]);
+        assert_eq!(timed_out.outcome, Outcome::TimedOut);
+        assert_eq!(timed_out.reported_outcome, None);
+
+        let mut succeeded = run(Outcome::Success, "status: partial");
+        succeeded.parse_output(&parsers, &[]);
+        assert_eq!(succeeded.outcome, Outcome::Success);
+        assert_eq!(succeeded.parsed.unwrap().status.as_deref(), Some("partial"));
+    }
 }
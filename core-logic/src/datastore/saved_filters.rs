@@ -0,0 +1,86 @@
+use bson::{DateTime, Document, doc, oid::ObjectId};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::datastore::Datastore;
+
+/// The pages filters can be saved on.
+pub const SAVED_FILTER_PAGES: &[&str] = &["runs", "agents"];
+/// Longest query string kept with a saved filter.
+const MAX_QUERY_LENGTH: usize = 4096;
+
+/// A combination of filters, sort order and time range a user saved on the runs or agents page,
+/// as the query string of the page. Its owner picks it from the page's saved filters, and anyone
+/// can open it through its stable URL, `/saved_filters/<id>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedFilterV1 {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub owner: String,
+    pub page: String, // One of `SAVED_FILTER_PAGES`
+    pub name: String,
+    /// The page's query string without the leading `?`, e.g. `outcome_filter=failure&sort=job_name`.
+    pub query: String,
+    pub created_at: DateTime,
+}
+
+impl SavedFilterV1 {
+    pub async fn create_indicies(
+        collection: &mongodb::Collection<Document>,
+    ) -> Result<(), Box<dyn Error>> {
+        Datastore::create_unique_index(collection, doc! { "owner": 1, "page": 1, "name": 1 })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Checks that the filter has a name, is for a known page and has a query string of bounded
+    /// length.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Saved filter name is required".to_string());
+        }
+        if !SAVED_FILTER_PAGES.contains(&self.page.as_str()) {
+            return Err(format!(
+                "Filters cannot be saved on page {}, only on {}",
+                self.page,
+                SAVED_FILTER_PAGES.join(", ")
+            ));
+        }
+        if self.query.len() > MAX_QUERY_LENGTH {
+            return Err(format!(
+                "Saved filters are limited to {} characters",
+                MAX_QUERY_LENGTH
+            ));
+        }
+        Ok(())
+    }
+
+    /// The URL of the page with the filter applied.
+    pub fn url(&self) -> String {
+        match self.query.is_empty() {
+            true => format!("/{}", self.page),
+            false => format!("/{}?{}", self.page, self.query),
+        }
+    }
+
+    /// The filters `owner` saved on `page`, by name.
+    pub async fn list(
+        datastore: &Datastore,
+        owner: &str,
+        page: &str,
+    ) -> Result<Vec<SavedFilterV1>, Box<dyn Error>> {
+        let collection = datastore
+            .get_collection::<SavedFilterV1>("saved_filters")
+            .await?;
+        let filters = collection
+            .find(doc! { "owner": owner, "page": page })
+            .sort(doc! { "name": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(filters)
+    }
+}
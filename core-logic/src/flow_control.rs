@@ -0,0 +1,94 @@
+//! This module sizes the chunks that messages are written and read in between agents and central
+//! command.
+//!
+//! # Adaptive Sizing
+//!
+//! A `ChunkSizer` starts at a configured chunk size. When adaptive sizing is enabled it moves
+//! between an eighth and 32 times that size (within `MIN_CHUNK_SIZE` and `MAX_CHUNK_SIZE`) based on
+//! what it observes:
+//!
+//! - Writes: after each chunk the throughput is compared with the previous chunk's. While it keeps
+//!   up the chunk size is doubled, so high-latency WAN links spend fewer round trips waiting on
+//!   small writes. When throughput drops by more than a quarter the size is halved again.
+//! - Reads: a read that fills the whole buffer means more data was waiting, so the buffer is
+//!   doubled. Reads that return less than a quarter of what was asked for halve it.
+//!
+//! With adaptive sizing disabled the configured size is always used.
+//!
+//! # Example
+//!
+//! ```rust
+//! use core_logic::flow_control::ChunkSizer;
+//! use std::time::Duration;
+//!
+//! let mut sizer = ChunkSizer::new(8192, true);
+//! sizer.record_write(8192, Duration::from_millis(10));
+//! sizer.record_write(8192, Duration::from_millis(10));
+//! assert_eq!(sizer.size(), 16384);
+//! ```
+use std::time::Duration;
+
+/// Smallest chunk adaptive sizing shrinks to.
+pub const MIN_CHUNK_SIZE: usize = 1024;
+/// Largest chunk adaptive sizing grows to.
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct ChunkSizer {
+    size: usize,
+    min: usize,
+    max: usize,
+    adaptive: bool,
+    last_throughput: Option<f64>, // Bytes per second of the previous write
+}
+
+impl ChunkSizer {
+    pub fn new(size: usize, adaptive: bool) -> Self {
+        let size = size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+        Self {
+            size,
+            min: (size / 8).max(MIN_CHUNK_SIZE),
+            max: size.saturating_mul(32).min(MAX_CHUNK_SIZE),
+            adaptive,
+            last_throughput: None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Records that `bytes` were written in `elapsed`.
+    pub fn record_write(&mut self, bytes: usize, elapsed: Duration) {
+        if !self.adaptive || bytes == 0 {
+            return;
+        }
+        let throughput = bytes as f64 / elapsed.as_secs_f64().max(1e-6);
+        match self.last_throughput {
+            Some(last) if throughput < last * 0.75 => self.shrink(),
+            Some(_) => self.grow(),
+            None => {}
+        }
+        self.last_throughput = Some(throughput);
+    }
+
+    /// Records a read of `read` bytes when up to `requested` (at most `size()`) were asked for.
+    pub fn record_read(&mut self, requested: usize, read: usize) {
+        if !self.adaptive {
+            return;
+        }
+        if read == self.size {
+            self.grow();
+        } else if read < requested / 4 {
+            self.shrink();
+        }
+    }
+
+    fn grow(&mut self) {
+        self.size = self.size.saturating_mul(2).min(self.max);
+    }
+
+    fn shrink(&mut self) {
+        self.size = (self.size / 2).max(self.min);
+    }
+}
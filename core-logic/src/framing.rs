@@ -0,0 +1,392 @@
+//! This module reads the frames that agents send to central command, and that central command
+//! pushes to agents over reverse dispatch connections (see `messages`).
+//!
+//! # Wire Format
+//!
+//! Each frame is a 12-byte header followed by the body, an rkyv serialized `Message`, as written
+//! by `Message::to_frame`. The header is `FRAME_MAGIC`, the body's length as a big-endian `u32`
+//! and the CRC32 (IEEE) of the body as a big-endian `u32`. Zero-length frames carry no message;
+//! central command sends them as reverse dispatch acknowledgments.
+//!
+//! Legacy frames, written by `Message::to_legacy_frame` and by peers that predate checksums, are
+//! only the 4-byte big-endian length followed by the body. Readers accept both, and central
+//! command answers a peer in the format the peer sends.
+//!
+//! # Reading
+//!
+//! - A stream that closes between frames ends cleanly (`Ok(None)`). One that closes part way
+//!   through a frame, including its header, fails with `UnexpectedEof`, so a reconnecting peer
+//!   never has half a message mistaken for a whole one.
+//! - Frames longer than the reader's limit fail with `InvalidData` before their body is read or
+//!   allocated.
+//! - Frames may arrive split over any number of reads, or several to a read.
+//! - A frame whose body does not match its checksum is read whole and fails with
+//!   `ProtocolError::ChecksumMismatch`, leaving the stream at the next frame.
+//!
+//! # Resynchronization
+//!
+//! Once a `FrameReader` has seen a checksummed frame, a header that does not start with
+//! `FRAME_MAGIC` means the stream fell out of step, for instance after a corrupted length. The
+//! reader skips ahead to the next magic, reporting the bytes it skipped in `FrameHeader::skipped`,
+//! and fails with `ProtocolError::Desynchronized` if none starts within `MAX_RESYNC_BYTES`.
+//!
+//! # Example
+//!
+//! ```rust
+//! use core_logic::framing::{self, FrameReader, ProtocolError};
+//! use core_logic::messages::Message;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let mut stream: Vec<u8> = Message::Ping.to_frame().unwrap();
+//! stream.extend(Message::Ping.to_frame().unwrap());
+//! let mut reader = stream.as_slice();
+//!
+//! for _ in 0..2 {
+//!     let frame = framing::read_frame(&mut reader, 1024).await.unwrap().unwrap();
+//!     assert_eq!(Message::try_from(frame).unwrap(), Message::Ping);
+//! }
+//! assert!(framing::read_frame(&mut reader, 1024).await.unwrap().is_none());
+//!
+//! // A corrupted frame is reported and skipped.
+//! let mut corrupted = Message::Ping.to_frame().unwrap();
+//! *corrupted.last_mut().unwrap() ^= 0xff;
+//! corrupted.extend(Message::Ping.to_frame().unwrap());
+//! let mut reader = corrupted.as_slice();
+//! let mut frames = FrameReader::new(1024);
+//!
+//! let error = frames.read_frame(&mut reader).await.unwrap_err();
+//! assert!(matches!(
+//!     ProtocolError::from_io(&error),
+//!     Some(ProtocolError::ChecksumMismatch { .. })
+//! ));
+//! let frame = frames.read_frame(&mut reader).await.unwrap().unwrap();
+//! assert_eq!(Message::try_from(frame).unwrap(), Message::Ping);
+//! # }
+//! ```
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::flow_control::ChunkSizer;
+use crate::messages::MessageBuffer;
+
+/// Starts every checksummed frame.
+pub const FRAME_MAGIC: [u8; 4] = *b"RADF";
+/// Bytes of the header that starts every checksummed frame: the magic, the length and the CRC32
+/// of the body.
+pub const FRAME_HEADER_LEN: usize = 12;
+/// Bytes of the length that starts every legacy frame.
+pub const LEGACY_FRAME_HEADER_LEN: usize = 4;
+/// Most bytes skipped looking for the next frame before a stream is given up on.
+pub const MAX_RESYNC_BYTES: usize = 1024 * 1024;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC32 (IEEE) of `data`, as carried in frame headers.
+///
+/// ```rust
+/// assert_eq!(core_logic::framing::crc32(b"123456789"), 0xcbf4_3926);
+/// ```
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// The checksummed header of a frame carrying `body`, for writers that send the body separately.
+pub fn frame_header(body: &[u8]) -> [u8; FRAME_HEADER_LEN] {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    header[..4].copy_from_slice(&FRAME_MAGIC);
+    header[4..8].copy_from_slice(&(body.len() as u32).to_be_bytes());
+    header[8..].copy_from_slice(&crc32(body).to_be_bytes());
+    header
+}
+
+/// Frames `body` with a checksummed header.
+pub fn encode_frame(body: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + body.len());
+    frame.extend_from_slice(&frame_header(body));
+    frame.extend_from_slice(body);
+    frame
+}
+
+/// Frames `body` with only its length, for peers that predate checksummed frames.
+pub fn encode_legacy_frame(body: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(LEGACY_FRAME_HEADER_LEN + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(body);
+    frame
+}
+
+/// A frame that could not be used, as opposed to a connection that failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The body did not match the checksum in its header.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// Bytes were skipped to find the next frame after a header without the magic.
+    Resynchronized { skipped: usize },
+    /// No frame started within `MAX_RESYNC_BYTES` of a header without the magic.
+    Desynchronized { skipped: usize },
+    /// The body is not a message this build understands, from a corrupted or incompatible peer.
+    Malformed(String),
+}
+
+impl ProtocolError {
+    /// The `ProtocolError` behind `error`, if it is one.
+    pub fn from_io(error: &io::Error) -> Option<&ProtocolError> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Frame checksum {:08x} does not match its body ({:08x})",
+                expected, actual
+            ),
+            ProtocolError::Resynchronized { skipped } => {
+                write!(f, "Skipped {} bytes to find the next frame", skipped)
+            }
+            ProtocolError::Desynchronized { skipped } => {
+                write!(f, "No frame found in the next {} bytes", skipped)
+            }
+            ProtocolError::Malformed(detail) => write!(f, "Malformed message: {}", detail),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+impl From<ProtocolError> for io::Error {
+    fn from(error: ProtocolError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// The header of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// Bytes of the body that follows.
+    pub len: usize,
+    /// CRC32 of the body, `None` for legacy frames.
+    pub checksum: Option<u32>,
+    /// Bytes skipped before the header to get back in step with the stream.
+    pub skipped: usize,
+}
+
+impl FrameHeader {
+    /// Bytes of the header itself.
+    pub fn header_len(&self) -> usize {
+        match self.checksum {
+            Some(_) => FRAME_HEADER_LEN,
+            None => LEGACY_FRAME_HEADER_LEN,
+        }
+    }
+
+    /// Checks `body` against the header's checksum. Legacy frames always pass.
+    pub fn verify(&self, body: &[u8]) -> Result<(), ProtocolError> {
+        match self.checksum {
+            Some(expected) if crc32(body) != expected => Err(ProtocolError::ChecksumMismatch {
+                expected,
+                actual: crc32(body),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reads the frames of one stream, remembering whether its peer sends checksummed frames.
+#[derive(Debug, Clone)]
+pub struct FrameReader {
+    max_len: usize,
+    checksummed: bool,
+}
+
+impl FrameReader {
+    /// A reader refusing frames over `max_len` bytes (`0` is unlimited).
+    pub fn new(max_len: usize) -> Self {
+        FrameReader {
+            max_len,
+            checksummed: false,
+        }
+    }
+
+    /// Whether the peer sent checksummed frames, and so understands them in return.
+    pub fn checksummed(&self) -> bool {
+        self.checksummed
+    }
+
+    /// Reads the next frame, or `None` if the stream closed before it started.
+    pub async fn read_frame<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let Some(header) = self.read_header(reader).await? else {
+            return Ok(None);
+        };
+        let mut frame = vec![0u8; header.len];
+        reader.read_exact(&mut frame).await?;
+        header.verify(&frame)?;
+        Ok(Some(frame))
+    }
+
+    /// Reads the header of the next frame, or `None` if the stream closed before it started.
+    pub async fn read_header<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+    ) -> io::Result<Option<FrameHeader>> {
+        let mut first = [0u8; 1];
+        if reader.read(&mut first).await? == 0 {
+            return Ok(None);
+        }
+        self.read_header_after(reader, first[0]).await.map(Some)
+    }
+
+    /// Reads the rest of a header whose first byte, `first`, was already read.
+    pub async fn read_header_after<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+        first: u8,
+    ) -> io::Result<FrameHeader> {
+        let mut start = [first, 0, 0, 0];
+        reader.read_exact(&mut start[1..]).await?;
+        let mut skipped = 0;
+        if start != FRAME_MAGIC {
+            if !self.checksummed {
+                let len = u32::from_be_bytes(start) as usize;
+                check_frame_length(len, self.max_len)?;
+                return Ok(FrameHeader {
+                    len,
+                    checksum: None,
+                    skipped,
+                });
+            }
+            skipped = resynchronize(reader, start).await?;
+        }
+        let mut rest = [0u8; FRAME_HEADER_LEN - FRAME_MAGIC.len()];
+        reader.read_exact(&mut rest).await?;
+        let [l0, l1, l2, l3, c0, c1, c2, c3] = rest;
+        let len = u32::from_be_bytes([l0, l1, l2, l3]) as usize;
+        self.checksummed = true;
+        check_frame_length(len, self.max_len)?;
+        Ok(FrameHeader {
+            len,
+            checksum: Some(u32::from_be_bytes([c0, c1, c2, c3])),
+            skipped,
+        })
+    }
+}
+
+/// Skips bytes until the last four read are `FRAME_MAGIC`, returning how many were skipped.
+async fn resynchronize<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    mut window: [u8; 4],
+) -> io::Result<usize> {
+    let mut skipped = 0;
+    while window != FRAME_MAGIC {
+        if skipped >= MAX_RESYNC_BYTES {
+            return Err(ProtocolError::Desynchronized { skipped }.into());
+        }
+        window.rotate_left(1);
+        window[3] = reader.read_u8().await?;
+        skipped += 1;
+    }
+    Ok(skipped)
+}
+
+/// Reads the next frame, or `None` if the stream closed before it started. `max_len` of `0` is
+/// unlimited.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_len: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    FrameReader::new(max_len).read_frame(reader).await
+}
+
+/// Fails with `InvalidData` if a frame of `len` bytes is over `max_len` (`0` is unlimited).
+pub fn check_frame_length(len: usize, max_len: usize) -> io::Result<()> {
+    match max_len > 0 && len > max_len {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {} bytes exceeds the limit of {}", len, max_len),
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Reads a frame body of `len` bytes in chunks sized by `chunk_sizer`, so large frames are not
+/// read in one allocation sized by the peer before any of it arrived.
+pub async fn read_frame_body<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    len: usize,
+    chunk_sizer: &mut ChunkSizer,
+) -> io::Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(len.min(chunk_sizer.size()));
+    read_chunks(reader, len, chunk_sizer, &mut body, grow_vec).await?;
+    Ok(body)
+}
+
+/// Reads a frame body of `len` bytes into the start of `buffer` like `read_frame_body`, reusing
+/// the buffer's allocation, so the message can be read in place (see `MessageBuffer::message`).
+pub async fn read_frame_body_into<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    len: usize,
+    chunk_sizer: &mut ChunkSizer,
+    buffer: &mut MessageBuffer,
+) -> io::Result<()> {
+    read_chunks(reader, len, chunk_sizer, buffer, MessageBuffer::slice_mut).await
+}
+
+fn grow_vec(body: &mut Vec<u8>, len: usize) -> &mut [u8] {
+    body.resize(len, 0);
+    body
+}
+
+/// Reads `len` bytes into `body` in chunks sized by `chunk_sizer`. `grow` gives the first bytes
+/// of `body` up to the end of the next chunk, growing it as needed.
+async fn read_chunks<R: AsyncRead + Unpin, B>(
+    reader: &mut R,
+    len: usize,
+    chunk_sizer: &mut ChunkSizer,
+    body: &mut B,
+    grow: fn(&mut B, usize) -> &mut [u8],
+) -> io::Result<()> {
+    let mut read = 0;
+    while read < len {
+        let to_read = chunk_sizer.size().min(len - read);
+        let n = reader.read(&mut grow(body, read + to_read)[read..]).await?;
+        chunk_sizer.record_read(to_read, n);
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed while reading message",
+            ));
+        }
+        read += n;
+    }
+    Ok(())
+}
@@ -0,0 +1,193 @@
+//! This module provides the `/healthz` and `/readyz` endpoints served by central command and the
+//! agent for container orchestration probes.
+//!
+//! # Checks
+//!
+//! Components record the state of what they depend on in a shared `Health` with `Health::set`,
+//! e.g. central command's datastore connection or the agent's connection to central command.
+//! Values that are cheaper to read on demand, such as a queue depth, are registered as probes with
+//! `Health::probe` and evaluated on every request.
+//!
+//! # Endpoints
+//!
+//! - `GET /healthz`: Liveness. Always `200` while the process is serving, with every check in the
+//!   body so a failing dependency is visible without restarting the process.
+//! - `GET /readyz`: Readiness. `200` when every check passes, `503` otherwise. A check that has
+//!   not reported yet counts as failing.
+//!
+//! Both return a JSON body:
+//!
+//! ```json
+//! { "status": "ok", "checks": { "datastore": { "ok": true, "detail": "ping succeeded" } } }
+//! ```
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use core_logic::health::Health;
+//!
+//! # async fn example() {
+//! let health = Health::new(&["datastore"]);
+//! health.set("datastore", true, "ping succeeded");
+//! tokio::spawn(health.clone().serve("0.0.0.0:9090".to_string()));
+//! # }
+//! ```
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info};
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+const MAX_REQUEST_BYTES: usize = 8192;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckStatus {
+    pub ok: bool,
+    pub detail: String,
+}
+
+type Probe = Box<dyn Fn() -> CheckStatus + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct Health {
+    checks: Arc<Mutex<BTreeMap<String, CheckStatus>>>,
+    probes: Arc<Mutex<BTreeMap<String, Probe>>>,
+}
+
+#[derive(Serialize)]
+struct HealthReport {
+    status: &'static str,
+    checks: BTreeMap<String, CheckStatus>,
+}
+
+impl Health {
+    /// Creates a `Health` whose `checks` fail until they are first set.
+    pub fn new(checks: &[&str]) -> Self {
+        let health = Self::default();
+        for check in checks {
+            health.set(check, false, "not checked yet");
+        }
+        health
+    }
+
+    /// Records the state of a check.
+    pub fn set(&self, check: &str, ok: bool, detail: impl Into<String>) {
+        if let Ok(mut checks) = self.checks.lock() {
+            checks.insert(
+                check.to_string(),
+                CheckStatus {
+                    ok,
+                    detail: detail.into(),
+                },
+            );
+        }
+    }
+
+    /// Registers a check evaluated on every request.
+    pub fn probe(&self, check: &str, probe: impl Fn() -> CheckStatus + Send + Sync + 'static) {
+        if let Ok(mut probes) = self.probes.lock() {
+            probes.insert(check.to_string(), Box::new(probe));
+        }
+    }
+
+    /// The current state of every check.
+    pub fn checks(&self) -> BTreeMap<String, CheckStatus> {
+        let mut checks = self
+            .checks
+            .lock()
+            .map(|checks| checks.clone())
+            .unwrap_or_default();
+        if let Ok(probes) = self.probes.lock() {
+            for (check, probe) in probes.iter() {
+                checks.insert(check.clone(), probe());
+            }
+        }
+        checks
+    }
+
+    /// Whether every check passes.
+    pub fn is_ready(&self) -> bool {
+        self.checks().values().all(|check| check.ok)
+    }
+
+    /// Serves `/healthz` and `/readyz` on `address` until the listener fails.
+    pub async fn serve(self, address: String) {
+        let listener = match TcpListener::bind(&address).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to bind health endpoint to {}: {}", address, e);
+                return;
+            }
+        };
+        info!("Serving /healthz and /readyz on {}", address);
+        loop {
+            let (stream, peer_addr) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    error!("Health endpoint on {} failed: {}", address, e);
+                    return;
+                }
+            };
+            let health = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = health.respond(stream).await {
+                    debug!("Health request from {} failed: {}", peer_addr, e);
+                }
+            });
+        }
+    }
+
+    /// Answers one HTTP request. Only the request line is looked at.
+    async fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n")
+            && request.len() < MAX_REQUEST_BYTES
+        {
+            let n = stream.read(&mut buffer).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..n]);
+        }
+        let request = String::from_utf8_lossy(&request);
+        let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+        let (method, path) = (request_line.next(), request_line.next());
+        // Probes may add a query string, e.g. `/readyz?verbose`.
+        let path = path.map(|path| path.split('?').next().unwrap_or_default());
+
+        let (status, body) = match (method, path) {
+            (Some("GET" | "HEAD"), Some("/healthz")) => ("200 OK", self.report(true)),
+            (Some("GET" | "HEAD"), Some("/readyz")) => {
+                let ready = self.is_ready();
+                let status = match ready {
+                    true => "200 OK",
+                    false => "503 Service Unavailable",
+                };
+                (status, self.report(ready))
+            }
+            (Some("GET" | "HEAD"), _) => ("404 Not Found", "{}".to_string()),
+            _ => ("405 Method Not Allowed", "{}".to_string()),
+        };
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        if method != Some("HEAD") {
+            stream.write_all(body.as_bytes()).await?;
+        }
+        stream.shutdown().await
+    }
+
+    fn report(&self, ok: bool) -> String {
+        let report = HealthReport {
+            status: if ok { "ok" } else { "unavailable" },
+            checks: self.checks(),
+        };
+        serde_json::to_string(&report).unwrap_or_default()
+    }
+}
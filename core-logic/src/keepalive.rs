@@ -0,0 +1,56 @@
+//! This module enables TCP keepalive on the connections between agents and central command, so a
+//! peer that vanished without closing its connection (a crashed host, a dropped NAT mapping) is
+//! detected by the operating system instead of lingering until the next write fails.
+//!
+//! # Probes
+//!
+//! After `idle` without traffic the OS starts sending probes, one every quarter of `idle`. When
+//! `KEEPALIVE_RETRIES` probes in a row go unanswered the connection is reset, so a dead peer is
+//! detected roughly `2 * idle` after it was last heard from. Reads and writes on the connection then
+//! fail, and the connection is dropped like any other broken one.
+//!
+//! Keepalive only detects peers that stopped answering at the TCP level. Peers that answer but
+//! stopped sending messages are caught by the application-level idle timeouts of the agent and
+//! central command.
+//!
+//! # Example
+//!
+//! ```rust
+//! use core_logic::keepalive;
+//! use std::time::Duration;
+//! use tokio::net::{TcpListener, TcpStream};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+//! let stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+//! keepalive::enable(&stream, Duration::from_secs(30)).unwrap();
+//! # }
+//! ```
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+
+use std::io;
+use std::time::Duration;
+
+/// Unanswered probes after which the connection is reset, where the OS allows setting it.
+pub const KEEPALIVE_RETRIES: u32 = 4;
+
+/// Enables keepalive on `stream`, probing after `idle` without traffic. An `idle` of zero leaves
+/// the OS defaults untouched.
+pub fn enable(stream: &TcpStream, idle: Duration) -> io::Result<()> {
+    if idle.is_zero() {
+        return Ok(());
+    }
+    let keepalive = TcpKeepalive::new().with_time(idle);
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        windows
+    ))]
+    let keepalive = keepalive.with_interval((idle / 4).max(Duration::from_secs(1)));
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+    let keepalive = keepalive.with_retries(KEEPALIVE_RETRIES);
+    SockRef::from(stream).set_tcp_keepalive(&keepalive)
+}
@@ -1,2 +1,14 @@
+pub mod blob_store;
+pub mod bus;
+pub mod cron;
 pub mod datastore;
+pub mod flow_control;
+pub mod framing;
+pub mod health;
+pub mod keepalive;
+pub mod logging;
 pub mod messages;
+pub mod output_parsing;
+pub mod priority;
+pub mod receipts;
+pub mod redaction;
@@ -0,0 +1,59 @@
+//! This module sets up logging for central command and the agent.
+//!
+//! # Configuration
+//!
+//! - `LOG_FORMAT`: `text` (default) for human readable lines, or `json` for one JSON object per
+//!   line, e.g. to ship logs to ELK or Loki.
+//! - `RUST_LOG`: Which events are logged, as a `tracing_subscriber` filter (default: `info`).
+//!
+//! # Correlation
+//!
+//! Work done for a run is logged inside a `run` span (see `run_span`) carrying `run_id`,
+//! `job_name` and `agent_name`, in both central command and the agent. In JSON mode the fields of the current
+//! span are included in every event under `span`, so all of a run's log lines can be found by
+//! its `run_id`, which is also stored on the run.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use core_logic::logging;
+//! use tracing::info;
+//!
+//! logging::init();
+//! let _span = logging::run_span(Some("1b4e"), "backup", "db-1").entered();
+//! info!("Job started");
+//! ```
+use tracing::{Span, info_span};
+use tracing_subscriber::EnvFilter;
+
+use std::env;
+
+/// Installs the global subscriber selected by `LOG_FORMAT` and `RUST_LOG`.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    let json = env::var("LOG_FORMAT").is_ok_and(|format| format.eq_ignore_ascii_case("json"));
+    let result = match json {
+        true => tracing::subscriber::set_global_default(
+            builder
+                .json()
+                .flatten_event(true)
+                .with_current_span(true)
+                .with_span_list(false)
+                .finish(),
+        ),
+        false => tracing::subscriber::set_global_default(builder.finish()),
+    };
+    result.expect("Failed to set global default subscriber");
+}
+
+/// Span for work done on one run. Runs dispatched before run ids existed have an empty `run_id`.
+pub fn run_span(run_id: Option<&str>, job_name: &str, agent_name: &str) -> Span {
+    info_span!(
+        "run",
+        run_id = run_id.unwrap_or_default(),
+        job_name,
+        agent_name
+    )
+}
@@ -6,11 +6,45 @@
 //! # Structures
 //!
 //! - `RegisterAgent`: Represents an agent registration message, containing the agent's name,
-//!   hostname, and port.
-//! - `DispatchJob`: Represents a job dispatch message, including job name, command, arguments, and
-//!   an optional agent name.
-//! - `JobComplete`: Indicates the completion of a job by an agent, including job and agent names.
+//!   hostname, port, running version, local timezone and locale, receipt public key, and the
+//!   platform it runs on: OS, architecture, kernel, available shells and enabled features.
+//! - `ContainerInfo`: The container runtime an agent runs in and, on Kubernetes, its node, pod,
+//!   namespace and node labels.
+//! - `DispatchJob`: Represents a job dispatch message, including job name, command, arguments, an
+//!   optional agent name, what triggered the run, patterns to redact from its output and the id
+//!   that correlates the run's logs (see `logging`), the digests of the files pushed for it and
+//!   the script it runs instead of a command and the container it runs the command in, if any.
+//! - `DispatchBatch`: Several `DispatchJob`s sent to an agent at once and acknowledged once, to
+//!   agents with the `dispatch_batch` feature.
+//! - `JobStep`: One command of a multi-step job, run by the agent in order after the previous one.
+//! - `JobScript`: A script body the agent runs with an interpreter instead of a command.
+//! - `JobContainer`: The image, mounts and environment of the container a job's command runs in.
+//! - `FileChunk`: Part of a file central command pushes to an agent ahead of a `DispatchJob` (see
+//!   File Distribution).
+//! - `FileDigest`: A pushed file the agent checks before running the job it was pushed for.
+//! - `JobComplete`: Indicates the completion of a job by an agent, including job and agent names,
+//!   the result of each step and the agent's signature of the result (see `receipts`).
+//! - `StepResult`: How one step of a multi-step job finished.
+//! - `JobProgress`: Sent by an agent while a job is still running, warning central command that
+//!   the run has used most of its timeout and is likely to be killed.
+//! - `CancelJob`: Asks an agent to kill a running job and its child processes.
+//! - `ExtendTimeout`: Asks an agent to give a running job more time before it is killed.
+//! - `UpdateAgent`: Asks an agent to download, verify and restart into a new binary.
+//! - `ReverseDispatch`: Sent by an agent as the first message on its connection to central command
+//!   to receive dispatches over that same connection instead of through its listen port.
+//! - `PollWork`: Sent by an agent that pulls its jobs, asking central command for the work waiting
+//!   for it (see Pull Dispatch).
+//! - `Authenticate`: Sent by an agent as the first message on its connection when central command
+//!   requires agents to authenticate, carrying a `Credential` (a shared token or SPIFFE JWT-SVID).
+//! - `OpenShell`: Sent by central command on a connection it dialed for the purpose, opening an
+//!   interactive shell on the agent for an operator (see Remote Shells).
+//! - `ShellData`: Keystrokes for, or output from, a remote shell.
+//! - `ResizeShell`: The operator's terminal changed size.
+//! - `CloseShell`: Ends a remote shell, saying why.
+//! - `Envelope`: A request carrying another message under an id, or the response to one (see
+//!   Multiplexing).
 //! - `Message`: An enum encapsulating all possible message types exchanged in the system.
+//! - `Priority`: Whether a message is control traffic or a bulk payload, see `priority`.
 //!
 //! # Error Handling
 //!
@@ -22,31 +56,155 @@
 //! the network. Provides `TryFrom` implementations for converting between `Message` and `Vec<u8>`
 //! using `rkyv` serialization.
 //!
+//! # Zero-Copy Receive
+//!
+//! Receivers on hot paths read each message into a `MessageBuffer`, allocated once per
+//! connection, and validate it in place with `MessageBuffer::message` (or `access_message`),
+//! getting an `ArchivedMessage` without copying or deserializing it. Frequent messages such as
+//! `Ping` are handled from the archived form; others are converted with `Message::from`.
+//!
+//! ```rust
+//! use core_logic::messages::{ArchivedMessage, Message, MessageBuffer};
+//!
+//! let serialized: Vec<u8> = Message::Ping.try_into().unwrap();
+//! let mut buffer = MessageBuffer::new();
+//! buffer.slice_mut(serialized.len()).copy_from_slice(&serialized);
+//!
+//! let archived = buffer.message(serialized.len()).unwrap();
+//! assert!(matches!(archived, ArchivedMessage::Ping));
+//! assert_eq!(archived.kind(), "Ping");
+//! assert_eq!(Message::from(archived), Message::Ping);
+//! ```
+//!
 //! # TCP Communication
 //!
 //! - `Message::tcp_write`: Asynchronously writes a serialized message to a `TcpStream`.
+//! - `Message::to_frame`: Serializes a message into a checksummed frame (see `framing`).
+//! - `Message::to_legacy_frame`: Serializes a message with only a 4-byte big-endian length
+//!   prefix, for peers that predate checksummed frames.
+//!
+//! # Reverse Dispatch
+//!
+//! After an agent sends `ReverseDispatch` (acknowledged with `OK`), central command pushes
+//! messages to it as frames on the same connection and acknowledges the agent's
+//! messages with zero-length frames. Frames are checksummed if the agent's are; agents sending
+//! legacy frames get legacy frames, and `REVERSE_DISPATCH_ACK` as acknowledgment.
+//!
+//! # Pull Dispatch
+//!
+//! An agent that pulls its jobs sends `PollWork` as the first message on its connection to
+//! central command (acknowledged with `OK`) and again at its poll interval. The connection then
+//! works as a reverse dispatch connection, except that jobs are not pushed as soon as they are
+//! due: each `PollWork` is acknowledged, and central command then sends the `FileChunk`s and
+//! `DispatchJob` of each run it claimed for the agent, up to `max_jobs`. Cancellations, timeout
+//! extensions and updates are still pushed.
+//!
+//! # File Distribution
+//!
+//! Before dispatching a job with files, central command sends each file as `FileChunk`s in order,
+//! each small enough to fit a single read on the agent's listen port. The agent writes them to a
+//! temporary file next to the destination and moves it into place once the last chunk arrived
+//! and the file's SHA-256 matches. The `DispatchJob` lists the files' digests, and the agent fails
+//! the run without running anything if a file is missing or differs.
+//!
+//! # Remote Shells
+//!
+//! Central command dials the agent's listen port for each remote shell and writes `OpenShell` as
+//! it writes any other message, and the agent answers `OK`. From then on both sides exchange
+//! frames on the connection: `ShellData` and `ResizeShell` towards the agent,
+//! `ShellData` from it, and `CloseShell` from either side before it closes the connection.
+//!
+//! # Multiplexing
+//!
+//! Instead of waiting for `OK` after each message, an agent may send its messages to central
+//! command wrapped in `Envelope`s with increasing ids. Central command answers every envelope
+//! with a frame holding an `Envelope` with the same id and an empty payload, so
+//! many messages can be in flight on one connection and each acknowledgment is matched to its
+//! message by id. Envelopes are never nested.
+//!
+//! ```rust
+//! use core_logic::messages::{Envelope, Message};
+//!
+//! let request = Envelope::request(7, &Message::Ping).unwrap();
+//! assert_eq!(request.message().unwrap(), Some(Message::Ping));
+//!
+//! let response = Envelope::response(request.id);
+//! assert_eq!(response.message().unwrap(), None);
+//! ```
 //!
 //! # Example
 //!
 //! ```rust
 //! use tokio::net::TcpStream;
-//! use core_logic::communications::Message;
+//! use core_logic::messages::Message;
 //!
 //! async fn send_message(stream: &mut TcpStream, message: Message) -> Result<(), Box<dyn std::error::Error>> {
 //!     message.tcp_write(stream).await?;
 //!     Ok(())
 //! }
 //! ```
-use rkyv::{Archive, Deserialize, Serialize, option::ArchivedOption, rancor::Error};
+use rkyv::{
+    Archive, Deserialize, Serialize, option::ArchivedOption, rancor::Error, util::AlignedVec,
+};
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tracing::error;
 
+use crate::framing;
+
+/// Acknowledgment for agent messages on a reverse dispatch connection, as a legacy frame.
+pub const REVERSE_DISPATCH_ACK: [u8; 4] = 0u32.to_be_bytes();
+
 #[derive(Archive, Deserialize, Serialize, Hash, PartialEq, Eq, Debug, Clone)]
 pub struct RegisterAgent {
     pub name: String,
     pub hostname: String,
     pub port: u16,
+    pub version: String,
+    pub timezone: String, // IANA timezone name, e.g. "Europe/Berlin"
+    pub locale: String,   // e.g. "de_DE.UTF-8"
+    pub receipt_public_key: Option<String>, // Hex encoded Ed25519 key that signs `JobComplete`s
+    pub os: String,       // e.g. "linux", "macos", "windows"
+    pub arch: String,     // e.g. "amd64", "arm64" (see `datastore::agents::normalize_arch`)
+    pub kernel: String,   // Kernel release, e.g. "6.8.0-45-generic"
+    pub shells: Vec<String>, // Shells found on the host, e.g. "sh", "bash", "powershell"
+    pub features: Vec<String>, // Optional agent features enabled, e.g. "reverse_dispatch"
+    pub container: Option<ContainerInfo>, // Set when the agent runs in a container
+}
+
+/// Where an agent running in a container runs, as detected by the agent.
+#[derive(Archive, Deserialize, Serialize, Hash, PartialEq, Eq, Debug, Clone)]
+pub struct ContainerInfo {
+    pub runtime: String,           // "kubernetes", "docker", "podman" or "containerd"
+    pub node_name: Option<String>, // The Kubernetes node the pod is scheduled on
+    pub pod_name: Option<String>,
+    pub pod_namespace: Option<String>,
+    pub node_labels: Vec<String>, // Labels of the Kubernetes node, as `key=value`
+}
+
+/// What caused a run to be dispatched.
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+pub enum TriggeredBy {
+    #[default]
+    Scheduler,
+    User(String),     // User name
+    ApiToken(String), // Token name
+    Webhook(String),  // Webhook name
+    Retry(String),    // Id of the run being retried
+    Hook(String), // `<job name>:on_success` or `<job name>:on_failure` of the job whose hook fired
+}
+
+impl From<&ArchivedTriggeredBy> for TriggeredBy {
+    fn from(archived: &ArchivedTriggeredBy) -> Self {
+        match archived {
+            ArchivedTriggeredBy::Scheduler => TriggeredBy::Scheduler,
+            ArchivedTriggeredBy::User(name) => TriggeredBy::User(name.to_string()),
+            ArchivedTriggeredBy::ApiToken(name) => TriggeredBy::ApiToken(name.to_string()),
+            ArchivedTriggeredBy::Webhook(name) => TriggeredBy::Webhook(name.to_string()),
+            ArchivedTriggeredBy::Retry(run_id) => TriggeredBy::Retry(run_id.to_string()),
+            ArchivedTriggeredBy::Hook(hook) => TriggeredBy::Hook(hook.to_string()),
+        }
+    }
 }
 
 #[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
@@ -56,13 +214,68 @@ pub struct DispatchJob {
     pub args: String,
     pub agent_name: Option<String>,
     pub valid_return_codes: Option<Vec<i32>>, // Optional list of valid return codes
+    pub timeout: Option<u32>,                 // Seconds before the job is killed
+    pub triggered_by: TriggeredBy,
+    pub redact_patterns: Vec<String>, // Applied to the output in addition to the agent's defaults
+    pub run_id: Option<String>,       // Correlates the run's log lines and its stored result
+    pub steps: Vec<JobStep>,          // Run in order instead of `command` when not empty
+    pub files: Vec<FileDigest>,       // Pushed before the dispatch, checked before running
+    pub script: Option<JobScript>,    // Run instead of `command` when set
+    pub container: Option<JobContainer>, // Runs the command, or each step, in a container
+    pub env: Vec<String>,             // `KEY=VALUE` pairs set for the command and every step
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct JobScript {
+    pub interpreter: String, // "bash", "python" or "powershell"
+    pub body: String,
+}
+
+/// A container image the agent runs a job's command in, with `docker run` or `podman run`.
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct JobContainer {
+    pub image: String,
+    pub mounts: Vec<String>, // `host path:container path`, optionally with `:ro`
+    pub env: Vec<String>,    // `KEY=VALUE` pairs set in the container
+    pub workdir: Option<String>, // The image's working directory when `None`
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct FileDigest {
+    pub destination: String,
+    pub sha256: String, // Hex encoded
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct FileChunk {
+    pub job_name: String,
+    pub destination: String, // Absolute path on the agent
+    pub mode: Option<u32>,   // Permission bits set on Unix agents
+    pub size: u64,           // Of the whole file
+    pub sha256: String,      // Hex encoded, of the whole file
+    pub offset: u64,         // Of `data` in the file; chunks are sent in order
+    pub data: Vec<u8>,
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct JobStep {
+    pub name: String,
+    pub command: String,
+    pub args: String,
+    pub cwd: Option<String>, // Working directory, the agent's own when `None`
+    pub env: Vec<String>,    // `KEY=VALUE` pairs added to the agent's environment
+    pub continue_on_error: bool, // Run the next step even if this one fails
 }
 
 #[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
 pub enum JobOutCome {
     Failure = 0,
     Success = 1,
-    Unknown,
+    Unknown = 2,
+    TimedOut = 3,       // Killed for exceeding the job's timeout
+    Cancelled = 4,      // Killed because the job was cancelled
+    Skipped = 5,        // A step not run because an earlier step ended the job
+    DispatchFailed = 6, // Central command could not deliver the job to the agent
 }
 
 impl From<&ArchivedJobOutCome> for JobOutCome {
@@ -71,6 +284,10 @@ impl From<&ArchivedJobOutCome> for JobOutCome {
             ArchivedJobOutCome::Failure => JobOutCome::Failure,
             ArchivedJobOutCome::Success => JobOutCome::Success,
             ArchivedJobOutCome::Unknown => JobOutCome::Unknown,
+            ArchivedJobOutCome::TimedOut => JobOutCome::TimedOut,
+            ArchivedJobOutCome::Cancelled => JobOutCome::Cancelled,
+            ArchivedJobOutCome::Skipped => JobOutCome::Skipped,
+            ArchivedJobOutCome::DispatchFailed => JobOutCome::DispatchFailed,
         }
     }
 }
@@ -86,6 +303,11 @@ impl From<i32> for JobOutCome {
         match value {
             0 => JobOutCome::Failure,
             1 => JobOutCome::Success,
+            2 => JobOutCome::Unknown,
+            3 => JobOutCome::TimedOut,
+            4 => JobOutCome::Cancelled,
+            5 => JobOutCome::Skipped,
+            6 => JobOutCome::DispatchFailed,
             _ => {
                 error!("Warning: Unknown JobOutCome value encountered: {}", value);
                 JobOutCome::Unknown // Default to Failure for unknown values
@@ -104,6 +326,143 @@ pub struct JobComplete {
     pub return_code: i32,
     pub outcome: JobOutCome,
     pub output: String,
+    pub triggered_by: TriggeredBy, // Echoed from the `DispatchJob`
+    pub truncated: bool,           // Only the head and tail of the output were kept
+    pub artifact: Option<String>,  // Path of the full output on the agent host
+    pub signature: Option<String>, // Hex encoded receipt signature, see `receipts`
+    pub run_id: Option<String>,    // Echoed from the `DispatchJob`
+    pub steps: Vec<StepResult>,    // One per step that ran, in order
+    pub timeout_extension: u32,    // Seconds added to the timeout by `ExtendTimeout`s
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct StepResult {
+    pub name: String,
+    pub command: String,
+    pub started_at: i64,   // Milliseconds since epoch
+    pub completed_at: i64, // Milliseconds since epoch
+    pub return_code: i32,
+    pub outcome: JobOutCome,
+    pub output: String,
+    pub truncated: bool,
+    pub artifact: Option<String>,
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct JobProgress {
+    pub job_name: String,
+    pub agent_name: String,
+    pub run_id: Option<String>, // Echoed from the `DispatchJob`
+    pub elapsed: u32,           // Seconds the run has taken so far
+    pub timeout: u32,           // Seconds after which the agent kills the run
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct CancelJob {
+    pub job_name: String,
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct ExtendTimeout {
+    pub job_name: String,
+    pub seconds: u32, // Added to the running job's timeout
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct UpdateAgent {
+    pub version: String,
+    pub url: String,
+    pub checksum: String, // Hex encoded SHA-256 of the binary at `url`
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct ReverseDispatch {
+    pub agent_name: String,
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct PollWork {
+    pub agent_name: String,
+    pub max_jobs: u32, // Most runs central command hands out in answer to this poll
+}
+
+/// Proof of an agent's identity. `Debug` leaves the secret out so it never reaches the logs.
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub enum Credential {
+    Token(String),   // Shared token, see `AGENT_AUTH_TOKEN`
+    JwtSvid(String), // SPIFFE JWT-SVID
+}
+
+impl std::fmt::Debug for Credential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credential::Token(_) => write!(f, "Token(..)"),
+            Credential::JwtSvid(_) => write!(f, "JwtSvid(..)"),
+        }
+    }
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct Authenticate {
+    pub agent_name: String,
+    pub credential: Credential,
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct Envelope {
+    pub id: u64,          // Chosen by the sender, echoed in the response
+    pub payload: Vec<u8>, // A serialized `Message`, empty in responses
+}
+
+impl Envelope {
+    /// Wraps `message` in a request with `id`.
+    pub fn request(id: u64, message: &Message) -> Result<Self, Error> {
+        Ok(Envelope {
+            id,
+            payload: message.clone().try_into()?,
+        })
+    }
+
+    /// Acknowledges the request with `id`.
+    pub fn response(id: u64) -> Self {
+        Envelope {
+            id,
+            payload: vec![],
+        }
+    }
+
+    /// The message carried by a request, or `None` for a response.
+    pub fn message(&self) -> Result<Option<Message>, Error> {
+        match self.payload.is_empty() {
+            true => Ok(None),
+            false => Message::try_from(self.payload.clone()).map(Some),
+        }
+    }
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct OpenShell {
+    pub session_id: String,
+    pub agent_name: String,
+    pub user: String, // Operator the shell was opened for
+    pub cols: u16,
+    pub rows: u16,
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct ShellData {
+    pub data: Vec<u8>, // Raw terminal bytes
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct ResizeShell {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+#[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct CloseShell {
+    pub reason: String, // e.g. "exited with 0" or why the shell could not be opened
 }
 
 #[derive(Archive, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
@@ -112,8 +471,31 @@ pub enum Message {
     RegisterAgent(RegisterAgent),
     DispatchJob(DispatchJob),
     JobComplete(JobComplete), // Job Name
+    CancelJob(CancelJob),
+    UpdateAgent(UpdateAgent),
+    ReverseDispatch(ReverseDispatch),
+    Authenticate(Authenticate),
+    JobProgress(JobProgress),
+    ExtendTimeout(ExtendTimeout),
+    OpenShell(OpenShell),
+    ShellData(ShellData),
+    ResizeShell(ResizeShell),
+    CloseShell(CloseShell),
+    FileChunk(FileChunk),
+    Envelope(Envelope),
+    PollWork(PollWork),
+    DispatchBatch(Vec<DispatchJob>),
+}
+
+/// Which lane a message is sent in. Control messages are small and time sensitive, so they are
+/// written ahead of bulk payloads waiting for the same connection (see `priority`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Control,
+    Bulk,
 }
 
+#[derive(Debug)]
 pub enum MessageError {
     SerializationError(Error),
     WriteError(tokio::io::Error),
@@ -130,14 +512,170 @@ impl std::fmt::Display for MessageError {
     }
 }
 
+impl std::error::Error for MessageError {}
+
 impl Message {
-    pub async fn tcp_write(self, stream: &mut TcpStream) -> Result<(), MessageError> {
+    /// Writes the serialized message to `stream`, returning the number of bytes written.
+    pub async fn tcp_write(self, stream: &mut TcpStream) -> Result<usize, MessageError> {
         let message: Vec<u8> = self.try_into().map_err(MessageError::SerializationError)?;
         stream
             .write_all(&message)
             .await
             .map_err(MessageError::WriteError)?;
-        Ok(())
+        Ok(message.len())
+    }
+
+    /// Name of the message variant, used for logging and connection metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Ping => "Ping",
+            Message::RegisterAgent(_) => "RegisterAgent",
+            Message::DispatchJob(_) => "DispatchJob",
+            Message::JobComplete(_) => "JobComplete",
+            Message::CancelJob(_) => "CancelJob",
+            Message::UpdateAgent(_) => "UpdateAgent",
+            Message::ReverseDispatch(_) => "ReverseDispatch",
+            Message::Authenticate(_) => "Authenticate",
+            Message::JobProgress(_) => "JobProgress",
+            Message::ExtendTimeout(_) => "ExtendTimeout",
+            Message::OpenShell(_) => "OpenShell",
+            Message::ShellData(_) => "ShellData",
+            Message::ResizeShell(_) => "ResizeShell",
+            Message::CloseShell(_) => "CloseShell",
+            Message::FileChunk(_) => "FileChunk",
+            Message::Envelope(_) => "Envelope",
+            Message::PollWork(_) => "PollWork",
+            Message::DispatchBatch(_) => "DispatchBatch",
+        }
+    }
+
+    pub fn priority(&self) -> Priority {
+        match self {
+            Message::Ping
+            | Message::CancelJob(_)
+            | Message::ReverseDispatch(_)
+            | Message::PollWork(_)
+            | Message::Authenticate(_)
+            | Message::JobProgress(_)
+            | Message::ExtendTimeout(_)
+            | Message::OpenShell(_)
+            | Message::ShellData(_)
+            | Message::ResizeShell(_)
+            | Message::CloseShell(_) => Priority::Control,
+            // Responses are acknowledgments; requests are sent in their message's lane.
+            Message::Envelope(envelope) if envelope.payload.is_empty() => Priority::Control,
+            Message::RegisterAgent(_)
+            | Message::DispatchJob(_)
+            | Message::DispatchBatch(_)
+            | Message::JobComplete(_)
+            | Message::UpdateAgent(_)
+            | Message::FileChunk(_)
+            | Message::Envelope(_) => Priority::Bulk,
+        }
+    }
+
+    pub fn to_frame(self) -> Result<Vec<u8>, MessageError> {
+        let message: Vec<u8> = self.try_into().map_err(MessageError::SerializationError)?;
+        Ok(framing::encode_frame(&message))
+    }
+
+    pub fn to_legacy_frame(self) -> Result<Vec<u8>, MessageError> {
+        let message: Vec<u8> = self.try_into().map_err(MessageError::SerializationError)?;
+        Ok(framing::encode_legacy_frame(&message))
+    }
+}
+
+impl From<&ArchivedContainerInfo> for ContainerInfo {
+    fn from(archived: &ArchivedContainerInfo) -> Self {
+        ContainerInfo {
+            runtime: archived.runtime.to_string(),
+            node_name: archived.node_name.as_ref().map(|name| name.to_string()),
+            pod_name: archived.pod_name.as_ref().map(|name| name.to_string()),
+            pod_namespace: archived
+                .pod_namespace
+                .as_ref()
+                .map(|namespace| namespace.to_string()),
+            node_labels: archived
+                .node_labels
+                .iter()
+                .map(|label| label.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl From<&ArchivedJobStep> for JobStep {
+    fn from(archived: &ArchivedJobStep) -> Self {
+        JobStep {
+            name: archived.name.to_string(),
+            command: archived.command.to_string(),
+            args: archived.args.to_string(),
+            cwd: archived.cwd.as_ref().map(|cwd| cwd.to_string()),
+            env: archived.env.iter().map(|var| var.to_string()).collect(),
+            continue_on_error: archived.continue_on_error,
+        }
+    }
+}
+
+impl From<&ArchivedStepResult> for StepResult {
+    fn from(archived: &ArchivedStepResult) -> Self {
+        StepResult {
+            name: archived.name.to_string(),
+            command: archived.command.to_string(),
+            started_at: archived.started_at.into(),
+            completed_at: archived.completed_at.into(),
+            return_code: archived.return_code.into(),
+            outcome: (&archived.outcome).into(),
+            output: archived.output.to_string(),
+            truncated: archived.truncated,
+            artifact: archived.artifact.as_ref().map(|path| path.to_string()),
+        }
+    }
+}
+
+impl From<&ArchivedDispatchJob> for DispatchJob {
+    fn from(archived: &ArchivedDispatchJob) -> Self {
+        DispatchJob {
+            job_name: archived.job_name.to_string(),
+            command: archived.command.to_string(),
+            args: archived.args.to_string(),
+            valid_return_codes: archived
+                .valid_return_codes
+                .as_ref()
+                .map(|v| v.iter().map(|&x| x.into()).collect()),
+            timeout: archived.timeout.as_ref().map(|&t| t.into()),
+            triggered_by: (&archived.triggered_by).into(),
+            agent_name: match &archived.agent_name {
+                ArchivedOption::None => None,
+                ArchivedOption::Some(name) => Some(name.to_string()),
+            },
+            redact_patterns: archived
+                .redact_patterns
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            run_id: archived.run_id.as_ref().map(|run_id| run_id.to_string()),
+            steps: archived.steps.iter().map(Into::into).collect(),
+            files: archived
+                .files
+                .iter()
+                .map(|file| FileDigest {
+                    destination: file.destination.to_string(),
+                    sha256: file.sha256.to_string(),
+                })
+                .collect(),
+            script: archived.script.as_ref().map(|script| JobScript {
+                interpreter: script.interpreter.to_string(),
+                body: script.body.to_string(),
+            }),
+            container: archived.container.as_ref().map(|container| JobContainer {
+                image: container.image.to_string(),
+                mounts: container.mounts.iter().map(|m| m.to_string()).collect(),
+                env: container.env.iter().map(|var| var.to_string()).collect(),
+                workdir: container.workdir.as_ref().map(|dir| dir.to_string()),
+            }),
+            env: archived.env.iter().map(|var| var.to_string()).collect(),
+        }
     }
 }
 
@@ -149,31 +687,38 @@ impl From<&ArchivedMessage> for Message {
                 let name = archived.name.to_string();
                 let hostname = archived.hostname.to_string();
                 let port = archived.port.into();
+                let version = archived.version.to_string();
+                let timezone = archived.timezone.to_string();
+                let locale = archived.locale.to_string();
+                let receipt_public_key = archived
+                    .receipt_public_key
+                    .as_ref()
+                    .map(|key| key.to_string());
                 Message::RegisterAgent(RegisterAgent {
                     name,
                     hostname,
                     port,
+                    version,
+                    timezone,
+                    locale,
+                    receipt_public_key,
+                    os: archived.os.to_string(),
+                    arch: archived.arch.to_string(),
+                    kernel: archived.kernel.to_string(),
+                    shells: archived
+                        .shells
+                        .iter()
+                        .map(|shell| shell.to_string())
+                        .collect(),
+                    features: archived
+                        .features
+                        .iter()
+                        .map(|feature| feature.to_string())
+                        .collect(),
+                    container: archived.container.as_ref().map(ContainerInfo::from),
                 })
             }
-            ArchivedMessage::DispatchJob(archived) => {
-                let job_name = archived.job_name.to_string();
-                let job_command = archived.command.to_string();
-                let job_args = archived.args.to_string();
-                let agent_name = match &archived.agent_name {
-                    ArchivedOption::None => None,
-                    ArchivedOption::Some(name) => Some(name.to_string()),
-                };
-                Message::DispatchJob(DispatchJob {
-                    job_name: job_name.to_string(),
-                    command: job_command,
-                    args: job_args.to_string(),
-                    valid_return_codes: archived
-                        .valid_return_codes
-                        .as_ref()
-                        .map(|v| v.iter().map(|&x| x.into()).collect()),
-                    agent_name,
-                })
-            }
+            ArchivedMessage::DispatchJob(archived) => Message::DispatchJob(archived.into()),
             ArchivedMessage::JobComplete(archived) => {
                 let job_name = archived.job_name.to_string();
                 let agent_name = archived.agent_name.to_string();
@@ -189,8 +734,86 @@ impl From<&ArchivedMessage> for Message {
                     outcome: outcome.into(),
                     command,
                     output,
+                    triggered_by: (&archived.triggered_by).into(),
+                    truncated: archived.truncated,
+                    artifact: archived.artifact.as_ref().map(|path| path.to_string()),
+                    signature: archived
+                        .signature
+                        .as_ref()
+                        .map(|signature| signature.to_string()),
+                    run_id: archived.run_id.as_ref().map(|run_id| run_id.to_string()),
+                    steps: archived.steps.iter().map(Into::into).collect(),
+                    timeout_extension: archived.timeout_extension.into(),
+                })
+            }
+            ArchivedMessage::CancelJob(archived) => Message::CancelJob(CancelJob {
+                job_name: archived.job_name.to_string(),
+            }),
+            ArchivedMessage::UpdateAgent(archived) => Message::UpdateAgent(UpdateAgent {
+                version: archived.version.to_string(),
+                url: archived.url.to_string(),
+                checksum: archived.checksum.to_string(),
+            }),
+            ArchivedMessage::ReverseDispatch(archived) => {
+                Message::ReverseDispatch(ReverseDispatch {
+                    agent_name: archived.agent_name.to_string(),
                 })
             }
+            ArchivedMessage::Authenticate(archived) => Message::Authenticate(Authenticate {
+                agent_name: archived.agent_name.to_string(),
+                credential: match &archived.credential {
+                    ArchivedCredential::Token(token) => Credential::Token(token.to_string()),
+                    ArchivedCredential::JwtSvid(svid) => Credential::JwtSvid(svid.to_string()),
+                },
+            }),
+            ArchivedMessage::JobProgress(archived) => Message::JobProgress(JobProgress {
+                job_name: archived.job_name.to_string(),
+                agent_name: archived.agent_name.to_string(),
+                run_id: archived.run_id.as_ref().map(|run_id| run_id.to_string()),
+                elapsed: archived.elapsed.into(),
+                timeout: archived.timeout.into(),
+            }),
+            ArchivedMessage::ExtendTimeout(archived) => Message::ExtendTimeout(ExtendTimeout {
+                job_name: archived.job_name.to_string(),
+                seconds: archived.seconds.into(),
+            }),
+            ArchivedMessage::OpenShell(archived) => Message::OpenShell(OpenShell {
+                session_id: archived.session_id.to_string(),
+                agent_name: archived.agent_name.to_string(),
+                user: archived.user.to_string(),
+                cols: archived.cols.into(),
+                rows: archived.rows.into(),
+            }),
+            ArchivedMessage::ShellData(archived) => Message::ShellData(ShellData {
+                data: archived.data.to_vec(),
+            }),
+            ArchivedMessage::ResizeShell(archived) => Message::ResizeShell(ResizeShell {
+                cols: archived.cols.into(),
+                rows: archived.rows.into(),
+            }),
+            ArchivedMessage::CloseShell(archived) => Message::CloseShell(CloseShell {
+                reason: archived.reason.to_string(),
+            }),
+            ArchivedMessage::FileChunk(archived) => Message::FileChunk(FileChunk {
+                job_name: archived.job_name.to_string(),
+                destination: archived.destination.to_string(),
+                mode: archived.mode.as_ref().map(|&mode| mode.into()),
+                size: archived.size.into(),
+                sha256: archived.sha256.to_string(),
+                offset: archived.offset.into(),
+                data: archived.data.to_vec(),
+            }),
+            ArchivedMessage::Envelope(archived) => Message::Envelope(Envelope {
+                id: archived.id.into(),
+                payload: archived.payload.to_vec(),
+            }),
+            ArchivedMessage::PollWork(archived) => Message::PollWork(PollWork {
+                agent_name: archived.agent_name.to_string(),
+                max_jobs: archived.max_jobs.into(),
+            }),
+            ArchivedMessage::DispatchBatch(archived) => {
+                Message::DispatchBatch(archived.iter().map(Into::into).collect())
+            }
         }
     }
 }
@@ -208,7 +831,70 @@ impl TryFrom<Vec<u8>> for Message {
     type Error = Error;
 
     fn try_from(bytes: Vec<u8>) -> Result<Self, Error> {
-        let archived = rkyv::access::<ArchivedMessage, Error>(&bytes)?;
-        Ok(archived.into())
+        Ok(access_message(&bytes)?.into())
+    }
+}
+
+/// Validates `bytes` as a serialized `Message` and reads it in place, without copying it or
+/// deserializing it. `bytes` must be aligned for `ArchivedMessage`, as those of a `Vec<u8>` or a
+/// `MessageBuffer` are; misaligned bytes fail validation.
+pub fn access_message(bytes: &[u8]) -> Result<&ArchivedMessage, Error> {
+    rkyv::access::<ArchivedMessage, Error>(bytes)
+}
+
+/// A buffer the messages of one connection are read into and read from in place, so receiving
+/// a message allocates nothing once the buffer has grown to fit the largest one.
+#[derive(Debug, Default)]
+pub struct MessageBuffer {
+    bytes: AlignedVec,
+}
+
+impl MessageBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The first `len` bytes of the buffer, to read a message into, growing it if needed.
+    pub fn slice_mut(&mut self, len: usize) -> &mut [u8] {
+        if self.bytes.len() < len {
+            self.bytes.resize(len, 0);
+        }
+        &mut self.bytes[..len]
+    }
+
+    /// The first `len` bytes of the buffer.
+    pub fn bytes(&self, len: usize) -> &[u8] {
+        &self.bytes[..len]
+    }
+
+    /// The message in the first `len` bytes of the buffer, validated and read in place.
+    pub fn message(&self, len: usize) -> Result<&ArchivedMessage, Error> {
+        access_message(&self.bytes[..len])
+    }
+}
+
+impl ArchivedMessage {
+    /// Name of the message variant, as `Message::kind` names it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ArchivedMessage::Ping => "Ping",
+            ArchivedMessage::RegisterAgent(_) => "RegisterAgent",
+            ArchivedMessage::DispatchJob(_) => "DispatchJob",
+            ArchivedMessage::JobComplete(_) => "JobComplete",
+            ArchivedMessage::CancelJob(_) => "CancelJob",
+            ArchivedMessage::UpdateAgent(_) => "UpdateAgent",
+            ArchivedMessage::ReverseDispatch(_) => "ReverseDispatch",
+            ArchivedMessage::Authenticate(_) => "Authenticate",
+            ArchivedMessage::JobProgress(_) => "JobProgress",
+            ArchivedMessage::ExtendTimeout(_) => "ExtendTimeout",
+            ArchivedMessage::OpenShell(_) => "OpenShell",
+            ArchivedMessage::ShellData(_) => "ShellData",
+            ArchivedMessage::ResizeShell(_) => "ResizeShell",
+            ArchivedMessage::CloseShell(_) => "CloseShell",
+            ArchivedMessage::FileChunk(_) => "FileChunk",
+            ArchivedMessage::Envelope(_) => "Envelope",
+            ArchivedMessage::PollWork(_) => "PollWork",
+            ArchivedMessage::DispatchBatch(_) => "DispatchBatch",
+        }
     }
 }
@@ -0,0 +1,278 @@
+//! This module extracts structured results from job output: a status and named values such as
+//! `records_processed`, which central command stores on each run (see `RunsV1::parsed`) so
+//! dashboards can chart them instead of reading output.
+//!
+//! # Parsers
+//!
+//! Jobs list their parsers in `output_parsers`:
+//! - `regex`: A regular expression matched against the whole output. Each named capture group
+//!   becomes a value, from the last match that set it, e.g. `records_processed: (?P<records>\d+)`.
+//! - `json`: The last line of the output that is a JSON object. Each entry of `fields` names a
+//!   value and the JSON pointer it is read from, e.g. `{"records": "/stats/records"}`.
+//!
+//! Values named `status` become the run's status, which the job's `success_statuses` can turn
+//! into a failure. Numeric values become metrics, the others fields. Later parsers overwrite the
+//! values of earlier ones.
+//!
+//! # Example
+//!
+//! ```rust
+//! use core_logic::output_parsing::{self, OutputParser};
+//!
+//! let parsers = vec![OutputParser::Regex {
+//!     pattern: r"records_processed: (?P<records>\d+), status: (?P<status>\w+)".to_string(),
+//! }];
+//! let parsed = output_parsing::parse(&parsers, "records_processed: 1234, status: partial").unwrap();
+//! assert_eq!(parsed.status.as_deref(), Some("partial"));
+//! assert_eq!(parsed.metrics["records"], 1234.0);
+//! ```
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+
+/// The value that becomes a run's status.
+pub const STATUS_FIELD: &str = "status";
+
+/// Largest compiled size of a parser's regular expression, in bytes.
+const MAX_PATTERN_SIZE: usize = 1024 * 1024;
+
+/// How a job's output is parsed into values.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OutputParser {
+    /// A regular expression whose named capture groups become values.
+    Regex { pattern: String },
+    /// Values read by JSON pointer from the last line of the output that is a JSON object.
+    Json { fields: BTreeMap<String, String> },
+}
+
+impl OutputParser {
+    /// The names of the values the parser extracts.
+    pub fn names(&self) -> Result<Vec<String>, String> {
+        match self {
+            OutputParser::Regex { pattern } => {
+                let regex = compile(pattern)
+                    .map_err(|e| format!("Invalid output parser {}: {}", pattern, e))?;
+                Ok(regex.capture_names().flatten().map(String::from).collect())
+            }
+            OutputParser::Json { fields } => Ok(fields.keys().cloned().collect()),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputParser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputParser::Regex { pattern } => write!(f, "regex {}", pattern),
+            OutputParser::Json { fields } => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, pointer)| format!("{}={}", name, pointer))
+                    .collect();
+                write!(f, "json {}", fields.join(", "))
+            }
+        }
+    }
+}
+
+/// What the parsers of a job extracted from a run's output.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParsedOutput {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// Numeric values, e.g. `records_processed`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, f64>,
+    /// The other values.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+}
+
+impl ParsedOutput {
+    fn is_empty(&self) -> bool {
+        self.status.is_none() && self.metrics.is_empty() && self.fields.is_empty()
+    }
+
+    fn insert(&mut self, name: &str, value: String) {
+        if name == STATUS_FIELD {
+            self.status = Some(value);
+            return;
+        }
+        self.fields.remove(name);
+        self.metrics.remove(name);
+        match value.trim().parse::<f64>() {
+            Ok(number) if number.is_finite() => {
+                self.metrics.insert(name.to_string(), number);
+            }
+            _ => {
+                self.fields.insert(name.to_string(), value);
+            }
+        }
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .size_limit(MAX_PATTERN_SIZE)
+        .build()
+}
+
+/// Checks that regex parsers compile and have named groups, that JSON parsers have fields read
+/// from JSON pointers, and that values have names that can be stored, e.g. `records_processed`.
+pub fn validate_parsers(parsers: &[OutputParser]) -> Result<(), String> {
+    let valid_name = |name: &str| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    for parser in parsers {
+        if let OutputParser::Json { fields } = parser
+            && let Some((name, pointer)) = fields
+                .iter()
+                .find(|(_, pointer)| !pointer.is_empty() && !pointer.starts_with('/'))
+        {
+            return Err(format!(
+                "Invalid JSON pointer {} of output field {}",
+                pointer, name
+            ));
+        }
+        let names = parser.names()?;
+        if names.is_empty() {
+            return Err(format!("Output parser {} extracts no named values", parser));
+        }
+        if let Some(name) = names.iter().find(|name| !valid_name(name)) {
+            return Err(format!(
+                "Invalid output field name {}, use letters, digits and _",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The values `parsers` extract from `output`, or `None` when they extract nothing.
+pub fn parse(parsers: &[OutputParser], output: &str) -> Option<ParsedOutput> {
+    let mut parsed = ParsedOutput::default();
+    for parser in parsers {
+        match parser {
+            OutputParser::Regex { pattern } => {
+                // Jobs are validated when saved, so an invalid pattern extracts nothing.
+                let Ok(regex) = compile(pattern) else {
+                    continue;
+                };
+                for captures in regex.captures_iter(output) {
+                    for name in regex.capture_names().flatten() {
+                        if let Some(value) = captures.name(name) {
+                            parsed.insert(name, value.as_str().to_string());
+                        }
+                    }
+                }
+            }
+            OutputParser::Json { fields } => {
+                let Some(object) = output.lines().rev().find_map(|line| {
+                    serde_json::from_str::<Value>(line.trim())
+                        .ok()
+                        .filter(Value::is_object)
+                }) else {
+                    continue;
+                };
+                for (name, pointer) in fields {
+                    let value = match object.pointer(pointer) {
+                        None | Some(Value::Null) => continue,
+                        Some(Value::String(value)) => value.clone(),
+                        Some(value) => value.to_string(),
+                    };
+                    parsed.insert(name, value);
+                }
+            }
+        }
+    }
+    (!parsed.is_empty()).then_some(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regex(pattern: &str) -> OutputParser {
+        OutputParser::Regex {
+            pattern: pattern.to_string(),
+        }
+    }
+
+    fn json(fields: &[(&str, &str)]) -> OutputParser {
+        OutputParser::Json {
+            fields: fields
+                .iter()
+                .map(|(name, pointer)| (name.to_string(), pointer.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn regex_keeps_the_last_match_of_each_group() {
+        let parsers = [regex(r"(?:records: (?P<records>\d+)|host: (?P<host>\S+))")];
+        let parsed = parse(&parsers, "records: 1\nhost: db1\nrecords: 2\n").unwrap();
+        assert_eq!(parsed.metrics["records"], 2.0);
+        assert_eq!(parsed.fields["host"], "db1");
+    }
+
+    #[test]
+    fn non_finite_numbers_are_fields_and_status_is_never_a_metric() {
+        let parsers = [regex(r"status: (?P<status>\S+) rate: (?P<rate>\S+)")];
+        let parsed = parse(&parsers, "status: 200 rate: NaN").unwrap();
+        assert_eq!(parsed.status.as_deref(), Some("200"));
+        assert_eq!(parsed.fields["rate"], "NaN");
+        assert!(parsed.metrics.is_empty());
+    }
+
+    #[test]
+    fn json_reads_the_last_object_line() {
+        let parsers = [json(&[
+            ("records", "/stats/records"),
+            ("ok", "/ok"),
+            ("missing", "/none"),
+            ("empty", "/empty"),
+        ])];
+        let output = concat!(
+            "{\"stats\": {\"records\": 1}}\n",
+            "  {\"stats\": {\"records\": 5}, \"ok\": true, \"empty\": null}  \n",
+            "[1, 2]\ndone\n",
+        );
+        let parsed = parse(&parsers, output).unwrap();
+        assert_eq!(parsed.metrics["records"], 5.0);
+        assert_eq!(parsed.fields["ok"], "true");
+        assert!(!parsed.fields.contains_key("missing"));
+        assert!(!parsed.fields.contains_key("empty"));
+    }
+
+    #[test]
+    fn later_parsers_overwrite_earlier_ones() {
+        let parsers = [
+            regex(r"count: (?P<count>\d+)"),
+            json(&[("count", "/count")]),
+        ];
+        let parsed = parse(&parsers, "count: 3\n{\"count\": \"many\"}").unwrap();
+        assert_eq!(parsed.fields["count"], "many");
+        assert!(!parsed.metrics.contains_key("count"));
+    }
+
+    #[test]
+    fn nothing_extracted_is_none() {
+        assert_eq!(parse(&[], "records: 1"), None);
+        assert_eq!(parse(&[regex(r"records: (?P<records>\d+)")], "no"), None);
+        assert_eq!(parse(&[json(&[("a", "/a")])], "not json"), None);
+        assert_eq!(parse(&[regex(r"(?P<broken")], "anything"), None);
+    }
+
+    #[test]
+    fn validate_parsers_rejects_unusable_parsers() {
+        assert!(validate_parsers(&[regex(r"records: (?P<records>\d+)")]).is_ok());
+        assert!(validate_parsers(&[json(&[("records", "/records"), ("all", "")])]).is_ok());
+        assert!(validate_parsers(&[regex(r"(?P<broken")]).is_err());
+        assert!(validate_parsers(&[regex(r"records: (\d+)")]).is_err());
+        assert!(validate_parsers(&[json(&[])]).is_err());
+        assert!(validate_parsers(&[json(&[("records", "records")])]).is_err());
+        assert!(validate_parsers(&[json(&[("records.count", "/records")])]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! This module keeps control messages from being starved by bulk payloads on a shared connection.
+//!
+//! A connection carries both small control messages (`Ping`, `CancelJob`, acknowledgments) and
+//! potentially large payloads (`JobComplete` with its output, `DispatchJob`). Written in arrival
+//! order, a cancellation or heartbeat waits behind every payload queued ahead of it. Each
+//! `Message` has a `Priority` (see `Message::priority`) and the types here let control traffic
+//! overtake bulk traffic that has not started yet. A frame already being written is always
+//! finished first, as frames are not interleaved on the wire.
+//!
+//! # Types
+//!
+//! - `PriorityLock`: Guards a connection's writer. Control writers are let in ahead of waiting
+//!   bulk writers.
+//! - `PriorityReceiver`: Wraps the channel feeding a connection, yielding queued control messages
+//!   before queued bulk ones.
+//!
+//! # Example
+//!
+//! ```rust
+//! use core_logic::messages::{Message, Priority};
+//! use core_logic::priority::{PriorityLock, PriorityReceiver};
+//!
+//! # async fn example() {
+//! let writer = PriorityLock::new(Vec::<u8>::new());
+//! writer.lock(Priority::Control).await.extend_from_slice(b"OK");
+//!
+//! let (sender, receiver) = tokio::sync::mpsc::channel(16);
+//! let mut receiver = PriorityReceiver::new(receiver);
+//! sender.send(Message::Ping).await.unwrap();
+//! assert_eq!(receiver.recv().await, Some(Message::Ping));
+//! # }
+//! ```
+use tokio::sync::{Mutex, MutexGuard, Notify, mpsc};
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::messages::{Message, Priority};
+
+/// A mutex whose `Priority::Control` waiters acquire it before any `Priority::Bulk` waiter.
+#[derive(Debug)]
+pub struct PriorityLock<T> {
+    inner: Mutex<T>,
+    control_waiting: AtomicUsize,
+    control_done: Notify,
+}
+
+/// Counts a control waiter for as long as it waits, including when its `lock` is cancelled.
+struct ControlWaiter<'a, T>(&'a PriorityLock<T>);
+
+impl<T> Drop for ControlWaiter<'_, T> {
+    fn drop(&mut self) {
+        if self.0.control_waiting.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.control_done.notify_waiters();
+        }
+    }
+}
+
+impl<T> PriorityLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+            control_waiting: AtomicUsize::new(0),
+            control_done: Notify::new(),
+        }
+    }
+
+    pub async fn lock(&self, priority: Priority) -> MutexGuard<'_, T> {
+        match priority {
+            Priority::Control => {
+                self.control_waiting.fetch_add(1, Ordering::SeqCst);
+                let _waiter = ControlWaiter(self);
+                self.inner.lock().await
+            }
+            Priority::Bulk => loop {
+                let control_done = self.control_done.notified();
+                tokio::pin!(control_done);
+                control_done.as_mut().enable();
+                if self.control_waiting.load(Ordering::SeqCst) > 0 {
+                    control_done.await;
+                    continue;
+                }
+                let guard = self.inner.lock().await;
+                if self.control_waiting.load(Ordering::SeqCst) == 0 {
+                    return guard;
+                }
+                // A control waiter arrived while this one was queued, let it go first.
+                drop(guard);
+            },
+        }
+    }
+}
+
+/// Receives messages from a channel, control messages first. At most the channel's capacity is
+/// held back, so a slow connection still pushes back on its senders.
+#[derive(Debug)]
+pub struct PriorityReceiver {
+    receiver: mpsc::Receiver<Message>,
+    control: VecDeque<Message>,
+    bulk: VecDeque<Message>,
+}
+
+impl PriorityReceiver {
+    pub fn new(receiver: mpsc::Receiver<Message>) -> Self {
+        Self {
+            receiver,
+            control: VecDeque::new(),
+            bulk: VecDeque::new(),
+        }
+    }
+
+    /// The next message, or `None` once the channel is closed and everything has been received.
+    pub async fn recv(&mut self) -> Option<Message> {
+        while self.control.len() + self.bulk.len() < self.receiver.max_capacity() {
+            match self.receiver.try_recv() {
+                Ok(message) => match message.priority() {
+                    Priority::Control => self.control.push_back(message),
+                    Priority::Bulk => self.bulk.push_back(message),
+                },
+                Err(_) => break,
+            }
+        }
+        match self.control.pop_front().or_else(|| self.bulk.pop_front()) {
+            Some(message) => Some(message),
+            None => self.receiver.recv().await,
+        }
+    }
+}
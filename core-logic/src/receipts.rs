@@ -0,0 +1,274 @@
+//! This module provides job execution receipts. Agents sign a digest of every `JobComplete` with
+//! a per-agent Ed25519 key, so central command can check that a run result was reported by the
+//! agent it names and was not altered in transit. The receipt is stored with the run, so the
+//! result can be checked again later against what is in the `runs` collection.
+//!
+//! # Digest
+//!
+//! SHA-256 over the job name, agent name, command, start and completion times, return code,
+//! outcome, output, truncation flag and artifact path, each length-prefixed so that field
+//! boundaries cannot be shifted, followed by the same fields of each step of a multi-step job.
+//! Single command jobs have no steps, so their digest is unchanged. `triggered_by` is left out
+//! because central command replaces it with the provenance recorded on the job.
+//!
+//! # Keys
+//!
+//! Each agent keeps its key pair in a PKCS#8 file, generated on first start, and sends the hex
+//! encoded public key when it registers. Central command trusts the first key it sees for an
+//! agent (`AgentV1.receipt_public_key`) until an operator clears it.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use core_logic::receipts::{ReceiptPayload, ReceiptSigner, verify};
+//! # fn example(job_complete: &core_logic::messages::JobComplete) {
+//! let signer = ReceiptSigner::load_or_generate("receipt_key.pk8".as_ref()).unwrap();
+//! let payload = ReceiptPayload::from(job_complete);
+//! let signature = signer.sign(&payload);
+//! assert!(verify(&signer.public_key(), &payload, &signature));
+//! # }
+//! ```
+use ring::rand::SystemRandom;
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use sha2::{Digest, Sha256};
+
+use std::path::Path;
+
+use crate::datastore::runs::{RunStep, RunsV1};
+use crate::messages::{JobComplete, StepResult};
+
+#[derive(Debug)]
+pub struct ReceiptError {
+    pub path: String,
+    pub error: String,
+}
+
+impl std::fmt::Display for ReceiptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid receipt key {}: {}", self.path, self.error)
+    }
+}
+
+impl std::error::Error for ReceiptError {}
+
+/// The fields of a run result covered by its receipt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiptPayload<'a> {
+    pub job_name: &'a str,
+    pub agent_name: &'a str,
+    pub command: &'a str,
+    pub started_at: i64,   // Milliseconds since epoch
+    pub completed_at: i64, // Milliseconds since epoch
+    pub return_code: i32,
+    pub outcome: i32,
+    pub output: &'a str,
+    pub truncated: bool,
+    pub artifact: Option<&'a str>,
+    pub steps: Vec<ReceiptStep<'a>>,
+}
+
+/// The fields of one step's result covered by the receipt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiptStep<'a> {
+    pub name: &'a str,
+    pub command: &'a str,
+    pub started_at: i64,   // Milliseconds since epoch
+    pub completed_at: i64, // Milliseconds since epoch
+    pub return_code: i32,
+    pub outcome: i32,
+    pub output: &'a str,
+    pub truncated: bool,
+    pub artifact: Option<&'a str>,
+}
+
+impl ReceiptPayload<'_> {
+    /// SHA-256 digest of the payload, which is what agents sign.
+    pub fn digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        for field in [self.job_name, self.agent_name, self.command] {
+            update_str(&mut hasher, field);
+        }
+        update_result(
+            &mut hasher,
+            (self.started_at, self.completed_at),
+            self.return_code,
+            self.outcome,
+            self.output,
+            self.truncated,
+            self.artifact,
+        );
+        if !self.steps.is_empty() {
+            hasher.update((self.steps.len() as u64).to_be_bytes());
+        }
+        for step in &self.steps {
+            update_str(&mut hasher, step.name);
+            update_str(&mut hasher, step.command);
+            update_result(
+                &mut hasher,
+                (step.started_at, step.completed_at),
+                step.return_code,
+                step.outcome,
+                step.output,
+                step.truncated,
+                step.artifact,
+            );
+        }
+        hasher.finalize().into()
+    }
+}
+
+fn update_str(hasher: &mut Sha256, field: &str) {
+    hasher.update((field.len() as u64).to_be_bytes());
+    hasher.update(field.as_bytes());
+}
+
+/// Adds the fields shared by a run and each of its steps.
+fn update_result(
+    hasher: &mut Sha256,
+    (started_at, completed_at): (i64, i64),
+    return_code: i32,
+    outcome: i32,
+    output: &str,
+    truncated: bool,
+    artifact: Option<&str>,
+) {
+    hasher.update(started_at.to_be_bytes());
+    hasher.update(completed_at.to_be_bytes());
+    hasher.update(return_code.to_be_bytes());
+    hasher.update(outcome.to_be_bytes());
+    update_str(hasher, output);
+    hasher.update([truncated as u8]);
+    match artifact {
+        Some(artifact) => {
+            hasher.update([1]);
+            update_str(hasher, artifact);
+        }
+        None => hasher.update([0]),
+    }
+}
+
+impl<'a> From<&'a JobComplete> for ReceiptPayload<'a> {
+    fn from(job_complete: &'a JobComplete) -> Self {
+        Self {
+            job_name: &job_complete.job_name,
+            agent_name: &job_complete.agent_name,
+            command: &job_complete.command,
+            started_at: job_complete.started_at,
+            completed_at: job_complete.completed_at,
+            return_code: job_complete.return_code,
+            outcome: job_complete.outcome.clone().into(),
+            output: &job_complete.output,
+            truncated: job_complete.truncated,
+            artifact: job_complete.artifact.as_deref(),
+            steps: job_complete.steps.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<'a> From<&'a StepResult> for ReceiptStep<'a> {
+    fn from(step: &'a StepResult) -> Self {
+        Self {
+            name: &step.name,
+            command: &step.command,
+            started_at: step.started_at,
+            completed_at: step.completed_at,
+            return_code: step.return_code,
+            outcome: step.outcome.clone().into(),
+            output: &step.output,
+            truncated: step.truncated,
+            artifact: step.artifact.as_deref(),
+        }
+    }
+}
+
+impl<'a> From<&'a RunsV1> for ReceiptPayload<'a> {
+    fn from(run: &'a RunsV1) -> Self {
+        Self {
+            job_name: &run.job_name,
+            agent_name: &run.agent_name,
+            command: &run.command,
+            started_at: run.started_at.timestamp_millis(),
+            completed_at: run.completed_at.timestamp_millis(),
+            return_code: run.return_code,
+            outcome: run.reported_outcome.unwrap_or(run.outcome).into(),
+            output: &run.output,
+            truncated: run.truncated,
+            artifact: run.output_artifact.as_deref(),
+            steps: run.steps.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<'a> From<&'a RunStep> for ReceiptStep<'a> {
+    fn from(step: &'a RunStep) -> Self {
+        Self {
+            name: &step.name,
+            command: &step.command,
+            started_at: step.started_at.timestamp_millis(),
+            completed_at: step.completed_at.timestamp_millis(),
+            return_code: step.return_code,
+            outcome: step.outcome.into(),
+            output: &step.output,
+            truncated: step.truncated,
+            artifact: step.output_artifact.as_deref(),
+        }
+    }
+}
+
+/// An agent's receipt signing key.
+pub struct ReceiptSigner {
+    key_pair: Ed25519KeyPair,
+}
+
+impl ReceiptSigner {
+    /// Loads the PKCS#8 key pair at `path`, generating and saving a new one if it does not exist.
+    pub fn load_or_generate(path: &Path) -> Result<Self, ReceiptError> {
+        let error = |error: String| ReceiptError {
+            path: path.display().to_string(),
+            error,
+        };
+        let pkcs8 = match std::fs::read(path) {
+            Ok(pkcs8) => pkcs8,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                    .map_err(|e| error(e.to_string()))?;
+                write_private(path, pkcs8.as_ref()).map_err(|e| error(e.to_string()))?;
+                pkcs8.as_ref().to_vec()
+            }
+            Err(e) => return Err(error(e.to_string())),
+        };
+        let key_pair = Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|e| error(e.to_string()))?;
+        Ok(Self { key_pair })
+    }
+
+    /// Hex encoded public key, sent to central command on registration.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.key_pair.public_key().as_ref())
+    }
+
+    /// Hex encoded signature of the payload's digest.
+    pub fn sign(&self, payload: &ReceiptPayload) -> String {
+        hex::encode(self.key_pair.sign(&payload.digest()).as_ref())
+    }
+}
+
+/// Writes a new key file that only its owner can read.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
+
+/// Checks a hex encoded `signature` of the payload's digest against a hex encoded public key.
+pub fn verify(public_key: &str, payload: &ReceiptPayload, signature: &str) -> bool {
+    let (Ok(public_key), Ok(signature)) = (hex::decode(public_key), hex::decode(signature)) else {
+        return false;
+    };
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&payload.digest(), &signature)
+        .is_ok()
+}
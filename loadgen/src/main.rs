@@ -0,0 +1,525 @@
+//! `loadgen` load-tests the dispatch pipeline of a running central command, so performance
+//! regressions in its `AgentManager` and `CommandReceiver` are measurable.
+//!
+//! It starts a number of fake agents, which register with central command over TCP like real
+//! agents and advertise `dispatch_batch`, but run nothing: each dispatched run is reported back
+//! as a successful `JobComplete` after `LOADGEN_JOB_DURATION_MS`. Once central command has dialed
+//! every fake agent, `loadgen` inserts one-shot jobs into MongoDB, each targeting one agent, with
+//! their `next_run` spread evenly over `LOADGEN_RAMP_SECONDS`, and waits for all of them to
+//! complete.
+//!
+//! Jobs and agents are named `loadgen-…`; they, and the runs and cycles recorded for them, are
+//! removed before and after each load test.
+//!
+//! # Report
+//! - Dispatch latency: milliseconds from a job's `next_run` to its dispatch reaching the fake
+//!   agent, as the 50th, 90th, 99th percentile and maximum. `next_run` has a resolution of a
+//!   second and jobs are only due once it passed, so expect up to a second of it on an idle
+//!   central command.
+//! - MongoDB operations per second while the jobs ran, by kind, from the server's `opcounters`.
+//!   They include every client of the server, so run against a database nothing else uses.
+//!
+//! Exits non-zero if not every job completed within `LOADGEN_TIMEOUT_SECONDS`.
+//!
+//! # Configuration
+//! - `LOADGEN_CENTRAL_COMMAND_ADDRESS`: Address central command listens on for agents (default:
+//!   `127.0.0.1:8080`).
+//! - `LOADGEN_AGENT_HOST`: Host name central command dials the fake agents at (default:
+//!   `127.0.0.1`).
+//! - `LOADGEN_AGENTS`: Number of fake agents (default: 10).
+//! - `LOADGEN_JOBS`: Number of jobs (default: 100).
+//! - `LOADGEN_RAMP_SECONDS`: Seconds the jobs' `next_run` is spread over (default: 10).
+//! - `LOADGEN_JOB_DURATION_MS`: How long each fake run takes (default: 100).
+//! - `LOADGEN_TIMEOUT_SECONDS`: How long to wait for the agents to be dialed, and then for the
+//!   jobs to complete (default: 120).
+//! - `LOADGEN_AUTH_TOKEN`: Token the fake agents authenticate with, when central command requires
+//!   agents to authenticate (its `AGENT_AUTH` setting).
+//! - `MONGODB_URI`: The database central command uses (default: `mongodb://localhost:27017`).
+//!
+//! # Example
+//!
+//! ```sh
+//! LOADGEN_AGENTS=50 LOADGEN_JOBS=5000 LOADGEN_RAMP_SECONDS=30 cargo run --release -p loadgen
+//! ```
+use bson::{Bson, Document, doc};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, mpsc};
+use tokio::time::{Instant, sleep, timeout};
+use tracing::{debug, error, info, warn};
+
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::io;
+use std::process::ExitCode;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use core_logic::datastore::Datastore;
+use core_logic::datastore::jobs::next_run_datetime;
+use core_logic::logging;
+use core_logic::messages::{
+    ArchivedMessage, Authenticate, Credential, DispatchJob, JobComplete, JobOutCome, Message,
+    MessageBuffer, RegisterAgent,
+};
+
+const DEFAULT_CENTRAL_COMMAND_ADDRESS: &str = "127.0.0.1:8080";
+const DEFAULT_AGENT_HOST: &str = "127.0.0.1";
+const NAME_PREFIX: &str = "loadgen-";
+const MAX_MESSAGE_BYTES: usize = 64 * 1024; // What a real agent's listen port reads at once
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+const OP_COUNTERS: [&str; 6] = ["insert", "query", "update", "delete", "getmore", "command"];
+
+static CENTRAL_COMMAND_ADDRESS: OnceLock<String> = OnceLock::new();
+static AGENT_HOST: OnceLock<String> = OnceLock::new();
+static AGENTS: OnceLock<usize> = OnceLock::new();
+static JOBS: OnceLock<usize> = OnceLock::new();
+static RAMP_SECONDS: OnceLock<u64> = OnceLock::new();
+static JOB_DURATION_MS: OnceLock<u64> = OnceLock::new();
+static TIMEOUT_SECONDS: OnceLock<u64> = OnceLock::new();
+static AUTH_TOKEN: OnceLock<Option<String>> = OnceLock::new();
+
+fn env_number<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid {}", name)),
+        Err(_) => default,
+    }
+}
+
+fn get_central_command_address() -> &'static str {
+    CENTRAL_COMMAND_ADDRESS.get_or_init(|| {
+        env::var("LOADGEN_CENTRAL_COMMAND_ADDRESS")
+            .unwrap_or_else(|_| DEFAULT_CENTRAL_COMMAND_ADDRESS.to_string())
+    })
+}
+
+fn get_agent_host() -> &'static str {
+    AGENT_HOST.get_or_init(|| {
+        env::var("LOADGEN_AGENT_HOST").unwrap_or_else(|_| DEFAULT_AGENT_HOST.to_string())
+    })
+}
+
+fn get_agents() -> usize {
+    *AGENTS.get_or_init(|| env_number("LOADGEN_AGENTS", 10).max(1))
+}
+
+fn get_jobs() -> usize {
+    *JOBS.get_or_init(|| env_number("LOADGEN_JOBS", 100).max(1))
+}
+
+fn get_ramp_seconds() -> u64 {
+    *RAMP_SECONDS.get_or_init(|| env_number("LOADGEN_RAMP_SECONDS", 10))
+}
+
+fn get_job_duration_ms() -> u64 {
+    *JOB_DURATION_MS.get_or_init(|| env_number("LOADGEN_JOB_DURATION_MS", 100))
+}
+
+fn get_timeout() -> Duration {
+    Duration::from_secs(*TIMEOUT_SECONDS.get_or_init(|| env_number("LOADGEN_TIMEOUT_SECONDS", 120)))
+}
+
+fn get_auth_token() -> Option<&'static str> {
+    AUTH_TOKEN
+        .get_or_init(|| {
+            env::var("LOADGEN_AUTH_TOKEN")
+                .ok()
+                .filter(|token| !token.trim().is_empty())
+        })
+        .as_deref()
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+/// What the fake agents observed, shared between them.
+#[derive(Debug, Default)]
+struct Stats {
+    next_runs: HashMap<String, i64>, // Seconds since epoch, by job name
+    latencies_ms: HashMap<String, i64>, // Of each job's first dispatch, by job name
+    completed: usize,
+    batches: usize,
+}
+
+/// An agent that registers and accepts dispatches like a real one, but runs nothing.
+#[derive(Debug)]
+struct FakeAgent {
+    name: String,
+    central_command: Mutex<TcpStream>,
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl FakeAgent {
+    /// Listens on an ephemeral port and registers it with central command. `dialed` is sent the
+    /// agent's name when central command first connects to it.
+    async fn start(
+        name: String,
+        stats: Arc<Mutex<Stats>>,
+        dialed: mpsc::UnboundedSender<String>,
+    ) -> io::Result<()> {
+        let listener = TcpListener::bind("0.0.0.0:0").await?;
+        let port = listener.local_addr()?.port();
+        let agent = Arc::new(FakeAgent {
+            central_command: Mutex::new(Self::connect(&name).await?),
+            name,
+            stats,
+        });
+        agent
+            .send(Message::RegisterAgent(RegisterAgent {
+                name: agent.name.clone(),
+                hostname: get_agent_host().to_string(),
+                port,
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                timezone: "UTC".to_string(),
+                locale: "C".to_string(),
+                receipt_public_key: None,
+                os: env::consts::OS.to_string(),
+                arch: env::consts::ARCH.to_string(),
+                kernel: String::new(),
+                shells: vec!["sh".to_string()],
+                features: vec!["dispatch_batch".to_string()],
+                container: None,
+            }))
+            .await?;
+        debug!("Registered {} on port {}", agent.name, port);
+
+        tokio::spawn(async move {
+            let mut dialed = Some(dialed);
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        error!("{} failed to accept a connection: {}", agent.name, e);
+                        continue;
+                    }
+                };
+                if let Some(dialed) = dialed.take() {
+                    let _ = dialed.send(agent.name.clone());
+                }
+                tokio::spawn(agent.clone().serve(stream));
+            }
+        });
+        Ok(())
+    }
+
+    /// Opens a connection to central command, authenticating it if `LOADGEN_AUTH_TOKEN` is set.
+    async fn connect(name: &str) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(get_central_command_address()).await?;
+        if let Some(token) = get_auth_token() {
+            let message = Message::Authenticate(Authenticate {
+                agent_name: name.to_string(),
+                credential: Credential::Token(token.to_string()),
+            });
+            Self::write(&mut stream, message).await?;
+        }
+        Ok(stream)
+    }
+
+    /// Writes a message to central command and waits for its `OK`.
+    async fn write(stream: &mut TcpStream, message: Message) -> io::Result<()> {
+        let frame = message.to_frame().map_err(io::Error::other)?;
+        stream.write_all(&frame).await?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await?;
+        match &reply == b"OK" {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Central command did not acknowledge the message",
+            )),
+        }
+    }
+
+    /// Sends a message to central command, reconnecting once if the connection was lost.
+    async fn send(&self, message: Message) -> io::Result<()> {
+        let mut stream = self.central_command.lock().await;
+        if Self::write(&mut stream, message.clone()).await.is_ok() {
+            return Ok(());
+        }
+        *stream = Self::connect(&self.name).await?;
+        Self::write(&mut stream, message).await
+    }
+
+    /// Answers the messages central command sends on a connection it dialed, one per read as a
+    /// real agent's listen port does, completing the runs dispatched to the agent.
+    async fn serve(self: Arc<Self>, mut stream: TcpStream) {
+        let mut buffer = MessageBuffer::new();
+        loop {
+            let n = match stream.read(buffer.slice_mut(MAX_MESSAGE_BYTES)).await {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    warn!(
+                        "{} lost its connection from central command: {}",
+                        self.name, e
+                    );
+                    break;
+                }
+            };
+            let jobs = match buffer.message(n) {
+                Ok(ArchivedMessage::Ping) => vec![],
+                Ok(archived) => match Message::from(archived) {
+                    Message::DispatchJob(job) => vec![job],
+                    Message::DispatchBatch(jobs) => {
+                        self.stats.lock().await.batches += 1;
+                        jobs
+                    }
+                    _ => vec![],
+                },
+                Err(e) => {
+                    error!("{} failed to parse a message: {}", self.name, e);
+                    vec![]
+                }
+            };
+            self.record_dispatches(&jobs).await;
+            if let Err(e) = stream.write_all(b"OK").await {
+                warn!("{} failed to acknowledge a message: {}", self.name, e);
+                break;
+            }
+            for job in jobs {
+                tokio::spawn(self.clone().complete(job));
+            }
+        }
+    }
+
+    async fn record_dispatches(&self, jobs: &[DispatchJob]) {
+        let received_at = now_ms();
+        let mut stats = self.stats.lock().await;
+        for job in jobs {
+            let Some(next_run) = stats.next_runs.get(&job.job_name).copied() else {
+                continue; // Not a job of this load test
+            };
+            stats
+                .latencies_ms
+                .entry(job.job_name.clone())
+                .or_insert(received_at - next_run * 1000);
+        }
+    }
+
+    /// Reports a run as succeeded once it took `LOADGEN_JOB_DURATION_MS`.
+    async fn complete(self: Arc<Self>, job: DispatchJob) {
+        let started_at = now_ms();
+        sleep(Duration::from_millis(get_job_duration_ms())).await;
+        let job_name = job.job_name.clone();
+        let message = Message::JobComplete(JobComplete {
+            started_at,
+            completed_at: now_ms(),
+            job_name: job.job_name,
+            command: job.command,
+            agent_name: self.name.clone(),
+            return_code: 0,
+            outcome: JobOutCome::Success,
+            output: String::new(),
+            triggered_by: job.triggered_by,
+            truncated: false,
+            artifact: None,
+            signature: None,
+            run_id: job.run_id,
+            steps: vec![],
+            timeout_extension: 0,
+        });
+        match self.send(message).await {
+            Ok(()) => self.stats.lock().await.completed += 1,
+            Err(e) => error!("{} failed to complete {}: {}", self.name, job_name, e),
+        }
+    }
+}
+
+/// Removes the jobs and agents of load tests, and the runs and cycles recorded for them.
+async fn clean_up(db: &mongodb::Database) -> Result<(), Box<dyn Error>> {
+    let pattern = doc! { "$regex": format!("^{}", NAME_PREFIX) };
+    for (collection, field) in [
+        ("jobs", "name"),
+        ("agents", "name"),
+        ("runs", "job_name"),
+        ("job_executions", "job_name"),
+    ] {
+        db.collection::<Document>(collection)
+            .delete_many(doc! { field: pattern.clone() })
+            .await?;
+    }
+    Ok(())
+}
+
+/// One-shot jobs, each targeting one of the agents in turn, and the `next_run` of each by name.
+fn job_documents(agents: &[String], start: i64) -> (Vec<Document>, HashMap<String, i64>) {
+    let jobs = get_jobs();
+    let ramp = get_ramp_seconds() as i64;
+    let mut documents = Vec::with_capacity(jobs);
+    let mut next_runs = HashMap::with_capacity(jobs);
+    for i in 0..jobs {
+        let name = format!("{}job-{}", NAME_PREFIX, i);
+        let next_run = start + ramp * i as i64 / jobs as i64;
+        documents.push(doc! {
+            "name": &name,
+            "next_run": next_run_datetime(next_run),
+            "status": 0,
+            "description": "Load test job",
+            "command": "true",
+            "args": [],
+            "env": [],
+            "cwd": "",
+            "timeout": 60,
+            "retries": 0,
+            "valid_return_codes": [0],
+            "agents_required": [&agents[i % agents.len()]],
+            "agents_running": [],
+            "agents_complete": [],
+            "one_shot": true,
+        });
+        next_runs.insert(name, next_run);
+    }
+    (documents, next_runs)
+}
+
+/// The server's operation counters, by kind.
+async fn op_counters(db: &mongodb::Database) -> Result<HashMap<&'static str, i64>, Box<dyn Error>> {
+    let status = db.run_command(doc! { "serverStatus": 1 }).await?;
+    let counters = status.get_document("opcounters")?;
+    Ok(OP_COUNTERS
+        .iter()
+        .map(|kind| {
+            let count = match counters.get(*kind) {
+                Some(Bson::Int32(count)) => *count as i64,
+                Some(Bson::Int64(count)) => *count,
+                Some(Bson::Double(count)) => *count as i64,
+                _ => 0,
+            };
+            (*kind, count)
+        })
+        .collect())
+}
+
+/// The `percentile`th percentile of sorted values, by the nearest rank.
+fn percentile(sorted: &[i64], percentile: usize) -> i64 {
+    let rank = (sorted.len() * percentile).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn report(
+    stats: &Stats,
+    elapsed: Duration,
+    before: &HashMap<&str, i64>,
+    after: &HashMap<&str, i64>,
+) {
+    println!(
+        "{} agents, {} jobs: {} dispatched ({} batches), {} completed in {:.1}s",
+        get_agents(),
+        get_jobs(),
+        stats.latencies_ms.len(),
+        stats.batches,
+        stats.completed,
+        elapsed.as_secs_f64()
+    );
+    let mut latencies: Vec<i64> = stats.latencies_ms.values().copied().collect();
+    latencies.sort_unstable();
+    if let Some(max) = latencies.last() {
+        println!(
+            "Dispatch latency (ms): p50 {} p90 {} p99 {} max {}",
+            percentile(&latencies, 50),
+            percentile(&latencies, 90),
+            percentile(&latencies, 99),
+            max
+        );
+    }
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    let mut total = 0.0;
+    let rates: Vec<String> = OP_COUNTERS
+        .iter()
+        .map(|kind| {
+            let rate = (after[kind] - before[kind]) as f64 / seconds;
+            total += rate;
+            format!("{} {:.1}", kind, rate)
+        })
+        .collect();
+    println!("MongoDB ops/sec: {} (total {:.1})", rates.join(" "), total);
+}
+
+async fn run() -> Result<bool, Box<dyn Error>> {
+    let datastore = Datastore::try_new().await?;
+    let db = datastore.get_database();
+    clean_up(&db).await?;
+
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let (dialed_tx, mut dialed_rx) = mpsc::unbounded_channel();
+    let agents: Vec<String> = (0..get_agents())
+        .map(|i| format!("{}agent-{}", NAME_PREFIX, i))
+        .collect();
+    for name in &agents {
+        FakeAgent::start(name.clone(), stats.clone(), dialed_tx.clone()).await?;
+    }
+    drop(dialed_tx);
+    info!(
+        "Registered {} agents, waiting for central command to dial them",
+        agents.len()
+    );
+    let all_dialed = timeout(get_timeout(), async {
+        for _ in 0..agents.len() {
+            dialed_rx.recv().await;
+        }
+    })
+    .await;
+    if all_dialed.is_err() {
+        error!("Central command did not dial every agent in time");
+        clean_up(&db).await?;
+        return Ok(false);
+    }
+
+    let (documents, next_runs) = job_documents(&agents, now_ms() / 1000);
+    stats.lock().await.next_runs = next_runs;
+    db.collection::<Document>("jobs")
+        .insert_many(documents)
+        .await?;
+    info!(
+        "Scheduled {} jobs over {} seconds",
+        get_jobs(),
+        get_ramp_seconds()
+    );
+
+    let before = op_counters(&db).await?;
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(get_ramp_seconds()) + get_timeout();
+    let mut progress_at = started + PROGRESS_INTERVAL;
+    loop {
+        let completed = stats.lock().await.completed;
+        if completed >= get_jobs() || Instant::now() >= deadline {
+            break;
+        }
+        if Instant::now() >= progress_at {
+            info!("{}/{} jobs completed", completed, get_jobs());
+            progress_at += PROGRESS_INTERVAL;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    let elapsed = started.elapsed();
+    let after = op_counters(&db).await?;
+
+    let stats = stats.lock().await;
+    report(&stats, elapsed, &before, &after);
+    clean_up(&db).await?;
+    Ok(stats.completed >= get_jobs())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    logging::init();
+    match run().await {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => {
+            error!("Not every job completed in time");
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            error!("Load test failed: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Conformance tests for the wire protocol between agents and central command, run with
+//! `cargo test -p protocol-tests`. They send messages through real sockets with the same code the
+//! agent and central command use, so a refactor that changes what goes over the wire fails here.
+//!
+//! # Covered
+//!
+//! - Every `Message` variant round-trips as a frame (agents to central command, and reverse
+//!   dispatch) and as a direct write (central command to an agent's listen port).
+//! - Frames split over many reads, several frames to a read, and reverse dispatch
+//!   acknowledgments between them.
+//! - Concurrent writers sharing a connection through `PriorityLock` never interleave frames.
+//! - A connection closed part way through a frame fails instead of yielding half a message, and
+//!   the reconnected peer is read normally.
+//! - Frames over the reader's limit are refused before their body is read.
+//!
+//! # Fixtures
+//!
+//! - `every_message`: One message of each variant, with every optional field set.
+//! - `socket_pair`: The two ends of a loopback TCP connection.
+use tokio::net::{TcpListener, TcpStream};
+
+use core_logic::messages::{
+    Authenticate, CancelJob, CloseShell, ContainerInfo, Credential, DispatchJob, Envelope,
+    ExtendTimeout, FileChunk, FileDigest, JobComplete, JobContainer, JobOutCome, JobProgress,
+    JobScript, JobStep, Message, OpenShell, PollWork, RegisterAgent, ResizeShell, ReverseDispatch,
+    ShellData, StepResult, TriggeredBy, UpdateAgent,
+};
+
+/// One message of each variant. Adding a variant to `Message` fails to compile here until it is
+/// added, so new messages cannot skip the conformance tests.
+pub fn every_message() -> Vec<Message> {
+    let messages = vec![
+        Message::Ping,
+        Message::RegisterAgent(RegisterAgent {
+            name: "web-1".to_string(),
+            hostname: "web-1.example.com".to_string(),
+            port: 8081,
+            version: "0.1.0".to_string(),
+            timezone: "Europe/Berlin".to_string(),
+            locale: "de_DE.UTF-8".to_string(),
+            receipt_public_key: Some("ab".repeat(32)),
+            os: "linux".to_string(),
+            arch: "amd64".to_string(),
+            kernel: "6.8.0-45-generic".to_string(),
+            shells: vec!["sh".to_string(), "bash".to_string()],
+            features: vec!["reverse_dispatch".to_string(), "spool".to_string()],
+            container: Some(ContainerInfo {
+                runtime: "kubernetes".to_string(),
+                node_name: Some("node-a".to_string()),
+                pod_name: Some("web-1-7d4b9c".to_string()),
+                pod_namespace: Some("batch".to_string()),
+                node_labels: vec!["disktype=ssd".to_string()],
+            }),
+        }),
+        Message::DispatchJob(DispatchJob {
+            job_name: "nightly-backup".to_string(),
+            command: "/usr/local/bin/backup".to_string(),
+            args: "--full --verbose".to_string(),
+            agent_name: Some("web-1".to_string()),
+            valid_return_codes: Some(vec![0, 3]),
+            timeout: Some(3600),
+            triggered_by: TriggeredBy::User("alice".to_string()),
+            redact_patterns: vec!["password=\\S+".to_string()],
+            run_id: Some("7f9c0a52-8d0e-4d1b-a1f4-3b6e2c9d8e10".to_string()),
+            steps: vec![JobStep {
+                name: "dump".to_string(),
+                command: "pg_dump".to_string(),
+                args: "app".to_string(),
+                cwd: Some("/srv/backups".to_string()),
+                env: vec!["PGUSER=backup".to_string()],
+                continue_on_error: true,
+            }],
+            files: vec![FileDigest {
+                destination: "/srv/backups/backup.sh".to_string(),
+                sha256: "0a".repeat(32),
+            }],
+            script: Some(JobScript {
+                interpreter: "bash".to_string(),
+                body: "#!/bin/bash\nset -euo pipefail\npg_dump app > \"$1\"\n".to_string(),
+            }),
+            container: None,
+            env: vec!["PGHOST=db.internal".to_string()],
+        }),
+        Message::JobComplete(JobComplete {
+            started_at: 1_749_204_000_000,
+            completed_at: 1_749_204_060_000,
+            job_name: "nightly-backup".to_string(),
+            command: "/usr/local/bin/backup".to_string(),
+            agent_name: "web-1".to_string(),
+            return_code: 0,
+            outcome: JobOutCome::Success,
+            output: "done\n".repeat(100),
+            triggered_by: TriggeredBy::Retry("run-1".to_string()),
+            truncated: true,
+            artifact: Some("/var/lib/agent/output/run-2.log".to_string()),
+            signature: Some("cd".repeat(64)),
+            run_id: Some("run-2".to_string()),
+            steps: vec![StepResult {
+                name: "dump".to_string(),
+                command: "pg_dump app".to_string(),
+                started_at: 1_749_204_000_000,
+                completed_at: 1_749_204_030_000,
+                return_code: 0,
+                outcome: JobOutCome::Success,
+                output: "dumped\n".to_string(),
+                truncated: false,
+                artifact: None,
+            }],
+            timeout_extension: 600,
+        }),
+        Message::CancelJob(CancelJob {
+            job_name: "nightly-backup".to_string(),
+        }),
+        Message::UpdateAgent(UpdateAgent {
+            version: "0.2.0".to_string(),
+            url: "https://example.com/agent-0.2.0".to_string(),
+            checksum: "ef".repeat(32),
+        }),
+        Message::ReverseDispatch(ReverseDispatch {
+            agent_name: "web-1".to_string(),
+        }),
+        Message::Authenticate(Authenticate {
+            agent_name: "web-1".to_string(),
+            credential: Credential::JwtSvid("header.payload.signature".to_string()),
+        }),
+        Message::JobProgress(JobProgress {
+            job_name: "nightly-backup".to_string(),
+            agent_name: "web-1".to_string(),
+            run_id: Some("run-2".to_string()),
+            elapsed: 2880,
+            timeout: 3600,
+        }),
+        Message::ExtendTimeout(ExtendTimeout {
+            job_name: "nightly-backup".to_string(),
+            seconds: 600,
+        }),
+        Message::OpenShell(OpenShell {
+            session_id: "2c1f6e0a-5b7d-4e8f-9a3c-1d2e3f4a5b6c".to_string(),
+            agent_name: "web-1".to_string(),
+            user: "alice".to_string(),
+            cols: 120,
+            rows: 40,
+        }),
+        Message::ShellData(ShellData {
+            data: b"ls -la\r\x1b[31mred\x1b[0m\xff".to_vec(),
+        }),
+        Message::ResizeShell(ResizeShell {
+            cols: 200,
+            rows: 50,
+        }),
+        Message::CloseShell(CloseShell {
+            reason: "exited with 0".to_string(),
+        }),
+        Message::FileChunk(FileChunk {
+            job_name: "nightly-backup".to_string(),
+            destination: "/srv/backups/backup.sh".to_string(),
+            mode: Some(0o755),
+            size: 70_000,
+            sha256: "0a".repeat(32),
+            offset: 32_768,
+            data: vec![0xff; 32_768],
+        }),
+        Message::Envelope(
+            Envelope::request(
+                u64::MAX - 1,
+                &Message::RegisterAgent(RegisterAgent {
+                    name: "web-2".to_string(),
+                    hostname: "web-2.internal".to_string(),
+                    port: 8081,
+                    version: "0.1.0".to_string(),
+                    timezone: "UTC".to_string(),
+                    locale: "C.UTF-8".to_string(),
+                    receipt_public_key: None,
+                    os: "linux".to_string(),
+                    arch: "arm64".to_string(),
+                    kernel: "6.8.0".to_string(),
+                    shells: vec!["sh".to_string()],
+                    features: vec![],
+                    container: None,
+                }),
+            )
+            .expect("Failed to wrap message"),
+        ),
+        Message::PollWork(PollWork {
+            agent_name: "web-1".to_string(),
+            max_jobs: 4,
+        }),
+        Message::DispatchBatch(vec![
+            DispatchJob {
+                job_name: "rotate-logs".to_string(),
+                command: "logrotate".to_string(),
+                args: "/etc/logrotate.conf".to_string(),
+                agent_name: Some("web-1".to_string()),
+                valid_return_codes: None,
+                timeout: None,
+                triggered_by: TriggeredBy::Scheduler,
+                redact_patterns: vec![],
+                run_id: Some("run-3".to_string()),
+                steps: vec![],
+                files: vec![],
+                script: None,
+                container: None,
+                env: vec![],
+            },
+            DispatchJob {
+                job_name: "rollback".to_string(),
+                command: "/usr/local/bin/rollback".to_string(),
+                args: String::new(),
+                agent_name: Some("web-1".to_string()),
+                valid_return_codes: Some(vec![0]),
+                timeout: Some(600),
+                triggered_by: TriggeredBy::Hook("deploy:on_failure".to_string()),
+                redact_patterns: vec![],
+                run_id: Some("run-4".to_string()),
+                steps: vec![],
+                files: vec![],
+                script: None,
+                container: Some(JobContainer {
+                    image: "registry.example.com/deploy:2.1".to_string(),
+                    mounts: vec!["/srv/releases:/releases:ro".to_string()],
+                    env: vec!["RELEASE=previous".to_string()],
+                    workdir: Some("/releases".to_string()),
+                }),
+                env: vec![],
+            },
+        ]),
+    ];
+    for message in &messages {
+        match message {
+            Message::Ping
+            | Message::RegisterAgent(_)
+            | Message::DispatchJob(_)
+            | Message::JobComplete(_)
+            | Message::CancelJob(_)
+            | Message::UpdateAgent(_)
+            | Message::ReverseDispatch(_)
+            | Message::Authenticate(_)
+            | Message::JobProgress(_)
+            | Message::ExtendTimeout(_)
+            | Message::OpenShell(_)
+            | Message::ShellData(_)
+            | Message::ResizeShell(_)
+            | Message::CloseShell(_)
+            | Message::FileChunk(_)
+            | Message::Envelope(_)
+            | Message::PollWork(_)
+            | Message::DispatchBatch(_) => (),
+        }
+    }
+    messages
+}
+
+/// The connecting and accepted ends of a loopback TCP connection.
+pub async fn socket_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind loopback listener");
+    let address = listener.local_addr().expect("Listener has no address");
+    let (client, accepted) = tokio::join!(TcpStream::connect(address), listener.accept());
+    let client = client.expect("Failed to connect to loopback listener");
+    let (server, _) = accepted.expect("Failed to accept loopback connection");
+    (client, server)
+}
@@ -0,0 +1,445 @@
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{Duration, timeout};
+
+use std::io;
+use std::sync::Arc;
+
+use core_logic::bus::{self, agent_name_from_central_subject, central_subject};
+use core_logic::flow_control::{ChunkSizer, MIN_CHUNK_SIZE};
+use core_logic::framing::{self, FrameReader, ProtocolError};
+use core_logic::messages::{
+    DispatchJob, Envelope, Message, MessageBuffer, REVERSE_DISPATCH_ACK, TriggeredBy,
+    access_message,
+};
+use core_logic::priority::PriorityLock;
+use protocol_tests::{every_message, socket_pair};
+
+const MAX_FRAME: usize = 1024 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn frame(message: &Message) -> Vec<u8> {
+    message.clone().to_frame().expect("Failed to frame message")
+}
+
+/// Reads a frame the way central command does: the header, then the body in adaptive chunks
+/// into a reused buffer it is read from in place.
+async fn read_like_central_command(
+    reader: &mut (impl tokio::io::AsyncRead + Unpin),
+    chunk_sizer: &mut ChunkSizer,
+    body: &mut MessageBuffer,
+) -> io::Result<Option<Message>> {
+    let Some(header) = FrameReader::new(MAX_FRAME).read_header(reader).await? else {
+        return Ok(None);
+    };
+    framing::read_frame_body_into(reader, header.len, chunk_sizer, body).await?;
+    header.verify(body.bytes(header.len))?;
+    let archived = body
+        .message(header.len)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Some(Message::from(archived)))
+}
+
+async fn read_message(reader: &mut (impl tokio::io::AsyncRead + Unpin)) -> Message {
+    let frame = timeout(READ_TIMEOUT, framing::read_frame(reader, MAX_FRAME))
+        .await
+        .expect("Timed out reading frame")
+        .expect("Failed to read frame")
+        .expect("Connection closed before the frame");
+    Message::try_from(frame).expect("Failed to parse frame")
+}
+
+#[test]
+fn every_variant_has_a_fixture() {
+    let kinds: Vec<&str> = every_message().iter().map(Message::kind).collect();
+    let mut unique = kinds.clone();
+    unique.sort_unstable();
+    unique.dedup();
+    assert_eq!(kinds.len(), unique.len(), "Duplicate fixtures: {:?}", kinds);
+}
+
+#[test]
+fn archived_messages_have_the_kind_of_their_message() {
+    for message in every_message() {
+        let serialized: Vec<u8> = message.clone().try_into().unwrap();
+        let archived = access_message(&serialized).unwrap();
+        assert_eq!(archived.kind(), message.kind());
+        assert_eq!(Message::from(archived), message);
+    }
+}
+
+#[tokio::test]
+async fn agent_frames_reach_central_command() {
+    let (mut agent, mut central) = socket_pair().await;
+    let messages = every_message();
+    for message in &messages {
+        agent.write_all(&frame(message)).await.unwrap();
+    }
+    drop(agent);
+
+    let mut chunk_sizer = ChunkSizer::new(MIN_CHUNK_SIZE, true);
+    let mut body = MessageBuffer::new();
+    for expected in &messages {
+        let received = read_like_central_command(&mut central, &mut chunk_sizer, &mut body)
+            .await
+            .unwrap();
+        assert_eq!(received.as_ref(), Some(expected));
+    }
+    let end = read_like_central_command(&mut central, &mut chunk_sizer, &mut body).await;
+    assert!(matches!(end, Ok(None)), "Expected a clean close: {:?}", end);
+}
+
+#[tokio::test]
+async fn reverse_dispatch_frames_and_acks_reach_the_agent() {
+    let (mut agent, mut central) = socket_pair().await;
+    let messages = every_message();
+    for message in &messages {
+        central.write_all(&REVERSE_DISPATCH_ACK).await.unwrap();
+        central.write_all(&frame(message)).await.unwrap();
+    }
+    drop(central);
+
+    for expected in &messages {
+        let ack = framing::read_frame(&mut agent, MAX_FRAME).await.unwrap();
+        assert_eq!(ack, Some(vec![]), "Expected an acknowledgment");
+        assert_eq!(&read_message(&mut agent).await, expected);
+    }
+    assert_eq!(
+        framing::read_frame(&mut agent, MAX_FRAME).await.unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn direct_writes_reach_the_agent_listen_port() {
+    for expected in every_message() {
+        let (mut central, mut agent) = socket_pair().await;
+        let written = expected.clone().tcp_write(&mut central).await.unwrap();
+        central.shutdown().await.unwrap();
+
+        let mut received = vec![];
+        agent.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received.len(), written);
+        assert_eq!(Message::try_from(received).unwrap(), expected);
+    }
+}
+
+#[tokio::test]
+async fn frames_split_over_many_reads() {
+    let (mut agent, mut central) = socket_pair().await;
+    agent.set_nodelay(true).unwrap();
+    let messages = every_message();
+    let bytes: Vec<u8> = messages.iter().flat_map(frame).collect();
+    let writer = tokio::spawn(async move {
+        for (index, chunk) in bytes.chunks(7).enumerate() {
+            agent.write_all(chunk).await.unwrap();
+            agent.flush().await.unwrap();
+            if index % 16 == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+    });
+
+    let mut chunk_sizer = ChunkSizer::new(MIN_CHUNK_SIZE, true);
+    let mut body = MessageBuffer::new();
+    for expected in &messages {
+        let received = read_like_central_command(&mut central, &mut chunk_sizer, &mut body)
+            .await
+            .unwrap();
+        assert_eq!(received.as_ref(), Some(expected));
+    }
+    writer.await.unwrap();
+}
+
+#[tokio::test]
+async fn several_frames_in_one_write() {
+    let (mut agent, mut central) = socket_pair().await;
+    let messages = every_message();
+    let bytes: Vec<u8> = messages.iter().flat_map(frame).collect();
+    agent.write_all(&bytes).await.unwrap();
+
+    for expected in &messages {
+        assert_eq!(&read_message(&mut central).await, expected);
+    }
+}
+
+#[tokio::test]
+async fn concurrent_writers_do_not_interleave_frames() {
+    const WRITERS: usize = 20;
+    let (agent, mut central) = socket_pair().await;
+    let (_, writer) = agent.into_split();
+    let writer = Arc::new(PriorityLock::new(writer));
+
+    let mut tasks = vec![];
+    for _ in 0..WRITERS {
+        let writer = writer.clone();
+        tasks.push(tokio::spawn(async move {
+            for message in every_message() {
+                let bytes = frame(&message);
+                writer
+                    .lock(message.priority())
+                    .await
+                    .write_all(&bytes)
+                    .await
+                    .unwrap();
+            }
+        }));
+    }
+
+    let messages = every_message();
+    let mut received = vec![];
+    for _ in 0..WRITERS * messages.len() {
+        received.push(read_message(&mut central).await);
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    for expected in &messages {
+        let count = received
+            .iter()
+            .filter(|message| *message == expected)
+            .count();
+        assert_eq!(count, WRITERS, "{} frames received", expected.kind());
+    }
+}
+
+#[tokio::test]
+async fn connection_closed_mid_frame_fails_and_reconnect_is_read() {
+    for expected in every_message() {
+        let bytes = frame(&expected);
+        // Closed inside the header, then inside the body.
+        for cut in [
+            2,
+            framing::FRAME_HEADER_LEN + (bytes.len() - framing::FRAME_HEADER_LEN) / 2,
+        ] {
+            let (mut agent, mut central) = socket_pair().await;
+            agent.write_all(&bytes[..cut]).await.unwrap();
+            drop(agent);
+            let error = framing::read_frame(&mut central, MAX_FRAME)
+                .await
+                .expect_err("Half a frame was read as a whole one");
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+            let (mut agent, mut central) = socket_pair().await;
+            agent.write_all(&bytes[..cut]).await.unwrap();
+            drop(agent);
+            let mut chunk_sizer = ChunkSizer::new(MIN_CHUNK_SIZE, true);
+            let mut body = MessageBuffer::new();
+            let error = read_like_central_command(&mut central, &mut chunk_sizer, &mut body)
+                .await
+                .expect_err("Half a frame was read as a whole one");
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        }
+
+        // The agent reconnects and sends the message again.
+        let (mut agent, mut central) = socket_pair().await;
+        agent.write_all(&bytes).await.unwrap();
+        assert_eq!(read_message(&mut central).await, expected);
+    }
+}
+
+#[tokio::test]
+async fn enveloped_messages_share_a_stream_and_responses_match_by_id() {
+    let (mut agent, mut central) = socket_pair().await;
+    let messages = every_message();
+    // Every request is written before any response is read.
+    for (id, message) in messages.iter().enumerate() {
+        let request = Envelope::request(id as u64, message).unwrap();
+        agent
+            .write_all(&frame(&Message::Envelope(request)))
+            .await
+            .unwrap();
+    }
+
+    let mut ids = vec![];
+    for expected in &messages {
+        let Message::Envelope(request) = read_message(&mut central).await else {
+            panic!("Expected an envelope");
+        };
+        assert_eq!(request.message().unwrap().as_ref(), Some(expected));
+        ids.push(request.id);
+    }
+    // Answered out of order; the agent matches each response by its id.
+    for id in ids.iter().rev() {
+        let response = Message::Envelope(Envelope::response(*id));
+        central.write_all(&frame(&response)).await.unwrap();
+    }
+    for id in ids.iter().rev() {
+        let Message::Envelope(response) = read_message(&mut agent).await else {
+            panic!("Expected an envelope");
+        };
+        assert_eq!(response.id, *id);
+        assert_eq!(response.message().unwrap(), None);
+    }
+}
+
+#[tokio::test]
+async fn oversized_frames_are_refused_before_their_body() {
+    let (mut agent, mut central) = socket_pair().await;
+    // Only the length is sent: a reader waiting for the body would time out.
+    agent.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+    let error = timeout(READ_TIMEOUT, framing::read_frame(&mut central, MAX_FRAME))
+        .await
+        .expect("Reader waited for the body of an oversized frame")
+        .expect_err("Oversized frame was accepted");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn frames_up_to_the_limit_are_accepted() {
+    let large = Message::DispatchJob(DispatchJob {
+        job_name: "large".to_string(),
+        command: "echo".to_string(),
+        args: "x".repeat(512 * 1024),
+        agent_name: None,
+        valid_return_codes: None,
+        timeout: None,
+        triggered_by: TriggeredBy::Scheduler,
+        redact_patterns: vec![],
+        run_id: None,
+        steps: vec![],
+        files: vec![],
+        script: None,
+        container: None,
+        env: vec![],
+    });
+    let bytes = frame(&large);
+    let limit = bytes.len() - framing::FRAME_HEADER_LEN;
+
+    let (mut agent, mut central) = socket_pair().await;
+    let writer = tokio::spawn(async move {
+        agent.write_all(&bytes).await.unwrap();
+        agent.write_all(&bytes).await.unwrap();
+        agent
+    });
+    let frame = framing::read_frame(&mut central, limit).await.unwrap();
+    assert_eq!(Message::try_from(frame.unwrap()).unwrap(), large);
+    let error = framing::read_frame(&mut central, limit - 1)
+        .await
+        .expect_err("Frame over the limit was accepted");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    drop(writer.await.unwrap());
+}
+
+#[tokio::test]
+async fn corrupt_frames_fail_to_parse() {
+    let (mut agent, mut central) = socket_pair().await;
+    let garbage = [0xffu8; 64];
+    agent
+        .write_all(&(garbage.len() as u32).to_be_bytes())
+        .await
+        .unwrap();
+    agent.write_all(&garbage).await.unwrap();
+    agent.write_all(&frame(&Message::Ping)).await.unwrap();
+
+    let corrupt = framing::read_frame(&mut central, MAX_FRAME)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(Message::try_from(corrupt).is_err());
+    // The stream stays in step: the next frame is read normally.
+    assert_eq!(read_message(&mut central).await, Message::Ping);
+}
+
+#[tokio::test]
+async fn legacy_frames_are_still_read() {
+    let (mut agent, mut central) = socket_pair().await;
+    let messages = every_message();
+    for message in &messages {
+        let legacy = message.clone().to_legacy_frame().unwrap();
+        agent.write_all(&legacy).await.unwrap();
+    }
+    drop(agent);
+
+    let mut frames = FrameReader::new(MAX_FRAME);
+    for expected in &messages {
+        let frame = frames.read_frame(&mut central).await.unwrap().unwrap();
+        assert_eq!(&Message::try_from(frame).unwrap(), expected);
+    }
+    assert!(frames.read_frame(&mut central).await.unwrap().is_none());
+    // Central command answers the agent in legacy frames.
+    assert!(!frames.checksummed());
+}
+
+#[tokio::test]
+async fn corrupted_frames_are_skipped_and_the_stream_resynchronizes() {
+    let (mut agent, mut central) = socket_pair().await;
+    // A body that no longer matches its checksum.
+    let mut corrupted = frame(&Message::Ping);
+    *corrupted.last_mut().unwrap() ^= 0x01;
+    agent.write_all(&corrupted).await.unwrap();
+    // The rest of a frame whose length was too short, then a good frame.
+    agent.write_all(&[0x42; 37]).await.unwrap();
+    agent.write_all(&frame(&Message::Ping)).await.unwrap();
+
+    let mut frames = FrameReader::new(MAX_FRAME);
+    let error = frames
+        .read_frame(&mut central)
+        .await
+        .expect_err("A corrupted frame was accepted");
+    assert!(matches!(
+        ProtocolError::from_io(&error),
+        Some(ProtocolError::ChecksumMismatch { .. })
+    ));
+
+    let header = frames.read_header(&mut central).await.unwrap().unwrap();
+    assert_eq!(header.skipped, 37);
+    let mut body = vec![0u8; header.len];
+    central.read_exact(&mut body).await.unwrap();
+    header.verify(&body).unwrap();
+    assert_eq!(Message::try_from(body).unwrap(), Message::Ping);
+}
+
+#[tokio::test]
+async fn streams_without_a_frame_in_reach_are_given_up() {
+    let (mut agent, mut central) = socket_pair().await;
+    let writer = tokio::spawn(async move {
+        agent.write_all(&frame(&Message::Ping)).await.unwrap();
+        let garbage = vec![0x42; framing::MAX_RESYNC_BYTES + framing::FRAME_HEADER_LEN];
+        // The reader stops before the end, so the write may fail.
+        let _ = agent.write_all(&garbage).await;
+        agent
+    });
+
+    let mut frames = FrameReader::new(MAX_FRAME);
+    let frame = frames.read_frame(&mut central).await.unwrap().unwrap();
+    assert_eq!(Message::try_from(frame).unwrap(), Message::Ping);
+    let error = timeout(READ_TIMEOUT, frames.read_frame(&mut central))
+        .await
+        .expect("Reader waited past the resynchronization limit")
+        .expect_err("Garbage was read as a frame");
+    assert!(matches!(
+        ProtocolError::from_io(&error),
+        Some(ProtocolError::Desynchronized { .. })
+    ));
+    drop(central);
+    drop(writer.await.unwrap());
+}
+
+#[tokio::test]
+async fn memory_bus_keeps_messages_until_a_subscriber_matches() {
+    let bus = bus::connect("memory://conformance").await.unwrap();
+    // The agent may register before central command subscribes.
+    bus.publish(central_subject("agent-0"), Message::Ping)
+        .await
+        .unwrap();
+    let mut central = bus
+        .subscribe(bus::central_wildcard_subject())
+        .await
+        .unwrap();
+    bus.publish(central_subject("agent-1"), Message::Ping)
+        .await
+        .unwrap();
+
+    for agent in ["agent-0", "agent-1"] {
+        let received = timeout(READ_TIMEOUT, central.next())
+            .await
+            .expect("Message was not delivered")
+            .unwrap();
+        assert_eq!(
+            agent_name_from_central_subject(&received.subject),
+            Some(agent)
+        );
+        assert_eq!(received.message, Message::Ping);
+    }
+}
@@ -0,0 +1,688 @@
+//! `radctl` is a command line client for Rust Action Dispatch. It talks to the web UI's HTTP
+//! API, so it needs no database access of its own.
+//!
+//! # Commands
+//! - `radctl agents`: Lists the agents with their status and address.
+//! - `radctl jobs`: Lists the jobs with their status and required agents, with the agent groups
+//!   they target shown as `@group`. Archived one-shot jobs are left out.
+//! - `radctl create-job <file>`: Creates a job from a JSON definition (`-` reads standard input).
+//!   The fields match the web UI's `POST /jobs`; `name`, `agents_required` or `agent_groups`,
+//!   and either `command` or `steps` are required.
+//! - `radctl validate-job <file>`: Checks a job definition without creating it, printing its
+//!   errors and warnings, upcoming runs and the agents it would target. Exits non-zero if
+//!   `create-job` would reject it.
+//! - `radctl upload-file <name> <file>`: Stores a file as a new revision of the job file `name`,
+//!   which jobs push to their agents before running with `{"kind": "grid_fs", "name": ...}` as
+//!   the source of one of their `files`.
+//! - `radctl run <job>`: Runs a job now, recorded as triggered by whoever `RADCTL_TOKEN` acts as.
+//! - `radctl tail <job> [--follow]`: Prints the output of the job's latest run. With `--follow`,
+//!   keeps printing the output of each new run as it completes, until interrupted.
+//! - `radctl cancel <job>`: Asks central command to cancel a running job on its agents.
+//! - `radctl history <job>`: Lists the job's definition revisions, newest first, with who made
+//!   each and the fields it changed.
+//! - `radctl rollback <job> <revision>`: Restores the job's definition at a revision, recorded
+//!   as a new revision.
+//! - `radctl export <job>`: Prints the job's definition as JSON, with its namespace and latest
+//!   revision.
+//! - `radctl promote <file>`: Requests that a job's definition be copied to another namespace,
+//!   e.g. from staging to prod, from a JSON request (`-` reads standard input). The fields match
+//!   the web UI's `POST /job_promotions`; `job_name`, `target_namespace`, `target_job_name` and
+//!   `note` are required. Prints the promotion's id.
+//! - `radctl promotions`: Lists the promotions waiting for review.
+//! - `radctl approve <promotion> [<comment>]`: Approves a promotion, applying it to the target
+//!   job. Promotions are approved by somebody other than their requester.
+//! - `radctl reject <promotion> [<comment>]`: Rejects a promotion.
+//! - `radctl extend-timeout <job> <agent> <seconds>`: Gives the job's run on an agent more time
+//!   before it is killed.
+//! - `radctl verify-outputs [<job>]`: Re-hashes stored run outputs, optionally only the job's,
+//!   and lists runs whose output no longer matches the checksum recorded when it was stored.
+//!   Exits non-zero if any output is corrupted.
+//! - `radctl ping-agent <name>`: Asks central command to ping an agent and prints the
+//!   round-trip time, or the error encountered. Exits non-zero if the ping failed.
+//!
+//! # Configuration
+//! - `RADCTL_WEBUI_URL`: Base URL of the web UI (default: `http://127.0.0.1:8000`).
+//! - `RADCTL_TOKEN`: API token sent as `Authorization: Bearer`, created on the web UI's settings
+//!   page. Needed when the web UI is behind an authenticating proxy, e.g. for CI systems.
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::io::Read;
+use std::process::ExitCode;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const DEFAULT_WEBUI_URL: &str = "http://127.0.0.1:8000";
+const TAIL_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const FETCH_PAGE_SIZE: u32 = 500; // The web UI's largest page
+
+static WEBUI_URL: OnceLock<String> = OnceLock::new();
+static TOKEN: OnceLock<Option<String>> = OnceLock::new();
+
+fn get_webui_url() -> &'static str {
+    WEBUI_URL.get_or_init(|| {
+        env::var("RADCTL_WEBUI_URL")
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|_| DEFAULT_WEBUI_URL.to_string())
+    })
+}
+
+fn get_token() -> Option<&'static str> {
+    TOKEN
+        .get_or_init(|| {
+            env::var("RADCTL_TOKEN")
+                .ok()
+                .map(|token| token.trim().to_string())
+                .filter(|token| !token.is_empty())
+        })
+        .as_deref()
+}
+
+/// A client sending `RADCTL_TOKEN`, when set, with every request.
+fn client() -> Result<reqwest::Client, Box<dyn Error>> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(token) = get_token() {
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))?;
+        value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+    }
+    Ok(reqwest::Client::builder()
+        .default_headers(headers)
+        .build()?)
+}
+
+fn usage() -> ExitCode {
+    eprintln!(
+        "Usage: radctl <command>\n\
+         \n\
+         Commands:\n  \
+           agents                   List agents\n  \
+           jobs                     List jobs\n  \
+           create-job <file>        Create a job from a JSON definition (- for stdin)\n  \
+           validate-job <file>      Check a job definition without creating it\n  \
+           upload-file <name> <file>\n                           \
+           Store a file jobs can push to their agents\n  \
+           run <job>                Run a job now\n  \
+           tail <job> [--follow]    Print the output of the job's latest run\n  \
+           cancel <job>             Cancel a running job\n  \
+           history <job>            List a job's definition revisions\n  \
+           rollback <job> <revision>\n                           \
+           Restore a job's definition at a revision\n  \
+           export <job>             Print a job's definition as JSON\n  \
+           promote <file>           Request a job's promotion to another namespace\n  \
+           promotions               List promotions waiting for review\n  \
+           approve <promotion> [<comment>]\n                           \
+           Approve a promotion, applying it\n  \
+           reject <promotion> [<comment>]\n                           \
+           Reject a promotion\n  \
+           extend-timeout <job> <agent> <seconds>\n                           \
+           Give a job's run on an agent more time\n  \
+           verify-outputs [<job>]   Check stored run outputs against their checksums\n  \
+           ping-agent <name>        Ping an agent through central command"
+    );
+    ExitCode::from(2)
+}
+
+/// Returns the body of a successful response, or an error holding the body and status.
+async fn check_response(response: reqwest::Response) -> Result<String, Box<dyn Error>> {
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        return Err(format!("{} ({})", body, status).into());
+    }
+    Ok(body)
+}
+
+/// Fetches every page of a `*_data` listing, returning the items.
+async fn fetch_all(
+    path: &str,
+    query: &[(&str, &str)],
+) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+    let client = client()?;
+    let url = format!("{}{}", get_webui_url(), path);
+    let mut items = vec![];
+    let mut after: Option<String> = None;
+    loop {
+        let mut request = client
+            .get(&url)
+            .query(query)
+            .query(&[("page_size", FETCH_PAGE_SIZE)]);
+        if let Some(after) = &after {
+            request = request.query(&[("after", after)]);
+        }
+        let body = check_response(request.send().await?).await?;
+        let data: serde_json::Value = serde_json::from_str(&body)?;
+        if let Some(page_items) = data["items"].as_array() {
+            items.extend(page_items.iter().cloned());
+        }
+        match data["next_cursor"].as_str() {
+            Some(next_cursor) => after = Some(next_cursor.to_string()),
+            None => return Ok(items),
+        }
+    }
+}
+
+fn object_id(item: &serde_json::Value) -> &str {
+    item["_id"]["$oid"].as_str().unwrap_or_default()
+}
+
+async fn list_agents() -> Result<bool, Box<dyn Error>> {
+    let agents = fetch_all("/agents/data", &[("sort", "name")]).await?;
+    println!(
+        "{:<24} {:<8} {:<32} {:<14} VERSION",
+        "NAME", "STATUS", "ADDRESS", "PLATFORM"
+    );
+    for agent in &agents {
+        let status = match agent["status"].as_i64() {
+            Some(0) => "offline",
+            Some(1) => "online",
+            Some(2) => "draining",
+            Some(3) => "degraded",
+            _ => "unknown",
+        };
+        let platform = match agent["os"].as_str() {
+            Some(os) if !os.is_empty() => {
+                format!("{}/{}", os, agent["arch"].as_str().unwrap_or_default())
+            }
+            _ => "-".to_string(),
+        };
+        println!(
+            "{:<24} {:<8} {:<32} {:<14} {}",
+            agent["name"].as_str().unwrap_or_default(),
+            status,
+            format!(
+                "{}:{}",
+                agent["hostname"].as_str().unwrap_or_default(),
+                agent["port"]
+            ),
+            platform,
+            agent["agent_version"].as_str().unwrap_or_default(),
+        );
+    }
+    Ok(true)
+}
+
+async fn list_jobs() -> Result<bool, Box<dyn Error>> {
+    let jobs = fetch_all("/jobs_data", &[("sort", "name")]).await?;
+    println!("{:<32} {:<17} AGENTS", "NAME", "STATUS");
+    for job in &jobs {
+        let status = match job["status"].as_i64() {
+            Some(0) => "pending",
+            Some(1) => "running",
+            Some(2) => "completed",
+            Some(3) => "disabled",
+            Some(5) => "archived",
+            Some(6) => "awaiting approval",
+            _ => "error",
+        };
+        let names = |field: &str| -> Vec<String> {
+            job[field]
+                .as_array()
+                .map(|names| {
+                    names
+                        .iter()
+                        .filter_map(|name| name.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let mut agents = names("agents_required");
+        agents.extend(
+            names("agent_groups")
+                .iter()
+                .map(|group| format!("@{}", group)),
+        );
+        println!(
+            "{:<32} {:<17} {}",
+            job["name"].as_str().unwrap_or_default(),
+            status,
+            agents.join(",")
+        );
+    }
+    Ok(true)
+}
+
+/// Reads a JSON job definition from `path`, or standard input for `-`.
+fn read_job_definition(path: &str) -> Result<serde_json::Value, Box<dyn Error>> {
+    let definition = match path {
+        "-" => {
+            let mut definition = String::new();
+            std::io::stdin().read_to_string(&mut definition)?;
+            definition
+        }
+        path => std::fs::read_to_string(path)?,
+    };
+    Ok(serde_json::from_str(&definition)
+        .map_err(|e| format!("Invalid job definition {}: {}", path, e))?)
+}
+
+/// Creates a job from the JSON definition in `path`, or standard input when it is `-`.
+async fn create_job(path: &str) -> Result<bool, Box<dyn Error>> {
+    let definition = read_job_definition(path)?;
+    let url = format!("{}/jobs", get_webui_url());
+    let response = client()?
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(definition.to_string())
+        .send()
+        .await?;
+    check_response(response).await?;
+    println!(
+        "Created job {}",
+        definition["name"].as_str().unwrap_or_default()
+    );
+    Ok(true)
+}
+
+async fn validate_job(path: &str) -> Result<bool, Box<dyn Error>> {
+    let definition = read_job_definition(path)?;
+    let url = format!("{}/jobs/validate", get_webui_url());
+    let response = client()?
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(definition.to_string())
+        .send()
+        .await?;
+    let validation: serde_json::Value = serde_json::from_str(&check_response(response).await?)?;
+
+    let strings = |field: &str| -> Vec<String> {
+        validation[field]
+            .as_array()
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|value| value.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    for error in strings("errors") {
+        println!("error: {}", error);
+    }
+    for warning in strings("warnings") {
+        println!("warning: {}", warning);
+    }
+    if validation["cron_valid"] == false {
+        println!("cron: does not parse");
+    }
+    if let Some(runs) = validation["upcoming_runs"].as_array() {
+        for run in runs.iter().filter_map(|run| run.as_i64()) {
+            let time = chrono::DateTime::from_timestamp(run, 0).unwrap_or_default();
+            println!("run: {}", time.format("%Y-%m-%d %H:%M:%S UTC"));
+        }
+    }
+    for agent in strings("targeted_agents") {
+        println!("target: {}", agent);
+    }
+    if let Some(skipped) = validation["skipped_agents"].as_array() {
+        for agent in skipped {
+            println!(
+                "skipped: {} ({})",
+                agent["name"].as_str().unwrap_or_default(),
+                agent["reason"].as_str().unwrap_or_default()
+            );
+        }
+    }
+    Ok(validation["valid"].as_bool().unwrap_or(false))
+}
+
+/// Uploads `path` as the job file `name`, which jobs push to their agents with a `grid_fs` source.
+async fn upload_file(name: &str, path: &str) -> Result<bool, Box<dyn Error>> {
+    let contents = std::fs::read(path)?;
+    let url = format!("{}/job_files/{}", get_webui_url(), name);
+    let response = client()?
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+        .body(contents)
+        .send()
+        .await?;
+    let sha256 = check_response(response).await?;
+    println!("Uploaded {} as job file {} (sha256 {})", path, name, sha256);
+    Ok(true)
+}
+
+async fn run_job(job_name: &str) -> Result<bool, Box<dyn Error>> {
+    let url = format!("{}/jobs/{}/run", get_webui_url(), job_name);
+    let response = client()?.post(&url).send().await?;
+    check_response(response).await?;
+    println!("Job {} will run shortly", job_name);
+    Ok(true)
+}
+
+async fn extend_timeout(
+    job_name: &str,
+    agent_name: &str,
+    seconds: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let seconds: u32 = seconds
+        .parse()
+        .map_err(|_| format!("Invalid number of seconds: {}", seconds))?;
+    let url = format!(
+        "{}/jobs/{}/agents/{}/extend_timeout",
+        get_webui_url(),
+        job_name,
+        agent_name
+    );
+    let response = client()?
+        .post(&url)
+        .query(&[("seconds", seconds)])
+        .send()
+        .await?;
+    check_response(response).await?;
+    println!(
+        "Extension of job {} on agent {} by {} seconds requested",
+        job_name, agent_name, seconds
+    );
+    Ok(true)
+}
+
+async fn cancel_job(job_name: &str) -> Result<bool, Box<dyn Error>> {
+    let url = format!("{}/jobs/{}/cancel", get_webui_url(), job_name);
+    let response = client()?.post(&url).send().await?;
+    check_response(response).await?;
+    println!("Cancellation of job {} requested", job_name);
+    Ok(true)
+}
+
+/// Prints the job's revisions, newest first, each followed by the fields it changed.
+async fn job_history(job_name: &str) -> Result<bool, Box<dyn Error>> {
+    let url = format!("{}/jobs/{}/revisions", get_webui_url(), job_name);
+    let response = client()?.get(&url).send().await?;
+    let body = check_response(response).await?;
+    let data: serde_json::Value = serde_json::from_str(&body)?;
+    let revisions = data["items"].as_array().cloned().unwrap_or_default();
+    if revisions.is_empty() {
+        println!("No revisions recorded for job {}", job_name);
+        return Ok(true);
+    }
+    for revision in &revisions {
+        let created_at = revision["created_at"]["$date"]["$numberLong"]
+            .as_str()
+            .and_then(|millis| millis.parse().ok())
+            .and_then(chrono::DateTime::from_timestamp_millis)
+            .map(|at| at.to_rfc3339())
+            .unwrap_or_default();
+        println!(
+            "{:>4}  {}  {}  {}",
+            revision["revision"],
+            created_at,
+            revision["author"].as_str().unwrap_or("-"),
+            revision["source"].as_str().unwrap_or("-")
+        );
+        for change in revision["changes"].as_array().into_iter().flatten() {
+            println!(
+                "        {}: {:?} -> {:?}",
+                change["field"].as_str().unwrap_or_default(),
+                change["old"].as_str().unwrap_or_default(),
+                change["new"].as_str().unwrap_or_default()
+            );
+        }
+    }
+    Ok(true)
+}
+
+async fn rollback_job(job_name: &str, revision: &str) -> Result<bool, Box<dyn Error>> {
+    let revision: u32 = revision
+        .parse()
+        .map_err(|_| format!("Invalid revision: {}", revision))?;
+    let url = format!(
+        "{}/jobs/{}/revisions/{}/rollback",
+        get_webui_url(),
+        job_name,
+        revision
+    );
+    let response = client()?.post(&url).send().await?;
+    println!("{}", check_response(response).await?);
+    Ok(true)
+}
+
+/// Prints the job's definition, as `promote` would capture it, as JSON.
+async fn export_job(job_name: &str) -> Result<bool, Box<dyn Error>> {
+    let url = format!("{}/jobs/{}/export", get_webui_url(), job_name);
+    let response = client()?.get(&url).send().await?;
+    let body = check_response(response).await?;
+    let export: serde_json::Value = serde_json::from_str(&body)?;
+    println!("{}", serde_json::to_string_pretty(&export)?);
+    Ok(true)
+}
+
+/// Requests the promotion described by the JSON request in `path`, or standard input when it is
+/// `-`.
+async fn promote_job(path: &str) -> Result<bool, Box<dyn Error>> {
+    let request = read_job_definition(path)?;
+    let url = format!("{}/job_promotions", get_webui_url());
+    let response = client()?
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(request.to_string())
+        .send()
+        .await?;
+    let body = check_response(response).await?;
+    let promotion: serde_json::Value = serde_json::from_str(&body)?;
+    println!(
+        "Requested promotion {} of job {}; it is applied once approved",
+        promotion["id"].as_str().unwrap_or_default(),
+        request["job_name"].as_str().unwrap_or_default()
+    );
+    Ok(true)
+}
+
+async fn list_promotions() -> Result<bool, Box<dyn Error>> {
+    let url = format!("{}/job_promotions", get_webui_url());
+    let response = client()?
+        .get(&url)
+        .query(&[("status", "pending")])
+        .send()
+        .await?;
+    let body = check_response(response).await?;
+    let data: serde_json::Value = serde_json::from_str(&body)?;
+    let promotions = data["items"].as_array().cloned().unwrap_or_default();
+    if promotions.is_empty() {
+        println!("No promotions waiting for review");
+        return Ok(true);
+    }
+    println!(
+        "{:<24} {:<40} {:<16} NOTE",
+        "ID", "PROMOTION", "REQUESTED BY"
+    );
+    for promotion in &promotions {
+        let namespace = |field: &str| promotion[field].as_str().unwrap_or("default").to_string();
+        println!(
+            "{:<24} {:<40} {:<16} {}",
+            object_id(promotion),
+            format!(
+                "{} ({}) -> {} ({})",
+                promotion["source_job"].as_str().unwrap_or_default(),
+                namespace("source_namespace"),
+                promotion["target_job"].as_str().unwrap_or_default(),
+                namespace("target_namespace")
+            ),
+            promotion["requested_by"].as_str().unwrap_or_default(),
+            promotion["note"].as_str().unwrap_or_default()
+        );
+    }
+    Ok(true)
+}
+
+/// Approves or rejects the promotion, as `decision`.
+async fn review_promotion(
+    id: &str,
+    decision: &str,
+    comment: Option<&str>,
+) -> Result<bool, Box<dyn Error>> {
+    let url = format!("{}/job_promotions/{}/{}", get_webui_url(), id, decision);
+    let mut request = client()?.post(&url);
+    if let Some(comment) = comment {
+        request = request.query(&[("comment", comment)]);
+    }
+    println!("{}", check_response(request.send().await?).await?);
+    Ok(true)
+}
+
+/// The job's latest runs, newest first.
+async fn latest_runs(job_name: &str) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+    let url = format!("{}/runs_data", get_webui_url());
+    let response = client()?
+        .get(&url)
+        .query(&[
+            ("filter", job_name),
+            ("sort", "started_at"),
+            ("order", "desc"),
+        ])
+        .send()
+        .await?;
+    let body = check_response(response).await?;
+    let data: serde_json::Value = serde_json::from_str(&body)?;
+    // The filter is a search across several fields, so keep only this job's runs.
+    Ok(data["items"]
+        .as_array()
+        .map(|runs| {
+            runs.iter()
+                .filter(|run| run["job_name"].as_str() == Some(job_name))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+async fn print_run(run: &serde_json::Value) -> Result<(), Box<dyn Error>> {
+    let url = format!("{}/runs_output", get_webui_url());
+    let response = client()?
+        .get(&url)
+        .query(&[("id", object_id(run))])
+        .send()
+        .await?;
+    let output = check_response(response).await?;
+    println!(
+        "==> {} on {} (return code {}) <==",
+        run["job_name"].as_str().unwrap_or_default(),
+        run["agent_name"].as_str().unwrap_or_default(),
+        run["return_code"]
+    );
+    println!("{}", output.trim_end());
+    Ok(())
+}
+
+/// Prints the output of the job's latest run and, when following, of every later run.
+async fn tail_job(job_name: &str, follow: bool) -> Result<bool, Box<dyn Error>> {
+    let runs = latest_runs(job_name).await?;
+    match runs.first() {
+        Some(run) => print_run(run).await?,
+        None if !follow => return Err(format!("Job {} has no runs", job_name).into()),
+        None => (),
+    }
+    if !follow {
+        return Ok(true);
+    }
+
+    let mut seen: HashSet<String> = runs.iter().map(|run| object_id(run).to_string()).collect();
+    loop {
+        tokio::time::sleep(TAIL_POLL_INTERVAL).await;
+        let runs = latest_runs(job_name).await?;
+        // Oldest first, so runs are printed in the order they started.
+        for run in runs.iter().rev() {
+            if seen.insert(object_id(run).to_string()) {
+                print_run(run).await?;
+            }
+        }
+    }
+}
+
+/// Checks stored run outputs against their checksums, returning whether none are corrupted.
+async fn verify_outputs(job_name: Option<&str>) -> Result<bool, Box<dyn Error>> {
+    let url = format!("{}/runs/verify_outputs", get_webui_url());
+    let mut request = client()?.get(&url);
+    if let Some(job_name) = job_name {
+        request = request.query(&[("job_name", job_name)]);
+    }
+    let body = check_response(request.send().await?).await?;
+    let report: serde_json::Value = serde_json::from_str(&body)?;
+
+    let corrupted = report["corrupted"].as_array().cloned().unwrap_or_default();
+    for run in &corrupted {
+        println!(
+            "CORRUPTED {} ({} on {})",
+            run["id"].as_str().unwrap_or_default(),
+            run["job_name"].as_str().unwrap_or_default(),
+            run["agent_name"].as_str().unwrap_or_default()
+        );
+    }
+    println!(
+        "Checked {} runs: {} corrupted, {} without a checksum",
+        report["checked"],
+        corrupted.len(),
+        report["missing_checksum"]
+    );
+    Ok(corrupted.is_empty())
+}
+
+/// Pings `agent_name` through central command, returning whether the ping succeeded.
+async fn ping_agent(agent_name: &str) -> Result<bool, Box<dyn Error>> {
+    let url = format!("{}/agents/{}/ping", get_webui_url(), agent_name);
+    let response = client()?.post(&url).send().await?;
+    let body = check_response(response).await?;
+
+    let result: serde_json::Value = serde_json::from_str(&body)?;
+    let via = result["via"].as_str().unwrap_or_default();
+    if let Some(error) = result["error"].as_str() {
+        println!("{}: ping failed via {}: {}", agent_name, via, error);
+        return Ok(false);
+    }
+    match result["rtt_ms"].as_f64() {
+        Some(rtt_ms) => println!("{}: {:.2} ms via {}", agent_name, rtt_ms, via),
+        None => println!(
+            "{}: ping queued via {} (round-trip time is not measured)",
+            agent_name, via
+        ),
+    }
+    Ok(true)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [command] if command == "agents" => list_agents().await,
+        [command] if command == "jobs" => list_jobs().await,
+        [command, path] if command == "create-job" => create_job(path).await,
+        [command, path] if command == "validate-job" => validate_job(path).await,
+        [command, name, path] if command == "upload-file" => upload_file(name, path).await,
+        [command, job_name] if command == "run" => run_job(job_name).await,
+        [command, job_name] if command == "tail" => tail_job(job_name, false).await,
+        [command, job_name, follow] if command == "tail" && follow == "--follow" => {
+            tail_job(job_name, true).await
+        }
+        [command, job_name] if command == "cancel" => cancel_job(job_name).await,
+        [command, job_name] if command == "history" => job_history(job_name).await,
+        [command, job_name, revision] if command == "rollback" => {
+            rollback_job(job_name, revision).await
+        }
+        [command, job_name] if command == "export" => export_job(job_name).await,
+        [command, path] if command == "promote" => promote_job(path).await,
+        [command] if command == "promotions" => list_promotions().await,
+        [command, id] if command == "approve" => review_promotion(id, "approve", None).await,
+        [command, id, comment] if command == "approve" => {
+            review_promotion(id, "approve", Some(comment)).await
+        }
+        [command, id] if command == "reject" => review_promotion(id, "reject", None).await,
+        [command, id, comment] if command == "reject" => {
+            review_promotion(id, "reject", Some(comment)).await
+        }
+        [command, job_name, agent_name, seconds] if command == "extend-timeout" => {
+            extend_timeout(job_name, agent_name, seconds).await
+        }
+        [command] if command == "verify-outputs" => verify_outputs(None).await,
+        [command, job_name] if command == "verify-outputs" => verify_outputs(Some(job_name)).await,
+        [command, agent_name] if command == "ping-agent" => ping_agent(agent_name).await,
+        _ => return usage(),
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
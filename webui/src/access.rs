@@ -0,0 +1,322 @@
+use mongodb::bson::{Document, doc};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::OnceLock;
+
+use crate::audit::Actor;
+use crate::internal_error;
+use core_logic::datastore::Datastore;
+use core_logic::datastore::agent_groups::AgentGroupV1;
+use core_logic::datastore::agents::AgentV1;
+use core_logic::datastore::jobs::JobV1;
+
+static WEBUI_TEAMS: OnceLock<HashMap<String, Vec<String>>> = OnceLock::new();
+static WEBUI_ADMIN_USERS: OnceLock<Vec<String>> = OnceLock::new();
+static WEBUI_APPROVERS: OnceLock<Vec<String>> = OnceLock::new();
+
+fn parse_users(users: &str) -> Vec<String> {
+    users
+        .split(',')
+        .map(str::trim)
+        .filter(|user| !user.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Teams and their members, read from `WEBUI_TEAMS` as `team=user,user;team=user`. Users are
+/// identified as by `Actor`, so service tokens are members as `service:<name>`. Teams are not
+/// scoped when it is empty (the default): everybody sees everything.
+fn get_webui_teams() -> &'static HashMap<String, Vec<String>> {
+    WEBUI_TEAMS.get_or_init(|| {
+        env::var("WEBUI_TEAMS")
+            .unwrap_or_default()
+            .split(';')
+            .filter_map(|team| team.split_once('='))
+            .map(|(team, users)| (team.trim().to_string(), parse_users(users)))
+            .filter(|(team, _)| !team.is_empty())
+            .collect()
+    })
+}
+
+/// Users who see and modify every job and agent while teams are scoped, read from the comma
+/// separated `WEBUI_ADMIN_USERS`.
+fn get_webui_admin_users() -> &'static [String] {
+    WEBUI_ADMIN_USERS
+        .get_or_init(|| parse_users(&env::var("WEBUI_ADMIN_USERS").unwrap_or_default()))
+}
+
+/// Users who may approve or reject the runs of jobs that require approval, read from the comma
+/// separated `WEBUI_APPROVERS`. When it is empty (the default), the admins in `WEBUI_ADMIN_USERS`
+/// may, or anybody when there are no admins either.
+fn get_webui_approvers() -> &'static [String] {
+    WEBUI_APPROVERS.get_or_init(|| parse_users(&env::var("WEBUI_APPROVERS").unwrap_or_default()))
+}
+
+/// What the user making a request may see and modify when teams are scoped by `WEBUI_TEAMS`:
+/// the jobs and agents of their teams, the runs, executions, revisions, statistics and schedule
+/// of those jobs, the connections and events of those agents, and the agent groups whose members
+/// are all their agents. Anything else answers as if it did not exist. Jobs and agents without a
+/// team, and users in no team, are left to the admins in `WEBUI_ADMIN_USERS`. Only the Prometheus
+/// `/metrics` and alerting rules are not scoped, for the monitoring scraping them.
+pub struct Access {
+    /// The user's teams, sorted.
+    teams: Vec<String>,
+    /// Whether the user sees everything: an admin, or anybody while teams are not scoped.
+    unrestricted: bool,
+    /// Whether the user is one of the admins in `WEBUI_ADMIN_USERS`.
+    admin: bool,
+    /// Whether the user may approve or reject runs, see `WEBUI_APPROVERS`.
+    approver: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Access {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Outcome::Success(Actor(user)) = Actor::from_request(request).await else {
+            unreachable!("Actor is infallible");
+        };
+        Outcome::Success(Access::for_user(user.as_deref()))
+    }
+}
+
+impl Access {
+    fn for_user(user: Option<&str>) -> Self {
+        let teams = get_webui_teams();
+        let mut member_of: Vec<String> = teams
+            .iter()
+            .filter(|(_, members)| user.is_some_and(|user| members.iter().any(|m| m == user)))
+            .map(|(team, _)| team.clone())
+            .collect();
+        member_of.sort();
+        let is_in = |users: &[String]| user.is_some_and(|user| users.iter().any(|u| u == user));
+        let approvers = match get_webui_approvers() {
+            [] => get_webui_admin_users(),
+            approvers => approvers,
+        };
+        let admin = is_in(get_webui_admin_users());
+        Access {
+            teams: member_of,
+            unrestricted: teams.is_empty() || admin,
+            admin,
+            approver: approvers.is_empty() || is_in(approvers),
+        }
+    }
+
+    /// Whether the user is one of the admins in `WEBUI_ADMIN_USERS`. Unlike seeing everything,
+    /// this does not follow from teams being unscoped.
+    pub fn is_admin(&self) -> bool {
+        self.admin
+    }
+
+    /// `filter` narrowed to the jobs or agents the user may see.
+    pub fn scope(&self, filter: Document) -> Document {
+        match self.unrestricted {
+            true => filter,
+            false => doc! { "$and": [filter, { "team": { "$in": &self.teams } }] },
+        }
+    }
+
+    /// `filter` on a collection recording `field` as a job name, e.g. runs, narrowed to the jobs
+    /// the user may see.
+    pub async fn scope_by_job(
+        &self,
+        datastore: &Datastore,
+        field: &str,
+        filter: Document,
+    ) -> Result<Document, (Status, String)> {
+        if self.unrestricted {
+            return Ok(filter);
+        }
+        let collection = datastore
+            .get_collection::<JobV1>("jobs")
+            .await
+            .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+        let names = collection
+            .distinct("name", self.scope(doc! {}))
+            .await
+            .map_err(|e| internal_error("Error fetching jobs", e))?;
+        Ok(doc! { "$and": [filter, { field: { "$in": names } }] })
+    }
+
+    /// `filter` on a collection recording `field` as an agent name, e.g. agent events, narrowed
+    /// to the agents the user may see.
+    pub async fn scope_by_agent(
+        &self,
+        datastore: &Datastore,
+        field: &str,
+        filter: Document,
+    ) -> Result<Document, (Status, String)> {
+        match self.visible_agents(datastore).await? {
+            None => Ok(filter),
+            Some(agents) => Ok(doc! { "$and": [filter, { field: { "$in": agents } }] }),
+        }
+    }
+
+    /// The team new jobs get when none is given: the user's first team while teams are scoped.
+    pub fn default_team(&self) -> Option<String> {
+        match self.unrestricted {
+            true => None,
+            false => self.teams.first().cloned(),
+        }
+    }
+
+    /// Whether the user may give a job or agent to `team`, or to no team when `None`: admins may
+    /// give them to any team, others only to one of their own.
+    pub fn check_assign(&self, team: Option<&str>) -> Result<(), (Status, String)> {
+        match team {
+            _ if self.unrestricted => Ok(()),
+            Some(team) if self.teams.iter().any(|own| own == team) => Ok(()),
+            Some(team) => Err((Status::Forbidden, format!("Not a member of team {}", team))),
+            None => Err((
+                Status::Forbidden,
+                "Only admins may leave the team unassigned".to_string(),
+            )),
+        }
+    }
+
+    /// Fails with `403 Forbidden` unless the user may approve or reject runs.
+    pub fn check_approve(&self) -> Result<(), (Status, String)> {
+        match self.approver {
+            true => Ok(()),
+            false => Err((
+                Status::Forbidden,
+                "Only approvers may approve or reject runs".to_string(),
+            )),
+        }
+    }
+
+    /// Fails with `404 Not Found` unless the job named `name` exists and the user may see it.
+    pub async fn check_job(
+        &self,
+        datastore: &Datastore,
+        name: &str,
+    ) -> Result<(), (Status, String)> {
+        if self.unrestricted {
+            return Ok(());
+        }
+        let collection = datastore
+            .get_collection::<JobV1>("jobs")
+            .await
+            .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+        match collection.find_one(self.scope(doc! { "name": name })).await {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err((Status::NotFound, format!("Job {} not found", name))),
+            Err(e) => Err(internal_error("Error fetching job", e)),
+        }
+    }
+
+    /// Fails with `404 Not Found` unless the agent named `name` exists and the user may see it.
+    pub async fn check_agent(
+        &self,
+        datastore: &Datastore,
+        name: &str,
+    ) -> Result<(), (Status, String)> {
+        if self.unrestricted {
+            return Ok(());
+        }
+        let collection = datastore
+            .get_collection::<AgentV1>("agents")
+            .await
+            .map_err(|e| internal_error("Error accessing agents collection", e))?;
+        match collection.find_one(self.scope(doc! { "name": name })).await {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err((Status::NotFound, "Agent not found".to_string())),
+            Err(e) => Err(internal_error("Error fetching agent", e)),
+        }
+    }
+
+    /// The names of the agents the user may see, or `None` when they see every agent.
+    async fn visible_agents(
+        &self,
+        datastore: &Datastore,
+    ) -> Result<Option<Vec<String>>, (Status, String)> {
+        if self.unrestricted {
+            return Ok(None);
+        }
+        let collection = datastore
+            .get_collection::<AgentV1>("agents")
+            .await
+            .map_err(|e| internal_error("Error accessing agents collection", e))?;
+        let names = collection
+            .distinct("name", self.scope(doc! {}))
+            .await
+            .map_err(|e| internal_error("Error fetching agents", e))?;
+        Ok(Some(
+            names
+                .into_iter()
+                .filter_map(|name| name.as_str().map(str::to_string))
+                .collect(),
+        ))
+    }
+
+    /// `filter` on agent groups narrowed to the groups the user may see: those with no member
+    /// outside the agents the user may see.
+    pub async fn scope_groups(
+        &self,
+        datastore: &Datastore,
+        filter: Document,
+    ) -> Result<Document, (Status, String)> {
+        match self.visible_agents(datastore).await? {
+            None => Ok(filter),
+            Some(agents) => Ok(doc! { "$and": [
+                filter,
+                { "members": { "$not": { "$elemMatch": { "$nin": agents } } } },
+            ] }),
+        }
+    }
+
+    /// Fails with `404 Not Found` unless the agent group named `name` exists and the user may
+    /// see it.
+    pub async fn check_group(
+        &self,
+        datastore: &Datastore,
+        name: &str,
+    ) -> Result<(), (Status, String)> {
+        let collection = datastore
+            .get_collection::<AgentGroupV1>("agent_groups")
+            .await
+            .map_err(|e| internal_error("Error accessing agent groups collection", e))?;
+        let filter = self.scope_groups(datastore, doc! { "name": name }).await?;
+        match collection.find_one(filter).await {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => Err((Status::NotFound, format!("Agent group {} not found", name))),
+            Err(e) => Err(internal_error("Error fetching agent group", e)),
+        }
+    }
+
+    /// Fails with `404 Not Found` unless the user may see each of a job's `agents` and the
+    /// existing ones of its `groups`, so that a job only runs on agents its author may see.
+    /// Groups that do not exist yet are left to the validation warnings.
+    pub async fn check_targets(
+        &self,
+        datastore: &Datastore,
+        agents: &[String],
+        groups: &[String],
+    ) -> Result<(), (Status, String)> {
+        if self.unrestricted {
+            return Ok(());
+        }
+        for agent in agents {
+            self.check_agent(datastore, agent)
+                .await
+                .map_err(|(status, _)| (status, format!("Agent {} not found", agent)))?;
+        }
+        let collection = datastore
+            .get_collection::<AgentGroupV1>("agent_groups")
+            .await
+            .map_err(|e| internal_error("Error accessing agent groups collection", e))?;
+        let existing = collection
+            .distinct("name", doc! { "name": { "$in": groups } })
+            .await
+            .map_err(|e| internal_error("Error fetching agent groups", e))?;
+        for group in existing.iter().filter_map(|name| name.as_str()) {
+            self.check_group(datastore, group).await?;
+        }
+        Ok(())
+    }
+}
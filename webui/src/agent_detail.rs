@@ -0,0 +1,184 @@
+use futures::TryStreamExt;
+use mongodb::bson::{DateTime, Document, doc, oid::ObjectId};
+use rocket::State;
+use rocket::get;
+use rocket::serde::json::Json;
+use rocket_dyn_templates::{Template, context};
+use serde_json::json;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::data_page::with_iso_dates;
+use crate::internal_error;
+use core_logic::datastore::agent_groups::AgentGroupV1;
+use core_logic::datastore::agents::AgentV1;
+use core_logic::datastore::job_executions::JobExecutionV1;
+use core_logic::datastore::jobs::{JobV1, Status};
+use core_logic::datastore::run_stats::{RunStats, StatsInterval};
+
+const DEFAULT_ACTIVITY_DAYS: u32 = 7;
+const MAX_ACTIVITY_DAYS: u32 = 90;
+/// Runs listed on the agent page, newest first.
+const RECENT_RUNS: i64 = 50;
+
+/// Everything about one agent on a single page: its status, version, platform, labels and
+/// uptime, with its current jobs, recent runs and health charts loaded from `agent_activity` and
+/// its connectivity from `agent_events`.
+#[get("/agents/<id>")]
+pub async fn agent_page(state: &State<WebState>, access: Access, id: &str) -> Template {
+    let render = |error: &str, agent: Option<AgentV1>, groups: Vec<String>| {
+        Template::render(
+            "agent",
+            context! {
+                page_name: "Agents",
+                agent_id: id.to_string(),
+                status_name: agent.as_ref().map(|agent| agent.status.name()),
+                last_seen_ms: agent
+                    .as_ref()
+                    .map(|agent| agent.last_ping.timestamp_millis())
+                    .filter(|millis| *millis > 0),
+                // Uptime counts from the start of the current run of heartbeats.
+                up_since_ms: agent
+                    .as_ref()
+                    .filter(|agent| agent.status.is_connected())
+                    .and_then(|agent| agent.heartbeat_since)
+                    .map(|since| since.timestamp_millis()),
+                groups,
+                agent,
+                error: error.to_string(),
+            },
+        )
+    };
+
+    let object_id = match ObjectId::parse_str(id) {
+        Ok(oid) => oid,
+        Err(_) => return render("Invalid agent ID format", None, vec![]),
+    };
+    let agent_collection = match state.datastore.get_collection::<AgentV1>("agents").await {
+        Ok(coll) => coll,
+        Err(_) => return render("Failed to access agents collection", None, vec![]),
+    };
+    let agent = match agent_collection
+        .find_one(access.scope(doc! { "_id": object_id }))
+        .await
+    {
+        Ok(Some(agent)) => agent,
+        Ok(None) => return render("Agent not found", None, vec![]),
+        Err(e) => return render(&format!("Error fetching agent: {}", e), None, vec![]),
+    };
+
+    let groups = match agent.deleted_at {
+        Some(_) => agent.deleted_groups.clone(),
+        None => {
+            AgentGroupV1::names_with_members(&state.datastore, std::slice::from_ref(&agent.name))
+                .await
+                .unwrap_or_else(|e| {
+                    eprintln!("Error fetching groups of agent {}: {}", agent.name, e);
+                    vec![]
+                })
+        }
+    };
+    render("", Some(agent), groups)
+}
+
+/// The jobs running on the agent, its latest runs, and its runs over the last `days` (default 7,
+/// at most 90) counted by outcome per day and per job, for the agent page. Times are ISO 8601.
+#[get("/agents/<name>/activity?<days>")]
+pub async fn agent_activity(
+    state: &State<WebState>,
+    access: Access,
+    name: &str,
+    days: Option<u32>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    access.check_agent(&state.datastore, name).await?;
+    let days = days
+        .unwrap_or(DEFAULT_ACTIVITY_DAYS)
+        .clamp(1, MAX_ACTIVITY_DAYS);
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let jobs: Vec<JobV1> = job_collection
+        .find(access.scope(doc! {
+            "status": Status::Running,
+            "agents_running": name,
+            "agents_complete": { "$ne": name },
+        }))
+        .sort(doc! { "name": 1 })
+        .await
+        .map_err(|e| internal_error("Error fetching jobs", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading jobs", e))?;
+    let mut running = vec![];
+    for job in &jobs {
+        let execution = match &job.cycle_id {
+            Some(cycle_id) => JobExecutionV1::find(&state.datastore, cycle_id)
+                .await
+                .map_err(|e| internal_error("Error fetching job execution", e))?,
+            None => None,
+        };
+        let started_at = execution
+            .map(|execution| execution.started_at)
+            .unwrap_or(DateTime::from_millis(job.next_run.saturating_mul(1000)));
+        running.push(json!({
+            "job_name": job.name,
+            "cycle_id": job.cycle_id,
+            "started_at": started_at,
+        }));
+    }
+
+    let run_collection = state
+        .datastore
+        .get_collection::<Document>("runs")
+        .await
+        .map_err(|e| internal_error("Error accessing runs collection", e))?;
+    let run_filter = access
+        .scope_by_job(&state.datastore, "job_name", doc! { "agent_name": name })
+        .await?;
+    let documents: Vec<Document> = run_collection
+        .find(run_filter)
+        .projection(doc! {
+            "job_name": 1,
+            "run_id": 1,
+            "started_at": 1,
+            "completed_at": 1,
+            "outcome": 1,
+            "return_code": 1,
+        })
+        .sort(doc! { "started_at": -1 })
+        .limit(RECENT_RUNS)
+        .await
+        .map_err(|e| internal_error("Error fetching runs", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading runs", e))?;
+    let runs: Vec<serde_json::Value> = documents
+        .iter()
+        .filter_map(|run| {
+            Some(json!({
+                "job_name": run.get_str("job_name").ok()?,
+                "run_id": run.get_str("run_id").ok(),
+                "started_at": run.get_datetime("started_at").ok()?,
+                "completed_at": run.get_datetime("completed_at").ok()?,
+                "outcome": run.get_i32("outcome").ok(),
+                "return_code": run.get_i32("return_code").ok(),
+            }))
+        })
+        .collect();
+
+    let since = DateTime::from_millis(
+        DateTime::now().timestamp_millis() - days as i64 * StatsInterval::Day.millis(),
+    );
+    let stats = RunStats::for_agent(&state.datastore, name, since, StatsInterval::Day)
+        .await
+        .map_err(|e| internal_error("Error aggregating run stats", e))?;
+
+    Ok(Json(with_iso_dates(json!({
+        "running": running,
+        "runs": runs,
+        "stats": stats,
+    }))))
+}
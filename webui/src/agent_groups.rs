@@ -0,0 +1,291 @@
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use mongodb::options::ReturnDocument;
+use rocket::State;
+use rocket::serde::Deserialize;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post};
+use rocket_dyn_templates::{Template, context};
+use serde_json::json;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::internal_error;
+use core_logic::datastore::agent_groups::AgentGroupV1;
+use core_logic::datastore::agents::AgentV1;
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+use core_logic::datastore::jobs::{JobV1, Status};
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde", deny_unknown_fields)]
+pub struct AgentGroupRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+/// Fails with `BadRequest` naming the first of `agent_names` that is not a known agent, counting
+/// agents in the trash and agents the user may not see as unknown.
+async fn check_agents_exist(
+    state: &State<WebState>,
+    access: &Access,
+    agent_names: &[String],
+) -> Result<(), (rocket::http::Status, String)> {
+    let agent_collection = state
+        .datastore
+        .get_collection::<AgentV1>("agents")
+        .await
+        .map_err(|e| internal_error("Error accessing agents collection", e))?;
+    let known: Vec<AgentV1> = agent_collection
+        .find(access.scope(doc! { "name": { "$in": agent_names }, "deleted_at": null }))
+        .await
+        .map_err(|e| internal_error("Error fetching agents", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading agents", e))?;
+    match agent_names
+        .iter()
+        .find(|name| !known.iter().any(|agent| &agent.name == *name))
+    {
+        Some(name) => Err((
+            rocket::http::Status::BadRequest,
+            format!("Agent {} not found", name),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[get("/agent_groups")]
+pub async fn agent_groups_page() -> Template {
+    Template::render(
+        "agent_groups",
+        context! {
+            page_name: "Agent Groups",
+        },
+    )
+}
+
+/// Every agent group the user may see, by name.
+#[get("/agent_groups/data")]
+pub async fn agent_groups_data(
+    state: &State<WebState>,
+    access: Access,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let filter = access.scope_groups(&state.datastore, doc! {}).await?;
+    let group_collection = state
+        .datastore
+        .get_collection::<AgentGroupV1>("agent_groups")
+        .await
+        .map_err(|e| internal_error("Error accessing agent groups collection", e))?;
+
+    let groups: Vec<AgentGroupV1> = group_collection
+        .find(filter)
+        .sort(doc! { "name": 1 })
+        .await
+        .map_err(|e| internal_error("Error fetching agent groups", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading agent groups", e))?;
+
+    Ok(Json(json!({
+        "items": groups,
+    })))
+}
+
+/// Creates a group, or replaces the description and members of the group with the same name.
+/// While teams are scoped, members are the user's agents and only groups the user may see are
+/// replaced.
+#[post("/agent_groups", data = "<request>")]
+pub async fn post_agent_group(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    request: Json<AgentGroupRequest>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let request = request.into_inner();
+    if request.name.trim().is_empty() {
+        return Err((
+            rocket::http::Status::BadRequest,
+            "Group name is required".to_string(),
+        ));
+    }
+    let mut members: Vec<String> = vec![];
+    for member in request.members {
+        if !member.trim().is_empty() && !members.contains(&member) {
+            members.push(member);
+        }
+    }
+    check_agents_exist(state, &access, &members).await?;
+
+    let group = AgentGroupV1 {
+        id: None,
+        name: request.name,
+        description: request.description,
+        members,
+    };
+    let group_collection = state
+        .datastore
+        .get_collection::<AgentGroupV1>("agent_groups")
+        .await
+        .map_err(|e| internal_error("Error accessing agent groups collection", e))?;
+    let existing = group_collection
+        .find_one(doc! { "name": &group.name })
+        .await
+        .map_err(|e| internal_error("Error fetching agent group", e))?;
+    if existing.is_some() {
+        access.check_group(&state.datastore, &group.name).await?;
+    }
+
+    let previous = group_collection
+        .find_one_and_replace(doc! { "name": &group.name }, &group)
+        .upsert(true)
+        .await
+        .map_err(|e| internal_error("Error saving agent group", e))?;
+
+    let action = match previous {
+        Some(_) => AuditAction::Update,
+        None => AuditAction::Create,
+    };
+    let entry = AuditEntryV1::new(actor.name(), action, AuditResource::AgentGroup, &group.name);
+    audit::record(state, entry.with_diff(previous.as_ref(), Some(&group))).await;
+
+    Ok("Success".to_string())
+}
+
+/// Adds an agent to a group. Jobs targeting the group run on it from their next cycle.
+#[post("/agent_groups/<name>/members/<agent_name>")]
+pub async fn add_agent_group_member(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+    agent_name: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    access.check_group(&state.datastore, name).await?;
+    check_agents_exist(state, &access, &[agent_name.to_string()]).await?;
+    update_members(
+        state,
+        actor,
+        name,
+        doc! { "$addToSet": { "members": agent_name } },
+    )
+    .await
+}
+
+/// Removes an agent from a group. A cycle already running on it is left to finish.
+#[delete("/agent_groups/<name>/members/<agent_name>")]
+pub async fn remove_agent_group_member(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+    agent_name: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    access.check_group(&state.datastore, name).await?;
+    update_members(
+        state,
+        actor,
+        name,
+        doc! { "$pull": { "members": agent_name } },
+    )
+    .await
+}
+
+async fn update_members(
+    state: &State<WebState>,
+    actor: Actor,
+    name: &str,
+    update: mongodb::bson::Document,
+) -> Result<String, (rocket::http::Status, String)> {
+    let group_collection = state
+        .datastore
+        .get_collection::<AgentGroupV1>("agent_groups")
+        .await
+        .map_err(|e| internal_error("Error accessing agent groups collection", e))?;
+
+    let previous = group_collection
+        .find_one(doc! { "name": name })
+        .await
+        .map_err(|e| internal_error("Error fetching agent group", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Agent group {} not found", name),
+            )
+        })?;
+    let updated = group_collection
+        .find_one_and_update(doc! { "name": name }, update)
+        .return_document(ReturnDocument::After)
+        .await
+        .map_err(|e| internal_error("Error updating agent group", e))?;
+
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Update,
+        AuditResource::AgentGroup,
+        name,
+    );
+    audit::record(state, entry.with_diff(Some(&previous), updated.as_ref())).await;
+
+    Ok("Success".to_string())
+}
+
+/// Deletes a group. Groups still targeted by a job that is not archived are kept, since the job
+/// would silently lose those agents.
+#[delete("/agent_groups/<name>")]
+pub async fn delete_agent_group(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    access.check_group(&state.datastore, name).await?;
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let targeting_job = job_collection
+        .find_one(doc! {
+            "agent_groups": name,
+            "status": { "$ne": Status::Archived },
+        })
+        .await
+        .map_err(|e| internal_error("Error fetching jobs", e))?;
+    if let Some(job) = targeting_job {
+        return Err((
+            rocket::http::Status::Conflict,
+            format!("Agent group {} is still targeted by job {}", name, job.name),
+        ));
+    }
+
+    let group_collection = state
+        .datastore
+        .get_collection::<AgentGroupV1>("agent_groups")
+        .await
+        .map_err(|e| internal_error("Error accessing agent groups collection", e))?;
+
+    let deleted = group_collection
+        .find_one_and_delete(doc! { "name": name })
+        .await
+        .map_err(|e| internal_error("Error deleting agent group", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Agent group {} not found", name),
+            )
+        })?;
+
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Delete,
+        AuditResource::AgentGroup,
+        name,
+    );
+    audit::record(state, entry.with_diff(Some(&deleted), None)).await;
+
+    Ok("Success".to_string())
+}
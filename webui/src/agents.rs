@@ -1,3 +1,4 @@
+use futures::TryStreamExt;
 use mongodb::bson::{doc, oid::ObjectId};
 use rocket::State;
 use rocket::form::{Form, FromForm};
@@ -8,10 +9,30 @@ use rocket_dyn_templates::{Template, context};
 use serde_json::json;
 
 use std::collections::HashMap;
+use std::time::Duration;
 
 use crate::WebState;
-use crate::data_page::{DataPage, DataPageParams};
-use core_logic::datastore::agents::AgentV1;
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::data_page::{DataPage, DataPageParams, with_iso_dates};
+use crate::namespaces::Namespace;
+use core_logic::datastore::agent_events::{AgentEventKind, AgentEventV1};
+use core_logic::datastore::agent_groups::AgentGroupV1;
+use core_logic::datastore::agents::{AgentUpdate, AgentV1, PingResult, Status};
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+
+/// Fields the agents page can be sorted and range filtered by.
+const AGENT_SORT_FIELDS: &[&str] = &[
+    "name",
+    "hostname",
+    "status",
+    "last_ping",
+    "port",
+    "agent_version",
+    "os",
+    "arch",
+];
+const AGENT_RANGE_FIELDS: &[&str] = &["last_ping"];
 
 #[derive(FromForm, Debug)]
 pub struct AgentForm {
@@ -19,13 +40,30 @@ pub struct AgentForm {
     pub name: String,
     pub hostname: String,
     pub port: u16,
+    /// Left empty for no owner.
+    pub owner: Option<String>,
+    /// Left empty for no team, or the user's first team while teams are scoped.
+    pub team: Option<String>,
+}
+
+/// `value` trimmed, or `None` when it is empty.
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
 }
 
 #[post("/agents", data = "<form>")]
 pub async fn post_agents(
     state: &State<WebState>,
+    actor: Actor,
+    access: Access,
     form: Form<AgentForm>,
 ) -> Result<String, (rocket::http::Status, String)> {
+    let owner = non_empty(form.owner.as_deref());
+    let team = non_empty(form.team.as_deref()).or_else(|| access.default_team());
+    access.check_assign(team.as_deref())?;
     let agent_collection = state
         .datastore
         .get_collection::<AgentV1>("agents")
@@ -42,14 +80,23 @@ pub async fn post_agents(
             name: form.name.clone(),
             hostname: form.hostname.clone(),
             port: form.port,
+            owner,
+            team,
             ..Default::default()
         };
-        agent_collection.insert_one(new_agent).await.map_err(|e| {
+        agent_collection.insert_one(&new_agent).await.map_err(|e| {
             (
                 rocket::http::Status::InternalServerError,
                 format!("Error inserting agent: {}", e),
             )
         })?;
+        let entry = AuditEntryV1::new(
+            actor.name(),
+            AuditAction::Create,
+            AuditResource::Agent,
+            &new_agent.name,
+        );
+        audit::record(state, entry.with_diff(None, Some(&new_agent))).await;
     } else {
         let object_id = ObjectId::parse_str(&form.id).map_err(|_| {
             (
@@ -58,7 +105,7 @@ pub async fn post_agents(
             )
         })?;
         let agent = agent_collection
-            .find_one(doc! { "_id": object_id })
+            .find_one(access.scope(doc! { "_id": object_id }))
             .await
             .map_err(|e| {
                 (
@@ -66,7 +113,7 @@ pub async fn post_agents(
                     format!("Error fetching agent: {}", e),
                 )
             })?;
-        agent.ok_or((
+        let agent = agent.ok_or((
             rocket::http::Status::NotFound,
             "Agent not found".to_string(),
         ))?;
@@ -75,6 +122,8 @@ pub async fn post_agents(
                 "name": &form.name,
                 "hostname": &form.hostname,
                 "port": form.port as i32,
+                "owner": &owner,
+                "team": &team,
             }
         };
         agent_collection
@@ -86,14 +135,341 @@ pub async fn post_agents(
                     format!("Error updating agent: {}", e),
                 )
             })?;
+        let updated = AgentV1 {
+            name: form.name.clone(),
+            hostname: form.hostname.clone(),
+            port: form.port,
+            owner,
+            team,
+            ..agent.clone()
+        };
+        let entry = AuditEntryV1::new(
+            actor.name(),
+            AuditAction::Update,
+            AuditResource::Agent,
+            &agent.name,
+        );
+        audit::record(state, entry.with_diff(Some(&agent), Some(&updated))).await;
     };
 
     Ok("Success".to_string())
 }
 
+#[derive(FromForm, Debug)]
+pub struct AgentUpdateForm {
+    pub id: String,
+    pub version: String,
+    pub url: String,
+    pub checksum: String,
+}
+
+/// Records an update for the agent; central command pushes it the next time it checks.
+#[post("/agents/update", data = "<form>")]
+pub async fn post_agent_update(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    form: Form<AgentUpdateForm>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let agent_collection = state
+        .datastore
+        .get_collection::<AgentV1>("agents")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing agents collection: {}", e),
+            )
+        })?;
+
+    let object_id = ObjectId::parse_str(&form.id).map_err(|_| {
+        (
+            rocket::http::Status::BadRequest,
+            "Invalid agent ID format".to_string(),
+        )
+    })?;
+
+    if form.version.trim().is_empty() || form.url.trim().is_empty() {
+        return Err((
+            rocket::http::Status::BadRequest,
+            "Version and URL are required".to_string(),
+        ));
+    }
+    if form.checksum.trim().trim_start_matches("sha256:").len() != 64 {
+        return Err((
+            rocket::http::Status::BadRequest,
+            "Checksum must be a hex encoded SHA-256".to_string(),
+        ));
+    }
+
+    let update = AgentUpdate {
+        version: form.version.trim().to_string(),
+        url: form.url.trim().to_string(),
+        checksum: form.checksum.trim().to_string(),
+    };
+    let update_doc = doc! {
+        "$set": {
+            "pending_update": bson::to_bson(&update).map_err(|e| {
+                (
+                    rocket::http::Status::InternalServerError,
+                    format!("Error serializing update: {}", e),
+                )
+            })?,
+        }
+    };
+    let agent = agent_collection
+        .find_one_and_update(access.scope(doc! { "_id": object_id }), update_doc)
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error updating agent: {}", e),
+            )
+        })?
+        .ok_or((
+            rocket::http::Status::NotFound,
+            "Agent not found".to_string(),
+        ))?;
+
+    let updated = AgentV1 {
+        pending_update: Some(update),
+        ..agent.clone()
+    };
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Update,
+        AuditResource::Agent,
+        &agent.name,
+    );
+    audit::record(state, entry.with_diff(Some(&agent), Some(&updated))).await;
+
+    Ok("Update scheduled".to_string())
+}
+
+/// Sets whether the agent is draining (default: `true`). A draining agent is not given new jobs;
+/// central command marks it `Draining` while it stays reachable.
+#[post("/agents/<name>/drain?<enabled>")]
+pub async fn drain_agent(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+    enabled: Option<bool>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let enabled = enabled.unwrap_or(true);
+    let agent_collection = state
+        .datastore
+        .get_collection::<AgentV1>("agents")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing agents collection: {}", e),
+            )
+        })?;
+
+    // Reflect the change right away rather than on the next heartbeat, leaving agents that are
+    // not reachable as they are.
+    let status = match enabled {
+        true => doc! { "$cond": [
+            { "$in": ["$status", [Status::Online as i32, Status::Degraded as i32]] },
+            Status::Draining as i32,
+            "$status",
+        ] },
+        false => doc! { "$cond": [
+            { "$eq": ["$status", Status::Draining as i32] },
+            Status::Online as i32,
+            "$status",
+        ] },
+    };
+    let update = vec![doc! { "$set": { "draining": enabled, "status": status } }];
+    let agent = agent_collection
+        .find_one_and_update(access.scope(doc! { "name": name }), update)
+        .return_document(mongodb::options::ReturnDocument::Before)
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error updating agent: {}", e),
+            )
+        })?
+        .ok_or((
+            rocket::http::Status::NotFound,
+            "Agent not found".to_string(),
+        ))?;
+
+    if agent.draining != enabled {
+        let kind = match enabled {
+            true => AgentEventKind::Drained,
+            false => AgentEventKind::Undrained,
+        };
+        let event =
+            AgentEventV1::new(&agent.name, kind).with_detail(format!("by {}", actor.name()));
+        if let Err(e) = event.insert_entry(&state.datastore).await {
+            eprintln!(
+                "Error recording {} event for agent {}: {}",
+                kind, agent.name, e
+            );
+        }
+    }
+
+    let updated = AgentV1 {
+        draining: enabled,
+        ..agent.clone()
+    };
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Update,
+        AuditResource::Agent,
+        &agent.name,
+    );
+    audit::record(state, entry.with_diff(Some(&agent), Some(&updated))).await;
+
+    Ok(match enabled {
+        true => format!("Agent {} is draining", agent.name),
+        false => format!("Agent {} is accepting jobs", agent.name),
+    })
+}
+
+const PING_WAIT_SECS: u64 = 10; // How long to wait for central command to answer a ping request
+const PING_POLL_MILLIS: u64 = 250;
+
+/// Asks central command to ping the agent and waits for the round-trip time or error.
+#[post("/agents/<name>/ping")]
+pub async fn ping_agent(
+    state: &State<WebState>,
+    access: Access,
+    name: &str,
+) -> Result<Json<PingResult>, (rocket::http::Status, String)> {
+    let agent_collection = state
+        .datastore
+        .get_collection::<AgentV1>("agents")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing agents collection: {}", e),
+            )
+        })?;
+
+    let requested_at = bson::DateTime::now();
+    let result = agent_collection
+        .update_one(
+            access.scope(doc! { "name": name }),
+            doc! { "$set": { "ping_requested_at": requested_at } },
+        )
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error requesting ping: {}", e),
+            )
+        })?;
+    if result.matched_count == 0 {
+        return Err((
+            rocket::http::Status::NotFound,
+            "Agent not found".to_string(),
+        ));
+    }
+
+    let polls = PING_WAIT_SECS * 1000 / PING_POLL_MILLIS;
+    for _ in 0..polls {
+        rocket::tokio::time::sleep(Duration::from_millis(PING_POLL_MILLIS)).await;
+        let agent = agent_collection
+            .find_one(doc! { "name": name })
+            .await
+            .map_err(|e| {
+                (
+                    rocket::http::Status::InternalServerError,
+                    format!("Error fetching agent: {}", e),
+                )
+            })?;
+        if let Some(ping_result) = agent.and_then(|agent| agent.ping_result)
+            && ping_result.requested_at == requested_at
+        {
+            return Ok(Json(ping_result));
+        }
+    }
+
+    Err((
+        rocket::http::Status::GatewayTimeout,
+        "Central command did not answer the ping request".to_string(),
+    ))
+}
+
+const DEFAULT_EVENT_DAYS: u32 = 7;
+
+/// The agent's lifecycle events over the last `days` (default: 7), oldest first, with the
+/// connectivity event before the window so the timeline knows the agent's state at its start.
+#[get("/agents/<name>/events?<days>")]
+pub async fn agent_events(
+    state: &State<WebState>,
+    access: Access,
+    name: &str,
+    days: Option<u32>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    access.check_agent(&state.datastore, name).await?;
+    let event_collection = state
+        .datastore
+        .get_collection::<AgentEventV1>("agent_events")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing agent events collection: {}", e),
+            )
+        })?;
+
+    let days = days.unwrap_or(DEFAULT_EVENT_DAYS);
+    let since = bson::DateTime::from_millis(
+        bson::DateTime::now().timestamp_millis() - days as i64 * 24 * 60 * 60 * 1000,
+    );
+
+    let previous = event_collection
+        .find_one(doc! {
+            "agent_name": name,
+            "at": { "$lt": since },
+            "kind": {
+                "$in": [AgentEventKind::Connected.name(), AgentEventKind::Disconnected.name()],
+            },
+        })
+        .sort(doc! { "at": -1 })
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error fetching agent events: {}", e),
+            )
+        })?;
+    let events: Vec<AgentEventV1> = event_collection
+        .find(doc! { "agent_name": name, "at": { "$gte": since } })
+        .sort(doc! { "at": 1 })
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error fetching agent events: {}", e),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading agent events: {}", e),
+            )
+        })?;
+
+    Ok(Json(with_iso_dates(json!({
+        "since": since,
+        "previous": previous,
+        "items": events,
+    }))))
+}
+
 #[allow(clippy::too_many_arguments)]
 #[get(
-    "/agents?<page>&<relative_select>&<relative_select_unit>&<relative_select_value>&<range_start>&<range_end>&<filter>&<sort>&<status_filter>"
+    "/agents?<page>&<relative_select>&<relative_select_unit>&<relative_select_value>&<range_start>&<range_end>&<filter>&<sort>&<status_filter>&<team_filter>"
 )]
 pub async fn agents_page(
     page: Option<u32>,
@@ -104,6 +480,7 @@ pub async fn agents_page(
     range_end: Option<u64>, // range_end is not used in agents_page, but required for data_page
     filter: Option<String>,
     status_filter: Option<String>,
+    team_filter: Option<String>,
     sort: Option<String>,
 ) -> Template {
     Template::render(
@@ -112,24 +489,28 @@ pub async fn agents_page(
             sort: sort.unwrap_or_default(),
             range_start: range_start.unwrap_or_default(),
             range_end: range_end.unwrap_or_default(),
-            range_fields: vec!["last_ping".to_string()], // Assuming last_ping is the field for range filtering
+            range_fields: AGENT_RANGE_FIELDS,
             relative_select: relative_select.unwrap_or_default(),
             relative_select_value: relative_select_value.unwrap_or(30),
             relative_select_unit: relative_select_unit.unwrap_or_default(),
             current_page: page,
             filter: filter.unwrap_or_default(),
             page_name: "Agents",
+            saved_filters_page: "agents",
             status_filter,
+            team_filter: team_filter.unwrap_or_default(),
         },
     )
 }
 
 #[allow(clippy::too_many_arguments)]
 #[get(
-    "/agents/data?<page>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<sort>&<order>&<status_filter>"
+    "/agents/data?<page>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<sort>&<order>&<status_filter>&<team_filter>&<page_size>&<after>"
 )]
 pub async fn agents_data(
     state: &State<WebState>,
+    access: Access,
+    namespace: Namespace,
     page: Option<u32>,
     relative_select: Option<String>,
     relative_select_value: Option<u8>,
@@ -140,10 +521,19 @@ pub async fn agents_data(
     sort: Option<String>,
     order: Option<String>,
     status_filter: Option<String>,
-) -> Json<serde_json::Value> {
+    team_filter: Option<String>,
+    page_size: Option<u32>,
+    after: Option<String>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    // Trashed agents are listed in the trash instead.
+    let mut base_filter = doc! { "deleted_at": null };
+    if let Some(team_filter) = team_filter.filter(|team_filter| !team_filter.is_empty()) {
+        base_filter.insert("team", team_filter);
+    }
     let data_page_params = DataPageParams {
         collection: "agents".to_string(),
         range_field: Some("last_ping".to_string()), // Assuming last_ping is the field for range filtering
+        range_fields: AGENT_RANGE_FIELDS,
         range_start,
         range_end,
         search_fields: vec![
@@ -152,46 +542,50 @@ pub async fn agents_data(
             "last_ping".to_string(),
             "status".to_string(),
             "port".to_string(),
+            "container.node_name".to_string(),
+            "container.pod_name".to_string(),
         ],
-        additional_filters: if status_filter.is_some() {
-            let mut filters = HashMap::new();
-            filters.insert("status".to_string(), status_filter.unwrap());
-            Some(filters)
-        } else {
-            None
-        },
+        additional_filters: status_filter
+            .filter(|status_filter| !status_filter.is_empty())
+            .map(|status_filter| {
+                // Accept a status name such as `draining` as well as its stored number.
+                let status_filter = match Status::from_name(&status_filter) {
+                    Some(status) => (status as i32).to_string(),
+                    None => status_filter,
+                };
+                HashMap::from([("status".to_string(), status_filter)])
+            }),
         page,
         filter: filter.clone(),
+        base_filter: Some(access.scope(namespace.scope(base_filter))),
         sort: sort.clone(),
+        sort_fields: AGENT_SORT_FIELDS,
         order,
         relative_select,
         relative_value: relative_select_value.map(|v| v as u64),
         relative_unit: relative_select_unit,
+        page_size,
+        after,
     };
 
-    let runs_page: DataPage<AgentV1> = DataPage::new(state, data_page_params).await;
-
-    let DataPage {
-        items: runs,
-        total_pages,
-        current_page: page,
-    } = runs_page;
+    let agents_page: DataPage<AgentV1> = DataPage::new(state, data_page_params).await?;
 
-    Json(json!({
-        "items": runs,
-        "total_pages": total_pages,
-        "current_page": page,
-    }))
+    Ok(Json(agents_page.json()))
 }
 
 #[get("/agents/edit?<id>")]
-pub async fn edit_agent(state: &State<WebState>, id: &str) -> Template {
+pub async fn edit_agent(state: &State<WebState>, access: Access, id: &str) -> Template {
     let render = |error: &str, agent: Option<AgentV1>| {
         Template::render(
             "edit_agent",
             context! {
                 page_name: "Edit Agent",
                 agent_id: id.to_string(),
+                status_name: agent.as_ref().map(|agent| agent.status.name()),
+                last_seen_ms: agent
+                    .as_ref()
+                    .map(|agent| agent.last_ping.timestamp_millis())
+                    .filter(|millis| *millis > 0),
                 agent,
                 error: error.to_string(),
             },
@@ -208,7 +602,10 @@ pub async fn edit_agent(state: &State<WebState>, id: &str) -> Template {
         Err(_) => return render("Invalid agent ID format", None),
     };
 
-    match agent_collection.find_one(doc! { "_id": object_id }).await {
+    match agent_collection
+        .find_one(access.scope(doc! { "_id": object_id }))
+        .await
+    {
         Ok(Some(agent)) => render("", Some(agent)),
         Ok(None) => render("Agent not found", None),
         Err(e) => render(&format!("Error fetching agent: {}", e), None),
@@ -225,9 +622,63 @@ pub async fn add_agent(_state: &State<WebState>) -> Template {
     )
 }
 
+/// Moves the agent to the trash, taking it out of its agent groups so jobs targeting the groups
+/// stop waiting for it. The groups are recorded to put it back in when it is restored.
+async fn trash_agent(
+    state: &State<WebState>,
+    actor: &Actor,
+    agent_collection: &mongodb::Collection<AgentV1>,
+    agent: &AgentV1,
+) -> Result<(), (rocket::http::Status, String)> {
+    let groups =
+        AgentGroupV1::names_with_members(&state.datastore, std::slice::from_ref(&agent.name))
+            .await
+            .map_err(|e| {
+                (
+                    rocket::http::Status::InternalServerError,
+                    format!("Error fetching agent groups: {}", e),
+                )
+            })?;
+    let deleted_at = mongodb::bson::DateTime::now();
+    let result = agent_collection
+        .update_one(
+            doc! { "_id": agent.id, "deleted_at": null },
+            doc! { "$set": { "deleted_at": deleted_at, "deleted_groups": &groups } },
+        )
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error deleting agent: {}", e),
+            )
+        })?;
+    if result.modified_count == 0 {
+        return Ok(());
+    }
+    if let Err(e) = AgentGroupV1::remove_member_everywhere(&state.datastore, &agent.name).await {
+        eprintln!("Error removing agent {} from its groups: {}", agent.name, e);
+    }
+
+    let deleted = AgentV1 {
+        deleted_at: Some(deleted_at),
+        deleted_groups: groups,
+        ..agent.clone()
+    };
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Delete,
+        AuditResource::Agent,
+        &agent.name,
+    );
+    audit::record(state, entry.with_diff(Some(agent), Some(&deleted))).await;
+    Ok(())
+}
+
 #[delete("/agents/<id>")]
 pub async fn delete_agent(
     state: &State<WebState>,
+    actor: Actor,
+    access: Access,
     id: &str,
 ) -> Result<String, (rocket::http::Status, String)> {
     let agent_collection = state
@@ -248,15 +699,18 @@ pub async fn delete_agent(
         )
     })?;
 
-    agent_collection
-        .delete_one(doc! { "_id": object_id })
+    let agent = agent_collection
+        .find_one(access.scope(doc! { "_id": object_id }))
         .await
         .map_err(|e| {
             (
                 rocket::http::Status::InternalServerError,
-                format!("Error deleting agent: {}", e),
+                format!("Error fetching agent: {}", e),
             )
         })?;
+    if let Some(agent) = agent {
+        trash_agent(state, &actor, &agent_collection, &agent).await?;
+    }
 
     Ok("Success".to_string())
 }
@@ -269,6 +723,8 @@ pub struct DeleteAgentsRequest {
 #[delete("/agents", data = "<ids_json>")]
 pub async fn delete_agents_bulk(
     state: &State<WebState>,
+    actor: Actor,
+    access: Access,
     ids_json: Json<DeleteAgentsRequest>,
 ) -> Result<String, (rocket::http::Status, String)> {
     let agent_collection = state
@@ -292,15 +748,27 @@ pub async fn delete_agents_bulk(
         )
     })?;
 
-    agent_collection
-        .delete_many(doc! { "_id": { "$in": object_ids } })
+    let agents: Vec<AgentV1> = agent_collection
+        .find(access.scope(doc! { "_id": { "$in": &object_ids } }))
         .await
         .map_err(|e| {
             (
                 rocket::http::Status::InternalServerError,
-                format!("Error deleting agents: {}", e),
+                format!("Error fetching agents: {}", e),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading agents: {}", e),
             )
         })?;
 
+    for agent in &agents {
+        trash_agent(state, &actor, &agent_collection, agent).await?;
+    }
+
     Ok("Success".to_string())
 }
@@ -0,0 +1,207 @@
+use bson::oid::ObjectId;
+use mongodb::bson::doc;
+use rocket::State;
+use rocket::serde::json::Json;
+use rocket::{get, post};
+use rocket_dyn_templates::{Template, context};
+
+use std::collections::HashMap;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::data_page::{DataPage, DataPageParams};
+use crate::internal_error;
+use core_logic::datastore::alerts::{AlertStatus, AlertV1};
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+
+/// Fields the alerts page can be sorted and range filtered by.
+const ALERT_SORT_FIELDS: &[&str] = &["last_at", "first_at", "occurrences"];
+const ALERT_RANGE_FIELDS: &[&str] = &["last_at", "first_at"];
+
+#[allow(clippy::too_many_arguments)]
+#[get(
+    "/alerts?<page>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<status_filter>&<kind_filter>"
+)]
+pub async fn alerts_page(
+    page: Option<u32>,
+    relative_select: Option<String>,
+    relative_select_value: Option<u8>,
+    relative_select_unit: Option<String>,
+    range_start: Option<u64>,
+    range_end: Option<u64>,
+    filter: Option<String>,
+    status_filter: Option<String>,
+    kind_filter: Option<String>,
+) -> Template {
+    Template::render(
+        "alerts",
+        context! {
+            page: page.unwrap_or(1),
+            range_start: range_start.unwrap_or_default(),
+            range_end: range_end.unwrap_or_default(),
+            range_fields: ALERT_RANGE_FIELDS,
+            relative_select: relative_select.unwrap_or_default(),
+            relative_select_value: relative_select_value.unwrap_or(30),
+            relative_select_unit: relative_select_unit.unwrap_or_default(),
+            filter: filter.unwrap_or_default(),
+            status_filter: status_filter.unwrap_or_default(),
+            kind_filter: kind_filter.unwrap_or_default(),
+            page_name: "Alerts",
+        },
+    )
+}
+
+/// Alerts of the jobs the user may see, most recently raised first. `status_filter` is a status,
+/// `all`, or empty for the alerts not yet resolved.
+#[allow(clippy::too_many_arguments)]
+#[get(
+    "/alerts/data?<page>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<status_filter>&<kind_filter>&<sort>&<order>&<page_size>&<after>"
+)]
+pub async fn alerts_data(
+    state: &State<WebState>,
+    access: Access,
+    page: Option<u32>,
+    relative_select: Option<String>,
+    relative_select_value: Option<u8>,
+    relative_select_unit: Option<String>,
+    range_start: Option<u64>,
+    range_end: Option<u64>,
+    filter: Option<String>,
+    status_filter: Option<String>,
+    kind_filter: Option<String>,
+    sort: Option<String>,
+    order: Option<String>,
+    page_size: Option<u32>,
+    after: Option<String>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let status_filter = status_filter.unwrap_or_default();
+    let base_filter = match status_filter.as_str() {
+        "" => doc! { "status": { "$ne": AlertStatus::Resolved.name() } },
+        _ => doc! {},
+    };
+    let base_filter = access
+        .scope_by_job(&state.datastore, "job_name", base_filter)
+        .await?;
+    let mut additional_filters = HashMap::new();
+    if !status_filter.is_empty() && status_filter != "all" {
+        additional_filters.insert("status".to_string(), status_filter);
+    }
+    if let Some(kind_filter) = kind_filter.filter(|kind_filter| !kind_filter.is_empty()) {
+        additional_filters.insert("kind".to_string(), kind_filter);
+    }
+    let data_page_params = DataPageParams {
+        collection: "alerts".to_string(),
+        range_field: Some("last_at".to_string()),
+        range_fields: ALERT_RANGE_FIELDS,
+        range_start,
+        range_end,
+        search_fields: vec![
+            "job_name".to_string(),
+            "agent_name".to_string(),
+            "detail".to_string(),
+            "acknowledged_by".to_string(),
+        ],
+        additional_filters: (!additional_filters.is_empty()).then_some(additional_filters),
+        page,
+        filter,
+        base_filter: (!base_filter.is_empty()).then_some(base_filter),
+        sort: Some(sort.unwrap_or_else(|| "last_at".to_string())),
+        sort_fields: ALERT_SORT_FIELDS,
+        order: Some(order.unwrap_or_else(|| "desc".to_string())),
+        relative_select,
+        relative_value: relative_select_value.map(|v| v as u64),
+        relative_unit: relative_select_unit,
+        page_size,
+        after,
+    };
+
+    let alerts_page: DataPage<AlertV1> = DataPage::new(state, data_page_params).await?;
+
+    Ok(Json(alerts_page.json()))
+}
+
+/// The alert `id`, if it exists and concerns a job the user may see.
+async fn alert_or_not_found(
+    state: &State<WebState>,
+    access: &Access,
+    id: &str,
+) -> Result<ObjectId, (rocket::http::Status, String)> {
+    let not_found = || {
+        (
+            rocket::http::Status::NotFound,
+            format!("Alert {} not found", id),
+        )
+    };
+    let object_id = ObjectId::parse_str(id).map_err(|_| not_found())?;
+    let filter = access
+        .scope_by_job(&state.datastore, "job_name", doc! { "_id": object_id })
+        .await?;
+    let collection = state
+        .datastore
+        .get_collection::<AlertV1>("alerts")
+        .await
+        .map_err(|e| internal_error("Error accessing alerts collection", e))?;
+    collection
+        .find_one(filter)
+        .await
+        .map_err(|e| internal_error("Error fetching alert", e))?
+        .ok_or_else(not_found)?;
+    Ok(object_id)
+}
+
+/// Acknowledges an open alert, so its repeats are counted without being sent to the webhooks
+/// until it is resolved.
+#[post("/alerts/<id>/acknowledge")]
+pub async fn acknowledge_alert(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    id: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let object_id = alert_or_not_found(state, &access, id).await?;
+    AlertV1::acknowledge(&state.datastore, object_id, actor.name())
+        .await
+        .map_err(|e| internal_error("Error acknowledging alert", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::Conflict,
+                format!("Alert {} is not open", id),
+            )
+        })?;
+
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Acknowledge,
+        AuditResource::Alert,
+        id,
+    );
+    audit::record(state, entry).await;
+
+    Ok("Alert acknowledged".to_string())
+}
+
+/// Resolves an alert; the next occurrence of its problem opens a new one.
+#[post("/alerts/<id>/resolve")]
+pub async fn resolve_alert(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    id: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let object_id = alert_or_not_found(state, &access, id).await?;
+    AlertV1::resolve(&state.datastore, object_id, actor.name())
+        .await
+        .map_err(|e| internal_error("Error resolving alert", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::Conflict,
+                format!("Alert {} was already resolved", id),
+            )
+        })?;
+
+    let entry = AuditEntryV1::new(actor.name(), AuditAction::Resolve, AuditResource::Alert, id);
+    audit::record(state, entry).await;
+
+    Ok("Alert resolved".to_string())
+}
@@ -0,0 +1,599 @@
+use futures::{StreamExt, TryStreamExt};
+use mongodb::bson::doc;
+use rocket::State;
+use rocket::http::{ContentType, Header};
+use rocket::serde::json::Json;
+use rocket::{Responder, get, post};
+
+use std::fmt::Write;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::job_revisions::record_revision;
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+use core_logic::datastore::job_changes::{FieldChange, JobChangeKind, JobChangeV1};
+use core_logic::datastore::jobs::{JobSla, JobV1, Status, next_run_datetime};
+use core_logic::datastore::runs::RunsV1;
+
+/// Name of the rule group in the generated rules file.
+const RULE_GROUP: &str = "rust_action_dispatch";
+const DEFAULT_SEVERITY: &str = "warning";
+
+#[derive(Responder)]
+#[response(content_type = "text/yaml")]
+pub struct AlertRulesFile {
+    body: String,
+    disposition: Header<'static>,
+}
+
+/// Escapes a Prometheus label value, for both the exposition format and PromQL selectors.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Quotes `value` as a YAML double quoted scalar, whose escapes are a superset of JSON's.
+fn yaml_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+fn write_rule(
+    rules: &mut String,
+    alert: &str,
+    expr: &str,
+    job_name: &str,
+    severity: &str,
+    summary: &str,
+) {
+    let _ = writeln!(rules, "      - alert: {}", yaml_string(alert));
+    let _ = writeln!(rules, "        expr: {}", yaml_string(expr));
+    let _ = writeln!(rules, "        labels:");
+    let _ = writeln!(rules, "          severity: {}", yaml_string(severity));
+    let _ = writeln!(rules, "          job_name: {}", yaml_string(job_name));
+    let _ = writeln!(rules, "        annotations:");
+    let _ = writeln!(rules, "          summary: {}", yaml_string(summary));
+}
+
+/// Builds a Prometheus rules file with one rule per limit set in the jobs' SLAs. The rules
+/// evaluate the metrics served by `/metrics`.
+pub fn alert_rules(jobs: &[JobV1]) -> String {
+    let mut rules = String::new();
+    let _ = writeln!(rules, "groups:");
+    let _ = writeln!(rules, "  - name: {}", yaml_string(RULE_GROUP));
+    let _ = writeln!(rules, "    rules:");
+
+    let mut rule_count = 0;
+    for job in jobs {
+        let Some(sla) = job.sla.as_ref() else {
+            continue;
+        };
+        let selector = format!("{{job_name=\"{}\"}}", escape_label(&job.name));
+        let severity = sla.severity.as_deref().unwrap_or(DEFAULT_SEVERITY);
+        if let Some(seconds) = sla.expected_duration {
+            write_rule(
+                &mut rules,
+                "RadJobDurationExceeded",
+                &format!(
+                    "rad_job_last_run_duration_seconds{} > {}",
+                    selector, seconds
+                ),
+                &job.name,
+                severity,
+                &format!("Job {} took longer than {} seconds", job.name, seconds),
+            );
+            rule_count += 1;
+        }
+        if let Some(seconds) = sla.max_success_interval {
+            write_rule(
+                &mut rules,
+                "RadJobSuccessOverdue",
+                &format!(
+                    "time() - rad_job_last_success_timestamp_seconds{} > {}",
+                    selector, seconds
+                ),
+                &job.name,
+                severity,
+                &format!(
+                    "Job {} has not succeeded in the last {} seconds",
+                    job.name, seconds
+                ),
+            );
+            rule_count += 1;
+        }
+        if let Some(seconds) = sla.max_start_delay {
+            write_rule(
+                &mut rules,
+                "RadJobOverdue",
+                &format!("rad_job_overdue_seconds{} > {}", selector, seconds),
+                &job.name,
+                severity,
+                &format!(
+                    "Job {} has not started within {} seconds of its scheduled run",
+                    job.name, seconds
+                ),
+            );
+            rule_count += 1;
+        }
+    }
+    if rule_count == 0 {
+        // Prometheus rejects a group whose `rules` is null.
+        rules = rules.replace("    rules:\n", "    rules: []\n");
+    }
+    rules
+}
+
+/// Jobs that have an SLA, ordered by name.
+async fn jobs_with_sla(
+    state: &State<WebState>,
+) -> Result<Vec<JobV1>, (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing jobs collection: {}", e),
+            )
+        })?;
+
+    job_collection
+        .find(doc! { "sla": { "$exists": true, "$ne": null } })
+        .sort(doc! { "name": 1 })
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error fetching jobs: {}", e),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading jobs: {}", e),
+            )
+        })
+}
+
+/// Downloads the Prometheus alerting rules generated from the job SLAs.
+#[get("/alert_rules.yml")]
+pub async fn alert_rules_file(
+    state: &State<WebState>,
+) -> Result<AlertRulesFile, (rocket::http::Status, String)> {
+    let jobs = jobs_with_sla(state).await?;
+    Ok(AlertRulesFile {
+        body: alert_rules(&jobs),
+        disposition: Header::new(
+            "Content-Disposition",
+            "attachment; filename=\"rust_action_dispatch_rules.yml\"",
+        ),
+    })
+}
+
+/// Upper bounds, in seconds, of the `rad_scheduling_lag_seconds` histogram buckets.
+const SCHEDULING_LAG_BUCKETS: [f64; 8] = [0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// Prometheus metrics about the dispatcher keeping up with the schedule: the jobs due but not yet
+/// dispatched and how long each has been waiting, each job's latest scheduling lag, and the
+/// distribution of the lag over every cycle with runs still stored.
+async fn scheduling_metrics(
+    state: &State<WebState>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing jobs collection: {}", e),
+            )
+        })?;
+    let run_collection = state
+        .datastore
+        .get_collection::<RunsV1>("runs")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing runs collection: {}", e),
+            )
+        })?;
+
+    let now = chrono::Utc::now().timestamp();
+    let due: Vec<JobV1> = job_collection
+        .find(doc! {
+            "status": Status::Pending,
+            "next_run": { "$lte": next_run_datetime(now) },
+        })
+        .sort(doc! { "name": 1 })
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error fetching due jobs: {}", e),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading due jobs: {}", e),
+            )
+        })?;
+    let mut metrics = format!(
+        "# HELP rad_dispatch_queue_depth Jobs that are due but have not been dispatched yet.\n\
+         # TYPE rad_dispatch_queue_depth gauge\n\
+         rad_dispatch_queue_depth {}\n",
+        due.len()
+    );
+    metrics.push_str(
+        "# HELP rad_job_overdue_seconds How long ago the job's scheduled run that has not started was due.\n\
+         # TYPE rad_job_overdue_seconds gauge\n",
+    );
+    for job in &due {
+        if let Some(overdue) = job.overdue_seconds(now) {
+            let _ = writeln!(
+                metrics,
+                "rad_job_overdue_seconds{{job_name=\"{}\"}} {}",
+                escape_label(&job.name),
+                overdue
+            );
+        }
+    }
+
+    let jobs: Vec<JobV1> = job_collection
+        .find(doc! { "scheduling_lag_ms": { "$exists": true } })
+        .sort(doc! { "name": 1 })
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error fetching jobs: {}", e),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading jobs: {}", e),
+            )
+        })?;
+    metrics.push_str(
+        "# HELP rad_job_scheduling_lag_seconds How long after it was due the job's latest cycle was dispatched.\n\
+         # TYPE rad_job_scheduling_lag_seconds gauge\n",
+    );
+    for job in &jobs {
+        if let Some(lag_ms) = job.scheduling_lag_ms {
+            let _ = writeln!(
+                metrics,
+                "rad_job_scheduling_lag_seconds{{job_name=\"{}\"}} {}",
+                escape_label(&job.name),
+                lag_ms as f64 / 1000.0
+            );
+        }
+    }
+
+    // One lag per cycle, not per agent run.
+    let lag_ms = "$scheduling_lag_ms";
+    let mut histogram = doc! {
+        "_id": null,
+        "count": { "$sum": 1 },
+        "sum": { "$sum": lag_ms },
+    };
+    for (i, bound) in SCHEDULING_LAG_BUCKETS.iter().enumerate() {
+        histogram.insert(
+            format!("bucket_{}", i),
+            doc! { "$sum": { "$cond": [{ "$lte": [lag_ms, bound * 1000.0] }, 1, 0] } },
+        );
+    }
+    let pipeline = vec![
+        doc! { "$match": { "scheduling_lag_ms": { "$exists": true } } },
+        doc! { "$group": {
+            "_id": { "$ifNull": ["$cycle_id", "$_id"] },
+            "scheduling_lag_ms": { "$first": lag_ms },
+        } },
+        doc! { "$group": histogram },
+    ];
+    let summary = run_collection
+        .aggregate(pipeline)
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error aggregating runs: {}", e),
+            )
+        })?
+        .next()
+        .await
+        .transpose()
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading runs: {}", e),
+            )
+        })?
+        .unwrap_or_default();
+    let number = |key: &str| -> f64 {
+        match summary.get(key) {
+            Some(bson::Bson::Int32(value)) => *value as f64,
+            Some(bson::Bson::Int64(value)) => *value as f64,
+            Some(bson::Bson::Double(value)) => *value,
+            _ => 0.0,
+        }
+    };
+    metrics.push_str(
+        "# HELP rad_scheduling_lag_seconds How long after they were due job cycles were dispatched.\n\
+         # TYPE rad_scheduling_lag_seconds histogram\n",
+    );
+    for (i, bound) in SCHEDULING_LAG_BUCKETS.iter().enumerate() {
+        let _ = writeln!(
+            metrics,
+            "rad_scheduling_lag_seconds_bucket{{le=\"{}\"}} {}",
+            bound,
+            number(&format!("bucket_{}", i))
+        );
+    }
+    let _ = writeln!(
+        metrics,
+        "rad_scheduling_lag_seconds_bucket{{le=\"+Inf\"}} {}",
+        number("count")
+    );
+    let _ = writeln!(
+        metrics,
+        "rad_scheduling_lag_seconds_sum {}",
+        number("sum") / 1000.0
+    );
+    let _ = writeln!(
+        metrics,
+        "rad_scheduling_lag_seconds_count {}",
+        number("count")
+    );
+
+    Ok(metrics)
+}
+
+/// Prometheus metrics about each job's latest runs, evaluated by the generated alerting rules,
+/// and about scheduling lag.
+#[get("/metrics")]
+pub async fn metrics(
+    state: &State<WebState>,
+) -> Result<(ContentType, String), (rocket::http::Status, String)> {
+    let collection = state
+        .datastore
+        .get_collection::<RunsV1>("runs")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing runs collection: {}", e),
+            )
+        })?;
+
+    let pipeline = vec![
+        doc! { "$sort": { "completed_at": -1 } },
+        doc! { "$group": {
+            "_id": "$job_name",
+            "started_at": { "$first": "$started_at" },
+            "completed_at": { "$first": "$completed_at" },
+            "outcome": { "$first": "$outcome" },
+            "last_success_at": {
+                "$max": { "$cond": [{ "$eq": ["$outcome", 1] }, "$completed_at", null] }
+            },
+        } },
+        doc! { "$sort": { "_id": 1 } },
+    ];
+    let mut cursor = collection.aggregate(pipeline).await.map_err(|e| {
+        (
+            rocket::http::Status::InternalServerError,
+            format!("Error aggregating runs: {}", e),
+        )
+    })?;
+
+    let mut duration = String::from(
+        "# HELP rad_job_last_run_duration_seconds Duration of the job's latest run.\n\
+         # TYPE rad_job_last_run_duration_seconds gauge\n",
+    );
+    let mut success = String::from(
+        "# HELP rad_job_last_run_success Whether the job's latest run succeeded.\n\
+         # TYPE rad_job_last_run_success gauge\n",
+    );
+    let mut last_success = String::from(
+        "# HELP rad_job_last_success_timestamp_seconds Completion time of the job's latest successful run.\n\
+         # TYPE rad_job_last_success_timestamp_seconds gauge\n",
+    );
+    while let Some(job) = cursor.next().await {
+        let job = job.map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading runs: {}", e),
+            )
+        })?;
+        let Ok(job_name) = job.get_str("_id") else {
+            continue;
+        };
+        let label = format!("{{job_name=\"{}\"}}", escape_label(job_name));
+        if let (Ok(started_at), Ok(completed_at)) = (
+            job.get_datetime("started_at"),
+            job.get_datetime("completed_at"),
+        ) {
+            let seconds =
+                (completed_at.timestamp_millis() - started_at.timestamp_millis()) as f64 / 1000.0;
+            let _ = writeln!(
+                duration,
+                "rad_job_last_run_duration_seconds{} {}",
+                label, seconds
+            );
+        }
+        let succeeded = job.get_i32("outcome").map(|outcome| outcome == 1);
+        let _ = writeln!(
+            success,
+            "rad_job_last_run_success{} {}",
+            label,
+            succeeded.unwrap_or_default() as u8
+        );
+        if let Ok(last_success_at) = job.get_datetime("last_success_at") {
+            let _ = writeln!(
+                last_success,
+                "rad_job_last_success_timestamp_seconds{} {}",
+                label,
+                last_success_at.timestamp_millis() as f64 / 1000.0
+            );
+        }
+    }
+
+    let scheduling = scheduling_metrics(state).await?;
+
+    Ok((
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        format!("{}{}{}{}", duration, success, last_success, scheduling),
+    ))
+}
+
+/// Jobs whose scheduled run has not started within their `sla.max_start_delay`, longest overdue
+/// first, for the dashboard.
+#[get("/overdue_jobs/data")]
+pub async fn overdue_jobs_data(
+    state: &State<WebState>,
+    access: Access,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing jobs collection: {}", e),
+            )
+        })?;
+
+    let now = chrono::Utc::now().timestamp();
+    let jobs: Vec<JobV1> = job_collection
+        .find(access.scope(doc! {
+            "status": Status::Pending,
+            "next_run": { "$lt": next_run_datetime(now) },
+            "sla.max_start_delay": { "$exists": true },
+        }))
+        .sort(doc! { "next_run": 1 })
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error fetching jobs: {}", e),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading jobs: {}", e),
+            )
+        })?;
+
+    let items: Vec<serde_json::Value> = jobs
+        .iter()
+        .filter_map(|job| {
+            let overdue = job.overdue_seconds(now)?;
+            let max_start_delay = job.sla.as_ref()?.max_start_delay?;
+            (overdue > max_start_delay as i64).then(|| {
+                serde_json::json!({
+                    "name": job.name,
+                    "next_run": next_run_datetime(job.next_run).try_to_rfc3339_string().ok(),
+                    "overdue_seconds": overdue,
+                    "max_start_delay": max_start_delay,
+                    "agents_required": job.agents_required,
+                    "agent_groups": job.agent_groups,
+                })
+            })
+        })
+        .collect();
+    Ok(Json(serde_json::json!({ "items": items })))
+}
+
+/// Sets or, with an empty body, clears a job's SLA.
+#[post("/jobs/<name>/sla", data = "<sla>")]
+pub async fn post_job_sla(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+    sla: Json<JobSla>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let sla = Some(sla.into_inner()).filter(|sla| !sla.is_empty());
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing jobs collection: {}", e),
+            )
+        })?;
+
+    let update = match &sla {
+        Some(sla) => {
+            let sla = bson::to_bson(sla).map_err(|e| {
+                (
+                    rocket::http::Status::InternalServerError,
+                    format!("Error serializing SLA: {}", e),
+                )
+            })?;
+            doc! { "$set": { "sla": sla } }
+        }
+        None => doc! { "$unset": { "sla": "" } },
+    };
+    let previous = job_collection
+        .find_one_and_update(access.scope(doc! { "name": name }), update)
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error updating job: {}", e),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Job {} not found", name),
+            )
+        })?;
+
+    let updated = JobV1 {
+        sla: sla.clone(),
+        ..previous.clone()
+    };
+    let entry = AuditEntryV1::new(actor.name(), AuditAction::Update, AuditResource::Job, name);
+    audit::record(state, entry.with_diff(Some(&previous), Some(&updated))).await;
+
+    if previous.sla != sla {
+        let change = JobChangeV1 {
+            changes: vec![FieldChange {
+                field: "sla".to_string(),
+                old: previous
+                    .sla
+                    .as_ref()
+                    .map(|sla| sla.to_string())
+                    .unwrap_or_default(),
+                new: sla.map(|sla| sla.to_string()).unwrap_or_default(),
+            }],
+            ..JobChangeV1::new(name, JobChangeKind::Updated, "web UI")
+        };
+        if let Err(e) = change.insert_entry(&state.datastore).await {
+            eprintln!("Error recording job change: {}", e);
+        }
+        record_revision(state, Some(&previous), &updated, &actor, "web UI").await;
+    }
+
+    Ok("Success".to_string())
+}
@@ -0,0 +1,324 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Method;
+use rocket::http::uri::Origin;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::Deserialize;
+use rocket::serde::json::Json;
+use rocket::{Data, State};
+use rocket::{delete, get, post};
+use rocket_dyn_templates::{Template, context};
+use serde_json::json;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::data_page::with_iso_dates;
+use crate::internal_error;
+use core_logic::datastore::api_tokens::{ApiTokenKind, ApiTokenV1};
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+
+/// Where requests with a bad token are sent instead of the route they asked for.
+const REJECTED_PATH: &str = "/settings/api_tokens/rejected";
+/// Longest lifetime a token can be created with.
+const MAX_EXPIRES_IN_DAYS: u32 = 3650;
+
+/// The API token a request was authenticated with, if any, as set by `ApiTokenAuth`.
+pub struct TokenCaller(pub Option<CallerToken>);
+
+#[derive(Debug, Clone)]
+pub struct CallerToken {
+    pub name: String,
+    /// Who the token acts as, see `ApiTokenV1::actor`.
+    pub actor: String,
+}
+
+pub fn token_caller(request: &Request<'_>) -> Option<CallerToken> {
+    request.local_cache(|| TokenCaller(None)).0.clone()
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TokenCaller {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(TokenCaller(token_caller(request)))
+    }
+}
+
+/// Authenticates requests carrying an `Authorization: Bearer` token, independently of the
+/// reverse proxy's `WEBUI_USER_HEADER`, so programs such as CI systems can call the API. The
+/// token's actor then stands for the caller. Requests with an unknown, revoked or expired token
+/// are answered `401 Unauthorized` whichever route they asked for, so a bad token is never
+/// mistaken for no token. Other authorization schemes are left to the proxy.
+pub struct ApiTokenAuth;
+
+#[rocket::async_trait]
+impl Fairing for ApiTokenAuth {
+    fn info(&self) -> Info {
+        Info {
+            name: "API token authentication",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let Some(token) = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|authorization| authorization.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim().to_string())
+        else {
+            return;
+        };
+
+        let caller = match request.rocket().state::<WebState>() {
+            Some(state) => match ApiTokenV1::authenticate(&state.datastore, &token).await {
+                Ok(token) => token.map(|token| CallerToken {
+                    actor: token.actor(),
+                    name: token.name,
+                }),
+                Err(e) => {
+                    eprintln!("Error authenticating API token: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        match caller {
+            Some(caller) => {
+                request.local_cache(|| TokenCaller(Some(caller)));
+            }
+            None => {
+                request.set_method(Method::Get);
+                request.set_uri(Origin::parse(REJECTED_PATH).expect("valid rejected path"));
+            }
+        }
+    }
+}
+
+#[get("/settings/api_tokens/rejected")]
+pub async fn api_token_rejected() -> (rocket::http::Status, String) {
+    (
+        rocket::http::Status::Unauthorized,
+        "Invalid, revoked or expired API token".to_string(),
+    )
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde", deny_unknown_fields)]
+pub struct ApiTokenRequest {
+    pub name: String,
+    pub kind: ApiTokenKind,
+    /// Days until the token expires; it never does when unset.
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+}
+
+#[get("/settings")]
+pub async fn settings_page() -> Template {
+    Template::render(
+        "settings",
+        context! {
+            page_name: "Settings",
+        },
+    )
+}
+
+/// Whether `actor` may create a token of `kind`: only signed in users may, and only admins may
+/// create service tokens, since their actor can be named after any team member.
+fn check_create(
+    actor: Option<&str>,
+    kind: ApiTokenKind,
+    admin: bool,
+) -> Result<(), (rocket::http::Status, String)> {
+    match (actor, kind) {
+        (None, _) => Err((
+            rocket::http::Status::Unauthorized,
+            "Sign in to create API tokens".to_string(),
+        )),
+        (Some(_), ApiTokenKind::Service) if !admin => Err((
+            rocket::http::Status::Forbidden,
+            "Only admins may create service tokens".to_string(),
+        )),
+        (Some(_), _) => Ok(()),
+    }
+}
+
+/// The API tokens the user created, or every token for admins, newest first, without their
+/// hashes.
+#[get("/settings/api_tokens")]
+pub async fn api_tokens_data(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let created_by = match access.is_admin() {
+        true => None,
+        false => Some(actor.name()),
+    };
+    let tokens = ApiTokenV1::list(&state.datastore, created_by)
+        .await
+        .map_err(|e| internal_error("Error fetching API tokens", e))?;
+    let items: Vec<serde_json::Value> = tokens
+        .iter()
+        .map(|token| {
+            json!({
+                "_id": token.id,
+                "name": token.name,
+                "kind": token.kind,
+                "actor": token.actor(),
+                "created_by": token.created_by,
+                "created_at": token.created_at,
+                "hint": token.hint,
+                "expires_at": token.expires_at,
+                "last_used_at": token.last_used_at,
+                "revoked_at": token.revoked_at,
+            })
+        })
+        .collect();
+    Ok(Json(with_iso_dates(json!({ "items": items }))))
+}
+
+/// Creates an API token and returns it. It is not shown again. Tokens are created by signed in
+/// users, service tokens by admins only, and never with another token.
+#[post("/settings/api_tokens", data = "<request>")]
+pub async fn post_api_token(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    caller: TokenCaller,
+    request: Json<ApiTokenRequest>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    if caller.0.is_some() {
+        return Err((
+            rocket::http::Status::Forbidden,
+            "API tokens cannot create other tokens".to_string(),
+        ));
+    }
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err((
+            rocket::http::Status::BadRequest,
+            "Token name is required".to_string(),
+        ));
+    }
+    check_create(actor.0.as_deref(), request.kind, access.is_admin())?;
+    let expires_at = match request.expires_in_days {
+        Some(days @ 1..=MAX_EXPIRES_IN_DAYS) => Some(DateTime::from_millis(
+            DateTime::now().timestamp_millis() + days as i64 * 24 * 60 * 60 * 1000,
+        )),
+        Some(_) => {
+            return Err((
+                rocket::http::Status::BadRequest,
+                format!(
+                    "Tokens expire after 1 to {} days, or never",
+                    MAX_EXPIRES_IN_DAYS
+                ),
+            ));
+        }
+        None => None,
+    };
+
+    let (token, mut record) = ApiTokenV1::generate(name, request.kind, actor.name(), expires_at)
+        .map_err(|e| internal_error("Error generating API token", e))?;
+    record.id = Some(
+        record
+            .insert_entry(&state.datastore)
+            .await
+            .map_err(|e| internal_error("Error saving API token", e))?,
+    );
+
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Create,
+        AuditResource::ApiToken,
+        name,
+    );
+    audit::record(state, entry.with_diff(None, Some(&record))).await;
+
+    Ok(Json(json!({
+        "token": token,
+        "actor": record.actor(),
+    })))
+}
+
+/// Revokes an API token. Requests made with it are rejected from then on. Only the user who
+/// created it, or an admin, may.
+#[delete("/settings/api_tokens/<id>")]
+pub async fn revoke_api_token(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    id: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let object_id = ObjectId::parse_str(id).map_err(|_| {
+        (
+            rocket::http::Status::BadRequest,
+            "Invalid token ID format".to_string(),
+        )
+    })?;
+    let owned = ApiTokenV1::find(&state.datastore, object_id)
+        .await
+        .map_err(|e| internal_error("Error fetching API token", e))?
+        .is_some_and(|token| actor.0.as_deref() == Some(token.created_by.as_str()));
+    if !owned && !access.is_admin() {
+        return Err((
+            rocket::http::Status::Forbidden,
+            "Only the token's creator or an admin may revoke it".to_string(),
+        ));
+    }
+    let token = ApiTokenV1::revoke(&state.datastore, object_id)
+        .await
+        .map_err(|e| internal_error("Error revoking API token", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                "Token not found or already revoked".to_string(),
+            )
+        })?;
+
+    let revoked = ApiTokenV1 {
+        revoked_at: Some(DateTime::now()),
+        ..token.clone()
+    };
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Delete,
+        AuditResource::ApiToken,
+        &token.name,
+    );
+    audit::record(state, entry.with_diff(Some(&token), Some(&revoked))).await;
+
+    Ok(format!("Revoked token {}", token.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anonymous_callers_cannot_create_service_tokens() {
+        let result = check_create(None, ApiTokenKind::Service, false);
+        assert_eq!(result.unwrap_err().0, rocket::http::Status::Unauthorized);
+    }
+
+    #[test]
+    fn anonymous_callers_cannot_create_personal_tokens() {
+        let result = check_create(None, ApiTokenKind::Personal, false);
+        assert_eq!(result.unwrap_err().0, rocket::http::Status::Unauthorized);
+    }
+
+    #[test]
+    fn only_admins_create_service_tokens() {
+        let result = check_create(Some("alice"), ApiTokenKind::Service, false);
+        assert_eq!(result.unwrap_err().0, rocket::http::Status::Forbidden);
+        assert!(check_create(Some("root"), ApiTokenKind::Service, true).is_ok());
+    }
+
+    #[test]
+    fn signed_in_users_create_personal_tokens() {
+        assert!(check_create(Some("alice"), ApiTokenKind::Personal, false).is_ok());
+    }
+}
@@ -0,0 +1,146 @@
+use rocket::State;
+use rocket::get;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket_dyn_templates::{Template, context};
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::OnceLock;
+
+use crate::WebState;
+use crate::api_tokens;
+use crate::data_page::{DataPage, DataPageParams};
+use core_logic::datastore::audit_log::AuditEntryV1;
+
+/// Fields the audit page can be sorted and range filtered by.
+const AUDIT_SORT_FIELDS: &[&str] = &["at"];
+const AUDIT_RANGE_FIELDS: &[&str] = &["at"];
+
+static WEBUI_USER_HEADER: OnceLock<String> = OnceLock::new();
+
+/// The header an authenticating reverse proxy puts the user name in (default: `X-Forwarded-User`).
+fn get_webui_user_header() -> &'static str {
+    WEBUI_USER_HEADER
+        .get_or_init(|| {
+            env::var("WEBUI_USER_HEADER").unwrap_or_else(|_| "X-Forwarded-User".to_string())
+        })
+        .as_str()
+}
+
+/// The authenticated user making a request: the actor of its API token, or else the user in
+/// `WEBUI_USER_HEADER`. `None` when neither is given, e.g. when the web UI is not behind an
+/// authenticating proxy.
+pub struct Actor(pub Option<String>);
+
+impl Actor {
+    pub fn name(&self) -> &str {
+        self.0.as_deref().unwrap_or("anonymous")
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Actor {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if let Some(caller) = api_tokens::token_caller(request) {
+            return Outcome::Success(Actor(Some(caller.actor)));
+        }
+        let user = request
+            .headers()
+            .get_one(get_webui_user_header())
+            .map(str::trim)
+            .filter(|user| !user.is_empty())
+            .map(str::to_string);
+        Outcome::Success(Actor(user))
+    }
+}
+
+/// Saves an audit entry. Failing to record it does not fail the action it describes.
+pub async fn record(state: &State<WebState>, entry: AuditEntryV1) {
+    if let Err(e) = entry.insert_entry(&state.datastore).await {
+        eprintln!("Error recording audit entry: {}", e);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[get(
+    "/audit?<page>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<resource_filter>"
+)]
+pub async fn audit_page(
+    page: Option<u32>,
+    relative_select: Option<String>,
+    relative_select_value: Option<u8>,
+    relative_select_unit: Option<String>,
+    range_start: Option<u64>,
+    range_end: Option<u64>,
+    filter: Option<String>,
+    resource_filter: Option<String>,
+) -> Template {
+    Template::render(
+        "audit",
+        context! {
+            page: page.unwrap_or(1),
+            range_start: range_start.unwrap_or_default(),
+            range_end: range_end.unwrap_or_default(),
+            range_fields: AUDIT_RANGE_FIELDS,
+            relative_select: relative_select.unwrap_or_default(),
+            relative_select_value: relative_select_value.unwrap_or(30),
+            relative_select_unit: relative_select_unit.unwrap_or_default(),
+            filter: filter.unwrap_or_default(),
+            resource_filter: resource_filter.unwrap_or_default(),
+            page_name: "Audit",
+        },
+    )
+}
+
+/// Audit entries, newest first.
+#[allow(clippy::too_many_arguments)]
+#[get(
+    "/audit/data?<page>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<resource_filter>&<page_size>&<after>"
+)]
+pub async fn audit_data(
+    state: &State<WebState>,
+    page: Option<u32>,
+    relative_select: Option<String>,
+    relative_select_value: Option<u8>,
+    relative_select_unit: Option<String>,
+    range_start: Option<u64>,
+    range_end: Option<u64>,
+    filter: Option<String>,
+    resource_filter: Option<String>,
+    page_size: Option<u32>,
+    after: Option<String>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let data_page_params = DataPageParams {
+        collection: "audit_log".to_string(),
+        range_field: Some("at".to_string()),
+        range_fields: AUDIT_RANGE_FIELDS,
+        range_start,
+        range_end,
+        search_fields: vec![
+            "user".to_string(),
+            "action".to_string(),
+            "resource".to_string(),
+        ],
+        additional_filters: resource_filter
+            .filter(|resource_filter| !resource_filter.is_empty())
+            .map(|resource_filter| HashMap::from([("resource_kind".to_string(), resource_filter)])),
+        page,
+        filter,
+        base_filter: None,
+        sort: Some("at".to_string()),
+        sort_fields: AUDIT_SORT_FIELDS,
+        order: Some("desc".to_string()),
+        relative_select,
+        relative_value: relative_select_value.map(|v| v as u64),
+        relative_unit: relative_select_unit,
+        page_size,
+        after,
+    };
+
+    let audit_page: DataPage<AuditEntryV1> = DataPage::new(state, data_page_params).await?;
+
+    Ok(Json(audit_page.json()))
+}
@@ -0,0 +1,172 @@
+use futures::TryStreamExt;
+use mongodb::bson::{DateTime, doc};
+use rocket::State;
+use rocket::serde::Deserialize;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post};
+use rocket_dyn_templates::{Template, context};
+use serde_json::json;
+
+use crate::WebState;
+use crate::audit::{self, Actor};
+use crate::data_page::with_iso_dates;
+use crate::internal_error;
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+use core_logic::datastore::blackout_windows::BlackoutWindowV1;
+use core_logic::datastore::jobs::JobV1;
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde", deny_unknown_fields)]
+pub struct BlackoutWindowRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Unix timestamps of the start and end of the window.
+    pub start: i64,
+    pub end: i64,
+    /// Jobs held back by the window; every job when empty.
+    #[serde(default)]
+    pub jobs: Vec<String>,
+}
+
+/// Fails with `BadRequest` naming the first of `job_names` that is not a known job.
+async fn check_jobs_exist(
+    state: &State<WebState>,
+    job_names: &[String],
+) -> Result<(), (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let known: Vec<JobV1> = job_collection
+        .find(doc! { "name": { "$in": job_names } })
+        .await
+        .map_err(|e| internal_error("Error fetching jobs", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading jobs", e))?;
+    match job_names
+        .iter()
+        .find(|name| !known.iter().any(|job| &job.name == *name))
+    {
+        Some(name) => Err((
+            rocket::http::Status::BadRequest,
+            format!("Job {} not found", name),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[get("/blackout_windows")]
+pub async fn blackout_windows_page() -> Template {
+    Template::render(
+        "blackout_windows",
+        context! {
+            page_name: "Blackout Windows",
+        },
+    )
+}
+
+/// The blackout windows that have not ended, in progress or upcoming, by start time.
+#[get("/blackout_windows/data")]
+pub async fn blackout_windows_data(
+    state: &State<WebState>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let windows = BlackoutWindowV1::upcoming(&state.datastore, DateTime::now())
+        .await
+        .map_err(|e| internal_error("Error fetching blackout windows", e))?;
+
+    Ok(Json(with_iso_dates(json!({
+        "items": windows,
+    }))))
+}
+
+/// Creates a blackout window, or replaces the window with the same name.
+#[post("/blackout_windows", data = "<request>")]
+pub async fn post_blackout_window(
+    state: &State<WebState>,
+    actor: Actor,
+    request: Json<BlackoutWindowRequest>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let request = request.into_inner();
+    let mut jobs: Vec<String> = vec![];
+    for job in request.jobs {
+        if !job.trim().is_empty() && !jobs.contains(&job) {
+            jobs.push(job);
+        }
+    }
+    let window = BlackoutWindowV1 {
+        id: None,
+        name: request.name,
+        description: request.description,
+        start: DateTime::from_millis(request.start.saturating_mul(1000)),
+        end: DateTime::from_millis(request.end.saturating_mul(1000)),
+        jobs,
+    };
+    window
+        .validate()
+        .map_err(|e| (rocket::http::Status::BadRequest, e))?;
+    check_jobs_exist(state, &window.jobs).await?;
+
+    let window_collection = state
+        .datastore
+        .get_collection::<BlackoutWindowV1>("blackout_windows")
+        .await
+        .map_err(|e| internal_error("Error accessing blackout windows collection", e))?;
+
+    let previous = window_collection
+        .find_one_and_replace(doc! { "name": &window.name }, &window)
+        .upsert(true)
+        .await
+        .map_err(|e| internal_error("Error saving blackout window", e))?;
+
+    let action = match previous {
+        Some(_) => AuditAction::Update,
+        None => AuditAction::Create,
+    };
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        action,
+        AuditResource::BlackoutWindow,
+        &window.name,
+    );
+    audit::record(state, entry.with_diff(previous.as_ref(), Some(&window))).await;
+
+    Ok("Success".to_string())
+}
+
+/// Deletes a blackout window. Jobs it held back are dispatched on the next check.
+#[delete("/blackout_windows/<name>")]
+pub async fn delete_blackout_window(
+    state: &State<WebState>,
+    actor: Actor,
+    name: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let window_collection = state
+        .datastore
+        .get_collection::<BlackoutWindowV1>("blackout_windows")
+        .await
+        .map_err(|e| internal_error("Error accessing blackout windows collection", e))?;
+
+    let deleted = window_collection
+        .find_one_and_delete(doc! { "name": name })
+        .await
+        .map_err(|e| internal_error("Error deleting blackout window", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Blackout window {} not found", name),
+            )
+        })?;
+
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Delete,
+        AuditResource::BlackoutWindow,
+        name,
+    );
+    audit::record(state, entry.with_diff(Some(&deleted), None)).await;
+
+    Ok("Success".to_string())
+}
@@ -0,0 +1,52 @@
+use mongodb::bson::doc;
+use rocket::State;
+use rocket::get;
+use rocket::serde::json::Json;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::internal_error;
+use core_logic::datastore::check_states::CheckStateV1;
+
+/// The current state of every check job on each of its agents, for the dashboard's service
+/// status grid, with the checks and agents that have a state in name order.
+#[get("/check_states/data")]
+pub async fn check_states_data(
+    state: &State<WebState>,
+    access: Access,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let filter = access
+        .scope_by_job(&state.datastore, "job_name", doc! {})
+        .await?;
+    let states = CheckStateV1::find_all(&state.datastore, filter)
+        .await
+        .map_err(|e| internal_error("Error fetching check states", e))?;
+
+    let mut jobs: Vec<&str> = states.iter().map(|check| check.job_name.as_str()).collect();
+    jobs.dedup();
+    let mut agents: Vec<&str> = states
+        .iter()
+        .map(|check| check.agent_name.as_str())
+        .collect();
+    agents.sort_unstable();
+    agents.dedup();
+    let items: Vec<serde_json::Value> = states
+        .iter()
+        .map(|check| {
+            serde_json::json!({
+                "job_name": check.job_name,
+                "agent_name": check.agent_name,
+                "state": check.state,
+                "since": check.since.timestamp_millis(),
+                "checked_at": check.checked_at.timestamp_millis(),
+                "run_id": check.run_id,
+                "summary": check.summary,
+            })
+        })
+        .collect();
+    Ok(Json(serde_json::json!({
+        "jobs": jobs,
+        "agents": agents,
+        "items": items,
+    })))
+}
@@ -0,0 +1,90 @@
+use futures::TryStreamExt;
+use mongodb::bson::{DateTime, doc};
+use rocket::State;
+use rocket::get;
+use rocket::serde::json::Json;
+use rocket_dyn_templates::{Template, context};
+use serde_json::json;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::data_page::with_iso_dates;
+use core_logic::datastore::connections::ConnectionV1;
+use core_logic::datastore::leases::{LEADER_LEASE, LeaseV1, PARTITION_LEASE_PREFIX};
+
+#[get("/connections")]
+pub async fn connections_page() -> Template {
+    Template::render(
+        "connections",
+        context! {
+            page_name: "Connections",
+        },
+    )
+}
+
+/// Connections central command currently holds, as last published by each instance, the
+/// instance that leads if they elect one, and the agent partitions each instance holds if agents
+/// are partitioned.
+#[get("/connections/data")]
+pub async fn connections_data(
+    state: &State<WebState>,
+    access: Access,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let connection_collection = state
+        .datastore
+        .get_collection::<ConnectionV1>("connections")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing connections collection: {}", e),
+            )
+        })?;
+
+    let filter = access
+        .scope_by_agent(&state.datastore, "agent_name", doc! {})
+        .await?;
+    let connections: Vec<ConnectionV1> = connection_collection
+        .find(filter)
+        .sort(doc! { "agent_name": 1, "connected_since": 1 })
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error fetching connections: {}", e),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading connections: {}", e),
+            )
+        })?;
+
+    let leader = LeaseV1::find(&state.datastore, LEADER_LEASE)
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading leader lease: {}", e),
+            )
+        })?
+        .filter(|lease| lease.expires_at > DateTime::now());
+
+    let partitions = LeaseV1::live(&state.datastore, PARTITION_LEASE_PREFIX)
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading agent partition leases: {}", e),
+            )
+        })?;
+
+    Ok(Json(with_iso_dates(json!({
+        "items": connections,
+        "leader": leader,
+        "partitions": partitions,
+    }))))
+}
@@ -1,29 +1,48 @@
-use bson::{DateTime, doc};
+use bson::{Bson, DateTime, Document, doc};
 use chrono::{Duration, Utc};
 use futures::StreamExt;
 use mongodb::options::FindOptions;
 use rocket::State;
+use rocket::http::Status;
+use serde_json::json;
 
 use std::collections::HashMap;
 
 use crate::WebState;
 
+pub const DEFAULT_PAGE_SIZE: u32 = 20;
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+/// What to fetch for a data route. `sort` and `range_field` come from the client and are rejected
+/// unless listed in `sort_fields` and `range_fields`; `search_fields` and the keys of
+/// `additional_filters` are chosen by the route.
 #[derive(Default, Debug)]
 pub struct DataPageParams {
     pub collection: String,
     pub range_field: Option<String>,
+    /// Every range field is stored as a BSON DateTime, so one range filters each the same way.
+    pub range_fields: &'static [&'static str],
+    /// Milliseconds since the epoch, as are `range_end` and the relative range.
     pub range_start: Option<u64>,
     pub range_end: Option<u64>,
     pub search_fields: Vec<String>,
     pub page: Option<u32>,
     pub filter: Option<String>,
     pub additional_filters: Option<HashMap<String, String>>,
+    /// Applied as is, whatever the client asks for.
+    pub base_filter: Option<Document>,
     pub sort: Option<String>,
+    pub sort_fields: &'static [&'static str],
     pub order: Option<String>,
     // New fields for relative selection
     pub relative_select: Option<String>, // "absolute" or "relative"
     pub relative_value: Option<u64>,
     pub relative_unit: Option<String>, // "seconds", "minutes", "hours", "days", "weeks"
+    /// Items per page, between 1 and `MAX_PAGE_SIZE` (default: `DEFAULT_PAGE_SIZE`).
+    pub page_size: Option<u32>,
+    /// A `next_cursor` from a previous page. Continues after that page's last item instead of
+    /// skipping `page`, which stays fast however deep into a large collection it goes.
+    pub after: Option<String>,
 }
 
 pub enum RelativeSelect {
@@ -43,23 +62,118 @@ impl From<&str> for RelativeSelect {
 
 pub struct DataPage<T> {
     pub items: Vec<T>,
+    pub total_items: u64,
     pub total_pages: u64,
     pub current_page: u32,
+    pub page_size: u32,
+    /// Pass as `after` to fetch the next page; `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Replaces the BSON DateTimes in `value`, which serialize as `{"$date": {"$numberLong": ..}}`,
+/// with ISO 8601 strings such as `2025-06-01T12:00:00Z`.
+pub fn iso_dates(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            let millis = fields
+                .get("$date")
+                .and_then(|date| date.get("$numberLong"))
+                .and_then(|millis| millis.as_str())
+                .and_then(|millis| millis.parse::<i64>().ok());
+            match millis {
+                Some(millis) if fields.len() == 1 => {
+                    if let Ok(iso) = DateTime::from_millis(millis).try_to_rfc3339_string() {
+                        *value = serde_json::Value::String(iso);
+                    }
+                }
+                _ => fields.values_mut().for_each(iso_dates),
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(iso_dates),
+        _ => {}
+    }
+}
+
+/// `value` with its BSON DateTimes as ISO 8601 strings, see `iso_dates`.
+pub fn with_iso_dates(mut value: serde_json::Value) -> serde_json::Value {
+    iso_dates(&mut value);
+    value
+}
+
+impl<T: serde::Serialize> DataPage<T> {
+    /// The page as the JSON returned by the data routes, with times in ISO 8601.
+    pub fn json(&self) -> serde_json::Value {
+        let mut page = json!({
+            "items": self.items,
+            "total_items": self.total_items,
+            "total_pages": self.total_pages,
+            "current_page": self.current_page,
+            "page_size": self.page_size,
+            "next_cursor": self.next_cursor,
+        });
+        iso_dates(&mut page);
+        page
+    }
 }
 
 impl<T: Send + Sync + for<'de> serde::Deserialize<'de>> DataPage<T> {
-    pub async fn new(state: &State<WebState>, params: DataPageParams) -> DataPage<T> {
+    pub async fn new(
+        state: &State<WebState>,
+        params: DataPageParams,
+    ) -> Result<DataPage<T>, (Status, String)> {
+        let sort_field = params
+            .sort
+            .as_deref()
+            .filter(|field| !field.is_empty() && *field != "_id");
+        if let Some(field) = sort_field
+            && !params.sort_fields.contains(&field)
+        {
+            return Err((
+                Status::BadRequest,
+                format!("Cannot sort {} by {}", params.collection, field),
+            ));
+        }
+        if let Some(field) = params.range_field.as_deref()
+            && !params.range_fields.contains(&field)
+        {
+            return Err((
+                Status::BadRequest,
+                format!(
+                    "Cannot select {} by a range of {}",
+                    params.collection, field
+                ),
+            ));
+        }
+        let after = match params.after.as_deref() {
+            Some(cursor) => Some(Self::decode_cursor(cursor).ok_or_else(|| {
+                (
+                    Status::BadRequest,
+                    format!("Invalid page cursor: {}", cursor),
+                )
+            })?),
+            None => None,
+        };
+
         let collection = state
             .datastore
-            .get_collection::<T>(&params.collection)
+            .get_collection::<Document>(&params.collection)
             .await
-            .expect("Failed to get collection");
+            .map_err(|e| {
+                eprintln!("Error getting collection {}: {}", params.collection, e);
+                (
+                    Status::InternalServerError,
+                    format!("Failed to get collection: {}", e),
+                )
+            })?;
 
-        let page_size = 20;
+        let page_size = params
+            .page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
         let page = params.page.unwrap_or(1);
         let skip = page.saturating_sub(1).saturating_mul(page_size);
 
-        let find_options = Self::build_find_options(&params);
+        let find_options = Self::build_find_options(sort_field, params.order.as_deref());
 
         let mut filter_doc = Self::build_filter(
             params.filter.unwrap_or_default(),
@@ -90,32 +204,120 @@ impl<T: Send + Sync + for<'de> serde::Deserialize<'de>> DataPage<T> {
             }
         }
 
-        let total_count = collection
-            .count_documents(filter_doc.clone())
-            .await
-            .expect("Failed to count documents");
-        let total_pages = total_count.div_ceil(page_size as u64);
+        if let Some(base_filter) = params.base_filter {
+            filter_doc = doc! {
+                "$and": [filter_doc, base_filter]
+            };
+        }
+
+        // Counting every document is slow on large collections, so use the estimate when
+        // nothing is filtered.
+        let total_items = if filter_doc.is_empty() {
+            collection.estimated_document_count().await
+        } else {
+            collection.count_documents(filter_doc.clone()).await
+        }
+        .map_err(|e| {
+            eprintln!("Error counting {}: {}", params.collection, e);
+            (
+                Status::InternalServerError,
+                format!("Failed to count documents: {}", e),
+            )
+        })?;
+        let total_pages = total_items.div_ceil(page_size as u64);
+
+        let descending = params.order.as_deref() == Some("desc");
+        let (find, skip) = match after {
+            Some((value, id)) => {
+                let after_filter = Self::build_after_filter(sort_field, descending, value, id);
+                (doc! { "$and": [filter_doc, after_filter] }, 0)
+            }
+            None => (filter_doc, skip as u64),
+        };
 
         let mut cursor = collection
-            .find(filter_doc)
+            .find(find)
             .with_options(find_options)
-            .skip(skip as u64)
+            .skip(skip)
             .limit(page_size as i64)
             .await
-            .expect("Failed to fetch data");
+            .map_err(|e| {
+                eprintln!("Error fetching {}: {}", params.collection, e);
+                (
+                    Status::InternalServerError,
+                    format!("Failed to fetch data: {}", e),
+                )
+            })?;
 
         let mut items = Vec::new();
+        let mut last = None;
         while let Some(result) = cursor.next().await {
-            match result {
-                Ok(doc) => items.push(doc),
+            match result.map(|doc| (bson::from_document(doc.clone()), doc)) {
+                Ok((Ok(item), doc)) => {
+                    items.push(item);
+                    last = Some(doc);
+                }
+                Ok((Err(e), _)) => eprintln!("Error reading document: {:?}", e),
                 Err(e) => eprintln!("Error reading document: {:?}", e),
             }
         }
 
-        DataPage {
+        let next_cursor = last
+            .filter(|_| items.len() == page_size as usize)
+            .and_then(|last| Self::encode_cursor(&last, sort_field));
+
+        Ok(DataPage {
             items,
+            total_items,
             total_pages,
             current_page: page,
+            page_size,
+            next_cursor,
+        })
+    }
+
+    /// Hex encoded BSON of the last item's sort value and `_id`.
+    fn encode_cursor(last: &Document, sort_field: Option<&str>) -> Option<String> {
+        let value = sort_field
+            .and_then(|field| Self::get_path(last, field))
+            .cloned()
+            .unwrap_or(Bson::Null);
+        let cursor = doc! { "value": value, "id": last.get("_id")?.clone() };
+        let mut bytes = vec![];
+        cursor.to_writer(&mut bytes).ok()?;
+        Some(hex::encode(bytes))
+    }
+
+    fn decode_cursor(cursor: &str) -> Option<(Bson, Bson)> {
+        let bytes = hex::decode(cursor).ok()?;
+        let cursor = Document::from_reader(bytes.as_slice()).ok()?;
+        Some((cursor.get("value")?.clone(), cursor.get("id")?.clone()))
+    }
+
+    /// Looks up a dotted field such as `triggered_by.source`.
+    fn get_path<'a>(doc: &'a Document, path: &str) -> Option<&'a Bson> {
+        let mut fields = path.split('.');
+        let mut value = doc.get(fields.next()?)?;
+        for field in fields {
+            value = value.as_document()?.get(field)?;
+        }
+        Some(value)
+    }
+
+    /// Items after the cursor's item in `(sort field, _id)` order.
+    fn build_after_filter(
+        sort_field: Option<&str>,
+        descending: bool,
+        value: Bson,
+        id: Bson,
+    ) -> Document {
+        let operator = if descending { "$lt" } else { "$gt" };
+        match sort_field {
+            Some(field) => doc! { "$or": [
+                { field: { operator: value.clone() } },
+                { field: value, "_id": { operator: id } },
+            ] },
+            None => doc! { "_id": { operator: id } },
         }
     }
 
@@ -131,7 +333,8 @@ impl<T: Send + Sync + for<'de> serde::Deserialize<'de>> DataPage<T> {
         relative_unit: Option<String>,
     ) -> bson::Document {
         let mut filter = if !filter_str.trim().is_empty() {
-            let regex = doc! { "$regex": &filter_str, "$options": "i" };
+            // Matched literally, so input such as `(a+)+` cannot run an expensive pattern.
+            let regex = doc! { "$regex": regex::escape(&filter_str), "$options": "i" };
             let mut or_conditions: Vec<_> = search_fields
                 .iter()
                 .map(|field| doc! { field: regex.clone() })
@@ -192,15 +395,17 @@ impl<T: Send + Sync + for<'de> serde::Deserialize<'de>> DataPage<T> {
         filter
     }
 
-    fn build_find_options(params: &DataPageParams) -> FindOptions {
-        let mut options = FindOptions::default();
-        if let Some(ref sort_field) = params.sort {
-            let sort_order = match params.order.as_deref() {
-                Some("desc") => -1,
-                _ => 1,
-            };
-            options.sort = Some(doc! { sort_field: sort_order });
+    /// Sorts by the requested field, then `_id` so that pages are stable and cursors can resume.
+    fn build_find_options(sort_field: Option<&str>, order: Option<&str>) -> FindOptions {
+        let sort_order = match order {
+            Some("desc") => -1,
+            _ => 1,
+        };
+        let mut sort = doc! {};
+        if let Some(sort_field) = sort_field {
+            sort.insert(sort_field, sort_order);
         }
-        options
+        sort.insert("_id", sort_order);
+        FindOptions::builder().sort(sort).build()
     }
 }
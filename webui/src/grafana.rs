@@ -0,0 +1,308 @@
+use bson::{DateTime, doc};
+use rocket::State;
+use rocket::serde::json::Json;
+use rocket::{get, post};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::WebState;
+use crate::access::Access;
+use crate::internal_error;
+use core_logic::datastore::agent_events::AgentEventV1;
+use core_logic::datastore::run_stats::{self, RunStats, SeriesBucket};
+
+/// The metrics Grafana can query, with what they measure.
+const METRICS: &[(&str, &str)] = &[
+    ("runs.total", "Runs started"),
+    ("runs.succeeded", "Runs started that succeeded"),
+    (
+        "runs.failed",
+        "Runs started that failed, timed out, were cancelled or could not be dispatched",
+    ),
+    (
+        "runs.duration_avg_ms",
+        "Average duration of the runs started, in milliseconds",
+    ),
+    (
+        "runs.duration_max_ms",
+        "Longest duration of the runs started, in milliseconds",
+    ),
+    ("agents.online", "Average number of agents online"),
+];
+/// Most agent events returned as annotations.
+const MAX_ANNOTATIONS: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct TimeRange {
+    from: String,
+    to: String,
+}
+
+impl TimeRange {
+    /// The range's bounds, as Grafana sends them in RFC 3339.
+    fn bounds(&self) -> Result<(DateTime, DateTime), (rocket::http::Status, String)> {
+        let parse = |value: &str| {
+            DateTime::parse_rfc3339_str(value).map_err(|_| {
+                (
+                    rocket::http::Status::BadRequest,
+                    format!("Invalid time {}, expected RFC 3339", value),
+                )
+            })
+        };
+        let (from, to) = (parse(&self.from)?, parse(&self.to)?);
+        if from >= to {
+            return Err((
+                rocket::http::Status::BadRequest,
+                "The range must end after it starts".to_string(),
+            ));
+        }
+        Ok((from, to))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryTarget {
+    target: String,
+    #[serde(default)]
+    hide: bool,
+    /// `job_name` and `agent_name` narrowing the metric, entered in the query editor.
+    #[serde(default)]
+    payload: Value,
+}
+
+impl QueryTarget {
+    fn payload(&self, field: &str) -> Option<&str> {
+        self.payload
+            .get(field)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryRequest {
+    range: TimeRange,
+    #[serde(default)]
+    interval_ms: Option<i64>,
+    #[serde(default)]
+    max_data_points: Option<i64>,
+    targets: Vec<QueryTarget>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimeSeries {
+    target: String,
+    /// `[value, milliseconds since the epoch]` pairs, oldest first.
+    datapoints: Vec<(f64, i64)>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnnotationRequest {
+    range: TimeRange,
+    /// The annotation being queried; its `query` is the agent whose events to show, or empty for
+    /// all agents.
+    #[serde(default)]
+    annotation: Value,
+}
+
+/// Answers Grafana's JSON datasource connection test, so dashboards can chart runs and agents
+/// from `/api/metrics` instead of reading MongoDB. Configure the datasource with an API token as
+/// a bearer token when the web UI sits behind an authenticating proxy.
+#[get("/api/metrics")]
+pub fn metrics_datasource() -> &'static str {
+    "OK"
+}
+
+/// The metric names, for the query editor of the SimpleJSON datasource.
+#[post("/api/metrics/search")]
+pub fn search_metrics() -> Json<Vec<&'static str>> {
+    Json(METRICS.iter().map(|(name, _)| *name).collect())
+}
+
+/// The metrics with the payload narrowing them, for the query editor of the JSON datasource.
+#[post("/api/metrics/metrics")]
+pub fn list_metrics() -> Json<Value> {
+    let payloads = json!([
+        { "name": "job_name", "label": "Job", "type": "input" },
+        { "name": "agent_name", "label": "Agent", "type": "input" },
+    ]);
+    Json(json!(
+        METRICS
+            .iter()
+            .map(|(name, description)| json!({
+                "label": description,
+                "value": name,
+                "payloads": payloads,
+            }))
+            .collect::<Vec<_>>()
+    ))
+}
+
+/// The time series of each target over the range, in buckets of Grafana's `intervalMs`, widened
+/// to at most `maxDataPoints` and `MAX_STATS_BUCKETS` buckets. Run metrics count the runs started
+/// in each bucket, of the payload's `job_name` and `agent_name` if given. `agents.online` is the
+/// average number of agents online in each bucket, or the fraction of it the payload's
+/// `agent_name` was online.
+#[post("/api/metrics/query", data = "<request>")]
+pub async fn query_metrics(
+    state: &State<WebState>,
+    access: Access,
+    request: Json<QueryRequest>,
+) -> Result<Json<Vec<TimeSeries>>, (rocket::http::Status, String)> {
+    let request = request.into_inner();
+    let (from, to) = request.range.bounds()?;
+    let span = to.timestamp_millis() - from.timestamp_millis();
+    let max_buckets = request
+        .max_data_points
+        .filter(|points| *points > 0)
+        .map_or(run_stats::MAX_STATS_BUCKETS, |points| {
+            points.min(run_stats::MAX_STATS_BUCKETS)
+        });
+    let interval_ms = request
+        .interval_ms
+        .unwrap_or(60 * 1000)
+        .max((span + max_buckets - 1) / max_buckets)
+        .max(1);
+    let bucket_starts = || {
+        (0..(span + interval_ms - 1) / interval_ms)
+            .map(move |index| from.timestamp_millis() + index * interval_ms)
+    };
+
+    let mut series = vec![];
+    for target in request.targets.iter().filter(|target| !target.hide) {
+        let agent_name = target.payload("agent_name");
+        let datapoints = match target.target.as_str() {
+            "agents.online" => {
+                let mut filter = doc! {};
+                if let Some(agent_name) = agent_name {
+                    filter.insert("agent_name", agent_name);
+                }
+                let filter = access
+                    .scope_by_agent(&state.datastore, "agent_name", filter)
+                    .await?;
+                AgentEventV1::online_series(&state.datastore, filter, from, to, interval_ms)
+                    .await
+                    .map_err(|e| internal_error("Error aggregating agent availability", e))?
+                    .into_iter()
+                    .map(|bucket| (bucket.online, bucket.start.timestamp_millis()))
+                    .collect()
+            }
+            name if name.starts_with("runs.") => {
+                let mut filter = doc! {};
+                if let Some(job_name) = target.payload("job_name") {
+                    filter.insert("job_name", job_name);
+                }
+                if let Some(agent_name) = agent_name {
+                    filter.insert("agent_name", agent_name);
+                }
+                let filter = access
+                    .scope_by_job(&state.datastore, "job_name", filter)
+                    .await?;
+                let buckets = RunStats::series(&state.datastore, filter, from, to, interval_ms)
+                    .await
+                    .map_err(|e| internal_error("Error aggregating run stats", e))?;
+                run_datapoints(name, &buckets, bucket_starts())?
+            }
+            name => {
+                return Err((
+                    rocket::http::Status::BadRequest,
+                    format!("Unknown metric {}", name),
+                ));
+            }
+        };
+        series.push(TimeSeries {
+            target: target.target.clone(),
+            datapoints,
+        });
+    }
+
+    Ok(Json(series))
+}
+
+/// The datapoints of the run metric `name`. Counts are zero in buckets without runs, which
+/// durations leave out.
+fn run_datapoints(
+    name: &str,
+    buckets: &[SeriesBucket],
+    bucket_starts: impl Iterator<Item = i64>,
+) -> Result<Vec<(f64, i64)>, (rocket::http::Status, String)> {
+    let duration = |value: fn(&SeriesBucket) -> Option<f64>| {
+        buckets
+            .iter()
+            .filter_map(|bucket| Some((value(bucket)?, bucket.start.timestamp_millis())))
+            .collect()
+    };
+    let count = |value: fn(&SeriesBucket) -> i64| {
+        let mut buckets = buckets.iter().peekable();
+        bucket_starts
+            .map(|start| {
+                let count = buckets
+                    .next_if(|bucket| bucket.start.timestamp_millis() == start)
+                    .map_or(0, value);
+                (count as f64, start)
+            })
+            .collect()
+    };
+    match name {
+        "runs.total" => Ok(count(|bucket| bucket.counts.total)),
+        "runs.succeeded" => Ok(count(|bucket| bucket.counts.succeeded)),
+        "runs.failed" => Ok(count(|bucket| bucket.counts.failed)),
+        "runs.duration_avg_ms" => Ok(duration(|bucket| bucket.average_duration_ms)),
+        "runs.duration_max_ms" => Ok(duration(|bucket| bucket.max_duration_ms)),
+        _ => Err((
+            rocket::http::Status::BadRequest,
+            format!("Unknown metric {}", name),
+        )),
+    }
+}
+
+/// Agent lifecycle events over the range as Grafana annotations, e.g. to mark agents going
+/// offline on run charts. At most `MAX_ANNOTATIONS`, oldest first.
+#[post("/api/metrics/annotations", data = "<request>")]
+pub async fn metrics_annotations(
+    state: &State<WebState>,
+    access: Access,
+    request: Json<AnnotationRequest>,
+) -> Result<Json<Vec<Value>>, (rocket::http::Status, String)> {
+    let request = request.into_inner();
+    let (from, to) = request.range.bounds()?;
+    let agent_name = request
+        .annotation
+        .get("query")
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|query| !query.is_empty());
+
+    let mut filter = doc! {};
+    if let Some(agent_name) = agent_name {
+        filter.insert("agent_name", agent_name);
+    }
+    let filter = access
+        .scope_by_agent(&state.datastore, "agent_name", filter)
+        .await?;
+    let events = AgentEventV1::find_between(&state.datastore, filter, from, to, MAX_ANNOTATIONS)
+        .await
+        .map_err(|e| internal_error("Error fetching agent events", e))?;
+
+    Ok(Json(
+        events
+            .into_iter()
+            .map(|event| {
+                let title = format!("{} {}", event.agent_name, event.kind);
+                json!({
+                    "annotation": request.annotation,
+                    "time": event.at.timestamp_millis(),
+                    "title": title,
+                    "text": match event.detail.is_empty() {
+                        true => title.clone(),
+                        false => format!("{}: {}", title, event.detail),
+                    },
+                    "tags": ["agent", event.agent_name, event.kind.name()],
+                })
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,33 @@
+use core_logic::health::CheckStatus;
+use rocket::State;
+use rocket::get;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde_json::json;
+
+use crate::WebState;
+
+async fn datastore_check(state: &State<WebState>) -> CheckStatus {
+    state.datastore.check().await
+}
+
+/// Liveness probe. Succeeds while the web UI is serving, reporting datastore health in the body.
+#[get("/healthz")]
+pub async fn healthz(state: &State<WebState>) -> Json<serde_json::Value> {
+    let datastore = datastore_check(state).await;
+    Json(json!({ "status": "ok", "checks": { "datastore": datastore } }))
+}
+
+/// Readiness probe. Fails with `503` while the datastore is unreachable.
+#[get("/readyz")]
+pub async fn readyz(state: &State<WebState>) -> (Status, Json<serde_json::Value>) {
+    let datastore = datastore_check(state).await;
+    let (status, label) = match datastore.ok {
+        true => (Status::Ok, "ok"),
+        false => (Status::ServiceUnavailable, "unavailable"),
+    };
+    (
+        status,
+        Json(json!({ "status": label, "checks": { "datastore": datastore } })),
+    )
+}
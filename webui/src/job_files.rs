@@ -0,0 +1,86 @@
+use mongodb::bson::doc;
+use rocket::State;
+use rocket::data::{Data, ToByteUnit};
+use rocket::post;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::internal_error;
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+use core_logic::datastore::jobs::{JOB_FILES_BUCKET, JobV1};
+
+/// Largest file central command pushes to agents.
+const MAX_JOB_FILE_MIB: u64 = 64;
+
+/// Stores the request body as a new revision of the job file `name`, which jobs push to their
+/// agents with a `grid_fs` file source. Returns the file's SHA-256. Only users who may see every
+/// job pushing the file may replace it, as it runs on those jobs' agents.
+#[post("/job_files/<name>", data = "<data>")]
+pub async fn upload_job_file(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+    data: Data<'_>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let jobs = job_collection
+        .distinct(
+            "name",
+            doc! { "files": { "$elemMatch": { "source.kind": "grid_fs", "source.name": name } } },
+        )
+        .await
+        .map_err(|e| internal_error("Error fetching jobs", e))?;
+    for job in jobs.iter().filter_map(|job| job.as_str()) {
+        access
+            .check_job(&state.datastore, job)
+            .await
+            .map_err(|(status, message)| {
+                if status == rocket::http::Status::NotFound {
+                    (
+                        rocket::http::Status::Forbidden,
+                        format!("Job file {} is pushed by jobs of another team", name),
+                    )
+                } else {
+                    (status, message)
+                }
+            })?;
+    }
+
+    let contents = data
+        .open(MAX_JOB_FILE_MIB.mebibytes())
+        .into_bytes()
+        .await
+        .map_err(|e| internal_error("Error reading upload", e))?;
+    if !contents.is_complete() {
+        return Err((
+            rocket::http::Status::PayloadTooLarge,
+            format!("Job files are limited to {} MiB", MAX_JOB_FILE_MIB),
+        ));
+    }
+
+    state
+        .datastore
+        .blob_store()
+        .put(format!("{}/{}", JOB_FILES_BUCKET, name), contents.to_vec())
+        .await
+        .map_err(|e| internal_error("Error storing job file", e))?;
+
+    let sha256 = hex::encode(ring::digest::digest(&ring::digest::SHA256, &contents));
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Create,
+        AuditResource::JobFile,
+        name,
+    )
+    .with_field("size", contents.len().to_string())
+    .with_field("sha256", &sha256);
+    audit::record(state, entry).await;
+
+    Ok(sha256)
+}
@@ -0,0 +1,515 @@
+use mongodb::bson::{DateTime, doc, oid::ObjectId};
+use rocket::State;
+use rocket::serde::Deserialize;
+use rocket::serde::json::Json;
+use rocket::{get, post};
+use serde_json::json;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::internal_error;
+use crate::job_revisions::record_revision;
+use crate::jobs::existing_job_error;
+use crate::namespaces::{namespace_name, parse_namespace};
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+use core_logic::datastore::job_changes::{JobChangeKind, JobChangeV1};
+use core_logic::datastore::job_promotions::{JobPromotionV1, PromotionStatus};
+use core_logic::datastore::job_revisions::JobRevisionV1;
+use core_logic::datastore::jobs::{JobV1, Status, next_run_datetime};
+
+const DEFAULT_PROMOTIONS_LIMIT: i64 = 50;
+const MAX_PROMOTIONS_LIMIT: i64 = 500;
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde", deny_unknown_fields)]
+pub struct PromoteJobRequest {
+    pub job_name: String,
+    /// The namespace to promote to, `default` for the default namespace.
+    pub target_namespace: String,
+    /// Job names are unique across namespaces, so the target is named differently, e.g.
+    /// `backup-prod` for `backup-staging`. It is created unless it exists in the target namespace.
+    pub target_job_name: String,
+    /// Why the definition is promoted, recorded with the change to the target job.
+    pub note: String,
+    /// Agents the target job runs on instead of the source job's, which are in another namespace.
+    #[serde(default)]
+    pub agents_required: Option<Vec<String>>,
+    /// Agent groups the target job runs on instead of the source job's.
+    #[serde(default)]
+    pub agent_groups: Option<Vec<String>>,
+}
+
+async fn promotion_or_not_found(
+    state: &State<WebState>,
+    access: &Access,
+    id: &str,
+) -> Result<JobPromotionV1, (rocket::http::Status, String)> {
+    let object_id = ObjectId::parse_str(id).map_err(|_| {
+        (
+            rocket::http::Status::BadRequest,
+            "Invalid promotion ID format".to_string(),
+        )
+    })?;
+    let not_found = || {
+        (
+            rocket::http::Status::NotFound,
+            format!("Promotion {} not found", id),
+        )
+    };
+    let promotion = JobPromotionV1::find(&state.datastore, object_id)
+        .await
+        .map_err(|e| internal_error("Error fetching promotion", e))?
+        .ok_or_else(not_found)?;
+    // Promotions of jobs the user may not see answer as if they did not exist.
+    access
+        .check_job(&state.datastore, &promotion.source_job)
+        .await
+        .map_err(|_| not_found())?;
+    Ok(promotion)
+}
+
+/// The job the promotion is applied to, if it exists already, failing unless the promotion can
+/// be applied to it: it has to be in the target namespace, out of the trash, not synced from a
+/// jobs file, and visible to the user.
+async fn promotion_target(
+    state: &State<WebState>,
+    access: &Access,
+    target_job: &str,
+    target_namespace: Option<&str>,
+) -> Result<Option<JobV1>, (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let Some(existing) = job_collection
+        .find_one(doc! { "name": target_job })
+        .await
+        .map_err(|e| internal_error("Error fetching job", e))?
+    else {
+        return Ok(None);
+    };
+    access.check_job(&state.datastore, target_job).await?;
+    if existing.deleted_at.is_some() {
+        return Err((
+            rocket::http::Status::Conflict,
+            existing_job_error(&existing),
+        ));
+    }
+    if existing.namespace.as_deref() != target_namespace {
+        return Err((
+            rocket::http::Status::Conflict,
+            format!(
+                "Job {} is in namespace {}, not {}",
+                target_job,
+                namespace_name(existing.namespace.as_deref()),
+                namespace_name(target_namespace)
+            ),
+        ));
+    }
+    if let Some(source) = &existing.managed_by {
+        return Err((
+            rocket::http::Status::Conflict,
+            format!(
+                "Job {} is synced from {}; promote by editing it there instead",
+                target_job, source
+            ),
+        ));
+    }
+    Ok(Some(existing))
+}
+
+/// The job's definition without the state of its runs, as stored in its revisions, with its
+/// namespace and latest revision, e.g. to review it before promoting it.
+#[get("/jobs/<name>/export")]
+pub async fn export_job(
+    state: &State<WebState>,
+    access: Access,
+    name: &str,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    access.check_job(&state.datastore, name).await?;
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let job = job_collection
+        .find_one(doc! { "name": name, "deleted_at": null })
+        .await
+        .map_err(|e| internal_error("Error fetching job", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Job {} not found", name),
+            )
+        })?;
+    let revision = JobRevisionV1::latest(&state.datastore, name)
+        .await
+        .map_err(|e| internal_error("Error fetching job revision", e))?
+        .map(|revision| revision.revision);
+    Ok(Json(json!({
+        "name": job.name,
+        "namespace": namespace_name(job.namespace.as_deref()),
+        "revision": revision,
+        "definition": JobRevisionV1::snapshot(&job),
+    })))
+}
+
+/// Requests that a job's definition be copied to another namespace, to be applied once somebody
+/// else approves it. The definition is captured now, as the target job will get it, and the
+/// promotion's id is returned.
+#[post("/job_promotions", data = "<request>")]
+pub async fn post_job_promotion(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    request: Json<PromoteJobRequest>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let request = request.into_inner();
+    let target_job = request.target_job_name.trim();
+    let note = request.note.trim();
+    if target_job.is_empty() || note.is_empty() {
+        return Err((
+            rocket::http::Status::BadRequest,
+            "Target job name and note are required".to_string(),
+        ));
+    }
+    access
+        .check_job(&state.datastore, &request.job_name)
+        .await?;
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let source = job_collection
+        .find_one(doc! { "name": &request.job_name, "deleted_at": null })
+        .await
+        .map_err(|e| internal_error("Error fetching job", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Job {} not found", request.job_name),
+            )
+        })?;
+
+    let target_namespace = parse_namespace(&request.target_namespace);
+    if target_namespace == source.namespace {
+        return Err((
+            rocket::http::Status::BadRequest,
+            format!(
+                "Job {} is already in namespace {}",
+                source.name,
+                namespace_name(target_namespace.as_deref())
+            ),
+        ));
+    }
+    if target_job == source.name {
+        return Err((
+            rocket::http::Status::BadRequest,
+            "Job names are unique across namespaces; name the target job differently".to_string(),
+        ));
+    }
+    let existing =
+        promotion_target(state, &access, target_job, target_namespace.as_deref()).await?;
+    if JobPromotionV1::pending_for(&state.datastore, target_job)
+        .await
+        .map_err(|e| internal_error("Error fetching promotions", e))?
+    {
+        return Err((
+            rocket::http::Status::Conflict,
+            format!(
+                "A promotion to job {} is already waiting for review",
+                target_job
+            ),
+        ));
+    }
+
+    let mut job = JobRevisionV1::snapshot(&source);
+    job.name = target_job.to_string();
+    job.namespace = target_namespace.clone();
+    job.managed_by = None;
+    job.owner = actor.0.clone();
+    if let Some(agents_required) = request.agents_required {
+        job.agents_required = agents_required;
+    }
+    if let Some(agent_groups) = request.agent_groups {
+        job.agent_groups = agent_groups;
+    }
+    if job.agents_required.is_empty() && job.agent_groups.is_empty() {
+        return Err((
+            rocket::http::Status::BadRequest,
+            "Job needs agents_required or agent_groups".to_string(),
+        ));
+    }
+    access
+        .check_targets(&state.datastore, &job.agents_required, &job.agent_groups)
+        .await?;
+    if let Some(existing) = &existing {
+        job.team = existing.team.clone();
+    }
+
+    let source_revision = JobRevisionV1::latest(&state.datastore, &source.name)
+        .await
+        .map_err(|e| internal_error("Error fetching job revision", e))?
+        .map(|revision| revision.revision);
+    let mut promotion = JobPromotionV1 {
+        id: None,
+        source_job: source.name.clone(),
+        source_namespace: source.namespace.clone(),
+        source_revision,
+        target_job: target_job.to_string(),
+        target_namespace,
+        note: note.to_string(),
+        requested_by: actor.name().to_string(),
+        requested_at: DateTime::now(),
+        job,
+        status: PromotionStatus::Pending,
+        reviewed_by: None,
+        reviewed_at: None,
+        review_comment: None,
+    };
+    let id = promotion
+        .insert_entry(&state.datastore)
+        .await
+        .map_err(|e| internal_error("Error saving promotion", e))?;
+    promotion.id = Some(id);
+
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Create,
+        AuditResource::JobPromotion,
+        &id.to_hex(),
+    );
+    audit::record(state, entry.with_diff(None, Some(&promotion))).await;
+
+    Ok(Json(json!({ "id": id.to_hex() })))
+}
+
+/// Up to `limit` promotions (default 50, at most 500), only those with `status` when given,
+/// newest first.
+#[get("/job_promotions?<status>&<limit>")]
+pub async fn job_promotions_data(
+    state: &State<WebState>,
+    access: Access,
+    status: Option<String>,
+    limit: Option<i64>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let limit = limit
+        .unwrap_or(DEFAULT_PROMOTIONS_LIMIT)
+        .clamp(1, MAX_PROMOTIONS_LIMIT);
+    let filter = match status.as_deref() {
+        None | Some("") => doc! {},
+        Some(status @ ("pending" | "approved" | "rejected")) => doc! { "status": status },
+        Some(status) => {
+            return Err((
+                rocket::http::Status::BadRequest,
+                format!("Invalid promotion status {}", status),
+            ));
+        }
+    };
+    let filter = access
+        .scope_by_job(&state.datastore, "source_job", filter)
+        .await?;
+    let promotions = JobPromotionV1::list(&state.datastore, filter, limit)
+        .await
+        .map_err(|e| internal_error("Error fetching promotions", e))?;
+    Ok(Json(json!({ "items": promotions })))
+}
+
+/// Approves a promotion and applies it: the target job is created with the promoted definition,
+/// pending, or its definition is replaced, keeping its runs, place in the schedule, owner and
+/// team. Promotions are approved by somebody other than their requester, unless users are not
+/// identified.
+#[post("/job_promotions/<id>/approve?<comment>")]
+pub async fn approve_job_promotion(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    id: &str,
+    comment: Option<String>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let promotion = promotion_or_not_found(state, &access, id).await?;
+    if promotion.status != PromotionStatus::Pending {
+        return Err((
+            rocket::http::Status::Conflict,
+            format!("Promotion {} was already reviewed", id),
+        ));
+    }
+    if actor.0.is_some() && promotion.requested_by == actor.name() {
+        return Err((
+            rocket::http::Status::Forbidden,
+            "Promotions are approved by somebody other than their requester".to_string(),
+        ));
+    }
+    let target_namespace = promotion.target_namespace.as_deref();
+    let existing =
+        promotion_target(state, &access, &promotion.target_job, target_namespace).await?;
+    if existing.is_none() {
+        access.check_assign(promotion.job.team.as_deref())?;
+    }
+    access
+        .check_targets(
+            &state.datastore,
+            &promotion.job.agents_required,
+            &promotion.job.agent_groups,
+        )
+        .await?;
+
+    let comment = comment.filter(|comment| !comment.trim().is_empty());
+    let approved = JobPromotionV1::decide(
+        &state.datastore,
+        promotion.id.expect("stored promotion has an id"),
+        PromotionStatus::Approved,
+        actor.name(),
+        comment.as_deref(),
+    )
+    .await
+    .map_err(|e| internal_error("Error approving promotion", e))?
+    .ok_or_else(|| {
+        (
+            rocket::http::Status::Conflict,
+            format!("Promotion {} was already reviewed", id),
+        )
+    })?;
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let source = approved.source();
+    let now = DateTime::now().timestamp_millis() / 1000;
+    let name = &approved.target_job;
+    match existing {
+        Some(current) => {
+            let mut promoted = JobV1 {
+                id: current.id,
+                next_run: current.next_run,
+                status: current.status,
+                agents_running: current.agents_running.clone(),
+                agents_complete: current.agents_complete.clone(),
+                cycle_agents: current.cycle_agents.clone(),
+                triggered_by: current.triggered_by.clone(),
+                cycle_id: current.cycle_id.clone(),
+                agents_pending: current.agents_pending.clone(),
+                cancel_requested_at: current.cancel_requested_at,
+                cancel_sent_to: current.cancel_sent_to.clone(),
+                timeout_extension_requests: current.timeout_extension_requests.clone(),
+                scheduling_lag_ms: current.scheduling_lag_ms,
+                rerun: current.rerun.clone(),
+                approval: current.approval.clone(),
+                hook_chain: current.hook_chain.clone(),
+                owner: current.owner.clone(),
+                team: current.team.clone(),
+                ..approved.job.clone()
+            };
+            if (&promoted.cron, &promoted.timezone) != (&current.cron, &current.timezone)
+                && let Some((schedule, timezone)) = promoted.cron_schedule()
+                && let Some(next_run) = schedule.next_after(now, timezone)
+            {
+                promoted.next_run = next_run;
+            }
+            let Some(change) = JobChangeV1::between(&current, &promoted, &source) else {
+                return Ok(format!(
+                    "Approved promotion {}; job {} already matches it",
+                    id, name
+                ));
+            };
+            let mut update = promoted
+                .definition()
+                .map_err(|e| internal_error("Error serializing job", e))?;
+            update.insert("next_run", next_run_datetime(promoted.next_run));
+            job_collection
+                .update_one(
+                    doc! { "name": name, "managed_by": null, "deleted_at": null },
+                    doc! { "$set": update },
+                )
+                .await
+                .map_err(|e| internal_error("Error updating job", e))?;
+            if let Err(e) = change.insert_entry(&state.datastore).await {
+                eprintln!("Error recording job change: {}", e);
+            }
+            record_revision(state, Some(&current), &promoted, &actor, &source).await;
+            let entry =
+                AuditEntryV1::new(actor.name(), AuditAction::Update, AuditResource::Job, name);
+            audit::record(state, entry.with_diff(Some(&current), Some(&promoted))).await;
+        }
+        None => {
+            let mut job = JobV1 {
+                status: Status::Pending,
+                next_run: now,
+                ..approved.job.clone()
+            };
+            if let Some((schedule, timezone)) = job.cron_schedule() {
+                job.next_run = schedule.next_after(now, timezone).unwrap_or(now);
+            }
+            job_collection
+                .insert_one(&job)
+                .await
+                .map_err(|e| internal_error("Error creating job", e))?;
+            let change = JobChangeV1::new(name, JobChangeKind::Created, &source);
+            if let Err(e) = change.insert_entry(&state.datastore).await {
+                eprintln!("Error recording job change: {}", e);
+            }
+            record_revision(state, None, &job, &actor, &source).await;
+            let entry =
+                AuditEntryV1::new(actor.name(), AuditAction::Create, AuditResource::Job, name);
+            audit::record(state, entry.with_diff(None, Some(&job))).await;
+        }
+    }
+
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Approve,
+        AuditResource::JobPromotion,
+        id,
+    );
+    audit::record(state, entry.with_diff(Some(&promotion), Some(&approved))).await;
+
+    Ok(format!(
+        "Promoted job {} to job {} in namespace {}",
+        approved.source_job,
+        name,
+        namespace_name(target_namespace)
+    ))
+}
+
+/// Rejects a promotion; its requester may reject it to withdraw it.
+#[post("/job_promotions/<id>/reject?<comment>")]
+pub async fn reject_job_promotion(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    id: &str,
+    comment: Option<String>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let promotion = promotion_or_not_found(state, &access, id).await?;
+    let comment = comment.filter(|comment| !comment.trim().is_empty());
+    let rejected = JobPromotionV1::decide(
+        &state.datastore,
+        promotion.id.expect("stored promotion has an id"),
+        PromotionStatus::Rejected,
+        actor.name(),
+        comment.as_deref(),
+    )
+    .await
+    .map_err(|e| internal_error("Error rejecting promotion", e))?
+    .ok_or_else(|| {
+        (
+            rocket::http::Status::Conflict,
+            format!("Promotion {} was already reviewed", id),
+        )
+    })?;
+
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Reject,
+        AuditResource::JobPromotion,
+        id,
+    );
+    audit::record(state, entry.with_diff(Some(&promotion), Some(&rejected))).await;
+
+    Ok(format!("Rejected promotion {}", id))
+}
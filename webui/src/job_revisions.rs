@@ -0,0 +1,193 @@
+use mongodb::bson::{DateTime, doc};
+use rocket::State;
+use rocket::serde::json::Json;
+use rocket::{get, post};
+use serde_json::json;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::data_page::with_iso_dates;
+use crate::internal_error;
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+use core_logic::datastore::job_changes::JobChangeV1;
+use core_logic::datastore::job_revisions::JobRevisionV1;
+use core_logic::datastore::jobs::{JobV1, next_run_datetime};
+
+const DEFAULT_HISTORY_LIMIT: i64 = 20;
+const MAX_HISTORY_LIMIT: i64 = 100;
+
+/// Records `job` as a new revision made by `actor` through `source`, logging rather than failing
+/// the edit when it cannot be recorded, as for job changes.
+pub async fn record_revision(
+    state: &State<WebState>,
+    previous: Option<&JobV1>,
+    job: &JobV1,
+    actor: &Actor,
+    source: &str,
+) {
+    if let Err(e) =
+        JobRevisionV1::record(&state.datastore, previous, job, actor.name(), source).await
+    {
+        eprintln!("Error recording job revision: {}", e);
+    }
+}
+
+/// The job's latest revisions (default 20, at most 100), newest first, each with the fields that
+/// changed from the revision before it.
+#[get("/jobs/<name>/revisions?<limit>")]
+pub async fn job_revision_history(
+    state: &State<WebState>,
+    access: Access,
+    name: &str,
+    limit: Option<i64>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    access.check_job(&state.datastore, name).await?;
+    let limit = limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .clamp(1, MAX_HISTORY_LIMIT);
+    // One more than shown, to diff the oldest one shown against.
+    let revisions = JobRevisionV1::history(&state.datastore, name, limit + 1)
+        .await
+        .map_err(|e| internal_error("Error fetching job revisions", e))?;
+
+    let items: Vec<serde_json::Value> = revisions
+        .iter()
+        .enumerate()
+        .take(limit as usize)
+        .map(|(index, revision)| {
+            let changes = revisions
+                .get(index + 1)
+                .map(|previous| revision.changes_since(previous))
+                .unwrap_or_default();
+            json!({
+                "revision": revision.revision,
+                "created_at": revision.created_at,
+                "author": revision.author,
+                "source": revision.source,
+                "changes": changes,
+                "job": revision.job,
+            })
+        })
+        .collect();
+    Ok(Json(with_iso_dates(json!({ "items": items }))))
+}
+
+/// Restores the definition the job had at `revision`, recording it as a new revision. The job's
+/// runs and its place in the schedule are kept, except that restoring a different cron
+/// expression or time zone moves `next_run` to the expression's next occurrence. Jobs synced from
+/// a jobs file are rolled back by editing the file instead.
+#[post("/jobs/<name>/revisions/<revision>/rollback")]
+pub async fn rollback_job(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+    revision: u32,
+) -> Result<String, (rocket::http::Status, String)> {
+    access.check_job(&state.datastore, name).await?;
+    let target = JobRevisionV1::find(&state.datastore, name, revision)
+        .await
+        .map_err(|e| internal_error("Error fetching job revision", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Job {} has no revision {}", name, revision),
+            )
+        })?;
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let current = job_collection
+        .find_one(doc! { "name": name, "deleted_at": null })
+        .await
+        .map_err(|e| internal_error("Error fetching job", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Job {} not found", name),
+            )
+        })?;
+    if let Some(source) = &current.managed_by {
+        return Err((
+            rocket::http::Status::Conflict,
+            format!(
+                "Job {} is synced from {}; edit it there instead",
+                name, source
+            ),
+        ));
+    }
+
+    let mut restored = JobV1 {
+        id: current.id,
+        name: current.name.clone(),
+        next_run: current.next_run,
+        status: current.status,
+        agents_running: current.agents_running.clone(),
+        agents_complete: current.agents_complete.clone(),
+        cycle_agents: current.cycle_agents.clone(),
+        triggered_by: current.triggered_by.clone(),
+        cycle_id: current.cycle_id.clone(),
+        agents_pending: current.agents_pending.clone(),
+        template: current.template.clone(),
+        managed_by: None,
+        cancel_requested_at: current.cancel_requested_at,
+        cancel_sent_to: current.cancel_sent_to.clone(),
+        timeout_extension_requests: current.timeout_extension_requests.clone(),
+        scheduling_lag_ms: current.scheduling_lag_ms,
+        deleted_at: None,
+        status_before_delete: None,
+        rerun: current.rerun.clone(),
+        approval: current.approval.clone(),
+        hook_chain: current.hook_chain.clone(),
+        owner: current.owner.clone(),
+        team: current.team.clone(),
+        namespace: current.namespace.clone(),
+        ..target.job.clone()
+    };
+    access
+        .check_targets(
+            &state.datastore,
+            &restored.agents_required,
+            &restored.agent_groups,
+        )
+        .await?;
+    if (&restored.cron, &restored.timezone) != (&current.cron, &current.timezone)
+        && let Some((schedule, timezone)) = restored.cron_schedule()
+        && let Some(next_run) =
+            schedule.next_after(DateTime::now().timestamp_millis() / 1000, timezone)
+    {
+        restored.next_run = next_run;
+    }
+
+    let source = format!("rollback to revision {}", revision);
+    let Some(change) = JobChangeV1::between(&current, &restored, &source) else {
+        return Ok(format!(
+            "Job {} already matches revision {}",
+            name, revision
+        ));
+    };
+    let mut update = restored
+        .definition()
+        .map_err(|e| internal_error("Error serializing job", e))?;
+    update.insert("next_run", next_run_datetime(restored.next_run));
+    job_collection
+        .update_one(
+            doc! { "name": name, "managed_by": null },
+            doc! { "$set": update },
+        )
+        .await
+        .map_err(|e| internal_error("Error updating job", e))?;
+
+    if let Err(e) = change.insert_entry(&state.datastore).await {
+        eprintln!("Error recording job change: {}", e);
+    }
+    record_revision(state, Some(&current), &restored, &actor, &source).await;
+    let entry = AuditEntryV1::new(actor.name(), AuditAction::Update, AuditResource::Job, name);
+    audit::record(state, entry.with_diff(Some(&current), Some(&restored))).await;
+
+    Ok(format!("Rolled back job {} to revision {}", name, revision))
+}
@@ -0,0 +1,281 @@
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use rocket::State;
+use rocket::serde::Deserialize;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post};
+use serde_json::json;
+
+use std::collections::HashMap;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::job_revisions::record_revision;
+use crate::namespaces::Namespace;
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+use core_logic::datastore::job_changes::{JobChangeKind, JobChangeV1};
+use core_logic::datastore::job_templates::JobTemplateV1;
+use core_logic::datastore::jobs::JobV1;
+
+#[get("/job_templates/data")]
+pub async fn job_templates_data(
+    state: &State<WebState>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let template_collection = state
+        .datastore
+        .get_collection::<JobTemplateV1>("job_templates")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing job templates collection: {}", e),
+            )
+        })?;
+
+    let templates: Vec<JobTemplateV1> = template_collection
+        .find(doc! {})
+        .sort(doc! { "name": 1 })
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error fetching job templates: {}", e),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading job templates: {}", e),
+            )
+        })?;
+
+    Ok(Json(json!({
+        "items": templates,
+    })))
+}
+
+/// Creates a template, or replaces the template with the same name.
+#[post("/job_templates", data = "<template>")]
+pub async fn post_job_template(
+    state: &State<WebState>,
+    actor: Actor,
+    template: Json<JobTemplateV1>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let mut template = template.into_inner();
+    template.id = None;
+    if template.name.trim().is_empty() || template.command.trim().is_empty() {
+        return Err((
+            rocket::http::Status::BadRequest,
+            "Template name and command are required".to_string(),
+        ));
+    }
+    template
+        .validate()
+        .map_err(|e| (rocket::http::Status::BadRequest, e.to_string()))?;
+
+    let template_collection = state
+        .datastore
+        .get_collection::<JobTemplateV1>("job_templates")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing job templates collection: {}", e),
+            )
+        })?;
+
+    let previous = template_collection
+        .find_one_and_replace(doc! { "name": &template.name }, &template)
+        .upsert(true)
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error saving job template: {}", e),
+            )
+        })?;
+
+    let action = match previous {
+        Some(_) => AuditAction::Update,
+        None => AuditAction::Create,
+    };
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        action,
+        AuditResource::JobTemplate,
+        &template.name,
+    );
+    audit::record(state, entry.with_diff(previous.as_ref(), Some(&template))).await;
+
+    Ok("Success".to_string())
+}
+
+#[delete("/job_templates/<name>")]
+pub async fn delete_job_template(
+    state: &State<WebState>,
+    actor: Actor,
+    name: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let template_collection = state
+        .datastore
+        .get_collection::<JobTemplateV1>("job_templates")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing job templates collection: {}", e),
+            )
+        })?;
+
+    let deleted = template_collection
+        .find_one_and_delete(doc! { "name": name })
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error deleting job template: {}", e),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Job template {} not found", name),
+            )
+        })?;
+
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Delete,
+        AuditResource::JobTemplate,
+        name,
+    );
+    audit::record(state, entry.with_diff(Some(&deleted), None)).await;
+
+    Ok("Success".to_string())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InstantiateTemplateRequest {
+    pub job_name: String,
+    #[serde(default)]
+    pub parameters: HashMap<String, String>,
+    pub agents_required: Vec<String>,
+    /// Unix timestamp of the first run; omitted to run as soon as possible.
+    pub next_run: Option<i64>,
+    /// The team the job belongs to; the user's first team when omitted while teams are scoped.
+    #[serde(default)]
+    pub team: Option<String>,
+}
+
+/// Creates a pending job from a template, validating the parameter values. The job joins the
+/// selected namespace.
+#[post("/job_templates/<name>/instantiate", data = "<request>")]
+pub async fn instantiate_job_template(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    namespace: Namespace,
+    name: &str,
+    request: Json<InstantiateTemplateRequest>,
+) -> Result<String, (rocket::http::Status, String)> {
+    if request.job_name.trim().is_empty() {
+        return Err((
+            rocket::http::Status::BadRequest,
+            "Job name is required".to_string(),
+        ));
+    }
+    let team = request
+        .team
+        .as_deref()
+        .map(str::trim)
+        .filter(|team| !team.is_empty())
+        .map(str::to_string)
+        .or_else(|| access.default_team());
+    access.check_assign(team.as_deref())?;
+
+    let template_collection = state
+        .datastore
+        .get_collection::<JobTemplateV1>("job_templates")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing job templates collection: {}", e),
+            )
+        })?;
+
+    let template = template_collection
+        .find_one(doc! { "name": name })
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error fetching job template: {}", e),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Job template {} not found", name),
+            )
+        })?;
+
+    let next_run = request
+        .next_run
+        .unwrap_or_else(|| chrono::Utc::now().timestamp());
+    let mut job = template
+        .instantiate(
+            &request.job_name,
+            &request.parameters,
+            request.agents_required.clone(),
+            next_run,
+        )
+        .map_err(|e| (rocket::http::Status::BadRequest, e.to_string()))?;
+    job.owner = actor.0.clone();
+    job.team = team;
+    job.namespace = namespace.for_new();
+    access
+        .check_targets(&state.datastore, &job.agents_required, &job.agent_groups)
+        .await?;
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing jobs collection: {}", e),
+            )
+        })?;
+
+    job_collection.insert_one(&job).await.map_err(|e| {
+        (
+            rocket::http::Status::InternalServerError,
+            format!("Error creating job: {}", e),
+        )
+    })?;
+
+    let change = JobChangeV1::new(
+        &job.name,
+        JobChangeKind::Created,
+        &format!("template {}", template.name),
+    );
+    if let Err(e) = change.insert_entry(&state.datastore).await {
+        eprintln!("Error recording job change: {}", e);
+    }
+    let source = format!("template {}", template.name);
+    record_revision(state, None, &job, &actor, &source).await;
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Create,
+        AuditResource::Job,
+        &job.name,
+    );
+    audit::record(state, entry.with_diff(None, Some(&job))).await;
+
+    Ok("Success".to_string())
+}
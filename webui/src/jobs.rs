@@ -1,18 +1,50 @@
-use core_logic::datastore::jobs::JobV1;
+use core_logic::cron::CronSchedule;
+use core_logic::datastore::agent_groups::AgentGroupV1;
+use core_logic::datastore::agents::{AgentV1, Status as AgentStatus};
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+use core_logic::datastore::job_changes::{FieldChange, JobChangeKind, JobChangeV1};
+use core_logic::datastore::job_executions::JobExecutionV1;
+use core_logic::datastore::jobs::{
+    self, JobApproval, JobContainer, JobExecutor, JobFile, JobScript, JobSla, JobStep, JobV1,
+    MisfirePolicy, Status, SuccessRule, TimeoutExtensionRequest,
+};
+use core_logic::datastore::runs::TriggeredBy;
+use core_logic::output_parsing::OutputParser;
+use core_logic::redaction;
+use futures::TryStreamExt;
+use mongodb::bson::{self, doc, oid::ObjectId};
 use rocket::State;
-use rocket::get;
 use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{delete, get, post};
 use rocket_dyn_templates::{Template, context};
-use serde_json::json;
 
 use std::collections::HashMap;
 
 use crate::WebState;
+use crate::access::Access;
+use crate::api_tokens::TokenCaller;
+use crate::audit::{self, Actor};
 use crate::data_page::{DataPage, DataPageParams};
+use crate::internal_error;
+use crate::job_revisions::record_revision;
+use crate::namespaces::Namespace;
+
+/// Fields the jobs page can be sorted and range filtered by.
+const JOB_SORT_FIELDS: &[&str] = &[
+    "name",
+    "description",
+    "status",
+    "team",
+    "command",
+    "next_run",
+    "scheduling_lag_ms",
+];
+const JOB_RANGE_FIELDS: &[&str] = &["next_run"];
 
 #[allow(clippy::too_many_arguments)]
 #[get(
-    "/jobs?<page>&<range_select>&<status_filter>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<outcome_filter>&<sort>&<order>"
+    "/jobs?<page>&<range_select>&<status_filter>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<outcome_filter>&<sort>&<order>&<team_filter>&<tag_filter>"
 )]
 pub async fn jobs_page(
     range_start: Option<u64>,
@@ -26,6 +58,8 @@ pub async fn jobs_page(
     status_filter: Option<String>,
     order: Option<String>,
     outcome_filter: Option<String>,
+    team_filter: Option<String>,
+    tag_filter: Option<String>,
     page: Option<u32>,
 ) -> Template {
     Template::render(
@@ -36,7 +70,7 @@ pub async fn jobs_page(
             range_start: range_start.unwrap_or_default(),
             range_end: range_end.unwrap_or_default(),
             range_select: range_select.unwrap_or_default(),
-            range_fields: vec!["next_run".to_string()],
+            range_fields: JOB_RANGE_FIELDS,
             filter: filter.unwrap_or_default(),
             order: order.unwrap_or_default(),
             outcome_filter: outcome_filter.unwrap_or_default(),
@@ -45,16 +79,20 @@ pub async fn jobs_page(
             relative_select_value: relative_select_value.unwrap_or(30),
             relative_select_unit: relative_select_unit.unwrap_or_default(),
             status_filter: status_filter.unwrap_or_default(),
+            team_filter: team_filter.unwrap_or_default(),
+            tag_filter: tag_filter.unwrap_or_default(),
         },
     )
 }
 
 #[allow(clippy::too_many_arguments)]
 #[get(
-    "/jobs_data?<page>&<range_select>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<sort>&<status_filter>&<order>"
+    "/jobs_data?<page>&<range_select>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<sort>&<status_filter>&<team_filter>&<tag_filter>&<order>&<page_size>&<after>"
 )]
 pub async fn jobs_data(
     state: &State<WebState>,
+    access: Access,
+    namespace: Namespace,
     page: Option<u32>,
     range_select: Option<String>,
     range_end: Option<u64>,
@@ -66,15 +104,36 @@ pub async fn jobs_data(
     sort: Option<String>,
     order: Option<String>,
     status_filter: Option<String>,
-) -> Json<serde_json::Value> {
+    team_filter: Option<String>,
+    tag_filter: Option<String>,
+    page_size: Option<u32>,
+    after: Option<String>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
     let range_select = range_select
         .clone()
-        .unwrap_or_else(|| "started_at".to_string());
+        .unwrap_or_else(|| "next_run".to_string());
+    // Trashed jobs are listed in the trash instead, and archived ones only when asked for by
+    // status.
+    let mut base_filter = doc! { "deleted_at": null };
+    if status_filter
+        .as_ref()
+        .is_none_or(|status_filter| status_filter.is_empty())
+    {
+        base_filter.insert("status", doc! { "$ne": Status::Archived });
+    }
+    if let Some(team_filter) = team_filter.filter(|team_filter| !team_filter.is_empty()) {
+        base_filter.insert("team", team_filter);
+    }
+    // Jobs with every tag of the comma separated `tag_filter`.
+    if let Some(tags) = tag_filter.as_deref().and_then(jobs::tags_filter) {
+        base_filter.extend(tags);
+    }
     let data_page_params = DataPageParams {
         collection: "jobs".to_string(),
         range_start,
         range_end,
         range_field: Some(range_select),
+        range_fields: JOB_RANGE_FIELDS,
         search_fields: vec![
             "job_name".to_string(),
             "agent_name".to_string(),
@@ -84,31 +143,1274 @@ pub async fn jobs_data(
         ],
         page,
         filter: filter.clone(),
-        additional_filters: if status_filter.is_some() {
-            let mut filters = HashMap::new();
-            filters.insert("status".to_string(), status_filter.unwrap());
-            Some(filters)
-        } else {
-            None
-        },
+        additional_filters: status_filter
+            .clone()
+            .map(|status_filter| HashMap::from([("status".to_string(), status_filter)])),
+        base_filter: Some(access.scope(namespace.scope(base_filter))),
         sort: sort.clone(),
+        sort_fields: JOB_SORT_FIELDS,
         order,
         relative_select,
         relative_value: relative_select_value.map(|v| v as u64),
         relative_unit: relative_select_unit,
+        page_size,
+        after,
+    };
+
+    let jobs_page: DataPage<JobV1> = DataPage::new(state, data_page_params).await?;
+
+    Ok(Json(jobs_page.json()))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", deny_unknown_fields)]
+pub struct CreateJobRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// May be left out when the job has `steps`.
+    #[serde(default)]
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub cwd: String,
+    #[serde(default)]
+    pub timeout: u32,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default)]
+    pub valid_return_codes: Vec<i32>,
+    #[serde(default)]
+    pub agents_required: Vec<String>,
+    /// Agent groups whose members also run the job; expanded each time it is dispatched.
+    #[serde(default)]
+    pub agent_groups: Vec<String>,
+    /// Platforms the job runs on, as `os` or `os/arch` (e.g. `linux/amd64`); any when omitted.
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// Kubernetes node labels, as `key=value` (e.g. `disktype=ssd`), the node of each agent must
+    /// have; any node when omitted.
+    #[serde(default)]
+    pub node_selector: Vec<String>,
+    /// `{"kind": "kubernetes", "image": ...}` runs the job as Kubernetes Jobs created by central
+    /// command instead of on agents, and `{"kind": "ssh", "host": ...}` over SSH on a host without
+    /// an agent.
+    #[serde(default)]
+    pub executor: JobExecutor,
+    #[serde(default)]
+    pub redact_patterns: Vec<String>,
+    #[serde(default)]
+    pub sla: Option<JobSla>,
+    #[serde(default)]
+    pub steps: Vec<JobStep>,
+    /// Files pushed to each agent before the job is dispatched to it.
+    #[serde(default)]
+    pub files: Vec<JobFile>,
+    /// A bash, python or powershell script the agent runs instead of `command`.
+    #[serde(default)]
+    pub script: Option<JobScript>,
+    /// A container image the agents run the command in, e.g. `{"image": "alpine:3.20"}`.
+    #[serde(default)]
+    pub container: Option<JobContainer>,
+    /// Extract a status and values from each run's output, e.g.
+    /// `[{"kind": "json", "fields": {"records": "/stats/records"}}]`.
+    #[serde(default)]
+    pub output_parsers: Vec<OutputParser>,
+    /// Parsed statuses of successful runs; runs with another status fail.
+    #[serde(default)]
+    pub success_statuses: Vec<String>,
+    /// Run as a monitoring check whose exit code sets its state: 0 OK, 1 WARNING, 2 CRITICAL.
+    #[serde(default)]
+    pub check: bool,
+    #[serde(default)]
+    pub success_rule: SuccessRule,
+    /// Run once at `next_run`, or straight away, then archive the job.
+    #[serde(default)]
+    pub one_shot: bool,
+    /// Whether each run waits for an operator's approval before it is dispatched.
+    #[serde(default)]
+    pub requires_approval: bool,
+    /// Jobs to trigger when a run of the job succeeds.
+    #[serde(default)]
+    pub on_success: Vec<String>,
+    /// Jobs to trigger when a run of the job fails, e.g. a cleanup or rollback job.
+    #[serde(default)]
+    pub on_failure: Vec<String>,
+    /// Seconds between runs; the job runs once when omitted.
+    #[serde(default)]
+    pub schedule_interval: Option<u32>,
+    /// Cron expression to run on instead of `schedule_interval`.
+    #[serde(default)]
+    pub cron: Option<String>,
+    /// IANA time zone the cron expression is evaluated in; UTC when omitted.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// What to do about runs missed while central command was not dispatching.
+    #[serde(default)]
+    pub misfire_policy: MisfirePolicy,
+    /// Unix timestamp of the first run; when omitted the job runs as soon as possible, or at the
+    /// first cron occurrence for cron jobs.
+    #[serde(default)]
+    pub next_run: Option<i64>,
+    /// The team the job belongs to; the creator's first team when omitted while teams are scoped.
+    #[serde(default)]
+    pub team: Option<String>,
+    /// Labels organizing the job, e.g. `["backup", "nightly"]`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Free-form values describing the job, e.g. `{"ticket": "OPS-12"}`.
+    #[serde(default)]
+    pub metadata: bson::Document,
+    /// The namespace the job belongs to; the selected namespace when omitted.
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+/// Explains why `name` could not be updated: it does not exist, or its status is `conflict`.
+async fn job_update_error(
+    state: &State<WebState>,
+    access: &Access,
+    name: &str,
+    conflict: &str,
+) -> (rocket::http::Status, String) {
+    let job_collection = match state.datastore.get_collection::<JobV1>("jobs").await {
+        Ok(collection) => collection,
+        Err(e) => return internal_error("Error accessing jobs collection", e),
+    };
+    match job_collection
+        .find_one(access.scope(doc! { "name": name }))
+        .await
+    {
+        Ok(Some(_)) => (
+            rocket::http::Status::Conflict,
+            format!("Job {} {}", name, conflict),
+        ),
+        Ok(None) => (
+            rocket::http::Status::NotFound,
+            format!("Job {} not found", name),
+        ),
+        Err(e) => internal_error("Error fetching job", e),
+    }
+}
+
+/// Why a job cannot be created with the name of `existing`.
+pub fn existing_job_error(existing: &JobV1) -> String {
+    match existing.deleted_at {
+        Some(_) => format!(
+            "Job {} is in the trash; restore or purge it first",
+            existing.name
+        ),
+        None => format!("Job {} already exists", existing.name),
+    }
+}
+
+/// Everything `create_job` rejects `request` for, apart from its name being taken.
+fn request_errors(request: &CreateJobRequest) -> Vec<String> {
+    let mut errors = vec![];
+    if request.name.trim().is_empty()
+        || (request.command.trim().is_empty()
+            && request.script.is_none()
+            && request.steps.is_empty())
+    {
+        errors.push("Job name and command, script or steps are required".to_string());
+    }
+    if request
+        .steps
+        .iter()
+        .any(|step| step.name.trim().is_empty() || step.command.trim().is_empty())
+    {
+        errors.push("Every step needs a name and command".to_string());
+    }
+    let on_executor = request.executor.agent_name().is_some();
+    if request.agents_required.is_empty() && request.agent_groups.is_empty() && !on_executor {
+        errors.push("Job needs agents_required or agent_groups".to_string());
+    }
+    let checks = [
+        request.success_rule.validate(match on_executor {
+            true => Some(1),
+            false => request
+                .agent_groups
+                .is_empty()
+                .then_some(request.agents_required.len()),
+        }),
+        jobs::validate_platforms(&request.platforms),
+        jobs::validate_node_selector(&request.node_selector),
+        jobs::validate_executor(
+            &request.executor,
+            &request.steps,
+            request.script.as_ref(),
+            &request.files,
+            &request.node_selector,
+        ),
+        jobs::validate_hooks(&request.name, &request.on_success, &request.on_failure),
+        jobs::validate_tags(&request.tags),
+        jobs::validate_files(&request.files),
+        jobs::validate_script(request.script.as_ref(), &request.command, &request.steps),
+        jobs::validate_container(
+            request.container.as_ref(),
+            request.script.as_ref(),
+            &request.executor,
+        ),
+        jobs::validate_output_parsers(&request.output_parsers, &request.success_statuses),
+        jobs::validate_schedule(
+            request.schedule_interval,
+            request.cron.as_deref(),
+            request.timezone.as_deref(),
+            request.one_shot,
+        ),
+    ];
+    errors.extend(checks.into_iter().filter_map(Result::err));
+    for pattern in &request.redact_patterns {
+        if let Err(e) = redaction::validate_pattern(pattern) {
+            errors.push(e.to_string());
+        }
+    }
+    errors
+}
+
+/// The team of the job `request` describes: its own, or the user's default.
+fn job_team(access: &Access, request: &CreateJobRequest) -> Option<String> {
+    request
+        .team
+        .as_deref()
+        .map(str::trim)
+        .filter(|team| !team.is_empty())
+        .map(str::to_string)
+        .or_else(|| access.default_team())
+}
+
+/// Up to `count` upcoming runs of the job `request` describes, as Unix timestamps. The first is
+/// `next_run` or, without one, now or the first cron occurrence.
+fn upcoming_runs(request: &CreateJobRequest, count: usize) -> Vec<i64> {
+    let start = request
+        .next_run
+        .unwrap_or_else(|| chrono::Utc::now().timestamp());
+    if let Some(cron) = &request.cron {
+        let (Ok(schedule), Ok(timezone)) = (
+            CronSchedule::parse(cron),
+            jobs::parse_timezone(request.timezone.as_deref()),
+        ) else {
+            return vec![];
+        };
+        let mut runs: Vec<i64> = request.next_run.into_iter().collect();
+        while runs.len() < count {
+            match schedule.next_after(runs.last().copied().unwrap_or(start), timezone) {
+                Some(next) => runs.push(next),
+                None => break,
+            }
+        }
+        return runs;
+    }
+    match request.schedule_interval.filter(|_| !request.one_shot) {
+        Some(interval) => (0..count as i64)
+            .map(|index| start + index * interval as i64)
+            .collect(),
+        None => vec![start],
+    }
+}
+
+/// Creates a job. The job is pending, so it runs at `next_run` or straight away.
+#[post("/jobs", data = "<request>")]
+pub async fn create_job(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    namespace: Namespace,
+    request: Json<CreateJobRequest>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let request = request.into_inner();
+    if let Some(error) = request_errors(&request).into_iter().next() {
+        return Err((rocket::http::Status::BadRequest, error));
+    }
+    let team = job_team(&access, &request);
+    access.check_assign(team.as_deref())?;
+    access
+        .check_targets(
+            &state.datastore,
+            &request.agents_required,
+            &request.agent_groups,
+        )
+        .await?;
+    let namespace = request
+        .namespace
+        .as_deref()
+        .map(str::trim)
+        .filter(|namespace| !namespace.is_empty())
+        .map(str::to_string)
+        .or_else(|| namespace.for_new());
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+
+    let existing = job_collection
+        .find_one(doc! { "name": &request.name })
+        .await
+        .map_err(|e| internal_error("Error fetching job", e))?;
+    if let Some(existing) = existing {
+        return Err((
+            rocket::http::Status::Conflict,
+            existing_job_error(&existing),
+        ));
+    }
+
+    let next_run = upcoming_runs(&request, 1)
+        .first()
+        .copied()
+        .unwrap_or_else(|| chrono::Utc::now().timestamp());
+    let job = JobV1 {
+        id: None,
+        name: request.name,
+        next_run,
+        status: Status::Pending,
+        description: request.description,
+        command: request.command,
+        args: request.args,
+        env: request.env,
+        cwd: request.cwd,
+        timeout: request.timeout,
+        retries: request.retries,
+        valid_return_codes: request.valid_return_codes,
+        agents_required: request.agents_required,
+        agents_running: vec![],
+        agents_complete: vec![],
+        agent_groups: request.agent_groups,
+        platforms: request.platforms,
+        node_selector: request.node_selector,
+        executor: request.executor,
+        cycle_agents: vec![],
+        triggered_by: None,
+        cycle_id: None,
+        agents_pending: vec![],
+        redact_patterns: request.redact_patterns,
+        template: None,
+        sla: request.sla.filter(|sla| !sla.is_empty()),
+        managed_by: None,
+        cancel_requested_at: None,
+        cancel_sent_to: vec![],
+        timeout_extension_requests: vec![],
+        scheduling_lag_ms: None,
+        steps: request.steps,
+        files: request.files,
+        script: request.script,
+        container: request.container,
+        output_parsers: request.output_parsers,
+        success_statuses: request.success_statuses,
+        check: request.check,
+        success_rule: request.success_rule,
+        one_shot: request.one_shot,
+        requires_approval: request.requires_approval,
+        approval: None,
+        on_success: request.on_success,
+        on_failure: request.on_failure,
+        hook_chain: vec![],
+        schedule_interval: request.schedule_interval,
+        cron: request.cron,
+        timezone: request.timezone,
+        misfire_policy: request.misfire_policy,
+        deleted_at: None,
+        status_before_delete: None,
+        rerun: None,
+        owner: actor.0.clone(),
+        team,
+        tags: request.tags,
+        metadata: request.metadata,
+        namespace,
+    };
+    job_collection
+        .insert_one(&job)
+        .await
+        .map_err(|e| internal_error("Error creating job", e))?;
+
+    let change = JobChangeV1::new(&job.name, JobChangeKind::Created, "web UI");
+    if let Err(e) = change.insert_entry(&state.datastore).await {
+        eprintln!("Error recording job change: {}", e);
+    }
+    record_revision(state, None, &job, &actor, "web UI").await;
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Create,
+        AuditResource::Job,
+        &job.name,
+    );
+    audit::record(state, entry.with_diff(None, Some(&job))).await;
+
+    Ok("Success".to_string())
+}
+
+/// A dry run of `create_job`, reported by `validate_job`.
+#[derive(Debug, Default, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct JobValidation {
+    /// Whether `create_job` would accept the definition.
+    pub valid: bool,
+    /// Why `create_job` would reject the definition.
+    pub errors: Vec<String>,
+    /// Problems that do not stop the job being created, e.g. a required agent that is not
+    /// registered yet.
+    pub warnings: Vec<String>,
+    /// Whether `cron` parses; omitted for jobs without one.
+    pub cron_valid: Option<bool>,
+    /// The first few runs, as Unix timestamps.
+    pub upcoming_runs: Vec<i64>,
+    /// Agents the job would be dispatched to if it were due now.
+    pub targeted_agents: Vec<String>,
+    /// Agents named by the job, directly or through a group, it would not be dispatched to now.
+    pub skipped_agents: Vec<SkippedAgent>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SkippedAgent {
+    pub name: String,
+    pub reason: String,
+}
+
+/// Number of runs listed in `JobValidation::upcoming_runs`.
+const UPCOMING_RUNS: usize = 5;
+
+/// Checks a job definition the way `create_job` does, without saving it, and reports which of the
+/// currently known agents it would target.
+#[post("/jobs/validate", data = "<request>")]
+pub async fn validate_job(
+    state: &State<WebState>,
+    access: Access,
+    request: Json<CreateJobRequest>,
+) -> Result<Json<JobValidation>, (rocket::http::Status, String)> {
+    let request = request.into_inner();
+    let mut validation = JobValidation {
+        errors: request_errors(&request),
+        cron_valid: request
+            .cron
+            .as_deref()
+            .map(|cron| CronSchedule::parse(cron).is_ok()),
+        upcoming_runs: upcoming_runs(&request, UPCOMING_RUNS),
+        ..Default::default()
+    };
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let existing = job_collection
+        .find_one(doc! { "name": &request.name })
+        .await
+        .map_err(|e| internal_error("Error fetching job", e))?;
+    if let Some(existing) = existing {
+        validation.errors.push(existing_job_error(&existing));
+    }
+    if let Err((_, error)) = access.check_assign(job_team(&access, &request).as_deref()) {
+        validation.errors.push(error);
+    }
+    if let Err((_, error)) = access
+        .check_targets(
+            &state.datastore,
+            &request.agents_required,
+            &request.agent_groups,
+        )
+        .await
+    {
+        validation.errors.push(error);
+    }
+
+    // Required agents first, then group members, in the order dispatch considers them.
+    let group_collection = state
+        .datastore
+        .get_collection::<AgentGroupV1>("agent_groups")
+        .await
+        .map_err(|e| internal_error("Error accessing agent groups collection", e))?;
+    let groups: Vec<AgentGroupV1> = group_collection
+        .find(doc! { "name": { "$in": &request.agent_groups } })
+        .sort(doc! { "name": 1 })
+        .await
+        .map_err(|e| internal_error("Error fetching agent groups", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error fetching agent groups", e))?;
+    for name in &request.agent_groups {
+        if !groups.iter().any(|group| &group.name == name) {
+            validation
+                .warnings
+                .push(format!("Agent group {} does not exist", name));
+        }
+    }
+    let executor_agent = request.executor.agent_name();
+    let kubernetes = matches!(request.executor, JobExecutor::Kubernetes { .. });
+    let mut candidates = request.agents_required.clone();
+    for member in groups.into_iter().flat_map(|group| group.members) {
+        if !candidates.contains(&member) {
+            candidates.push(member);
+        }
+    }
+    // Central command's executors run the job instead of its agents.
+    if let Some(agent) = executor_agent {
+        candidates = vec![agent.to_string()];
+    }
+
+    let agent_collection = state
+        .datastore
+        .get_collection::<AgentV1>("agents")
+        .await
+        .map_err(|e| internal_error("Error accessing agents collection", e))?;
+    let agents: Vec<AgentV1> = agent_collection
+        .find(doc! { "name": { "$in": &candidates } })
+        .await
+        .map_err(|e| internal_error("Error fetching agents", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error fetching agents", e))?;
+    for name in candidates {
+        let Some(agent) = agents.iter().find(|agent| agent.name == name) else {
+            if executor_agent.is_some() {
+                validation.warnings.push(format!(
+                    "The {} executor is not enabled on central command",
+                    request.executor.kind()
+                ));
+            } else if request.agents_required.contains(&name) {
+                validation
+                    .warnings
+                    .push(format!("Agent {} is not registered", name));
+            }
+            validation.skipped_agents.push(SkippedAgent {
+                name,
+                reason: "not registered".to_string(),
+            });
+            continue;
+        };
+        let on_platform = executor_agent.is_some()
+            || request.platforms.is_empty()
+            || request
+                .platforms
+                .iter()
+                .any(|platform| agent.is_platform(platform));
+        let reason = if agent.deleted_at.is_some() {
+            Some("in the trash".to_string())
+        } else if !on_platform {
+            Some(match agent.platform() {
+                Some(platform) => format!("runs on {}", platform),
+                None => "platform unknown".to_string(),
+            })
+        } else if !kubernetes && !agent.matches_node_selector(&request.node_selector) {
+            let node_name = agent
+                .container
+                .as_ref()
+                .and_then(|container| container.node_name.as_deref());
+            Some(match node_name {
+                Some(node_name) => format!("node {} does not match the node selector", node_name),
+                None => "not on a Kubernetes node".to_string(),
+            })
+        } else if agent.draining || agent.status == AgentStatus::Draining {
+            Some("draining".to_string())
+        } else if matches!(agent.status, AgentStatus::Offline | AgentStatus::Unknown) {
+            Some("offline".to_string())
+        } else {
+            None
+        };
+        if reason.is_none()
+            && request.container.is_some()
+            && !agent.features.iter().any(|feature| feature == "containers")
+        {
+            validation
+                .warnings
+                .push(format!("Agent {} has no container runtime", name));
+        }
+        match reason {
+            Some(reason) => validation
+                .skipped_agents
+                .push(SkippedAgent { name, reason }),
+            None => validation.targeted_agents.push(name),
+        }
+    }
+    if validation.targeted_agents.is_empty() {
+        validation
+            .warnings
+            .push("No agent would run the job if it were due now".to_string());
+    }
+
+    validation.valid = validation.errors.is_empty();
+    Ok(Json(validation))
+}
+
+/// What a run started from the web UI or its API is triggered by: the API token the request was
+/// made with, or else the user.
+fn manual_trigger(actor: &Actor, caller: &TokenCaller) -> TriggeredBy {
+    match &caller.0 {
+        Some(token) => TriggeredBy::ApiToken(token.name.clone()),
+        None => TriggeredBy::User(actor.name().to_string()),
+    }
+}
+
+/// Runs a job now, on behalf of the API token or authenticated user making the request, or
+/// `anonymous` without either. Running and disabled jobs are left alone.
+#[post("/jobs/<name>/run")]
+pub async fn run_job(
+    state: &State<WebState>,
+    actor: Actor,
+    caller: TokenCaller,
+    access: Access,
+    name: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let triggered_by = manual_trigger(&actor, &caller);
+    let triggered_by_bson =
+        bson::to_bson(&triggered_by).map_err(|e| internal_error("Error serializing trigger", e))?;
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+
+    let next_run = chrono::Utc::now().timestamp();
+    let previous = job_collection
+        .find_one_and_update(
+            access.scope(doc! {
+                "name": name,
+                "status": { "$nin": [Status::Running, Status::Frozen, Status::Archived] },
+            }),
+            doc! { "$set": {
+                "status": Status::Pending,
+                "next_run": jobs::next_run_datetime(next_run),
+                "triggered_by": triggered_by_bson,
+            } },
+        )
+        .await
+        .map_err(|e| internal_error("Error updating job", e))?;
+    let Some(previous) = previous else {
+        let conflict = "is running, disabled or archived";
+        return Err(job_update_error(state, &access, name, conflict).await);
+    };
+
+    let triggered = JobV1 {
+        status: Status::Pending,
+        next_run,
+        triggered_by: Some(triggered_by),
+        ..previous.clone()
+    };
+    let entry = AuditEntryV1::new(actor.name(), AuditAction::Trigger, AuditResource::Job, name);
+    audit::record(state, entry.with_diff(Some(&previous), Some(&triggered))).await;
+
+    Ok("Success".to_string())
+}
+
+/// Asks central command to cancel a running job on every agent running it.
+#[post("/jobs/<name>/cancel")]
+pub async fn cancel_job(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+
+    let requested_at = bson::DateTime::now();
+    let previous = job_collection
+        .find_one_and_update(
+            access.scope(doc! { "name": name, "status": Status::Running }),
+            doc! {
+                "$set": { "cancel_requested_at": requested_at },
+                "$unset": { "cancel_sent_to": "" },
+            },
+        )
+        .await
+        .map_err(|e| internal_error("Error updating job", e))?;
+    let Some(previous) = previous else {
+        return Err(job_update_error(state, &access, name, "is not running").await);
+    };
+
+    let cancelled = JobV1 {
+        cancel_requested_at: Some(requested_at),
+        cancel_sent_to: vec![],
+        ..previous.clone()
     };
+    let entry = AuditEntryV1::new(actor.name(), AuditAction::Cancel, AuditResource::Job, name);
+    audit::record(state, entry.with_diff(Some(&previous), Some(&cancelled))).await;
 
-    let jobs_page: DataPage<JobV1> = DataPage::new(state, data_page_params).await;
+    Ok("Success".to_string())
+}
+
+/// Approves the due run of a job waiting for approval, so central command dispatches it. Only
+/// approvers (see `WEBUI_APPROVERS`) may.
+#[post("/jobs/<name>/approve?<comment>")]
+pub async fn approve_job_run(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+    comment: Option<String>,
+) -> Result<String, (rocket::http::Status, String)> {
+    access.check_approve()?;
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+
+    let approval = JobApproval {
+        approved_by: actor.name().to_string(),
+        approved_at: bson::DateTime::now(),
+        comment: comment.filter(|comment| !comment.trim().is_empty()),
+    };
+    let approval_bson =
+        bson::to_bson(&approval).map_err(|e| internal_error("Error serializing approval", e))?;
+    let previous = job_collection
+        .find_one_and_update(
+            access.scope(doc! { "name": name, "status": Status::PendingApproval }),
+            doc! { "$set": { "status": Status::Pending, "approval": approval_bson } },
+        )
+        .await
+        .map_err(|e| internal_error("Error updating job", e))?;
+    let Some(previous) = previous else {
+        return Err(job_update_error(state, &access, name, "is not waiting for approval").await);
+    };
+
+    let approved = JobV1 {
+        status: Status::Pending,
+        approval: Some(approval),
+        ..previous.clone()
+    };
+    let entry = AuditEntryV1::new(actor.name(), AuditAction::Approve, AuditResource::Job, name);
+    audit::record(state, entry.with_diff(Some(&previous), Some(&approved))).await;
+
+    Ok(format!("Approved the run of job {}", name))
+}
+
+/// Rejects the due run of a job waiting for approval: the run is skipped and the job waits for
+/// its next scheduled run, or is disabled when it has none. Only approvers (see
+/// `WEBUI_APPROVERS`) may.
+#[post("/jobs/<name>/reject?<comment>")]
+pub async fn reject_job_run(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+    comment: Option<String>,
+) -> Result<String, (rocket::http::Status, String)> {
+    access.check_approve()?;
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let filter = access.scope(doc! { "name": name, "status": Status::PendingApproval });
+    let Some(current) = job_collection
+        .find_one(filter.clone())
+        .await
+        .map_err(|e| internal_error("Error fetching job", e))?
+    else {
+        return Err(job_update_error(state, &access, name, "is not waiting for approval").await);
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let rejected = match current.next_run_after(now).filter(|_| !current.one_shot) {
+        Some(next_run) => JobV1 {
+            status: Status::Pending,
+            next_run,
+            triggered_by: None,
+            rerun: None,
+            hook_chain: vec![],
+            ..current.clone()
+        },
+        None => JobV1 {
+            status: Status::Frozen,
+            triggered_by: None,
+            rerun: None,
+            hook_chain: vec![],
+            ..current.clone()
+        },
+    };
+    let result = job_collection
+        .update_one(
+            doc! { "_id": current.id, "status": Status::PendingApproval },
+            doc! {
+                "$set": {
+                    "status": rejected.status,
+                    "next_run": jobs::next_run_datetime(rejected.next_run),
+                },
+                "$unset": { "triggered_by": "", "rerun": "", "hook_chain": "" },
+            },
+        )
+        .await
+        .map_err(|e| internal_error("Error updating job", e))?;
+    if result.modified_count == 0 {
+        return Err(job_update_error(state, &access, name, "is not waiting for approval").await);
+    }
+
+    let mut entry = AuditEntryV1::new(actor.name(), AuditAction::Reject, AuditResource::Job, name);
+    entry = entry.with_diff(Some(&current), Some(&rejected));
+    if let Some(comment) = comment.filter(|comment| !comment.trim().is_empty()) {
+        entry = entry.with_field("comment", comment);
+    }
+    audit::record(state, entry).await;
+
+    Ok(match rejected.status {
+        Status::Frozen => format!("Rejected the run of job {}; it is disabled", name),
+        _ => format!(
+            "Rejected the run of job {}; it waits for its next run",
+            name
+        ),
+    })
+}
+
+/// Asks central command to give the run of a job on one agent `seconds` more before it is killed.
+/// The extension is recorded on the run once it completes.
+#[post("/jobs/<name>/agents/<agent_name>/extend_timeout?<seconds>")]
+pub async fn extend_job_timeout(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+    agent_name: &str,
+    seconds: u32,
+) -> Result<String, (rocket::http::Status, String)> {
+    if seconds == 0 {
+        return Err((
+            rocket::http::Status::BadRequest,
+            "seconds must be greater than 0".to_string(),
+        ));
+    }
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+
+    let request = TimeoutExtensionRequest {
+        agent_name: agent_name.to_string(),
+        seconds,
+        requested_at: bson::DateTime::now(),
+    };
+    let request_bson =
+        bson::to_bson(&request).map_err(|e| internal_error("Error serializing request", e))?;
+    let previous = job_collection
+        .find_one_and_update(
+            access.scope(doc! {
+                "name": name,
+                "status": Status::Running,
+                "agents_running": agent_name,
+                "timeout": { "$gt": 0 },
+            }),
+            doc! { "$push": { "timeout_extension_requests": request_bson } },
+        )
+        .await
+        .map_err(|e| internal_error("Error updating job", e))?;
+    let Some(previous) = previous else {
+        let conflict = format!("is not running with a timeout on agent {}", agent_name);
+        return Err(job_update_error(state, &access, name, &conflict).await);
+    };
+
+    let mut extended = previous.clone();
+    extended.timeout_extension_requests.push(request);
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::ExtendTimeout,
+        AuditResource::Job,
+        name,
+    );
+    audit::record(state, entry.with_diff(Some(&previous), Some(&extended))).await;
+
+    Ok("Success".to_string())
+}
+
+/// The job's most recent dispatch cycles (default 20, at most 100), newest first, each with the
+/// result of every agent and the outcome its success rule decided.
+#[get("/jobs/<name>/executions?<limit>")]
+pub async fn job_executions(
+    state: &State<WebState>,
+    access: Access,
+    name: &str,
+    limit: Option<i64>,
+) -> Result<Json<Vec<JobExecutionV1>>, (rocket::http::Status, String)> {
+    access.check_job(&state.datastore, name).await?;
+    let collection = state
+        .datastore
+        .get_collection::<JobExecutionV1>("job_executions")
+        .await
+        .map_err(|e| internal_error("Error accessing job executions collection", e))?;
+    let executions = collection
+        .find(doc! { "job_name": name })
+        .sort(doc! { "started_at": -1 })
+        .limit(limit.unwrap_or(20).clamp(1, 100))
+        .await
+        .map_err(|e| internal_error("Error fetching job executions", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading job executions", e))?;
+    Ok(Json(executions))
+}
 
-    let DataPage {
-        items: jobs,
-        total_pages,
-        current_page: page,
-    } = jobs_page;
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BulkJobsRequest {
+    pub ids: Vec<String>,
+}
+
+/// The jobs selected by a bulk action, leaving out jobs in the trash and jobs the user may not
+/// see.
+async fn fetch_selected_jobs(
+    job_collection: &mongodb::Collection<JobV1>,
+    access: &Access,
+    request: &BulkJobsRequest,
+) -> Result<Vec<JobV1>, (rocket::http::Status, String)> {
+    let object_ids: Vec<ObjectId> = request
+        .ids
+        .iter()
+        .map(ObjectId::parse_str)
+        .collect::<Result<_, _>>()
+        .map_err(|_| {
+            (
+                rocket::http::Status::BadRequest,
+                "One or more invalid job ID formats".to_string(),
+            )
+        })?;
+    job_collection
+        .find(access.scope(doc! { "_id": { "$in": &object_ids }, "deleted_at": null }))
+        .sort(doc! { "name": 1 })
+        .await
+        .map_err(|e| internal_error("Error fetching jobs", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading jobs", e))
+}
+
+/// Reports a bulk action, e.g. `Disabled 3 jobs; kept backup is running`.
+fn bulk_message(summary: String, kept: &[String]) -> String {
+    let mut message = summary;
+    if !kept.is_empty() {
+        message.push_str(&format!("; kept {}", kept.join(", ")));
+    }
+    message
+}
+
+/// Moves the jobs to the trash, freezing them so they are not scheduled. Running jobs and jobs
+/// synced from a jobs file are left alone; they are named in the response.
+#[delete("/jobs", data = "<request>")]
+pub async fn delete_jobs_bulk(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    request: Json<BulkJobsRequest>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let jobs = fetch_selected_jobs(&job_collection, &access, &request).await?;
+
+    let mut trashed = 0;
+    let mut kept = vec![];
+    for job in jobs {
+        if job.status == Status::Running {
+            kept.push(format!("{} is running", job.name));
+            continue;
+        }
+        if let Some(source) = &job.managed_by {
+            kept.push(format!("{} is synced from {}", job.name, source));
+            continue;
+        }
+        let deleted_at = bson::DateTime::now();
+        let result = job_collection
+            .update_one(
+                doc! {
+                    "_id": job.id,
+                    "deleted_at": null,
+                    "status": job.status,
+                    "managed_by": null,
+                },
+                doc! { "$set": {
+                    "deleted_at": deleted_at,
+                    "status_before_delete": job.status,
+                    "status": Status::Frozen,
+                } },
+            )
+            .await
+            .map_err(|e| internal_error("Error updating job", e))?;
+        if result.modified_count == 0 {
+            kept.push(format!("{} changed meanwhile", job.name));
+            continue;
+        }
+        trashed += 1;
+        let deleted = JobV1 {
+            deleted_at: Some(deleted_at),
+            status_before_delete: Some(job.status),
+            status: Status::Frozen,
+            ..job.clone()
+        };
+        let entry = AuditEntryV1::new(
+            actor.name(),
+            AuditAction::Delete,
+            AuditResource::Job,
+            &job.name,
+        );
+        audit::record(state, entry.with_diff(Some(&job), Some(&deleted))).await;
+    }
 
-    Json(json!({
-        "items": jobs,
-        "total_pages": total_pages,
-        "current_page": page,
-    }))
+    Ok(bulk_message(
+        format!("Moved {} jobs to the trash", trashed),
+        &kept,
+    ))
+}
+
+/// Disables the jobs (`Status::Frozen`) when `enabled` is false, so they are not scheduled, or
+/// enables disabled jobs again, making them pending. Running and archived jobs, and jobs synced
+/// from a jobs file, whose file decides whether they are enabled, are left alone; they are named
+/// in the response.
+#[post("/jobs/enabled?<enabled>", data = "<request>")]
+pub async fn set_jobs_enabled(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    enabled: bool,
+    request: Json<BulkJobsRequest>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let jobs = fetch_selected_jobs(&job_collection, &access, &request).await?;
+
+    let status = match enabled {
+        true => Status::Pending,
+        false => Status::Frozen,
+    };
+    let mut changed = 0;
+    let mut kept = vec![];
+    for job in jobs {
+        if let Some(source) = &job.managed_by {
+            kept.push(format!("{} is synced from {}", job.name, source));
+            continue;
+        }
+        match (enabled, job.status) {
+            (_, Status::Running) => kept.push(format!("{} is running", job.name)),
+            (_, Status::Archived) => kept.push(format!("{} is archived", job.name)),
+            (true, Status::Frozen)
+            | (
+                false,
+                Status::Pending | Status::Completed | Status::Error | Status::PendingApproval,
+            ) => {
+                let result = job_collection
+                    .update_one(
+                        doc! {
+                            "_id": job.id,
+                            "deleted_at": null,
+                            "status": job.status,
+                            "managed_by": null,
+                        },
+                        doc! { "$set": { "status": status } },
+                    )
+                    .await
+                    .map_err(|e| internal_error("Error updating job", e))?;
+                if result.modified_count == 0 {
+                    kept.push(format!("{} changed meanwhile", job.name));
+                    continue;
+                }
+                changed += 1;
+
+                let change = match enabled {
+                    true => JobChangeV1 {
+                        changes: vec![FieldChange {
+                            field: "status".to_string(),
+                            old: "disabled".to_string(),
+                            new: "enabled".to_string(),
+                        }],
+                        ..JobChangeV1::new(&job.name, JobChangeKind::Updated, "web UI")
+                    },
+                    false => JobChangeV1::new(&job.name, JobChangeKind::Disabled, "web UI"),
+                };
+                if let Err(e) = change.insert_entry(&state.datastore).await {
+                    eprintln!("Error recording job change: {}", e);
+                }
+                let updated = JobV1 {
+                    status,
+                    ..job.clone()
+                };
+                let entry = AuditEntryV1::new(
+                    actor.name(),
+                    AuditAction::Update,
+                    AuditResource::Job,
+                    &job.name,
+                );
+                audit::record(state, entry.with_diff(Some(&job), Some(&updated))).await;
+            }
+            (true, _) => kept.push(format!("{} is already enabled", job.name)),
+            (false, _) => kept.push(format!("{} is already disabled", job.name)),
+        }
+    }
+
+    let done = match enabled {
+        true => "Enabled",
+        false => "Disabled",
+    };
+    Ok(bulk_message(format!("{} {} jobs", done, changed), &kept))
+}
+
+/// Runs the jobs now, as `run_job` does for one job, recording the change of `next_run` in each
+/// job's history. Running, disabled and archived jobs are left alone; they are named in the
+/// response.
+#[post("/jobs/run", data = "<request>")]
+pub async fn run_jobs_bulk(
+    state: &State<WebState>,
+    actor: Actor,
+    caller: TokenCaller,
+    access: Access,
+    request: Json<BulkJobsRequest>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let triggered_by = manual_trigger(&actor, &caller);
+    let triggered_by_bson =
+        bson::to_bson(&triggered_by).map_err(|e| internal_error("Error serializing trigger", e))?;
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let jobs = fetch_selected_jobs(&job_collection, &access, &request).await?;
+
+    let next_run = chrono::Utc::now().timestamp();
+    let mut triggered = 0;
+    let mut kept = vec![];
+    for job in jobs {
+        let result = job_collection
+            .update_one(
+                doc! {
+                    "_id": job.id,
+                    "deleted_at": null,
+                    "status": { "$nin": [Status::Running, Status::Frozen, Status::Archived] },
+                },
+                doc! { "$set": {
+                    "status": Status::Pending,
+                    "next_run": jobs::next_run_datetime(next_run),
+                    "triggered_by": &triggered_by_bson,
+                } },
+            )
+            .await
+            .map_err(|e| internal_error("Error updating job", e))?;
+        if result.modified_count == 0 {
+            kept.push(format!("{} is running, disabled or archived", job.name));
+            continue;
+        }
+        triggered += 1;
+
+        let run_at = |seconds: i64| {
+            jobs::next_run_datetime(seconds)
+                .try_to_rfc3339_string()
+                .unwrap_or_else(|_| seconds.to_string())
+        };
+        let change = JobChangeV1 {
+            changes: vec![FieldChange {
+                field: "next_run".to_string(),
+                old: run_at(job.next_run),
+                new: run_at(next_run),
+            }],
+            ..JobChangeV1::new(&job.name, JobChangeKind::Updated, "web UI")
+        };
+        if let Err(e) = change.insert_entry(&state.datastore).await {
+            eprintln!("Error recording job change: {}", e);
+        }
+        let updated = JobV1 {
+            status: Status::Pending,
+            next_run,
+            triggered_by: Some(triggered_by.clone()),
+            ..job.clone()
+        };
+        let entry = AuditEntryV1::new(
+            actor.name(),
+            AuditAction::Trigger,
+            AuditResource::Job,
+            &job.name,
+        );
+        audit::record(state, entry.with_diff(Some(&job), Some(&updated))).await;
+    }
+
+    Ok(bulk_message(format!("Triggered {} jobs", triggered), &kept))
+}
+
+/// Gives the jobs to `team`, or to no team when it is empty. Users who are not admins may only
+/// give jobs to their own teams. Jobs synced from a jobs file, whose file decides their team, are
+/// left alone; they are named in the response.
+#[post("/jobs/team?<team>", data = "<request>")]
+pub async fn set_jobs_team(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    team: Option<String>,
+    request: Json<BulkJobsRequest>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let team = team
+        .as_deref()
+        .map(str::trim)
+        .filter(|team| !team.is_empty())
+        .map(str::to_string);
+    access.check_assign(team.as_deref())?;
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let jobs = fetch_selected_jobs(&job_collection, &access, &request).await?;
+
+    let mut changed = 0;
+    let mut kept = vec![];
+    for job in jobs {
+        if let Some(source) = &job.managed_by {
+            kept.push(format!("{} is synced from {}", job.name, source));
+            continue;
+        }
+        if job.team == team {
+            continue;
+        }
+        let result = job_collection
+            .update_one(
+                doc! { "_id": job.id, "managed_by": null },
+                doc! { "$set": { "team": &team } },
+            )
+            .await
+            .map_err(|e| internal_error("Error updating job", e))?;
+        if result.modified_count == 0 {
+            kept.push(format!("{} changed meanwhile", job.name));
+            continue;
+        }
+        changed += 1;
+
+        let change = JobChangeV1 {
+            changes: vec![FieldChange {
+                field: "team".to_string(),
+                old: job.team.clone().unwrap_or_default(),
+                new: team.clone().unwrap_or_default(),
+            }],
+            ..JobChangeV1::new(&job.name, JobChangeKind::Updated, "web UI")
+        };
+        if let Err(e) = change.insert_entry(&state.datastore).await {
+            eprintln!("Error recording job change: {}", e);
+        }
+        let updated = JobV1 {
+            team: team.clone(),
+            ..job.clone()
+        };
+        let entry = AuditEntryV1::new(
+            actor.name(),
+            AuditAction::Update,
+            AuditResource::Job,
+            &job.name,
+        );
+        audit::record(state, entry.with_diff(Some(&job), Some(&updated))).await;
+    }
+
+    let summary = match &team {
+        Some(team) => format!("Gave {} jobs to team {}", changed, team),
+        None => format!("Unassigned {} jobs from their team", changed),
+    };
+    Ok(bulk_message(summary, &kept))
 }
@@ -0,0 +1,260 @@
+mod access;
+mod agent_detail;
+mod agent_groups;
+mod agents;
+mod alert_list;
+mod alerts;
+mod api_tokens;
+mod audit;
+mod blackout_windows;
+mod checks;
+mod connections;
+mod data_page;
+mod grafana;
+mod health;
+mod job_files;
+mod job_promotions;
+mod job_revisions;
+mod job_templates;
+mod jobs;
+mod namespaces;
+mod runs;
+mod saved_filters;
+mod schedule;
+mod shell;
+mod trash;
+
+use rocket::fs::NamedFile;
+use rocket::fs::{FileServer, relative};
+use rocket::get;
+use rocket::http::Status;
+use rocket::response::{Responder, status::Custom};
+use rocket::routes;
+use rocket::{Build, Catcher, Request, Rocket, catcher};
+use rocket_dyn_templates::{Template, context, minijinja::Environment};
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use agent_detail::{agent_activity, agent_page};
+use agent_groups::{
+    add_agent_group_member, agent_groups_data, agent_groups_page, delete_agent_group,
+    post_agent_group, remove_agent_group_member,
+};
+use agents::{
+    add_agent, agent_events, agents_data, agents_page, delete_agent, delete_agents_bulk,
+    drain_agent, edit_agent, ping_agent, post_agent_update, post_agents,
+};
+use alert_list::{acknowledge_alert, alerts_data, alerts_page, resolve_alert};
+use alerts::{alert_rules_file, metrics, overdue_jobs_data, post_job_sla};
+use api_tokens::{
+    ApiTokenAuth, api_token_rejected, api_tokens_data, post_api_token, revoke_api_token,
+    settings_page,
+};
+use audit::{audit_data, audit_page};
+use blackout_windows::{
+    blackout_windows_data, blackout_windows_page, delete_blackout_window, post_blackout_window,
+};
+use checks::check_states_data;
+use connections::{connections_data, connections_page};
+use core_logic::datastore::Datastore;
+use grafana::{
+    list_metrics, metrics_annotations, metrics_datasource, query_metrics, search_metrics,
+};
+use health::{healthz, readyz};
+use job_files::upload_job_file;
+use job_promotions::{
+    approve_job_promotion, export_job, job_promotions_data, post_job_promotion,
+    reject_job_promotion,
+};
+use job_revisions::{job_revision_history, rollback_job};
+use job_templates::{
+    delete_job_template, instantiate_job_template, job_templates_data, post_job_template,
+};
+use jobs::{
+    approve_job_run, cancel_job, create_job, delete_jobs_bulk, extend_job_timeout, job_executions,
+    jobs_data, jobs_page, reject_job_run, run_job, run_jobs_bulk, set_jobs_enabled, set_jobs_team,
+    validate_job,
+};
+use namespaces::namespaces_data;
+use runs::{
+    rerun_run, run_receipt, runs_cycles_data, runs_data, runs_output, runs_page, runs_stats,
+    verify_run_outputs,
+};
+use saved_filters::{
+    delete_saved_filter, open_saved_filter, post_saved_filter, saved_filters_data,
+};
+use schedule::{schedule_page, schedule_preview};
+use shell::{agent_shell, agent_terminal};
+use trash::{purge_agent, purge_job, restore_agent, restore_job, trash_data, trash_page};
+
+pub struct WebState {
+    datastore: Datastore,
+}
+
+/// The `500 Internal Server Error` answered when the datastore fails, with what was being done.
+fn internal_error(context: &str, e: impl std::fmt::Display) -> (Status, String) {
+    (Status::InternalServerError, format!("{}: {}", context, e))
+}
+
+#[get("/")]
+pub fn index() -> Template {
+    Template::render(
+        "index",
+        context! {
+            title: "Dashboard",
+        },
+    )
+}
+
+#[rocket::get("/static/<path..>")]
+pub async fn static_files(path: PathBuf) -> Option<NamedFile> {
+    let path = Path::new(relative!("static")).join(path);
+    NamedFile::open(path).await.ok()
+}
+
+fn not_found_handler<'r>(_: Status, req: &'r Request) -> catcher::BoxFuture<'r> {
+    let responder = Custom(Status::NotFound, format!("Couldn't find: {}", req.uri()));
+    Box::pin(async move { responder.respond_to(req) })
+}
+
+pub fn customize(_env: &mut Environment) {}
+
+/// Where the page templates are, for running the web UI from another directory than its own
+/// (see `all-in-one`); `rocket.toml` sets `template_dir` relative to the web UI's directory.
+pub const TEMPLATE_DIR: &str = relative!("templates");
+
+/// The web UI, ready to launch.
+pub async fn rocket() -> Rocket<Build> {
+    let not_found_catcher = Catcher::new(404, not_found_handler);
+
+    let web_state = WebState {
+        datastore: Datastore::try_new()
+            .await
+            .expect("Failed to initialize datastore"),
+    };
+    // Read port from environment variable or default to 8000
+    let port: u16 = env::var("WEBUI_PORT")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(8000);
+
+    let figment = rocket::Config::figment().merge(("port", port));
+
+    rocket::build()
+        .configure(rocket::Config::from(figment))
+        .manage(web_state)
+        .attach(ApiTokenAuth)
+        .mount(
+            "/",
+            routes![
+                index,
+                healthz,
+                readyz,
+                runs_page,
+                runs_output,
+                run_receipt,
+                rerun_run,
+                verify_run_outputs,
+                agents_page,
+                edit_agent,
+                agent_page,
+                agent_activity,
+                runs_data,
+                runs_cycles_data,
+                runs_stats,
+                saved_filters_data,
+                post_saved_filter,
+                open_saved_filter,
+                delete_saved_filter,
+                schedule_page,
+                schedule_preview,
+                agents_data,
+                post_agents,
+                post_agent_update,
+                ping_agent,
+                drain_agent,
+                agent_events,
+                add_agent,
+                delete_agent,
+                delete_agents_bulk,
+                restore_agent,
+                purge_agent,
+                agent_groups_page,
+                agent_groups_data,
+                post_agent_group,
+                add_agent_group_member,
+                remove_agent_group_member,
+                delete_agent_group,
+                blackout_windows_page,
+                blackout_windows_data,
+                post_blackout_window,
+                delete_blackout_window,
+                jobs_data,
+                jobs_page,
+                create_job,
+                validate_job,
+                delete_jobs_bulk,
+                set_jobs_enabled,
+                set_jobs_team,
+                run_jobs_bulk,
+                restore_job,
+                purge_job,
+                run_job,
+                cancel_job,
+                approve_job_run,
+                reject_job_run,
+                extend_job_timeout,
+                job_executions,
+                job_revision_history,
+                rollback_job,
+                export_job,
+                post_job_promotion,
+                job_promotions_data,
+                approve_job_promotion,
+                reject_job_promotion,
+                post_job_sla,
+                alert_rules_file,
+                metrics,
+                metrics_datasource,
+                search_metrics,
+                list_metrics,
+                query_metrics,
+                metrics_annotations,
+                overdue_jobs_data,
+                check_states_data,
+                alerts_page,
+                alerts_data,
+                acknowledge_alert,
+                resolve_alert,
+                job_templates_data,
+                post_job_template,
+                delete_job_template,
+                instantiate_job_template,
+                upload_job_file,
+                connections_page,
+                connections_data,
+                audit_page,
+                audit_data,
+                trash_page,
+                trash_data,
+                settings_page,
+                api_tokens_data,
+                post_api_token,
+                revoke_api_token,
+                namespaces_data,
+                api_token_rejected,
+                agent_terminal,
+                agent_shell,
+            ],
+        )
+        .mount("/", rocket::routes![static_files])
+        .mount(
+            "/",
+            FileServer::new(relative!("static"), rocket::fs::Options::default()),
+        )
+        .register("/", vec![not_found_catcher])
+        .attach(Template::custom(|engines| {
+            customize(&mut engines.minijinja);
+        }))
+}
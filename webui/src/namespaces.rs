@@ -0,0 +1,123 @@
+use mongodb::bson::{Bson, Document, doc};
+use rocket::State;
+use rocket::get;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use serde_json::json;
+
+use std::collections::BTreeSet;
+
+use crate::WebState;
+use crate::internal_error;
+use core_logic::datastore::agents::AgentV1;
+use core_logic::datastore::jobs::JobV1;
+
+/// Cookie the namespace switcher stores the selected namespace in.
+const NAMESPACE_COOKIE: &str = "namespace";
+/// How the default namespace, of agents and jobs without one, is selected and listed.
+const DEFAULT_NAMESPACE: &str = "default";
+
+/// The namespace selected with the namespace switcher, which the jobs, agents and runs pages are
+/// narrowed to, and which jobs created from the web UI join.
+pub enum Namespace {
+    /// Every namespace, when none is selected.
+    All,
+    /// The default namespace.
+    Default,
+    Named(String),
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Namespace {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let namespace = match request.cookies().get(NAMESPACE_COOKIE).map(|c| c.value()) {
+            None | Some("") => Namespace::All,
+            Some(DEFAULT_NAMESPACE) => Namespace::Default,
+            Some(name) => Namespace::Named(name.to_string()),
+        };
+        Outcome::Success(namespace)
+    }
+}
+
+/// The namespace named `name` by users, `None` for the default namespace.
+pub fn parse_namespace(name: &str) -> Option<String> {
+    match name.trim() {
+        "" | DEFAULT_NAMESPACE => None,
+        name => Some(name.to_string()),
+    }
+}
+
+/// The name users know the namespace `namespace` by.
+pub fn namespace_name(namespace: Option<&str>) -> &str {
+    namespace.unwrap_or(DEFAULT_NAMESPACE)
+}
+
+impl Namespace {
+    /// `filter` narrowed to the jobs, agents or runs of the namespace.
+    pub fn scope(&self, mut filter: Document) -> Document {
+        match self {
+            Namespace::All => {}
+            Namespace::Default => {
+                filter.insert("namespace", Bson::Null);
+            }
+            Namespace::Named(name) => {
+                filter.insert("namespace", name);
+            }
+        }
+        filter
+    }
+
+    /// The namespace new jobs join.
+    pub fn for_new(&self) -> Option<String> {
+        match self {
+            Namespace::Named(name) => Some(name.clone()),
+            Namespace::All | Namespace::Default => None,
+        }
+    }
+}
+
+/// The namespaces of agents and jobs, sorted with the default namespace first, and the one
+/// selected, for the namespace switcher.
+#[get("/namespaces")]
+pub async fn namespaces_data(
+    state: &State<WebState>,
+    namespace: Namespace,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let mut names = BTreeSet::new();
+    let agents = state
+        .datastore
+        .get_collection::<AgentV1>("agents")
+        .await
+        .map_err(|e| internal_error("Error accessing agents collection", e))?;
+    let jobs = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    for distinct in [
+        agents.distinct("namespace", doc! {}).await,
+        jobs.distinct("namespace", doc! {}).await,
+    ] {
+        let distinct = distinct.map_err(|e| internal_error("Error fetching namespaces", e))?;
+        names.extend(
+            distinct
+                .iter()
+                .filter_map(Bson::as_str)
+                .filter(|name| *name != DEFAULT_NAMESPACE)
+                .map(str::to_string),
+        );
+    }
+    let mut namespaces = vec![DEFAULT_NAMESPACE.to_string()];
+    namespaces.extend(names);
+    let selected = match namespace {
+        Namespace::All => None,
+        Namespace::Default => Some(DEFAULT_NAMESPACE.to_string()),
+        Namespace::Named(name) => Some(name),
+    };
+    Ok(Json(json!({
+        "namespaces": namespaces,
+        "selected": selected,
+    })))
+}
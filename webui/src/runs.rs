@@ -1,18 +1,46 @@
-use core_logic::datastore::runs::RunsV1;
+use bson::DateTime;
+use core_logic::datastore::{
+    agents::AgentV1,
+    audit_log::{AuditAction, AuditEntryV1, AuditResource},
+    job_changes::JobChangeV1,
+    jobs::{self, JobRerun, JobV1, Status as JobStatus},
+    run_stats::{self, RunStats, StatsInterval},
+    runs::{Outcome, RunJobSnapshot, RunsV1, TriggeredBy},
+};
+use futures::StreamExt;
+use mongodb::bson::doc;
 use rocket::State;
-use rocket::get;
 use rocket::serde::json::Json;
+use rocket::{get, post};
 use rocket_dyn_templates::{Template, context};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::WebState;
-use crate::data_page::{DataPage, DataPageParams};
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::data_page::{DataPage, DataPageParams, iso_dates, with_iso_dates};
+use crate::internal_error;
+use crate::namespaces::Namespace;
+
+/// Fields the runs page can be sorted and range filtered by.
+const RUN_SORT_FIELDS: &[&str] = &[
+    "job_name",
+    "agent_name",
+    "command",
+    "return_code",
+    "outcome",
+    "triggered_by.kind",
+    "started_at",
+    "completed_at",
+];
+const RUN_RANGE_FIELDS: &[&str] = &["started_at", "completed_at"];
 
 #[allow(clippy::too_many_arguments)]
 #[get(
-    "/runs?<page>&<range_select>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<outcome_filter>&<sort>&<order>"
+    "/runs?<page>&<range_select>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<output_search>&<outcome_filter>&<triggered_by_filter>&<cycle_filter>&<tag_filter>&<group_by_cycle>&<show_changes>&<sort>&<order>"
 )]
 pub async fn runs_page(
     range_start: Option<u64>,
@@ -22,9 +50,15 @@ pub async fn runs_page(
     relative_select_value: Option<u8>,
     relative_select_unit: Option<String>,
     filter: Option<String>,
+    output_search: Option<String>,
     sort: Option<String>,
     order: Option<String>,
     outcome_filter: Option<String>,
+    triggered_by_filter: Option<String>,
+    cycle_filter: Option<String>,
+    tag_filter: Option<String>,
+    group_by_cycle: Option<bool>,
+    show_changes: Option<bool>,
     page: Option<u32>,
 ) -> Template {
     Template::render(
@@ -35,11 +69,18 @@ pub async fn runs_page(
             range_start: range_start.unwrap_or_default(),
             range_end: range_end.unwrap_or_default(),
             range_select: range_select.unwrap_or_default(),
-            range_fields: vec!["started_at".to_string(), "completed_at".to_string()], // Assuming these are the fields for range filtering
+            range_fields: RUN_RANGE_FIELDS,
             filter: filter.unwrap_or_default(),
+            output_search: output_search.unwrap_or_default(),
             order: order.unwrap_or_default(),
             outcome_filter: outcome_filter.unwrap_or_default(),
+            triggered_by_filter: triggered_by_filter.unwrap_or_default(),
+            cycle_filter: cycle_filter.unwrap_or_default(),
+            tag_filter: tag_filter.unwrap_or_default(),
+            group_by_cycle: group_by_cycle.unwrap_or_default(),
+            show_changes: show_changes.unwrap_or_default(),
             page_name: "Runs",
+            saved_filters_page: "runs",
             relative_select: relative_select.unwrap_or_default(),
             relative_select_value: relative_select_value.unwrap_or(30),
             relative_select_unit: relative_select_unit.unwrap_or_default(),
@@ -48,7 +89,7 @@ pub async fn runs_page(
 }
 
 #[get("/runs_output?<id>")]
-pub async fn runs_output(state: &State<WebState>, id: Option<String>) -> String {
+pub async fn runs_output(state: &State<WebState>, access: Access, id: Option<String>) -> String {
     let collection = match state.datastore.get_collection::<RunsV1>("runs").await {
         Ok(coll) => coll,
         Err(_) => {
@@ -62,24 +103,308 @@ pub async fn runs_output(state: &State<WebState>, id: Option<String>) -> String
             return "Invalid ObjectId format".to_string();
         }
     };
-    let run_entry = match collection
-        .find_one(mongodb::bson::doc! { "_id": object_id })
+    let filter = match access
+        .scope_by_job(&state.datastore, "job_name", doc! { "_id": object_id })
         .await
     {
+        Ok(filter) => filter,
+        Err((_, error)) => return error,
+    };
+    let mut run_entry = match collection.find_one(filter).await {
         Ok(Some(entry)) => entry,
         _ => {
             return "Run entry not found".to_string();
         }
     };
-    run_entry.output
+    if let Err(e) = run_entry.load_output(&state.datastore).await {
+        return format!("Error reading run output: {}", e);
+    }
+    match (run_entry.truncated, run_entry.output_artifact) {
+        (true, Some(artifact)) => format!(
+            "{}\n\n[Output truncated; full output saved on {} at {}]",
+            run_entry.output, run_entry.agent_name, artifact
+        ),
+        (true, None) => format!("{}\n\n[Output truncated]", run_entry.output),
+        _ => run_entry.output,
+    }
+}
+
+/// Re-checks a stored run against the receipt its agent signed, so results altered after they
+/// were stored can be detected.
+#[get("/runs/<id>/receipt")]
+pub async fn run_receipt(
+    state: &State<WebState>,
+    access: Access,
+    id: &str,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let object_id = mongodb::bson::oid::ObjectId::parse_str(id).map_err(|e| {
+        (
+            rocket::http::Status::BadRequest,
+            format!("Invalid run id {}: {}", id, e),
+        )
+    })?;
+    let collection = state
+        .datastore
+        .get_collection::<RunsV1>("runs")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing runs collection: {}", e),
+            )
+        })?;
+    let filter = access
+        .scope_by_job(&state.datastore, "job_name", doc! { "_id": object_id })
+        .await?;
+    let mut run = collection
+        .find_one(filter)
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error fetching run: {}", e),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Run {} not found", id),
+            )
+        })?;
+    run.load_output(&state.datastore).await.map_err(|e| {
+        (
+            rocket::http::Status::InternalServerError,
+            format!("Error reading run output: {}", e),
+        )
+    })?;
+
+    Ok(Json(json!({
+        "signed": run.receipt.is_some(),
+        "verified_on_receipt": run.receipt.as_ref().is_some_and(|receipt| receipt.verified),
+        "verified": run.verify_receipt(),
+        "public_key": run.receipt.as_ref().and_then(|receipt| receipt.public_key.clone()),
+    })))
+}
+
+/// Runs a stored run again on the agent that ran it, with the command, arguments and environment
+/// recorded in its job snapshot, as the next cycle of its job. Secrets redacted from the recorded
+/// environment are taken from the job's current environment; runs whose command line had secrets
+/// redacted, or whose job no longer sets a redacted variable, are refused. The new run is triggered as a
+/// retry of this one, which links the two. Running, disabled, archived and trashed jobs are left
+/// alone.
+#[post("/runs/<id>/rerun")]
+pub async fn rerun_run(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    id: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let object_id = mongodb::bson::oid::ObjectId::parse_str(id).map_err(|e| {
+        (
+            rocket::http::Status::BadRequest,
+            format!("Invalid run id {}: {}", id, e),
+        )
+    })?;
+    let run_collection = state
+        .datastore
+        .get_collection::<RunsV1>("runs")
+        .await
+        .map_err(|e| internal_error("Error accessing runs collection", e))?;
+    let filter = access
+        .scope_by_job(&state.datastore, "job_name", doc! { "_id": object_id })
+        .await?;
+    let run = run_collection
+        .find_one(filter)
+        .await
+        .map_err(|e| internal_error("Error fetching run", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Run {} not found", id),
+            )
+        })?;
+
+    // Runs stored before job snapshots were recorded only have the command they ran.
+    let snapshot = run.job.clone().unwrap_or_else(|| RunJobSnapshot {
+        command: run.command.clone(),
+        ..Default::default()
+    });
+    if snapshot.is_command_line_redacted() {
+        return Err((
+            rocket::http::Status::Conflict,
+            format!(
+                "Secrets were redacted from the command line of run {}; run job {} instead",
+                id, run.job_name
+            ),
+        ));
+    }
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let current_env = job_collection
+        .find_one(access.scope(doc! { "name": &run.job_name }))
+        .await
+        .map_err(|e| internal_error("Error fetching job", e))?
+        .map(|job| job.env)
+        .unwrap_or_default();
+    let env = snapshot.env_with_secrets(&current_env).map_err(|name| {
+        (
+            rocket::http::Status::Conflict,
+            format!(
+                "Secret {} was redacted from run {} and job {} no longer sets it; run the job instead",
+                name, id, run.job_name
+            ),
+        )
+    })?;
+    let rerun = JobRerun {
+        run_id: id.to_string(),
+        agent_name: run.agent_name.clone(),
+        command: snapshot.command,
+        args: snapshot.args,
+        env,
+    };
+    let triggered_by = TriggeredBy::Retry(id.to_string());
+    let next_run = chrono::Utc::now().timestamp();
+    let update = doc! { "$set": {
+        "status": JobStatus::Pending,
+        "next_run": jobs::next_run_datetime(next_run),
+        "triggered_by": bson::to_bson(&triggered_by)
+            .map_err(|e| internal_error("Error serializing trigger", e))?,
+        "rerun": bson::to_bson(&rerun)
+            .map_err(|e| internal_error("Error serializing re-run", e))?,
+    } };
+    let previous = job_collection
+        .find_one_and_update(
+            access.scope(doc! {
+                "name": &run.job_name,
+                "status": { "$nin": [JobStatus::Running, JobStatus::Frozen, JobStatus::Archived] },
+                "deleted_at": null,
+            }),
+            update,
+        )
+        .await
+        .map_err(|e| internal_error("Error updating job", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::Conflict,
+                format!(
+                    "Job {} is missing, running, disabled, archived or in the trash",
+                    run.job_name
+                ),
+            )
+        })?;
+
+    // The audit log gets the environment as recorded, without the secrets filled in.
+    let rerunning = JobV1 {
+        status: JobStatus::Pending,
+        next_run,
+        triggered_by: Some(triggered_by),
+        rerun: Some(JobRerun {
+            env: snapshot.env,
+            ..rerun
+        }),
+        ..previous.clone()
+    };
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Trigger,
+        AuditResource::Job,
+        &run.job_name,
+    );
+    audit::record(state, entry.with_diff(Some(&previous), Some(&rerunning))).await;
+
+    Ok(format!(
+        "Re-running job {} on agent {}",
+        run.job_name, run.agent_name
+    ))
+}
+
+/// Re-hashes stored run outputs and reports those that no longer match the checksum recorded
+/// when they were stored. `job_name` and `since` (unix seconds, on `started_at`) narrow the runs
+/// checked.
+#[get("/runs/verify_outputs?<job_name>&<since>")]
+pub async fn verify_run_outputs(
+    state: &State<WebState>,
+    access: Access,
+    job_name: Option<String>,
+    since: Option<i64>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let collection = state
+        .datastore
+        .get_collection::<RunsV1>("runs")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing runs collection: {}", e),
+            )
+        })?;
+
+    let mut filter = doc! {};
+    if let Some(job_name) = job_name {
+        filter.insert("job_name", job_name);
+    }
+    if let Some(since) = since {
+        filter.insert(
+            "started_at",
+            doc! { "$gte": DateTime::from_millis(since.saturating_mul(1000)) },
+        );
+    }
+    let filter = access
+        .scope_by_job(&state.datastore, "job_name", filter)
+        .await?;
+    let mut cursor = collection.find(filter).await.map_err(|e| {
+        (
+            rocket::http::Status::InternalServerError,
+            format!("Error fetching runs: {}", e),
+        )
+    })?;
+
+    let (mut checked, mut missing_checksum) = (0u64, 0u64);
+    let mut corrupted = vec![];
+    while let Some(run) = cursor.next().await {
+        let mut run = run.map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading runs: {}", e),
+            )
+        })?;
+        run.load_output(&state.datastore).await.map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading run output: {}", e),
+            )
+        })?;
+        checked += 1;
+        match run.verify_output() {
+            Some(true) => (),
+            Some(false) => corrupted.push(json!({
+                "id": run.id.map(|id| id.to_hex()),
+                "job_name": run.job_name,
+                "agent_name": run.agent_name,
+                "started_at": run.started_at.timestamp_millis(),
+            })),
+            None => missing_checksum += 1,
+        }
+    }
+
+    Ok(Json(json!({
+        "checked": checked,
+        "missing_checksum": missing_checksum,
+        "corrupted": corrupted,
+    })))
 }
 
 #[allow(clippy::too_many_arguments)]
 #[get(
-    "/runs_data?<page>&<range_select>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<sort>&<outcome_filter>&<order>"
+    "/runs_data?<page>&<range_select>&<relative_select>&<relative_select_value>&<relative_select_unit>&<range_start>&<range_end>&<filter>&<output_search>&<sort>&<outcome_filter>&<triggered_by_filter>&<cycle_filter>&<tag_filter>&<show_changes>&<order>&<page_size>&<after>"
 )]
 pub async fn runs_data(
     state: &State<WebState>,
+    access: Access,
+    namespace: Namespace,
     page: Option<u32>,
     range_select: Option<String>,
     range_end: Option<u64>,
@@ -88,52 +413,317 @@ pub async fn runs_data(
     relative_select_value: Option<u8>,
     relative_select_unit: Option<String>,
     filter: Option<String>,
+    output_search: Option<String>,
     sort: Option<String>,
     order: Option<String>,
     outcome_filter: Option<String>,
-) -> Json<serde_json::Value> {
+    triggered_by_filter: Option<String>,
+    cycle_filter: Option<String>,
+    tag_filter: Option<String>,
+    show_changes: Option<bool>,
+    page_size: Option<u32>,
+    after: Option<String>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
     let range_select = range_select
         .clone()
         .unwrap_or_else(|| "started_at".to_string());
+    // Words of the output, or "quoted phrases", matched through the runs' text index.
+    let mut base_filter = output_search
+        .filter(|search| !search.trim().is_empty())
+        .map(|search| doc! { "$text": { "$search": search } })
+        .unwrap_or_default();
+    // Runs with every tag of the comma separated `tag_filter`, as their job had when they ran.
+    if let Some(tags) = tag_filter.as_deref().and_then(jobs::tags_filter) {
+        base_filter.extend(tags);
+    }
+    let base_filter = access
+        .scope_by_job(&state.datastore, "job_name", namespace.scope(base_filter))
+        .await?;
     let data_page_params = DataPageParams {
         collection: "runs".to_string(),
         range_start,
         range_end,
         range_field: Some(range_select),
+        range_fields: RUN_RANGE_FIELDS,
         search_fields: vec![
             "job_name".to_string(),
             "agent_name".to_string(),
             "return_code".to_string(),
             "command".to_string(),
             "output".to_string(),
+            "triggered_by.source".to_string(),
+            "run_id".to_string(),
         ],
         page,
         filter: filter.clone(),
-        additional_filters: if outcome_filter.is_some() {
+        additional_filters: {
             let mut filters = HashMap::new();
-            filters.insert("outcome".to_string(), outcome_filter.unwrap());
-            Some(filters)
-        } else {
-            None
+            if let Some(outcome_filter) = outcome_filter.filter(|filter| !filter.is_empty()) {
+                // Accept an outcome name such as `timed_out` as well as its stored number.
+                let outcome_filter = match Outcome::from_name(&outcome_filter) {
+                    Some(outcome) => i32::from(outcome).to_string(),
+                    None => outcome_filter,
+                };
+                filters.insert("outcome".to_string(), outcome_filter);
+            }
+            if let Some(triggered_by_filter) = triggered_by_filter {
+                filters.insert("triggered_by.kind".to_string(), triggered_by_filter);
+            }
+            if let Some(cycle_filter) = cycle_filter {
+                filters.insert("cycle_id".to_string(), cycle_filter);
+            }
+            (!filters.is_empty()).then_some(filters)
         },
+        base_filter: (!base_filter.is_empty()).then_some(base_filter),
         sort: sort.clone(),
+        sort_fields: RUN_SORT_FIELDS,
         order,
         relative_select,
         relative_value: relative_select_value.map(|v| v as u64),
         relative_unit: relative_select_unit,
+        page_size,
+        after,
+    };
+
+    let runs_page: DataPage<RunsV1> = DataPage::new(state, data_page_params).await?;
+
+    let agent_names: Vec<&str> = runs_page
+        .items
+        .iter()
+        .map(|run| run.agent_name.as_str())
+        .collect();
+    let agent_timezones = fetch_agent_timezones(state, &agent_names).await;
+    let job_changes = match show_changes.unwrap_or_default() {
+        true => fetch_job_changes(state, &runs_page.items).await,
+        false => vec![],
     };
 
-    let runs_page: DataPage<RunsV1> = DataPage::new(state, data_page_params).await;
+    let mut data = runs_page.json();
+    data["agent_timezones"] = json!(agent_timezones);
+    data["job_changes"] = json!(job_changes);
+    iso_dates(&mut data);
+    Ok(Json(data))
+}
+
+/// Run counts by outcome over the last `window` (default `24h`) in buckets of `group_by`
+/// (`minute`, `hour` or `day`, default `hour`), with per-job and per-agent breakdowns for charts.
+#[get("/runs/stats?<group_by>&<window>")]
+pub async fn runs_stats(
+    state: &State<WebState>,
+    access: Access,
+    group_by: Option<String>,
+    window: Option<String>,
+) -> Result<Json<RunStats>, (rocket::http::Status, String)> {
+    let group_by = StatsInterval::try_from(group_by.as_deref().unwrap_or("hour"))
+        .map_err(|e| (rocket::http::Status::BadRequest, e))?;
+    let window = run_stats::parse_window(window.as_deref().unwrap_or("24h"))
+        .map_err(|e| (rocket::http::Status::BadRequest, e))?;
+    if window / group_by.millis() > run_stats::MAX_STATS_BUCKETS {
+        return Err((
+            rocket::http::Status::BadRequest,
+            format!(
+                "Window is more than {} buckets, use a larger group_by",
+                run_stats::MAX_STATS_BUCKETS
+            ),
+        ));
+    }
+
+    let since = DateTime::from_millis(DateTime::now().timestamp_millis().saturating_sub(window));
+    let filter = access
+        .scope_by_job(&state.datastore, "job_name", doc! {})
+        .await?;
+    let stats = RunStats::query(&state.datastore, filter, since, group_by)
+        .await
+        .map_err(|e| {
+            eprintln!("Error aggregating run stats: {}", e);
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error aggregating run stats: {}", e),
+            )
+        })?;
+
+    Ok(Json(stats))
+}
+
+/// Runs produced by one firing of a job, rolled up into a single row.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunCycleSummary {
+    #[serde(rename = "_id")]
+    pub cycle_id: String,
+    pub job_name: String,
+    pub triggered_by: TriggeredBy,
+    pub started_at: DateTime,
+    pub completed_at: DateTime,
+    pub runs: i32,
+    pub succeeded: i32,
+    #[serde(default)]
+    pub status: String, // `success`, `failure` or `partial`
+}
+
+/// Runs grouped by `cycle_id`, newest cycle first. `filter` matches job names.
+#[get("/runs_cycles_data?<page>&<filter>")]
+pub async fn runs_cycles_data(
+    state: &State<WebState>,
+    access: Access,
+    page: Option<u32>,
+    filter: Option<String>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    const PAGE_SIZE: u32 = 20;
+    let page = page.unwrap_or(1).max(1);
+
+    let collection = state
+        .datastore
+        .get_collection::<RunsV1>("runs")
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error accessing runs collection: {}", e),
+            )
+        })?;
+
+    let mut match_doc = doc! { "cycle_id": { "$exists": true, "$ne": null } };
+    if let Some(filter) = filter.filter(|filter| !filter.trim().is_empty()) {
+        match_doc.insert("job_name", doc! { "$regex": filter, "$options": "i" });
+    }
+    let match_doc = access
+        .scope_by_job(&state.datastore, "job_name", match_doc)
+        .await?;
+    let pipeline = vec![
+        doc! { "$match": match_doc },
+        doc! { "$group": {
+            "_id": "$cycle_id",
+            "job_name": { "$first": "$job_name" },
+            "triggered_by": { "$first": "$triggered_by" },
+            "started_at": { "$min": "$started_at" },
+            "completed_at": { "$max": "$completed_at" },
+            "runs": { "$sum": 1 },
+            "succeeded": { "$sum": { "$cond": [{ "$eq": ["$outcome", 1] }, 1, 0] } },
+        } },
+        doc! { "$sort": { "started_at": -1 } },
+        doc! { "$facet": {
+            "items": [
+                { "$skip": ((page - 1) * PAGE_SIZE) as i64 },
+                { "$limit": PAGE_SIZE as i64 },
+            ],
+            "total": [{ "$count": "count" }],
+        } },
+    ];
+
+    let result = collection
+        .aggregate(pipeline)
+        .await
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error aggregating runs: {}", e),
+            )
+        })?
+        .next()
+        .await
+        .transpose()
+        .map_err(|e| {
+            (
+                rocket::http::Status::InternalServerError,
+                format!("Error reading run cycles: {}", e),
+            )
+        })?
+        .unwrap_or_default();
 
-    let DataPage {
-        items: runs,
-        total_pages,
-        current_page: page,
-    } = runs_page;
+    let mut cycles: Vec<RunCycleSummary> = result
+        .get_array("items")
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| item.as_document())
+                .filter_map(|item| bson::from_document(item.clone()).ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    for cycle in cycles.iter_mut() {
+        cycle.status = match cycle.succeeded {
+            succeeded if succeeded == cycle.runs => "success",
+            0 => "failure",
+            _ => "partial",
+        }
+        .to_string();
+    }
+    let total = result
+        .get_array("total")
+        .ok()
+        .and_then(|total| total.first())
+        .and_then(|total| total.as_document())
+        .and_then(|total| total.get_i32("count").ok())
+        .unwrap_or_default() as u32;
 
-    Json(json!({
-        "items": runs,
-        "total_pages": total_pages,
+    Ok(Json(with_iso_dates(json!({
+        "items": cycles,
+        "total_pages": total.div_ceil(PAGE_SIZE),
         "current_page": page,
-    }))
+    }))))
+}
+
+/// Definition changes to the jobs in `runs` made within the time span the runs cover.
+async fn fetch_job_changes(state: &State<WebState>, runs: &[RunsV1]) -> Vec<JobChangeV1> {
+    let (Some(earliest), Some(latest)) = (
+        runs.iter().map(|run| run.started_at).min(),
+        runs.iter().map(|run| run.started_at).max(),
+    ) else {
+        return vec![];
+    };
+    let job_names: HashSet<&str> = runs.iter().map(|run| run.job_name.as_str()).collect();
+    let Ok(collection) = state
+        .datastore
+        .get_collection::<JobChangeV1>("job_changes")
+        .await
+    else {
+        return vec![];
+    };
+    let filter = doc! {
+        "job_name": { "$in": job_names.into_iter().collect::<Vec<_>>() },
+        "changed_at": { "$gte": earliest, "$lte": latest },
+    };
+    match collection.find(filter).sort(doc! { "changed_at": 1 }).await {
+        Ok(cursor) => {
+            cursor
+                .filter_map(|result| async move {
+                    result
+                        .map_err(|e| eprintln!("Error reading job change: {:?}", e))
+                        .ok()
+                })
+                .collect()
+                .await
+        }
+        Err(e) => {
+            eprintln!("Error fetching job changes: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+/// Looks up the timezone each agent reported at registration so run times can be shown host-local.
+async fn fetch_agent_timezones(
+    state: &State<WebState>,
+    agent_names: &[&str],
+) -> HashMap<String, String> {
+    let mut timezones = HashMap::new();
+    let Ok(collection) = state.datastore.get_collection::<AgentV1>("agents").await else {
+        return timezones;
+    };
+    let Ok(mut cursor) = collection
+        .find(doc! { "name": { "$in": agent_names } })
+        .await
+    else {
+        return timezones;
+    };
+    while let Some(result) = cursor.next().await {
+        match result {
+            Ok(agent) if !agent.timezone.is_empty() => {
+                timezones.insert(agent.name, agent.timezone);
+            }
+            Ok(_) => (),
+            Err(e) => eprintln!("Error reading agent: {:?}", e),
+        }
+    }
+    timezones
 }
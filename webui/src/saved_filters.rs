@@ -0,0 +1,154 @@
+use mongodb::bson::{DateTime, doc, oid::ObjectId};
+use rocket::State;
+use rocket::response::Redirect;
+use rocket::serde::Deserialize;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post};
+use serde_json::json;
+
+use crate::WebState;
+use crate::audit::Actor;
+use crate::internal_error;
+use core_logic::datastore::saved_filters::SavedFilterV1;
+
+fn parse_id(id: &str) -> Result<ObjectId, (rocket::http::Status, String)> {
+    ObjectId::parse_str(id).map_err(|e| {
+        (
+            rocket::http::Status::BadRequest,
+            format!("Invalid saved filter id {}: {}", id, e),
+        )
+    })
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde", deny_unknown_fields)]
+pub struct SavedFilterRequest {
+    /// `runs` or `agents`.
+    pub page: String,
+    pub name: String,
+    /// The page's query string, with or without the leading `?`.
+    pub query: String,
+}
+
+/// The filters the user saved on `page`, by name, each with the stable URL it can be shared by.
+#[get("/saved_filters?<page>")]
+pub async fn saved_filters_data(
+    state: &State<WebState>,
+    actor: Actor,
+    page: &str,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let filters = SavedFilterV1::list(&state.datastore, actor.name(), page)
+        .await
+        .map_err(|e| internal_error("Error fetching saved filters", e))?;
+
+    Ok(Json(json!({
+        "items": filters
+            .iter()
+            .map(|filter| json!({
+                "id": filter.id.map(|id| id.to_hex()),
+                "name": filter.name,
+                "query": filter.query,
+                "url": filter.id.map(|id| format!("/saved_filters/{}", id.to_hex())),
+            }))
+            .collect::<Vec<_>>(),
+    })))
+}
+
+/// Saves the user's filter, replacing the one with the same name on the same page, which keeps
+/// its id and so its URL. Returns the id.
+#[post("/saved_filters", data = "<request>")]
+pub async fn post_saved_filter(
+    state: &State<WebState>,
+    actor: Actor,
+    request: Json<SavedFilterRequest>,
+) -> Result<String, (rocket::http::Status, String)> {
+    let request = request.into_inner();
+    let filter = SavedFilterV1 {
+        id: None,
+        owner: actor.name().to_string(),
+        page: request.page,
+        name: request.name.trim().to_string(),
+        query: request.query.trim_start_matches('?').to_string(),
+        created_at: DateTime::now(),
+    };
+    filter
+        .validate()
+        .map_err(|e| (rocket::http::Status::BadRequest, e))?;
+
+    let collection = state
+        .datastore
+        .get_collection::<SavedFilterV1>("saved_filters")
+        .await
+        .map_err(|e| internal_error("Error accessing saved filters collection", e))?;
+    let saved = collection
+        .find_one_and_update(
+            doc! { "owner": &filter.owner, "page": &filter.page, "name": &filter.name },
+            doc! {
+                "$set": { "query": &filter.query },
+                "$setOnInsert": { "created_at": filter.created_at },
+            },
+        )
+        .upsert(true)
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| internal_error("Error saving filter", e))?;
+
+    Ok(saved
+        .and_then(|saved| saved.id)
+        .map(|id| id.to_hex())
+        .unwrap_or_default())
+}
+
+/// Opens the page with the saved filter applied. The URL stays the same when the filter is saved
+/// again, so it can be shared, bookmarked or linked to from runbooks.
+#[get("/saved_filters/<id>")]
+pub async fn open_saved_filter(
+    state: &State<WebState>,
+    id: &str,
+) -> Result<Redirect, (rocket::http::Status, String)> {
+    let object_id = parse_id(id)?;
+    let collection = state
+        .datastore
+        .get_collection::<SavedFilterV1>("saved_filters")
+        .await
+        .map_err(|e| internal_error("Error accessing saved filters collection", e))?;
+    let filter = collection
+        .find_one(doc! { "_id": object_id })
+        .await
+        .map_err(|e| internal_error("Error fetching saved filter", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Saved filter {} not found", id),
+            )
+        })?;
+
+    Ok(Redirect::to(filter.url()))
+}
+
+/// Deletes one of the user's saved filters.
+#[delete("/saved_filters/<id>")]
+pub async fn delete_saved_filter(
+    state: &State<WebState>,
+    actor: Actor,
+    id: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let object_id = parse_id(id)?;
+    let collection = state
+        .datastore
+        .get_collection::<SavedFilterV1>("saved_filters")
+        .await
+        .map_err(|e| internal_error("Error accessing saved filters collection", e))?;
+    collection
+        .find_one_and_delete(doc! { "_id": object_id, "owner": actor.name() })
+        .await
+        .map_err(|e| internal_error("Error deleting saved filter", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Saved filter {} not found", id),
+            )
+        })?;
+
+    Ok("Success".to_string())
+}
@@ -0,0 +1,188 @@
+use futures::TryStreamExt;
+use mongodb::bson::{DateTime, Document, doc};
+use rocket::State;
+use rocket::get;
+use rocket::serde::json::Json;
+use rocket_dyn_templates::{Template, context};
+use serde_json::json;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::internal_error;
+use core_logic::datastore::agent_groups::AgentGroupV1;
+use core_logic::datastore::blackout_windows::BlackoutWindowV1;
+use core_logic::datastore::job_executions::JobExecutionV1;
+use core_logic::datastore::jobs::{JobV1, Status, next_run_datetime};
+
+const DEFAULT_HOURS: i64 = 24;
+/// Longest window, in hours, the preview covers ahead of and behind now.
+const MAX_HOURS: i64 = 14 * 24;
+/// Most upcoming runs listed per job, so a job running every second does not flood the preview.
+const MAX_SCHEDULED_RUNS_PER_JOB: usize = 500;
+/// Most past runs listed, newest first.
+const MAX_PAST_RUNS: i64 = 5000;
+
+#[get("/schedule")]
+pub async fn schedule_page() -> Template {
+    Template::render(
+        "schedule",
+        context! {
+            page_name: "Schedule",
+        },
+    )
+}
+
+/// The runs of the `hours` ahead, computed from each job's interval or cron schedule, the runs
+/// going on and the runs of the `past_hours` behind, for the schedule timeline. Only the job
+/// `job` is included when given. Times are Unix milliseconds.
+///
+/// Upcoming runs list every agent the job targets, its required agents and the members of its
+/// agent groups, although the scheduler may pick fewer of them, and are flagged `blacked_out`
+/// when a blackout window will hold them back.
+#[get("/schedule/preview?<hours>&<past_hours>&<job>")]
+pub async fn schedule_preview(
+    state: &State<WebState>,
+    access: Access,
+    hours: Option<i64>,
+    past_hours: Option<i64>,
+    job: Option<String>,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let hours = hours.unwrap_or(DEFAULT_HOURS).clamp(1, MAX_HOURS);
+    let past_hours = past_hours.unwrap_or(DEFAULT_HOURS).clamp(0, MAX_HOURS);
+    let now = DateTime::now().timestamp_millis();
+    let from = now - past_hours * 3_600_000;
+    let until = now + hours * 3_600_000;
+    let job = job.filter(|job| !job.trim().is_empty());
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let mut filter = doc! {
+        "status": { "$in": [Status::Pending, Status::Running, Status::PendingApproval] },
+        "next_run": { "$lte": next_run_datetime(until / 1000) },
+    };
+    if let Some(job) = &job {
+        filter.insert("name", job);
+    }
+    let jobs: Vec<JobV1> = job_collection
+        .find(access.scope(filter))
+        .sort(doc! { "name": 1 })
+        .await
+        .map_err(|e| internal_error("Error fetching jobs", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading jobs", e))?;
+    let blackouts = BlackoutWindowV1::upcoming(&state.datastore, DateTime::from_millis(now))
+        .await
+        .map_err(|e| internal_error("Error fetching blackout windows", e))?;
+
+    let mut scheduled = vec![];
+    let mut running = vec![];
+    for job in &jobs {
+        let mut agents = job.agents_required.clone();
+        let members = AgentGroupV1::members_of(&state.datastore, &job.agent_groups)
+            .await
+            .map_err(|e| internal_error("Error fetching agent groups", e))?;
+        for member in members {
+            if !agents.contains(&member) {
+                agents.push(member);
+            }
+        }
+        // The `next_run` of a running job is the cycle going on.
+        let skip = (job.status == Status::Running) as usize;
+        for at in job
+            .runs_until(until / 1000)
+            .skip(skip)
+            .take(MAX_SCHEDULED_RUNS_PER_JOB)
+        {
+            let at = DateTime::from_millis(at.saturating_mul(1000));
+            let blacked_out = blackouts
+                .iter()
+                .any(|window| window.holds_back(&job.name, at));
+            scheduled.push(json!({
+                "job_name": job.name,
+                "at": at.timestamp_millis(),
+                "agents": agents,
+                "blacked_out": blacked_out,
+            }));
+        }
+
+        if job.status != Status::Running {
+            continue;
+        }
+        let Some(cycle_id) = &job.cycle_id else {
+            continue;
+        };
+        let execution = JobExecutionV1::find(&state.datastore, cycle_id)
+            .await
+            .map_err(|e| internal_error("Error fetching job execution", e))?;
+        let started_at = execution
+            .map(|execution| execution.started_at.timestamp_millis())
+            .unwrap_or(job.next_run.saturating_mul(1000));
+        for agent_name in &job.agents_running {
+            if job.agents_complete.contains(agent_name) {
+                continue;
+            }
+            running.push(json!({
+                "job_name": job.name,
+                "agent_name": agent_name,
+                "started_at": started_at,
+            }));
+        }
+    }
+
+    let run_collection = state
+        .datastore
+        .get_collection::<Document>("runs")
+        .await
+        .map_err(|e| internal_error("Error accessing runs collection", e))?;
+    let mut filter = doc! { "completed_at": { "$gte": DateTime::from_millis(from) } };
+    if let Some(job) = &job {
+        filter.insert("job_name", job);
+    }
+    let filter = access
+        .scope_by_job(&state.datastore, "job_name", filter)
+        .await?;
+    let documents: Vec<Document> = run_collection
+        .find(filter)
+        .projection(doc! {
+            "job_name": 1,
+            "agent_name": 1,
+            "run_id": 1,
+            "started_at": 1,
+            "completed_at": 1,
+            "outcome": 1,
+        })
+        .sort(doc! { "completed_at": -1 })
+        .limit(MAX_PAST_RUNS)
+        .await
+        .map_err(|e| internal_error("Error fetching runs", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading runs", e))?;
+    let runs: Vec<serde_json::Value> = documents
+        .iter()
+        .filter_map(|run| {
+            Some(json!({
+                "job_name": run.get_str("job_name").ok()?,
+                "agent_name": run.get_str("agent_name").ok()?,
+                "run_id": run.get_str("run_id").ok(),
+                "started_at": run.get_datetime("started_at").ok()?.timestamp_millis(),
+                "completed_at": run.get_datetime("completed_at").ok()?.timestamp_millis(),
+                "outcome": run.get_i32("outcome").ok(),
+            }))
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "from": from,
+        "until": until,
+        "now": now,
+        "scheduled": scheduled,
+        "running": running,
+        "runs": runs,
+        "truncated": runs.len() as i64 >= MAX_PAST_RUNS,
+    })))
+}
@@ -0,0 +1,428 @@
+use base64::Engine;
+use futures::{SinkExt, StreamExt};
+use mongodb::bson::doc;
+use rocket::State;
+use rocket::data::{IoHandler, IoStream};
+use rocket::get;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::Deserialize;
+use rocket::tokio::io::{AsyncRead, AsyncWriteExt};
+use rocket::tokio::net::TcpStream;
+use rocket_dyn_templates::{Template, context};
+use tokio_websockets::{Message as WebSocketMessage, ServerBuilder, WebSocketStream};
+
+use std::env;
+use std::io;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::audit::Actor;
+use core_logic::datastore::Datastore;
+use core_logic::datastore::agents::AgentV1;
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+use core_logic::framing;
+use core_logic::messages::{
+    Authenticate, CloseShell, Credential, Message, OpenShell, ResizeShell, ShellData,
+};
+
+/// Appended to `Sec-WebSocket-Key` to compute `Sec-WebSocket-Accept` (RFC 6455).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Largest frame accepted from central command; output is sent in far smaller pieces.
+const MAX_OUTPUT_FRAME: usize = 1024 * 1024;
+
+static WEBUI_SHELL_USERS: OnceLock<Vec<String>> = OnceLock::new();
+static SHELL_PROXY_ADDRESS: OnceLock<Option<String>> = OnceLock::new();
+static SHELL_PROXY_TOKEN: OnceLock<Option<String>> = OnceLock::new();
+static WEBUI_ORIGIN: OnceLock<Option<String>> = OnceLock::new();
+
+/// Users allowed to open remote shells, read from the comma separated `WEBUI_SHELL_USERS`. Users
+/// are identified by `WEBUI_USER_HEADER`, so anonymous requests are never allowed. Nobody may
+/// open shells when it is empty (the default).
+fn get_webui_shell_users() -> &'static [String] {
+    WEBUI_SHELL_USERS.get_or_init(|| {
+        env::var("WEBUI_SHELL_USERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|user| !user.is_empty())
+            .map(str::to_string)
+            .collect()
+    })
+}
+
+/// Central command's remote shell proxy, read from `SHELL_PROXY_ADDRESS`. Remote shells are
+/// disabled when it is not set (the default).
+fn get_shell_proxy_address() -> Option<&'static str> {
+    SHELL_PROXY_ADDRESS
+        .get_or_init(|| env::var("SHELL_PROXY_ADDRESS").ok())
+        .as_deref()
+}
+
+/// Token presented to the shell proxy, read from `SHELL_PROXY_TOKEN`.
+fn get_shell_proxy_token() -> Option<&'static str> {
+    SHELL_PROXY_TOKEN
+        .get_or_init(|| {
+            env::var("SHELL_PROXY_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty())
+        })
+        .as_deref()
+}
+
+/// The origin the web UI is served from, e.g. `https://dispatch.example.com`, read from
+/// `WEBUI_ORIGIN`. When it is not set, the origin's host has to match the `Host` header instead,
+/// which a reverse proxy that rewrites it breaks.
+fn get_webui_origin() -> Option<&'static str> {
+    WEBUI_ORIGIN
+        .get_or_init(|| {
+            env::var("WEBUI_ORIGIN")
+                .ok()
+                .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                .filter(|origin| !origin.is_empty())
+        })
+        .as_deref()
+}
+
+/// Whether a request from `origin` comes from a page of the web UI: `origin` is `configured`
+/// when given, or else names `host`.
+fn origin_allowed(origin: Option<&str>, host: Option<&str>, configured: Option<&str>) -> bool {
+    let Some(origin) = origin else {
+        return false;
+    };
+    match configured {
+        Some(configured) => origin.eq_ignore_ascii_case(configured),
+        None => origin
+            .strip_prefix("https://")
+            .or_else(|| origin.strip_prefix("http://"))
+            .zip(host)
+            .is_some_and(|(authority, host)| authority.eq_ignore_ascii_case(host)),
+    }
+}
+
+/// Checks that remote shells are enabled and `actor` may open them.
+fn authorize(actor: &Actor) -> Result<(), (Status, String)> {
+    if get_shell_proxy_address().is_none() {
+        return Err((
+            Status::NotFound,
+            "Remote shells are not enabled (SHELL_PROXY_ADDRESS)".to_string(),
+        ));
+    }
+    match &actor.0 {
+        Some(user) if get_webui_shell_users().contains(user) => Ok(()),
+        _ => Err((
+            Status::Forbidden,
+            format!(
+                "{} may not open remote shells (WEBUI_SHELL_USERS)",
+                actor.name()
+            ),
+        )),
+    }
+}
+
+/// The agent `name`, unless it is in the trash or the user may not see it.
+async fn find_agent(
+    state: &State<WebState>,
+    access: &Access,
+    name: &str,
+) -> Result<AgentV1, (Status, String)> {
+    let collection = state
+        .datastore
+        .get_collection::<AgentV1>("agents")
+        .await
+        .map_err(|e| {
+            (
+                Status::InternalServerError,
+                format!("Error accessing agents collection: {}", e),
+            )
+        })?;
+    collection
+        .find_one(access.scope(doc! { "name": name, "deleted_at": null }))
+        .await
+        .map_err(|e| {
+            (
+                Status::InternalServerError,
+                format!("Error fetching agent: {}", e),
+            )
+        })?
+        .ok_or_else(|| (Status::NotFound, format!("Agent {} not found", name)))
+}
+
+/// The terminal page for a remote shell on the agent `name`.
+#[get("/agents/<name>/terminal")]
+pub async fn agent_terminal(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+) -> Result<Template, (Status, String)> {
+    authorize(&actor)?;
+    let agent = find_agent(state, &access, name).await?;
+    Ok(Template::render(
+        "agent_shell",
+        context! {
+            page_name: "Remote Shell",
+            agent,
+        },
+    ))
+}
+
+/// The `Sec-WebSocket-Key` of a WebSocket upgrade request from a page of the web UI, see
+/// `origin_allowed`. Browsers let any page open a WebSocket to any site, sending the site's
+/// cookies and proxy credentials along, so upgrades from other origins are refused.
+pub struct WebSocketKey(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WebSocketKey {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        if !origin_allowed(
+            headers.get_one("Origin"),
+            headers.get_one("Host"),
+            get_webui_origin(),
+        ) {
+            return Outcome::Error((Status::Forbidden, "Cross-origin WebSocket (WEBUI_ORIGIN)"));
+        }
+        match headers.get_one("Sec-WebSocket-Key") {
+            Some(key) => Outcome::Success(WebSocketKey(key.to_string())),
+            None => Outcome::Error((Status::BadRequest, "Expected a WebSocket upgrade")),
+        }
+    }
+}
+
+impl WebSocketKey {
+    fn accept(&self) -> String {
+        let mut digest = ring::digest::Context::new(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY);
+        digest.update(self.0.as_bytes());
+        digest.update(WEBSOCKET_GUID.as_bytes());
+        base64::engine::general_purpose::STANDARD.encode(digest.finish())
+    }
+}
+
+/// A terminal resize sent by the browser as a text message.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct TerminalSize {
+    cols: u16,
+    rows: u16,
+}
+
+/// A remote shell relayed between the browser's WebSocket and central command's shell proxy.
+pub struct ShellSession {
+    accept: String,
+    open: OpenShell,
+    datastore: Datastore,
+}
+
+/// Opens a remote shell on the agent `name` over a WebSocket. Binary messages from the browser
+/// are keystrokes, text messages are JSON `{"cols": .., "rows": ..}` resizes. Terminal output is
+/// sent back as binary messages, and why the shell ended as a final text message.
+///
+/// Only users in `WEBUI_SHELL_USERS` may open shells, from pages of the web UI (`WEBUI_ORIGIN`);
+/// opening and closing each session is recorded in the audit log.
+#[get("/agents/<name>/shell?<cols>&<rows>")]
+pub async fn agent_shell(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    key: WebSocketKey,
+    name: &str,
+    cols: Option<u16>,
+    rows: Option<u16>,
+) -> Result<ShellSession, (Status, String)> {
+    authorize(&actor)?;
+    let agent = find_agent(state, &access, name).await?;
+    let open = OpenShell {
+        session_id: uuid::Uuid::new_v4().to_string(),
+        agent_name: agent.name,
+        user: actor.name().to_string(),
+        cols: cols.unwrap_or(80),
+        rows: rows.unwrap_or(24),
+    };
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::OpenShell,
+        AuditResource::Agent,
+        &open.agent_name,
+    );
+    crate::audit::record(state, entry.with_field("session_id", &open.session_id)).await;
+    Ok(ShellSession {
+        accept: key.accept(),
+        open,
+        datastore: state.datastore.clone(),
+    })
+}
+
+impl<'r> Responder<'r, 'static> for ShellSession {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .raw_header("Sec-WebSocket-Accept", self.accept.clone())
+            .upgrade("websocket", self)
+            .ok()
+    }
+}
+
+#[rocket::async_trait]
+impl IoHandler for ShellSession {
+    async fn io(self: Pin<Box<Self>>, io: IoStream) -> io::Result<()> {
+        let session = Pin::into_inner(self);
+        let started = Instant::now();
+        let mut websocket = ServerBuilder::new().serve(io);
+        let reason = match session.relay(&mut websocket).await {
+            Ok(reason) => reason,
+            Err(e) => format!("Remote shell failed: {}", e),
+        };
+        let _ = websocket.send(WebSocketMessage::text(reason.clone())).await;
+        let _ = websocket.close().await;
+
+        let entry = AuditEntryV1::new(
+            &session.open.user,
+            AuditAction::CloseShell,
+            AuditResource::Agent,
+            &session.open.agent_name,
+        )
+        .with_field("session_id", &session.open.session_id)
+        .with_field("duration_seconds", started.elapsed().as_secs().to_string())
+        .with_field("reason", &reason);
+        if let Err(e) = entry.insert_entry(&session.datastore).await {
+            eprintln!("Error recording audit entry: {}", e);
+        }
+        Ok(())
+    }
+}
+
+impl ShellSession {
+    /// Relays the shell until either side ends it, returning why it ended.
+    async fn relay<S>(&self, websocket: &mut WebSocketStream<S>) -> io::Result<String>
+    where
+        S: AsyncRead + rocket::tokio::io::AsyncWrite + Unpin,
+    {
+        let address = get_shell_proxy_address().unwrap_or_default();
+        let proxy = TcpStream::connect(address).await.map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to connect to central command at {}: {}", address, e),
+            )
+        })?;
+        let (mut from_proxy, mut to_proxy) = proxy.into_split();
+        if let Some(token) = get_shell_proxy_token() {
+            let authenticate = Message::Authenticate(Authenticate {
+                agent_name: self.open.agent_name.clone(),
+                credential: Credential::Token(token.to_string()),
+            });
+            write_message(&mut to_proxy, authenticate).await?;
+        }
+        write_message(&mut to_proxy, Message::OpenShell(self.open.clone())).await?;
+
+        let (mut to_browser, mut from_browser) = websocket.split();
+        let output = async {
+            loop {
+                let Some(frame) = framing::read_frame(&mut from_proxy, MAX_OUTPUT_FRAME).await?
+                else {
+                    return Ok("Connection to central command closed".to_string());
+                };
+                match Message::try_from(frame).map_err(io::Error::other)? {
+                    Message::ShellData(data) => to_browser
+                        .send(WebSocketMessage::binary(data.data))
+                        .await
+                        .map_err(io::Error::other)?,
+                    Message::CloseShell(close) => return Ok(close.reason),
+                    _ => (),
+                }
+            }
+        };
+        let input = async {
+            while let Some(message) = from_browser.next().await {
+                let message = message.map_err(io::Error::other)?;
+                let message = if let Some(text) = message.as_text() {
+                    let Ok(size) = rocket::serde::json::from_str::<TerminalSize>(text) else {
+                        continue;
+                    };
+                    Message::ResizeShell(ResizeShell {
+                        cols: size.cols,
+                        rows: size.rows,
+                    })
+                } else if message.is_binary() {
+                    Message::ShellData(ShellData {
+                        data: message.as_payload().to_vec(),
+                    })
+                } else {
+                    continue;
+                };
+                write_message(&mut to_proxy, message).await?;
+            }
+            let close = Message::CloseShell(CloseShell {
+                reason: format!("Closed by {}", self.open.user),
+            });
+            write_message(&mut to_proxy, close).await?;
+            Ok(format!("Closed by {}", self.open.user))
+        };
+        rocket::tokio::select! {
+            reason = output => reason,
+            reason = input => reason,
+        }
+    }
+}
+
+async fn write_message(
+    writer: &mut rocket::tokio::net::tcp::OwnedWriteHalf,
+    message: Message,
+) -> io::Result<()> {
+    let frame = message.to_frame().map_err(io::Error::other)?;
+    writer.write_all(&frame).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_matching_host_is_allowed() {
+        assert!(origin_allowed(
+            Some("https://dispatch.example.com"),
+            Some("dispatch.example.com"),
+            None
+        ));
+        assert!(origin_allowed(
+            Some("http://localhost:8000"),
+            Some("localhost:8000"),
+            None
+        ));
+    }
+
+    #[test]
+    fn other_origins_are_rejected() {
+        assert!(!origin_allowed(
+            Some("https://evil.example"),
+            Some("dispatch.example.com"),
+            None
+        ));
+        assert!(!origin_allowed(None, Some("dispatch.example.com"), None));
+        assert!(!origin_allowed(
+            Some("null"),
+            Some("dispatch.example.com"),
+            None
+        ));
+    }
+
+    #[test]
+    fn configured_origin_overrides_host() {
+        let configured = Some("https://dispatch.example.com");
+        assert!(origin_allowed(
+            Some("https://dispatch.example.com"),
+            Some("webui:8000"),
+            configured
+        ));
+        assert!(!origin_allowed(
+            Some("http://webui:8000"),
+            Some("webui:8000"),
+            configured
+        ));
+    }
+}
@@ -0,0 +1,248 @@
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use rocket::State;
+use rocket::serde::json::Json;
+use rocket::{delete, get, post};
+use rocket_dyn_templates::{Template, context};
+use serde_json::json;
+
+use crate::WebState;
+use crate::access::Access;
+use crate::audit::{self, Actor};
+use crate::data_page::with_iso_dates;
+use crate::internal_error;
+use core_logic::datastore::agent_groups::AgentGroupV1;
+use core_logic::datastore::agents::AgentV1;
+use core_logic::datastore::audit_log::{AuditAction, AuditEntryV1, AuditResource};
+use core_logic::datastore::job_revisions::JobRevisionV1;
+use core_logic::datastore::jobs::{JobV1, Status};
+
+fn parse_agent_id(id: &str) -> Result<ObjectId, (rocket::http::Status, String)> {
+    ObjectId::parse_str(id).map_err(|_| {
+        (
+            rocket::http::Status::BadRequest,
+            "Invalid agent ID format".to_string(),
+        )
+    })
+}
+
+#[get("/trash")]
+pub async fn trash_page() -> Template {
+    Template::render(
+        "trash",
+        context! {
+            page_name: "Trash",
+        },
+    )
+}
+
+/// The agents and jobs in the trash, most recently deleted first.
+#[get("/trash/data")]
+pub async fn trash_data(
+    state: &State<WebState>,
+    access: Access,
+) -> Result<Json<serde_json::Value>, (rocket::http::Status, String)> {
+    let agent_collection = state
+        .datastore
+        .get_collection::<AgentV1>("agents")
+        .await
+        .map_err(|e| internal_error("Error accessing agents collection", e))?;
+    let agents: Vec<AgentV1> = agent_collection
+        .find(access.scope(doc! { "deleted_at": { "$ne": null } }))
+        .sort(doc! { "deleted_at": -1 })
+        .await
+        .map_err(|e| internal_error("Error fetching agents", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading agents", e))?;
+
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let jobs: Vec<JobV1> = job_collection
+        .find(access.scope(doc! { "deleted_at": { "$ne": null } }))
+        .sort(doc! { "deleted_at": -1 })
+        .await
+        .map_err(|e| internal_error("Error fetching jobs", e))?
+        .try_collect()
+        .await
+        .map_err(|e| internal_error("Error reading jobs", e))?;
+
+    Ok(Json(with_iso_dates(
+        json!({ "agents": agents, "jobs": jobs }),
+    )))
+}
+
+/// Takes the agent out of the trash, so it is dispatched to again, and puts it back in the agent
+/// groups it was a member of that still exist.
+#[post("/agents/<id>/restore")]
+pub async fn restore_agent(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    id: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let object_id = parse_agent_id(id)?;
+    let agent_collection = state
+        .datastore
+        .get_collection::<AgentV1>("agents")
+        .await
+        .map_err(|e| internal_error("Error accessing agents collection", e))?;
+    let agent = agent_collection
+        .find_one_and_update(
+            access.scope(doc! { "_id": object_id, "deleted_at": { "$ne": null } }),
+            doc! {
+                "$unset": { "deleted_at": "", "deleted_groups": "" },
+            },
+        )
+        .await
+        .map_err(|e| internal_error("Error restoring agent", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                "Agent not found in the trash".to_string(),
+            )
+        })?;
+
+    if let Err(e) =
+        AgentGroupV1::add_member_to(&state.datastore, &agent.deleted_groups, &agent.name).await
+    {
+        eprintln!(
+            "Error adding agent {} back to its groups: {}",
+            agent.name, e
+        );
+    }
+    let restored = AgentV1 {
+        deleted_at: None,
+        deleted_groups: vec![],
+        ..agent.clone()
+    };
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Restore,
+        AuditResource::Agent,
+        &agent.name,
+    );
+    audit::record(state, entry.with_diff(Some(&agent), Some(&restored))).await;
+
+    Ok(format!("Restored agent {}", agent.name))
+}
+
+/// Deletes an agent in the trash for good. Its runs and events are kept.
+#[delete("/agents/<id>/purge")]
+pub async fn purge_agent(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    id: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let object_id = parse_agent_id(id)?;
+    let agent_collection = state
+        .datastore
+        .get_collection::<AgentV1>("agents")
+        .await
+        .map_err(|e| internal_error("Error accessing agents collection", e))?;
+    let agent = agent_collection
+        .find_one_and_delete(access.scope(doc! { "_id": object_id, "deleted_at": { "$ne": null } }))
+        .await
+        .map_err(|e| internal_error("Error purging agent", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                "Agent not found in the trash".to_string(),
+            )
+        })?;
+
+    let entry = AuditEntryV1::new(
+        actor.name(),
+        AuditAction::Purge,
+        AuditResource::Agent,
+        &agent.name,
+    );
+    audit::record(state, entry.with_diff(Some(&agent), None)).await;
+
+    Ok(format!("Purged agent {}", agent.name))
+}
+
+/// Takes the job out of the trash with the status it had when it was deleted.
+#[post("/jobs/<name>/restore")]
+pub async fn restore_job(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let job = job_collection
+        .find_one(access.scope(doc! { "name": name, "deleted_at": { "$ne": null } }))
+        .await
+        .map_err(|e| internal_error("Error fetching job", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Job {} not found in the trash", name),
+            )
+        })?;
+
+    let status = job.status_before_delete.unwrap_or(Status::Pending);
+    job_collection
+        .update_one(
+            doc! { "name": name, "deleted_at": { "$ne": null } },
+            doc! {
+                "$set": { "status": status },
+                "$unset": { "deleted_at": "", "status_before_delete": "" },
+            },
+        )
+        .await
+        .map_err(|e| internal_error("Error restoring job", e))?;
+
+    let restored = JobV1 {
+        status,
+        deleted_at: None,
+        status_before_delete: None,
+        ..job.clone()
+    };
+    let entry = AuditEntryV1::new(actor.name(), AuditAction::Restore, AuditResource::Job, name);
+    audit::record(state, entry.with_diff(Some(&job), Some(&restored))).await;
+
+    Ok(format!("Restored job {}", name))
+}
+
+/// Deletes a job in the trash for good, with its revisions. Its runs are kept.
+#[delete("/jobs/<name>/purge")]
+pub async fn purge_job(
+    state: &State<WebState>,
+    actor: Actor,
+    access: Access,
+    name: &str,
+) -> Result<String, (rocket::http::Status, String)> {
+    let job_collection = state
+        .datastore
+        .get_collection::<JobV1>("jobs")
+        .await
+        .map_err(|e| internal_error("Error accessing jobs collection", e))?;
+    let job = job_collection
+        .find_one_and_delete(access.scope(doc! { "name": name, "deleted_at": { "$ne": null } }))
+        .await
+        .map_err(|e| internal_error("Error purging job", e))?
+        .ok_or_else(|| {
+            (
+                rocket::http::Status::NotFound,
+                format!("Job {} not found in the trash", name),
+            )
+        })?;
+
+    if let Err(e) = JobRevisionV1::delete_for_job(&state.datastore, name).await {
+        eprintln!("Error deleting revisions of job {}: {}", name, e);
+    }
+    let entry = AuditEntryV1::new(actor.name(), AuditAction::Purge, AuditResource::Job, name);
+    audit::record(state, entry.with_diff(Some(&job), None)).await;
+
+    Ok(format!("Purged job {}", name))
+}
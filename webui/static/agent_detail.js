@@ -0,0 +1,153 @@
+// Labels and colors of the stored outcome numbers, see `Outcome`.
+const AGENT_RUN_OUTCOMES = {
+    0: { label: "Failure", color: "red" },
+    1: { label: "Success", color: "green" },
+    2: { label: "Unknown", color: "gray" },
+    3: { label: "Timed Out", color: "darkorange" },
+    4: { label: "Cancelled", color: "darkorange" },
+    5: { label: "Skipped", color: "gray" },
+    6: { label: "Dispatch Failed", color: "red" },
+};
+
+const DAY_MILLIS = 24 * 60 * 60 * 1000;
+
+function escapeAgentDetailText(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+// Formats a duration such as an uptime, e.g. "2d 3h" or "5m 10s".
+function formatAgentDuration(millis) {
+    const seconds = Math.max(0, Math.floor(millis / 1000));
+    const parts = [[Math.floor(seconds / 86400), "d"], [Math.floor(seconds / 3600) % 24, "h"],
+        [Math.floor(seconds / 60) % 60, "m"], [seconds % 60, "s"]];
+    const first = parts.findIndex(([count]) => count > 0);
+    if (first === -1) return "0s";
+    return parts.slice(first, first + 2).map(([count, unit]) => `${count}${unit}`).join(" ");
+}
+
+function renderAgentUptime() {
+    const uptime = document.getElementById("agent-uptime");
+    if (!uptime) return;
+    uptime.textContent = formatAgentDuration(Date.now() - Number(uptime.dataset.timestamp));
+}
+
+function renderAgentRunning(running) {
+    const container = document.getElementById("agent-running");
+    if (!container) return;
+    if (running.length === 0) {
+        container.innerHTML = '<p>No jobs running on this agent.</p>';
+        return;
+    }
+    let table = '<table><thead><tr><th>Job</th><th>Started</th><th>Running For</th></tr></thead><tbody>';
+    running.forEach(job => {
+        table += '<tr>';
+        table += `<td><a href="/jobs?filter=${encodeURIComponent(job.job_name)}">${escapeAgentDetailText(job.job_name)}</a></td>`;
+        const startedAt = DateTimeUtils.toMillis(job.started_at);
+        table += `<td><span class="utc-date" data-timestamp="${startedAt}">${startedAt}</span></td>`;
+        table += `<td>${formatAgentDuration(Date.now() - startedAt)}</td>`;
+        table += '</tr>';
+    });
+    container.innerHTML = table + '</tbody></table>';
+}
+
+// Stacks each day's succeeded, failed and other runs, scaled to the busiest day. Days without
+// runs are left out of the stats, so every day of the window is laid out here.
+function renderAgentHealth(stats, days) {
+    const container = document.getElementById("agent-health");
+    if (!container) return;
+    const byDay = new Map((stats.timeline || []).map(bucket =>
+        [DateTimeUtils.toMillis(bucket.start), bucket]));
+    const today = Math.floor(Date.now() / DAY_MILLIS) * DAY_MILLIS;
+    const dayStarts = [];
+    for (let day = today - (days - 1) * DAY_MILLIS; day <= today; day += DAY_MILLIS) {
+        dayStarts.push(day);
+    }
+    const busiest = Math.max(1, ...dayStarts.map(day => (byDay.get(day) || { total: 0 }).total));
+
+    let chart = '<div class="agent-health-chart">';
+    dayStarts.forEach(day => {
+        const bucket = byDay.get(day) || { total: 0, succeeded: 0, failed: 0 };
+        const other = bucket.total - bucket.succeeded - bucket.failed;
+        const title = `${new Date(day).toLocaleDateString()}: ${bucket.total} runs, ${bucket.succeeded} succeeded, ${bucket.failed} failed`;
+        chart += `<div class="agent-health-day" title="${escapeAgentDetailText(title)}">`;
+        [[bucket.succeeded, "succeeded"], [bucket.failed, "failed"], [other, "other"]].forEach(([count, css]) => {
+            if (count > 0) {
+                chart += `<div class="agent-health-${css}" style="height: ${(count / busiest) * 100}%;"></div>`;
+            }
+        });
+        chart += '</div>';
+    });
+    chart += '</div>';
+
+    const totals = stats.totals || { total: 0, succeeded: 0, failed: 0 };
+    const rate = totals.total > 0 ? ((totals.succeeded / totals.total) * 100).toFixed(1) : "-";
+    let summary = `<p>${totals.total} runs, ${totals.succeeded} succeeded, ${totals.failed} failed (${rate}% success).</p>`;
+    const jobs = stats.jobs || [];
+    if (jobs.length > 0) {
+        summary += '<table><thead><tr><th>Job</th><th>Runs</th><th>Succeeded</th><th>Failed</th><th>Average Duration</th></tr></thead><tbody>';
+        jobs.forEach(job => {
+            summary += '<tr>';
+            summary += `<td>${escapeAgentDetailText(job.name)}</td>`;
+            summary += `<td>${job.total}</td><td>${job.succeeded}</td><td>${job.failed}</td>`;
+            summary += `<td>${formatAgentDuration(job.average_duration_ms)}</td>`;
+            summary += '</tr>';
+        });
+        summary += '</tbody></table>';
+    }
+    container.innerHTML = chart + summary;
+}
+
+function renderAgentRuns(runs) {
+    const container = document.getElementById("agent-runs");
+    if (!container) return;
+    if (runs.length === 0) {
+        container.innerHTML = '<p>No runs on this agent.</p>';
+        return;
+    }
+    let table = '<table><thead><tr><th>Job</th><th>Started</th><th>Duration</th><th>Outcome</th><th>Return Code</th></tr></thead><tbody>';
+    runs.forEach(run => {
+        const outcome = AGENT_RUN_OUTCOMES[run.outcome] || AGENT_RUN_OUTCOMES[2];
+        const job = escapeAgentDetailText(run.job_name);
+        table += '<tr>';
+        table += run.run_id
+            ? `<td><a href="/runs?filter=${encodeURIComponent(run.run_id)}">${job}</a></td>`
+            : `<td>${job}</td>`;
+        const startedAt = DateTimeUtils.toMillis(run.started_at);
+        table += `<td><span class="utc-date" data-timestamp="${startedAt}">${startedAt}</span></td>`;
+        table += `<td>${formatAgentDuration(DateTimeUtils.toMillis(run.completed_at) - startedAt)}</td>`;
+        table += `<td style="color: ${outcome.color};">${outcome.label}</td>`;
+        table += `<td>${run.return_code ?? ""}</td>`;
+        table += '</tr>';
+    });
+    container.innerHTML = table + '</tbody></table>';
+}
+
+function renderAgentActivity(name, days) {
+    AjaxUtils.getJsonData(`/agents/${encodeURIComponent(name)}/activity`, { days: days })
+        .then(data => {
+            renderAgentUptime();
+            renderAgentRunning(data.running || []);
+            renderAgentHealth(data.stats || {}, Number(days));
+            renderAgentRuns(data.runs || []);
+            DateTimeUtils.convertUtcDateElements();
+
+            TimeOutWrapper.createMyTimeout(() => renderAgentActivity(name, days), 30000);
+        })
+        .catch(error => {
+            const container = document.getElementById("agent-running");
+            if (container) {
+                container.innerHTML = `<p>Error loading activity: ${escapeAgentDetailText(error.message)}</p>`;
+            }
+        });
+}
+
+// Loads the agent's jobs, runs and health charts, and its connectivity over the same `days`.
+function renderAgentDetail(name, days = 7) {
+    renderAgentActivity(name, days);
+    renderAgentTimeline(name, days);
+}
@@ -0,0 +1,125 @@
+function escapeHtml(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+function showGroupStatus(message, isError) {
+    const statusSuccess = document.getElementById('status-success');
+    const statusError = document.getElementById('status-error');
+    statusSuccess.style.display = isError ? 'none' : 'block';
+    statusError.style.display = isError ? 'block' : 'none';
+    (isError ? statusError : statusSuccess).innerHTML = escapeHtml(message);
+}
+
+function sendGroupRequest(url, method, body) {
+    const options = { method: method };
+    if (body !== undefined) {
+        options.headers = { 'Content-Type': 'application/json' };
+        options.body = JSON.stringify(body);
+    }
+    return fetch(url, options)
+        .then(response => response.text().then(text => {
+            if (!response.ok) {
+                throw new Error(text || 'Server error');
+            }
+            showGroupStatus(text, false);
+            renderAgentGroupsTable();
+        }))
+        .catch(error => showGroupStatus(error.message, true));
+}
+
+function groupUrl(name) {
+    return '/agent_groups/' + encodeURIComponent(name);
+}
+
+function saveAgentGroup(event) {
+    event.preventDefault();
+    const members = document.getElementById('group-members').value
+        .split(',')
+        .map(member => member.trim())
+        .filter(Boolean);
+    sendGroupRequest('/agent_groups', 'POST', {
+        name: document.getElementById('group-name').value.trim(),
+        description: document.getElementById('group-description').value,
+        members: members,
+    });
+}
+
+function editAgentGroup(group) {
+    document.getElementById('group-name').value = group.name;
+    document.getElementById('group-description').value = group.description || '';
+    document.getElementById('group-members').value = (group.members || []).join(', ');
+}
+
+function addGroupMember(name, index) {
+    const input = document.getElementById('add-member-' + index);
+    const agentName = input ? input.value.trim() : '';
+    if (!agentName) return;
+    sendGroupRequest(groupUrl(name) + '/members/' + encodeURIComponent(agentName), 'POST');
+}
+
+function removeGroupMember(name, agentName) {
+    sendGroupRequest(groupUrl(name) + '/members/' + encodeURIComponent(agentName), 'DELETE');
+}
+
+function deleteAgentGroup(name) {
+    if (!window.confirm('Are you sure you want to delete agent group ' + name + '?')) {
+        return;
+    }
+    sendGroupRequest(groupUrl(name), 'DELETE');
+}
+
+let agentGroups = [];
+
+function renderAgentGroupsTable() {
+    AjaxUtils.getJsonData("/agent_groups/data", {})
+        .then(data => {
+            const container = document.getElementById("items");
+            if (!container) return;
+
+            agentGroups = data.items;
+
+            if (!Array.isArray(agentGroups) || agentGroups.length === 0) {
+                container.innerHTML = '<p>No agent groups.</p>';
+                return;
+            }
+
+            let table = '<table><thead><tr>';
+            table += '<th>Name</th>';
+            table += '<th>Description</th>';
+            table += '<th>Members</th>';
+            table += '<th>Add Member</th>';
+            table += '<th></th>';
+            table += '</tr></thead><tbody>';
+
+            agentGroups.forEach((group, index) => {
+                const name = escapeHtml(group.name);
+                const quotedName = escapeHtml(JSON.stringify(group.name));
+                const members = (group.members || []).map(member =>
+                    `${escapeHtml(member)} <a href="#" title="Remove from group" onclick="removeGroupMember(${quotedName}, ${escapeHtml(JSON.stringify(member))}); return false;">&times;</a>`
+                ).join(', ');
+                table += '<tr>';
+                table += `<td>${name}</td>`;
+                table += `<td>${escapeHtml(group.description)}</td>`;
+                table += `<td>${members || '<i>none</i>'}</td>`;
+                table += `<td><input type="text" id="add-member-${index}" placeholder="Agent name"> `;
+                table += `<a href="#" class="btn" onclick="addGroupMember(${quotedName}, ${index}); return false;">Add</a></td>`;
+                table += `<td><a href="#" class="btn" onclick="editAgentGroup(agentGroups[${index}]); return false;">Edit</a> `;
+                table += `<a href="#" class="btn" onclick="deleteAgentGroup(${quotedName}); return false;">Delete</a></td>`;
+                table += '</tr>';
+            });
+
+            table += '</tbody></table>';
+            container.innerHTML = table;
+        })
+        .catch(error => {
+            const container = document.getElementById("items");
+            if (container) {
+                container.innerHTML = `<p>Error loading data: ${escapeHtml(error.message)}</p>`;
+            }
+        });
+}
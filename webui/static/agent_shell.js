@@ -0,0 +1,102 @@
+// A minimal terminal for remote shells. Agents start shells with TERM=dumb, so escape sequences
+// are stripped rather than interpreted.
+const TERMINAL_KEYS = {
+    Enter: '\r',
+    Backspace: '\x7f',
+    Tab: '\t',
+    Escape: '\x1b',
+    ArrowUp: '\x1b[A',
+    ArrowDown: '\x1b[B',
+    ArrowRight: '\x1b[C',
+    ArrowLeft: '\x1b[D',
+    Delete: '\x1b[3~',
+};
+
+function terminalSize(terminal) {
+    const probe = document.createElement('span');
+    probe.textContent = 'M';
+    terminal.appendChild(probe);
+    const cols = Math.max(20, Math.floor(terminal.clientWidth / probe.offsetWidth));
+    const rows = Math.max(5, Math.floor(terminal.clientHeight / probe.offsetHeight));
+    terminal.removeChild(probe);
+    return { cols, rows };
+}
+
+function appendOutput(terminal, text) {
+    let content = terminal.textContent;
+    text = text.replace(/\x1b\[[0-9;?]*[A-Za-z]|\x1b\][^\x07]*\x07|\x1b[()][A-Za-z0-9]/g, '');
+    for (const ch of text) {
+        if (ch === '\b') {
+            content = content.slice(0, -1);
+        } else if (ch === '\r') {
+            continue;
+        } else if (ch === '\x07') {
+            continue;
+        } else {
+            content += ch;
+        }
+    }
+    terminal.textContent = content;
+    terminal.scrollTop = terminal.scrollHeight;
+}
+
+function openAgentShell(name) {
+    const terminal = document.getElementById('terminal');
+    const status = document.getElementById('terminal-status');
+    const size = terminalSize(terminal);
+    const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
+    const url = protocol + '//' + window.location.host + '/agents/' + encodeURIComponent(name) +
+        '/shell?cols=' + size.cols + '&rows=' + size.rows;
+    const socket = new WebSocket(url);
+    socket.binaryType = 'arraybuffer';
+    const encoder = new TextEncoder();
+    const decoder = new TextDecoder();
+
+    const send = (text) => {
+        if (socket.readyState === WebSocket.OPEN) {
+            socket.send(encoder.encode(text));
+        }
+    };
+
+    socket.onopen = () => {
+        status.textContent = 'Connected';
+        terminal.focus();
+    };
+    socket.onmessage = (event) => {
+        if (typeof event.data === 'string') {
+            status.textContent = event.data;
+        } else {
+            appendOutput(terminal, decoder.decode(event.data, { stream: true }));
+        }
+    };
+    socket.onclose = () => {
+        if (status.textContent === 'Connected' || status.textContent === 'Connecting...') {
+            status.textContent = 'Disconnected';
+        }
+    };
+
+    terminal.addEventListener('keydown', (event) => {
+        if (event.metaKey || (event.ctrlKey && event.key === 'v')) {
+            return;
+        }
+        let data = TERMINAL_KEYS[event.key];
+        if (event.ctrlKey && event.key.length === 1 && /[a-z]/i.test(event.key)) {
+            data = String.fromCharCode(event.key.toUpperCase().charCodeAt(0) - 64);
+        } else if (!data && event.key.length === 1 && !event.ctrlKey) {
+            data = event.key;
+        }
+        if (data) {
+            event.preventDefault();
+            send(data);
+        }
+    });
+    terminal.addEventListener('paste', (event) => {
+        event.preventDefault();
+        send(event.clipboardData.getData('text').replace(/\r?\n/g, '\r'));
+    });
+    window.addEventListener('resize', () => {
+        if (socket.readyState === WebSocket.OPEN) {
+            socket.send(JSON.stringify(terminalSize(terminal)));
+        }
+    });
+}
@@ -0,0 +1,84 @@
+const AGENT_EVENT_KINDS = {
+    connected: "Connected",
+    disconnected: "Disconnected",
+    registered: "Registered",
+    drained: "Drained",
+    undrained: "Undrained",
+};
+
+function isConnectivityEvent(event) {
+    return event.kind === "connected" || event.kind === "disconnected";
+}
+
+function escapeEventText(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;");
+}
+
+function eventTimestamp(event) {
+    return DateTimeUtils.toMillis(event["at"]);
+}
+
+function timelineSegment(state, start, end, windowStart, windowLength) {
+    const width = ((end - start) / windowLength) * 100;
+    const left = ((start - windowStart) / windowLength) * 100;
+    const label = state === "connected" ? "Online" : state === "disconnected" ? "Offline" : "Unknown";
+    const css = state === "connected" ? "agent-online" : state === "disconnected" ? "agent-offline" : "agent-unknown";
+    return `<div class="agent-timeline-segment ${css}" style="left: ${left}%; width: ${width}%;" title="${label} from ${new Date(start).toLocaleString()} to ${new Date(end).toLocaleString()}"></div>`;
+}
+
+function renderAgentTimeline(name, days = 7) {
+    AjaxUtils.getJsonData(`/agents/${encodeURIComponent(name)}/events`, { days: days })
+        .then(data => {
+            const timeline = document.getElementById("agent-timeline");
+            const list = document.getElementById("agent-events");
+            if (!timeline || !list) return;
+
+            const events = data.items || [];
+            const windowStart = DateTimeUtils.toMillis(data.since);
+            const windowEnd = Date.now();
+            const windowLength = Math.max(windowEnd - windowStart, 1);
+
+            let state = data.previous ? data.previous.kind : "unknown";
+            let segmentStart = windowStart;
+            let html = '';
+            events.filter(isConnectivityEvent).forEach(event => {
+                const at = eventTimestamp(event);
+                html += timelineSegment(state, segmentStart, at, windowStart, windowLength);
+                state = event.kind;
+                segmentStart = at;
+            });
+            html += timelineSegment(state, segmentStart, windowEnd, windowStart, windowLength);
+            timeline.innerHTML = html;
+
+            const disconnects = events.filter(event => event.kind === "disconnected").length;
+            if (events.length === 0) {
+                list.innerHTML = '<p>No agent events in this period.</p>';
+            } else {
+                let table = `<p>${disconnects} disconnect${disconnects === 1 ? "" : "s"} in this period.</p>`;
+                table += '<table><thead><tr><th>When</th><th>Event</th><th>Detail</th></tr></thead><tbody>';
+                events.slice().reverse().forEach(event => {
+                    const timestamp = eventTimestamp(event);
+                    table += '<tr>';
+                    table += `<td><span class="utc-date" data-timestamp="${timestamp}">${timestamp}</span></td>`;
+                    table += `<td>${AGENT_EVENT_KINDS[event.kind] || escapeEventText(event.kind)}</td>`;
+                    table += `<td>${escapeEventText(event.detail)}</td>`;
+                    table += '</tr>';
+                });
+                table += '</tbody></table>';
+                list.innerHTML = table;
+            }
+
+            DateTimeUtils.convertUtcDateElements();
+
+            TimeOutWrapper.createMyTimeout(() => renderAgentTimeline(name, days), 30000);
+        })
+        .catch(error => {
+            const list = document.getElementById("agent-events");
+            if (list) {
+                list.innerHTML = `<p>Error loading connectivity: ${error.message}</p>`;
+            }
+        });
+}
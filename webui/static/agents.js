@@ -1,4 +1,31 @@
 
+// Labels for the agent `status` numbers, see `core_logic::datastore::agents::Status`.
+const AGENT_STATUSES = {
+    0: "offline",
+    1: "online",
+    2: "draining",
+    3: "degraded",
+    4: "unknown",
+};
+
+function agentStatusName(status) {
+    return AGENT_STATUSES[status] || "unknown";
+}
+
+function agentStatusBadge(status) {
+    const name = agentStatusName(status);
+    return `<span class="agent-status-badge agent-status-${name}">${name.charAt(0).toUpperCase() + name.slice(1)}</span>`;
+}
+
+function escapeAgentText(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
 function renderAgentsTable(params = {}) {
     // Append filter string to the URL if provided
     const url = "/agents/data";
@@ -21,25 +48,52 @@ function renderAgentsTable(params = {}) {
                 let div = '<div class="agents-list">';
 
                 data.forEach(item => {
-                    div += `<div onclick="window.location='/agents/edit?id=${item['_id']['$oid']}'" class="agent-card`;
-                    if(item["status"] == 1) {
-                        div += ' agent-online';
-                    } else {
-                        div += ' agent-offline';       
-                    }
-                    div += '">';
+                    div += `<div onclick="window.location='/agents/${item['_id']['$oid']}'" class="agent-card agent-${agentStatusName(item["status"])}">`;
                     div += item["name"] + '<br>';
                     div += `<img width="100px;" src="/agent.png"><br>`;
                     div += `<span class="agent-host-info">${item["hostname"]}:${item["port"]}</span><br>`;
-                    div += '<div class="agent-online-info">';
-                    if (item["last_ping"] && item["last_ping"]["$date"] && item["last_ping"]["$date"]["$numberLong"] !== "0") {
-                        div += `Last Ping: <span class="utc-date" data-timestamp="${item["last_ping"]["$date"]["$numberLong"]}">${item["last_ping"]["$date"]["$numberLong"]}</span><br><br>`;
+                    if (item["agent_version"]) {
+                        div += `<span class="agent-host-info">v${item["agent_version"]}</span><br>`;
                     }
-                    if (item["status"] == 1) {
-                        div += 'Online';
-                    } else {
-                        div += 'Offline';
+                    if (item["timezone"]) {
+                        div += `<span class="agent-host-info">${item["timezone"]}${item["locale"] ? " / " + item["locale"] : ""}</span><br>`;
+                    }
+                    if (item["os"]) {
+                        div += `<span class="agent-host-info">${item["os"]}/${item["arch"]}${item["kernel"] ? " (" + item["kernel"] + ")" : ""}</span><br>`;
+                    }
+                    const podInfo = item["container"];
+                    if (podInfo) {
+                        let where = escapeAgentText(podInfo["runtime"]);
+                        if (podInfo["pod_name"]) {
+                            where += ` pod ${escapeAgentText(podInfo["pod_name"])}`;
+                            if (podInfo["pod_namespace"]) {
+                                where += ` in ${escapeAgentText(podInfo["pod_namespace"])}`;
+                            }
+                        }
+                        if (podInfo["node_name"]) {
+                            where += ` on ${escapeAgentText(podInfo["node_name"])}`;
+                        }
+                        div += `<span class="agent-host-info">${where}</span><br>`;
+                    }
+                    if (item["shells"] && item["shells"].length) {
+                        div += `<span class="agent-host-info">${item["shells"].join(", ")}</span><br>`;
+                    }
+                    if (item["features"] && item["features"].length) {
+                        div += `<span class="agent-host-info">${item["features"].join(", ")}</span><br>`;
+                    }
+                    if (item["team"]) {
+                        div += `<span class="agent-host-info">Team ${escapeAgentText(item["team"])}</span><br>`;
+                    }
+                    if (item["namespace"]) {
+                        div += `<span class="agent-host-info">Namespace ${escapeAgentText(item["namespace"])}</span><br>`;
+                    }
+                    div += '<div class="agent-online-info">';
+                    const lastPing = DateTimeUtils.toMillis(item["last_ping"]);
+                    if (lastPing > 0) {
+                        div += `Last seen <span class="relative-date" data-timestamp="${lastPing}">${lastPing}</span><br>`;
+                        div += `<span class="utc-date" data-timestamp="${lastPing}">${lastPing}</span><br><br>`;
                     }
+                    div += agentStatusBadge(item["status"]);
                     div += '</div>'; // Close agent-online-info
                     div += '</div>';
                 });
@@ -0,0 +1,130 @@
+const ALERT_KINDS = {
+    run_failed: "Failed run",
+    sla_breached: "SLA breached",
+    overdue: "Overdue",
+    timeout_warning: "Timeout warning",
+};
+
+const ALERT_STATUS_COLORS = {
+    open: "red",
+    acknowledged: "orange",
+    resolved: "green",
+};
+
+function escapeAlertText(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+function showAlertStatus(message, isError) {
+    const statusSuccess = document.getElementById('status-success');
+    const statusError = document.getElementById('status-error');
+    statusSuccess.style.display = isError ? 'none' : 'block';
+    statusError.style.display = isError ? 'block' : 'none';
+    (isError ? statusError : statusSuccess).innerHTML = escapeAlertText(message);
+}
+
+// Acknowledges or resolves an alert, then reloads the table.
+function updateAlert(id, action, params) {
+    fetch(`/alerts/${encodeURIComponent(id)}/${action}`, { method: 'POST' })
+        .then(response => response.text().then(text => {
+            if (!response.ok) {
+                throw new Error(text || 'Server error');
+            }
+            showAlertStatus(text, false);
+            TimeOutWrapper.haltAllTimeouts();
+            renderAlertsTable(params);
+        }))
+        .catch(error => showAlertStatus(error.message, true));
+}
+
+function alertTime(value) {
+    const timestamp = DateTimeUtils.toMillis(value);
+    return `<span class="utc-date" data-timestamp="${timestamp}">${timestamp}</span>`;
+}
+
+function renderAlertsTable(params = {}) {
+    AjaxUtils.getJsonData("/alerts/data", params)
+        .then(data => {
+            const container = document.getElementById("items");
+            if (!container) return;
+
+            let current_page = data.current_page;
+            let total_pages = data.total_pages;
+
+            data = data.items;
+
+            if (!Array.isArray(data) || data.length === 0) {
+                container.innerHTML = '<p>No alerts.</p>';
+            } else {
+                let table = '<table><thead><tr>';
+                table += '<th>Status</th>';
+                table += '<th>Kind</th>';
+                table += '<th>Job</th>';
+                table += '<th>Agent</th>';
+                table += '<th>Detail</th>';
+                table += '<th>Occurrences</th>';
+                table += '<th>First</th>';
+                table += '<th>Last</th>';
+                table += '<th></th>';
+                table += '</tr></thead><tbody>';
+
+                data.forEach(item => {
+                    const id = item["_id"]["$oid"];
+                    let status = escapeAlertText(item.status);
+                    if (item.status === "acknowledged" && item.acknowledged_by) {
+                        status += ` by ${escapeAlertText(item.acknowledged_by)}`;
+                    } else if (item.status === "resolved" && item.resolved_by) {
+                        status += ` by ${escapeAlertText(item.resolved_by)}`;
+                    }
+                    if (item.paged) {
+                        status += ' (paged)';
+                    }
+                    const detail = item.run_id
+                        ? `<a href="/runs?filter=${encodeURIComponent(item.run_id)}">${escapeAlertText(item.detail)}</a>`
+                        : escapeAlertText(item.detail);
+                    let actions = '';
+                    if (item.status === "open") {
+                        actions += `<button class="btn" onclick="updateAlert('${id}', 'acknowledge', ${escapeAlertText(JSON.stringify(params))})">Acknowledge</button> `;
+                    }
+                    if (item.status !== "resolved") {
+                        actions += `<button class="btn" onclick="updateAlert('${id}', 'resolve', ${escapeAlertText(JSON.stringify(params))})">Resolve</button>`;
+                    }
+                    table += '<tr>';
+                    table += `<td style="color:${ALERT_STATUS_COLORS[item.status] || "inherit"};">${status}</td>`;
+                    table += `<td>${ALERT_KINDS[item.kind] || escapeAlertText(item.kind)}</td>`;
+                    table += `<td><a href="/jobs?filter=${encodeURIComponent(item.job_name)}">${escapeAlertText(item.job_name)}</a></td>`;
+                    table += `<td>${escapeAlertText(item.agent_name)}</td>`;
+                    table += `<td>${detail}</td>`;
+                    table += `<td>${item.occurrences}</td>`;
+                    table += `<td>${alertTime(item.first_at)}</td>`;
+                    table += `<td>${alertTime(item.last_at)}</td>`;
+                    table += `<td>${actions}</td>`;
+                    table += '</tr>';
+                });
+
+                table += '</tbody></table>';
+
+                pagination = "<div class=\"pagination_controls\" id=\"pagination-controls\" style=\"margin-top: 20px;\"></div>";
+
+                container.innerHTML = table + pagination;
+
+                renderPaginationControls(current_page, total_pages);
+            }
+
+            DateTimeUtils.convertUtcDateElements();
+
+            TimeOutWrapper.createMyTimeout(() => renderAlertsTable(params), 10000);
+        })
+        .catch(error => {
+            const container = document.getElementById("items");
+            if (container) {
+                container.innerHTML = `<p>Error loading data: ${escapeAlertText(error.message)}</p>`;
+            }
+            TimeOutWrapper.createMyTimeout(() => renderAlertsTable(params), 10000);
+        });
+}
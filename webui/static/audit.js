@@ -0,0 +1,87 @@
+const AUDIT_RESOURCES = {
+    job: "Job",
+    agent: "Agent",
+    job_template: "Template",
+    agent_group: "Agent Group",
+    blackout_window: "Blackout Window",
+    job_file: "Job File",
+    api_token: "API Token",
+    job_promotion: "Promotion",
+    alert: "Alert",
+};
+
+function escapeHtml(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;");
+}
+
+function renderAuditChanges(changes) {
+    if (!Array.isArray(changes) || changes.length === 0) {
+        return '';
+    }
+    let html = '<ul class="audit-changes">';
+    changes.forEach(change => {
+        html += `<li><b>${escapeHtml(change.field)}</b>: `;
+        html += `<del>${escapeHtml(change.old)}</del> &rarr; <ins>${escapeHtml(change.new)}</ins></li>`;
+    });
+    html += '</ul>';
+    return html;
+}
+
+function renderAuditTable(params = {}) {
+    AjaxUtils.getJsonData("/audit/data", params)
+        .then(data => {
+            const container = document.getElementById("items");
+            if (!container) return;
+
+            let current_page = data.current_page;
+            let total_pages = data.total_pages;
+
+            data = data.items;
+
+            if (!Array.isArray(data) || data.length === 0) {
+                container.innerHTML = '<p>No audit entries.</p>';
+            } else {
+                let table = '<table><thead><tr>';
+                table += '<th>When</th>';
+                table += '<th>User</th>';
+                table += '<th>Action</th>';
+                table += '<th>Resource</th>';
+                table += '<th>Changes</th>';
+                table += '</tr></thead><tbody>';
+
+                data.forEach(item => {
+                    const timestamp = DateTimeUtils.toMillis(item["at"]);
+                    table += '<tr>';
+                    table += `<td><span class="utc-date" data-timestamp="${timestamp}">${timestamp}</span></td>`;
+                    table += `<td>${escapeHtml(item["user"])}</td>`;
+                    table += `<td>${escapeHtml(item["action"])}</td>`;
+                    table += `<td>${AUDIT_RESOURCES[item["resource_kind"]] || escapeHtml(item["resource_kind"])} ${escapeHtml(item["resource"])}</td>`;
+                    table += `<td>${renderAuditChanges(item["changes"])}</td>`;
+                    table += '</tr>';
+                });
+
+                table += '</tbody></table>';
+
+                pagination = "<div class=\"pagination_controls\" id=\"pagination-controls\" style=\"margin-top: 20px;\"></div>";
+
+                container.innerHTML = table + pagination;
+
+                renderPaginationControls(current_page, total_pages);
+            }
+
+            DateTimeUtils.convertUtcDateElements();
+
+            TimeOutWrapper.createMyTimeout(() => renderAuditTable(params), 10000);
+        })
+        .catch(error => {
+            const container = document.getElementById("items");
+            if (container) {
+                container.innerHTML = `<p>Error loading data: ${error.message}</p>`;
+            }
+            TimeOutWrapper.createMyTimeout(() => renderAuditTable(params), 10000);
+        });
+}
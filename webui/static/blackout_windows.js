@@ -0,0 +1,147 @@
+function escapeHtml(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+function showWindowStatus(message, isError) {
+    const statusSuccess = document.getElementById('status-success');
+    const statusError = document.getElementById('status-error');
+    statusSuccess.style.display = isError ? 'none' : 'block';
+    statusError.style.display = isError ? 'block' : 'none';
+    (isError ? statusError : statusSuccess).innerHTML = escapeHtml(message);
+}
+
+function sendWindowRequest(url, method, body) {
+    const options = { method: method };
+    if (body !== undefined) {
+        options.headers = { 'Content-Type': 'application/json' };
+        options.body = JSON.stringify(body);
+    }
+    return fetch(url, options)
+        .then(response => response.text().then(text => {
+            if (!response.ok) {
+                throw new Error(text || 'Server error');
+            }
+            showWindowStatus(text, false);
+            renderBlackoutWindowsTable("items", true);
+        }))
+        .catch(error => showWindowStatus(error.message, true));
+}
+
+// Unix seconds of a datetime-local input, which is in the browser's timezone.
+function inputSeconds(elementId) {
+    const value = document.getElementById(elementId).value;
+    return value ? Math.floor(new Date(value).getTime() / 1000) : NaN;
+}
+
+// The value of a datetime-local input showing the given milliseconds in the browser's timezone.
+function inputValue(ms) {
+    const date = new Date(ms);
+    return new Date(ms - date.getTimezoneOffset() * 60000).toISOString().slice(0, 16);
+}
+
+function windowMs(date) {
+    return DateTimeUtils.toMillis(date);
+}
+
+function saveBlackoutWindow(event) {
+    event.preventDefault();
+    const start = inputSeconds('window-start');
+    const end = inputSeconds('window-end');
+    if (isNaN(start) || isNaN(end)) {
+        showWindowStatus('Start and end are required', true);
+        return;
+    }
+    const jobs = document.getElementById('window-jobs').value
+        .split(',')
+        .map(job => job.trim())
+        .filter(Boolean);
+    sendWindowRequest('/blackout_windows', 'POST', {
+        name: document.getElementById('window-name').value.trim(),
+        description: document.getElementById('window-description').value,
+        start: start,
+        end: end,
+        jobs: jobs,
+    });
+}
+
+function editBlackoutWindow(blackout) {
+    document.getElementById('window-name').value = blackout.name;
+    document.getElementById('window-description').value = blackout.description || '';
+    document.getElementById('window-start').value = inputValue(windowMs(blackout.start));
+    document.getElementById('window-end').value = inputValue(windowMs(blackout.end));
+    document.getElementById('window-jobs').value = (blackout.jobs || []).join(', ');
+}
+
+function deleteBlackoutWindow(name) {
+    if (!window.confirm('Are you sure you want to delete blackout window ' + name + '?')) {
+        return;
+    }
+    sendWindowRequest('/blackout_windows/' + encodeURIComponent(name), 'DELETE');
+}
+
+let blackoutWindows = [];
+
+// Lists the windows that have not ended; `editable` adds edit and delete buttons.
+function renderBlackoutWindowsTable(containerId, editable) {
+    AjaxUtils.getJsonData("/blackout_windows/data", {})
+        .then(data => {
+            const container = document.getElementById(containerId);
+            if (!container) return;
+
+            blackoutWindows = data.items;
+
+            if (!Array.isArray(blackoutWindows) || blackoutWindows.length === 0) {
+                container.innerHTML = '<p>No upcoming blackout windows.</p>';
+                return;
+            }
+
+            const now = Date.now();
+            let table = '<table><thead><tr>';
+            table += '<th>Name</th>';
+            table += '<th>Description</th>';
+            table += '<th>Start</th>';
+            table += '<th>End</th>';
+            table += '<th>Jobs</th>';
+            if (editable) {
+                table += '<th></th>';
+            }
+            table += '</tr></thead><tbody>';
+
+            blackoutWindows.forEach((blackout, index) => {
+                const start = windowMs(blackout.start);
+                const end = windowMs(blackout.end);
+                const jobs = (blackout.jobs || []).map(escapeHtml).join(', ');
+                table += '<tr>';
+                table += `<td>${escapeHtml(blackout.name)}`;
+                if (start <= now) {
+                    table += ' <span style="color:red;">(in progress)</span>';
+                }
+                table += '</td>';
+                table += `<td>${escapeHtml(blackout.description)}</td>`;
+                table += `<td><span class="zoned-date" data-timestamp="${start}" data-timezone="">${start}</span></td>`;
+                table += `<td><span class="zoned-date" data-timestamp="${end}" data-timezone="">${end}</span></td>`;
+                table += `<td>${jobs || '<i>all jobs</i>'}</td>`;
+                if (editable) {
+                    const quotedName = escapeHtml(JSON.stringify(blackout.name));
+                    table += `<td><a href="#" class="btn" onclick="editBlackoutWindow(blackoutWindows[${index}]); return false;">Edit</a> `;
+                    table += `<a href="#" class="btn" onclick="deleteBlackoutWindow(${quotedName}); return false;">Delete</a></td>`;
+                }
+                table += '</tr>';
+            });
+
+            table += '</tbody></table>';
+            container.innerHTML = table;
+            DateTimeUtils.convertUtcDateElements();
+        })
+        .catch(error => {
+            const container = document.getElementById(containerId);
+            if (container) {
+                container.innerHTML = `<p>Error loading data: ${escapeHtml(error.message)}</p>`;
+            }
+        });
+}
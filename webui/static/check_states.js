@@ -0,0 +1,71 @@
+function escapeCheckText(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+const CHECK_STATE_COLORS = {
+    ok: "#2e7d32",
+    warning: "#f9a825",
+    critical: "#c62828",
+    unknown: "#6d6d6d",
+};
+
+// Shows the current state of each check job (rows) on each of its agents (columns).
+function renderCheckStatesGrid(containerId) {
+    AjaxUtils.getJsonData("/check_states/data", {})
+        .then(data => {
+            const container = document.getElementById(containerId);
+            if (!container) return;
+
+            const items = data.items;
+            if (!Array.isArray(items) || items.length === 0) {
+                container.innerHTML = '<p>No check jobs have run.</p>';
+                return;
+            }
+
+            const states = {};
+            items.forEach(item => {
+                states[`${item.job_name}\n${item.agent_name}`] = item;
+            });
+
+            let table = '<table><thead><tr>';
+            table += '<th>Check</th>';
+            data.agents.forEach(agent => {
+                table += `<th><a href="/agents?filter=${encodeURIComponent(agent)}">${escapeCheckText(agent)}</a></th>`;
+            });
+            table += '</tr></thead><tbody>';
+
+            data.jobs.forEach(job => {
+                table += '<tr>';
+                table += `<td><a href="/jobs?filter=${encodeURIComponent(job)}">${escapeCheckText(job)}</a></td>`;
+                data.agents.forEach(agent => {
+                    const check = states[`${job}\n${agent}`];
+                    if (!check) {
+                        table += '<td></td>';
+                        return;
+                    }
+                    const since = new Date(check.since).toLocaleString();
+                    const title = `${check.summary || check.state.toUpperCase()} (since ${since})`;
+                    const label = escapeCheckText(check.state.toUpperCase());
+                    const cell = check.run_id
+                        ? `<a href="/runs?filter=${encodeURIComponent(check.run_id)}" style="color:white;">${label}</a>`
+                        : label;
+                    table += `<td style="background-color:${CHECK_STATE_COLORS[check.state] || CHECK_STATE_COLORS.unknown};color:white;text-align:center;" title="${escapeCheckText(title)}">${cell}</td>`;
+                });
+                table += '</tr>';
+            });
+
+            table += '</tbody></table>';
+            container.innerHTML = table;
+        })
+        .catch(error => {
+            const container = document.getElementById(containerId);
+            if (container) {
+                container.innerHTML = `<p>Error loading data: ${escapeCheckText(error.message)}</p>`;
+            }
+        });
+}
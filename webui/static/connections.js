@@ -0,0 +1,136 @@
+const CONNECTION_KINDS = {
+    inbound: "Inbound",
+    reverse_dispatch: "Reverse Dispatch",
+    outbound: "Outbound",
+    pull: "Pull",
+};
+
+function escapeHtml(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+function formatBytes(bytes) {
+    const units = ["B", "KiB", "MiB", "GiB"];
+    let value = Number(bytes) || 0;
+    let unit = 0;
+    while (value >= 1024 && unit < units.length - 1) {
+        value /= 1024;
+        unit++;
+    }
+    return `${unit === 0 ? value : value.toFixed(1)} ${units[unit]}`;
+}
+
+function dateCell(date) {
+    const timestamp = DateTimeUtils.toMillis(date);
+    if (isNaN(timestamp)) {
+        return '<td></td>';
+    }
+    return `<td><span class="utc-date" data-timestamp="${timestamp}">${timestamp}</span></td>`;
+}
+
+// Frames from the peer that were corrupted or did not parse, with the last error as a tooltip.
+function protocolErrorsCell(item) {
+    const count = Number(item["protocol_errors"]) || 0;
+    if (count === 0) {
+        return '<td>0</td>';
+    }
+    return `<td class="error" title="${escapeHtml(item["last_protocol_error"])}">${count}</td>`;
+}
+
+// The central command instance dispatching jobs, when instances elect a leader.
+function renderLeader(leader) {
+    const element = document.getElementById("leader");
+    if (!element) return;
+    if (!leader) {
+        element.innerHTML = '';
+        return;
+    }
+    const since = DateTimeUtils.toMillis(leader["acquired_at"]);
+    element.innerHTML = `Leader: <b>${escapeHtml(leader["holder"])}</b> since ` +
+        `<span class="utc-date" data-timestamp="${since}">${since}</span>`;
+}
+
+// The agent partitions each central command instance holds, when agents are partitioned.
+function renderPartitions(partitions) {
+    const element = document.getElementById("partitions");
+    if (!element) return;
+    if (!Array.isArray(partitions) || partitions.length === 0) {
+        element.innerHTML = '';
+        return;
+    }
+    const byHolder = {};
+    partitions.forEach(lease => {
+        const partition = Number(String(lease["_id"]).split(":").pop());
+        (byHolder[lease["holder"]] = byHolder[lease["holder"]] || []).push(partition);
+    });
+    const holders = Object.keys(byHolder).sort().map(holder => {
+        const held = byHolder[holder].sort((a, b) => a - b).join(", ");
+        return `<b>${escapeHtml(holder)}</b>: ${escapeHtml(held)}`;
+    });
+    element.innerHTML = `Agent partitions: ${holders.join("; ")}`;
+}
+
+function renderConnectionsTable() {
+    AjaxUtils.getJsonData("/connections/data", {})
+        .then(data => {
+            const container = document.getElementById("items");
+            if (!container) return;
+
+            renderLeader(data.leader);
+            renderPartitions(data.partitions);
+            data = data.items;
+
+            if (!Array.isArray(data) || data.length === 0) {
+                container.innerHTML = '<p>No connections.</p>';
+            } else {
+                let table = '<table><thead><tr>';
+                table += '<th>Agent</th>';
+                table += '<th>Central Command</th>';
+                table += '<th>Kind</th>';
+                table += '<th>Remote Address</th>';
+                table += '<th>Connected Since</th>';
+                table += '<th>Bytes In</th>';
+                table += '<th>Bytes Out</th>';
+                table += '<th>Messages In / Out</th>';
+                table += '<th>Last Message</th>';
+                table += '<th>Last Message At</th>';
+                table += '<th>Protocol Errors</th>';
+                table += '</tr></thead><tbody>';
+
+                data.forEach(item => {
+                    table += '<tr>';
+                    table += `<td>${item["agent_name"] || "<i>unidentified</i>"}</td>`;
+                    table += `<td>${escapeHtml(item["central_command"])}</td>`;
+                    table += `<td>${CONNECTION_KINDS[item["kind"]] || item["kind"]}</td>`;
+                    table += `<td>${item["remote_addr"]}</td>`;
+                    table += dateCell(item["connected_since"]);
+                    table += `<td>${formatBytes(item["bytes_in"])}</td>`;
+                    table += `<td>${formatBytes(item["bytes_out"])}</td>`;
+                    table += `<td>${item["messages_in"]} / ${item["messages_out"]}</td>`;
+                    table += `<td>${item["last_message"] || ""}</td>`;
+                    table += dateCell(item["last_message_at"]);
+                    table += protocolErrorsCell(item);
+                    table += '</tr>';
+                });
+
+                table += '</tbody></table>';
+                container.innerHTML = table;
+            }
+
+            DateTimeUtils.convertUtcDateElements();
+
+            TimeOutWrapper.createMyTimeout(() => renderConnectionsTable(), 5000);
+        })
+        .catch(error => {
+            const container = document.getElementById("items");
+            if (container) {
+                container.innerHTML = `<p>Error loading data: ${error.message}</p>`;
+            }
+            TimeOutWrapper.createMyTimeout(() => renderConnectionsTable(), 5000);
+        });
+}
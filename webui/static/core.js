@@ -54,6 +54,17 @@ class TimeOutWrapper {
 }
 
 class DateTimeUtils {
+    // Milliseconds since the epoch of a time from the API: an ISO 8601 string, a BSON date in
+    // extended JSON or a number of milliseconds.
+    static toMillis(value) {
+        if (value === null || value === undefined) return NaN;
+        if (typeof value === 'number') return value;
+        if (typeof value === 'string') return Date.parse(value);
+        const date = value["$date"];
+        if (date === undefined) return NaN;
+        return typeof date === 'string' ? Date.parse(date) : Number(date["$numberLong"]);
+    }
+
     static formatUtcDate(timestamp) {
         if (isNaN(timestamp)) return '';
         const date = new Date(Number(timestamp));
@@ -71,12 +82,74 @@ class DateTimeUtils {
         return new Intl.DateTimeFormat('en-US', options).format(date);
     }
 
+    // Formats a timestamp in the given IANA timezone, e.g. "03:00 host-local (Europe/Berlin)".
+    static formatHostLocalDate(timestamp, timeZone) {
+        if (isNaN(timestamp) || !timeZone) return '';
+        const date = new Date(Number(timestamp));
+        try {
+            const time = new Intl.DateTimeFormat('en-US', {
+                hour: '2-digit',
+                minute: '2-digit',
+                hour12: window.config.prefer12HourFormat,
+                timeZone: timeZone,
+            }).format(date);
+            return `${time} host-local (${timeZone})`;
+        } catch (e) {
+            console.warn(`Unknown timezone ${timeZone}:`, e);
+            return '';
+        }
+    }
+
+    // Formats a timestamp as a full date in the given IANA timezone, or in the browser's own
+    // timezone when none is given.
+    static formatZonedDate(timestamp, timeZone) {
+        if (isNaN(timestamp)) return '';
+        const date = new Date(Number(timestamp));
+        try {
+            return new Intl.DateTimeFormat('en-US', {
+                year: 'numeric',
+                month: 'short',
+                day: 'numeric',
+                hour: '2-digit',
+                minute: '2-digit',
+                hour12: window.config.prefer12HourFormat,
+                timeZone: timeZone || undefined,
+                timeZoneName: 'short'
+            }).format(date);
+        } catch (e) {
+            console.warn(`Unknown timezone ${timeZone}:`, e);
+            return '';
+        }
+    }
+
+    // Formats how long ago a timestamp was, e.g. "3 minutes ago".
+    static formatRelative(timestamp) {
+        if (isNaN(timestamp)) return '';
+        const seconds = Math.max(0, Math.floor((Date.now() - Number(timestamp)) / 1000));
+        const units = [['day', 86400], ['hour', 3600], ['minute', 60], ['second', 1]];
+        for (const [unit, size] of units) {
+            const count = Math.floor(seconds / size);
+            if (count >= 1) return `${count} ${unit}${count === 1 ? '' : 's'} ago`;
+        }
+        return 'just now';
+    }
+
     static convertUtcDateElements() {
         DateTimeUtils.utcDateElements = document.querySelectorAll('.utc-date');
         DateTimeUtils.utcDateElements.forEach(cell => {
             const timestamp = cell.dataset.timestamp;
             cell.textContent = DateTimeUtils.formatUtcDate(timestamp);
         });
+        document.querySelectorAll('.host-local-date').forEach(cell => {
+            cell.textContent = DateTimeUtils.formatHostLocalDate(cell.dataset.timestamp, cell.dataset.timezone);
+        });
+        document.querySelectorAll('.zoned-date').forEach(cell => {
+            cell.textContent = DateTimeUtils.formatZonedDate(cell.dataset.timestamp, cell.dataset.timezone);
+        });
+        document.querySelectorAll('.relative-date').forEach(cell => {
+            cell.textContent = DateTimeUtils.formatRelative(cell.dataset.timestamp);
+            cell.title = DateTimeUtils.formatUtcDate(cell.dataset.timestamp);
+        });
     }
 
     static refreshUtcDateElementsCache() {
@@ -262,6 +335,100 @@ class FilterUtils {
         const currentOrder = url.searchParams.get('order');
         url.searchParams.set('order', currentOrder === 'asc' ? 'desc' : 'asc');
     }
+
+    // A badge for each of `tags`, filtering the page by the tag when clicked. The tag is only
+    // ever text or an attribute value, never script, and the click is handled below.
+    static tagBadges(tags) {
+        return (tags || []).map(tag => {
+            const div = document.createElement('div');
+            div.textContent = tag;
+            const escaped = div.innerHTML.replace(/"/g, '&quot;').replace(/'/g, '&#39;');
+            return ` <a href="#" class="job-tag" data-tag="${escaped}">${escaped}</a>`;
+        }).join('');
+    }
+}
+
+document.addEventListener('click', event => {
+    const badge = event.target.closest('.job-tag[data-tag]');
+    if (!badge) return;
+    event.preventDefault();
+    FilterUtils.applyFilterAndReload('tag_filter', badge.dataset.tag, false, true);
+});
+
+// Combinations of filters, sort order and time range saved on a page, each with a stable URL.
+class SavedFilters {
+    static load(page) {
+        AjaxUtils.getJsonData('/saved_filters', { page: page })
+            .then(data => {
+                const select = document.getElementById('saved_filters');
+                if (!select) return;
+                select.length = 1;
+                data.items.forEach(filter => {
+                    const option = document.createElement('option');
+                    option.value = filter.id;
+                    option.textContent = filter.name;
+                    select.appendChild(option);
+                });
+            })
+            .catch(error => SavedFilters.report(`Error loading saved filters: ${error.message}`));
+    }
+
+    static open(id) {
+        if (id) window.location.href = `/saved_filters/${id}`;
+    }
+
+    // Saves the page's current query string, without its page number, under a name asked for.
+    static save(page) {
+        const name = window.prompt('Name of the saved filter');
+        if (!name) return;
+        const params = new URL(window.location.href).searchParams;
+        params.delete('page');
+        fetch('/saved_filters', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ page: page, name: name, query: params.toString() })
+        })
+            .then(response => response.text().then(text => {
+                if (!response.ok) throw new Error(text || 'Server error');
+                SavedFilters.report(`Saved ${name}`);
+                SavedFilters.load(page);
+            }))
+            .catch(error => SavedFilters.report(`Error saving filter: ${error.message}`));
+    }
+
+    static share() {
+        const id = document.getElementById('saved_filters').value;
+        if (!id) {
+            SavedFilters.report('Choose a saved filter first.');
+            return;
+        }
+        const url = `${window.location.origin}/saved_filters/${id}`;
+        navigator.clipboard.writeText(url)
+            .then(() => SavedFilters.report(`Copied ${url}`))
+            .catch(() => window.prompt('Link to the saved filter', url));
+    }
+
+    static remove(page) {
+        const select = document.getElementById('saved_filters');
+        if (!select.value) {
+            SavedFilters.report('Choose a saved filter first.');
+            return;
+        }
+        const name = select.options[select.selectedIndex].textContent;
+        if (!window.confirm(`Delete saved filter ${name}?`)) return;
+        fetch(`/saved_filters/${select.value}`, { method: 'DELETE' })
+            .then(response => response.text().then(text => {
+                if (!response.ok) throw new Error(text || 'Server error');
+                SavedFilters.report(`Deleted ${name}`);
+                SavedFilters.load(page);
+            }))
+            .catch(error => SavedFilters.report(`Error deleting filter: ${error.message}`));
+    }
+
+    static report(message) {
+        const result = document.getElementById('saved_filters_result');
+        if (result) result.textContent = message;
+    }
 }
 
 class AjaxUtils {
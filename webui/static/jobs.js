@@ -1,9 +1,88 @@
 
+// Scheduling lag above which a job's drift is highlighted, matching central command's warning.
+const SCHEDULING_DRIFT_WARNING_MS = 5000;
+
+function formatDrift(ms) {
+    if (ms < 1000) {
+        return `${ms} ms`;
+    }
+    if (ms < 60000) {
+        return `${(ms / 1000).toFixed(1)} s`;
+    }
+    return `${(ms / 60000).toFixed(1)} min`;
+}
+
+// How late the latest cycle was dispatched, or how long a due job has been waiting to be.
+function driftCell(item) {
+    const waitingMs = item["status"] === 0 ? Date.now() - DateTimeUtils.toMillis(item["next_run"]) : 0;
+    if (waitingMs >= SCHEDULING_DRIFT_WARNING_MS) {
+        return `<td style="color:red;" title="Due but not dispatched yet">waiting ${formatDrift(waitingMs)}</td>`;
+    }
+    const lagMs = item["scheduling_lag_ms"];
+    if (lagMs === undefined || lagMs === null) {
+        return '<td></td>';
+    }
+    const color = lagMs >= SCHEDULING_DRIFT_WARNING_MS ? "red" : lagMs >= 1000 ? "orange" : "green";
+    return `<td style="color:${color};" title="Dispatched ${formatDrift(lagMs)} after it was due">${formatDrift(lagMs)}</td>`;
+}
+
+// The next run in the job's timezone, which its cron schedule follows, and in the browser's.
+function nextRunCell(item) {
+    const timestamp = DateTimeUtils.toMillis(item["next_run"]);
+    const timeZone = item["timezone"] || "UTC";
+    const schedule = item["cron"] ? `cron ${item["cron"]} (${timeZone})` : "";
+    let cell = `<td><span class="zoned-date" data-timestamp="${timestamp}" data-timezone="${timeZone}">${timestamp}</span>`;
+    cell += `<br><small><span class="zoned-date" data-timestamp="${timestamp}" data-timezone="">${timestamp}</span> local</small>`;
+    if (schedule) {
+        cell += `<br><small>${schedule}</small>`;
+    }
+    return cell + '</td>';
+}
+
+// The params the jobs table was last rendered with, to render it again after a bulk action.
+let jobsTableParams = {};
+
+function selectAllJobs(checked) {
+    document.querySelectorAll(".item-checkbox[data-id]").forEach(box => box.checked = checked);
+}
+
+// Applies a bulk action to the selected jobs after confirming it, reporting which were kept.
+function bulkJobAction(method, url, verb) {
+    const ids = Array.from(document.querySelectorAll(".item-checkbox[data-id]:checked"))
+        .map(box => box.dataset.id);
+    const result = document.getElementById("bulk-result");
+    if (ids.length === 0) {
+        result.textContent = "Select the jobs first.";
+        return;
+    }
+    if (!window.confirm(`${verb} ${ids.length} job${ids.length === 1 ? "" : "s"}?`)) {
+        return;
+    }
+    fetch(url, {
+        method: method,
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ ids: ids })
+    })
+        .then(response => response.text().then(text => {
+            if (!response.ok) {
+                throw new Error(text || 'Server error');
+            }
+            result.textContent = text;
+            TimeOutWrapper.haltAllTimeouts();
+            renderJobsTable(jobsTableParams);
+        }))
+        .catch(error => result.textContent = `${verb} failed: ${error.message}`);
+}
+
 function renderJobsTable(params = {}) {
+    jobsTableParams = params;
     // Append filter string to the URL if provided
     const url = "/jobs_data";
     AjaxUtils.getJsonData(url, params)
         .then(data => {
+            // Keep the selection across refreshes.
+            const selected = new Set(Array.from(document.querySelectorAll(".item-checkbox[data-id]:checked"))
+                .map(box => box.dataset.id));
             const container = document.getElementById("items");
             if (!container) return;
 
@@ -12,27 +91,31 @@ function renderJobsTable(params = {}) {
 
             data = data.items;
 
+            document.getElementById("item_ids").innerHTML = data.map(item => item._id.$oid).join(' ');
+
             // Assume data is an array of objects
             if (!Array.isArray(data) || data.length === 0) {
                 container.innerHTML = '<p>No data available.</p>';
             } else {
                 // Get table headers from object keys
                 let table = '<table><thead><tr>';
-                table += '<th><input type="checkbox" class="item-checkbox"></th>'
+                table += '<th><input type="checkbox" title="Select all" onchange="selectAllJobs(this.checked)"></th>';
                 table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'name', true); return false;\">Job Name</a></th>`;
                 table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'description', true); return false;\">Description</a></th>`;
                 table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'status', true); return false;\">Status</a></th>`;
+                table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'team', true); return false;\">Team</a></th>`;
                 table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'command', true); return false;\">Command</a></th>`;
                 table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'next_run', true); return false;\">Next Run</a></th>`;
+                table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'scheduling_lag_ms', true); return false;\">Drift</a></th>`;
                 table += `<th></th>`;
                 table += '</tr></thead><tbody>';
 
                 // Add table rows
                 data.forEach(item => {
-                    let next_run = item["next_run"];//.$date.$numberLong;
                     table += '<tr>';
-                    table += `<td><input type="checkbox" class="item-checkbox" data-id="${item["_id"]['$oid']}"></td>`;
-                    table += `<td>${item["name"]}</td>`;
+                    table += `<td><input type="checkbox" class="item-checkbox" data-id="${item["_id"]['$oid']}" ${selected.has(item["_id"]['$oid']) ? "checked" : ""}></td>`;
+                    const namespace = item["namespace"] ? ` <span class="namespace-tag">${escapeJobText(item["namespace"])}</span>` : "";
+                    table += `<td>${item["name"]}${namespace}${FilterUtils.tagBadges(item["tags"])}</td>`;
                     table += `<td>${item["description"]}</td>`;
                     let statusText = "";
                     let statusColor = "";
@@ -57,11 +140,20 @@ function renderJobsTable(params = {}) {
                             statusText = "Error";
                             statusColor = "red";
                             break;
+                        case 5:
+                            statusText = "Archived";
+                            statusColor = "gray";
+                            break;
+                        case 6:
+                            statusText = "Pending Approval";
+                            statusColor = "orange";
+                            break;
                         default:
                             statusText = item["status"];
                             statusColor = "";
                     }
                     table += `<td style="color:${statusColor};">${statusText}</td>`;
+                    table += `<td title="${item["owner"] ? `Owner: ${escapeJobText(item["owner"])}` : ""}">${escapeJobText(item["team"])}</td>`;
                     const command = item["command"] || "";
                     const shortCommand = command.length > 10 ? command.substring(0, 10) + "..." : command;
                     const commandId = `command-${item["_id"]['$oid']}`;
@@ -77,11 +169,18 @@ function renderJobsTable(params = {}) {
                             }
                         " data-expanded="false">${shortCommand}</span>
                     </td>`;
-                    table += `<td class="utc-date" data-timestamp="${next_run}">${next_run}</td>`;
+                    table += nextRunCell(item);
+                    table += driftCell(item);
                     table += '<td>';
                     table += '<button class="btn btn-primary" onclick="#">Runs</button>&nbsp';
+                    table += `<button class="btn btn-primary" onclick="showJobHistory(${escapeJobText(JSON.stringify(item["name"]))})">History</button>&nbsp`;
                     table += '<button class="btn btn-primary" onclick="#">Kill</button>&nbsp';
                     table += '<button class="btn btn-primary" onclick="#">Freeze</button>';
+                    if (item["status"] === 6) {
+                        const quotedName = escapeJobText(JSON.stringify(item["name"]));
+                        table += `&nbsp<button class="btn btn-primary" onclick="decideJobRun(${quotedName}, 'approve')">Approve</button>`;
+                        table += `&nbsp<button class="btn btn-primary" onclick="decideJobRun(${quotedName}, 'reject')">Reject</button>`;
+                    }
                     table += '</td>';
                     table += '</tr>';
                 });
@@ -108,3 +207,97 @@ function renderJobsTable(params = {}) {
             TimeOutWrapper.createMyTimeout(() => renderJobsTable(params), 10000);
         });
 }
+
+function escapeJobText(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+function revisionChangesCell(changes) {
+    if (!changes || changes.length === 0) {
+        return '<td><i>initial definition</i></td>';
+    }
+    let cell = '<td><table class="revision-diff"><tbody>';
+    changes.forEach(change => {
+        cell += '<tr>';
+        cell += `<td><b>${escapeJobText(change.field)}</b></td>`;
+        cell += `<td class="revision-old">${escapeJobText(change.old) || '<i>unset</i>'}</td>`;
+        cell += `<td class="revision-new">${escapeJobText(change.new) || '<i>unset</i>'}</td>`;
+        cell += '</tr>';
+    });
+    return cell + '</tbody></table></td>';
+}
+
+// Lists the job's revisions with what each changed, and lets an earlier one be restored.
+function showJobHistory(name) {
+    const container = document.getElementById("job-history");
+    if (!container) return;
+    AjaxUtils.getJsonData(`/jobs/${encodeURIComponent(name)}/revisions`, {})
+        .then(data => {
+            const revisions = data.items || [];
+            const quotedName = escapeJobText(JSON.stringify(name));
+            let html = `<h2>History of ${escapeJobText(name)}</h2>`;
+            html += '<a href="#" class="btn" onclick="document.getElementById(\'job-history\').innerHTML = \'\'; return false;">Close</a>';
+            if (revisions.length === 0) {
+                container.innerHTML = html + '<p>No revisions recorded yet. Revisions are recorded from the next edit on.</p>';
+                return;
+            }
+            html += '<table><thead><tr><th>Revision</th><th>When</th><th>Author</th><th>Source</th><th>Changes</th><th></th></tr></thead><tbody>';
+            revisions.forEach((revision, index) => {
+                const timestamp = DateTimeUtils.toMillis(revision.created_at);
+                html += '<tr>';
+                html += `<td>${revision.revision}</td>`;
+                html += `<td><span class="utc-date" data-timestamp="${timestamp}">${timestamp}</span></td>`;
+                html += `<td>${escapeJobText(revision.author)}</td>`;
+                html += `<td>${escapeJobText(revision.source)}</td>`;
+                html += revisionChangesCell(revision.changes);
+                html += index === 0
+                    ? '<td><i>current</i></td>'
+                    : `<td><button class="btn btn-primary" onclick="rollbackJob(${quotedName}, ${revision.revision})">Roll back</button></td>`;
+                html += '</tr>';
+            });
+            html += '</tbody></table>';
+            container.innerHTML = html;
+            DateTimeUtils.convertUtcDateElements();
+            container.scrollIntoView();
+        })
+        .catch(error => {
+            container.innerHTML = `<p>Error loading history: ${escapeJobText(error.message)}</p>`;
+        });
+}
+
+// Approves or rejects the due run of a job waiting for approval, with an optional comment.
+function decideJobRun(name, decision) {
+    const comment = window.prompt(`Comment on the ${decision === 'approve' ? 'approval' : 'rejection'} of the run of job ${name} (optional):`);
+    if (comment === null) {
+        return;
+    }
+    const query = comment.trim() ? `?comment=${encodeURIComponent(comment)}` : '';
+    fetch(`/jobs/${encodeURIComponent(name)}/${decision}${query}`, { method: 'POST' })
+        .then(response => response.text().then(text => {
+            if (!response.ok) {
+                throw new Error(text || 'Server error');
+            }
+            window.alert(text);
+        }))
+        .catch(error => window.alert(`${decision === 'approve' ? 'Approval' : 'Rejection'} failed: ${error.message}`));
+}
+
+function rollbackJob(name, revision) {
+    if (!window.confirm(`Restore revision ${revision} of job ${name}?`)) {
+        return;
+    }
+    fetch(`/jobs/${encodeURIComponent(name)}/revisions/${revision}/rollback`, { method: 'POST' })
+        .then(response => response.text().then(text => {
+            if (!response.ok) {
+                throw new Error(text || 'Server error');
+            }
+            window.alert(text);
+            showJobHistory(name);
+        }))
+        .catch(error => window.alert(`Rollback failed: ${error.message}`));
+}
@@ -0,0 +1,28 @@
+// Fills the namespace switcher in the navigation with the known namespaces. The selection is
+// kept in the `namespace` cookie, which narrows the jobs, agents and runs pages.
+class NamespaceSwitcher {
+    static load() {
+        const select = document.getElementById("namespace-switcher");
+        if (!select) return;
+        AjaxUtils.getJsonData("/namespaces")
+            .then(data => {
+                (data.namespaces || []).forEach(namespace => {
+                    const option = document.createElement("option");
+                    option.value = namespace;
+                    option.textContent = namespace;
+                    select.appendChild(option);
+                });
+                select.value = data.selected || "";
+            })
+            .catch(error => console.error("Error loading namespaces:", error));
+    }
+
+    static select(namespace) {
+        document.cookie = namespace
+            ? `namespace=${encodeURIComponent(namespace)}; path=/; SameSite=Lax`
+            : "namespace=; path=/; max-age=0; SameSite=Lax";
+        window.location.reload();
+    }
+}
+
+document.addEventListener("DOMContentLoaded", () => NamespaceSwitcher.load());
@@ -0,0 +1,61 @@
+function escapeOverdueText(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+function formatOverdue(seconds) {
+    if (seconds < 3600) {
+        return `${Math.floor(seconds / 60)} min`;
+    }
+    return `${(seconds / 3600).toFixed(1)} h`;
+}
+
+// Lists the jobs whose scheduled run has not started within their SLA's max start delay.
+function renderOverdueJobsTable(containerId) {
+    AjaxUtils.getJsonData("/overdue_jobs/data", {})
+        .then(data => {
+            const container = document.getElementById(containerId);
+            if (!container) return;
+
+            const jobs = data.items;
+            if (!Array.isArray(jobs) || jobs.length === 0) {
+                container.innerHTML = '<p>No overdue jobs.</p>';
+                return;
+            }
+
+            let table = '<table><thead><tr>';
+            table += '<th>Job</th>';
+            table += '<th>Scheduled</th>';
+            table += '<th>Overdue</th>';
+            table += '<th>Agents</th>';
+            table += '</tr></thead><tbody>';
+
+            jobs.forEach(job => {
+                const scheduled = DateTimeUtils.toMillis(job.next_run);
+                const agents = (job.agents_required || [])
+                    .concat((job.agent_groups || []).map(group => `group ${group}`))
+                    .map(escapeOverdueText)
+                    .join(', ');
+                table += '<tr>';
+                table += `<td><a href="/jobs?filter=${encodeURIComponent(job.name)}">${escapeOverdueText(job.name)}</a></td>`;
+                table += `<td><span class="zoned-date" data-timestamp="${scheduled}" data-timezone="">${scheduled}</span></td>`;
+                table += `<td style="color:red;" title="Expected to start within ${job.max_start_delay} s">${formatOverdue(job.overdue_seconds)}</td>`;
+                table += `<td>${agents}</td>`;
+                table += '</tr>';
+            });
+
+            table += '</tbody></table>';
+            container.innerHTML = table;
+            DateTimeUtils.convertUtcDateElements();
+        })
+        .catch(error => {
+            const container = document.getElementById(containerId);
+            if (container) {
+                container.innerHTML = `<p>Error loading data: ${escapeOverdueText(error.message)}</p>`;
+            }
+        });
+}
@@ -10,7 +10,184 @@ function clearOutcomeFilter() {
     window.location = url.toString();
 }
 
-function showRunOutputDialog(runId) {
+// Labels and colors of the stored outcome numbers, see `Outcome`.
+const RUN_OUTCOMES = {
+    0: { label: "Failure", color: "red" },
+    1: { label: "Success", color: "green" },
+    2: { label: "Unknown", color: "gray" },
+    3: { label: "Timed Out", color: "darkorange" },
+    4: { label: "Cancelled", color: "darkorange" },
+    5: { label: "Skipped", color: "gray" },
+    6: { label: "Dispatch Failed", color: "red" },
+};
+
+function formatOutcome(outcome) {
+    const known = RUN_OUTCOMES[outcome];
+    if (!known) return `<td>${outcome}</td>`;
+    return `<td style="color: ${known.color};">${known.label}</td>`;
+}
+
+// What triggered a run, as HTML.
+function formatTriggeredBy(triggeredBy) {
+    if (!triggeredBy || !triggeredBy.kind) return "Scheduler";
+    const source = escapeOutput(triggeredBy.source);
+    switch (triggeredBy.kind) {
+        case "scheduler":
+            return "Scheduler";
+        case "user":
+            return `User ${source}`;
+        case "api_token":
+            return `API Token ${source}`;
+        case "webhook":
+            return `Webhook ${source}`;
+        case "retry":
+            return `Retry of ${source}`;
+        case "hook":
+            return `Hook ${source}`;
+        default:
+            return escapeOutput(triggeredBy.kind);
+    }
+}
+
+function renderJobChangeRow(change) {
+    const changedAt = DateTimeUtils.toMillis(change["changed_at"]);
+    let details = escapeOutput(change["kind"]);
+    if (change["changes"] && change["changes"].length > 0) {
+        details += ": " + change["changes"]
+            .map(c => `${escapeOutput(c.field)} "${escapeOutput(c.old)}" &rarr; "${escapeOutput(c.new)}"`)
+            .join(", ");
+    }
+    return `<tr class="job-change-row" style="background-color: #f5f0dc;">
+        <td>${escapeOutput(change["job_name"])}</td>
+        <td colspan="7">Definition ${details} by ${escapeOutput(change["changed_by"])}</td>
+        <td><span class="utc-date" data-timestamp="${changedAt}">${changedAt}</span></td>
+    </tr>`;
+}
+
+// Inserts a marker row for each definition change next to the runs of the same job started around it.
+// Runs are assumed to be listed in start order, ascending or descending.
+function interleaveJobChanges(rows, runs, changes) {
+    if (!changes || changes.length === 0 || runs.length === 0) return rows;
+    const startedAt = run => DateTimeUtils.toMillis(run["started_at"]);
+    const descending = startedAt(runs[0]) > startedAt(runs[runs.length - 1]);
+    const markers = runs.map(() => []);
+    const trailing = [];
+    changes.forEach(change => {
+        const changedAt = DateTimeUtils.toMillis(change["changed_at"]);
+        const index = runs.findIndex(run => run["job_name"] === change["job_name"] &&
+            (descending ? startedAt(run) < changedAt : startedAt(run) > changedAt));
+        if (index === -1) {
+            trailing.push(renderJobChangeRow(change));
+        } else {
+            markers[index].push(renderJobChangeRow(change));
+        }
+    });
+    return rows.flatMap((row, index) => [...markers[index], row]).concat(trailing);
+}
+
+// Steps of the multi-step runs in the table, by run id, for the output dialog.
+const runSteps = {};
+// The job each run in the table was dispatched as, by run id, for the output dialog.
+const runJobs = {};
+// What triggered each run in the table, by run id, for the output dialog.
+const runTriggers = {};
+
+function escapeOutput(value) {
+    const div = document.createElement('div');
+    div.textContent = value ?? "";
+    return div.innerHTML;
+}
+
+// Renders the steps of a multi-step run as a pipeline, each with its outcome, duration and output.
+function renderPipeline(steps) {
+    let html = '<ol class="pipeline">';
+    steps.forEach(step => {
+        const startedAt = DateTimeUtils.toMillis(step["started_at"]);
+        const completedAt = DateTimeUtils.toMillis(step["completed_at"]);
+        const outcome = RUN_OUTCOMES[step["outcome"]] || RUN_OUTCOMES[2];
+        const stepClass = { 1: "success", 5: "skipped" }[step["outcome"]] || "failure";
+        html += `<li class="pipeline-step pipeline-step-${stepClass}">
+            <b>${escapeOutput(step["name"])}</b> &mdash; ${outcome.label}
+            (return code ${step["return_code"]}, ${completedAt - startedAt} ms)<br>
+            <code>${escapeOutput(step["command"])}</code>
+            <details><summary>Output${step["truncated"] ? " (truncated)" : ""}</summary>
+            <pre style='white-space: pre-wrap; word-wrap: break-word;'>${escapeOutput(step["output"])}</pre></details>
+        </li>`;
+    });
+    return html + '</ol>';
+}
+
+// The status and values the job's output parsers extracted from the run's output.
+function formatParsed(parsed) {
+    if (!parsed) return "";
+    const values = [
+        ...(parsed["status"] !== undefined ? [`status: ${parsed["status"]}`] : []),
+        ...Object.entries(parsed["metrics"] || {}).map(([name, value]) => `${name}: ${value}`),
+        ...Object.entries(parsed["fields"] || {}).map(([name, value]) => `${name}: ${value}`),
+    ];
+    return values.map(value => `<br><small>${escapeOutput(value)}</small>`).join("");
+}
+
+// Renders what the run was dispatched to do, as recorded when its cycle started.
+function renderJobSnapshot(job) {
+    const rows = [
+        ["Command", [job["command"], ...(job["args"] || [])].join(" ")],
+        ["Environment", (job["env"] || []).join(" ")],
+        ["Working directory", job["cwd"]],
+        ["Timeout", job["timeout"] ? `${job["timeout"]} s` : "none"],
+        ["Retries", job["retries"]],
+        ["Valid return codes", (job["valid_return_codes"] || []).join(", ")],
+        ["Agents", (job["agents_required"] || []).join(", ")],
+        ["Agent groups", (job["agent_groups"] || []).join(", ")],
+        ["Platforms", (job["platforms"] || []).join(", ")],
+        ["Node selector", (job["node_selector"] || []).join(", ")],
+        ["Script", job["script"]],
+        ["Container", job["container"]],
+        ["Steps", (job["steps"] || []).map(step => `${step["name"]}: ${[step["command"], ...(step["args"] || [])].join(" ")}`).join("; ")],
+    ];
+    let html = '<details><summary>Job as dispatched</summary><table>';
+    rows.filter(([, value]) => value !== undefined && value !== null && value !== "")
+        .forEach(([label, value]) => {
+            html += `<tr><th>${label}</th><td><code>${escapeOutput(String(value))}</code></td></tr>`;
+        });
+    return html + '</table></details>';
+}
+
+// Wires up the rows' buttons. Values come from data attributes rather than inline handlers, so
+// nothing a run records can end up in script.
+function attachRunHandlers(container) {
+    container.querySelectorAll('.run-output').forEach(button => button.addEventListener('click', () =>
+        showRunOutputDialog(button.dataset.runId, runTriggers[button.dataset.runId])));
+    container.querySelectorAll('.run-rerun').forEach(button => button.addEventListener('click', () =>
+        rerunRun(button.dataset.runId)));
+    container.querySelectorAll('.run-command').forEach(span => span.addEventListener('click', () => {
+        const expanded = span.dataset.expanded === 'true';
+        span.textContent = expanded ? span.dataset.short : span.dataset.full;
+        span.dataset.expanded = expanded ? 'false' : 'true';
+    }));
+}
+
+// Whether secrets were redacted from the environment recorded with the run.
+function hasRedactedEnv(run) {
+    return ((run.job && run.job.env) || []).some(pair => pair.includes("[REDACTED]"));
+}
+
+// Runs the run again on the same agent with the same command, arguments and environment.
+function rerunRun(runId) {
+    if (!window.confirm('Run this again on the same agent?')) {
+        return;
+    }
+    fetch(`/runs/${runId}/rerun`, { method: 'POST' })
+        .then(response => response.text().then(text => {
+            if (!response.ok) {
+                throw new Error(text || 'Server error');
+            }
+            window.alert(text);
+        }))
+        .catch(error => window.alert(`Re-run failed: ${error.message}`));
+}
+
+function showRunOutputDialog(runId, triggeredBy = "") {
     const url = `/runs_output?id=${runId}`;
     fetch(url)
         .then(data => {
@@ -18,8 +195,16 @@ function showRunOutputDialog(runId) {
                 data.text().then(text => {
                     const myDialog = document.getElementById('myDialog');
                     const content = document.getElementById('dialog-content');
-                    let outputHTML = "Output for Run ID: " + runId + "<br><br>";
-                    outputHTML += "<pre style='white-space: pre-wrap; word-wrap: break-word;'>" + text + "</pre><br>";
+                    let outputHTML = "Output for Run ID: " + runId + "<br>";
+                    outputHTML += "Triggered By: " + triggeredBy + "<br><br>";
+                    outputHTML += "<pre style='white-space: pre-wrap; word-wrap: break-word;'>" + escapeOutput(text) + "</pre><br>";
+                    const steps = runSteps[runId] || [];
+                    if (steps.length > 0) {
+                        outputHTML += "Steps:<br>" + renderPipeline(steps);
+                    }
+                    if (runJobs[runId]) {
+                        outputHTML += renderJobSnapshot(runJobs[runId]);
+                    }
                     content.innerHTML = outputHTML;
                     myDialog.showModal();
                 });
@@ -42,6 +227,8 @@ function renderRunsTable(params = {}) {
 
             let current_page = data.current_page;
             let total_pages = data.total_pages;
+            const agentTimezones = data.agent_timezones || {};
+            const jobChanges = data.job_changes || [];
 
             data = data.items;
 
@@ -56,54 +243,62 @@ function renderRunsTable(params = {}) {
                 table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'command', true); return false;\">Command</a></th>`;
                 table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'return_code', true); return false;\">Return Code</a></th>`;
                 table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'outcome', true); return false;\">Outcome</a></th>`;
+                table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'triggered_by.kind', true); return false;\">Triggered By</a></th>`;
                 table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'started_at', true); return false;\">Started At</a></th>`;
                 table += `<th><a href=\"#\" class=\"sort_column\" onclick=\"FilterUtils.applyFilterAndReload('sort', 'completed_at', true); return false;\">Completed At</a></th>`;
                 table += `<th>Output</th>`;
                 table += '</tr></thead><tbody>';
 
                 // Add table rows
-                data.forEach(item => {
-                    let start_at_value = item["started_at"].$date.$numberLong;
-                    let completed_at_value = item["completed_at"].$date.$numberLong;
+                const rows = data.map(item => {
+                    let table = '';
+                    let start_at_value = DateTimeUtils.toMillis(item["started_at"]);
+                    let completed_at_value = DateTimeUtils.toMillis(item["completed_at"]);
+                    runSteps[item["_id"]['$oid']] = item["steps"] || [];
+                    runJobs[item["_id"]['$oid']] = item["job"];
                     table += '<tr>';
-                    table += `<td>${item["job_name"]}</td>`;
-                    table += `<td>${item["agent_name"]}</td>`;
-                    const command = item["command"] || "";
+                    table += `<td>${escapeOutput(item["job_name"])}${FilterUtils.tagBadges(item["tags"])}</td>`;
+                    table += `<td>${escapeOutput(item["agent_name"])}</td>`;
+                    // Agents report the command without its arguments, which the job snapshot has.
+                    const job = item["job"];
+                    const args = job && !job["script"] && (job["steps"] || []).length === 0 ? job["args"] || [] : [];
+                    const command = [item["command"] || "", ...args].join(" ").trim();
                     const shortCommand = command.length > 10 ? command.substring(0, 10) + "..." : command;
-                    const commandId = `command-${item["_id"]['$oid']}`;
                     table += `<td>
-                        <span id="${commandId}" style="cursor:pointer;" onclick="
-                            const el = document.getElementById('${commandId}');
-                            if (el.dataset.expanded === 'true') {
-                                el.textContent = '${shortCommand.replace(/'/g, "\\'")}';
-                                el.dataset.expanded = 'false';
-                            } else {
-                                el.textContent = '${command.replace(/'/g, "\\'").replace(/"/g, '&quot;')}';
-                                el.dataset.expanded = 'true';
-                            }
-                        " data-expanded="false">${shortCommand}</span>
+                        <span class="run-command" style="cursor:pointer;" data-expanded="false"
+                            data-short="${escapeOutput(shortCommand)}" data-full="${escapeOutput(command)}">${escapeOutput(shortCommand)}</span>
                     </td>`;
-                    table += `<td>${item["return_code"]}</td>`;
-                    if (item["outcome"] === 1) {
-                        table += `<td style="color: green;">Success</td>`;
-                    } else if (item["outcome"] === 0) {
-                        table += `<td style="color: red;">Failure</td>`;
-                    } else {
-                        table += `<td>${item["outcome"]}</td>`;
-                    }
-                    table += `<td class="utc-date" data-timestamp="${start_at_value}">${start_at_value}</td>`;
-                    table += `<td class="utc-date" data-timestamp="${completed_at_value}">${completed_at_value}</td>`;
+                    const extension = item["timeout_extension_seconds"];
+                    const extensionNote = extension ? `<br><small>timeout extended by ${extension}s</small>` : "";
+                    const slaNote = item["sla_breached"] ? `<br><small>SLA breached</small>` : "";
+                    table += `<td>${item["return_code"]}${extensionNote}${slaNote}${formatParsed(item["parsed"])}</td>`;
+                    table += formatOutcome(item["outcome"]);
+                    const triggeredBy = formatTriggeredBy(item["triggered_by"]);
+                    runTriggers[item["_id"]['$oid']] = triggeredBy;
+                    table += `<td>${triggeredBy}</td>`;
+                    const agentTimezone = agentTimezones[item["agent_name"]] || "";
+                    table += `<td><span class="utc-date" data-timestamp="${start_at_value}">${start_at_value}</span><br>
+                        <small class="host-local-date" data-timestamp="${start_at_value}" data-timezone="${agentTimezone}"></small></td>`;
+                    table += `<td><span class="utc-date" data-timestamp="${completed_at_value}">${completed_at_value}</span><br>
+                        <small class="host-local-date" data-timestamp="${completed_at_value}" data-timezone="${agentTimezone}"></small></td>`;
+                    const rerunTitle = hasRedactedEnv(item)
+                        ? "Secrets redacted from this run are taken from the job's current environment"
+                        : "";
                     table += `<td>
-                        <button class="btn btn-primary" onclick="showRunOutputDialog('${item["_id"]['$oid']}')">Output</button>
+                        <button class="btn btn-primary run-output" data-run-id="${item["_id"]['$oid']}">Output</button>
+                        <button class="btn btn-primary run-rerun" title="${rerunTitle}" data-run-id="${item["_id"]['$oid']}">Re-run</button>
                     </td>`;
                     table += '</tr>';
+                    return table;
                 });
+                table += interleaveJobChanges(rows, data, jobChanges).join('');
 
                 table += '</tbody></table>';
 
                 pagination = "<div class=\"pagination_controls\" id=\"pagination-controls\" style=\"margin-top: 20px;\"></div>";
 
                 container.innerHTML = table + pagination;
+                attachRunHandlers(container);
 
                 renderPaginationControls(current_page, total_pages);
 
@@ -121,3 +316,79 @@ function renderRunsTable(params = {}) {
             TimeOutWrapper.createMyTimeout(() => renderRunsTable(params), 10000);
         });
 }
+
+function showCycleRuns(cycleId) {
+    const url = new URL(window.location.href);
+    url.searchParams.delete('group_by_cycle');
+    url.searchParams.delete('page');
+    url.searchParams.set('cycle_filter', cycleId);
+    window.location = url.toString();
+}
+
+function renderRunCyclesTable(params = {}) {
+    const url = "/runs_cycles_data";
+    AjaxUtils.getJsonData(url, params)
+        .then(data => {
+            const container = document.getElementById("items");
+            if (!container) return;
+
+            let current_page = data.current_page;
+            let total_pages = data.total_pages;
+
+            data = data.items;
+
+            if (!Array.isArray(data) || data.length === 0) {
+                container.innerHTML = '<p>No data available.</p>';
+            } else {
+                let table = '<table><thead><tr>';
+                table += '<th>Job Name</th>';
+                table += '<th>Agents</th>';
+                table += '<th>Succeeded</th>';
+                table += '<th>Status</th>';
+                table += '<th>Triggered By</th>';
+                table += '<th>Started At</th>';
+                table += '<th>Completed At</th>';
+                table += '</tr></thead><tbody>';
+
+                data.forEach(item => {
+                    let start_at_value = DateTimeUtils.toMillis(item["started_at"]);
+                    let completed_at_value = DateTimeUtils.toMillis(item["completed_at"]);
+                    table += `<tr style="cursor:pointer;" onclick="showCycleRuns('${item["_id"]}')">`;
+                    table += `<td>${item["job_name"]}</td>`;
+                    table += `<td>${item["runs"]}</td>`;
+                    table += `<td>${item["succeeded"]}</td>`;
+                    if (item["status"] === "success") {
+                        table += `<td style="color: green;">Success</td>`;
+                    } else if (item["status"] === "failure") {
+                        table += `<td style="color: red;">Failure</td>`;
+                    } else {
+                        table += `<td style="color: orange;">Partial</td>`;
+                    }
+                    table += `<td>${formatTriggeredBy(item["triggered_by"])}</td>`;
+                    table += `<td><span class="utc-date" data-timestamp="${start_at_value}">${start_at_value}</span></td>`;
+                    table += `<td><span class="utc-date" data-timestamp="${completed_at_value}">${completed_at_value}</span></td>`;
+                    table += '</tr>';
+                });
+
+                table += '</tbody></table>';
+
+                pagination = "<div class=\"pagination_controls\" id=\"pagination-controls\" style=\"margin-top: 20px;\"></div>";
+
+                container.innerHTML = table + pagination;
+
+                renderPaginationControls(current_page, total_pages);
+
+                DateTimeUtils.convertUtcDateElements();
+            }
+
+            // Auto-refresh the table every 10 seconds
+            TimeOutWrapper.createMyTimeout(() => renderRunCyclesTable(params), 10000);
+        })
+        .catch(error => {
+            const container = document.getElementById("items");
+            if (container) {
+                container.innerHTML = `<p>Error loading data: ${error.message}</p>`;
+            }
+            TimeOutWrapper.createMyTimeout(() => renderRunCyclesTable(params), 10000);
+        });
+}
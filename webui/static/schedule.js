@@ -0,0 +1,116 @@
+// Outcomes drawn as succeeded or failed, see `Outcome`; the others are drawn as "other".
+const SCHEDULE_SUCCESS = [1];
+const SCHEDULE_FAILURE = [0, 3, 4, 6];
+const SCHEDULE_NO_AGENT = "(no agent)";
+
+function escapeScheduleText(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+function scheduleInput(elementId) {
+    const element = document.getElementById(elementId);
+    return element ? element.value.trim() : "";
+}
+
+function outcomeClass(outcome) {
+    if (SCHEDULE_SUCCESS.includes(outcome)) return "schedule-success";
+    if (SCHEDULE_FAILURE.includes(outcome)) return "schedule-failure";
+    return "schedule-other";
+}
+
+// A bar from `start` to `end` on a row spanning `windowStart` to `windowEnd`, at least a sliver
+// wide so that short runs and upcoming runs stay visible.
+function scheduleBar(css, start, end, windowStart, windowEnd, title, link) {
+    const windowLength = Math.max(windowEnd - windowStart, 1);
+    const left = Math.max(start - windowStart, 0) / windowLength * 100;
+    const width = Math.max((Math.min(end, windowEnd) - Math.max(start, windowStart)) / windowLength * 100, 0.3);
+    const bar = `<div class="schedule-bar ${css}" style="left: ${left}%; width: ${width}%;" title="${escapeScheduleText(title)}"></div>`;
+    return link ? `<a href="${link}">${bar}</a>` : bar;
+}
+
+function scheduleAxis(windowStart, windowEnd, now) {
+    const windowLength = Math.max(windowEnd - windowStart, 1);
+    const hours = windowLength / 3600000;
+    const step = hours <= 48 ? 6 : hours <= 168 ? 24 : 72;
+    let html = '<div class="schedule-axis">';
+    const first = Math.ceil(windowStart / (step * 3600000)) * step * 3600000;
+    for (let tick = first; tick <= windowEnd; tick += step * 3600000) {
+        const left = (tick - windowStart) / windowLength * 100;
+        html += `<span class="schedule-tick" style="left: ${left}%;">${new Date(tick).toLocaleString([], { month: "short", day: "numeric", hour: "2-digit", minute: "2-digit" })}</span>`;
+    }
+    html += '</div>';
+    const nowLeft = (now - windowStart) / windowLength * 100;
+    return { axis: html, nowLine: `<div class="schedule-now" style="left: ${nowLeft}%;" title="Now"></div>` };
+}
+
+// Renders a row per agent with its past, running and upcoming runs.
+function renderSchedule() {
+    const params = {
+        hours: scheduleInput("schedule-hours") || 24,
+        past_hours: scheduleInput("schedule-past-hours") || 24,
+    };
+    const job = scheduleInput("schedule-job");
+    if (job) {
+        params.job = job;
+    }
+    TimeOutWrapper.haltAllTimeouts();
+    AjaxUtils.getJsonData("/schedule/preview", params)
+        .then(data => {
+            const timeline = document.getElementById("schedule-timeline");
+            const summary = document.getElementById("schedule-summary");
+            if (!timeline || !summary) return;
+
+            const rows = {};
+            const row = agent => (rows[agent] = rows[agent] || []);
+            (data.runs || []).forEach(run => {
+                const title = `${run.job_name}: ${new Date(run.started_at).toLocaleString()} to ${new Date(run.completed_at).toLocaleString()}`;
+                const link = run.run_id ? `/runs?filter=${encodeURIComponent(run.run_id)}` : "";
+                row(run.agent_name).push(scheduleBar(outcomeClass(run.outcome), run.started_at, run.completed_at, data.from, data.until, title, link));
+            });
+            (data.running || []).forEach(run => {
+                const title = `${run.job_name}: running since ${new Date(run.started_at).toLocaleString()}`;
+                row(run.agent_name).push(scheduleBar("schedule-running", run.started_at, data.now, data.from, data.until, title, ""));
+            });
+            (data.scheduled || []).forEach(run => {
+                const at = Math.max(run.at, data.now);
+                const css = run.blacked_out ? "schedule-blacked-out" : "schedule-upcoming";
+                const title = `${run.job_name}: ${run.at < data.now ? "overdue since" : "scheduled at"} ${new Date(run.at).toLocaleString()}${run.blacked_out ? " (blackout window)" : ""}`;
+                const agents = run.agents.length > 0 ? run.agents : [SCHEDULE_NO_AGENT];
+                agents.forEach(agent => row(agent).push(scheduleBar(css, at, at, data.from, data.until, title, "")));
+            });
+
+            const agents = Object.keys(rows).sort();
+            const scheduledCount = (data.scheduled || []).length;
+            summary.innerHTML = `<p>${(data.runs || []).length} past run(s), ${(data.running || []).length} running and ${scheduledCount} scheduled run(s) on ${agents.length} agent(s).${data.truncated ? " Only the latest past runs are shown." : ""}</p>`;
+            if (agents.length === 0) {
+                timeline.innerHTML = '<p>No runs in this period.</p>';
+                return;
+            }
+
+            const { axis, nowLine } = scheduleAxis(data.from, data.until, data.now);
+            let html = '<table class="schedule-table"><tbody>';
+            html += `<tr><td></td><td>${axis}</td></tr>`;
+            agents.forEach(agent => {
+                const name = agent === SCHEDULE_NO_AGENT
+                    ? `<i>${SCHEDULE_NO_AGENT}</i>`
+                    : `<a href="/agents?filter=${encodeURIComponent(agent)}">${escapeScheduleText(agent)}</a>`;
+                html += `<tr><td class="schedule-agent">${name}</td>`;
+                html += `<td><div class="schedule-row">${nowLine}${rows[agent].join("")}</div></td></tr>`;
+            });
+            html += '</tbody></table>';
+            timeline.innerHTML = html;
+
+            TimeOutWrapper.createMyTimeout(renderSchedule, 30000);
+        })
+        .catch(error => {
+            const timeline = document.getElementById("schedule-timeline");
+            if (timeline) {
+                timeline.innerHTML = `<p>Error loading schedule: ${escapeScheduleText(error.message)}</p>`;
+            }
+        });
+}
@@ -0,0 +1,102 @@
+function escapeTokenText(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+function showTokenStatus(message, isError) {
+    const statusSuccess = document.getElementById('status-success');
+    const statusError = document.getElementById('status-error');
+    statusSuccess.style.display = isError ? 'none' : 'block';
+    statusError.style.display = isError ? 'block' : 'none';
+    (isError ? statusError : statusSuccess).innerHTML = escapeTokenText(message);
+}
+
+function tokenDateCell(date) {
+    if (!date) return '<td></td>';
+    const timestamp = DateTimeUtils.toMillis(date);
+    return `<td><span class="utc-date" data-timestamp="${timestamp}">${timestamp}</span></td>`;
+}
+
+function createApiToken(event) {
+    event.preventDefault();
+    const expires = document.getElementById('token-expires').value;
+    fetch('/settings/api_tokens', {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({
+            name: document.getElementById('token-name').value.trim(),
+            kind: document.getElementById('token-kind').value,
+            expires_in_days: expires ? Number(expires) : null,
+        }),
+    })
+        .then(response => response.text().then(text => {
+            if (!response.ok) {
+                throw new Error(text || 'Server error');
+            }
+            const created = JSON.parse(text);
+            showTokenStatus(`Created a token acting as ${created.actor}`, false);
+            document.getElementById('new-token-value').textContent = created.token;
+            document.getElementById('new-token').style.display = 'block';
+            renderApiTokensTable();
+        }))
+        .catch(error => showTokenStatus(error.message, true));
+}
+
+function revokeApiToken(id, name) {
+    if (!window.confirm(`Revoke token ${name}? Programs using it are rejected from then on.`)) {
+        return;
+    }
+    fetch('/settings/api_tokens/' + encodeURIComponent(id), { method: 'DELETE' })
+        .then(response => response.text().then(text => {
+            if (!response.ok) {
+                throw new Error(text || 'Server error');
+            }
+            showTokenStatus(text, false);
+            renderApiTokensTable();
+        }))
+        .catch(error => showTokenStatus(error.message, true));
+}
+
+function renderApiTokensTable() {
+    AjaxUtils.getJsonData("/settings/api_tokens", {})
+        .then(data => {
+            const container = document.getElementById("items");
+            if (!container) return;
+            const tokens = data.items || [];
+            if (tokens.length === 0) {
+                container.innerHTML = '<p>No API tokens.</p>';
+                return;
+            }
+            let table = '<table><thead><tr><th>Name</th><th>Acts As</th><th>Token</th><th>Created By</th><th>Created</th><th>Expires</th><th>Last Used</th><th></th></tr></thead><tbody>';
+            tokens.forEach(token => {
+                table += '<tr>';
+                table += `<td>${escapeTokenText(token.name)}</td>`;
+                table += `<td>${escapeTokenText(token.actor)}</td>`;
+                table += `<td><code>${escapeTokenText(token.hint)}&hellip;</code></td>`;
+                table += `<td>${escapeTokenText(token.created_by)}</td>`;
+                table += tokenDateCell(token.created_at);
+                table += tokenDateCell(token.expires_at);
+                table += tokenDateCell(token.last_used_at);
+                if (token.revoked_at) {
+                    table += '<td>Revoked</td>';
+                } else {
+                    const id = escapeTokenText(JSON.stringify(token._id.$oid));
+                    const name = escapeTokenText(JSON.stringify(token.name));
+                    table += `<td><a href="#" class="btn" onclick="revokeApiToken(${id}, ${name}); return false;">Revoke</a></td>`;
+                }
+                table += '</tr>';
+            });
+            container.innerHTML = table + '</tbody></table>';
+            DateTimeUtils.convertUtcDateElements();
+        })
+        .catch(error => {
+            const container = document.getElementById("items");
+            if (container) {
+                container.innerHTML = `<p>Error loading data: ${escapeTokenText(error.message)}</p>`;
+            }
+        });
+}
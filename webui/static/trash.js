@@ -0,0 +1,88 @@
+function escapeTrashText(value) {
+    return String(value ?? "")
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+function deletedAtCell(item) {
+    const timestamp = DateTimeUtils.toMillis(item.deleted_at);
+    return `<td><span class="utc-date" data-timestamp="${timestamp}">${timestamp}</span></td>`;
+}
+
+function renderTrashAgents(agents) {
+    const container = document.getElementById("trash-agents");
+    if (!container) return;
+    if (agents.length === 0) {
+        container.innerHTML = '<p>No agents in the trash.</p>';
+        return;
+    }
+    let table = '<table><thead><tr><th>Name</th><th>Hostname</th><th>Groups</th><th>Deleted</th><th></th></tr></thead><tbody>';
+    agents.forEach(agent => {
+        const id = escapeTrashText(JSON.stringify(agent._id.$oid));
+        const name = escapeTrashText(JSON.stringify(agent.name));
+        table += '<tr>';
+        table += `<td>${escapeTrashText(agent.name)}</td>`;
+        table += `<td>${escapeTrashText(agent.hostname)}</td>`;
+        table += `<td>${escapeTrashText((agent.deleted_groups || []).join(", "))}</td>`;
+        table += deletedAtCell(agent);
+        table += `<td><button class="btn btn-primary" onclick="trashAction('POST', '/agents/' + encodeURIComponent(${id}) + '/restore', null)">Restore</button>&nbsp`;
+        table += `<button class="btn btn-secondary" onclick="trashAction('DELETE', '/agents/' + encodeURIComponent(${id}) + '/purge', 'Delete agent ' + ${name} + ' for good?')">Purge</button></td>`;
+        table += '</tr>';
+    });
+    container.innerHTML = table + '</tbody></table>';
+}
+
+function renderTrashJobs(jobs) {
+    const container = document.getElementById("trash-jobs");
+    if (!container) return;
+    if (jobs.length === 0) {
+        container.innerHTML = '<p>No jobs in the trash.</p>';
+        return;
+    }
+    let table = '<table><thead><tr><th>Name</th><th>Description</th><th>Command</th><th>Deleted</th><th></th></tr></thead><tbody>';
+    jobs.forEach(job => {
+        const name = escapeTrashText(JSON.stringify(job.name));
+        table += '<tr>';
+        table += `<td>${escapeTrashText(job.name)}</td>`;
+        table += `<td>${escapeTrashText(job.description)}</td>`;
+        table += `<td>${escapeTrashText(job.command)}</td>`;
+        table += deletedAtCell(job);
+        table += `<td><button class="btn btn-primary" onclick="trashAction('POST', '/jobs/' + encodeURIComponent(${name}) + '/restore', null)">Restore</button>&nbsp`;
+        table += `<button class="btn btn-secondary" onclick="trashAction('DELETE', '/jobs/' + encodeURIComponent(${name}) + '/purge', 'Delete job ' + ${name} + ' and its history for good?')">Purge</button></td>`;
+        table += '</tr>';
+    });
+    container.innerHTML = table + '</tbody></table>';
+}
+
+function renderTrash() {
+    AjaxUtils.getJsonData("/trash/data", {})
+        .then(data => {
+            renderTrashAgents(data.agents || []);
+            renderTrashJobs(data.jobs || []);
+            DateTimeUtils.convertUtcDateElements();
+        })
+        .catch(error => {
+            const container = document.getElementById("trash-agents");
+            if (container) {
+                container.innerHTML = `<p>Error loading data: ${escapeTrashText(error.message)}</p>`;
+            }
+        });
+}
+
+// Restores or purges an item, asking first when `question` is given.
+function trashAction(method, url, question) {
+    if (question && !window.confirm(question)) {
+        return;
+    }
+    fetch(url, { method: method })
+        .then(response => response.text().then(text => {
+            if (!response.ok) {
+                throw new Error(text || 'Server error');
+            }
+            renderTrash();
+        }))
+        .catch(error => window.alert(`Failed: ${error.message}`));
+}